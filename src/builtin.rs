@@ -0,0 +1,287 @@
+//! Functions that are part of the language core, because math is important.
+//! Also the builtin inputs, which are the things the GPU hands us for free (like `@frag.coord`).
+use crate::check::ShaderStage;
+use crate::parse::{CompositeSize, NumberType, SamplerDimension, YuriType};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BuiltinFunction {
+	Sin,
+	Cos,
+	Tan,
+	Asin,
+	Acos,
+	Atan,
+	Atan2,
+	Pow,
+	Exp,
+	Exp2,
+	Log,
+	Log2,
+	Sqrt,
+	InverseSqrt,
+	Abs,
+	Sign,
+	Floor,
+	Ceil,
+	Fract,
+	Round,
+	Trunc,
+	Min,
+	Max,
+	Clamp,
+	Mix,
+	Step,
+	SmoothStep,
+	Length,
+	Distance,
+	Dot,
+	Cross,
+	Normalize,
+	Reflect,
+	Transpose,
+	Determinant,
+	Inverse,
+	Sample,
+	/// Fails the test it's in (see [crate::testing]) unless its argument is true.
+	Assert,
+	/// Fails the test it's in unless both arguments are the same.
+	AssertEq,
+	/// Fails the test it's in unless both arguments are within a tolerance of each other, which floats usually need.
+	AssertNear,
+}
+
+impl BuiltinFunction {
+	pub const ALL: [BuiltinFunction; 40] = { use BuiltinFunction::*; [
+		Sin,
+		Cos,
+		Tan,
+		Asin,
+		Acos,
+		Atan,
+		Atan2,
+		Pow,
+		Exp,
+		Exp2,
+		Log,
+		Log2,
+		Sqrt,
+		InverseSqrt,
+		Abs,
+		Sign,
+		Floor,
+		Ceil,
+		Fract,
+		Round,
+		Trunc,
+		Min,
+		Max,
+		Clamp,
+		Mix,
+		Step,
+		SmoothStep,
+		Length,
+		Distance,
+		Dot,
+		Cross,
+		Normalize,
+		Reflect,
+		Transpose,
+		Determinant,
+		Inverse,
+		Sample,
+		Assert,
+		AssertEq,
+		AssertNear,
+	] };
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			BuiltinFunction::Sin 			=> "sin",
+			BuiltinFunction::Cos 			=> "cos",
+			BuiltinFunction::Tan 			=> "tan",
+			BuiltinFunction::Asin 			=> "asin",
+			BuiltinFunction::Acos 			=> "acos",
+			BuiltinFunction::Atan 			=> "atan",
+			BuiltinFunction::Atan2 			=> "atan2",
+			BuiltinFunction::Pow 			=> "pow",
+			BuiltinFunction::Exp 			=> "exp",
+			BuiltinFunction::Exp2 			=> "exp2",
+			BuiltinFunction::Log 			=> "log",
+			BuiltinFunction::Log2 			=> "log2",
+			BuiltinFunction::Sqrt 			=> "sqrt",
+			BuiltinFunction::InverseSqrt 	=> "inverse_sqrt",
+			BuiltinFunction::Abs 			=> "abs",
+			BuiltinFunction::Sign 			=> "sign",
+			BuiltinFunction::Floor 			=> "floor",
+			BuiltinFunction::Ceil 			=> "ceil",
+			BuiltinFunction::Fract 			=> "fract",
+			BuiltinFunction::Round 			=> "round",
+			BuiltinFunction::Trunc 			=> "trunc",
+			BuiltinFunction::Min 			=> "min",
+			BuiltinFunction::Max 			=> "max",
+			BuiltinFunction::Clamp 			=> "clamp",
+			BuiltinFunction::Mix 			=> "mix",
+			BuiltinFunction::Step 			=> "step",
+			BuiltinFunction::SmoothStep 	=> "smoothstep",
+			BuiltinFunction::Length 		=> "length",
+			BuiltinFunction::Distance 		=> "distance",
+			BuiltinFunction::Dot 			=> "dot",
+			BuiltinFunction::Cross 			=> "cross",
+			BuiltinFunction::Normalize 		=> "normalize",
+			BuiltinFunction::Reflect 		=> "reflect",
+			BuiltinFunction::Transpose 		=> "transpose",
+			BuiltinFunction::Determinant 	=> "determinant",
+			BuiltinFunction::Inverse 		=> "inverse",
+			BuiltinFunction::Sample 		=> "sample",
+			BuiltinFunction::Assert 		=> "assert",
+			BuiltinFunction::AssertEq 		=> "assert_eq",
+			BuiltinFunction::AssertNear 	=> "assert_near",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter()
+			.find(|f| f.name() == name)
+			.copied()
+	}
+
+	/// Assertions only do anything on the CPU, the GPU skips right over them.
+	pub fn is_assertion(self) -> bool {
+		matches!(self, BuiltinFunction::Assert | BuiltinFunction::AssertEq | BuiltinFunction::AssertNear)
+	}
+
+	/// Figures out what calling the function with the given argument types gives back,
+	/// or explains why it can't be called like that.
+	pub fn result_type(self, arguments: &[YuriType]) -> Result<YuriType, String> {
+		use BuiltinFunction::*;
+		let name = self.name();
+		let float = YuriType::Scalar(NumberType::Float);
+		let is_float = |ty: &YuriType| matches!(ty, YuriType::Scalar(NumberType::Float) | YuriType::Vector(NumberType::Float, _));
+		let is_signed = |ty: &YuriType| matches!(ty, YuriType::Scalar(NumberType::Float | NumberType::Signed) | YuriType::Vector(NumberType::Float | NumberType::Signed, _));
+		let is_number = |ty: &YuriType| matches!(ty, YuriType::Scalar(_) | YuriType::Vector(..));
+		let expected_count = match self {
+			Atan2 | Pow | Min | Max | Step | Distance | Dot | Cross | Reflect | Sample | AssertEq => 2,
+			Clamp | Mix | SmoothStep | AssertNear => 3,
+			_ => 1,
+		};
+		if arguments.len() != expected_count {
+			return Err(format!("`{name}` takes {expected_count} argument(s), but was given {}", arguments.len()));
+		}
+		let mismatch = || Err(format!(
+			"`{name}` can't be used on {}",
+			arguments.iter().map(|a| format!("`{a}`")).collect::<Vec<_>>().join(", ")
+		));
+		let all_same = arguments.iter().all(|a| *a == arguments[0]);
+		Ok(match self {
+			Sin | Cos | Tan | Asin | Acos | Atan | Exp | Exp2 | Log | Log2 | Sqrt | InverseSqrt
+			| Floor | Ceil | Fract | Round | Trunc | Normalize if is_float(&arguments[0]) => arguments[0].clone(),
+			Abs | Sign if is_signed(&arguments[0]) => arguments[0].clone(),
+			Atan2 | Pow | Step | Reflect if all_same && is_float(&arguments[0]) => arguments[0].clone(),
+			Min | Max | Clamp if all_same && is_number(&arguments[0]) => arguments[0].clone(),
+			Mix if is_float(&arguments[0]) && arguments[0] == arguments[1]
+				&& (arguments[2] == arguments[0] || arguments[2] == float) => arguments[0].clone(),
+			SmoothStep if is_float(&arguments[2]) && arguments[0] == arguments[1]
+				&& (arguments[0] == arguments[2] || arguments[0] == float) => arguments[2].clone(),
+			Length if is_float(&arguments[0]) => float,
+			Distance if all_same && is_float(&arguments[0]) => float,
+			Dot if all_same && matches!(arguments[0], YuriType::Vector(NumberType::Float, _)) => float,
+			Cross if all_same && arguments[0] == YuriType::Vector(NumberType::Float, CompositeSize::Three) => arguments[0].clone(),
+			Transpose | Inverse if matches!(arguments[0], YuriType::Matrix(_)) => arguments[0].clone(),
+			Determinant if matches!(arguments[0], YuriType::Matrix(_)) => float,
+			Sample => {
+				let YuriType::Sampler(dimension) = &arguments[0] else {
+					return mismatch();
+				};
+				let coordinates = match dimension {
+					SamplerDimension::One => float,
+					SamplerDimension::Two => YuriType::Vector(NumberType::Float, CompositeSize::Two),
+					SamplerDimension::Three | SamplerDimension::Cube => YuriType::Vector(NumberType::Float, CompositeSize::Three),
+				};
+				if arguments[1] != coordinates {
+					return Err(format!("sampling a `{}` needs `{coordinates}` coordinates, not `{}`", arguments[0], arguments[1]));
+				}
+				YuriType::Vector(NumberType::Float, CompositeSize::Four)
+			}
+			Assert if arguments[0] == YuriType::Bool => YuriType::Unit,
+			AssertEq if all_same && !matches!(arguments[0], YuriType::Sampler(_)) => YuriType::Unit,
+			AssertNear if arguments[0] == arguments[1] && arguments[2] == float
+				&& (is_float(&arguments[0]) || matches!(arguments[0], YuriType::Matrix(_))) => YuriType::Unit,
+			_ => return mismatch(),
+		})
+	}
+}
+
+/// Values provided by the GPU, used in expressions like `@frag.coord`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BuiltinInput {
+	/// Window-space fragment coordinates (only x and y, the rest aren't very useful).
+	/// Where the origin is depends on the [FragOrigin] of the entry point.
+	FragCoord,
+	FrontFacing,
+	VertexIndex,
+	InstanceIndex,
+}
+
+impl BuiltinInput {
+	pub const ALL: [BuiltinInput; 4] = [
+		BuiltinInput::FragCoord,
+		BuiltinInput::FrontFacing,
+		BuiltinInput::VertexIndex,
+		BuiltinInput::InstanceIndex,
+	];
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			BuiltinInput::FragCoord 		=> "frag.coord",
+			BuiltinInput::FrontFacing 		=> "frag.front_facing",
+			BuiltinInput::VertexIndex 		=> "vert.index",
+			BuiltinInput::InstanceIndex 	=> "vert.instance",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter()
+			.find(|i| i.name() == name)
+			.copied()
+	}
+
+	pub fn input_type(self) -> YuriType {
+		match self {
+			BuiltinInput::FragCoord => YuriType::Vector(NumberType::Float, CompositeSize::Two),
+			BuiltinInput::FrontFacing => YuriType::Bool,
+			BuiltinInput::VertexIndex | BuiltinInput::InstanceIndex => YuriType::Scalar(NumberType::Unsigned),
+		}
+	}
+
+	/// The only shader stage the input is available in.
+	pub fn stage(self) -> ShaderStage {
+		match self {
+			BuiltinInput::FragCoord | BuiltinInput::FrontFacing => ShaderStage::Fragment,
+			BuiltinInput::VertexIndex | BuiltinInput::InstanceIndex => ShaderStage::Vertex,
+		}
+	}
+}
+
+/// Where `@frag.coord` puts (0, 0), and which way y goes from there.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum FragOrigin {
+	/// y goes down, like Vulkan, D3D and Metal (and SDL). The default.
+	#[default]
+	UpperLeft,
+	/// y goes up, like OpenGL.
+	LowerLeft,
+}
+
+impl FragOrigin {
+	pub const fn name(self) -> &'static str {
+		match self {
+			FragOrigin::UpperLeft 	=> "upper_left",
+			FragOrigin::LowerLeft 	=> "lower_left",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		[FragOrigin::UpperLeft, FragOrigin::LowerLeft].into_iter()
+			.find(|o| o.name() == name)
+	}
+}
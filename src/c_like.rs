@@ -0,0 +1,832 @@
+//! What the GLSL, HLSL and MSL backends ([crate::glsl], [crate::hlsl], [crate::msl]) have in common, which is
+//! everything but the interface of the entry point. As far as Yuri cares, they're all C with vectors.
+//!
+//! GLSL for Vulkan has specialization constants and MSL has function constants, so `@spec` globals become those.
+//! HLSL doesn't have anything SDL can set, so there they just keep their defaults (same as GLSL for OpenGL).
+//! The props still have to end up where std140 puts them, since every SDL backend gets handed the same bytes:
+//! GLSL can just ask for std140, and the structs in HLSL's and MSL's uniform buffers get padding members
+//! wherever the language's own packing would put something elsewhere.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::ops::Range;
+use crate::builtin::{BuiltinFunction, BuiltinInput};
+use crate::check::{FunctionId, PropId, ShaderStage, TypedExpression, TypedProgram, number_type};
+use crate::compile::{descriptor_sets, implicit_lod, round_up, std140_layout, std140_offsets, without_annotations};
+use crate::error::YuriSemanticError;
+use crate::parse::{BinaryOperator, CompositeSize, NumberType, SamplerDimension, YuriType};
+use crate::target::Target;
+use crate::text::{Code, Emit, GlobalKind, Writer, fixed_length, identifier, unique_member, unsupported};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Dialect {
+	Glsl,
+	Hlsl,
+	Msl,
+}
+
+/// Words GLSL has taken (or reserved for later), plus the builtin functions the generated code calls,
+/// since a variable with the same name would hide them.
+const GLSL_RESERVED: &[&str] = &[
+	"active", "asm", "atomic_uint", "attribute", "bool", "break", "buffer", "case", "cast", "centroid", "class",
+	"coherent", "common", "const", "continue", "default", "discard", "do", "double", "else", "enum", "extern",
+	"external", "false", "filter", "fixed", "flat", "float", "for", "goto", "half", "highp", "if", "in", "inline",
+	"inout", "input", "int", "interface", "invariant", "layout", "long", "lowp", "main", "mediump", "namespace",
+	"noinline", "noperspective", "out", "output", "partition", "patch", "precise", "precision", "public",
+	"readonly", "resource", "restrict", "return", "sample", "shared", "short", "sizeof", "smooth", "static",
+	"struct", "subroutine", "superp", "switch", "template", "this", "true", "typedef", "uint", "uniform", "union",
+	"unsigned", "using", "varying", "void", "volatile", "while", "writeonly",
+	"abs", "acos", "asin", "atan", "ceil", "clamp", "cos", "cross", "determinant", "distance", "dot", "exp",
+	"exp2", "floor", "fract", "inverse", "inversesqrt", "length", "log", "log2", "max", "min", "mix", "normalize",
+	"pow", "reflect", "roundEven", "sign", "sin", "smoothstep", "sqrt", "step", "tan", "texture", "textureLod",
+	"transpose", "trunc", "uintBitsToFloat",
+];
+
+/// Keywords (HLSL's and the ones C++ has that it might take one day), plus the intrinsics the generated code calls.
+const HLSL_RESERVED: &[&str] = &[
+	"AppendStructuredBuffer", "BlendState", "Buffer", "ByteAddressBuffer", "CompileShader", "ComputeShader",
+	"ConstantBuffer", "ConsumeStructuredBuffer", "DepthStencilState", "DepthStencilView", "DomainShader",
+	"GeometryShader", "HullShader", "InputPatch", "LineStream", "NULL", "OutputPatch", "PixelShader", "PointStream",
+	"RWBuffer", "RWByteAddressBuffer", "RWStructuredBuffer", "RWTexture1D", "RWTexture2D", "RWTexture3D",
+	"RasterizerState", "RenderTargetView", "SamplerComparisonState", "SamplerState", "StructuredBuffer", "Texture1D",
+	"Texture2D", "Texture3D", "TextureCube", "TriangleStream", "VertexShader", "asm", "auto", "bool", "break", "case",
+	"catch", "cbuffer", "centroid", "char", "class", "column_major", "compile", "const", "const_cast", "continue",
+	"default", "delete", "discard", "do", "double", "dword", "dynamic_cast", "else", "enum", "explicit", "export",
+	"extern", "false", "float", "for", "friend", "goto", "groupshared", "half", "if", "in", "inline", "inout", "int",
+	"interface", "line", "lineadj", "linear", "long", "main", "matrix", "mutable", "namespace", "new",
+	"nointerpolation", "noperspective", "operator", "out", "packoffset", "pass", "point", "precise", "private",
+	"protected", "public", "register", "reinterpret_cast", "return", "row_major", "sample", "sampler", "shared",
+	"short", "signed", "sizeof", "snorm", "static", "static_cast", "string", "struct", "switch", "tbuffer",
+	"technique", "template", "texture", "this", "throw", "triangle", "triangleadj", "true", "try", "typedef",
+	"typename", "uint", "uniform", "union", "unorm", "unsigned", "using", "vector", "virtual", "void", "volatile",
+	"while",
+	"abs", "acos", "asfloat", "asin", "atan", "atan2", "ceil", "clamp", "cos", "cross", "determinant", "distance",
+	"dot", "exp", "exp2", "floor", "frac", "length", "lerp", "log", "log2", "max", "min", "mul", "normalize", "pow",
+	"reflect", "round", "rsqrt", "sign", "sin", "smoothstep", "sqrt", "step", "tan", "transpose", "trunc",
+];
+
+/// C++14's keywords, Metal's, and the functions the generated code calls.
+const MSL_RESERVED: &[&str] = &[
+	"alignas", "alignof", "and", "and_eq", "asm", "auto", "bitand", "bitor", "bool", "break", "case", "catch", "char",
+	"char16_t", "char32_t", "class", "compl", "const", "const_cast", "constexpr", "continue", "decltype", "default",
+	"delete", "do", "double", "dynamic_cast", "else", "enum", "explicit", "export", "extern", "false", "float", "for",
+	"friend", "goto", "if", "inline", "int", "long", "mutable", "namespace", "new", "noexcept", "not", "not_eq",
+	"nullptr", "operator", "or", "or_eq", "private", "protected", "public", "register", "reinterpret_cast", "return",
+	"short", "signed", "sizeof", "static", "static_assert", "static_cast", "struct", "switch", "template", "this",
+	"thread_local", "throw", "true", "try", "typedef", "typeid", "typename", "union", "unsigned", "using", "virtual",
+	"void", "volatile", "wchar_t", "while", "xor", "xor_eq",
+	"array", "as_type", "constant", "device", "fragment", "half", "kernel", "main", "metal", "sampler", "thread",
+	"threadgroup", "uchar", "uint", "ulong", "ushort", "vertex",
+	"abs", "acos", "asin", "atan", "atan2", "ceil", "clamp", "cos", "cross", "determinant", "distance", "dot", "exp",
+	"exp2", "floor", "fmod", "fract", "is_function_constant_defined", "length", "level", "log", "log2", "max", "min",
+	"mix", "normalize", "pow", "reflect", "rint", "rsqrt", "sign", "sin", "smoothstep", "sqrt", "step", "tan",
+	"transpose", "trunc",
+];
+
+impl Dialect {
+	pub(crate) fn name(self) -> &'static str {
+		match self {
+			Dialect::Glsl => "GLSL",
+			Dialect::Hlsl => "HLSL",
+			Dialect::Msl => "MSL",
+		}
+	}
+
+	fn is_reserved(self, name: &str) -> bool {
+		let (reserved, prefixes): (&[&str], &[&str]) = match self {
+			Dialect::Glsl => (GLSL_RESERVED, &[
+				"vec", "ivec", "uvec", "bvec", "dvec", "mat", "dmat", "sampler", "isampler", "usampler",
+				"image", "iimage", "uimage", "texture", "itexture", "utexture", "subpassInput",
+			]),
+			Dialect::Hlsl => (HLSL_RESERVED, &["float", "int", "uint", "bool", "half", "double", "min16", "min10", "min12"]),
+			Dialect::Msl => (MSL_RESERVED, &["float", "int", "uint", "bool", "half", "short", "ushort", "char", "uchar", "long", "ulong"]),
+		};
+		// the vector, matrix and texture types, like `float3`, `int2x2` or (in GLSL) `usampler2DArray`
+		let type_name = prefixes.iter()
+			.filter_map(|prefix| name.strip_prefix(prefix))
+			.any(|rest| rest.starts_with(|c: char| c.is_ascii_digit() || (self == Dialect::Glsl && c.is_ascii_uppercase())));
+		let texture = match self {
+			Dialect::Msl => name.starts_with("packed_") || name.starts_with("texture") || name.starts_with("depth"),
+			_ => false,
+		};
+		type_name || texture || reserved.contains(&name)
+	}
+
+	/// Turns a Yuri name (which might be qualified, like `lighting.shade`) into something the language accepts.
+	pub(crate) fn sanitize(self, name: &str) -> String {
+		let mut sanitized = identifier(name);
+		if self == Dialect::Glsl && sanitized.starts_with("gl_") {
+			sanitized.insert(0, '_');
+		}
+		if self.is_reserved(&sanitized) {
+			sanitized.push('_');
+		}
+		sanitized
+	}
+
+	/// How many bytes a member takes up in the uniform buffer, going by the language's own rules,
+	/// which is where the next member would go if nothing was in between.
+	/// HLSL can pack a scalar into the end of a `float3x3`, but starts a new register after a struct.
+	fn uniform_size(self, ty: &YuriType, packed: bool) -> u32 {
+		match (self, ty) {
+			(Dialect::Hlsl, YuriType::Matrix(size)) => 16 * (size.count() - 1) + 4 * size.count(),
+			(Dialect::Msl, YuriType::Vector(_, CompositeSize::Three)) if !packed => 16,
+			_ => std140_layout(ty).unwrap().0,
+		}
+	}
+
+	/// Where the language would put a member of the type if it came right after `offset` bytes of others,
+	/// as far as that's certain. MSL's arrays and structs just get padded up to where they go.
+	fn placement(self, ty: &YuriType, packed: bool, offset: u32) -> Option<u32> {
+		Some(match (self, ty) {
+			(_, YuriType::Scalar(_)) => offset,
+			// HLSL doesn't let a vector straddle two registers
+			(Dialect::Hlsl, YuriType::Vector(_, size)) if offset % 16 + 4 * size.count() > 16 => round_up(offset, 16),
+			(Dialect::Hlsl, YuriType::Vector(..)) => offset,
+			// and everything else starts on a register of its own
+			(Dialect::Hlsl, _) => round_up(offset, 16),
+			(Dialect::Msl, YuriType::Vector(_, CompositeSize::Three)) if packed => offset,
+			(Dialect::Msl, YuriType::Vector(_, CompositeSize::Two)) => round_up(offset, 8),
+			(Dialect::Msl, YuriType::Vector(..) | YuriType::Matrix(_)) => round_up(offset, 16),
+			// GLSL's block is std140 already
+			(Dialect::Glsl | Dialect::Msl, _) => return None,
+		})
+	}
+
+	/// Why the language can't lay out a prop of the type like std140, if it can't. GLSL and HLSL always can,
+	/// but MSL array elements are only as far apart as they are big, and `float2x2` columns are 8 bytes apart.
+	fn std140_mismatch(self, ty: &YuriType) -> Option<&'static str> {
+		let supported = match (self, ty) {
+			(Dialect::Glsl | Dialect::Hlsl, _) => true,
+			(Dialect::Msl, YuriType::Matrix(CompositeSize::Two)) => false,
+			(Dialect::Msl, YuriType::Array(inner, _)) => {
+				!matches!(**inner, YuriType::Scalar(_) | YuriType::Vector(_, CompositeSize::Two)) && self.std140_mismatch(inner).is_none()
+			}
+			(Dialect::Msl, YuriType::Complex(fields)) => fields.iter().all(|f| self.std140_mismatch(&f.field_type).is_none()),
+			_ => true,
+		};
+		(!supported).then_some("array elements are only as far apart as they are big, and `m2` columns are only 8 bytes apart")
+	}
+
+	/// Lays out a struct in the uniform buffer, with padding wherever the language wouldn't put a member
+	/// where std140 does. MSL gets a `packed_float3` when something goes in the last 4 bytes of a `float3`,
+	/// and padding at the end of structs (`pad`), since that's as far apart as array elements are.
+	fn uniform_members(self, types: &[&YuriType], pad: bool) -> Vec<Member> {
+		if self == Dialect::Glsl {
+			return (0..types.len()).map(|index| Member::Field { index, packed: false }).collect();
+		}
+		let offsets = std140_offsets(types.iter().copied());
+		let end = match (types.last(), offsets.last()) {
+			(Some(ty), Some(offset)) => offset + std140_layout(ty).unwrap().0,
+			_ => 0,
+		};
+		let size = round_up(end, 16);
+		let mut members = Vec::new();
+		let mut offset = 0;
+		for (index, (ty, target)) in types.iter().zip(&offsets).enumerate() {
+			let next = offsets.get(index + 1).copied().unwrap_or(if pad { size } else { end });
+			let packed = self == Dialect::Msl
+				&& matches!(ty, YuriType::Vector(_, CompositeSize::Three))
+				&& next < target + 16;
+			if offset < *target && self.placement(ty, packed, offset) != Some(*target) {
+				members.push(Member::Padding(target - offset));
+			}
+			members.push(Member::Field { index, packed });
+			offset = target + self.uniform_size(ty, packed);
+		}
+		if pad && self == Dialect::Msl && offset < size {
+			members.push(Member::Padding(size - offset));
+		}
+		members
+	}
+}
+
+/// How a struct's members get declared: its fields in order, with padding in between where it needs some.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Member {
+	/// A `packed` field is a `packed_float3` instead of a `float3`, which takes up 12 bytes instead of 16 (MSL only).
+	Field { index: usize, packed: bool },
+	/// Bytes nobody looks at.
+	Padding(u32),
+}
+
+/// Something a function needs from outside of it: a texture, a sampler, the uniform buffer, or a builtin input.
+/// MSL doesn't have any global variables, so it gets passed to every function that (eventually) uses it,
+/// under the same name each time. GLSL and HLSL have it all at the top instead.
+#[derive(Debug, Clone)]
+pub(crate) struct Resource {
+	pub name: String,
+	/// How it gets declared as a parameter, like `texture2d<float> tex`.
+	pub parameter: String,
+	/// How the entry point gets it, like `[[texture(0)]]` (or the semantic, for HLSL's builtin inputs).
+	pub attribute: String,
+}
+
+pub(crate) struct Emitter<'a> {
+	pub program: &'a TypedProgram,
+	pub dialect: Dialect,
+	pub stage: ShaderStage,
+	target: Target,
+	/// The resources each function needs, as indices into [Emitter::resources].
+	pub function_resources: HashMap<FunctionId, BTreeSet<usize>>,
+	/// The structs declared for complexes (and for arrays, in HLSL, since they can't be returned otherwise).
+	structs: HashMap<YuriType, String>,
+	/// How each struct's members are declared, by its name.
+	members: HashMap<String, Vec<Member>>,
+	/// The resources each sampler prop takes. The rest are in the uniform buffer.
+	sampler_resources: HashMap<PropId, Vec<usize>>,
+	/// The uniform buffer, if there is one.
+	uniforms: Option<usize>,
+	pub resources: Vec<Resource>,
+	pub builtins: Vec<(BuiltinInput, usize)>,
+	/// The resources the current function has used so far.
+	used: BTreeSet<usize>,
+	pub writer: Writer,
+}
+
+impl<'a> Emit<'a> for Emitter<'a> {
+	fn writer(&self) -> &Writer {
+		&self.writer
+	}
+
+	fn writer_mut(&mut self) -> &mut Writer {
+		&mut self.writer
+	}
+
+	fn program(&self) -> &'a TypedProgram {
+		self.program
+	}
+
+	fn stage(&self) -> ShaderStage {
+		self.stage
+	}
+
+	fn language(&self) -> &'static str {
+		self.dialect.name()
+	}
+
+	fn sanitize(&self, name: &str) -> String {
+		self.dialect.sanitize(name)
+	}
+
+	fn type_name(&mut self, ty: &YuriType) -> String {
+		let scalar = |number_type: &NumberType| match number_type {
+			NumberType::Float => "float",
+			NumberType::Signed => "int",
+			NumberType::Unsigned => "uint",
+		};
+		let prefix = |number_type: &NumberType| match number_type {
+			NumberType::Float => "",
+			NumberType::Signed => "i",
+			NumberType::Unsigned => "u",
+		};
+		match ty {
+			YuriType::Unit => "void".to_string(),
+			YuriType::Bool => "bool".to_string(),
+			YuriType::Scalar(number_type) => scalar(number_type).to_string(),
+			YuriType::Vector(number_type, size) if self.dialect == Dialect::Glsl => format!("{}vec{}", prefix(number_type), size.count()),
+			YuriType::Vector(number_type, size) => format!("{}{}", scalar(number_type), size.count()),
+			YuriType::Matrix(size) if self.dialect == Dialect::Glsl => format!("mat{}", size.count()),
+			YuriType::Matrix(size) => format!("float{0}x{0}", size.count()),
+			YuriType::Sampler(dimension) => match (self.dialect, dimension) {
+				(Dialect::Glsl, SamplerDimension::One) => "sampler1D",
+				(Dialect::Glsl, SamplerDimension::Two) => "sampler2D",
+				(Dialect::Glsl, SamplerDimension::Three) => "sampler3D",
+				(Dialect::Glsl, SamplerDimension::Cube) => "samplerCube",
+				(Dialect::Hlsl, SamplerDimension::One) => "Texture1D<float4>",
+				(Dialect::Hlsl, SamplerDimension::Two) => "Texture2D<float4>",
+				(Dialect::Hlsl, SamplerDimension::Three) => "Texture3D<float4>",
+				(Dialect::Hlsl, SamplerDimension::Cube) => "TextureCube<float4>",
+				(Dialect::Msl, SamplerDimension::One) => "texture1d<float>",
+				(Dialect::Msl, SamplerDimension::Two) => "texture2d<float>",
+				(Dialect::Msl, SamplerDimension::Three) => "texture3d<float>",
+				(Dialect::Msl, SamplerDimension::Cube) => "texturecube<float>",
+			}.to_string(),
+			YuriType::Array(inner, length) => match self.dialect {
+				Dialect::Glsl => {
+					// the outermost length goes first
+					let mut lengths = format!("[{}]", fixed_length(length));
+					let mut element = inner.as_ref();
+					while let YuriType::Array(inner, length) = element {
+						let _ = write!(lengths, "[{}]", fixed_length(length));
+						element = inner;
+					}
+					format!("{}{lengths}", self.type_name(element))
+				}
+				Dialect::Hlsl => self.struct_name(ty),
+				Dialect::Msl => format!("array<{}, {}>", self.type_name(inner), fixed_length(length)),
+			},
+			YuriType::Complex(_) => self.struct_name(ty),
+		}
+	}
+
+	fn declaration(&mut self, name: &str, ty: &YuriType, value: Option<&str>, _mutable: bool) -> String {
+		let ty = self.type_name(ty);
+		match value {
+			Some(value) => format!("{ty} {name} = {value};"),
+			None => format!("{ty} {name};"),
+		}
+	}
+
+	fn suffix(&self, number_type: NumberType) -> &'static str {
+		match number_type {
+			NumberType::Unsigned => "u",
+			_ => "",
+		}
+	}
+
+	fn float_bits(&self, bits: u32) -> Option<String> {
+		Some(match self.dialect {
+			Dialect::Glsl => format!("uintBitsToFloat({bits:#x}u)"),
+			Dialect::Hlsl => format!("asfloat({bits:#x}u)"),
+			Dialect::Msl => format!("as_type<float>({bits:#x}u)"),
+		})
+	}
+
+	fn specializable(&self) -> bool {
+		match self.dialect {
+			Dialect::Glsl => self.target.env().is_vulkan(),
+			Dialect::Hlsl => false,
+			Dialect::Msl => true,
+		}
+	}
+
+	fn global_declaration(&mut self, name: &str, ty: &YuriType, value: Code, kind: GlobalKind) -> String {
+		let ty = self.type_name(ty);
+		let value = value.text;
+		match (self.dialect, kind) {
+			(Dialect::Glsl, GlobalKind::Spec(spec_id)) => format!("layout(constant_id = {spec_id}) const {ty} {name} = {value};"),
+			(Dialect::Glsl, _) => format!("const {ty} {name} = {value};"),
+			(Dialect::Hlsl, _) => format!("static const {ty} {name} = {value};"),
+			// a function constant doesn't have a default of its own, it's just not defined if it isn't set
+			(Dialect::Msl, GlobalKind::Spec(spec_id)) => {
+				let constant = self.global_name(&format!("{name}_spec"));
+				format!(
+					"constant {ty} {constant} [[function_constant({spec_id})]];\n\
+					constant {ty} {name} = is_function_constant_defined({constant}) ? {constant} : {value};",
+				)
+			}
+			(Dialect::Msl, _) => format!("constant {ty} {name} = {value};"),
+		}
+	}
+
+	fn splat(&mut self, value: Code, from: &YuriType, to: &YuriType) -> Code {
+		match (from, to, self.dialect) {
+			// HLSL wants every component when constructing, but a cast repeats it
+			(YuriType::Scalar(_), YuriType::Vector(..), Dialect::Hlsl) => Code::compound(format!("({}){}", self.type_name(to), value.operand())),
+			(YuriType::Scalar(_), YuriType::Vector(..), _) => Code::atom(format!("{}({})", self.type_name(to), value.text)),
+			_ => value,
+		}
+	}
+
+	fn conditional(&mut self, ty: &YuriType, condition: &Code, values: [&Code; 2], _branches: [&TypedExpression; 2]) -> Option<Code> {
+		// the conditional operator only works out the side it picks, but HLSL only has it for numbers
+		let conditional = match self.dialect {
+			Dialect::Hlsl => matches!(ty, YuriType::Bool | YuriType::Scalar(_) | YuriType::Vector(..) | YuriType::Matrix(_)),
+			Dialect::Glsl | Dialect::Msl => true,
+		};
+		conditional.then(|| Code::compound(format!("{} ? {} : {}", condition.operand(), values[0].operand(), values[1].operand())))
+	}
+
+	fn operation(
+		&mut self,
+		operator: BinaryOperator,
+		[lhs, rhs]: [&TypedExpression; 2],
+		[a, b]: [&Code; 2],
+		ty: &YuriType,
+	) -> Result<Option<Code>, YuriSemanticError> {
+		// a matrix times a matrix or a vector, as opposed to a scalar
+		let matrices = matches!(
+			(&lhs.expression_type, &rhs.expression_type),
+			(YuriType::Matrix(_), YuriType::Matrix(_) | YuriType::Vector(..)) | (YuriType::Vector(..), YuriType::Matrix(_))
+		);
+		let number_type = number_type(ty);
+		Ok(match (operator, self.dialect) {
+			// HLSL's matrices are Yuri's transposed, so (a * b)^T = b^T * a^T, for vectors too
+			(BinaryOperator::Times, Dialect::Hlsl) if matrices => Some(Code::atom(format!("mul({}, {})", b.text, a.text))),
+			// GLSL's `mod` rounds down and its `%` isn't defined for negative numbers,
+			// but Yuri's `%` follows the sign of the left side (like Rust, HLSL and MSL)
+			(BinaryOperator::Modulo, Dialect::Glsl) if number_type != Some(NumberType::Unsigned) => {
+				let a = self.hoist(a.clone(), &lhs.expression_type);
+				let b = self.hoist(b.clone(), &rhs.expression_type);
+				let quotient = if number_type == Some(NumberType::Float) {
+					format!("trunc({a} / {b})")
+				} else {
+					format!("({a} / {b})")
+				};
+				Some(Code::compound(format!("{a} - {b} * {quotient}")))
+			}
+			// MSL only has `%` for integers
+			(BinaryOperator::Modulo, Dialect::Msl) if number_type == Some(NumberType::Float) => {
+				let a = self.splat(a.clone(), &lhs.expression_type, ty);
+				let b = self.splat(b.clone(), &rhs.expression_type, ty);
+				Some(Code::atom(format!("fmod({}, {})", a.text, b.text)))
+			}
+			_ => None,
+		})
+	}
+
+	fn builtin(&mut self, function: BuiltinFunction, arguments: &[TypedExpression], location: &Range<usize>) -> Result<Code, YuriSemanticError> {
+		use BuiltinFunction::*;
+		let glsl = self.dialect == Dialect::Glsl;
+		let hlsl = self.dialect == Dialect::Hlsl;
+		let name = match function {
+			Inverse if !glsl => return Err(self.missing(function, location)),
+			Sample if glsl => {
+				let arguments = self.arguments(arguments)?;
+				return Ok(Code::atom(if implicit_lod(self.stage) {
+					format!("texture({arguments})")
+				} else {
+					format!("textureLod({arguments}, 0.0)")
+				}));
+			}
+			Sample => {
+				let sampler = self.expression(&arguments[0])?;
+				let coordinates = self.expression(&arguments[1])?;
+				let (texture, sampler) = sampler.text.split_once(", ").expect("samplers are a texture and a sampler");
+				let one = arguments[0].expression_type == YuriType::Sampler(SamplerDimension::One);
+				let implicit = implicit_lod(self.stage);
+				return Ok(Code::atom(match self.dialect {
+					Dialect::Hlsl if implicit => format!("{texture}.Sample({sampler}, {})", coordinates.text),
+					Dialect::Hlsl => format!("{texture}.SampleLevel({sampler}, {}, 0.0)", coordinates.text),
+					// Metal's 1D textures don't have mipmaps anyway
+					_ if implicit || one => format!("{texture}.sample({sampler}, {})", coordinates.text),
+					_ => format!("{texture}.sample({sampler}, {}, level(0.0))", coordinates.text),
+				}));
+			}
+			// the interpolant (or the edges) can be scalars in Yuri and GLSL, but not in HLSL or MSL
+			Mix | SmoothStep if !glsl => {
+				let ty = &arguments[if function == Mix { 0 } else { 2 }].expression_type;
+				let arguments = self.splatted(arguments, ty)?;
+				let name = if function == Mix && hlsl { "lerp" } else { function.name() };
+				return Ok(Code::atom(format!("{name}({})", arguments.join(", "))));
+			}
+			Sign if !glsl => {
+				let ty = &arguments[0].expression_type;
+				let value = self.expression(&arguments[0])?;
+				let float = number_type(ty) == Some(NumberType::Float);
+				return Ok(match (self.dialect, float) {
+					// HLSL's `sign` always gives back integers
+					(Dialect::Hlsl, true) => Code::compound(format!("({})sign({})", self.type_name(ty), value.text)),
+					// and MSL's only takes floats
+					(Dialect::Msl, false) => {
+						let signed = YuriType::Scalar(NumberType::Signed);
+						let low = self.splat(Code::compound("-1"), &signed, ty);
+						let high = self.splat(Code::atom("1"), &signed, ty);
+						Code::atom(format!("clamp({}, {}, {})", value.text, low.text, high.text))
+					}
+					_ => Code::atom(format!("sign({})", value.text)),
+				});
+			}
+			Fract if hlsl => "frac",
+			// halfway cases go to the even number, which is what HLSL's `round` does already
+			Round if glsl => "roundEven",
+			Round if !hlsl => "rint",
+			InverseSqrt if glsl => "inversesqrt",
+			InverseSqrt => "rsqrt",
+			Atan2 if glsl => "atan",
+			Atan2 => "atan2",
+			other => other.name(),
+		};
+		let arguments = self.arguments(arguments)?;
+		Ok(Code::atom(format!("{name}({arguments})")))
+	}
+
+	/// GLSL has a variable for each builtin input. For HLSL and MSL they're only parameters of the entry point:
+	/// HLSL copies them into static variables, MSL passes them along to whatever needs them.
+	fn builtin_input(&mut self, input: BuiltinInput, location: &Range<usize>) -> Result<String, YuriSemanticError> {
+		if self.dialect == Dialect::Glsl {
+			let vulkan = self.target.env().is_vulkan();
+			return Ok(match input {
+				BuiltinInput::FragCoord => "gl_FragCoord",
+				BuiltinInput::FrontFacing => "gl_FrontFacing",
+				// OpenGL's gl_VertexID includes the base vertex, just like Vulkan's gl_VertexIndex
+				BuiltinInput::VertexIndex if vulkan => "uint(gl_VertexIndex)",
+				BuiltinInput::VertexIndex => "uint(gl_VertexID)",
+				BuiltinInput::InstanceIndex if vulkan => "uint(gl_InstanceIndex)",
+				// but its gl_InstanceID doesn't include the base instance
+				BuiltinInput::InstanceIndex => {
+					return Err(unsupported(
+						format!(
+							"`@{}` (at %) isn't available when targeting {}, since it wouldn't include the base instance",
+							input.name(), self.target.env(),
+						),
+						location,
+					));
+				}
+			}.to_string());
+		}
+		let (name, ty, hlsl_semantic, msl_attribute) = match input {
+			BuiltinInput::FragCoord => ("frag_coord", "float4", "SV_Position", "[[position]]"),
+			BuiltinInput::FrontFacing => ("front_facing", "bool", "SV_IsFrontFace", "[[front_facing]]"),
+			BuiltinInput::VertexIndex => ("vertex_index", "uint", "SV_VertexID", "[[vertex_id]]"),
+			BuiltinInput::InstanceIndex => ("instance_index", "uint", "SV_InstanceID", "[[instance_id]]"),
+		};
+		// D3D leaves out the vertex offset of indexed draws, and the first instance, unlike Vulkan and Metal
+		let missing = match input {
+			BuiltinInput::VertexIndex => "the base vertex",
+			_ => "the first instance",
+		};
+		if self.dialect == Dialect::Hlsl && matches!(input, BuiltinInput::VertexIndex | BuiltinInput::InstanceIndex) {
+			return Err(unsupported(
+				format!("`@{}` (at %) isn't available in HLSL, since {hlsl_semantic} doesn't include {missing}", input.name()),
+				location,
+			));
+		}
+		let resource = match self.builtins.iter().find(|(i, _)| *i == input) {
+			Some((_, resource)) => *resource,
+			None => {
+				let variable = self.global_name(name);
+				let resource = match self.dialect {
+					Dialect::Hlsl => {
+						let _ = writeln!(self.writer.global_declarations, "static {ty} {variable};");
+						self.resource(variable, format!("{ty} {name}"), hlsl_semantic.to_string())
+					}
+					_ => self.resource(variable.clone(), format!("{ty} {variable}"), msl_attribute.to_string()),
+				};
+				self.builtins.push((input, resource));
+				resource
+			}
+		};
+		self.used.insert(resource);
+		// flipping it takes the viewport height
+		if input == BuiltinInput::FragCoord && self.writer.viewport_height.is_some() {
+			self.used.extend(self.uniforms);
+		}
+		Ok(self.resources[resource].name.clone())
+	}
+
+	fn declare_sampler(&mut self, id: PropId, name: &str, ty: &YuriType, binding: u32) -> String {
+		let (sampler_space, _) = descriptor_sets(self.stage);
+		let ty = self.type_name(ty);
+		let texture = self.global_name(name);
+		if self.dialect == Dialect::Glsl {
+			let layout = self.binding(sampler_space, binding);
+			let _ = writeln!(self.writer.resource_declarations, "layout({layout}) uniform {ty} {texture};");
+			self.sampler_resources.insert(id, Vec::new());
+			return texture;
+		}
+		let sampler = self.global_name(&format!("{name}_sampler"));
+		let resources = match self.dialect {
+			Dialect::Hlsl => {
+				let _ = writeln!(self.writer.resource_declarations, "{ty} {texture} : register(t{binding}, space{sampler_space});");
+				let _ = writeln!(self.writer.resource_declarations, "SamplerState {sampler} : register(s{binding}, space{sampler_space});");
+				Vec::new()
+			}
+			_ => vec![
+				self.resource(texture.clone(), format!("{ty} {texture}"), format!("[[texture({binding})]]")),
+				self.resource(sampler.clone(), format!("sampler {sampler}"), format!("[[sampler({binding})]]")),
+			],
+		};
+		self.sampler_resources.insert(id, resources);
+		format!("{texture}, {sampler}")
+	}
+
+	fn std140_mismatch(&self, ty: &YuriType) -> Option<&'static str> {
+		self.dialect.std140_mismatch(ty)
+	}
+
+	fn declare_uniforms(&mut self, block: &str, variable: &str, types: &[&YuriType], names: &[String]) -> Vec<String> {
+		let (_, uniform_space) = descriptor_sets(self.stage);
+		let layout = self.dialect.uniform_members(types, false);
+		let declaration = self.struct_members(&layout, names, types);
+		let (parameter, attribute) = match self.dialect {
+			Dialect::Glsl => {
+				let layout = self.binding(uniform_space, 0);
+				let _ = writeln!(self.writer.resource_declarations, "layout(std140, {layout}) uniform {block} {{\n{declaration}}} {variable};");
+				(String::new(), String::new())
+			}
+			Dialect::Hlsl => {
+				let _ = writeln!(self.writer.struct_declarations, "struct {block} {{\n{declaration}}};");
+				let _ = writeln!(self.writer.resource_declarations, "ConstantBuffer<{block}> {variable} : register(b0, space{uniform_space});");
+				(String::new(), String::new())
+			}
+			Dialect::Msl => {
+				let _ = writeln!(self.writer.struct_declarations, "struct {block} {{\n{declaration}}};");
+				(format!("constant {block}& {variable}"), "[[buffer(0)]]".to_string())
+			}
+		};
+		self.uniforms = Some(self.resource(variable.to_string(), parameter, attribute));
+		layout.into_iter()
+			.filter_map(|member| match member {
+				Member::Field { index, packed: true } => Some(format!("float3({variable}.{})", names[index])),
+				Member::Field { index, packed: false } => Some(format!("{variable}.{}", names[index])),
+				Member::Padding(_) => None,
+			})
+			.collect()
+	}
+
+	fn sampler_type(&self) -> Option<&'static str> {
+		match self.dialect {
+			// GLSL's samplers are the texture and the sampler together
+			Dialect::Glsl => None,
+			Dialect::Hlsl => Some("SamplerState"),
+			Dialect::Msl => Some("sampler"),
+		}
+	}
+
+	fn signature(&mut self, id: FunctionId, name: &str, mut parameters: Vec<String>) -> String {
+		let used = std::mem::take(&mut self.used);
+		if self.dialect == Dialect::Msl {
+			parameters.extend(used.iter().map(|r| self.resources[*r].parameter.clone()));
+		}
+		self.function_resources.insert(id, used);
+		let return_type = self.type_name(&self.program.functions[id].return_type);
+		format!("{return_type} {name}({})", parameters.join(", "))
+	}
+
+	/// Metal doesn't negate or divide matrices.
+	fn matrix_arithmetic(&self) -> bool {
+		self.dialect != Dialect::Msl
+	}
+
+	/// HLSL wants every component.
+	fn splats_constants(&self) -> bool {
+		self.dialect != Dialect::Hlsl
+	}
+
+	/// HLSL only has initializer lists for structs (and arrays), and MSL has braces instead of parentheses.
+	fn initializer(&mut self, ty: &YuriType, values: Vec<String>) -> String {
+		let name = self.type_name(ty);
+		if self.dialect == Dialect::Glsl {
+			return format!("{name}({})", values.join(", "));
+		}
+		let members = match ty {
+			YuriType::Complex(_) => self.members[&name].clone(),
+			_ => (0..values.len()).map(|index| Member::Field { index, packed: false }).collect(),
+		};
+		let mut components = Vec::new();
+		for member in members {
+			match (self.dialect, member) {
+				(Dialect::Msl, Member::Padding(_)) => components.push("{}".to_string()),
+				(_, Member::Padding(bytes)) => components.extend((0..bytes / 4).map(|_| "0.0".to_string())),
+				(Dialect::Msl, Member::Field { index, packed: true }) => components.push(format!("packed_float3({})", values[index])),
+				(_, Member::Field { index, .. }) => components.push(values[index].clone()),
+			}
+		}
+		match self.dialect {
+			Dialect::Msl => format!("{name}{{{}}}", components.join(", ")),
+			_ => format!("{{ {} }}", components.join(", ")),
+		}
+	}
+
+	/// An initializer list only works in a declaration.
+	fn composite(&mut self, value: Code, ty: &YuriType) -> Code {
+		match self.dialect {
+			Dialect::Hlsl => Code::atom(self.variable("tmp", ty, Some(&value.text))),
+			_ => value,
+		}
+	}
+
+	/// They all convert the same way, HLSL just does it with a cast.
+	fn convert(&mut self, value: Code, ty: &YuriType) -> Code {
+		match self.dialect {
+			Dialect::Hlsl => Code::compound(format!("({}){}", self.type_name(ty), value.operand())),
+			_ => Code::atom(format!("{}({})", self.type_name(ty), value.text)),
+		}
+	}
+
+	fn property(&mut self, id: PropId) -> String {
+		match self.sampler_resources.get(&id) {
+			Some(resources) => self.used.extend(resources),
+			None => self.used.extend(self.uniforms),
+		}
+		self.writer.props[&id].clone()
+	}
+
+	/// Hands the function the resources it needs, too.
+	fn call(&mut self, function: FunctionId, mut arguments: Vec<String>) -> String {
+		let resources = self.function_resources[&function].clone();
+		if self.dialect == Dialect::Msl {
+			arguments.extend(resources.iter().map(|r| self.resources[*r].name.clone()));
+		}
+		self.used.extend(resources);
+		format!("{}({})", self.writer.functions[&function], arguments.join(", "))
+	}
+
+	/// A `packed_float3` has to be unpacked to be used as a vector.
+	fn field(&mut self, value: Code, ty: &YuriType, index: usize) -> Code {
+		let YuriType::Complex(fields) = ty else {
+			unreachable!("only complexes have fields");
+		};
+		let field = format!("{}.{}", value.operand(), self.dialect.sanitize(&fields[index].name));
+		let name = self.struct_name(ty);
+		if self.members[&name].contains(&Member::Field { index, packed: true }) {
+			Code::atom(format!("float3({field})"))
+		} else {
+			Code::atom(field)
+		}
+	}
+
+	/// HLSL's arrays are in a struct.
+	fn elements(&mut self, value: Code, ty: &YuriType, _dynamic: bool) -> String {
+		match (self.dialect, ty) {
+			(Dialect::Hlsl, YuriType::Array(..)) => format!("{}.value", value.operand()),
+			_ => value.operand(),
+		}
+	}
+}
+
+impl<'a> Emitter<'a> {
+	pub fn new(program: &'a TypedProgram, dialect: Dialect, stage: ShaderStage, target: Target) -> Self {
+		Self {
+			program,
+			dialect,
+			stage,
+			target,
+			function_resources: HashMap::new(),
+			structs: HashMap::new(),
+			members: HashMap::new(),
+			sampler_resources: HashMap::new(),
+			uniforms: None,
+			resources: Vec::new(),
+			builtins: Vec::new(),
+			used: BTreeSet::new(),
+			writer: Writer::default(),
+		}
+	}
+
+	/// The type of a struct member, which can be laid out differently than the same type anywhere else.
+	/// HLSL's matrices are transposed (see [Emit::operation]), so the columns end up where std140 puts them.
+	fn member_type(&mut self, ty: &YuriType, packed: bool) -> String {
+		let name = self.type_name(ty);
+		match (self.dialect, ty) {
+			(Dialect::Hlsl, YuriType::Matrix(_)) => format!("row_major {name}"),
+			(Dialect::Msl, _) if packed => format!("packed_{name}"),
+			_ => name,
+		}
+	}
+
+	/// Complex types are structural, but all three languages want a struct declared for each one.
+	/// HLSL also gets a struct for each array type, with the elements in `value`,
+	/// since functions can't return arrays there, and an array can't be written as an expression.
+	fn struct_name(&mut self, ty: &YuriType) -> String {
+		let ty = without_annotations(ty);
+		if let Some(name) = self.structs.get(&ty) {
+			return name.clone();
+		}
+		let name;
+		let mut declaration = String::new();
+		match &ty {
+			YuriType::Array(inner, length) => {
+				let element = self.member_type(inner, false);
+				name = self.global_name(&format!("Array{}", self.structs.len()));
+				let _ = writeln!(declaration, "\t{element} value[{}];", fixed_length(length));
+				self.members.insert(name.clone(), vec![Member::Field { index: 0, packed: false }]);
+			}
+			YuriType::Complex(fields) => {
+				let types: Vec<&YuriType> = fields.iter().map(|f| &f.field_type).collect();
+				let members = if self.writer.uniform_structs.contains(&ty) {
+					self.dialect.uniform_members(&types, true)
+				} else {
+					(0..fields.len()).map(|index| Member::Field { index, packed: false }).collect()
+				};
+				let names: Vec<String> = fields.iter().map(|f| self.dialect.sanitize(&f.name)).collect();
+				declaration = self.struct_members(&members, &names, &types);
+				name = self.global_name(&format!("Complex{}", self.structs.len()));
+				self.members.insert(name.clone(), members);
+			}
+			_ => unreachable!("only complexes and arrays are structs"),
+		}
+		let _ = writeln!(self.writer.struct_declarations, "struct {name} {{\n{declaration}}};");
+		self.structs.insert(ty, name.clone());
+		name
+	}
+
+	/// The member declarations of a struct, padding and all.
+	fn struct_members(&mut self, members: &[Member], names: &[String], types: &[&YuriType]) -> String {
+		let mut member_names: HashSet<String> = names.iter().cloned().collect();
+		let mut declaration = String::new();
+		for member in members {
+			match *member {
+				Member::Field { index, packed } => {
+					let ty = self.member_type(types[index], packed);
+					let _ = writeln!(declaration, "\t{ty} {};", names[index]);
+				}
+				// HLSL would put an array on a register of its own, and a scalar can go anywhere
+				Member::Padding(bytes) => match self.dialect {
+					Dialect::Msl => {
+						let _ = writeln!(declaration, "\tchar {}[{bytes}];", unique_member(&mut member_names, "_pad".to_string()));
+					}
+					_ => for _ in 0..bytes / 4 {
+						let _ = writeln!(declaration, "\tfloat {};", unique_member(&mut member_names, "_pad".to_string()));
+					},
+				},
+			}
+		}
+		declaration
+	}
+
+	fn resource(&mut self, name: String, parameter: String, attribute: String) -> usize {
+		self.resources.push(Resource { name, parameter, attribute });
+		self.resources.len() - 1
+	}
+
+	/// Where a GLSL resource goes. OpenGL only has bindings, same as in the SPIR-V.
+	fn binding(&self, set: u32, binding: u32) -> String {
+		if self.target.env().is_vulkan() {
+			format!("set = {set}, binding = {binding}")
+		} else {
+			format!("binding = {binding}")
+		}
+	}
+}
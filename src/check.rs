@@ -0,0 +1,2010 @@
+//! Type checking. This turns the syntax tree (plus the name resolution) into a typed IR,
+//! where every expression knows its type and every name has been replaced with whatever it refers to.
+//!
+//! Module-level `let`s are evaluated here too, since array lengths and loop counts depend on them.
+//! Everything is checked lazily, so declarations can be used before they're declared.
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::rc::Rc;
+use crate::builtin::{BuiltinFunction, BuiltinInput, FragOrigin};
+use crate::consteval::{ConstContext, ConstEvaluator, ConstValue};
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::import::ResolvedImports;
+use crate::lex::NumberSuffix;
+use crate::parse::{Annotation, AnnotationArgument, ArrayLength, BinaryOperator, Block, ComplexField, CompositeSize, Else, Expression, ExpressionKind, Literal, NumberType, Statement, UnaryOperator, YuriModule, YuriType};
+use crate::resolve::{ModuleId, NameResolution, ResolvedName, SymbolId, SymbolKind};
+use crate::source::SourceMap;
+
+pub type PropId = usize;
+pub type GlobalId = usize;
+pub type FunctionId = usize;
+/// Locals are numbered per function (or per global, for the rare global with a block in it).
+pub type LocalId = usize;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ShaderStage {
+	Vertex,
+	Fragment,
+}
+
+impl ShaderStage {
+	pub fn from_annotation(name: &str) -> Option<Self> {
+		match name {
+			"vert" => Some(ShaderStage::Vertex),
+			"frag" => Some(ShaderStage::Fragment),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedExpression {
+	pub kind: TypedExpressionKind,
+	pub expression_type: YuriType,
+	pub location: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedExpressionKind {
+	Constant(ConstValue),
+	Local(LocalId),
+	Global(GlobalId),
+	Property(PropId),
+	BuiltinInput(BuiltinInput),
+	Call {
+		function: FunctionId,
+		arguments: Vec<TypedExpression>,
+	},
+	Builtin {
+		function: BuiltinFunction,
+		arguments: Vec<TypedExpression>,
+	},
+	/// Builds a vector, matrix or array. Vectors take scalars and vectors (of the same number type),
+	/// matrices take their columns, arrays take their elements.
+	Construct(Vec<TypedExpression>),
+	/// Converts a scalar or vector to another number type (or to/from `bool`).
+	Convert(Box<TypedExpression>),
+	/// Repeats a scalar into every component of a vector.
+	Splat(Box<TypedExpression>),
+	Complex(Vec<TypedExpression>),
+	Array(Vec<TypedExpression>),
+	Swizzle {
+		target: Box<TypedExpression>,
+		components: Vec<u32>,
+	},
+	Field {
+		target: Box<TypedExpression>,
+		index: u32,
+	},
+	Index {
+		target: Box<TypedExpression>,
+		index: Box<TypedExpression>,
+	},
+	Unary {
+		operator: UnaryOperator,
+		operand: Box<TypedExpression>,
+	},
+	Binary {
+		operator: BinaryOperator,
+		lhs: Box<TypedExpression>,
+		rhs: Box<TypedExpression>,
+	},
+	Block {
+		bindings: Vec<(LocalId, TypedExpression)>,
+		tail: Box<TypedExpression>,
+	},
+	If {
+		condition: Box<TypedExpression>,
+		block: Box<TypedExpression>,
+		block_else: Option<Box<TypedExpression>>,
+	},
+	/// Builds an array by running the block `count` times. The index is always a `u`.
+	Loop {
+		index: LocalId,
+		count: u32,
+		block: Box<TypedExpression>,
+	},
+	/// Goes over an array, or over `0..items` if `items` is a number.
+	Fold {
+		accumulator: LocalId,
+		initial: Box<TypedExpression>,
+		item: LocalId,
+		items: Box<TypedExpression>,
+		block: Box<TypedExpression>,
+	},
+	Map {
+		item: LocalId,
+		items: Box<TypedExpression>,
+		block: Box<TypedExpression>,
+	},
+}
+
+impl TypedExpression {
+	pub(crate) fn new(kind: TypedExpressionKind, expression_type: YuriType, location: Range<usize>) -> Self {
+		Self { kind, expression_type, location }
+	}
+
+	pub fn unit(location: Range<usize>) -> Self {
+		Self::new(TypedExpressionKind::Constant(ConstValue::UNIT), YuriType::Unit, location)
+	}
+
+	/// Calls `f` on every expression directly inside this one.
+	pub fn for_each_child<'a>(&'a self, mut f: impl FnMut(&'a TypedExpression)) {
+		use TypedExpressionKind::*;
+		match &self.kind {
+			Constant(_) | Local(_) | Global(_) | Property(_) | BuiltinInput(_) => {}
+			Call { arguments, .. } | Builtin { arguments, .. } | Construct(arguments)
+			| Complex(arguments) | Array(arguments) => arguments.iter().for_each(f),
+			Convert(operand) | Splat(operand) | Unary { operand, .. }
+			| Swizzle { target: operand, .. } | Field { target: operand, .. } => f(operand),
+			Index { target: lhs, index: rhs } | Binary { lhs, rhs, .. } => {
+				f(lhs);
+				f(rhs);
+			}
+			Block { bindings, tail } => {
+				for (_, value) in bindings {
+					f(value);
+				}
+				f(tail);
+			}
+			If { condition, block, block_else } => {
+				f(condition);
+				f(block);
+				if let Some(block_else) = block_else {
+					f(block_else);
+				}
+			}
+			Loop { block, .. } => f(block),
+			Fold { initial, items, block, .. } => {
+				f(initial);
+				f(items);
+				f(block);
+			}
+			Map { items, block, .. } => {
+				f(items);
+				f(block);
+			}
+		}
+	}
+
+	/// Same as [TypedExpression::for_each_child], but the children can be changed.
+	pub fn for_each_child_mut(&mut self, mut f: impl FnMut(&mut TypedExpression)) {
+		use TypedExpressionKind::*;
+		match &mut self.kind {
+			Constant(_) | Local(_) | Global(_) | Property(_) | BuiltinInput(_) => {}
+			Call { arguments, .. } | Builtin { arguments, .. } | Construct(arguments)
+			| Complex(arguments) | Array(arguments) => arguments.iter_mut().for_each(f),
+			Convert(operand) | Splat(operand) | Unary { operand, .. }
+			| Swizzle { target: operand, .. } | Field { target: operand, .. } => f(operand),
+			Index { target: lhs, index: rhs } | Binary { lhs, rhs, .. } => {
+				f(lhs);
+				f(rhs);
+			}
+			Block { bindings, tail } => {
+				for (_, value) in bindings {
+					f(value);
+				}
+				f(tail);
+			}
+			If { condition, block, block_else } => {
+				f(condition);
+				f(block);
+				if let Some(block_else) = block_else {
+					f(block_else);
+				}
+			}
+			Loop { block, .. } => f(block),
+			Fold { initial, items, block, .. } => {
+				f(initial);
+				f(items);
+				f(block);
+			}
+			Map { items, block, .. } => {
+				f(items);
+				f(block);
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedLocal {
+	pub name: String,
+	pub local_type: YuriType,
+	pub location: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedProperty {
+	/// The fully-qualified name.
+	pub name: String,
+	pub property_type: YuriType,
+	pub annotations: Vec<Annotation>,
+	pub location: Range<usize>,
+	pub module: ModuleId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedGlobal {
+	/// The fully-qualified name.
+	pub name: String,
+	pub global_type: YuriType,
+	pub value: TypedExpression,
+	pub locals: Vec<TypedLocal>,
+	/// What the value works out to. Globals are always constant,
+	/// but if they depend on a specialization constant this is only the default.
+	pub constant: ConstValue,
+	/// The `SpecId` of the global, if it's a specialization constant (`@spec(3)`).
+	pub spec_id: Option<u32>,
+	/// The specialization constant the value depends on, if any (which might be the global itself).
+	pub specialization: Option<GlobalId>,
+	pub exported: bool,
+	pub annotations: Vec<Annotation>,
+	pub location: Range<usize>,
+	pub module: ModuleId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedFunction {
+	/// The fully-qualified name.
+	pub name: String,
+	pub arguments: Vec<LocalId>,
+	pub locals: Vec<TypedLocal>,
+	pub return_type: YuriType,
+	pub body: TypedExpression,
+	/// Set for entry points (functions annotated with `@vert` or `@frag`).
+	pub stage: Option<ShaderStage>,
+	/// Set if a fragment shader picked its own origin for `@frag.coord`, like `@frag(origin = lower_left)`.
+	/// Otherwise it's up to the target.
+	pub frag_origin: Option<FragOrigin>,
+	pub exported: bool,
+	pub annotations: Vec<Annotation>,
+	pub location: Range<usize>,
+	pub module: ModuleId,
+}
+
+/// Where an entry point's input or output goes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InterfaceSlot {
+	Location(u32),
+	/// The vertex position, marked with `@vert.pos`.
+	Position,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceVariable {
+	pub name: String,
+	pub variable_type: YuriType,
+	pub slot: InterfaceSlot,
+}
+
+/// The inputs and outputs of an entry point, as the pipeline sees them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInterface {
+	pub inputs: Vec<InterfaceVariable>,
+	pub outputs: Vec<InterfaceVariable>,
+	/// Whether the entry point returns a complex (with each field becoming an output),
+	/// as opposed to a single value.
+	pub complex_output: bool,
+}
+
+impl TypedFunction {
+	/// Whether the function is annotated with `@test`, so [crate::testing] runs it.
+	pub fn is_test(&self) -> bool {
+		self.annotations.iter().any(|a| a.name == "test")
+	}
+
+	/// Lays out the inputs and outputs of an entry point.
+	/// Inputs are the arguments in order, outputs are the fields of the return type in order.
+	pub fn interface(&self) -> Option<EntryInterface> {
+		let stage = self.stage?;
+		let inputs = self.arguments.iter()
+			.enumerate()
+			.map(|(i, id)| InterfaceVariable {
+				name: self.locals[*id].name.clone(),
+				variable_type: self.locals[*id].local_type.clone(),
+				slot: InterfaceSlot::Location(i as u32),
+			})
+			.collect();
+		let (outputs, complex_output) = match &self.return_type {
+			YuriType::Complex(fields) => {
+				let mut location = 0;
+				let outputs = fields.iter()
+					.map(|field| {
+						let slot = if stage == ShaderStage::Vertex && field.annotations.iter().any(|a| a == "vert.pos") {
+							InterfaceSlot::Position
+						} else {
+							location += 1;
+							InterfaceSlot::Location(location - 1)
+						};
+						InterfaceVariable { name: field.name.clone(), variable_type: field.field_type.clone(), slot }
+					})
+					.collect();
+				(outputs, true)
+			}
+			YuriType::Unit => (Vec::new(), false),
+			other => {
+				let slot = if stage == ShaderStage::Vertex { InterfaceSlot::Position } else { InterfaceSlot::Location(0) };
+				(vec![InterfaceVariable { name: "out".to_string(), variable_type: other.clone(), slot }], false)
+			}
+		};
+		Some(EntryInterface { inputs, outputs, complex_output })
+	}
+}
+
+/// A fully type-checked shader, including everything it imports.
+/// IDs are assigned in the same order as the symbols in the [NameResolution].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedProgram {
+	pub properties: Vec<TypedProperty>,
+	pub globals: Vec<TypedGlobal>,
+	pub functions: Vec<TypedFunction>,
+	/// Every file in the program, which is what all the locations point into.
+	pub sources: SourceMap,
+}
+
+impl TypedProgram {
+	pub fn entry_points(&self) -> impl Iterator<Item = (FunctionId, &TypedFunction)> {
+		self.functions.iter()
+			.enumerate()
+			.filter(|(_, function)| function.stage.is_some())
+	}
+}
+
+fn error(error_type: YuriSemanticErrorType, description: String, markers: Vec<Range<usize>>) -> YuriSemanticError {
+	YuriSemanticError { error_type, description: Some(description), markers }
+}
+
+fn mismatch(description: String, location: &Range<usize>) -> YuriSemanticError {
+	error(YuriSemanticErrorType::TypeMismatch, description, vec![location.clone()])
+}
+
+/// Reads the ID out of a `@spec(3)` annotation, if there is one.
+fn spec_id(annotations: &[Annotation]) -> Result<Option<u32>, YuriSemanticError> {
+	let Some(annotation) = annotations.iter().find(|a| a.name == "spec") else {
+		return Ok(None);
+	};
+	let id = match annotation.arguments.as_slice() {
+		[AnnotationArgument { name: None, value }] => match &value.kind {
+			ExpressionKind::Literal(
+				Literal::DecimalNumber(n, None | Some(NumberSuffix::Unsigned))
+				| Literal::HexNumber(n, None | Some(NumberSuffix::Unsigned))
+				| Literal::BinaryNumber(n, None | Some(NumberSuffix::Unsigned))
+			) => u32::try_from(*n).ok(),
+			_ => None,
+		},
+		_ => None,
+	};
+	id.map(Some).ok_or_else(|| error(
+		YuriSemanticErrorType::InvalidDeclaration,
+		"`@spec` needs the ID of the specialization constant, like `@spec(3)` (%)".to_string(),
+		vec![annotation.location.clone()],
+	))
+}
+
+/// Reads the arguments of an entry point annotation, the only one being `origin` on fragment shaders.
+fn entry_point_origin(annotation: &Annotation, stage: ShaderStage) -> Result<Option<FragOrigin>, YuriSemanticError> {
+	let mut origin = None;
+	for argument in &annotation.arguments {
+		let value = match (&argument.name, &argument.value.kind) {
+			(Some(name), ExpressionKind::Variable(value)) if name == "origin" && stage == ShaderStage::Fragment && origin.is_none() => {
+				FragOrigin::from_name(value)
+			}
+			_ => None,
+		};
+		let Some(value) = value else {
+			return Err(error(
+				YuriSemanticErrorType::InvalidEntryPoint,
+				format!(
+					"`@{}` (%) doesn't take that, the only option is `origin = upper_left` or `origin = lower_left` on fragment shaders",
+					annotation.name,
+				),
+				vec![annotation.location.clone()],
+			));
+		};
+		origin = Some(value);
+	}
+	Ok(origin)
+}
+
+fn reject_spec(annotations: &[Annotation], what: &str) -> Result<(), YuriSemanticError> {
+	match annotations.iter().find(|a| a.name == "spec") {
+		Some(annotation) => Err(error(
+			YuriSemanticErrorType::InvalidDeclaration,
+			format!("Only `let`s can be specialization constants, {what} can't (%)"),
+			vec![annotation.location.clone()],
+		)),
+		None => Ok(()),
+	}
+}
+
+/// Whether a global depending on a specialization constant can be built with `OpSpecConstantOp`,
+/// which can only do integer and bool operations (in shaders, anyway).
+/// Gives back the location of the first thing that can't be.
+fn check_specializable(expr: &TypedExpression) -> Result<(), Range<usize>> {
+	let integer_or_bool = |ty: &YuriType| matches!(
+		(ty, number_type(ty)),
+		(YuriType::Bool, _) | (_, Some(NumberType::Signed | NumberType::Unsigned))
+	);
+	let allowed = match &expr.kind {
+		TypedExpressionKind::Constant(_)
+		| TypedExpressionKind::Global(_)
+		| TypedExpressionKind::Splat(_)
+		| TypedExpressionKind::Construct(_)
+		| TypedExpressionKind::Complex(_)
+		| TypedExpressionKind::Array(_)
+		| TypedExpressionKind::Swizzle { .. }
+		| TypedExpressionKind::Field { .. } => true,
+		TypedExpressionKind::Index { index, .. } => matches!(index.kind, TypedExpressionKind::Constant(_)),
+		TypedExpressionKind::Block { bindings, .. } => bindings.is_empty(),
+		TypedExpressionKind::Unary { operand, .. } => integer_or_bool(&operand.expression_type),
+		TypedExpressionKind::Binary { operator, lhs, .. } => {
+			*operator != BinaryOperator::Exponent && integer_or_bool(&lhs.expression_type)
+		}
+		TypedExpressionKind::Convert(operand) => {
+			integer_or_bool(&operand.expression_type) && integer_or_bool(&expr.expression_type)
+		}
+		// picking between two values is fine, as long as there's no vector condition business
+		TypedExpressionKind::If { block_else, .. } => {
+			block_else.is_some() && matches!(expr.expression_type, YuriType::Scalar(_) | YuriType::Bool)
+		}
+		_ => false,
+	};
+	if !allowed {
+		return Err(expr.location.clone());
+	}
+	let mut result = Ok(());
+	expr.for_each_child(|child| if result.is_ok() {
+		result = check_specializable(child);
+	});
+	result
+}
+
+/// Two specialization constants with the same ID would always be set to the same thing, which is surely a mistake.
+fn check_spec_ids(globals: &[TypedGlobal]) -> Result<(), YuriSemanticError> {
+	let mut seen: HashMap<u32, &TypedGlobal> = HashMap::new();
+	for global in globals {
+		let Some(spec_id) = global.spec_id else { continue };
+		if let Some(previous) = seen.insert(spec_id, global) {
+			return Err(error(
+				YuriSemanticErrorType::DuplicateDeclaration,
+				format!("`{}` (at %) and `{}` (at %) both use the specialization constant ID {spec_id}", previous.name, global.name),
+				vec![previous.location.clone(), global.location.clone()],
+			));
+		}
+	}
+	Ok(())
+}
+
+/// Arrays of nothing wouldn't be much use, and there's no way to represent them anyway.
+fn array_element(ty: YuriType, location: &Range<usize>) -> Result<YuriType, YuriSemanticError> {
+	if ty == YuriType::Unit {
+		return Err(mismatch("Arrays can't hold `()` (%), every element needs a value".to_string(), location));
+	}
+	Ok(ty)
+}
+
+/// Complex types are the same if their fields are, annotations don't matter.
+pub fn types_match(a: &YuriType, b: &YuriType) -> bool {
+	match (a, b) {
+		(YuriType::Complex(a), YuriType::Complex(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| {
+			a.name == b.name && types_match(&a.field_type, &b.field_type)
+		}),
+		(YuriType::Array(a, a_length), YuriType::Array(b, b_length)) => a_length == b_length && types_match(a, b),
+		(a, b) => a == b,
+	}
+}
+
+/// The number type of a scalar or vector (matrices are all floats).
+pub fn number_type(ty: &YuriType) -> Option<NumberType> {
+	match ty {
+		YuriType::Scalar(number_type) | YuriType::Vector(number_type, _) => Some(*number_type),
+		YuriType::Matrix(_) => Some(NumberType::Float),
+		_ => None,
+	}
+}
+
+fn with_number_type(ty: &YuriType, number_type: NumberType) -> YuriType {
+	match ty {
+		YuriType::Vector(_, size) => YuriType::Vector(number_type, *size),
+		_ => YuriType::Scalar(number_type),
+	}
+}
+
+/// Rounds to the nearest value a half-precision float can hold (ties to even),
+/// giving infinity for anything too big and zero for anything too small, same as a cast would.
+fn round_to_half(value: f64) -> f32 {
+	let magnitude = value.abs();
+	if magnitude >= 65520.0 {
+		return f32::INFINITY.copysign(value as f32);
+	}
+	// halves have 10 bits after the point, and below 2^-14 they're subnormal with a fixed step of 2^-24
+	let exponent = magnitude.log2().floor().max(-14.0) as i32;
+	let step = 2f64.powi(exponent - 10);
+	((value / step).round_ties_even() * step) as f32
+}
+
+/// Whether the expression is an integer literal without a suffix (possibly negated),
+/// which takes its type from whatever it's used with.
+fn is_untyped_literal(expr: &Expression) -> bool {
+	match &expr.kind {
+		ExpressionKind::Literal(Literal::DecimalNumber(_, None) | Literal::HexNumber(_, None) | Literal::BinaryNumber(_, None)) => true,
+		ExpressionKind::Unary { operator: UnaryOperator::Negate, operand } => is_untyped_literal(operand),
+		_ => false,
+	}
+}
+
+/// Whether the expression has an assertion in it, or calls something that might (a function that returns nothing
+/// isn't good for much else).
+fn contains_assertion(expr: &TypedExpression) -> bool {
+	let here = match &expr.kind {
+		TypedExpressionKind::Builtin { function, .. } => function.is_assertion(),
+		TypedExpressionKind::Call { .. } => expr.expression_type == YuriType::Unit,
+		_ => false,
+	};
+	let mut found = here;
+	expr.for_each_child(|child| found = found || contains_assertion(child));
+	found
+}
+
+/// Types that can go in and out of entry points.
+fn is_interface_type(ty: &YuriType) -> bool {
+	matches!(ty, YuriType::Scalar(_) | YuriType::Vector(..))
+}
+
+enum Lazy<T> {
+	Pending,
+	InProgress,
+	Done(T),
+}
+
+struct Signature {
+	arguments: Vec<YuriType>,
+	return_type: YuriType,
+}
+
+/// The state of whatever body (function or global) is currently being checked.
+struct BodyScope {
+	module: ModuleId,
+	locals: Vec<TypedLocal>,
+	/// Bindings by name and declaration location, the same way [ResolvedName::Local] identifies them.
+	bindings: HashMap<(String, Range<usize>), LocalId>,
+}
+
+impl BodyScope {
+	fn new(module: ModuleId) -> Self {
+		Self { module, locals: Vec::new(), bindings: HashMap::new() }
+	}
+
+	fn declare(&mut self, name: &str, local_type: YuriType, location: &Range<usize>) -> LocalId {
+		let id = self.locals.len();
+		self.locals.push(TypedLocal { name: name.to_string(), local_type, location: location.clone() });
+		self.bindings.insert((name.to_string(), location.clone()), id);
+		id
+	}
+}
+
+struct Checker<'a> {
+	root: &'a YuriModule,
+	imports: &'a ResolvedImports,
+	resolution: &'a NameResolution,
+	property_ids: HashMap<SymbolId, PropId>,
+	global_ids: HashMap<SymbolId, GlobalId>,
+	function_ids: HashMap<SymbolId, FunctionId>,
+	property_symbols: Vec<SymbolId>,
+	global_symbols: Vec<SymbolId>,
+	function_symbols: Vec<SymbolId>,
+	properties: Vec<Lazy<Rc<TypedProperty>>>,
+	globals: Vec<Lazy<Rc<TypedGlobal>>>,
+	signatures: Vec<Lazy<Rc<Signature>>>,
+	functions: Vec<Lazy<Rc<TypedFunction>>>,
+}
+
+impl ConstContext for Checker<'_> {
+	fn global(&mut self, id: GlobalId, location: &Range<usize>) -> Result<ConstValue, YuriSemanticError> {
+		Ok(self.check_global(id, location)?.constant.clone())
+	}
+
+	fn function(&mut self, id: FunctionId, location: &Range<usize>) -> Result<Rc<TypedFunction>, YuriSemanticError> {
+		self.check_function(id, location)
+	}
+
+	fn property_name(&self, id: PropId) -> String {
+		self.resolution.symbols[self.property_symbols[id]].qualified_name.clone()
+	}
+
+	fn specialization(&mut self, id: GlobalId, location: &Range<usize>) -> Result<Option<String>, YuriSemanticError> {
+		let global = self.check_global(id, location)?;
+		Ok(global.specialization.map(|specialization| self.global_name(specialization)))
+	}
+}
+
+impl<'a> Checker<'a> {
+	fn module_ast(&self, module: ModuleId) -> &'a YuriModule {
+		self.resolution.module_ast(module, self.root, self.imports)
+	}
+
+	fn global_name(&self, id: GlobalId) -> String {
+		self.resolution.symbols[self.global_symbols[id]].qualified_name.clone()
+	}
+
+	/// Finds the specialization constant an expression depends on, if there is one.
+	fn specialization_of(
+		&mut self,
+		expr: &TypedExpression,
+		visited: &mut HashSet<FunctionId>,
+	) -> Result<Option<GlobalId>, YuriSemanticError> {
+		let found = match &expr.kind {
+			TypedExpressionKind::Global(id) => self.check_global(*id, &expr.location)?.specialization,
+			TypedExpressionKind::Call { function, .. } if visited.insert(*function) => {
+				let function = self.check_function(*function, &expr.location)?;
+				self.specialization_of(&function.body, visited)?
+			}
+			_ => None,
+		};
+		if found.is_some() {
+			return Ok(found);
+		}
+		let mut children = Vec::new();
+		expr.for_each_child(|child| children.push(child));
+		for child in children {
+			if let Some(found) = self.specialization_of(child, visited)? {
+				return Ok(Some(found));
+			}
+		}
+		Ok(None)
+	}
+
+	fn reference(&self, module: ModuleId, location: &Range<usize>) -> Result<&'a crate::resolve::Reference, YuriSemanticError> {
+		self.resolution.reference(module, location).ok_or_else(|| error(
+			YuriSemanticErrorType::UnresolvedName,
+			"The name at % was never resolved".to_string(),
+			vec![location.clone()],
+		))
+	}
+
+	fn check_property(&mut self, id: PropId, location: &Range<usize>) -> Result<Rc<TypedProperty>, YuriSemanticError> {
+		match &self.properties[id] {
+			Lazy::Done(property) => return Ok(property.clone()),
+			Lazy::InProgress => return Err(self.cycle(self.property_symbols[id], location)),
+			Lazy::Pending => {}
+		}
+		self.properties[id] = Lazy::InProgress;
+		let symbol = &self.resolution.symbols[self.property_symbols[id]];
+		let declaration = &self.module_ast(symbol.parent).properties[symbol.index];
+		let property_type = self.resolve_type(symbol.parent, &declaration.property_type, &declaration.location)?;
+		if matches!(property_type, YuriType::Unit) {
+			return Err(mismatch("Props can't be empty (%)".to_string(), &declaration.location));
+		}
+		reject_spec(&declaration.annotations, "props")?;
+		let property = Rc::new(TypedProperty {
+			name: symbol.qualified_name.clone(),
+			property_type,
+			annotations: declaration.annotations.clone(),
+			location: declaration.location.clone(),
+			module: symbol.parent,
+		});
+		self.properties[id] = Lazy::Done(property.clone());
+		Ok(property)
+	}
+
+	fn cycle(&self, symbol: SymbolId, location: &Range<usize>) -> YuriSemanticError {
+		let symbol = &self.resolution.symbols[symbol];
+		error(
+			YuriSemanticErrorType::Recursion,
+			format!("`{}` (at %) depends on itself (at %)", symbol.qualified_name),
+			vec![symbol.location.clone(), location.clone()],
+		)
+	}
+
+	fn check_global(&mut self, id: GlobalId, location: &Range<usize>) -> Result<Rc<TypedGlobal>, YuriSemanticError> {
+		match &self.globals[id] {
+			Lazy::Done(global) => return Ok(global.clone()),
+			Lazy::InProgress => return Err(self.cycle(self.global_symbols[id], location)),
+			Lazy::Pending => {}
+		}
+		self.globals[id] = Lazy::InProgress;
+		let symbol = &self.resolution.symbols[self.global_symbols[id]];
+		let declaration = &self.module_ast(symbol.parent).globals[symbol.index];
+		let explicit_type = declaration.explicit_type.as_ref()
+			.map(|ty| self.resolve_type(symbol.parent, ty, &declaration.location))
+			.transpose()?;
+		let mut scope = BodyScope::new(symbol.parent);
+		let value = self.check_expression(&mut scope, &declaration.value, explicit_type.as_ref())?;
+		if let Some(explicit_type) = &explicit_type {
+			expect_type(&value, explicit_type)?;
+		}
+		let global_type = explicit_type.unwrap_or_else(|| value.expression_type.clone());
+		let spec_id = spec_id(&declaration.annotations)?;
+		let (constant, specialization) = if spec_id.is_some() {
+			if !matches!(global_type, YuriType::Scalar(_) | YuriType::Bool) {
+				return Err(error(
+					YuriSemanticErrorType::Unsupported,
+					format!("Only numbers and bools can be specialization constants, not `{global_type}` (%)"),
+					vec![declaration.location.clone()],
+				));
+			}
+			let what = format!("The default value of the specialization constant `{}`", symbol.qualified_name);
+			(ConstEvaluator::new(self, what, scope.locals.len()).evaluate(&value)?, Some(id))
+		} else {
+			let what = format!("The global `{}`", symbol.qualified_name);
+			let constant = ConstEvaluator::new(self, what, scope.locals.len())
+				.allow_specialization()
+				.evaluate(&value)?;
+			let specialization = self.specialization_of(&value, &mut HashSet::new())?;
+			if let Some(specialization) = specialization {
+				check_specializable(&value).map_err(|location| error(
+					YuriSemanticErrorType::Unsupported,
+					format!(
+						"The global `{}` (at %) depends on the specialization constant `{}`, \
+						so it can only be built out of integer and bool operations, which this isn't (%)",
+						symbol.qualified_name,
+						self.global_name(specialization),
+					),
+					vec![declaration.location.clone(), location],
+				))?;
+			}
+			(constant, specialization)
+		};
+		let global = Rc::new(TypedGlobal {
+			name: symbol.qualified_name.clone(),
+			global_type,
+			value,
+			locals: scope.locals,
+			constant,
+			spec_id,
+			specialization,
+			exported: declaration.exported,
+			annotations: declaration.annotations.clone(),
+			location: declaration.location.clone(),
+			module: symbol.parent,
+		});
+		self.globals[id] = Lazy::Done(global.clone());
+		Ok(global)
+	}
+
+	fn check_signature(&mut self, id: FunctionId, location: &Range<usize>) -> Result<Rc<Signature>, YuriSemanticError> {
+		match &self.signatures[id] {
+			Lazy::Done(signature) => return Ok(signature.clone()),
+			Lazy::InProgress => return Err(self.cycle(self.function_symbols[id], location)),
+			Lazy::Pending => {}
+		}
+		self.signatures[id] = Lazy::InProgress;
+		let symbol = &self.resolution.symbols[self.function_symbols[id]];
+		let declaration = &self.module_ast(symbol.parent).functions[symbol.index];
+		let arguments = declaration.arguments.iter()
+			.map(|argument| self.resolve_type(symbol.parent, &argument.argument_type, &argument.location))
+			.collect::<Result<Vec<_>, _>>()?;
+		let return_type = self.resolve_type(symbol.parent, &declaration.return_type, &declaration.location)?;
+		let signature = Rc::new(Signature { arguments, return_type });
+		self.signatures[id] = Lazy::Done(signature.clone());
+		Ok(signature)
+	}
+
+	fn check_function(&mut self, id: FunctionId, location: &Range<usize>) -> Result<Rc<TypedFunction>, YuriSemanticError> {
+		match &self.functions[id] {
+			Lazy::Done(function) => return Ok(function.clone()),
+			Lazy::InProgress => return Err(self.cycle(self.function_symbols[id], location)),
+			Lazy::Pending => {}
+		}
+		let signature = self.check_signature(id, location)?;
+		self.functions[id] = Lazy::InProgress;
+		let symbol = &self.resolution.symbols[self.function_symbols[id]];
+		let declaration = &self.module_ast(symbol.parent).functions[symbol.index];
+		let mut scope = BodyScope::new(symbol.parent);
+		let arguments = declaration.arguments.iter()
+			.zip(&signature.arguments)
+			.map(|(argument, argument_type)| scope.declare(&argument.name, argument_type.clone(), &argument.location))
+			.collect();
+		let body = self.check_block(&mut scope, &declaration.body, Some(&signature.return_type), true)?;
+		expect_type(&body, &signature.return_type)?;
+
+		reject_spec(&declaration.annotations, "functions")?;
+		let mut stage = None;
+		let mut frag_origin = None;
+		for annotation in &declaration.annotations {
+			if let Some(annotated) = ShaderStage::from_annotation(&annotation.name) {
+				if stage.is_some() {
+					return Err(error(
+						YuriSemanticErrorType::InvalidEntryPoint,
+						"A function can only be one kind of entry point (%)".to_string(),
+						vec![annotation.location.clone()],
+					));
+				}
+				stage = Some(annotated);
+				frag_origin = entry_point_origin(annotation, annotated)?;
+			}
+		}
+		if let Some(annotation) = declaration.annotations.iter().find(|a| a.name == "test") {
+			if stage.is_some() {
+				return Err(error(
+					YuriSemanticErrorType::InvalidEntryPoint,
+					"An entry point can't also be a test (%)".to_string(),
+					vec![annotation.location.clone()],
+				));
+			}
+			if !declaration.arguments.is_empty() || !matches!(signature.return_type, YuriType::Bool | YuriType::Unit) {
+				return Err(error(
+					YuriSemanticErrorType::InvalidDeclaration,
+					format!("The test `{}` (%) can't take any arguments, and has to return a `bool` or nothing", symbol.qualified_name),
+					vec![declaration.location.clone()],
+				));
+			}
+		}
+		let function = Rc::new(TypedFunction {
+			name: symbol.qualified_name.clone(),
+			arguments,
+			locals: scope.locals,
+			return_type: signature.return_type.clone(),
+			body,
+			stage,
+			frag_origin,
+			exported: declaration.exported,
+			annotations: declaration.annotations.clone(),
+			location: declaration.location.clone(),
+			module: symbol.parent,
+		});
+		if let Some(stage) = stage {
+			check_entry_point(&function, stage)?;
+		}
+		self.functions[id] = Lazy::Done(function.clone());
+		Ok(function)
+	}
+
+	/// Resolves the named array lengths in a type into actual numbers.
+	fn resolve_type(&mut self, module: ModuleId, ty: &YuriType, location: &Range<usize>) -> Result<YuriType, YuriSemanticError> {
+		Ok(match ty {
+			YuriType::Array(inner, length) => {
+				let length = match length {
+					ArrayLength::Fixed(length) => *length,
+					ArrayLength::Named(name) => {
+						let reference = self.resolution.lookup(module, name, location)?;
+						let global = match reference.target {
+							ResolvedName::Symbol(symbol) if reference.members.is_empty() => self.global_ids.get(&symbol).copied(),
+							_ => None,
+						};
+						let Some(global) = global else {
+							return Err(mismatch(format!("The array length `{name}` (at %) has to be a global constant"), location));
+						};
+						let value = self.check_global(global, location)?;
+						if let Some(specialization) = value.specialization {
+							return Err(error(
+								YuriSemanticErrorType::NotConstant,
+								format!(
+									"The array length `{name}` (at %) has to be known at compile time, \
+									but it depends on the specialization constant `{}`, which can be changed after compiling",
+									self.global_name(specialization),
+								),
+								vec![location.clone()],
+							));
+						}
+						value.constant.as_index().ok_or_else(|| mismatch(
+							format!("The array length `{name}` (at %) has to be a non-negative integer"),
+							location,
+						))?
+					}
+				};
+				if length == 0 {
+					return Err(mismatch("Arrays can't be empty (%)".to_string(), location));
+				}
+				YuriType::Array(Box::new(self.resolve_type(module, inner, location)?), ArrayLength::Fixed(length))
+			}
+			YuriType::Complex(fields) => YuriType::Complex(fields.iter()
+				.map(|field| Ok(ComplexField {
+					name: field.name.clone(),
+					field_type: self.resolve_type(module, &field.field_type, location)?,
+					annotations: field.annotations.clone(),
+				}))
+				.collect::<Result<_, YuriSemanticError>>()?),
+			other => other.clone(),
+		})
+	}
+
+	/// Evaluates an expression that has to be a constant integer, like a loop count.
+	fn constant_count(&mut self, scope: &BodyScope, expr: &TypedExpression, what: &str) -> Result<u32, YuriSemanticError> {
+		let value = ConstEvaluator::new(self, what, scope.locals.len()).evaluate(expr)?;
+		value.as_index()
+			.and_then(|count| u32::try_from(count).ok())
+			.ok_or_else(|| mismatch(format!("{what} (%) has to be a non-negative integer"), &expr.location))
+	}
+
+	fn check_block(
+		&mut self,
+		scope: &mut BodyScope,
+		block: &Block,
+		expected: Option<&YuriType>,
+		function_body: bool,
+	) -> Result<TypedExpression, YuriSemanticError> {
+		let mut bindings = Vec::new();
+		let mut tail = None;
+		for (i, statement) in block.statements.iter().enumerate() {
+			match statement {
+				Statement::Variable(variable) => {
+					let explicit_type = variable.explicit_type.as_ref()
+						.map(|ty| self.resolve_type(scope.module, ty, &variable.location))
+						.transpose()?;
+					let value = self.check_expression(scope, &variable.value, explicit_type.as_ref())?;
+					if let Some(explicit_type) = &explicit_type {
+						expect_type(&value, explicit_type)?;
+					}
+					let local_type = explicit_type.unwrap_or_else(|| value.expression_type.clone());
+					let id = scope.declare(&variable.name, local_type, &variable.location);
+					bindings.push((id, value));
+				}
+				// there's no side effects, so this is only useful for catching errors.
+				// Except for assertions, which have to stay around for tests to run them.
+				Statement::Expression(expr) => {
+					let value = self.check_expression(scope, expr, None)?;
+					if contains_assertion(&value) {
+						let id = scope.declare("_", value.expression_type.clone(), &expr.location);
+						bindings.push((id, value));
+					}
+				}
+				Statement::Return(expr) => {
+					if !function_body || i + 1 != block.statements.len() || block.tail.is_some() {
+						return Err(error(
+							YuriSemanticErrorType::Unsupported,
+							"Early returns aren't supported, `return` (at %) has to be the last thing in a function".to_string(),
+							vec![expr.location.clone()],
+						));
+					}
+					tail = Some(self.check_expression(scope, expr, expected)?);
+				}
+			}
+		}
+		let tail = match (&block.tail, tail) {
+			(Some(expr), _) => self.check_expression(scope, expr, expected)?,
+			(None, Some(returned)) => returned,
+			(None, None) => TypedExpression::unit(block.location.end..block.location.end),
+		};
+		if bindings.is_empty() {
+			return Ok(tail);
+		}
+		let expression_type = tail.expression_type.clone();
+		Ok(TypedExpression::new(
+			TypedExpressionKind::Block { bindings, tail: Box::new(tail) },
+			expression_type,
+			block.location.clone(),
+		))
+	}
+
+	/// Checks a block with some extra bindings in scope, declared at the location of the expression.
+	fn check_block_with(
+		&mut self,
+		scope: &mut BodyScope,
+		bindings: &[(&String, YuriType)],
+		declared_at: &Range<usize>,
+		block: &Block,
+		expected: Option<&YuriType>,
+	) -> Result<(Vec<LocalId>, TypedExpression), YuriSemanticError> {
+		let ids = bindings.iter()
+			.map(|(name, ty)| scope.declare(name, ty.clone(), declared_at))
+			.collect();
+		let block = self.check_block(scope, block, expected, false)?;
+		Ok((ids, block))
+	}
+
+	/// Gives a literal its type, which works like this:
+	/// - a suffix always decides the type, so `1u` is a `u` and `1f` is an `f`
+	/// - floats without one are `f`, and never turn into integers
+	/// - integers without one take the number type that's expected of them, which can be `f`:
+	///   the declared type of a `let`, a parameter's type, or the type of the other side of a binary operator
+	///   or the other arguments of a call (so `f4(pos, 1)` and `x * 2` work)
+	/// - if nothing's expected, they're `i`, or `u` if they're too big for that
+	///
+	/// A negated integer literal is checked as one negative number, so `-2147483648` fits in an `i`.
+	/// Anything that doesn't fit in its type is an error at the literal, rather than getting wrapped or rounded to infinity.
+	fn check_literal(&self, literal: &Literal, expected: Option<&YuriType>, location: &Range<usize>) -> Result<TypedExpression, YuriSemanticError> {
+		let out_of_bounds = |number: String, suffix: Option<NumberSuffix>, number_type: NumberType| error(
+			YuriSemanticErrorType::TypeMismatch,
+			if suffix == Some(NumberSuffix::Half) {
+				format!("The number {number} (at %) doesn't fit in a half-precision float")
+			} else {
+				format!("The number {number} (at %) doesn't fit in a `{}`", YuriType::Scalar(number_type))
+			},
+			vec![location.clone()],
+		);
+		let (integer, suffix) = match literal {
+			Literal::FloatNumber(f, suffix) => {
+				let value = match suffix {
+					None | Some(NumberSuffix::Float) => *f as f32,
+					Some(NumberSuffix::Half) => round_to_half(*f),
+					Some(NumberSuffix::Unsigned | NumberSuffix::Signed) => return Err(error(
+						YuriSemanticErrorType::TypeMismatch,
+						format!("The number {f} (at %) has a point, so it can't be made an integer with a suffix"),
+						vec![location.clone()],
+					)),
+				};
+				// too small counts too, since it'd silently turn into zero
+				if value.is_infinite() || (value == 0.0 && *f != 0.0) {
+					return Err(out_of_bounds(f.to_string(), *suffix, NumberType::Float));
+				}
+				return Ok(TypedExpression::new(
+					TypedExpressionKind::Constant(ConstValue::Float(value)),
+					YuriType::Scalar(NumberType::Float),
+					location.clone(),
+				));
+			}
+			Literal::Boolean(b) => return Ok(TypedExpression::new(
+				TypedExpressionKind::Constant(ConstValue::Bool(*b)),
+				YuriType::Bool,
+				location.clone(),
+			)),
+			Literal::DecimalNumber(n, suffix) | Literal::HexNumber(n, suffix) | Literal::BinaryNumber(n, suffix) => (*n, *suffix),
+		};
+		let number_type = match (suffix, expected.and_then(number_type)) {
+			(Some(NumberSuffix::Unsigned), _) => NumberType::Unsigned,
+			(Some(NumberSuffix::Signed), _) => NumberType::Signed,
+			(Some(NumberSuffix::Float | NumberSuffix::Half), _) => NumberType::Float,
+			(None, Some(number_type)) => number_type,
+			(None, None) if i32::try_from(integer).is_err() && u32::try_from(integer).is_ok() => NumberType::Unsigned,
+			(None, None) => NumberType::Signed,
+		};
+		let out_of_bounds = || out_of_bounds(integer.to_string(), suffix, number_type);
+		let value = match number_type {
+			NumberType::Float if suffix == Some(NumberSuffix::Half) => {
+				let value = round_to_half(integer as f64);
+				if value.is_infinite() {
+					return Err(out_of_bounds());
+				}
+				ConstValue::Float(value)
+			}
+			// no literal is too big for a float, but one that adapted to being a float shouldn't quietly change value
+			NumberType::Float if suffix.is_none() && (integer as f32) as i128 != integer => return Err(error(
+				YuriSemanticErrorType::TypeMismatch,
+				format!(
+					"The number {integer} (at %) would get rounded to {} as an `f`, write it as `{integer}.0` if that's close enough",
+					integer as f32 as i128,
+				),
+				vec![location.clone()],
+			)),
+			NumberType::Float => ConstValue::Float(integer as f32),
+			NumberType::Signed => ConstValue::Signed(i32::try_from(integer).map_err(|_| out_of_bounds())?),
+			NumberType::Unsigned => ConstValue::Unsigned(u32::try_from(integer).map_err(|_| out_of_bounds())?),
+		};
+		Ok(TypedExpression::new(TypedExpressionKind::Constant(value), YuriType::Scalar(number_type), location.clone()))
+	}
+
+	/// Applies field accesses and swizzles (the `.xy` in `pos.xy`).
+	fn apply_members(&self, mut expr: TypedExpression, members: &[String]) -> Result<TypedExpression, YuriSemanticError> {
+		for member in members {
+			let location = expr.location.clone();
+			let unknown = |ty: &YuriType| error(
+				YuriSemanticErrorType::UnknownMember,
+				format!("`{ty}` doesn't have anything called `{member}` (used at %)"),
+				vec![location.clone()],
+			);
+			expr = match &expr.expression_type {
+				YuriType::Complex(fields) => {
+					let Some(index) = fields.iter().position(|f| f.name == *member) else {
+						return Err(unknown(&expr.expression_type));
+					};
+					let field_type = fields[index].field_type.clone();
+					TypedExpression::new(
+						TypedExpressionKind::Field { target: Box::new(expr), index: index as u32 },
+						field_type,
+						location,
+					)
+				}
+				YuriType::Vector(number_type, size) => {
+					let number_type = *number_type;
+					let components = ["xyzw", "rgba"].iter()
+						.find_map(|set| member.chars()
+							.map(|c| set.find(c).map(|i| i as u32).filter(|i| *i < size.count()))
+							.collect::<Option<Vec<_>>>())
+						.filter(|components| (1..=4).contains(&components.len()))
+						.ok_or_else(|| unknown(&expr.expression_type))?;
+					let swizzle_type = match CompositeSize::from_count(components.len()) {
+						Some(size) => YuriType::Vector(number_type, size),
+						None => YuriType::Scalar(number_type),
+					};
+					TypedExpression::new(
+						TypedExpressionKind::Swizzle { target: Box::new(expr), components },
+						swizzle_type,
+						location,
+					)
+				}
+				other => return Err(unknown(other)),
+			};
+		}
+		Ok(expr)
+	}
+
+	/// Checks the arguments of a call. Untyped literals are checked last,
+	/// so they can take their type from the rest of the arguments.
+	fn check_arguments(
+		&mut self,
+		scope: &mut BodyScope,
+		arguments: &[Expression],
+		expected: &[Option<YuriType>],
+	) -> Result<Vec<TypedExpression>, YuriSemanticError> {
+		let mut checked: Vec<Option<TypedExpression>> = vec![None; arguments.len()];
+		for (i, argument) in arguments.iter().enumerate() {
+			if !is_untyped_literal(argument) || expected.get(i).is_some_and(Option::is_some) {
+				checked[i] = Some(self.check_expression(scope, argument, expected.get(i).cloned().flatten().as_ref())?);
+			}
+		}
+		let hint = checked.iter()
+			.flatten()
+			.find_map(|a| number_type(&a.expression_type))
+			.map(YuriType::Scalar);
+		for (i, argument) in arguments.iter().enumerate() {
+			if checked[i].is_none() {
+				checked[i] = Some(self.check_expression(scope, argument, hint.as_ref())?);
+			}
+		}
+		Ok(checked.into_iter().flatten().collect())
+	}
+
+	fn check_expression(
+		&mut self,
+		scope: &mut BodyScope,
+		expr: &Expression,
+		expected: Option<&YuriType>,
+	) -> Result<TypedExpression, YuriSemanticError> {
+		let location = &expr.location;
+		let typed = |kind, expression_type| TypedExpression::new(kind, expression_type, location.clone());
+		Ok(match &expr.kind {
+			ExpressionKind::Literal(literal) => self.check_literal(literal, expected, location)?,
+			ExpressionKind::Variable(_) => {
+				let reference = self.reference(scope.module, location)?;
+				let base = match &reference.target {
+					ResolvedName::Local { name, declared_at } => {
+						let id = scope.bindings.get(&(name.clone(), declared_at.clone()))
+							.copied()
+							.ok_or_else(|| error(
+								YuriSemanticErrorType::UnresolvedName,
+								format!("`{name}` (used at %) isn't in scope"),
+								vec![location.clone()],
+							))?;
+						typed(TypedExpressionKind::Local(id), scope.locals[id].local_type.clone())
+					}
+					ResolvedName::Symbol(symbol) => match self.resolution.symbols[*symbol].kind {
+						SymbolKind::Property => {
+							let id = self.property_ids[symbol];
+							let property = self.check_property(id, location)?;
+							typed(TypedExpressionKind::Property(id), property.property_type.clone())
+						}
+						SymbolKind::Global => {
+							let id = self.global_ids[symbol];
+							let global = self.check_global(id, location)?;
+							typed(TypedExpressionKind::Global(id), global.global_type.clone())
+						}
+						SymbolKind::Function | SymbolKind::Module(_) => return Err(mismatch(
+							format!("`{}` (used at %) isn't a value", self.resolution.symbols[*symbol].qualified_name),
+							location,
+						)),
+					},
+					ResolvedName::Builtin(function) => return Err(mismatch(
+						format!("`{}` (used at %) is a function, and needs to be called", function.name()),
+						location,
+					)),
+				};
+				self.apply_members(base, &reference.members)?
+			}
+			ExpressionKind::Builtin(name) => {
+				// the longest builtin name that fits, with the rest being members (like `@frag.coord.x`)
+				let segments: Vec<&str> = name.split('.').collect();
+				let found = (1..=segments.len()).rev()
+					.find_map(|split| BuiltinInput::from_name(&segments[..split].join(".")).map(|input| (input, split)));
+				let Some((input, split)) = found else {
+					return Err(error(
+						YuriSemanticErrorType::UnresolvedName,
+						format!("There's no builtin input called `@{name}` (used at %)"),
+						vec![location.clone()],
+					));
+				};
+				let members: Vec<String> = segments[split..].iter().map(|s| s.to_string()).collect();
+				self.apply_members(typed(TypedExpressionKind::BuiltinInput(input), input.input_type()), &members)?
+			}
+			ExpressionKind::FunctionCall { function_name, arguments } => {
+				let reference = self.reference(scope.module, location)?;
+				match &reference.target {
+					ResolvedName::Symbol(symbol) if reference.members.is_empty() && self.function_ids.contains_key(symbol) => {
+						let id = self.function_ids[symbol];
+						let signature = self.check_signature(id, location)?;
+						if arguments.len() != signature.arguments.len() {
+							return Err(mismatch(format!(
+								"`{function_name}` takes {} argument(s), but was given {} (at %)",
+								signature.arguments.len(),
+								arguments.len(),
+							), location));
+						}
+						let expected: Vec<Option<YuriType>> = signature.arguments.iter().cloned().map(Some).collect();
+						let arguments = self.check_arguments(scope, arguments, &expected)?;
+						for (argument, expected) in arguments.iter().zip(&signature.arguments) {
+							expect_type(argument, expected)?;
+						}
+						typed(TypedExpressionKind::Call { function: id, arguments }, signature.return_type.clone())
+					}
+					ResolvedName::Builtin(function) => {
+						let function = *function;
+						let arguments = self.check_arguments(scope, arguments, &[])?;
+						let argument_types: Vec<YuriType> = arguments.iter().map(|a| a.expression_type.clone()).collect();
+						let result_type = function.result_type(&argument_types)
+							.map_err(|reason| mismatch(format!("Can't call % like that: {reason}"), location))?;
+						typed(TypedExpressionKind::Builtin { function, arguments }, result_type)
+					}
+					_ => return Err(mismatch(format!("`{function_name}` (used at %) isn't a function"), location)),
+				}
+			}
+			ExpressionKind::Construct { target, arguments } => {
+				let target = self.resolve_type(scope.module, target, location)?;
+				self.check_construct(scope, target, arguments, location)?
+			}
+			ExpressionKind::Complex(fields) => {
+				let expected_fields = match expected {
+					Some(YuriType::Complex(fields)) => Some(fields),
+					_ => None,
+				};
+				let mut values = Vec::new();
+				let mut field_types = Vec::new();
+				for (name, value) in fields {
+					if field_types.iter().any(|f: &ComplexField| f.name == *name) {
+						return Err(error(
+							YuriSemanticErrorType::DuplicateDeclaration,
+							format!("The field `{name}` is given more than once (at %)"),
+							vec![value.location.clone()],
+						));
+					}
+					let field_expected = expected_fields
+						.and_then(|fields| fields.iter().find(|f| f.name == *name))
+						.map(|f| &f.field_type);
+					let value = self.check_expression(scope, value, field_expected)?;
+					field_types.push(ComplexField {
+						name: name.clone(),
+						field_type: value.expression_type.clone(),
+						annotations: Vec::new(),
+					});
+					values.push(value);
+				}
+				// reorder to match what's expected, so `<| b = 1.0, a = 2.0 |>` can be an `<| a: f, b: f |>`
+				if let Some(expected_fields) = expected_fields
+					&& expected_fields.len() == field_types.len()
+					&& expected_fields.iter().all(|f| field_types.iter().any(|g| g.name == f.name))
+				{
+					let mut reordered = Vec::new();
+					let mut reordered_types = Vec::new();
+					for field in expected_fields {
+						let index = field_types.iter().position(|g| g.name == field.name).unwrap();
+						reordered.push(values[index].clone());
+						reordered_types.push(field_types[index].clone());
+					}
+					values = reordered;
+					field_types = reordered_types;
+				}
+				typed(TypedExpressionKind::Complex(values), YuriType::Complex(field_types))
+			}
+			ExpressionKind::Array(elements) => {
+				let mut element_type = match expected {
+					Some(YuriType::Array(inner, _)) => Some(inner.as_ref().clone()),
+					_ => None,
+				};
+				// check the typed elements first, so the untyped literals can follow them
+				if element_type.is_none()
+					&& let Some(first) = elements.iter().find(|e| !is_untyped_literal(e))
+				{
+					element_type = Some(self.check_expression(scope, first, None)?.expression_type);
+				}
+				let mut checked = Vec::new();
+				for element in elements {
+					let element = self.check_expression(scope, element, element_type.as_ref())?;
+					match &element_type {
+						Some(element_type) => expect_type(&element, element_type)?,
+						None => element_type = Some(element.expression_type.clone()),
+					}
+					checked.push(element);
+				}
+				let Some(element_type) = element_type else {
+					return Err(mismatch("Arrays can't be empty (%)".to_string(), location));
+				};
+				let length = checked.len();
+				typed(
+					TypedExpressionKind::Array(checked),
+					YuriType::Array(Box::new(array_element(element_type, location)?), ArrayLength::Fixed(length)),
+				)
+			}
+			ExpressionKind::Index { target, index } => {
+				let target = self.check_expression(scope, target, None)?;
+				let index = self.check_expression(scope, index, Some(&YuriType::Scalar(NumberType::Unsigned)))?;
+				if !matches!(index.expression_type, YuriType::Scalar(NumberType::Signed | NumberType::Unsigned)) {
+					return Err(mismatch(format!("Indices have to be integers, not `{}` (%)", index.expression_type), &index.location));
+				}
+				let (element_type, length) = match &target.expression_type {
+					YuriType::Array(inner, ArrayLength::Fixed(length)) => (inner.as_ref().clone(), *length),
+					YuriType::Vector(number_type, size) => (YuriType::Scalar(*number_type), size.count() as usize),
+					YuriType::Matrix(size) => (YuriType::Vector(NumberType::Float, *size), size.count() as usize),
+					other => return Err(mismatch(format!("`{other}` can't be indexed (%)"), &target.location)),
+				};
+				if let TypedExpressionKind::Constant(value) = &index.kind
+					&& value.as_index().is_none_or(|i| i >= length)
+				{
+					return Err(mismatch(format!("The index (at %) is out of bounds for `{}`", target.expression_type), &index.location));
+				}
+				typed(TypedExpressionKind::Index { target: Box::new(target), index: Box::new(index) }, element_type)
+			}
+			// `-` right in front of an integer makes a negative literal, so `-2147483648` is still an `i`
+			ExpressionKind::Unary { operator: UnaryOperator::Negate, operand } if let ExpressionKind::Literal(
+				Literal::DecimalNumber(n, suffix) | Literal::HexNumber(n, suffix) | Literal::BinaryNumber(n, suffix)
+			) = &operand.kind => self.check_literal(&Literal::DecimalNumber(-n, *suffix), expected, location)?,
+			ExpressionKind::Unary { operator, operand } => {
+				let operand = self.check_expression(scope, operand, expected)?;
+				let valid = match operator {
+					UnaryOperator::Negate => matches!(
+						operand.expression_type,
+						YuriType::Scalar(_) | YuriType::Vector(..) | YuriType::Matrix(_)
+					),
+					UnaryOperator::Not => matches!(
+						operand.expression_type,
+						YuriType::Bool | YuriType::Scalar(NumberType::Signed | NumberType::Unsigned)
+							| YuriType::Vector(NumberType::Signed | NumberType::Unsigned, _)
+					),
+				};
+				if !valid {
+					return Err(mismatch(format!("`{operator}` can't be used on `{}` (%)", operand.expression_type), location));
+				}
+				// fold negative literals right away, so `-1` is a constant and not an operation
+				if let (UnaryOperator::Negate, TypedExpressionKind::Constant(value)) = (operator, &operand.kind)
+					&& let Ok(negated) = crate::consteval::unary(*operator, value)
+				{
+					return Ok(typed(TypedExpressionKind::Constant(negated), operand.expression_type));
+				}
+				let expression_type = operand.expression_type.clone();
+				typed(TypedExpressionKind::Unary { operator: *operator, operand: Box::new(operand) }, expression_type)
+			}
+			ExpressionKind::Binary { operator, lhs, rhs } => self.check_binary(scope, *operator, lhs, rhs, expected, location)?,
+			ExpressionKind::Block(block) => self.check_block(scope, block, expected, false)?,
+			ExpressionKind::If(if_expr) => {
+				let condition = self.check_expression(scope, &if_expr.condition, Some(&YuriType::Bool))?;
+				expect_type(&condition, &YuriType::Bool)?;
+				let block = self.check_block(scope, &if_expr.block, expected, false)?;
+				let block_else = match &if_expr.block_else {
+					Some(Else::Block(block_else)) => Some(self.check_block(scope, block_else, Some(&block.expression_type), false)?),
+					Some(Else::If(else_if)) => Some(self.check_expression(scope, else_if, Some(&block.expression_type))?),
+					None => None,
+				};
+				match &block_else {
+					Some(block_else) => expect_type(block_else, &block.expression_type)?,
+					None if block.expression_type != YuriType::Unit => return Err(mismatch(
+						format!("An `if` without an `else` can't give back a value, but this one gives back `{}` (%)", block.expression_type),
+						location,
+					)),
+					None => {}
+				}
+				let expression_type = block.expression_type.clone();
+				typed(TypedExpressionKind::If {
+					condition: Box::new(condition),
+					block: Box::new(block),
+					block_else: block_else.map(Box::new),
+				}, expression_type)
+			}
+			ExpressionKind::Loop { index, count, block } => {
+				let count = self.check_expression(scope, count, Some(&YuriType::Scalar(NumberType::Unsigned)))?;
+				let count = self.constant_count(scope, &count, "The loop count")?;
+				if count == 0 {
+					return Err(mismatch("Loops have to run at least once, since arrays can't be empty (%)".to_string(), location));
+				}
+				let element_expected = match expected {
+					Some(YuriType::Array(inner, _)) => Some(inner.as_ref()),
+					_ => None,
+				};
+				let (ids, block) = self.check_block_with(
+					scope,
+					&[(index, YuriType::Scalar(NumberType::Unsigned))],
+					location,
+					block,
+					element_expected,
+				)?;
+				let element_type = array_element(block.expression_type.clone(), &block.location)?;
+				let expression_type = YuriType::Array(Box::new(element_type), ArrayLength::Fixed(count as usize));
+				typed(TypedExpressionKind::Loop { index: ids[0], count, block: Box::new(block) }, expression_type)
+			}
+			ExpressionKind::Fold { accumulator, initial, item, items, block } => {
+				let initial = self.check_expression(scope, initial, expected)?;
+				let items = self.check_expression(scope, items, None)?;
+				let item_type = match &items.expression_type {
+					YuriType::Array(inner, _) => inner.as_ref().clone(),
+					ty @ YuriType::Scalar(NumberType::Signed | NumberType::Unsigned) => ty.clone(),
+					other => return Err(mismatch(format!("`fold` goes over arrays or numbers, not `{other}` (%)"), &items.location)),
+				};
+				let accumulator_type = initial.expression_type.clone();
+				let (ids, block) = self.check_block_with(
+					scope,
+					&[(accumulator, accumulator_type.clone()), (item, item_type)],
+					location,
+					block,
+					Some(&accumulator_type),
+				)?;
+				expect_type(&block, &accumulator_type)?;
+				typed(TypedExpressionKind::Fold {
+					accumulator: ids[0],
+					initial: Box::new(initial),
+					item: ids[1],
+					items: Box::new(items),
+					block: Box::new(block),
+				}, accumulator_type)
+			}
+			ExpressionKind::Map { item, items, block } => {
+				let items = self.check_expression(scope, items, None)?;
+				let (item_type, length) = match &items.expression_type {
+					YuriType::Array(inner, length) => (inner.as_ref().clone(), length.clone()),
+					other => return Err(mismatch(
+						format!("`map` goes over arrays, not `{other}` (%), use `loop` to count instead"),
+						&items.location,
+					)),
+				};
+				let element_expected = match expected {
+					Some(YuriType::Array(inner, _)) => Some(inner.as_ref()),
+					_ => None,
+				};
+				let (ids, block) = self.check_block_with(scope, &[(item, item_type)], location, block, element_expected)?;
+				let expression_type = YuriType::Array(Box::new(array_element(block.expression_type.clone(), &block.location)?), length);
+				typed(TypedExpressionKind::Map { item: ids[0], items: Box::new(items), block: Box::new(block) }, expression_type)
+			}
+			ExpressionKind::Filter { .. } => return Err(error(
+				YuriSemanticErrorType::Unsupported,
+				"`filter` (at %) isn't supported yet, since arrays can't change length".to_string(),
+				vec![location.clone()],
+			)),
+		})
+	}
+
+	fn check_construct(
+		&mut self,
+		scope: &mut BodyScope,
+		target: YuriType,
+		arguments: &[Expression],
+		location: &Range<usize>,
+	) -> Result<TypedExpression, YuriSemanticError> {
+		let typed = |kind, expression_type| TypedExpression::new(kind, expression_type, location.clone());
+		let hint = match &target {
+			YuriType::Scalar(_) | YuriType::Bool => None,
+			YuriType::Array(inner, _) => Some(inner.as_ref().clone()),
+			other => number_type(other).map(YuriType::Scalar),
+		};
+		let expected: Vec<Option<YuriType>> = vec![hint; arguments.len()];
+		let mut arguments = self.check_arguments(scope, arguments, &expected)?;
+		let wrong = |arguments: &[TypedExpression]| mismatch(format!(
+			"Can't build a `{target}` out of {} (%)",
+			if arguments.is_empty() {
+				"nothing".to_string()
+			} else {
+				arguments.iter().map(|a| format!("`{}`", a.expression_type)).collect::<Vec<_>>().join(", ")
+			},
+		), location);
+		Ok(match &target {
+			YuriType::Scalar(_) | YuriType::Bool => {
+				let [argument] = <[TypedExpression; 1]>::try_from(arguments).map_err(|a| wrong(&a))?;
+				if !matches!(argument.expression_type, YuriType::Scalar(_) | YuriType::Bool) {
+					return Err(wrong(&[argument]));
+				}
+				convert(argument, &target)
+			}
+			YuriType::Vector(number_type, size) => {
+				let component_count: u32 = arguments.iter()
+					.map(|a| match &a.expression_type {
+						YuriType::Scalar(_) | YuriType::Bool => Some(1),
+						YuriType::Vector(_, size) => Some(size.count()),
+						_ => None,
+					})
+					.sum::<Option<u32>>()
+					.ok_or_else(|| wrong(&arguments))?;
+				if arguments.len() == 1 && component_count == 1 {
+					let argument = convert(arguments.pop().unwrap(), &YuriType::Scalar(*number_type));
+					return Ok(typed(TypedExpressionKind::Splat(Box::new(argument)), target));
+				}
+				if component_count != size.count() {
+					return Err(wrong(&arguments));
+				}
+				// a whole vector of the right size is just a conversion
+				if arguments.len() == 1 {
+					return Ok(convert(arguments.pop().unwrap(), &target));
+				}
+				let arguments = arguments.into_iter()
+					.map(|a| {
+						let converted_type = with_number_type(&a.expression_type, *number_type);
+						convert(a, &converted_type)
+					})
+					.collect();
+				typed(TypedExpressionKind::Construct(arguments), target)
+			}
+			YuriType::Matrix(size) => {
+				let n = size.count() as usize;
+				let column_type = YuriType::Vector(NumberType::Float, *size);
+				if arguments.len() == n && arguments.iter().all(|a| a.expression_type == column_type) {
+					typed(TypedExpressionKind::Construct(arguments), target)
+				} else if arguments.len() == n * n && arguments.iter().all(|a| matches!(a.expression_type, YuriType::Scalar(_))) {
+					// column by column, like GLSL
+					let mut scalars = arguments.into_iter().map(|a| convert(a, &YuriType::Scalar(NumberType::Float)));
+					let columns = (0..n)
+						.map(|_| {
+							let column: Vec<TypedExpression> = scalars.by_ref().take(n).collect();
+							let start = column[0].location.start;
+							let end = column[n - 1].location.end;
+							TypedExpression::new(TypedExpressionKind::Construct(column), column_type.clone(), start..end)
+						})
+						.collect();
+					typed(TypedExpressionKind::Construct(columns), target)
+				} else {
+					return Err(wrong(&arguments));
+				}
+			}
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => {
+				if arguments.len() != *length {
+					return Err(wrong(&arguments));
+				}
+				for argument in &arguments {
+					expect_type(argument, inner)?;
+				}
+				typed(TypedExpressionKind::Array(arguments), target)
+			}
+			_ => return Err(wrong(&arguments)),
+		})
+	}
+
+	fn check_binary(
+		&mut self,
+		scope: &mut BodyScope,
+		operator: BinaryOperator,
+		lhs: &Expression,
+		rhs: &Expression,
+		expected: Option<&YuriType>,
+		location: &Range<usize>,
+	) -> Result<TypedExpression, YuriSemanticError> {
+		use BinaryOperator::*;
+		let operand_expected = match operator {
+			Plus | Minus | Times | Divided | Modulo | Exponent | BitAnd | BitOr | BitXor | ShiftLeft | ShiftRight => {
+				expected.and_then(number_type).map(YuriType::Scalar)
+			}
+			And | Or | Xor | Nor => Some(YuriType::Bool),
+			Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual => None,
+		};
+		// untyped literals take their type from the other side
+		let (lhs, rhs) = if is_untyped_literal(lhs) && !is_untyped_literal(rhs) {
+			let rhs = self.check_expression(scope, rhs, operand_expected.as_ref())?;
+			let hint = number_type(&rhs.expression_type).map(YuriType::Scalar).or(operand_expected);
+			(self.check_expression(scope, lhs, hint.as_ref())?, rhs)
+		} else {
+			let lhs = self.check_expression(scope, lhs, operand_expected.as_ref())?;
+			let hint = number_type(&lhs.expression_type).map(YuriType::Scalar).or(operand_expected);
+			(lhs, self.check_expression(scope, rhs, hint.as_ref())?)
+		};
+		let result_type = binary_result_type(operator, &lhs.expression_type, &rhs.expression_type)
+			.ok_or_else(|| mismatch(format!(
+				"`{operator}` can't be used on `{}` and `{}` (%)",
+				lhs.expression_type,
+				rhs.expression_type,
+			), location))?;
+		Ok(TypedExpression::new(
+			TypedExpressionKind::Binary { operator, lhs: Box::new(lhs), rhs: Box::new(rhs) },
+			result_type,
+			location.clone(),
+		))
+	}
+}
+
+/// The type a binary operation gives back, if the operand types work with it.
+pub fn binary_result_type(operator: BinaryOperator, lhs: &YuriType, rhs: &YuriType) -> Option<YuriType> {
+	use BinaryOperator::*;
+	use YuriType::*;
+	let is_integer = |n: &NumberType| matches!(n, NumberType::Signed | NumberType::Unsigned);
+	match operator {
+		Plus | Minus | Times | Divided | Modulo | Exponent => match (lhs, rhs) {
+			(Scalar(a), Scalar(b)) if a == b => Some(lhs.clone()),
+			(Vector(a, a_size), Vector(b, b_size)) if a == b && a_size == b_size => Some(lhs.clone()),
+			// scalars get spread across every component
+			(Vector(a, _), Scalar(b)) if a == b => Some(lhs.clone()),
+			(Scalar(a), Vector(b, _)) if a == b => Some(rhs.clone()),
+			(Matrix(a), Matrix(b)) if a == b && matches!(operator, Plus | Minus | Times) => Some(lhs.clone()),
+			(Matrix(a), Vector(NumberType::Float, b)) if a == b && operator == Times => Some(rhs.clone()),
+			(Vector(NumberType::Float, a), Matrix(b)) if a == b && operator == Times => Some(lhs.clone()),
+			(Matrix(_), Scalar(NumberType::Float)) if matches!(operator, Times | Divided) => Some(lhs.clone()),
+			(Scalar(NumberType::Float), Matrix(_)) if operator == Times => Some(rhs.clone()),
+			_ => None,
+		}
+		.filter(|ty| operator != Exponent || number_type(ty) == Some(NumberType::Float)),
+		BitAnd | BitOr | BitXor => match (lhs, rhs) {
+			(Scalar(a), Scalar(b)) if a == b && is_integer(a) => Some(lhs.clone()),
+			(Vector(a, a_size), Vector(b, b_size)) if a == b && a_size == b_size && is_integer(a) => Some(lhs.clone()),
+			_ => None,
+		},
+		// the shift amount doesn't have to match the signedness
+		ShiftLeft | ShiftRight => match (lhs, rhs) {
+			(Scalar(a), Scalar(b)) if is_integer(a) && is_integer(b) => Some(lhs.clone()),
+			(Vector(a, a_size), Vector(b, b_size)) if a_size == b_size && is_integer(a) && is_integer(b) => Some(lhs.clone()),
+			(Vector(a, _), Scalar(b)) if is_integer(a) && is_integer(b) => Some(lhs.clone()),
+			_ => None,
+		},
+		Equal | NotEqual => match (lhs, rhs) {
+			(Scalar(a), Scalar(b)) if a == b => Some(Bool),
+			(Bool, Bool) => Some(Bool),
+			_ => None,
+		},
+		Less | LessEqual | Greater | GreaterEqual => match (lhs, rhs) {
+			(Scalar(a), Scalar(b)) if a == b => Some(Bool),
+			_ => None,
+		},
+		And | Or | Xor | Nor => match (lhs, rhs) {
+			(Bool, Bool) => Some(Bool),
+			_ => None,
+		},
+	}
+}
+
+/// Converts a scalar or vector to another type, if it isn't that type already.
+fn convert(expr: TypedExpression, target: &YuriType) -> TypedExpression {
+	if expr.expression_type == *target {
+		return expr;
+	}
+	// converting constants is free, so do it right away
+	if let TypedExpressionKind::Constant(value) = &expr.kind
+		&& let Some(converted) = value.convert(target)
+	{
+		return TypedExpression::new(TypedExpressionKind::Constant(converted), target.clone(), expr.location);
+	}
+	let location = expr.location.clone();
+	TypedExpression::new(TypedExpressionKind::Convert(Box::new(expr)), target.clone(), location)
+}
+
+fn expect_type(expr: &TypedExpression, expected: &YuriType) -> Result<(), YuriSemanticError> {
+	if types_match(&expr.expression_type, expected) {
+		Ok(())
+	} else {
+		Err(mismatch(format!("Expected `{expected}`, but found `{}` (%)", expr.expression_type), &expr.location))
+	}
+}
+
+fn check_entry_point(function: &TypedFunction, stage: ShaderStage) -> Result<(), YuriSemanticError> {
+	let invalid = |description: String| error(
+		YuriSemanticErrorType::InvalidEntryPoint,
+		description,
+		vec![function.location.clone()],
+	);
+	for id in &function.arguments {
+		let local = &function.locals[*id];
+		if !is_interface_type(&local.local_type) {
+			return Err(invalid(format!(
+				"Entry point inputs have to be numbers or vectors, but `{}` in `{}` (%) is a `{}`",
+				local.name, function.name, local.local_type,
+			)));
+		}
+	}
+	match (&function.return_type, stage) {
+		(YuriType::Complex(fields), _) => {
+			let mut positions = 0;
+			for field in fields {
+				if !is_interface_type(&field.field_type) {
+					return Err(invalid(format!(
+						"Entry point outputs have to be numbers or vectors, but `{}` in `{}` (%) is a `{}`",
+						field.name, function.name, field.field_type,
+					)));
+				}
+				for annotation in &field.annotations {
+					if annotation == "vert.pos" && stage == ShaderStage::Vertex {
+						if field.field_type != YuriType::Vector(NumberType::Float, CompositeSize::Four) {
+							return Err(invalid(format!("The `@vert.pos` output of `{}` (%) has to be an `f4`", function.name)));
+						}
+						positions += 1;
+					} else {
+						return Err(invalid(format!(
+							"`@{annotation}` doesn't mean anything on the output `{}` of `{}` (%)",
+							field.name, function.name,
+						)));
+					}
+				}
+			}
+			if stage == ShaderStage::Vertex && positions != 1 {
+				return Err(invalid(format!(
+					"Vertex shaders need exactly one output marked with `@vert.pos`, `{}` (%) has {positions}",
+					function.name,
+				)));
+			}
+		}
+		(YuriType::Vector(NumberType::Float, CompositeSize::Four), ShaderStage::Vertex) => {}
+		(other, ShaderStage::Vertex) => return Err(invalid(format!(
+			"Vertex shaders have to give back a position (`f4`) or a complex with a `@vert.pos` field, but `{}` (%) gives back `{other}`",
+			function.name,
+		))),
+		(other, ShaderStage::Fragment) if !is_interface_type(other) => return Err(invalid(format!(
+			"Fragment shaders have to give back a color, but `{}` (%) gives back `{other}`",
+			function.name,
+		))),
+		_ => {}
+	}
+	Ok(())
+}
+
+/// Finds any function that (eventually) calls itself. GPUs don't have a call stack, so that's not allowed.
+fn check_recursion(functions: &[TypedFunction]) -> Result<(), YuriSemanticError> {
+	fn calls(expr: &TypedExpression, out: &mut Vec<(FunctionId, Range<usize>)>) {
+		if let TypedExpressionKind::Call { function, .. } = &expr.kind {
+			out.push((*function, expr.location.clone()));
+		}
+		expr.for_each_child(|child| calls(child, out));
+	}
+	#[derive(Copy, Clone, Eq, PartialEq)]
+	enum Visit { New, Active, Done }
+	fn visit(
+		id: FunctionId,
+		graph: &[Vec<(FunctionId, Range<usize>)>],
+		functions: &[TypedFunction],
+		state: &mut [Visit],
+	) -> Result<(), YuriSemanticError> {
+		state[id] = Visit::Active;
+		for (callee, location) in &graph[id] {
+			match state[*callee] {
+				Visit::Active => return Err(error(
+					YuriSemanticErrorType::Recursion,
+					format!("`{}` ends up calling itself (at %), and recursion isn't allowed", functions[*callee].name),
+					vec![location.clone(), functions[*callee].location.clone()],
+				)),
+				Visit::New => visit(*callee, graph, functions, state)?,
+				Visit::Done => {}
+			}
+		}
+		state[id] = Visit::Done;
+		Ok(())
+	}
+	let graph: Vec<Vec<(FunctionId, Range<usize>)>> = functions.iter()
+		.map(|function| {
+			let mut out = Vec::new();
+			calls(&function.body, &mut out);
+			out
+		})
+		.collect();
+	let mut state = vec![Visit::New; functions.len()];
+	for id in 0..functions.len() {
+		if state[id] == Visit::New {
+			visit(id, &graph, functions, &mut state)?;
+		}
+	}
+	Ok(())
+}
+
+/// Type checks the root module and everything it imports, evaluating every global along the way.
+pub fn check_program(
+	root: &YuriModule,
+	sources: &SourceMap,
+	imports: &ResolvedImports,
+	resolution: &NameResolution,
+) -> Result<TypedProgram, YuriSemanticError> {
+	let mut checker = Checker {
+		root,
+		imports,
+		resolution,
+		property_ids: HashMap::new(),
+		global_ids: HashMap::new(),
+		function_ids: HashMap::new(),
+		property_symbols: Vec::new(),
+		global_symbols: Vec::new(),
+		function_symbols: Vec::new(),
+		properties: Vec::new(),
+		globals: Vec::new(),
+		signatures: Vec::new(),
+		functions: Vec::new(),
+	};
+	for (id, symbol) in resolution.symbols.iter().enumerate() {
+		match symbol.kind {
+			SymbolKind::Property => {
+				checker.property_ids.insert(id, checker.property_symbols.len());
+				checker.property_symbols.push(id);
+				checker.properties.push(Lazy::Pending);
+			}
+			SymbolKind::Global => {
+				checker.global_ids.insert(id, checker.global_symbols.len());
+				checker.global_symbols.push(id);
+				checker.globals.push(Lazy::Pending);
+			}
+			SymbolKind::Function => {
+				checker.function_ids.insert(id, checker.function_symbols.len());
+				checker.function_symbols.push(id);
+				checker.signatures.push(Lazy::Pending);
+				checker.functions.push(Lazy::Pending);
+			}
+			SymbolKind::Module(_) => {}
+		}
+	}
+
+	let mut properties = Vec::new();
+	for id in 0..checker.property_symbols.len() {
+		let location = resolution.symbols[checker.property_symbols[id]].location.clone();
+		properties.push(checker.check_property(id, &location)?);
+	}
+	let mut globals = Vec::new();
+	for id in 0..checker.global_symbols.len() {
+		let location = resolution.symbols[checker.global_symbols[id]].location.clone();
+		globals.push(checker.check_global(id, &location)?);
+	}
+	let mut functions = Vec::new();
+	for id in 0..checker.function_symbols.len() {
+		let location = resolution.symbols[checker.function_symbols[id]].location.clone();
+		functions.push(checker.check_function(id, &location)?);
+	}
+	// let go of the checker's copies, so the unwrapping below doesn't have to clone anything
+	drop(checker);
+	let program = TypedProgram {
+		properties: properties.into_iter().map(Rc::unwrap_or_clone).collect(),
+		globals: globals.into_iter().map(Rc::unwrap_or_clone).collect(),
+		functions: functions.into_iter().map(Rc::unwrap_or_clone).collect(),
+		sources: sources.clone(),
+	};
+	check_recursion(&program.functions)?;
+	check_spec_ids(&program.globals)?;
+	Ok(program)
+}
+
+/// Checks a single source without any imports, which is all most tests need.
+#[cfg(test)]
+pub(crate) fn check_source(source: &str) -> Result<TypedProgram, crate::error::YuriCompileError> {
+	let mut sources = SourceMap::new();
+	let root = sources.add("<test>", source);
+	crate::YuriShader::check_sources(&mut sources, root, &Default::default())
+}
+
+#[cfg(test)]
+mod test {
+	use crate::builtin::FragOrigin;
+	use crate::check::{check_source, TypedExpressionKind, TypedProgram};
+	use crate::consteval::ConstValue;
+	use crate::error::{YuriCompileError, YuriSemanticErrorType};
+	use crate::parse::{ArrayLength, NumberType, YuriType};
+
+	fn check_err(source: &str) -> YuriSemanticErrorType {
+		match check_source(source) {
+			Err(YuriCompileError::Semantic(err)) => err.error_type(),
+			other => panic!("expected a semantic error, got {other:?}"),
+		}
+	}
+
+	fn global(program: &TypedProgram, name: &str) -> ConstValue {
+		program.globals.iter()
+			.find(|g| g.name == name)
+			.map(|g| g.constant.clone())
+			.unwrap()
+	}
+
+	#[test]
+	fn globals_are_constant() {
+		let program = check_source("
+			let scale: f = 2.0;
+			let offset = f2(scale, 1.0) * 3.0;
+			let count: u = 3;
+			let total: u = fold sum = 0, n: count { sum + n };
+			fn double(x: f): f { x * 2.0 }
+			let doubled = double(scale);
+		").unwrap();
+		assert_eq!(global(&program, "offset"), ConstValue::Composite(vec![ConstValue::Float(6.0), ConstValue::Float(3.0)]));
+		assert_eq!(global(&program, "total"), ConstValue::Unsigned(3));
+		assert_eq!(global(&program, "doubled"), ConstValue::Float(4.0));
+	}
+
+	#[test]
+	fn negative_literals() {
+		let program = check_source("
+			let min: i = -2147483648;
+			let x: i = 5;
+			let a = x-1;
+			let b = -x;
+			let c = -1.5;
+			let d: f = -2;
+		").unwrap();
+		assert_eq!(global(&program, "min"), ConstValue::Signed(i32::MIN));
+		assert_eq!(global(&program, "a"), ConstValue::Signed(4));
+		assert_eq!(global(&program, "b"), ConstValue::Signed(-5));
+		assert_eq!(global(&program, "c"), ConstValue::Float(-1.5));
+		assert_eq!(global(&program, "d"), ConstValue::Float(-2.0));
+		// no more wrapping around when an unsigned value is asked for
+		assert_eq!(check_err("let n: u = -1;"), YuriSemanticErrorType::TypeMismatch);
+		assert_eq!(check_err("let n: i = -2147483649;"), YuriSemanticErrorType::TypeMismatch);
+	}
+
+	#[test]
+	fn literal_types() {
+		let program = check_source("
+			let a = 1u;
+			let b = -3i;
+			let c = 1f;
+			let d = 1e-3;
+			let e = 0x1.8p1;
+			let big = 0xFFFF_FFFF;
+			let x: f = 2.5;
+			let g = x * 2;
+			let h = 2.0h;
+			let third = 0.33333h;
+			let odd = 2049h;
+			fn main(pos: f3): f4 { f4(pos, 1) }
+		").unwrap();
+		assert_eq!(global(&program, "a"), ConstValue::Unsigned(1));
+		assert_eq!(global(&program, "b"), ConstValue::Signed(-3));
+		assert_eq!(global(&program, "c"), ConstValue::Float(1.0));
+		assert_eq!(global(&program, "d"), ConstValue::Float(1e-3));
+		assert_eq!(global(&program, "e"), ConstValue::Float(3.0));
+		assert_eq!(global(&program, "big"), ConstValue::Unsigned(u32::MAX));
+		assert_eq!(global(&program, "g"), ConstValue::Float(5.0));
+		assert_eq!(global(&program, "h"), ConstValue::Float(2.0));
+		// halves are `f`s that only hold what a half can
+		assert_eq!(global(&program, "third"), ConstValue::Float(0.33325195));
+		assert_eq!(global(&program, "odd"), ConstValue::Float(2048.0));
+
+		// suffixes don't give way to what's expected
+		assert_eq!(check_err("let n: f = 1u;"), YuriSemanticErrorType::TypeMismatch);
+		assert_eq!(check_err("let n = 1u + 1i;"), YuriSemanticErrorType::TypeMismatch);
+		assert_eq!(check_err("let n = 1.5i;"), YuriSemanticErrorType::TypeMismatch);
+		// integers that turn into floats have to stay the same number
+		assert_eq!(check_err("let n: f = 16777217;"), YuriSemanticErrorType::TypeMismatch);
+		assert!(check_source("let n: f = 16777216; let m: f = 16777217.0; let k = 16777217f;").is_ok());
+		for source in ["let n = 4294967296u;", "let n = -1u;", "let n = 1e39;", "let n = 1e-50;", "let n = 65520h;", "let n = 1e-8h;"] {
+			let Err(YuriCompileError::Semantic(err)) = check_source(source) else { panic!("{source} should overflow") };
+			// the error points at the whole literal, sign included
+			assert_eq!(err.markers(), [8..source.len() - 1], "{source}");
+		}
+	}
+
+	#[test]
+	fn globals_drive_lengths() {
+		let program = check_source("
+			let samples: u = 2 * 2;
+			let weights: f[samples] = loop n: samples { f(n) };
+			fn main(): f { fold sum = 0.0, w: weights { sum + w } }
+		").unwrap();
+		let function = &program.functions[0];
+		let TypedExpressionKind::Fold { items, .. } = &function.body.kind else { panic!("{:?}", function.body.kind) };
+		assert_eq!(items.expression_type, YuriType::Array(Box::new(YuriType::Scalar(NumberType::Float)), ArrayLength::Fixed(4)));
+		assert_eq!(check_err("let n: f = 2.0; fn main(): f[n] { loop k: 2 { 1.0 } }"), YuriSemanticErrorType::TypeMismatch);
+		assert_eq!(check_err("let n: u = 0; fn main(): f { let a: f[n] = [1.0]; a[0] }"), YuriSemanticErrorType::TypeMismatch);
+	}
+
+	#[test]
+	fn not_constant() {
+		assert_eq!(check_err("prop time: f; let later: f = time * 2.0;"), YuriSemanticErrorType::NotConstant);
+		// folds can count up to a prop just fine, it's only array lengths that have to be known
+		assert!(check_source("prop n: u; fn main(): f { fold sum = 0.0, k: n { sum + 1.0 } }").is_ok());
+		assert_eq!(check_err("prop n: u; fn main(): f[2] { loop k: n { 1.0 } }"), YuriSemanticErrorType::NotConstant);
+		assert_eq!(check_err("let a: f = b; let b: f = a;"), YuriSemanticErrorType::Recursion);
+	}
+
+	#[test]
+	fn type_errors() {
+		assert_eq!(check_err("fn main(x: f, y: i): f { x + y }"), YuriSemanticErrorType::TypeMismatch);
+		assert_eq!(check_err("fn main(x: f2): f { x.q }"), YuriSemanticErrorType::UnknownMember);
+		assert_eq!(check_err("fn a(): f { b() } fn b(): f { a() }"), YuriSemanticErrorType::Recursion);
+		assert_eq!(check_err("fn main(): f { let a = [1.0, 2.0]; a[2] }"), YuriSemanticErrorType::TypeMismatch);
+		assert!(check_source("fn main(x: f): f { x + 1.0 }").is_ok());
+	}
+
+	#[test]
+	fn specialization_constants() {
+		let program = check_source("
+			@spec(3) let SAMPLES: u = 8;
+			let HALF = SAMPLES / 2;
+			let FIXED: u = 4;
+			fn main(): f { fold sum = 0.0, k: HALF { sum + 1.0 } }
+		").unwrap();
+		let half = program.globals.iter().find(|g| g.name == "HALF").unwrap();
+		assert_eq!(half.constant, ConstValue::Unsigned(4));
+		assert_eq!(half.specialization, Some(0));
+		assert_eq!(program.globals[0].spec_id, Some(3));
+		assert_eq!(program.globals[2].specialization, None);
+
+		// things that decide types can't change after compiling
+		assert_eq!(check_err("@spec(0) let N: u = 2; fn main(): f[N] { loop k: 2 { 1.0 } }"), YuriSemanticErrorType::NotConstant);
+		assert_eq!(check_err("@spec(0) let N: u = 2; fn main(): f[2] { loop k: N { 1.0 } }"), YuriSemanticErrorType::NotConstant);
+		// OpSpecConstantOp can't do floats
+		assert_eq!(check_err("@spec(0) let S: f = 2.0; let T = S * 2.0;"), YuriSemanticErrorType::Unsupported);
+		assert!(check_source("@spec(0) let S: f = 2.0; let T = f2(S, 1.0);").is_ok());
+		assert_eq!(check_err("@spec(0) let S: f2 = f2(1.0, 2.0);"), YuriSemanticErrorType::Unsupported);
+		assert_eq!(check_err("@spec(0) let A: u = 1; @spec(0) let B: u = 2;"), YuriSemanticErrorType::DuplicateDeclaration);
+		assert_eq!(check_err("@spec let A: u = 1;"), YuriSemanticErrorType::InvalidDeclaration);
+		assert_eq!(check_err("@spec(0) prop a: f;"), YuriSemanticErrorType::InvalidDeclaration);
+	}
+
+	#[test]
+	fn entry_point_origin() {
+		let program = check_source("@frag(origin = lower_left) fn main(): f4 { f4(@frag.coord, 0.0, 1.0) }").unwrap();
+		assert_eq!(program.functions[0].frag_origin, Some(FragOrigin::LowerLeft));
+		assert_eq!(check_source("@frag fn main(): f4 { f4(1.0) }").unwrap().functions[0].frag_origin, None);
+		assert_eq!(check_err("@vert(origin = lower_left) fn main(): f4 { f4(1.0) }"), YuriSemanticErrorType::InvalidEntryPoint);
+		assert_eq!(check_err("@frag(origin = sideways) fn main(): f4 { f4(1.0) }"), YuriSemanticErrorType::InvalidEntryPoint);
+		assert_eq!(check_err("@frag(lower_left) fn main(): f4 { f4(1.0) }"), YuriSemanticErrorType::InvalidEntryPoint);
+	}
+}
@@ -1,127 +1,1864 @@
-use std::fs;
-use dr::Operand;
-use rspirv::binary::Assemble;
-use rspirv::binary::Disassemble;
-use rspirv::spirv::ExecutionMode;
-use rspirv::{dr, spirv};
-use std::mem::transmute;
-
-fn codegen() {
-	// Building
-	let mut b = dr::Builder::new();
-	b.set_version(1, 0);
-	b.capability(spirv::Capability::Shader);
-	b.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
-	// typedefs
-	let unit = b.type_void();
-	let f = b.type_float(32);
-	let f4 = b.type_vector(f, 4);
-	let f2 = b.type_vector(f, 2);
-	let void_func = b.type_function(unit, vec![]);
-	let out_f4 = b.type_pointer(None, spirv::StorageClass::Output, f4);
-	let in_f2 = b.type_pointer(None, spirv::StorageClass::Input, f2);
-
-	let position = b.variable(in_f2, None, spirv::StorageClass::Input, None);
-	b.decorate(
-		position,
-		spirv::Decoration::Location,
-		[Operand::LiteralBit32(0)],
-	);
-	b.name(position, "position");
-
-	let tex_coord = b.variable(in_f2, None, spirv::StorageClass::Input, None);
-	b.decorate(
-		tex_coord,
-		spirv::Decoration::Location,
-		[Operand::LiteralBit32(1)],
-	);
-	b.name(tex_coord, "tex_coord");
-
-	let frag_color = b.variable(out_f4, None, spirv::StorageClass::Output, None);
-	b.decorate(
-		frag_color,
-		spirv::Decoration::Location,
-		[Operand::LiteralBit32(0)],
-	);
-	b.name(frag_color, "frag_color");
-
-	let f_0 = b.constant_bit32(f, 0);
-	let f_1 = b.constant_bit32(f, unsafe { transmute(1.0f32) });
-
-	let f2_lit_0_1 = b.constant_composite(f2, [f_0, f_1]);
-
-	// entry point function
-	let entry = {
-		let entry = b
-			.begin_function(
-				unit,
-				None,
-				spirv::FunctionControl::NONE,
-				// spirv::FunctionControl::DONT_INLINE | spirv::FunctionControl::CONST,
-				void_func,
-			)
-			.unwrap();
-		b.begin_block(None).unwrap();
-
-		// %14 = OpLoad %v2float %texCoord
-		let tex_coord_local = b.load(f2, None, tex_coord, None, None).unwrap();
-
-		// %17 = OpCompositeExtract %float %14 0
-		// %18 = OpCompositeExtract %float %14 1
-		// %19 = OpCompositeConstruct %v4float %17 %18 %float_0 %float_1
-		let out = b
-			.composite_construct(f4, None, [tex_coord_local, f2_lit_0_1])
-			.unwrap();
-
-		// OpStore %fragColor %19
-		b.store(frag_color, out, None, None).unwrap();
-
-		b.ret().unwrap();
-		b.end_function().unwrap();
-		entry
-	};
-	b.entry_point(
-		spirv::ExecutionModel::Fragment,
-		entry,
-		"main",
-		[
-			// outputs
-			frag_color, // inputs
-			tex_coord,
-		],
-	);
-	// I get that an upper-left coordinate system is common,
-	// but we're not using it.
-	b.execution_mode(entry, ExecutionMode::OriginLowerLeft, []);
-	let module = b.module();
-
-	// Assembling
-	let code = module.assemble();
-	assert!(code.len() > 20); // Module header contains 5 words
-	assert_eq!(spirv::MAGIC_NUMBER, code[0]);
-
-	// Parsing
-	let mut loader = dr::Loader::new();
-	rspirv::binary::parse_words(&code, &mut loader).unwrap();
-	let module = loader.module();
-
-	let assembled = module.assemble();
-	let disassembled = module.disassemble();
-	println!("~~~~~ disassembly ~~~~~");
-	println!("{disassembled}");
-
-	fs::write(
-		"yuri.frag.spvasm",
-		disassembled
-	).unwrap();
-
-
-	let disassembled_bytes: Vec<u8> = assembled.iter().flat_map(|w| {
-		w.to_le_bytes()
-	}).collect();
-
-	fs::write(
-		"yuri.frag.spv",
-		disassembled_bytes
-	).unwrap();
-}
\ No newline at end of file
+//! SPIR-V code generation. Every entry point gets a module of its own (with the entry point called `main`),
+//! since that's what SDL's GPU API (and most everything else) wants.
+//!
+//! Props go in a uniform block using std140 layout, laid out the same way for every stage,
+//! and samplers get their own bindings. The descriptor sets follow SDL's conventions.
+//! Fragment shaders that need `@frag.coord` flipped get the render target's height added to the end of their block.
+use std::collections::{HashMap, HashSet};
+use rspirv::binary::{Assemble, Disassemble};
+use rspirv::dr::{self, Builder, InsertPoint, Operand};
+use rspirv::spirv::{self, GLOp, Word};
+use crate::builtin::{BuiltinFunction, BuiltinInput, FragOrigin};
+use crate::check::{EntryInterface, FunctionId, GlobalId, InterfaceSlot, LocalId, PropId, ShaderStage, TypedExpression, TypedExpressionKind, TypedFunction, TypedProgram, number_type};
+use crate::consteval::ConstValue;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::fold;
+use crate::parse::{ArrayLength, BinaryOperator, ComplexField, CompositeSize, NumberType, SamplerDimension, UnaryOperator, YuriType};
+use crate::options::{CompileOptions, OptimizationLevel};
+use crate::target::Target;
+use crate::source::FileId;
+
+/// A single compiled entry point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledShader {
+	/// The fully-qualified name of the entry point function.
+	/// The SPIR-V entry point is always called `main` though.
+	pub name: String,
+	pub stage: ShaderStage,
+	pub words: Vec<u32>,
+	/// How many sampler bindings the shader uses.
+	pub samplers: u32,
+	/// How many uniform buffers the shader uses (zero or one, since every prop goes in the same block).
+	pub uniform_buffers: u32,
+	/// The specialization constants the shader uses, sorted by ID.
+	pub specialization_constants: Vec<SpecializationConstant>,
+	/// Where the height of the render target (as an `f`) has to go in the uniform buffer, in bytes.
+	/// Only needed when the target can't give `@frag.coord` the origin the shader wants, so it has to be flipped.
+	pub viewport_height_offset: Option<u32>,
+}
+
+/// A specialization constant (a `let` with `@spec`), which can be changed when the pipeline is created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecializationConstant {
+	/// The fully-qualified name of the global.
+	pub name: String,
+	pub spec_id: u32,
+	pub constant_type: YuriType,
+	/// The value the shader uses if it isn't changed.
+	pub default: ConstValue,
+}
+
+impl CompiledShader {
+	/// The SPIR-V as little-endian bytes, ready to be written to a `.spv` file.
+	pub fn bytes(&self) -> Vec<u8> {
+		self.words.iter().flat_map(|w| w.to_le_bytes()).collect()
+	}
+
+	/// Human-readable SPIR-V assembly, for debugging.
+	pub fn disassemble(&self) -> String {
+		let mut loader = dr::Loader::new();
+		match rspirv::binary::parse_words(&self.words, &mut loader) {
+			Ok(()) => loader.module().disassemble(),
+			Err(err) => format!("; couldn't disassemble: {err}"),
+		}
+	}
+}
+
+// builder errors only happen if the code generation is broken.
+impl From<dr::Error> for YuriSemanticError {
+	fn from(value: dr::Error) -> Self {
+		YuriSemanticError {
+			error_type: YuriSemanticErrorType::Internal,
+			description: Some(format!("SPIR-V generation failed: {value}")),
+			markers: vec![],
+		}
+	}
+}
+
+/// The descriptor sets SDL expects things to be in, per stage.
+pub(crate) fn descriptor_sets(stage: ShaderStage) -> (u32, u32) {
+	// (samplers, uniform buffers)
+	match stage {
+		ShaderStage::Vertex => (0, 1),
+		ShaderStage::Fragment => (2, 3),
+	}
+}
+
+pub(crate) fn round_up(value: u32, alignment: u32) -> u32 {
+	value.div_ceil(alignment) * alignment
+}
+
+/// Size and alignment of a type in a std140 uniform block.
+pub(crate) fn std140_layout(ty: &YuriType) -> Option<(u32, u32)> {
+	Some(match ty {
+		YuriType::Scalar(_) => (4, 4),
+		YuriType::Vector(_, CompositeSize::Two) => (8, 8),
+		YuriType::Vector(_, CompositeSize::Three) => (12, 16),
+		YuriType::Vector(_, CompositeSize::Four) => (16, 16),
+		YuriType::Matrix(size) => (16 * size.count(), 16),
+		YuriType::Array(inner, ArrayLength::Fixed(length)) => {
+			let (size, alignment) = std140_layout(inner)?;
+			let stride = round_up(size, alignment.max(16));
+			(stride * *length as u32, alignment.max(16))
+		}
+		YuriType::Complex(fields) => {
+			let mut offset = 0;
+			let mut alignment = 16;
+			for field in fields {
+				let (size, field_alignment) = std140_layout(&field.field_type)?;
+				offset = round_up(offset, field_alignment) + size;
+				alignment = alignment.max(field_alignment);
+			}
+			(round_up(offset, alignment), alignment)
+		}
+		// bools don't have a size, and samplers aren't data
+		_ => return None,
+	})
+}
+
+/// The offset of each member of a std140 block (or struct).
+pub(crate) fn std140_offsets<'t>(members: impl Iterator<Item = &'t YuriType>) -> Vec<u32> {
+	let mut offset = 0;
+	members
+		.map(|ty| {
+			let (size, alignment) = std140_layout(ty).unwrap();
+			offset = round_up(offset, alignment);
+			offset += size;
+			offset - size
+		})
+		.collect()
+}
+
+/// Annotations on complex fields don't change the type, so they shouldn't get a type of their own either.
+pub(crate) fn without_annotations(ty: &YuriType) -> YuriType {
+	match ty {
+		YuriType::Array(inner, length) => YuriType::Array(Box::new(without_annotations(inner)), length.clone()),
+		YuriType::Complex(fields) => YuriType::Complex(fields.iter()
+			.map(|field| ComplexField {
+				name: field.name.clone(),
+				field_type: without_annotations(&field.field_type),
+				annotations: vec![],
+			})
+			.collect()),
+		other => other.clone(),
+	}
+}
+
+/// The state of the function currently being generated.
+struct FunctionState {
+	function: FunctionId,
+	locals: Vec<Word>,
+	/// The label of the block instructions are currently going into.
+	block: Word,
+	/// The expression instructions are currently being generated for, for debug info.
+	location: Option<std::ops::Range<usize>>,
+}
+
+/// Where things came from in the source, for `OpLine`.
+struct DebugInfo {
+	/// The `OpString` with the name of each file that's been used so far.
+	file_names: HashMap<FileId, Word>,
+	/// The block, file, line and column of the last `OpLine`, so it isn't repeated for everything on the same line.
+	last_line: Option<(Word, Word, u32, u32)>,
+}
+
+/// `OpSource` can only hold so much text (instructions have a 16-bit word count),
+/// so anything past that goes in `OpSourceContinued`s.
+const MAX_SOURCE_CHUNK: usize = 65000 * 4;
+
+fn source_chunks(text: &str) -> Vec<&str> {
+	let mut chunks = Vec::new();
+	let mut rest = text;
+	while rest.len() > MAX_SOURCE_CHUNK {
+		let mut end = MAX_SOURCE_CHUNK;
+		while !rest.is_char_boundary(end) {
+			end -= 1;
+		}
+		chunks.push(&rest[..end]);
+		rest = &rest[end..];
+	}
+	chunks.push(rest);
+	chunks
+}
+
+struct Codegen<'a> {
+	program: &'a TypedProgram,
+	stage: ShaderStage,
+	target: Target,
+	b: Builder,
+	glsl: Word,
+	types: HashMap<YuriType, Word>,
+	layout_types: HashMap<YuriType, Word>,
+	constants: HashMap<(Word, Vec<u32>), Word>,
+	functions: HashMap<FunctionId, Word>,
+	/// The uniform block, and the member index of each prop in it.
+	uniforms: Option<(Word, HashMap<PropId, u32>)>,
+	/// The member of the uniform block with the render target's height, and its offset.
+	viewport_height: Option<(u32, u32)>,
+	samplers: HashMap<PropId, Word>,
+	builtin_inputs: HashMap<BuiltinInput, Word>,
+	/// Globals that depend on specialization constants, which can't be regular constants.
+	specialized: HashMap<GlobalId, Word>,
+	specialization_constants: Vec<SpecializationConstant>,
+	/// Only there if debug info was asked for.
+	debug: Option<DebugInfo>,
+}
+
+impl<'a> Codegen<'a> {
+	fn new(program: &'a TypedProgram, stage: ShaderStage, target: Target, debug_info: bool) -> Self {
+		let mut b = Builder::new();
+		let version = target.spirv_version();
+		b.set_version(version.major, version.minor);
+		b.capability(spirv::Capability::Shader);
+		let glsl = b.ext_inst_import("GLSL.std.450");
+		b.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+		Self {
+			program,
+			stage,
+			target,
+			b,
+			glsl,
+			types: HashMap::new(),
+			layout_types: HashMap::new(),
+			constants: HashMap::new(),
+			functions: HashMap::new(),
+			uniforms: None,
+			viewport_height: None,
+			samplers: HashMap::new(),
+			builtin_inputs: HashMap::new(),
+			specialized: HashMap::new(),
+			specialization_constants: Vec::new(),
+			debug: debug_info.then(|| DebugInfo {
+				file_names: HashMap::new(),
+				last_line: None,
+			}),
+		}
+	}
+
+	/// The `OpString` naming the file, which also puts the file's text in the module the first time around.
+	fn debug_file(&mut self, file: FileId) -> Option<Word> {
+		let debug = self.debug.as_mut()?;
+		if let Some(name) = debug.file_names.get(&file) {
+			return Some(*name);
+		}
+		let source = self.program.sources.files().get(file)?;
+		let name = self.b.string(source.name());
+		debug.file_names.insert(file, name);
+		let chunks = source_chunks(source.text());
+		self.b.source(spirv::SourceLanguage::Unknown, 0, Some(name), Some(chunks[0]));
+		for chunk in &chunks[1..] {
+			self.b.source_continued(*chunk);
+		}
+		Some(name)
+	}
+
+	/// Adds an `OpLine` for where the expression is, unless the last one already covers it.
+	/// An `OpLine` that didn't end up covering any instructions gets replaced.
+	fn debug_line(&mut self, state: &FunctionState, location: &std::ops::Range<usize>) {
+		if self.debug.is_none() {
+			return;
+		}
+		let Some((file, position)) = self.program.sources.position(location.start) else {
+			return;
+		};
+		let file = file.id();
+		let Some(name) = self.debug_file(file) else {
+			return;
+		};
+		let Some(debug) = self.debug.as_mut() else {
+			return;
+		};
+		// `OpLine` counts from 1
+		let (line, column) = (position.line + 1, position.column + 1);
+		let current = (state.block, name, line as u32, column as u32);
+		if debug.last_line == Some(current) {
+			return;
+		}
+		debug.last_line = Some(current);
+		if let (Some(function), Some(block)) = (self.b.selected_function(), self.b.selected_block())
+			&& let Some(last) = self.b.module_ref().functions[function].blocks[block].instructions.last()
+			&& last.class.opcode == spirv::Op::Line
+		{
+			let _ = self.b.pop_instruction();
+		}
+		self.b.line(name, line as u32, column as u32);
+	}
+
+	/// Names a local, as long as the value is actually its own thing (and not a constant, say).
+	fn name_local(&mut self, state: &FunctionState, id: LocalId, value: &TypedExpression) {
+		// unit values don't have an ID to name
+		if value.expression_type != YuriType::Unit
+			&& !matches!(value.kind, TypedExpressionKind::Constant(_) | TypedExpressionKind::Local(_) | TypedExpressionKind::Global(_))
+		{
+			let name = &self.program.functions[state.function].locals[id].name;
+			self.b.name(state.locals[id], name.as_str());
+		}
+	}
+
+	fn type_of(&mut self, ty: &YuriType) -> Word {
+		let ty = &without_annotations(ty);
+		if let Some(id) = self.types.get(ty) {
+			return *id;
+		}
+		let id = match ty {
+			YuriType::Unit => self.b.type_void(),
+			YuriType::Bool => self.b.type_bool(),
+			YuriType::Scalar(NumberType::Float) => self.b.type_float(32),
+			YuriType::Scalar(NumberType::Signed) => self.b.type_int(32, 1),
+			YuriType::Scalar(NumberType::Unsigned) => self.b.type_int(32, 0),
+			YuriType::Vector(number_type, size) => {
+				let component = self.type_of(&YuriType::Scalar(*number_type));
+				self.b.type_vector(component, size.count())
+			}
+			YuriType::Matrix(size) => {
+				let column = self.type_of(&YuriType::Vector(NumberType::Float, *size));
+				self.b.type_matrix(column, size.count())
+			}
+			YuriType::Sampler(dimension) => {
+				let float = self.type_of(&YuriType::Scalar(NumberType::Float));
+				let dim = match dimension {
+					SamplerDimension::One => {
+						self.b.capability(spirv::Capability::Sampled1D);
+						spirv::Dim::Dim1D
+					}
+					SamplerDimension::Two => spirv::Dim::Dim2D,
+					SamplerDimension::Three => spirv::Dim::Dim3D,
+					SamplerDimension::Cube => spirv::Dim::DimCube,
+				};
+				let image = self.b.type_image(float, dim, 0, 0, 0, 1, spirv::ImageFormat::Unknown, None);
+				self.b.type_sampled_image(image)
+			}
+			YuriType::Array(inner, length) => {
+				let length = match length {
+					ArrayLength::Fixed(length) => *length as u32,
+					ArrayLength::Named(_) => unreachable!("array lengths are resolved by the type checker"),
+				};
+				let element = self.type_of(inner);
+				let length = self.constant(&ConstValue::Unsigned(length), &YuriType::Scalar(NumberType::Unsigned));
+				// aggregates get explicit IDs, otherwise the builder would merge them with the uniform block's types,
+				// and those have layout decorations that aren't allowed everywhere
+				let id = self.b.id();
+				self.b.type_array_id(Some(id), element, length)
+			}
+			YuriType::Complex(fields) => {
+				let members: Vec<Word> = fields.iter().map(|f| self.type_of(&f.field_type)).collect();
+				let id = self.b.id();
+				for (i, field) in fields.iter().enumerate() {
+					self.b.member_name(id, i as u32, field.name.as_str());
+				}
+				self.b.type_struct_id(Some(id), members)
+			}
+		};
+		self.types.insert(ty.clone(), id);
+		id
+	}
+
+	fn pointer_to(&mut self, storage_class: spirv::StorageClass, ty: Word) -> Word {
+		self.b.type_pointer(None, storage_class, ty)
+	}
+
+	/// Builds a type with explicit layout decorations, for use in the uniform block.
+	/// These can't be shared with the regular types, so they're kept separately.
+	fn layout_type_of(&mut self, ty: &YuriType) -> Word {
+		if let Some(id) = self.layout_types.get(ty) {
+			return *id;
+		}
+		let id = match ty {
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => {
+				let element = self.layout_type_of(inner);
+				let (size, alignment) = std140_layout(inner).unwrap();
+				let length = self.constant(&ConstValue::Unsigned(*length as u32), &YuriType::Scalar(NumberType::Unsigned));
+				let id = self.b.id();
+				self.b.type_array_id(Some(id), element, length);
+				self.b.decorate(id, spirv::Decoration::ArrayStride, [Operand::LiteralBit32(round_up(size, alignment.max(16)))]);
+				id
+			}
+			YuriType::Complex(fields) => {
+				let members: Vec<Word> = fields.iter().map(|f| self.layout_type_of(&f.field_type)).collect();
+				let id = self.b.id();
+				self.b.type_struct_id(Some(id), members);
+				self.decorate_members(id, fields.iter().map(|f| (f.name.as_str(), &f.field_type)));
+				id
+			}
+			other => return self.type_of(other),
+		};
+		self.layout_types.insert(ty.clone(), id);
+		id
+	}
+
+	/// Gives back the offset of each member.
+	fn decorate_members<'t>(&mut self, id: Word, members: impl Iterator<Item = (&'t str, &'t YuriType)>) -> Vec<u32> {
+		let members: Vec<_> = members.collect();
+		let offsets = std140_offsets(members.iter().map(|(_, ty)| *ty));
+		for (i, ((name, ty), offset)) in members.into_iter().zip(&offsets).enumerate() {
+			self.b.member_decorate(id, i as u32, spirv::Decoration::Offset, [Operand::LiteralBit32(*offset)]);
+			if let YuriType::Matrix(_) = ty {
+				self.b.member_decorate(id, i as u32, spirv::Decoration::ColMajor, []);
+				self.b.member_decorate(id, i as u32, spirv::Decoration::MatrixStride, [Operand::LiteralBit32(16)]);
+			}
+			self.b.member_name(id, i as u32, name);
+		}
+		offsets
+	}
+
+	fn constant(&mut self, value: &ConstValue, ty: &YuriType) -> Word {
+		let ty_id = self.type_of(ty);
+		let key = match value {
+			ConstValue::Bool(b) => vec![*b as u32],
+			ConstValue::Float(f) => vec![f.to_bits()],
+			ConstValue::Signed(i) => vec![*i as u32],
+			ConstValue::Unsigned(u) => vec![*u],
+			ConstValue::Composite(components) => {
+				let component_types: Vec<YuriType> = match ty {
+					YuriType::Vector(number_type, size) => vec![YuriType::Scalar(*number_type); size.count() as usize],
+					YuriType::Matrix(size) => vec![YuriType::Vector(NumberType::Float, *size); size.count() as usize],
+					YuriType::Array(inner, _) => vec![inner.as_ref().clone(); components.len()],
+					YuriType::Complex(fields) => fields.iter().map(|f| f.field_type.clone()).collect(),
+					_ => Vec::new(),
+				};
+				components.iter()
+					.zip(&component_types)
+					.map(|(c, ty)| self.constant(c, ty))
+					.collect()
+			}
+		};
+		if let Some(id) = self.constants.get(&(ty_id, key.clone())) {
+			return *id;
+		}
+		let id = match value {
+			ConstValue::Bool(true) => self.b.constant_true(ty_id),
+			ConstValue::Bool(false) => self.b.constant_false(ty_id),
+			ConstValue::Composite(_) if *ty == YuriType::Unit => 0,
+			ConstValue::Composite(_) => self.b.constant_composite(ty_id, key.clone()),
+			_ => self.b.constant_bit32(ty_id, key[0]),
+		};
+		self.constants.insert((ty_id, key), id);
+		id
+	}
+
+	fn global(&mut self, id: GlobalId) -> Result<Word, YuriSemanticError> {
+		let global = &self.program.globals[id];
+		if global.specialization.is_none() {
+			return Ok(self.constant(&global.constant, &global.global_type));
+		}
+		if let Some(word) = self.specialized.get(&id) {
+			return Ok(*word);
+		}
+		let word = if let Some(spec_id) = global.spec_id {
+			let ty = self.type_of(&global.global_type);
+			let word = match global.constant {
+				ConstValue::Bool(true) => self.b.spec_constant_true(ty),
+				ConstValue::Bool(false) => self.b.spec_constant_false(ty),
+				ConstValue::Float(f) => self.b.spec_constant_bit32(ty, f.to_bits()),
+				ConstValue::Signed(i) => self.b.spec_constant_bit32(ty, i as u32),
+				ConstValue::Unsigned(u) => self.b.spec_constant_bit32(ty, u),
+				ConstValue::Composite(_) => unreachable!("specialization constants are always scalars"),
+			};
+			self.b.decorate(word, spirv::Decoration::SpecId, [Operand::LiteralBit32(spec_id)]);
+			self.specialization_constants.push(SpecializationConstant {
+				name: global.name.clone(),
+				spec_id,
+				constant_type: global.global_type.clone(),
+				default: global.constant.clone(),
+			});
+			word
+		} else {
+			self.specialized_expression(&global.value)?
+		};
+		self.b.name(word, global.name.as_str());
+		self.specialized.insert(id, word);
+		Ok(word)
+	}
+
+	fn spec_op(&mut self, ty: Word, opcode: spirv::Op, operands: impl IntoIterator<Item = Operand>) -> Word {
+		let id = self.b.id();
+		let mut all_operands = vec![Operand::LiteralSpecConstantOpInteger(opcode)];
+		all_operands.extend(operands);
+		let instruction = dr::Instruction::new(spirv::Op::SpecConstantOp, Some(ty), Some(id), all_operands);
+		self.b.insert_types_global_values(InsertPoint::End, instruction);
+		id
+	}
+
+	fn spec_splat(&mut self, value: Word, ty: &YuriType) -> Word {
+		match ty {
+			YuriType::Vector(_, size) => {
+				let ty = self.type_of(ty);
+				self.b.spec_constant_composite(ty, vec![value; size.count() as usize])
+			}
+			_ => value,
+		}
+	}
+
+	/// Generates a global that depends on a specialization constant, using `OpSpecConstantOp`
+	/// so it changes along with it. The type checker already made sure this will work.
+	fn specialized_expression(&mut self, expr: &TypedExpression) -> Result<Word, YuriSemanticError> {
+		use spirv::Op;
+		let ty = &expr.expression_type;
+		let ty_id = self.type_of(ty);
+		let extract = |this: &mut Self, ty: Word, composite: Word, index: u32| {
+			this.spec_op(ty, Op::CompositeExtract, [Operand::IdRef(composite), Operand::LiteralBit32(index)])
+		};
+		Ok(match &expr.kind {
+			TypedExpressionKind::Constant(value) => self.constant(value, ty),
+			TypedExpressionKind::Global(id) => self.global(*id)?,
+			TypedExpressionKind::Block { tail, .. } => self.specialized_expression(tail)?,
+			TypedExpressionKind::Splat(operand) => {
+				let value = self.specialized_expression(operand)?;
+				self.spec_splat(value, ty)
+			}
+			TypedExpressionKind::Construct(arguments) if matches!(ty, YuriType::Vector(..)) => {
+				// vectors can be built out of other vectors, but composites can't
+				let mut components = Vec::new();
+				for argument in arguments {
+					let value = self.specialized_expression(argument)?;
+					match &argument.expression_type {
+						YuriType::Vector(number_type, size) => {
+							let component_type = self.type_of(&YuriType::Scalar(*number_type));
+							for i in 0..size.count() {
+								components.push(extract(self, component_type, value, i));
+							}
+						}
+						_ => components.push(value),
+					}
+				}
+				self.b.spec_constant_composite(ty_id, components)
+			}
+			TypedExpressionKind::Construct(arguments)
+			| TypedExpressionKind::Complex(arguments)
+			| TypedExpressionKind::Array(arguments) => {
+				let arguments = arguments.iter()
+					.map(|a| self.specialized_expression(a))
+					.collect::<Result<Vec<_>, _>>()?;
+				self.b.spec_constant_composite(ty_id, arguments)
+			}
+			TypedExpressionKind::Swizzle { target, components } => {
+				let value = self.specialized_expression(target)?;
+				if components.len() == 1 {
+					extract(self, ty_id, value, components[0])
+				} else {
+					let operands = [Operand::IdRef(value), Operand::IdRef(value)].into_iter()
+						.chain(components.iter().map(|c| Operand::LiteralBit32(*c)));
+					self.spec_op(ty_id, Op::VectorShuffle, operands)
+				}
+			}
+			TypedExpressionKind::Field { target, index } => {
+				let value = self.specialized_expression(target)?;
+				extract(self, ty_id, value, *index)
+			}
+			TypedExpressionKind::Index { target, index } => {
+				let value = self.specialized_expression(target)?;
+				let TypedExpressionKind::Constant(index) = &index.kind else {
+					unreachable!("specialized indices are always constant");
+				};
+				extract(self, ty_id, value, index.as_index().unwrap_or(0) as u32)
+			}
+			TypedExpressionKind::Convert(operand) => {
+				let value = self.specialized_expression(operand)?;
+				let from = &operand.expression_type;
+				match (from, number_type(from), number_type(ty)) {
+					(YuriType::Bool, _, Some(number)) => {
+						let one = ConstValue::Unsigned(1).convert(&YuriType::Scalar(number)).unwrap();
+						let one = self.constant(&one, ty);
+						let zero = self.constant(&ConstValue::zero(number), ty);
+						self.spec_op(ty_id, Op::Select, [Operand::IdRef(value), Operand::IdRef(one), Operand::IdRef(zero)])
+					}
+					(_, Some(number), None) => {
+						let zero = self.constant(&ConstValue::zero(number), from);
+						self.spec_op(ty_id, Op::INotEqual, [Operand::IdRef(value), Operand::IdRef(zero)])
+					}
+					// there's no bitcast in here, but signedness doesn't matter to adding
+					(_, Some(_), Some(number)) => {
+						let zero = self.constant(&ConstValue::zero(number), ty);
+						self.spec_op(ty_id, Op::IAdd, [Operand::IdRef(value), Operand::IdRef(zero)])
+					}
+					_ => value,
+				}
+			}
+			TypedExpressionKind::Unary { operator, operand } => {
+				let value = self.specialized_expression(operand)?;
+				let opcode = match (operator, ty) {
+					(UnaryOperator::Negate, _) => Op::SNegate,
+					(UnaryOperator::Not, YuriType::Bool) => Op::LogicalNot,
+					(UnaryOperator::Not, _) => Op::Not,
+				};
+				self.spec_op(ty_id, opcode, [Operand::IdRef(value)])
+			}
+			TypedExpressionKind::Binary { operator, lhs, rhs } => {
+				use BinaryOperator::*;
+				let a = self.specialized_expression(lhs)?;
+				let b = self.specialized_expression(rhs)?;
+				let (a, b) = match (&lhs.expression_type, &rhs.expression_type) {
+					(YuriType::Vector(_, size), YuriType::Scalar(number)) => (a, self.spec_splat(b, &YuriType::Vector(*number, *size))),
+					(YuriType::Scalar(number), YuriType::Vector(_, size)) => (self.spec_splat(a, &YuriType::Vector(*number, *size)), b),
+					_ => (a, b),
+				};
+				let boolean = lhs.expression_type == YuriType::Bool;
+				let signed = number_type(&lhs.expression_type) == Some(NumberType::Signed);
+				let opcode = match operator {
+					Plus => Op::IAdd,
+					Minus => Op::ISub,
+					Times => Op::IMul,
+					Divided if signed => Op::SDiv,
+					Divided => Op::UDiv,
+					Modulo if signed => Op::SRem,
+					Modulo => Op::UMod,
+					BitAnd => Op::BitwiseAnd,
+					BitOr => Op::BitwiseOr,
+					BitXor => Op::BitwiseXor,
+					ShiftLeft => Op::ShiftLeftLogical,
+					ShiftRight if signed => Op::ShiftRightArithmetic,
+					ShiftRight => Op::ShiftRightLogical,
+					Equal if boolean => Op::LogicalEqual,
+					Equal => Op::IEqual,
+					NotEqual | Xor if boolean => Op::LogicalNotEqual,
+					NotEqual => Op::INotEqual,
+					Less if signed => Op::SLessThan,
+					Less => Op::ULessThan,
+					LessEqual if signed => Op::SLessThanEqual,
+					LessEqual => Op::ULessThanEqual,
+					Greater if signed => Op::SGreaterThan,
+					Greater => Op::UGreaterThan,
+					GreaterEqual if signed => Op::SGreaterThanEqual,
+					GreaterEqual => Op::UGreaterThanEqual,
+					And => Op::LogicalAnd,
+					Or | Nor => Op::LogicalOr,
+					Xor | Exponent => unreachable!("not allowed on integers"),
+				};
+				let result = self.spec_op(ty_id, opcode, [Operand::IdRef(a), Operand::IdRef(b)]);
+				if *operator == Nor {
+					self.spec_op(ty_id, Op::LogicalNot, [Operand::IdRef(result)])
+				} else {
+					result
+				}
+			}
+			TypedExpressionKind::If { condition, block, block_else } => {
+				let condition = self.specialized_expression(condition)?;
+				let a = self.specialized_expression(block)?;
+				let b = self.specialized_expression(block_else.as_ref().expect("specialized ifs always have an else"))?;
+				self.spec_op(ty_id, Op::Select, [Operand::IdRef(condition), Operand::IdRef(a), Operand::IdRef(b)])
+			}
+			_ => unreachable!("the type checker only lets through things that work as OpSpecConstantOp"),
+		})
+	}
+
+	/// Puts the props in the uniform block (and the samplers next to it).
+	/// If `@frag.coord` needs flipping, the render target's height gets tacked onto the end of the block.
+	fn declare_props(&mut self, viewport_height: bool) {
+		let (sampler_set, uniform_set) = descriptor_sets(self.stage);
+		let mut members = Vec::new();
+		let mut member_indices = HashMap::new();
+		for (id, property) in self.program.properties.iter().enumerate() {
+			if let YuriType::Sampler(_) = property.property_type {
+				let ty = self.type_of(&property.property_type);
+				let pointer = self.pointer_to(spirv::StorageClass::UniformConstant, ty);
+				let variable = self.b.variable(pointer, None, spirv::StorageClass::UniformConstant, None);
+				if self.target.env().is_vulkan() {
+					self.b.decorate(variable, spirv::Decoration::DescriptorSet, [Operand::LiteralBit32(sampler_set)]);
+				}
+				self.b.decorate(variable, spirv::Decoration::Binding, [Operand::LiteralBit32(self.samplers.len() as u32)]);
+				self.b.name(variable, property.name.as_str());
+				self.samplers.insert(id, variable);
+			} else {
+				member_indices.insert(id, members.len() as u32);
+				members.push(id);
+			}
+		}
+		if members.is_empty() && !viewport_height {
+			return;
+		}
+		let float = YuriType::Scalar(NumberType::Float);
+		let mut member_types: Vec<Word> = members.iter()
+			.map(|id| self.layout_type_of(&self.program.properties[*id].property_type.clone()))
+			.collect();
+		if viewport_height {
+			member_types.push(self.type_of(&float));
+		}
+		let block = self.b.id();
+		self.b.type_struct_id(Some(block), member_types);
+		self.b.decorate(block, spirv::Decoration::Block, []);
+		self.b.name(block, "Props");
+		let program = self.program;
+		let offsets = self.decorate_members(block, members.iter()
+			.map(|id| {
+				let property = &program.properties[*id];
+				(property.name.as_str(), &property.property_type)
+			})
+			.chain(viewport_height.then_some(("viewport_height", &float))));
+		if viewport_height {
+			self.viewport_height = Some((members.len() as u32, offsets[members.len()]));
+		}
+		let pointer = self.pointer_to(spirv::StorageClass::Uniform, block);
+		let variable = self.b.variable(pointer, None, spirv::StorageClass::Uniform, None);
+		// OpenGL only has bindings, but the stages lay things out the same way so they don't clash
+		if self.target.env().is_vulkan() {
+			self.b.decorate(variable, spirv::Decoration::DescriptorSet, [Operand::LiteralBit32(uniform_set)]);
+		}
+		self.b.decorate(variable, spirv::Decoration::Binding, [Operand::LiteralBit32(0)]);
+		self.b.name(variable, "props");
+		self.uniforms = Some((variable, member_indices));
+	}
+
+	/// Loads a value out of the uniform block. Aggregates have to be loaded piece by piece,
+	/// since the block's types have layout decorations and the regular types don't.
+	fn load_uniform(&mut self, pointer: Word, ty: &YuriType) -> Result<Word, YuriSemanticError> {
+		let ty_id = self.type_of(ty);
+		let element_types: Vec<YuriType> = match ty {
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => vec![inner.as_ref().clone(); *length],
+			YuriType::Complex(fields) => fields.iter().map(|f| f.field_type.clone()).collect(),
+			_ => return Ok(self.b.load(ty_id, None, pointer, None, [])?),
+		};
+		let mut elements = Vec::new();
+		for (i, element_type) in element_types.iter().enumerate() {
+			let index = self.constant(&ConstValue::Unsigned(i as u32), &YuriType::Scalar(NumberType::Unsigned));
+			let element_pointer_type = {
+				let element = self.layout_type_of(element_type);
+				self.pointer_to(spirv::StorageClass::Uniform, element)
+			};
+			let element_pointer = self.b.access_chain(element_pointer_type, None, pointer, [index])?;
+			elements.push(self.load_uniform(element_pointer, element_type)?);
+		}
+		Ok(self.b.composite_construct(ty_id, None, elements)?)
+	}
+
+	fn builtin_input(&mut self, input: BuiltinInput, location: &std::ops::Range<usize>) -> Result<Word, YuriSemanticError> {
+		check_input_stage(input, self.stage, location)?;
+		if let Some(variable) = self.builtin_inputs.get(&input) {
+			return Ok(*variable);
+		}
+		let (ty, builtin) = match input {
+			// the GPU gives us all 4 components, but only x and y are worth anything
+			BuiltinInput::FragCoord => (YuriType::Vector(NumberType::Float, CompositeSize::Four), spirv::BuiltIn::FragCoord),
+			BuiltinInput::FrontFacing => (YuriType::Bool, spirv::BuiltIn::FrontFacing),
+			// OpenGL's VertexId includes the base vertex, just like Vulkan's VertexIndex
+			BuiltinInput::VertexIndex if !self.target.env().is_vulkan() => (YuriType::Scalar(NumberType::Unsigned), spirv::BuiltIn::VertexId),
+			BuiltinInput::VertexIndex => (YuriType::Scalar(NumberType::Unsigned), spirv::BuiltIn::VertexIndex),
+			// but its InstanceId doesn't include the base instance
+			BuiltinInput::InstanceIndex if !self.target.env().is_vulkan() => {
+				return Err(YuriSemanticError {
+					error_type: YuriSemanticErrorType::Unsupported,
+					description: Some(format!(
+						"`@{}` (at %) isn't available when targeting {}, since it wouldn't include the base instance",
+						input.name(), self.target.env(),
+					)),
+					markers: vec![location.clone()],
+				});
+			}
+			BuiltinInput::InstanceIndex => (YuriType::Scalar(NumberType::Unsigned), spirv::BuiltIn::InstanceIndex),
+		};
+		let ty = self.type_of(&ty);
+		let pointer = self.pointer_to(spirv::StorageClass::Input, ty);
+		// these are found in the middle of a function, but the variable has to be global
+		let current = self.b.selected_block();
+		self.b.select_block(None)?;
+		let variable = self.b.variable(pointer, None, spirv::StorageClass::Input, None);
+		self.b.select_block(current)?;
+		self.b.decorate(variable, spirv::Decoration::BuiltIn, [Operand::BuiltIn(builtin)]);
+		self.b.name(variable, input.name());
+		self.builtin_inputs.insert(input, variable);
+		Ok(variable)
+	}
+
+	fn begin_block(&mut self, state: &mut FunctionState, label: Word) -> Result<(), YuriSemanticError> {
+		self.b.begin_block(Some(label))?;
+		state.block = label;
+		Ok(())
+	}
+
+	/// Creates a function-local variable. These have to be at the very start of the function.
+	fn function_variable(&mut self, ty: Word) -> Result<Word, YuriSemanticError> {
+		let pointer = self.pointer_to(spirv::StorageClass::Function, ty);
+		let id = self.b.id();
+		let current = self.b.selected_block();
+		self.b.select_block(Some(0))?;
+		self.b.insert_into_block(InsertPoint::Begin, dr::Instruction::new(
+			spirv::Op::Variable,
+			Some(pointer),
+			Some(id),
+			vec![Operand::StorageClass(spirv::StorageClass::Function)],
+		))?;
+		self.b.select_block(current)?;
+		Ok(id)
+	}
+
+	fn function(&mut self, id: FunctionId) -> Result<(), YuriSemanticError> {
+		let function = &self.program.functions[id];
+		let return_type = self.type_of(&function.return_type);
+		let argument_types: Vec<Word> = function.arguments.iter()
+			.map(|a| self.type_of(&function.locals[*a].local_type))
+			.collect();
+		let function_type = self.b.type_function(return_type, argument_types.clone());
+		let function_id = self.functions[&id];
+		self.b.begin_function(return_type, Some(function_id), spirv::FunctionControl::NONE, function_type)?;
+		self.b.name(function_id, function.name.as_str());
+		let mut state = FunctionState { function: id, locals: vec![0; function.locals.len()], block: 0, location: None };
+		for (argument, ty) in function.arguments.iter().zip(argument_types) {
+			let parameter = self.b.function_parameter(ty)?;
+			self.b.name(parameter, function.locals[*argument].name.as_str());
+			state.locals[*argument] = parameter;
+		}
+		let entry = self.b.id();
+		self.begin_block(&mut state, entry)?;
+		let value = self.expression(&mut state, &function.body)?;
+		if function.return_type == YuriType::Unit {
+			self.b.ret()?;
+		} else {
+			self.b.ret_value(value)?;
+		}
+		self.b.end_function()?;
+		Ok(())
+	}
+
+	fn expression(&mut self, state: &mut FunctionState, expr: &TypedExpression) -> Result<Word, YuriSemanticError> {
+		if self.debug.is_none() {
+			return self.expression_instructions(state, expr);
+		}
+		// whatever comes after the subexpressions belongs to the parent again
+		let parent = state.location.replace(expr.location.clone());
+		self.debug_line(state, &expr.location);
+		let result = self.expression_instructions(state, expr);
+		state.location = parent;
+		if let Some(parent) = &state.location {
+			self.debug_line(state, &parent.clone());
+		}
+		result
+	}
+
+	fn expression_instructions(&mut self, state: &mut FunctionState, expr: &TypedExpression) -> Result<Word, YuriSemanticError> {
+		let ty = &expr.expression_type;
+		let ty_id = self.type_of(ty);
+		Ok(match &expr.kind {
+			TypedExpressionKind::Constant(value) => self.constant(value, ty),
+			TypedExpressionKind::Local(id) => state.locals[*id],
+			TypedExpressionKind::Global(id) => self.global(*id)?,
+			TypedExpressionKind::Property(id) => {
+				if let Some(sampler) = self.samplers.get(id) {
+					let sampler = *sampler;
+					self.b.load(ty_id, None, sampler, None, [])?
+				} else {
+					let (block, members) = self.uniforms.as_ref().expect("props without a uniform block");
+					let (block, member) = (*block, members[id]);
+					let member = self.constant(&ConstValue::Unsigned(member), &YuriType::Scalar(NumberType::Unsigned));
+					let pointee = self.layout_type_of(ty);
+					let pointer_type = self.pointer_to(spirv::StorageClass::Uniform, pointee);
+					let pointer = self.b.access_chain(pointer_type, None, block, [member])?;
+					self.load_uniform(pointer, ty)?
+				}
+			}
+			TypedExpressionKind::BuiltinInput(input) => {
+				let variable = self.builtin_input(*input, &expr.location)?;
+				if *input == BuiltinInput::FragCoord {
+					let f4 = self.type_of(&YuriType::Vector(NumberType::Float, CompositeSize::Four));
+					let coord = self.b.load(f4, None, variable, None, [])?;
+					if let Some((member, _)) = self.viewport_height {
+						// y = height - y, to get the origin the shader asked for
+						let float = self.type_of(&YuriType::Scalar(NumberType::Float));
+						let (block, _) = self.uniforms.as_ref().expect("no uniform block for the viewport height");
+						let block = *block;
+						let member = self.constant(&ConstValue::Unsigned(member), &YuriType::Scalar(NumberType::Unsigned));
+						let pointer_type = self.pointer_to(spirv::StorageClass::Uniform, float);
+						let pointer = self.b.access_chain(pointer_type, None, block, [member])?;
+						let height = self.b.load(float, None, pointer, None, [])?;
+						let x = self.b.composite_extract(float, None, coord, [0])?;
+						let y = self.b.composite_extract(float, None, coord, [1])?;
+						let y = self.b.f_sub(float, None, height, y)?;
+						self.b.composite_construct(ty_id, None, [x, y])?
+					} else {
+						self.b.vector_shuffle(ty_id, None, coord, coord, [0, 1])?
+					}
+				} else {
+					self.b.load(ty_id, None, variable, None, [])?
+				}
+			}
+			TypedExpressionKind::Call { function, arguments } => {
+				let arguments = arguments.iter()
+					.map(|a| self.expression(state, a))
+					.collect::<Result<Vec<_>, _>>()?;
+				let function = self.functions[function];
+				self.b.function_call(ty_id, None, function, arguments)?
+			}
+			TypedExpressionKind::Builtin { function, arguments } => self.builtin(state, *function, arguments, ty)?,
+			TypedExpressionKind::Construct(arguments)
+			| TypedExpressionKind::Complex(arguments)
+			| TypedExpressionKind::Array(arguments) => {
+				let arguments = arguments.iter()
+					.map(|a| self.expression(state, a))
+					.collect::<Result<Vec<_>, _>>()?;
+				self.b.composite_construct(ty_id, None, arguments)?
+			}
+			TypedExpressionKind::Convert(operand) => {
+				let value = self.expression(state, operand)?;
+				self.convert(value, &operand.expression_type, ty)?
+			}
+			TypedExpressionKind::Splat(operand) => {
+				let value = self.expression(state, operand)?;
+				self.splat(value, ty)?
+			}
+			TypedExpressionKind::Swizzle { target, components } => {
+				let value = self.expression(state, target)?;
+				if components.len() == 1 {
+					self.b.composite_extract(ty_id, None, value, [components[0]])?
+				} else {
+					self.b.vector_shuffle(ty_id, None, value, value, components.iter().copied())?
+				}
+			}
+			TypedExpressionKind::Field { target, index } => {
+				let value = self.expression(state, target)?;
+				self.b.composite_extract(ty_id, None, value, [*index])?
+			}
+			TypedExpressionKind::Index { target, index } => {
+				let value = self.expression(state, target)?;
+				if let TypedExpressionKind::Constant(constant) = &index.kind
+					&& let Some(index) = constant.as_index()
+				{
+					self.b.composite_extract(ty_id, None, value, [index as u32])?
+				} else {
+					let index = self.expression(state, index)?;
+					self.dynamic_index(value, &target.expression_type, index, ty)?
+				}
+			}
+			TypedExpressionKind::Unary { operator, operand } => {
+				let value = self.expression(state, operand)?;
+				self.unary(*operator, value, ty)?
+			}
+			TypedExpressionKind::Binary { operator, lhs, rhs } => {
+				let a = self.expression(state, lhs)?;
+				let b = self.expression(state, rhs)?;
+				self.binary(*operator, a, &lhs.expression_type, b, &rhs.expression_type, ty)?
+			}
+			TypedExpressionKind::Block { bindings, tail } => {
+				for (id, value) in bindings {
+					state.locals[*id] = self.expression(state, value)?;
+					self.name_local(state, *id, value);
+				}
+				self.expression(state, tail)?
+			}
+			TypedExpressionKind::If { condition, block, block_else } => {
+				let condition = self.expression(state, condition)?;
+				let then_label = self.b.id();
+				let else_label = self.b.id();
+				let merge_label = self.b.id();
+				self.b.selection_merge(merge_label, spirv::SelectionControl::NONE)?;
+				let false_label = if block_else.is_some() { else_label } else { merge_label };
+				self.b.branch_conditional(condition, then_label, false_label, [])?;
+				let previous = state.block;
+
+				self.begin_block(state, then_label)?;
+				let then_value = self.expression(state, block)?;
+				let then_end = state.block;
+				self.b.branch(merge_label)?;
+
+				let (else_value, else_end) = if let Some(block_else) = block_else {
+					self.begin_block(state, else_label)?;
+					let value = self.expression(state, block_else)?;
+					let end = state.block;
+					self.b.branch(merge_label)?;
+					(value, end)
+				} else {
+					(0, previous)
+				};
+
+				self.begin_block(state, merge_label)?;
+				if *ty == YuriType::Unit {
+					0
+				} else {
+					self.b.phi(ty_id, None, [(then_value, then_end), (else_value, else_end)])?
+				}
+			}
+			TypedExpressionKind::Loop { index, count, block } => {
+				let u = YuriType::Scalar(NumberType::Unsigned);
+				let count = self.constant(&ConstValue::Unsigned(*count), &u);
+				let array = self.function_variable(ty_id)?;
+				let element_type = self.type_of(&block.expression_type);
+				let element_pointer = self.pointer_to(spirv::StorageClass::Function, element_type);
+				self.counted_loop(state, count, &u, &[], |this, state, i, _| {
+					state.locals[*index] = i;
+					this.b.name(i, this.program.functions[state.function].locals[*index].name.as_str());
+					let value = this.expression(state, block)?;
+					let pointer = this.b.access_chain(element_pointer, None, array, [i])?;
+					this.b.store(pointer, value, None, [])?;
+					Ok(vec![])
+				})?;
+				self.b.load(ty_id, None, array, None, [])?
+			}
+			TypedExpressionKind::Fold { accumulator, initial, item, items, block } => {
+				let initial = self.expression(state, initial)?;
+				let items_value = self.expression(state, items)?;
+				let (count, counter_type, items_variable) = self.iteration(items_value, &items.expression_type)?;
+				let results = self.counted_loop(state, count, &counter_type, &[(initial, ty_id)], |this, state, i, values| {
+					state.locals[*accumulator] = values[0];
+					state.locals[*item] = this.iteration_item(items_variable, &items.expression_type, i)?;
+					let locals = &this.program.functions[state.function].locals;
+					this.b.name(values[0], locals[*accumulator].name.as_str());
+					this.b.name(state.locals[*item], locals[*item].name.as_str());
+					Ok(vec![this.expression(state, block)?])
+				})?;
+				results[0]
+			}
+			TypedExpressionKind::Map { item, items, block } => {
+				let items_value = self.expression(state, items)?;
+				let (count, counter_type, items_variable) = self.iteration(items_value, &items.expression_type)?;
+				let array = self.function_variable(ty_id)?;
+				let element_type = self.type_of(&block.expression_type);
+				let element_pointer = self.pointer_to(spirv::StorageClass::Function, element_type);
+				self.counted_loop(state, count, &counter_type, &[], |this, state, i, _| {
+					state.locals[*item] = this.iteration_item(items_variable, &items.expression_type, i)?;
+					this.b.name(state.locals[*item], this.program.functions[state.function].locals[*item].name.as_str());
+					let value = this.expression(state, block)?;
+					let pointer = this.b.access_chain(element_pointer, None, array, [i])?;
+					this.b.store(pointer, value, None, [])?;
+					Ok(vec![])
+				})?;
+				self.b.load(ty_id, None, array, None, [])?
+			}
+		})
+	}
+
+	/// Sets up what a fold or map goes over: the number of iterations, the type of the counter,
+	/// and (for arrays) a variable holding the array so it can be indexed.
+	fn iteration(&mut self, items: Word, items_type: &YuriType) -> Result<(Word, YuriType, Option<Word>), YuriSemanticError> {
+		Ok(match items_type {
+			YuriType::Array(_, ArrayLength::Fixed(length)) => {
+				let u = YuriType::Scalar(NumberType::Unsigned);
+				let count = self.constant(&ConstValue::Unsigned(*length as u32), &u);
+				let ty = self.type_of(items_type);
+				let variable = self.function_variable(ty)?;
+				self.b.store(variable, items, None, [])?;
+				(count, u, Some(variable))
+			}
+			counter_type => (items, counter_type.clone(), None),
+		})
+	}
+
+	fn iteration_item(&mut self, items: Option<Word>, items_type: &YuriType, i: Word) -> Result<Word, YuriSemanticError> {
+		match (items, items_type) {
+			(Some(variable), YuriType::Array(inner, _)) => {
+				let ty = self.type_of(inner);
+				let pointer_type = self.pointer_to(spirv::StorageClass::Function, ty);
+				let pointer = self.b.access_chain(pointer_type, None, variable, [i])?;
+				Ok(self.b.load(ty, None, pointer, None, [])?)
+			}
+			_ => Ok(i),
+		}
+	}
+
+	/// Emits a structured loop running `count` times, with some values carried between iterations.
+	/// The body gets the counter and the current values, and gives back the next values.
+	/// Gives back the final values.
+	fn counted_loop(
+		&mut self,
+		state: &mut FunctionState,
+		count: Word,
+		counter_type: &YuriType,
+		carried: &[(Word, Word)],
+		body: impl FnOnce(&mut Self, &mut FunctionState, Word, &[Word]) -> Result<Vec<Word>, YuriSemanticError>,
+	) -> Result<Vec<Word>, YuriSemanticError> {
+		let counter_type_id = self.type_of(counter_type);
+		let bool_type = self.type_of(&YuriType::Bool);
+		let zero = self.constant(&ConstValue::zero(number_type(counter_type).unwrap()), counter_type);
+		let one = self.constant(&ConstValue::Unsigned(1).convert(counter_type).unwrap(), counter_type);
+		let header = self.b.id();
+		let condition_label = self.b.id();
+		let body_label = self.b.id();
+		let continue_label = self.b.id();
+		let merge = self.b.id();
+		let next_counter = self.b.id();
+		let next_values: Vec<Word> = carried.iter().map(|_| self.b.id()).collect();
+		let pre = state.block;
+		self.b.branch(header)?;
+
+		self.begin_block(state, header)?;
+		let counter = self.b.phi(counter_type_id, None, [(zero, pre), (next_counter, continue_label)])?;
+		let values = carried.iter()
+			.zip(&next_values)
+			.map(|((initial, ty), next)| self.b.phi(*ty, None, [(*initial, pre), (*next, continue_label)]))
+			.collect::<Result<Vec<_>, _>>()?;
+		self.b.loop_merge(merge, continue_label, spirv::LoopControl::NONE, [])?;
+		self.b.branch(condition_label)?;
+
+		self.begin_block(state, condition_label)?;
+		let in_range = if *counter_type == YuriType::Scalar(NumberType::Signed) {
+			self.b.s_less_than(bool_type, None, counter, count)?
+		} else {
+			self.b.u_less_than(bool_type, None, counter, count)?
+		};
+		self.b.branch_conditional(in_range, body_label, merge, [])?;
+
+		self.begin_block(state, body_label)?;
+		let results = body(self, state, counter, &values)?;
+		self.b.branch(continue_label)?;
+
+		self.begin_block(state, continue_label)?;
+		for ((result, next), (_, ty)) in results.iter().zip(&next_values).zip(carried) {
+			self.b.copy_object(*ty, Some(*next), *result)?;
+		}
+		self.b.i_add(counter_type_id, Some(next_counter), counter, one)?;
+		self.b.branch(header)?;
+
+		self.begin_block(state, merge)?;
+		Ok(values)
+	}
+
+	/// Indexes an array or matrix with a runtime index, which needs a variable to point into.
+	fn dynamic_index(&mut self, value: Word, target_type: &YuriType, index: Word, ty: &YuriType) -> Result<Word, YuriSemanticError> {
+		let ty_id = self.type_of(ty);
+		if let YuriType::Vector(..) = target_type {
+			return Ok(self.b.vector_extract_dynamic(ty_id, None, value, index)?);
+		}
+		let target_type = self.type_of(target_type);
+		let variable = self.function_variable(target_type)?;
+		self.b.store(variable, value, None, [])?;
+		let pointer_type = self.pointer_to(spirv::StorageClass::Function, ty_id);
+		let pointer = self.b.access_chain(pointer_type, None, variable, [index])?;
+		Ok(self.b.load(ty_id, None, pointer, None, [])?)
+	}
+
+	fn splat(&mut self, value: Word, ty: &YuriType) -> Result<Word, YuriSemanticError> {
+		let ty_id = self.type_of(ty);
+		Ok(match ty {
+			YuriType::Vector(_, size) => self.b.composite_construct(ty_id, None, vec![value; size.count() as usize])?,
+			_ => value,
+		})
+	}
+
+	fn convert(&mut self, value: Word, from: &YuriType, to: &YuriType) -> Result<Word, YuriSemanticError> {
+		let to_id = self.type_of(to);
+		Ok(match (from, number_type(from), number_type(to)) {
+			(YuriType::Bool, _, Some(number)) => {
+				let one = ConstValue::Unsigned(1).convert(&YuriType::Scalar(number)).unwrap();
+				let one = self.constant(&one, to);
+				let zero = self.constant(&ConstValue::zero(number), to);
+				self.b.select(to_id, None, value, one, zero)?
+			}
+			(_, Some(number), None) => {
+				let zero = self.constant(&ConstValue::zero(number), from);
+				if number == NumberType::Float {
+					self.b.f_unord_not_equal(to_id, None, value, zero)?
+				} else {
+					self.b.i_not_equal(to_id, None, value, zero)?
+				}
+			}
+			(_, Some(from_number), Some(to_number)) => match (from_number, to_number) {
+				(NumberType::Float, NumberType::Signed) => self.b.convert_f_to_s(to_id, None, value)?,
+				(NumberType::Float, NumberType::Unsigned) => self.b.convert_f_to_u(to_id, None, value)?,
+				(NumberType::Signed, NumberType::Float) => self.b.convert_s_to_f(to_id, None, value)?,
+				(NumberType::Unsigned, NumberType::Float) => self.b.convert_u_to_f(to_id, None, value)?,
+				(a, b) if a == b => value,
+				_ => self.b.bitcast(to_id, None, value)?,
+			},
+			_ => value,
+		})
+	}
+
+	fn unary(&mut self, operator: UnaryOperator, value: Word, ty: &YuriType) -> Result<Word, YuriSemanticError> {
+		let ty_id = self.type_of(ty);
+		Ok(match (operator, ty) {
+			(UnaryOperator::Negate, YuriType::Matrix(size)) => {
+				let column_type = YuriType::Vector(NumberType::Float, *size);
+				let column_id = self.type_of(&column_type);
+				let columns = (0..size.count())
+					.map(|i| {
+						let column = self.b.composite_extract(column_id, None, value, [i])?;
+						self.b.f_negate(column_id, None, column)
+					})
+					.collect::<Result<Vec<_>, _>>()?;
+				self.b.composite_construct(ty_id, None, columns)?
+			}
+			(UnaryOperator::Negate, _) if number_type(ty) == Some(NumberType::Float) => self.b.f_negate(ty_id, None, value)?,
+			(UnaryOperator::Negate, _) => self.b.s_negate(ty_id, None, value)?,
+			(UnaryOperator::Not, YuriType::Bool) => self.b.logical_not(ty_id, None, value)?,
+			(UnaryOperator::Not, _) => self.b.not(ty_id, None, value)?,
+		})
+	}
+
+	fn binary(
+		&mut self,
+		operator: BinaryOperator,
+		a: Word,
+		a_type: &YuriType,
+		b: Word,
+		b_type: &YuriType,
+		ty: &YuriType,
+	) -> Result<Word, YuriSemanticError> {
+		use BinaryOperator::*;
+		let ty_id = self.type_of(ty);
+		// matrices have their own set of instructions
+		match (a_type, b_type, operator) {
+			(YuriType::Matrix(_), YuriType::Vector(..), Times) => return Ok(self.b.matrix_times_vector(ty_id, None, a, b)?),
+			(YuriType::Vector(..), YuriType::Matrix(_), Times) => return Ok(self.b.vector_times_matrix(ty_id, None, a, b)?),
+			(YuriType::Matrix(_), YuriType::Matrix(_), Times) => return Ok(self.b.matrix_times_matrix(ty_id, None, a, b)?),
+			(YuriType::Matrix(_), YuriType::Scalar(_), Times) => return Ok(self.b.matrix_times_scalar(ty_id, None, a, b)?),
+			(YuriType::Scalar(_), YuriType::Matrix(_), Times) => return Ok(self.b.matrix_times_scalar(ty_id, None, b, a)?),
+			(YuriType::Matrix(_), YuriType::Scalar(_), Divided) => {
+				let one = self.constant(&ConstValue::Float(1.0), b_type);
+				let inverse = self.b.f_div(self.types[b_type], None, one, b)?;
+				return Ok(self.b.matrix_times_scalar(ty_id, None, a, inverse)?);
+			}
+			(YuriType::Matrix(size), YuriType::Matrix(_), Plus | Minus) => {
+				let column_id = self.type_of(&YuriType::Vector(NumberType::Float, *size));
+				let columns = (0..size.count())
+					.map(|i| {
+						let a = self.b.composite_extract(column_id, None, a, [i])?;
+						let b = self.b.composite_extract(column_id, None, b, [i])?;
+						if operator == Plus {
+							self.b.f_add(column_id, None, a, b)
+						} else {
+							self.b.f_sub(column_id, None, a, b)
+						}
+					})
+					.collect::<Result<Vec<_>, _>>()?;
+				return Ok(self.b.composite_construct(ty_id, None, columns)?);
+			}
+			(YuriType::Vector(NumberType::Float, _), YuriType::Scalar(_), Times) => {
+				return Ok(self.b.vector_times_scalar(ty_id, None, a, b)?);
+			}
+			(YuriType::Scalar(_), YuriType::Vector(NumberType::Float, _), Times) => {
+				return Ok(self.b.vector_times_scalar(ty_id, None, b, a)?);
+			}
+			_ => {}
+		}
+		// everything else works component by component, so spread any scalars out first
+		let operand_type = if matches!(a_type, YuriType::Vector(..)) { a_type } else { b_type };
+		// (shift amounts keep their own signedness, so they can't just take the other side's type)
+		let (a, b) = match (a_type, b_type) {
+			(YuriType::Vector(_, size), YuriType::Scalar(number)) => (a, self.splat(b, &YuriType::Vector(*number, *size))?),
+			(YuriType::Scalar(number), YuriType::Vector(_, size)) => (self.splat(a, &YuriType::Vector(*number, *size))?, b),
+			_ => (a, b),
+		};
+		let number = number_type(operand_type);
+		let float = number == Some(NumberType::Float);
+		let signed = number == Some(NumberType::Signed);
+		Ok(match operator {
+			Plus if float => self.b.f_add(ty_id, None, a, b)?,
+			Plus => self.b.i_add(ty_id, None, a, b)?,
+			Minus if float => self.b.f_sub(ty_id, None, a, b)?,
+			Minus => self.b.i_sub(ty_id, None, a, b)?,
+			Times if float => self.b.f_mul(ty_id, None, a, b)?,
+			Times => self.b.i_mul(ty_id, None, a, b)?,
+			Divided if float => self.b.f_div(ty_id, None, a, b)?,
+			Divided if signed => self.b.s_div(ty_id, None, a, b)?,
+			Divided => self.b.u_div(ty_id, None, a, b)?,
+			// same as Rust's `%`, the sign follows the left side
+			Modulo if float => self.b.f_rem(ty_id, None, a, b)?,
+			Modulo if signed => self.b.s_rem(ty_id, None, a, b)?,
+			Modulo => self.b.u_mod(ty_id, None, a, b)?,
+			Exponent => self.b.ext_inst(ty_id, None, self.glsl, GLOp::Pow as u32, [Operand::IdRef(a), Operand::IdRef(b)])?,
+			BitAnd => self.b.bitwise_and(ty_id, None, a, b)?,
+			BitOr => self.b.bitwise_or(ty_id, None, a, b)?,
+			BitXor => self.b.bitwise_xor(ty_id, None, a, b)?,
+			ShiftLeft => self.b.shift_left_logical(ty_id, None, a, b)?,
+			ShiftRight if signed => self.b.shift_right_arithmetic(ty_id, None, a, b)?,
+			ShiftRight => self.b.shift_right_logical(ty_id, None, a, b)?,
+			Equal if *a_type == YuriType::Bool => self.b.logical_equal(ty_id, None, a, b)?,
+			Equal if float => self.b.f_ord_equal(ty_id, None, a, b)?,
+			Equal => self.b.i_equal(ty_id, None, a, b)?,
+			NotEqual if *a_type == YuriType::Bool => self.b.logical_not_equal(ty_id, None, a, b)?,
+			NotEqual if float => self.b.f_unord_not_equal(ty_id, None, a, b)?,
+			NotEqual => self.b.i_not_equal(ty_id, None, a, b)?,
+			Less if float => self.b.f_ord_less_than(ty_id, None, a, b)?,
+			Less if signed => self.b.s_less_than(ty_id, None, a, b)?,
+			Less => self.b.u_less_than(ty_id, None, a, b)?,
+			LessEqual if float => self.b.f_ord_less_than_equal(ty_id, None, a, b)?,
+			LessEqual if signed => self.b.s_less_than_equal(ty_id, None, a, b)?,
+			LessEqual => self.b.u_less_than_equal(ty_id, None, a, b)?,
+			Greater if float => self.b.f_ord_greater_than(ty_id, None, a, b)?,
+			Greater if signed => self.b.s_greater_than(ty_id, None, a, b)?,
+			Greater => self.b.u_greater_than(ty_id, None, a, b)?,
+			GreaterEqual if float => self.b.f_ord_greater_than_equal(ty_id, None, a, b)?,
+			GreaterEqual if signed => self.b.s_greater_than_equal(ty_id, None, a, b)?,
+			GreaterEqual => self.b.u_greater_than_equal(ty_id, None, a, b)?,
+			And => self.b.logical_and(ty_id, None, a, b)?,
+			Or => self.b.logical_or(ty_id, None, a, b)?,
+			Xor => self.b.logical_not_equal(ty_id, None, a, b)?,
+			Nor => {
+				let or = self.b.logical_or(ty_id, None, a, b)?;
+				self.b.logical_not(ty_id, None, or)?
+			}
+		})
+	}
+
+	fn builtin(
+		&mut self,
+		state: &mut FunctionState,
+		function: BuiltinFunction,
+		arguments: &[TypedExpression],
+		ty: &YuriType,
+	) -> Result<Word, YuriSemanticError> {
+		use BuiltinFunction::*;
+		// assertions are only checked on the CPU, the GPU doesn't even need their arguments
+		if function.is_assertion() {
+			return Ok(0);
+		}
+		let ty_id = self.type_of(ty);
+		let mut values = arguments.iter()
+			.map(|a| self.expression(state, a))
+			.collect::<Result<Vec<_>, _>>()?;
+		let number = number_type(&arguments[0].expression_type);
+		let pick = |float: GLOp, signed: GLOp, unsigned: GLOp| match number {
+			Some(NumberType::Signed) => signed,
+			Some(NumberType::Unsigned) => unsigned,
+			_ => float,
+		};
+		let op = match function {
+			Sin => GLOp::Sin,
+			Cos => GLOp::Cos,
+			Tan => GLOp::Tan,
+			Asin => GLOp::Asin,
+			Acos => GLOp::Acos,
+			Atan => GLOp::Atan,
+			Atan2 => GLOp::Atan2,
+			Pow => GLOp::Pow,
+			Exp => GLOp::Exp,
+			Exp2 => GLOp::Exp2,
+			Log => GLOp::Log,
+			Log2 => GLOp::Log2,
+			Sqrt => GLOp::Sqrt,
+			InverseSqrt => GLOp::InverseSqrt,
+			Abs => pick(GLOp::FAbs, GLOp::SAbs, GLOp::SAbs),
+			Sign => pick(GLOp::FSign, GLOp::SSign, GLOp::SSign),
+			Floor => GLOp::Floor,
+			Ceil => GLOp::Ceil,
+			Fract => GLOp::Fract,
+			Round => GLOp::RoundEven,
+			Trunc => GLOp::Trunc,
+			Min => pick(GLOp::FMin, GLOp::SMin, GLOp::UMin),
+			Max => pick(GLOp::FMax, GLOp::SMax, GLOp::UMax),
+			Clamp => pick(GLOp::FClamp, GLOp::SClamp, GLOp::UClamp),
+			Mix => {
+				// the blend factor can be a scalar, but FMix wants it to match
+				values[2] = self.splat(values[2], ty)?;
+				GLOp::FMix
+			}
+			Step => GLOp::Step,
+			SmoothStep => {
+				values[0] = self.splat(values[0], ty)?;
+				values[1] = self.splat(values[1], ty)?;
+				GLOp::SmoothStep
+			}
+			Length => GLOp::Length,
+			Distance => GLOp::Distance,
+			Dot => return Ok(self.b.dot(ty_id, None, values[0], values[1])?),
+			Cross => GLOp::Cross,
+			Normalize => GLOp::Normalize,
+			Reflect => GLOp::Reflect,
+			Transpose => return Ok(self.b.transpose(ty_id, None, values[0])?),
+			Determinant => GLOp::Determinant,
+			Inverse => GLOp::MatrixInverse,
+			Assert | AssertEq | AssertNear => unreachable!("assertions are skipped above"),
+			Sample => {
+				return Ok(if implicit_lod(self.stage) {
+					self.b.image_sample_implicit_lod(ty_id, None, values[0], values[1], None, [])?
+				} else {
+					let zero = self.constant(&ConstValue::Float(0.0), &YuriType::Scalar(NumberType::Float));
+					self.b.image_sample_explicit_lod(ty_id, None, values[0], values[1], spirv::ImageOperands::LOD, [Operand::IdRef(zero)])?
+				});
+			}
+		};
+		Ok(self.b.ext_inst(ty_id, None, self.glsl, op as u32, values.into_iter().map(Operand::IdRef))?)
+	}
+
+	/// Generates the actual `main` function, which reads the inputs, calls the entry point and writes the outputs.
+	fn entry_point(&mut self, id: FunctionId, function: &TypedFunction, interface: &EntryInterface) -> Result<(), YuriSemanticError> {
+		let mut variables = Vec::new();
+		let mut inputs = Vec::new();
+		for input in &interface.inputs {
+			let ty = self.type_of(&input.variable_type);
+			let pointer = self.pointer_to(spirv::StorageClass::Input, ty);
+			let variable = self.b.variable(pointer, None, spirv::StorageClass::Input, None);
+			if let InterfaceSlot::Location(location) = input.slot {
+				self.b.decorate(variable, spirv::Decoration::Location, [Operand::LiteralBit32(location)]);
+			}
+			if self.stage == ShaderStage::Fragment && !interpolated(&input.variable_type) {
+				self.b.decorate(variable, spirv::Decoration::Flat, []);
+			}
+			self.b.name(variable, input.name.as_str());
+			variables.push(variable);
+			inputs.push((variable, ty));
+		}
+		let mut outputs = Vec::new();
+		for output in &interface.outputs {
+			let ty = self.type_of(&output.variable_type);
+			let pointer = self.pointer_to(spirv::StorageClass::Output, ty);
+			let variable = self.b.variable(pointer, None, spirv::StorageClass::Output, None);
+			match output.slot {
+				InterfaceSlot::Location(location) => {
+					self.b.decorate(variable, spirv::Decoration::Location, [Operand::LiteralBit32(location)]);
+				}
+				InterfaceSlot::Position => {
+					self.b.decorate(variable, spirv::Decoration::BuiltIn, [Operand::BuiltIn(spirv::BuiltIn::Position)]);
+				}
+			}
+			self.b.name(variable, output.name.as_str());
+			variables.push(variable);
+			outputs.push((variable, ty));
+		}
+
+		let void = self.type_of(&YuriType::Unit);
+		let void_function = self.b.type_function(void, []);
+		let main = self.b.begin_function(void, None, spirv::FunctionControl::NONE, void_function)?;
+		self.b.name(main, "main");
+		self.b.begin_block(None)?;
+		let arguments = inputs.iter()
+			.map(|(variable, ty)| self.b.load(*ty, None, *variable, None, []))
+			.collect::<Result<Vec<_>, _>>()?;
+		let return_type = self.type_of(&function.return_type);
+		let result = self.b.function_call(return_type, None, self.functions[&id], arguments)?;
+		if interface.complex_output {
+			for (i, (variable, ty)) in outputs.iter().enumerate() {
+				let value = self.b.composite_extract(*ty, None, result, [i as u32])?;
+				self.b.store(*variable, value, None, [])?;
+			}
+		} else if let Some((variable, _)) = outputs.first() {
+			self.b.store(*variable, result, None, [])?;
+		}
+		self.b.ret()?;
+		self.b.end_function()?;
+
+		// builtin inputs get declared as they're used, so they have to be added at the end
+		variables.extend(self.builtin_inputs.values().copied());
+		if self.target.lists_every_global() {
+			variables.extend(self.uniforms.as_ref().map(|(variable, _)| *variable));
+			variables.extend(self.samplers.values().copied());
+		}
+		let model = match self.stage {
+			ShaderStage::Vertex => spirv::ExecutionModel::Vertex,
+			ShaderStage::Fragment => spirv::ExecutionModel::Fragment,
+		};
+		self.b.entry_point(model, main, "main", variables);
+		if self.stage == ShaderStage::Fragment {
+			// if the target can't do the origin the shader wants, `@frag.coord` gets flipped instead
+			let origin = function.frag_origin.unwrap_or(self.target.frag_origin());
+			let mode = match origin {
+				FragOrigin::LowerLeft if self.target.env().supports_origin(origin) => spirv::ExecutionMode::OriginLowerLeft,
+				_ => spirv::ExecutionMode::OriginUpperLeft,
+			};
+			self.b.execution_mode(main, mode, []);
+		}
+		Ok(())
+	}
+
+	/// Assembles the module, leaving out the names unless there's supposed to be debug info.
+	/// (Everything else only gets added with debug info in the first place.)
+	fn finish(self, debug_info: bool) -> Vec<u32> {
+		let mut module = self.b.module();
+		if !debug_info {
+			module.debug_names.clear();
+		}
+		module.assemble()
+	}
+}
+
+/// Finds every function the entry point (eventually) calls, including itself.
+fn reachable_functions(program: &TypedProgram, entry: FunctionId) -> Vec<FunctionId> {
+	fn visit(expr: &TypedExpression, program: &TypedProgram, seen: &mut HashSet<FunctionId>, order: &mut Vec<FunctionId>) {
+		if let TypedExpressionKind::Call { function, .. } = &expr.kind
+			&& seen.insert(*function)
+		{
+			order.push(*function);
+			visit(&program.functions[*function].body, program, seen, order);
+		}
+		expr.for_each_child(|child| visit(child, program, seen, order));
+	}
+	let mut seen = HashSet::from([entry]);
+	let mut order = vec![entry];
+	visit(&program.functions[entry].body, program, &mut seen, &mut order);
+	order
+}
+
+/// Whether a shader made of these functions is a fragment shader that reads `@frag.coord`,
+/// which might need flipping to get the origin it asked for.
+pub(crate) fn reads_frag_coord(program: &TypedProgram, stage: ShaderStage, functions: &[FunctionId]) -> bool {
+	fn reads(expr: &TypedExpression) -> bool {
+		let mut found = matches!(expr.kind, TypedExpressionKind::BuiltinInput(BuiltinInput::FragCoord));
+		expr.for_each_child(|child| found = found || reads(child));
+		found
+	}
+	stage == ShaderStage::Fragment && functions.iter().any(|id| reads(&program.functions[*id].body))
+}
+
+pub(crate) fn check_props(program: &TypedProgram) -> Result<(), YuriSemanticError> {
+	for property in &program.properties {
+		if !matches!(property.property_type, YuriType::Sampler(_)) && std140_layout(&property.property_type).is_none() {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::Unsupported,
+				description: Some(format!(
+					"The prop `{}` (at %) can't be a `{}`, props can only hold numbers, vectors, matrices, or arrays/complexes of them (or be a sampler)",
+					property.name, property.property_type,
+				)),
+				markers: vec![property.location.clone()],
+			});
+		}
+	}
+	Ok(())
+}
+
+/// The stage and interface of an entry point, or an error if the function isn't one.
+pub(crate) fn entry_interface(function: &TypedFunction) -> Result<(ShaderStage, EntryInterface), YuriSemanticError> {
+	match (function.stage, function.interface()) {
+		(Some(stage), Some(interface)) => Ok((stage, interface)),
+		_ => Err(YuriSemanticError {
+			error_type: YuriSemanticErrorType::InvalidEntryPoint,
+			description: Some(format!("`{}` (at %) isn't an entry point", function.name)),
+			markers: vec![function.location.clone()],
+		}),
+	}
+}
+
+pub(crate) fn check_input_stage(input: BuiltinInput, stage: ShaderStage, location: &std::ops::Range<usize>) -> Result<(), YuriSemanticError> {
+	if input.stage() != stage {
+		return Err(YuriSemanticError {
+			error_type: YuriSemanticErrorType::InvalidEntryPoint,
+			description: Some(format!("`@{}` (at %) can't be used in a {:?} shader", input.name(), stage)),
+			markers: vec![location.clone()],
+		});
+	}
+	Ok(())
+}
+
+/// Whether a fragment shader input of this type gets interpolated. Integers can't be, so they're flat.
+pub(crate) fn interpolated(ty: &YuriType) -> bool {
+	number_type(ty) == Some(NumberType::Float)
+}
+
+/// Whether sampling in this stage can pick the level of detail by itself.
+/// Implicit LOD needs derivatives, which only fragment shaders have, so everything else samples level 0.
+pub(crate) fn implicit_lod(stage: ShaderStage) -> bool {
+	stage == ShaderStage::Fragment
+}
+
+/// Compiles a single entry point into its own SPIR-V module.
+pub fn compile_entry_point(program: &TypedProgram, entry: FunctionId, options: &CompileOptions) -> Result<CompiledShader, YuriSemanticError> {
+	let target = options.target;
+	check_props(program)?;
+	let function = &program.functions[entry];
+	let (stage, interface) = entry_interface(function)?;
+	let reachable = reachable_functions(program, entry);
+	let origin = function.frag_origin.unwrap_or(target.frag_origin());
+	let flip = !target.env().supports_origin(origin) && reads_frag_coord(program, stage, &reachable);
+	let mut codegen = Codegen::new(program, stage, target, options.debug_info);
+	// the root file always goes in, even if nothing in it ends up being used
+	codegen.debug_file(0);
+	codegen.declare_props(flip);
+	for id in &reachable {
+		let word = codegen.b.id();
+		codegen.functions.insert(*id, word);
+	}
+	for id in reachable.iter().rev() {
+		codegen.function(*id)?;
+	}
+	codegen.entry_point(entry, function, &interface)?;
+	let mut specialization_constants = std::mem::take(&mut codegen.specialization_constants);
+	specialization_constants.sort_by_key(|constant| constant.spec_id);
+	Ok(CompiledShader {
+		name: function.name.clone(),
+		stage,
+		samplers: codegen.samplers.len() as u32,
+		uniform_buffers: codegen.uniforms.is_some() as u32,
+		specialization_constants,
+		viewport_height_offset: codegen.viewport_height.map(|(_, offset)| offset),
+		words: codegen.finish(options.debug_info),
+	})
+}
+
+/// The stage of the first builtin input the expression reads, if it reads any.
+fn input_stage(expr: &TypedExpression) -> Option<ShaderStage> {
+	if let TypedExpressionKind::BuiltinInput(input) = expr.kind {
+		return Some(input.stage());
+	}
+	let mut stage = None;
+	expr.for_each_child(|child| stage = stage.or_else(|| input_stage(child)));
+	stage
+}
+
+/// Compiles a single function (and whatever it calls) into a SPIR-V module without an entry point,
+/// so [crate::emulate] can run it on its own. The names are kept, since that's how it finds the function.
+pub(crate) fn compile_function(program: &TypedProgram, id: FunctionId, target: Target) -> Result<Vec<u32>, YuriSemanticError> {
+	check_props(program)?;
+	let reachable = reachable_functions(program, id);
+	// builtin inputs only exist in one stage, so that's the one it has to be
+	let stage = reachable.iter()
+		.find_map(|id| input_stage(&program.functions[*id].body))
+		.unwrap_or(ShaderStage::Fragment);
+	let mut codegen = Codegen::new(program, stage, target, false);
+	codegen.declare_props(false);
+	for id in &reachable {
+		let word = codegen.b.id();
+		codegen.functions.insert(*id, word);
+	}
+	for id in reachable.iter().rev() {
+		codegen.function(*id)?;
+	}
+	Ok(codegen.finish(true))
+}
+
+/// Folds whatever can be worked out ahead of time (unless the options say not to),
+/// and picks out the entry points the options ask for.
+pub(crate) fn prepare_program(program: &TypedProgram, options: &CompileOptions) -> Result<(TypedProgram, Vec<FunctionId>), YuriSemanticError> {
+	if let Some(names) = &options.entry_points
+		&& let Some(missing) = names.iter().find(|name| !program.entry_points().any(|(_, f)| &f.name == *name))
+	{
+		return Err(YuriSemanticError {
+			error_type: YuriSemanticErrorType::InvalidEntryPoint,
+			description: Some(format!("There's no entry point called `{missing}` to compile")),
+			markers: vec![],
+		});
+	}
+	let mut program = program.clone();
+	if options.optimization != OptimizationLevel::None {
+		fold::fold_program(&mut program);
+	}
+	let entry_points = program.entry_points()
+		.filter(|(_, function)| options.entry_points.as_ref().is_none_or(|names| names.contains(&function.name)))
+		.map(|(id, _)| id)
+		.collect();
+	Ok((program, entry_points))
+}
+
+/// Compiles every entry point in the program (or just the ones the options ask for),
+/// after folding whatever can be worked out ahead of time.
+pub fn compile_program(program: &TypedProgram, options: &CompileOptions) -> Result<Vec<CompiledShader>, YuriSemanticError> {
+	let (program, entry_points) = prepare_program(program, options)?;
+	entry_points.into_iter()
+		.map(|id| compile_entry_point(&program, id, options))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use rspirv::dr::{Loader, Module, Operand};
+	use rspirv::spirv::{Decoration, ExecutionMode, Op};
+	use crate::builtin::FragOrigin;
+	use crate::check::ShaderStage;
+	use crate::consteval::ConstValue;
+	use crate::error::{YuriCompileError, YuriSemanticErrorType};
+	use crate::options::{CompileOptions, OptimizationLevel};
+	use crate::target::{SpirvVersion, Target, TargetEnv};
+	use crate::YuriShader;
+
+	fn load(words: &[u32]) -> Module {
+		let mut loader = Loader::new();
+		rspirv::binary::parse_words(words, &mut loader).unwrap();
+		loader.module()
+	}
+
+	fn compile_for(source: &str, target: Target) -> Result<YuriShader, YuriCompileError> {
+		YuriShader::compile_with(source, &CompileOptions::new().with_target(target))
+	}
+
+	#[test]
+	fn compile_basic() {
+		let shader = YuriShader::new(include_str!("../basic.yuri")).unwrap();
+		let stages: Vec<(&str, ShaderStage)> = shader.shaders.iter()
+			.map(|s| (s.name.as_str(), s.stage))
+			.collect();
+		assert_eq!(stages, [("my_vert_main", ShaderStage::Vertex), ("my_frag_main", ShaderStage::Fragment)]);
+		for compiled in &shader.shaders {
+			let module = load(&compiled.words);
+			assert_eq!(module.entry_points.len(), 1);
+			assert_eq!(module.entry_points[0].operands[2], Operand::LiteralString("main".to_string()));
+		}
+		// both props go in the vertex shader's uniform block, even though only one is used
+		assert_eq!(shader.shaders[0].uniform_buffers, 1);
+		assert_eq!(shader.shaders[0].samplers, 0);
+	}
+
+	#[test]
+	fn globals_become_constants() {
+		let shader = YuriShader::new("
+			let scale: f = 2.5;
+			let offset = f2(scale, 1.0) * 2.0;
+			@frag
+			fn main(coord: f2): f4 { f4(coord * offset, scale, 1.0) }
+		").unwrap();
+		let module = load(&shader.shaders[0].words);
+		let float_constants: Vec<f32> = module.types_global_values.iter()
+			.filter(|inst| inst.class.opcode == Op::Constant)
+			.map(|inst| match inst.operands[0] {
+				Operand::LiteralBit32(bits) => f32::from_bits(bits),
+				_ => panic!("{inst:?}"),
+			})
+			.collect();
+		assert!(float_constants.contains(&2.5));
+		assert!(float_constants.contains(&5.0));
+		assert!(module.types_global_values.iter().any(|inst| inst.class.opcode == Op::ConstantComposite));
+		// nothing about the globals should be left for the GPU to work out
+		let multiplications = module.functions.iter()
+			.flat_map(|f| f.all_inst_iter())
+			.filter(|inst| matches!(inst.class.opcode, Op::FMul | Op::VectorTimesScalar))
+			.count();
+		assert_eq!(multiplications, 1);
+	}
+
+	#[test]
+	fn specialization_constants() {
+		let shader = YuriShader::new("
+			@spec(3) let SAMPLES: u = 8;
+			@spec(1) let FANCY: bool = true;
+			let HALF = if FANCY { SAMPLES / 2 } else { 1 };
+			@frag
+			fn main(coord: f2): f4 { f4(coord, f(HALF), 1.0) }
+		").unwrap();
+		let compiled = &shader.shaders[0];
+		let reflected: Vec<(&str, u32, ConstValue)> = compiled.specialization_constants.iter()
+			.map(|c| (c.name.as_str(), c.spec_id, c.default.clone()))
+			.collect();
+		assert_eq!(reflected, [("FANCY", 1, ConstValue::Bool(true)), ("SAMPLES", 3, ConstValue::Unsigned(8))]);
+		let module = load(&compiled.words);
+		let spec_ids: Vec<&Operand> = module.annotations.iter()
+			.filter(|inst| inst.operands[1] == Operand::Decoration(Decoration::SpecId))
+			.map(|inst| &inst.operands[2])
+			.collect();
+		assert_eq!(spec_ids, [&Operand::LiteralBit32(1), &Operand::LiteralBit32(3)]);
+		let spec_ops = module.types_global_values.iter()
+			.filter(|inst| inst.class.opcode == Op::SpecConstantOp)
+			.count();
+		assert_eq!(spec_ops, 2);
+	}
+
+	#[test]
+	fn targets() {
+		let source = "
+			prop tint: f4;
+			prop tex: sampler2;
+			@frag
+			fn main(coord: f2): f4 { sample(tex, coord) * tint }
+		";
+		let old = compile_for(source, Target::default()).unwrap();
+		let module = load(&old.shaders[0].words);
+		assert_eq!(module.header.unwrap().version(), (1, 0));
+		// execution model, function, name, and then just the input and the output
+		assert_eq!(module.entry_points[0].operands.len(), 5);
+
+		let new = compile_for(source, Target::new(TargetEnv::Vulkan1_2)).unwrap();
+		let module = load(&new.shaders[0].words);
+		assert_eq!(module.header.unwrap().version(), (1, 5));
+		// the uniform block and the sampler have to be listed too
+		assert_eq!(module.entry_points[0].operands.len(), 7);
+
+		let gl = compile_for(source, Target::with_spirv_version(TargetEnv::OpenGL4_5, SpirvVersion::new(1, 0)).unwrap()).unwrap();
+		let module = load(&gl.shaders[0].words);
+		assert!(!module.annotations.iter().any(|inst| inst.operands[1] == Operand::Decoration(Decoration::DescriptorSet)));
+		let err = compile_for("@vert fn main(): f4 { f4(f(@vert.instance)) }", Target::new(TargetEnv::OpenGL4_5));
+		assert!(matches!(err, Err(YuriCompileError::Semantic(err)) if err.error_type() == YuriSemanticErrorType::Unsupported));
+	}
+
+	#[test]
+	fn frag_origin() {
+		let source = "
+			prop tint: f3;
+			fn shade(): f2 { @frag.coord / 100.0 }
+			@frag(origin = lower_left)
+			fn flipped(): f4 { f4(shade(), tint.x, 1.0) }
+			@frag
+			fn plain(): f4 { f4(@frag.coord, 0.0, 1.0) }
+		";
+		let execution_mode = |module: &Module| match module.execution_modes[0].operands[1] {
+			Operand::ExecutionMode(mode) => mode,
+			_ => panic!(),
+		};
+		let subtractions = |module: &Module| module.functions.iter()
+			.flat_map(|f| f.all_inst_iter())
+			.filter(|inst| inst.class.opcode == Op::FSub)
+			.count();
+
+		// Vulkan can only do upper left, so lower left needs the height to flip y
+		let vulkan = compile_for(source, Target::default()).unwrap();
+		assert_eq!(vulkan.shaders[0].viewport_height_offset, Some(12));
+		assert_eq!(vulkan.shaders[1].viewport_height_offset, None);
+		let module = load(&vulkan.shaders[0].words);
+		assert_eq!(execution_mode(&module), ExecutionMode::OriginUpperLeft);
+		assert_eq!(subtractions(&module), 1);
+
+		// OpenGL can do either
+		let gl = compile_for(source, Target::new(TargetEnv::OpenGL4_5)).unwrap();
+		assert_eq!(gl.shaders[0].viewport_height_offset, None);
+		let module = load(&gl.shaders[0].words);
+		assert_eq!(execution_mode(&module), ExecutionMode::OriginLowerLeft);
+		assert_eq!(subtractions(&module), 0);
+
+		// the target's origin applies to shaders that don't pick one, even without props
+		let default_lower = Target::default().with_frag_origin(FragOrigin::LowerLeft);
+		let shader = compile_for("@frag fn main(): f4 { f4(@frag.coord, 0.0, 1.0) }", default_lower).unwrap();
+		assert_eq!(shader.shaders[0].viewport_height_offset, Some(0));
+		assert_eq!(shader.shaders[0].uniform_buffers, 1);
+	}
+
+	#[test]
+	fn compile_options() {
+		let source = "
+			let SCALE: f = 1.0;
+			@vert
+			fn vert_main(pos: f3): f4 { f4(pos * SCALE, 1.0) }
+			@frag
+			fn frag_main(coord: f2): f4 { f4(coord * (2.0 * SCALE), 0.0, 1.0) }
+		";
+		let multiplications = |shader: &YuriShader| shader.shaders.iter()
+			.flat_map(|s| load(&s.words).functions)
+			.flat_map(|f| f.all_inst_iter().cloned().collect::<Vec<_>>())
+			.filter(|inst| matches!(inst.class.opcode, Op::FMul | Op::VectorTimesScalar))
+			.count();
+
+		let only_frag = CompileOptions::new().with_entry_point("frag_main");
+		let shader = YuriShader::compile_with(source, &only_frag).unwrap();
+		assert_eq!(shader.shaders.len(), 1);
+		assert_eq!(shader.shaders[0].name, "frag_main");
+		assert!(YuriShader::compile_with(source, &CompileOptions::new().with_entry_point("nope")).is_err());
+
+		// `* 1.0` goes away, and so does `2.0 * 1.0`, unless folding is turned off
+		assert_eq!(multiplications(&YuriShader::compile_with(source, &CompileOptions::new()).unwrap()), 1);
+		let unoptimized = CompileOptions::new().with_optimization(OptimizationLevel::None);
+		assert_eq!(multiplications(&YuriShader::compile_with(source, &unoptimized).unwrap()), 3);
+
+		// the define changes the constant, so the multiplication stays
+		let defined = CompileOptions::new().with_define("SCALE", "0.25");
+		assert_eq!(multiplications(&YuriShader::compile_with(source, &defined).unwrap()), 2);
+
+		let names = |options: &CompileOptions| load(&YuriShader::compile_with(source, options).unwrap().shaders[0].words)
+			.debug_names
+			.len();
+		assert_eq!(names(&CompileOptions::new()), 0);
+		assert_ne!(names(&CompileOptions::new().with_debug_info(true)), 0);
+	}
+
+	#[test]
+	fn debug_info() {
+		let source = "let SCALE: f = 2.0;\n\
+			fn double(x: f): f { x * SCALE }\n\
+			@frag\n\
+			fn main(coord: f2): <| color: f4 |> {\n\
+				let total = fold sum = 0.0, k: 3 { sum + double(coord.x) };\n\
+				<| color = f4(total) |>\n\
+			}";
+		let options = CompileOptions::new().with_debug_info(true).with_source_name("shaders/test.yuri");
+		let module = load(&YuriShader::compile_with(source, &options).unwrap().shaders[0].words);
+		let strings = |op: Op, insts: &[rspirv::dr::Instruction]| insts.iter()
+			.filter(|inst| inst.class.opcode == op)
+			.flat_map(|inst| inst.operands.iter())
+			.filter_map(|operand| match operand {
+				Operand::LiteralString(s) => Some(s.clone()),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+
+		assert_eq!(strings(Op::String, &module.debug_string_source), ["shaders/test.yuri"]);
+		assert!(strings(Op::Source, &module.debug_string_source).concat().contains("fn double"));
+		let names = strings(Op::Name, &module.debug_names);
+		for name in ["double", "x", "total", "sum", "k"] {
+			assert!(names.iter().any(|n| n == name), "{name} isn't named in {names:?}");
+		}
+		assert_eq!(strings(Op::MemberName, &module.debug_names), ["color"]);
+
+		let instructions: Vec<_> = module.functions.iter()
+			.flat_map(|f| f.blocks.iter())
+			.flat_map(|b| b.instructions.iter())
+			.collect();
+		let lines: Vec<_> = instructions.iter()
+			.filter(|inst| inst.class.opcode == Op::Line)
+			.map(|inst| (inst.operands[1].unwrap_literal_bit32(), inst.operands[2].unwrap_literal_bit32()))
+			.collect();
+		// the multiplication in `double`, and the addition in the fold
+		assert!(lines.contains(&(2, 22)), "{lines:?}");
+		assert!(lines.contains(&(5, 36)), "{lines:?}");
+		// lines that didn't cover anything get replaced
+		assert!(instructions.windows(2).all(|pair| pair[0].class.opcode != Op::Line || pair[1].class.opcode != Op::Line));
+	}
+}
@@ -0,0 +1,884 @@
+//! Compile-time evaluation of typed expressions.
+//! Yuri doesn't have mutation, so anything that doesn't touch a prop or a builtin input
+//! can be worked out ahead of time. Everything is done with the same 32-bit semantics the GPU uses.
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::rc::Rc;
+use crate::builtin::{BuiltinFunction, BuiltinInput};
+use crate::check::{FunctionId, GlobalId, PropId, TypedExpression, TypedExpressionKind, TypedFunction};
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::parse::{ArrayLength, BinaryOperator, NumberType, UnaryOperator, YuriType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+	Bool(bool),
+	Float(f32),
+	Signed(i32),
+	Unsigned(u32),
+	/// Vectors, matrices (as a list of columns), arrays and complex values.
+	/// The unit value is an empty composite.
+	Composite(Vec<ConstValue>),
+}
+
+impl ConstValue {
+	pub const UNIT: ConstValue = ConstValue::Composite(Vec::new());
+
+	pub fn as_f32(&self) -> Option<f32> {
+		if let ConstValue::Float(f) = self { Some(*f) } else { None }
+	}
+
+	pub fn as_bool(&self) -> Option<bool> {
+		if let ConstValue::Bool(b) = self { Some(*b) } else { None }
+	}
+
+	/// Interprets an integer value as an index or count. Negative numbers aren't one.
+	pub fn as_index(&self) -> Option<usize> {
+		match self {
+			ConstValue::Signed(i) => usize::try_from(*i).ok(),
+			ConstValue::Unsigned(u) => Some(*u as usize),
+			_ => None,
+		}
+	}
+
+	pub fn components(&self) -> &[ConstValue] {
+		match self {
+			ConstValue::Composite(components) => components,
+			scalar => std::slice::from_ref(scalar),
+		}
+	}
+
+	/// The zero value of a number type.
+	pub fn zero(number_type: NumberType) -> Self {
+		match number_type {
+			NumberType::Float => ConstValue::Float(0.0),
+			NumberType::Signed => ConstValue::Signed(0),
+			NumberType::Unsigned => ConstValue::Unsigned(0),
+		}
+	}
+
+	/// Converts a scalar to another scalar type, the same way a constructor like `f(x)` does.
+	pub fn convert(&self, target: &YuriType) -> Option<ConstValue> {
+		Some(match (self, target) {
+			(ConstValue::Composite(components), _) => {
+				let target = match target {
+					YuriType::Vector(number_type, _) => YuriType::Scalar(*number_type),
+					_ => return None,
+				};
+				ConstValue::Composite(components.iter()
+					.map(|c| c.convert(&target))
+					.collect::<Option<Vec<_>>>()?)
+			}
+			(value, YuriType::Bool) => ConstValue::Bool(match value {
+				ConstValue::Bool(b) => *b,
+				ConstValue::Float(f) => *f != 0.0,
+				ConstValue::Signed(i) => *i != 0,
+				ConstValue::Unsigned(u) => *u != 0,
+				ConstValue::Composite(_) => unreachable!(),
+			}),
+			(value, YuriType::Scalar(NumberType::Float)) => ConstValue::Float(match value {
+				ConstValue::Bool(b) => if *b { 1.0 } else { 0.0 },
+				ConstValue::Float(f) => *f,
+				ConstValue::Signed(i) => *i as f32,
+				ConstValue::Unsigned(u) => *u as f32,
+				ConstValue::Composite(_) => unreachable!(),
+			}),
+			(value, YuriType::Scalar(NumberType::Signed)) => ConstValue::Signed(match value {
+				ConstValue::Bool(b) => *b as i32,
+				ConstValue::Float(f) => *f as i32,
+				ConstValue::Signed(i) => *i,
+				ConstValue::Unsigned(u) => *u as i32,
+				ConstValue::Composite(_) => unreachable!(),
+			}),
+			(value, YuriType::Scalar(NumberType::Unsigned)) => ConstValue::Unsigned(match value {
+				ConstValue::Bool(b) => *b as u32,
+				ConstValue::Float(f) => *f as u32,
+				ConstValue::Signed(i) => *i as u32,
+				ConstValue::Unsigned(u) => *u,
+				ConstValue::Composite(_) => unreachable!(),
+			}),
+			_ => return None,
+		})
+	}
+	/// Whether the value could be of the type, going by its shape. Samplers don't have values.
+	pub fn has_type(&self, ty: &YuriType) -> bool {
+		let all = |values: &[ConstValue], ty: &YuriType| values.iter().all(|value| value.has_type(ty));
+		match (self, ty) {
+			(ConstValue::Bool(_), YuriType::Bool) => true,
+			(ConstValue::Float(_), YuriType::Scalar(NumberType::Float)) => true,
+			(ConstValue::Signed(_), YuriType::Scalar(NumberType::Signed)) => true,
+			(ConstValue::Unsigned(_), YuriType::Scalar(NumberType::Unsigned)) => true,
+			(ConstValue::Composite(c), YuriType::Unit) => c.is_empty(),
+			(ConstValue::Composite(c), YuriType::Vector(number_type, size)) => {
+				c.len() == size.count() as usize && all(c, &YuriType::Scalar(*number_type))
+			}
+			(ConstValue::Composite(c), YuriType::Matrix(size)) => {
+				c.len() == size.count() as usize && all(c, &YuriType::Vector(NumberType::Float, *size))
+			}
+			(ConstValue::Composite(c), YuriType::Array(inner, length)) => {
+				let length_matches = match length {
+					ArrayLength::Fixed(length) => c.len() == *length,
+					ArrayLength::Named(_) => true,
+				};
+				length_matches && all(c, inner)
+			}
+			(ConstValue::Composite(c), YuriType::Complex(fields)) => {
+				c.len() == fields.len() && c.iter().zip(fields).all(|(value, field)| value.has_type(&field.field_type))
+			}
+			_ => false,
+		}
+	}
+}
+
+impl From<bool> for ConstValue {
+	fn from(value: bool) -> Self {
+		ConstValue::Bool(value)
+	}
+}
+
+impl From<f32> for ConstValue {
+	fn from(value: f32) -> Self {
+		ConstValue::Float(value)
+	}
+}
+
+impl From<i32> for ConstValue {
+	fn from(value: i32) -> Self {
+		ConstValue::Signed(value)
+	}
+}
+
+impl From<u32> for ConstValue {
+	fn from(value: u32) -> Self {
+		ConstValue::Unsigned(value)
+	}
+}
+
+/// Vectors and arrays, like `[1.0, 2.0, 3.0]` for an `f3`.
+impl<T: Into<ConstValue>, const N: usize> From<[T; N]> for ConstValue {
+	fn from(value: [T; N]) -> Self {
+		ConstValue::Composite(value.into_iter().map(Into::into).collect())
+	}
+}
+
+/// Composites are written like arrays, since the value doesn't know if it's a vector or something else.
+impl Display for ConstValue {
+	fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+		match self {
+			ConstValue::Bool(b) => write!(f, "{b}"),
+			ConstValue::Float(x) => write!(f, "{x:?}"),
+			ConstValue::Signed(i) => write!(f, "{i}"),
+			ConstValue::Unsigned(u) => write!(f, "{u}"),
+			ConstValue::Composite(c) => {
+				write!(f, "[")?;
+				for (i, component) in c.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					write!(f, "{component}")?;
+				}
+				write!(f, "]")
+			}
+		}
+	}
+}
+
+
+/// Provides the evaluator with everything that lives outside the expression itself.
+pub trait ConstContext {
+	fn global(&mut self, id: GlobalId, location: &Range<usize>) -> Result<ConstValue, YuriSemanticError>;
+	fn function(&mut self, id: FunctionId, location: &Range<usize>) -> Result<Rc<TypedFunction>, YuriSemanticError>;
+	fn property_name(&self, id: PropId) -> String;
+	/// The name of the specialization constant the global depends on, if it does.
+	fn specialization(&mut self, id: GlobalId, location: &Range<usize>) -> Result<Option<String>, YuriSemanticError>;
+	/// The value of a prop. It's never known at compile time, only when something's running the shader.
+	fn property(&mut self, _id: PropId) -> Option<ConstValue> {
+		None
+	}
+	/// Same as [ConstContext::property], for builtin inputs.
+	fn builtin_input(&mut self, _input: BuiltinInput) -> Option<ConstValue> {
+		None
+	}
+}
+
+/// How deep function calls can go before we give up. Recursion is rejected anyway,
+/// this is just so a bug in that check can't blow the stack.
+const MAX_CALL_DEPTH: usize = 256;
+
+pub struct ConstEvaluator<'a> {
+	context: &'a mut dyn ConstContext,
+	/// A description of what's being evaluated, for error messages. Something like "the global `x`".
+	what: String,
+	locals: Vec<Option<ConstValue>>,
+	depth: usize,
+	/// Whether specialization constants can be used, in which case their defaults are used.
+	specialization: bool,
+	/// Whether the code is being run rather than folded, which only changes what the errors say.
+	runtime: bool,
+}
+
+fn scalar_binary(operator: BinaryOperator, lhs: &ConstValue, rhs: &ConstValue) -> Result<ConstValue, String> {
+	use BinaryOperator::*;
+	use ConstValue::*;
+	let division_by_zero = || "division by zero".to_string();
+	Ok(match (lhs, rhs) {
+		// shifts can mix signedness, the shift amount is just a number of bits
+		(Signed(_) | Unsigned(_), Signed(_) | Unsigned(_)) if matches!(operator, ShiftLeft | ShiftRight) => {
+			let amount = match rhs {
+				Signed(b) => *b as u32,
+				Unsigned(b) => *b,
+				_ => unreachable!(),
+			};
+			match (lhs, operator) {
+				(Signed(a), ShiftLeft) => Signed(a.wrapping_shl(amount)),
+				(Signed(a), ShiftRight) => Signed(a.wrapping_shr(amount)),
+				(Unsigned(a), ShiftLeft) => Unsigned(a.wrapping_shl(amount)),
+				(Unsigned(a), ShiftRight) => Unsigned(a.wrapping_shr(amount)),
+				_ => unreachable!(),
+			}
+		}
+		(Float(a), Float(b)) => match operator {
+			Plus => Float(a + b),
+			Minus => Float(a - b),
+			Times => Float(a * b),
+			Divided => Float(a / b),
+			Modulo => Float(a % b),
+			Exponent => Float(a.powf(*b)),
+			Equal => Bool(a == b),
+			NotEqual => Bool(a != b),
+			Less => Bool(a < b),
+			LessEqual => Bool(a <= b),
+			Greater => Bool(a > b),
+			GreaterEqual => Bool(a >= b),
+			_ => return Err(format!("`{operator}` can't be used on floats")),
+		},
+		(Signed(a), Signed(b)) => match operator {
+			Plus => Signed(a.wrapping_add(*b)),
+			Minus => Signed(a.wrapping_sub(*b)),
+			Times => Signed(a.wrapping_mul(*b)),
+			Divided => Signed(a.checked_div(*b).or_else(|| (*b != 0).then(|| a.wrapping_div(*b))).ok_or_else(division_by_zero)?),
+			Modulo => Signed(a.checked_rem(*b).or_else(|| (*b != 0).then(|| a.wrapping_rem(*b))).ok_or_else(division_by_zero)?),
+			BitAnd => Signed(a & b),
+			BitOr => Signed(a | b),
+			BitXor => Signed(a ^ b),
+			Equal => Bool(a == b),
+			NotEqual => Bool(a != b),
+			Less => Bool(a < b),
+			LessEqual => Bool(a <= b),
+			Greater => Bool(a > b),
+			GreaterEqual => Bool(a >= b),
+			_ => return Err(format!("`{operator}` can't be used on signed integers")),
+		},
+		(Unsigned(a), Unsigned(b)) => match operator {
+			Plus => Unsigned(a.wrapping_add(*b)),
+			Minus => Unsigned(a.wrapping_sub(*b)),
+			Times => Unsigned(a.wrapping_mul(*b)),
+			Divided => Unsigned(a.checked_div(*b).ok_or_else(division_by_zero)?),
+			Modulo => Unsigned(a.checked_rem(*b).ok_or_else(division_by_zero)?),
+			BitAnd => Unsigned(a & b),
+			BitOr => Unsigned(a | b),
+			BitXor => Unsigned(a ^ b),
+			Equal => Bool(a == b),
+			NotEqual => Bool(a != b),
+			Less => Bool(a < b),
+			LessEqual => Bool(a <= b),
+			Greater => Bool(a > b),
+			GreaterEqual => Bool(a >= b),
+			_ => return Err(format!("`{operator}` can't be used on unsigned integers")),
+		},
+		(Bool(a), Bool(b)) => match operator {
+			And => Bool(*a && *b),
+			Or => Bool(*a || *b),
+			Xor | NotEqual => Bool(a != b),
+			Nor => Bool(!(*a || *b)),
+			Equal => Bool(a == b),
+			_ => return Err(format!("`{operator}` can't be used on booleans")),
+		},
+		_ => return Err(format!("`{operator}` can't be used on {lhs:?} and {rhs:?}")),
+	})
+}
+
+fn componentwise(a: &ConstValue, b: &ConstValue, f: &dyn Fn(&ConstValue, &ConstValue) -> Result<ConstValue, String>) -> Result<ConstValue, String> {
+	match (a, b) {
+		(ConstValue::Composite(a), ConstValue::Composite(b)) => Ok(ConstValue::Composite(
+			a.iter().zip(b).map(|(a, b)| componentwise(a, b, f)).collect::<Result<_, _>>()?
+		)),
+		(ConstValue::Composite(a), b) => Ok(ConstValue::Composite(
+			a.iter().map(|a| componentwise(a, b, f)).collect::<Result<_, _>>()?
+		)),
+		(a, ConstValue::Composite(b)) => Ok(ConstValue::Composite(
+			b.iter().map(|b| componentwise(a, b, f)).collect::<Result<_, _>>()?
+		)),
+		(a, b) => f(a, b),
+	}
+}
+
+fn dot(a: &[ConstValue], b: &[ConstValue]) -> f32 {
+	a.iter().zip(b)
+		.map(|(a, b)| a.as_f32().unwrap_or(0.0) * b.as_f32().unwrap_or(0.0))
+		.sum()
+}
+
+fn floats(values: &[f32]) -> ConstValue {
+	ConstValue::Composite(values.iter().map(|f| ConstValue::Float(*f)).collect())
+}
+
+fn to_floats(value: &ConstValue) -> Vec<f32> {
+	value.components().iter().map(|c| c.as_f32().unwrap_or(0.0)).collect()
+}
+
+/// Every float in the value, with matrices flattened out.
+fn all_floats(value: &ConstValue) -> Vec<f32> {
+	match value {
+		ConstValue::Composite(c) => c.iter().flat_map(all_floats).collect(),
+		_ => value.as_f32().into_iter().collect(),
+	}
+}
+
+/// Checks an assertion, returning what went wrong if it doesn't hold.
+fn assertion(function: BuiltinFunction, arguments: &[ConstValue]) -> Option<String> {
+	match (function, arguments) {
+		(BuiltinFunction::Assert, [ConstValue::Bool(true)]) => None,
+		(BuiltinFunction::Assert, _) => Some("it's false".to_string()),
+		(BuiltinFunction::AssertEq, [a, b]) if a == b => None,
+		(BuiltinFunction::AssertEq, [a, b]) => Some(format!("the left side is {a}, but the right side is {b}")),
+		(BuiltinFunction::AssertNear, [a, b, ConstValue::Float(tolerance)]) => {
+			// comparisons with NaN are always false, so NaN is never near anything
+			let near = all_floats(a).iter().zip(all_floats(b)).all(|(a, b)| (a - b).abs() <= *tolerance);
+			(!near).then(|| format!("the left side is {a}, which isn't within {tolerance:?} of the right side {b}"))
+		}
+		_ => Some("the arguments don't make sense".to_string()),
+	}
+}
+
+/// Matrices are stored as a list of columns.
+fn to_matrix(value: &ConstValue) -> Vec<Vec<f32>> {
+	value.components().iter().map(to_floats).collect()
+}
+
+fn from_matrix(columns: &[Vec<f32>]) -> ConstValue {
+	ConstValue::Composite(columns.iter().map(|c| floats(c)).collect())
+}
+
+fn matrix_times_vector(m: &[Vec<f32>], v: &[f32]) -> Vec<f32> {
+	(0..m[0].len())
+		.map(|row| m.iter().zip(v).map(|(column, v)| column[row] * v).sum())
+		.collect()
+}
+
+fn vector_times_matrix(v: &[f32], m: &[Vec<f32>]) -> Vec<f32> {
+	m.iter()
+		.map(|column| column.iter().zip(v).map(|(c, v)| c * v).sum())
+		.collect()
+}
+
+pub fn binary(
+	operator: BinaryOperator,
+	lhs: &ConstValue,
+	lhs_type: &YuriType,
+	rhs: &ConstValue,
+	rhs_type: &YuriType,
+) -> Result<ConstValue, String> {
+	match (lhs_type, rhs_type, operator) {
+		(YuriType::Matrix(_), YuriType::Vector(..), BinaryOperator::Times) => {
+			Ok(floats(&matrix_times_vector(&to_matrix(lhs), &to_floats(rhs))))
+		}
+		(YuriType::Vector(..), YuriType::Matrix(_), BinaryOperator::Times) => {
+			Ok(floats(&vector_times_matrix(&to_floats(lhs), &to_matrix(rhs))))
+		}
+		(YuriType::Matrix(_), YuriType::Matrix(_), BinaryOperator::Times) => {
+			let a = to_matrix(lhs);
+			let columns: Vec<Vec<f32>> = to_matrix(rhs).iter()
+				.map(|column| matrix_times_vector(&a, column))
+				.collect();
+			Ok(from_matrix(&columns))
+		}
+		_ => componentwise(lhs, rhs, &|a, b| scalar_binary(operator, a, b)),
+	}
+}
+
+pub fn unary(operator: UnaryOperator, operand: &ConstValue) -> Result<ConstValue, String> {
+	Ok(match operand {
+		ConstValue::Composite(components) => ConstValue::Composite(
+			components.iter().map(|c| unary(operator, c)).collect::<Result<_, _>>()?
+		),
+		ConstValue::Float(f) if operator == UnaryOperator::Negate => ConstValue::Float(-f),
+		ConstValue::Signed(i) if operator == UnaryOperator::Negate => ConstValue::Signed(i.wrapping_neg()),
+		ConstValue::Unsigned(u) if operator == UnaryOperator::Negate => ConstValue::Unsigned(u.wrapping_neg()),
+		ConstValue::Bool(b) if operator == UnaryOperator::Not => ConstValue::Bool(!b),
+		ConstValue::Signed(i) if operator == UnaryOperator::Not => ConstValue::Signed(!i),
+		ConstValue::Unsigned(u) if operator == UnaryOperator::Not => ConstValue::Unsigned(!u),
+		_ => return Err(format!("`{operator}` can't be used on {operand:?}")),
+	})
+}
+
+/// The matrix without one of its columns and one of its rows.
+fn minor(m: &[Vec<f32>], column: usize, row: usize) -> Vec<Vec<f32>> {
+	m.iter().enumerate()
+		.filter(|(c, _)| *c != column)
+		.map(|(_, c)| c.iter().enumerate().filter(|(r, _)| *r != row).map(|(_, v)| *v).collect())
+		.collect()
+}
+
+fn determinant(m: &[Vec<f32>]) -> f32 {
+	match m.len() {
+		1 => m[0][0],
+		2 => m[0][0] * m[1][1] - m[1][0] * m[0][1],
+		n => (0..n).map(|column| {
+			let sign = if column % 2 == 0 { 1.0 } else { -1.0 };
+			sign * m[column][0] * determinant(&minor(m, column, 0))
+		}).sum(),
+	}
+}
+
+/// Inverts a matrix by dividing its adjugate by its determinant, which is how drivers tend to do it.
+/// Singular matrices give infinities and NaNs, which is as good as anything the GPU would come up with.
+fn inverse(m: &[Vec<f32>]) -> Vec<Vec<f32>> {
+	let det = determinant(m);
+	(0..m.len())
+		.map(|column| (0..m.len()).map(|row| {
+			// the adjugate is the transposed cofactors, so the row and column swap
+			let sign = if (row + column) % 2 == 0 { 1.0 } else { -1.0 };
+			sign * determinant(&minor(m, row, column)) / det
+		}).collect())
+		.collect()
+}
+
+/// Evaluates a builtin function on constant arguments, following the GLSL.std.450 definitions.
+/// Returns `None` for builtins that can't be evaluated ahead of time (like `sample`).
+pub fn builtin(function: BuiltinFunction, arguments: &[ConstValue]) -> Option<ConstValue> {
+	use BuiltinFunction::*;
+	fn map1(a: &ConstValue, f: &dyn Fn(&ConstValue) -> ConstValue) -> ConstValue {
+		match a {
+			ConstValue::Composite(c) => ConstValue::Composite(c.iter().map(|c| map1(c, f)).collect()),
+			scalar => f(scalar),
+		}
+	}
+	fn float1(a: &ConstValue, f: fn(f32) -> f32) -> ConstValue {
+		map1(a, &|c| ConstValue::Float(f(c.as_f32().unwrap_or(0.0))))
+	}
+	fn float2(a: &ConstValue, b: &ConstValue, f: fn(f32, f32) -> f32) -> Option<ConstValue> {
+		componentwise(a, b, &|a, b| Ok(ConstValue::Float(f(a.as_f32().unwrap_or(0.0), b.as_f32().unwrap_or(0.0))))).ok()
+	}
+	fn float3(a: &ConstValue, b: &ConstValue, c: &ConstValue, f: fn(f32, f32, f32) -> f32) -> ConstValue {
+		let (a, b, c) = (to_floats(a), to_floats(b), to_floats(c));
+		let n = a.len().max(b.len()).max(c.len());
+		let at = |v: &Vec<f32>, i: usize| if v.len() == 1 { v[0] } else { v[i] };
+		let result: Vec<f32> = (0..n).map(|i| f(at(&a, i), at(&b, i), at(&c, i))).collect();
+		if n == 1 { ConstValue::Float(result[0]) } else { floats(&result) }
+	}
+	fn ordering(a: &ConstValue, b: &ConstValue, pick_first: fn(&ConstValue, &ConstValue) -> bool) -> Option<ConstValue> {
+		componentwise(a, b, &|a, b| Ok(if pick_first(a, b) { a.clone() } else { b.clone() })).ok()
+	}
+	fn less(a: &ConstValue, b: &ConstValue) -> bool {
+		match (a, b) {
+			(ConstValue::Float(a), ConstValue::Float(b)) => a < b,
+			(ConstValue::Signed(a), ConstValue::Signed(b)) => a < b,
+			(ConstValue::Unsigned(a), ConstValue::Unsigned(b)) => a < b,
+			_ => false,
+		}
+	}
+
+	Some(match (function, arguments) {
+		(Sin, [a]) => float1(a, f32::sin),
+		(Cos, [a]) => float1(a, f32::cos),
+		(Tan, [a]) => float1(a, f32::tan),
+		(Asin, [a]) => float1(a, f32::asin),
+		(Acos, [a]) => float1(a, f32::acos),
+		(Atan, [a]) => float1(a, f32::atan),
+		(Atan2, [y, x]) => float2(y, x, f32::atan2)?,
+		(Pow, [a, b]) => float2(a, b, f32::powf)?,
+		(Exp, [a]) => float1(a, f32::exp),
+		(Exp2, [a]) => float1(a, f32::exp2),
+		(Log, [a]) => float1(a, f32::ln),
+		(Log2, [a]) => float1(a, f32::log2),
+		(Sqrt, [a]) => float1(a, f32::sqrt),
+		(InverseSqrt, [a]) => float1(a, |x| 1.0 / x.sqrt()),
+		(Abs, [a]) => map1(a, &|c| match c {
+			ConstValue::Float(f) => ConstValue::Float(f.abs()),
+			ConstValue::Signed(i) => ConstValue::Signed(i.wrapping_abs()),
+			other => other.clone(),
+		}),
+		(Sign, [a]) => map1(a, &|c| match c {
+			ConstValue::Float(f) => ConstValue::Float(if *f > 0.0 { 1.0 } else if *f < 0.0 { -1.0 } else { 0.0 }),
+			ConstValue::Signed(i) => ConstValue::Signed(i.signum()),
+			other => other.clone(),
+		}),
+		(Floor, [a]) => float1(a, f32::floor),
+		(Ceil, [a]) => float1(a, f32::ceil),
+		(Fract, [a]) => float1(a, |x| x - x.floor()),
+		// RoundEven is what the GPU does for the halfway cases... sometimes. GLSL lets it pick.
+		(Round, [a]) => float1(a, f32::round_ties_even),
+		(Trunc, [a]) => float1(a, f32::trunc),
+		(Min, [a, b]) => ordering(a, b, less)?,
+		(Max, [a, b]) => ordering(a, b, |a, b| less(b, a))?,
+		(Clamp, [x, low, high]) => {
+			let raised = ordering(x, low, |a, b| less(b, a))?;
+			ordering(&raised, high, less)?
+		}
+		(Mix, [a, b, t]) => float3(a, b, t, |a, b, t| a * (1.0 - t) + b * t),
+		(Step, [edge, x]) => float2(edge, x, |edge, x| if x < edge { 0.0 } else { 1.0 })?,
+		(SmoothStep, [low, high, x]) => float3(low, high, x, |low, high, x| {
+			let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+			t * t * (3.0 - 2.0 * t)
+		}),
+		(Length, [a]) => ConstValue::Float(dot(a.components(), a.components()).sqrt()),
+		(Distance, [a, b]) => {
+			let difference = binary(BinaryOperator::Minus, a, &YuriType::Unit, b, &YuriType::Unit).ok()?;
+			ConstValue::Float(dot(difference.components(), difference.components()).sqrt())
+		}
+		(Dot, [a, b]) => ConstValue::Float(dot(a.components(), b.components())),
+		(Cross, [a, b]) => {
+			let (a, b) = (to_floats(a), to_floats(b));
+			floats(&[
+				a[1] * b[2] - b[1] * a[2],
+				a[2] * b[0] - b[2] * a[0],
+				a[0] * b[1] - b[0] * a[1],
+			])
+		}
+		(Normalize, [a]) => {
+			let length = dot(a.components(), a.components()).sqrt();
+			map1(a, &|c| ConstValue::Float(c.as_f32().unwrap_or(0.0) / length))
+		}
+		(Reflect, [i, n]) => {
+			let d = 2.0 * dot(n.components(), i.components());
+			let (i, n) = (to_floats(i), to_floats(n));
+			floats(&i.iter().zip(&n).map(|(i, n)| i - d * n).collect::<Vec<_>>())
+		}
+		(Transpose, [m]) => {
+			let m = to_matrix(m);
+			let columns: Vec<Vec<f32>> = (0..m.len()).map(|r| m.iter().map(|c| c[r]).collect()).collect();
+			from_matrix(&columns)
+		}
+		(Determinant, [m]) => ConstValue::Float(determinant(&to_matrix(m))),
+		(Inverse, [m]) => from_matrix(&inverse(&to_matrix(m))),
+		_ => return None,
+	})
+}
+
+impl<'a> ConstEvaluator<'a> {
+	pub fn new(context: &'a mut dyn ConstContext, what: impl Into<String>, local_count: usize) -> Self {
+		Self {
+			context,
+			what: what.into(),
+			locals: vec![None; local_count],
+			depth: 0,
+			specialization: false,
+			runtime: false,
+		}
+	}
+
+	/// Lets specialization constants be used, going with their default values.
+	/// Only makes sense for things that can be changed along with them, like other globals.
+	pub fn allow_specialization(mut self) -> Self {
+		self.specialization = true;
+		self
+	}
+
+	/// For running code instead of folding it (see [Interpreter](crate::interpret::Interpreter)),
+	/// where anything that goes wrong is a [YuriSemanticErrorType::Runtime] error.
+	pub fn at_runtime(mut self) -> Self {
+		self.runtime = true;
+		self
+	}
+
+	fn not_constant(&self, location: &Range<usize>, reason: &str) -> YuriSemanticError {
+		let (error_type, description) = match self.runtime {
+			true => (YuriSemanticErrorType::Runtime, format!("Can't run {} on the CPU, since {reason} (at %)", self.what)),
+			false => (YuriSemanticErrorType::NotConstant, format!("{} has to be known at compile time, but {reason} (at %)", self.what)),
+		};
+		YuriSemanticError { error_type, description: Some(description), markers: vec![location.clone()] }
+	}
+
+	fn set_local(&mut self, id: usize, value: ConstValue) {
+		if self.locals.len() <= id {
+			self.locals.resize(id + 1, None);
+		}
+		self.locals[id] = Some(value);
+	}
+
+	pub fn evaluate(&mut self, expr: &TypedExpression) -> Result<ConstValue, YuriSemanticError> {
+		let runtime = self.runtime;
+		let failed = |reason: String| YuriSemanticError {
+			error_type: if runtime { YuriSemanticErrorType::Runtime } else { YuriSemanticErrorType::NotConstant },
+			description: Some(match runtime {
+				true => format!("Couldn't run %: {reason}"),
+				false => format!("Couldn't evaluate % at compile time: {reason}"),
+			}),
+			markers: vec![expr.location.clone()],
+		};
+		Ok(match &expr.kind {
+			TypedExpressionKind::Constant(value) => value.clone(),
+			TypedExpressionKind::Local(id) => self.locals.get(*id)
+				.cloned()
+				.flatten()
+				.ok_or_else(|| failed("the variable isn't bound".to_string()))?,
+			TypedExpressionKind::Global(id) => {
+				if !self.specialization
+					&& let Some(name) = self.context.specialization(*id, &expr.location)?
+				{
+					return Err(self.not_constant(
+						&expr.location,
+						&format!("it depends on the specialization constant `{name}`, which can be changed after compiling"),
+					));
+				}
+				self.context.global(*id, &expr.location)?
+			}
+			TypedExpressionKind::Property(id) => match self.context.property(*id) {
+				Some(value) => value,
+				None => {
+					let name = self.context.property_name(*id);
+					let reason = match self.runtime {
+						true => format!("the prop `{name}` wasn't given a value"),
+						false => format!("it depends on the prop `{name}`, which isn't known until the shader runs"),
+					};
+					return Err(self.not_constant(&expr.location, &reason));
+				}
+			},
+			TypedExpressionKind::BuiltinInput(input) => match self.context.builtin_input(*input) {
+				Some(value) => value,
+				None => {
+					let reason = match self.runtime {
+						true => format!("the builtin input `@{}` wasn't given a value", input.name()),
+						false => format!("it depends on the builtin input `@{}`", input.name()),
+					};
+					return Err(self.not_constant(&expr.location, &reason));
+				}
+			},
+			TypedExpressionKind::Call { function, arguments } => {
+				let arguments = arguments.iter()
+					.map(|a| self.evaluate(a))
+					.collect::<Result<Vec<_>, _>>()?;
+				if self.depth >= MAX_CALL_DEPTH {
+					return Err(failed("function calls are nested too deeply".to_string()));
+				}
+				let function = self.context.function(*function, &expr.location)?;
+				self.call(&function, arguments)?
+			}
+			TypedExpressionKind::Builtin { function, arguments } if function.is_assertion() => {
+				let arguments = arguments.iter()
+					.map(|a| self.evaluate(a))
+					.collect::<Result<Vec<_>, _>>()?;
+				if let Some(reason) = assertion(*function, &arguments) {
+					return Err(YuriSemanticError {
+						error_type: YuriSemanticErrorType::AssertionFailed,
+						description: Some(format!("The assertion % failed: {reason}")),
+						markers: vec![expr.location.clone()],
+					});
+				}
+				ConstValue::UNIT
+			}
+			TypedExpressionKind::Builtin { function, arguments } => {
+				let arguments = arguments.iter()
+					.map(|a| self.evaluate(a))
+					.collect::<Result<Vec<_>, _>>()?;
+				builtin(*function, &arguments).ok_or_else(|| self.not_constant(
+					&expr.location,
+					&format!("`{}` can only run on the GPU", function.name())
+				))?
+			}
+			TypedExpressionKind::Construct(arguments) => {
+				let arguments = arguments.iter()
+					.map(|a| self.evaluate(a))
+					.collect::<Result<Vec<_>, _>>()?;
+				match &expr.expression_type {
+					// vectors are built out of all the components of their arguments
+					YuriType::Vector(..) => ConstValue::Composite(
+						arguments.iter().flat_map(|a| a.components().to_vec()).collect()
+					),
+					_ => ConstValue::Composite(arguments),
+				}
+			}
+			TypedExpressionKind::Convert(operand) => {
+				let value = self.evaluate(operand)?;
+				value.convert(&expr.expression_type)
+					.ok_or_else(|| failed("the conversion isn't possible".to_string()))?
+			}
+			TypedExpressionKind::Splat(operand) => {
+				let value = self.evaluate(operand)?;
+				let count = match &expr.expression_type {
+					YuriType::Vector(_, size) => size.count() as usize,
+					_ => 1,
+				};
+				ConstValue::Composite(vec![value; count])
+			}
+			TypedExpressionKind::Complex(fields) => ConstValue::Composite(
+				fields.iter().map(|f| self.evaluate(f)).collect::<Result<_, _>>()?
+			),
+			TypedExpressionKind::Array(elements) => ConstValue::Composite(
+				elements.iter().map(|e| self.evaluate(e)).collect::<Result<_, _>>()?
+			),
+			TypedExpressionKind::Swizzle { target, components } => {
+				let value = self.evaluate(target)?;
+				let picked: Vec<ConstValue> = components.iter()
+					.map(|c| value.components()[*c as usize].clone())
+					.collect();
+				if picked.len() == 1 {
+					picked.into_iter().next().unwrap()
+				} else {
+					ConstValue::Composite(picked)
+				}
+			}
+			TypedExpressionKind::Field { target, index } => {
+				self.evaluate(target)?.components()[*index as usize].clone()
+			}
+			TypedExpressionKind::Index { target, index } => {
+				let value = self.evaluate(target)?;
+				let index_value = self.evaluate(index)?;
+				let position = index_value.as_index()
+					.filter(|i| *i < value.components().len())
+					.ok_or_else(|| failed(format!("the index {index_value:?} is out of bounds")))?;
+				value.components()[position].clone()
+			}
+			TypedExpressionKind::Unary { operator, operand } => {
+				let value = self.evaluate(operand)?;
+				unary(*operator, &value).map_err(failed)?
+			}
+			TypedExpressionKind::Binary { operator, lhs, rhs } => {
+				let a = self.evaluate(lhs)?;
+				// the logical operators short-circuit, like they do on the GPU
+				match (operator, &a) {
+					(BinaryOperator::And, ConstValue::Bool(false)) => return Ok(ConstValue::Bool(false)),
+					(BinaryOperator::Or, ConstValue::Bool(true)) => return Ok(ConstValue::Bool(true)),
+					_ => {}
+				}
+				let b = self.evaluate(rhs)?;
+				binary(*operator, &a, &lhs.expression_type, &b, &rhs.expression_type).map_err(failed)?
+			}
+			TypedExpressionKind::Block { bindings, tail } => {
+				for (id, value) in bindings {
+					let value = self.evaluate(value)?;
+					self.set_local(*id, value);
+				}
+				self.evaluate(tail)?
+			}
+			TypedExpressionKind::If { condition, block, block_else } => {
+				if self.evaluate(condition)?.as_bool().unwrap_or(false) {
+					self.evaluate(block)?
+				} else if let Some(block_else) = block_else {
+					self.evaluate(block_else)?
+				} else {
+					ConstValue::UNIT
+				}
+			}
+			TypedExpressionKind::Loop { index, count, block } => {
+				let mut elements = Vec::with_capacity(*count as usize);
+				for i in 0..*count {
+					self.set_local(*index, ConstValue::Unsigned(i));
+					elements.push(self.evaluate(block)?);
+				}
+				ConstValue::Composite(elements)
+			}
+			TypedExpressionKind::Fold { accumulator, initial, item, items, block } => {
+				let mut value = self.evaluate(initial)?;
+				for element in self.iteration_items(items)? {
+					self.set_local(*accumulator, value);
+					self.set_local(*item, element);
+					value = self.evaluate(block)?;
+				}
+				value
+			}
+			TypedExpressionKind::Map { item, items, block } => {
+				let mut elements = Vec::new();
+				for element in self.iteration_items(items)? {
+					self.set_local(*item, element);
+					elements.push(self.evaluate(block)?);
+				}
+				ConstValue::Composite(elements)
+			}
+		})
+	}
+
+	/// Runs a function with the given arguments, which have to be of the right types.
+	pub fn call(&mut self, function: &TypedFunction, arguments: Vec<ConstValue>) -> Result<ConstValue, YuriSemanticError> {
+		let frame = std::mem::replace(&mut self.locals, vec![None; function.locals.len()]);
+		for (id, value) in function.arguments.iter().zip(arguments) {
+			self.set_local(*id, value);
+		}
+		self.depth += 1;
+		let result = self.evaluate(&function.body);
+		self.depth -= 1;
+		self.locals = frame;
+		result
+	}
+
+	/// The things a fold or map goes over: either the elements of an array, or `0..n` for a number.
+	fn iteration_items(&mut self, items: &TypedExpression) -> Result<Vec<ConstValue>, YuriSemanticError> {
+		let value = self.evaluate(items)?;
+		Ok(match (&items.expression_type, &value) {
+			(YuriType::Scalar(NumberType::Signed), ConstValue::Signed(n)) => (0..*n).map(ConstValue::Signed).collect(),
+			(YuriType::Scalar(NumberType::Unsigned), ConstValue::Unsigned(n)) => (0..*n).map(ConstValue::Unsigned).collect(),
+			(_, value) => value.components().to_vec(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::builtin::BuiltinFunction::{self, *};
+	use crate::consteval::{binary, builtin, ConstValue};
+	use crate::parse::{BinaryOperator, YuriType};
+
+	fn call(function: BuiltinFunction, arguments: &[ConstValue]) -> ConstValue {
+		builtin(function, arguments).unwrap()
+	}
+
+	fn scalar(operator: BinaryOperator, lhs: impl Into<ConstValue>, rhs: impl Into<ConstValue>) -> Result<ConstValue, String> {
+		binary(operator, &lhs.into(), &YuriType::Unit, &rhs.into(), &YuriType::Unit)
+	}
+
+	#[test]
+	fn builtins() {
+		assert_eq!(call(Sign, &[[-3.0, 0.0, 0.25].into()]), ConstValue::from([-1.0, 0.0, 1.0]));
+		assert_eq!(call(Sign, &[i32::MIN.into()]), ConstValue::Signed(-1));
+		assert_eq!(call(Abs, &[i32::MIN.into()]), ConstValue::Signed(i32::MIN));
+		assert_eq!(call(Clamp, &[[-1.0, 0.5, 2.0].into(), 0.0.into(), 1.0.into()]), ConstValue::from([0.0, 0.5, 1.0]));
+		assert_eq!(call(Clamp, &[(-5i32).into(), (-2i32).into(), 3i32.into()]), ConstValue::Signed(-2));
+		// halfway cases go to the even neighbour, like RoundEven
+		assert_eq!(call(Round, &[[0.5, 1.5, 2.5, -2.5, 2.6].into()]), ConstValue::from([0.0, 2.0, 2.0, -2.0, 3.0]));
+		// `x - floor(x)`, so it's never negative
+		assert_eq!(call(Fract, &[[-1.25, 1.25].into()]), ConstValue::from([0.75, 0.25]));
+		assert_eq!(call(Trunc, &[(-1.75).into()]), ConstValue::Float(-1.0));
+		assert_eq!(call(Mix, &[[0.0, 10.0].into(), [4.0, 20.0].into(), 0.25.into()]), ConstValue::from([1.0, 12.5]));
+		assert_eq!(call(Mix, &[2.0.into(), 4.0.into(), 1.5.into()]), ConstValue::Float(5.0));
+		assert_eq!(
+			call(SmoothStep, &[0.0.into(), 2.0.into(), [-1.0, 0.5, 1.0, 3.0].into()]),
+			ConstValue::from([0.0, 0.15625, 0.5, 1.0]),
+		);
+		assert_eq!(call(Step, &[1.0.into(), [0.5, 1.0].into()]), ConstValue::from([0.0, 1.0]));
+		assert_eq!(call(Min, &[[1u32, 7].into(), 3u32.into()]), ConstValue::from([1u32, 3]));
+		assert_eq!(call(Length, &[[3.0, 4.0].into()]), ConstValue::Float(5.0));
+		assert_eq!(call(Cross, &[[1.0, 0.0, 0.0].into(), [0.0, 1.0, 0.0].into()]), ConstValue::from([0.0, 0.0, 1.0]));
+		assert_eq!(call(Reflect, &[[1.0, -1.0].into(), [0.0, 1.0].into()]), ConstValue::from([1.0, 1.0]));
+		let matrix = ConstValue::Composite(vec![[2.0, 0.0].into(), [1.0, 4.0].into()]);
+		assert_eq!(call(Determinant, std::slice::from_ref(&matrix)), ConstValue::Float(8.0));
+		assert_eq!(call(Inverse, &[matrix]), ConstValue::Composite(vec![[0.5, 0.0].into(), [-0.125, 0.25].into()]));
+		// there's nothing to work out without a texture
+		assert_eq!(builtin(Sample, &[]), None);
+	}
+
+	#[test]
+	fn integer_arithmetic() {
+		use BinaryOperator::*;
+		// signed integers wrap rather than overflowing
+		assert_eq!(scalar(Plus, i32::MAX, 1i32), Ok(ConstValue::Signed(i32::MIN)));
+		assert_eq!(scalar(Times, i32::MIN, -1i32), Ok(ConstValue::Signed(i32::MIN)));
+		assert_eq!(scalar(Divided, i32::MIN, -1i32), Ok(ConstValue::Signed(i32::MIN)));
+		assert_eq!(scalar(Modulo, i32::MIN, -1i32), Ok(ConstValue::Signed(0)));
+		assert_eq!(scalar(Minus, 0u32, 1u32), Ok(ConstValue::Unsigned(u32::MAX)));
+		// division rounds towards zero, and the remainder takes the sign of the left side (OpSRem, not OpSMod)
+		assert_eq!(scalar(Divided, -7i32, 2i32), Ok(ConstValue::Signed(-3)));
+		assert_eq!(scalar(Modulo, -7i32, 3i32), Ok(ConstValue::Signed(-1)));
+		assert_eq!(scalar(Modulo, 7i32, -3i32), Ok(ConstValue::Signed(1)));
+		assert_eq!(scalar(Modulo, -7.5, 2.0), Ok(ConstValue::Float(-1.5)));
+		assert_eq!(scalar(Divided, 1i32, 0i32), Err("division by zero".to_string()));
+		assert_eq!(scalar(Modulo, 1u32, 0u32), Err("division by zero".to_string()));
+		// the GPU doesn't define shifting by the whole width or more, this masks the amount like most hardware does
+		assert_eq!(scalar(ShiftLeft, 1u32, 31u32), Ok(ConstValue::Unsigned(1 << 31)));
+		assert_eq!(scalar(ShiftLeft, 1u32, 32u32), Ok(ConstValue::Unsigned(1)));
+		assert_eq!(scalar(ShiftLeft, 1u32, 33i32), Ok(ConstValue::Unsigned(2)));
+		// right shifts of signed integers keep the sign
+		assert_eq!(scalar(ShiftRight, -8i32, 1u32), Ok(ConstValue::Signed(-4)));
+		assert_eq!(scalar(ShiftRight, 0x8000_0000u32, 31u32), Ok(ConstValue::Unsigned(1)));
+		assert!(scalar(BitAnd, 1.0, 1.0).is_err());
+	}
+}
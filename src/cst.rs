@@ -0,0 +1,358 @@
+//! A concrete syntax tree, which keeps every byte of the source, comments and whitespace included.
+//! It's built out of the tokens from a lossless lex (see [LosslessLexer](crate::lex::LosslessLexer))
+//! and the module parsed from them, so it has the same shape as the AST with the tokens hanging off of it.
+//! Tokens that don't belong to anything smaller (like the `;` after a statement) go to the closest node around them.
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::ops::Range;
+use crate::error::YuriCompileError;
+use crate::lex::{lex_lossless, Keyword, LosslessToken, LosslessTokens, Trivia, YuriTokenType};
+use crate::parse::{parse_input, Annotation, Block, Else, Expression, ExpressionKind, Statement, YuriModule};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SyntaxKind {
+	/// The whole file.
+	Root,
+	Import,
+	Property,
+	Variable,
+	Function,
+	/// A nested `module name { ... }` block.
+	Module,
+	Annotation,
+	/// A function argument, like `pos: f3`.
+	Argument,
+	Block,
+	/// An expression followed by a `;`.
+	ExpressionStatement,
+	Return,
+	Literal,
+	Name,
+	Builtin,
+	Call,
+	Construct,
+	Complex,
+	Array,
+	Index,
+	Unary,
+	Binary,
+	If,
+	Loop,
+	Fold,
+	Map,
+	Filter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement<'a> {
+	Node(SyntaxNode<'a>),
+	Token(LosslessToken<'a>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode<'a> {
+	pub kind: SyntaxKind,
+	/// Where the node's tokens are, not counting their trivia.
+	pub location: Range<usize>,
+	pub children: Vec<SyntaxElement<'a>>,
+}
+
+impl<'a> SyntaxNode<'a> {
+	/// Every token in the node, in order.
+	pub fn tokens(&self) -> Vec<&LosslessToken<'a>> {
+		let mut tokens = Vec::new();
+		self.collect_tokens(&mut tokens);
+		tokens
+	}
+
+	fn collect_tokens<'t>(&'t self, tokens: &mut Vec<&'t LosslessToken<'a>>) {
+		for child in &self.children {
+			match child {
+				SyntaxElement::Node(node) => node.collect_tokens(tokens),
+				SyntaxElement::Token(token) => tokens.push(token),
+			}
+		}
+	}
+
+	pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode<'a>> {
+		self.children.iter().filter_map(|child| match child {
+			SyntaxElement::Node(node) => Some(node),
+			SyntaxElement::Token(_) => None,
+		})
+	}
+
+	/// The innermost node that covers an offset.
+	pub fn node_at(&self, offset: usize) -> &SyntaxNode<'a> {
+		match self.nodes().find(|node| node.location.contains(&offset)) {
+			Some(node) => node.node_at(offset),
+			None => self,
+		}
+	}
+}
+
+impl Display for SyntaxNode<'_> {
+	/// Writes the node's source back out, including the trivia around its first and last tokens.
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		for child in &self.children {
+			match child {
+				SyntaxElement::Node(node) => write!(f, "{node}")?,
+				SyntaxElement::Token(token) => write!(f, "{token}")?,
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A whole file as a [SyntaxNode] of kind [SyntaxKind::Root], and the module it was built from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree<'a> {
+	pub root: SyntaxNode<'a>,
+	/// The trivia after the last token.
+	pub end: Vec<Trivia<'a>>,
+	pub module: YuriModule,
+}
+
+impl<'a> SyntaxTree<'a> {
+	/// Lexes and parses the input, keeping everything.
+	pub fn parse(input: &'a str) -> Result<Self, YuriCompileError> {
+		let tokens = lex_lossless(input)?;
+		let module = parse_input(&tokens.to_ast())?;
+		Ok(Self::build(tokens, module))
+	}
+
+	/// Puts the tokens into a tree shaped like the module, which has to have been parsed from them.
+	pub fn build(tokens: LosslessTokens<'a>, module: YuriModule) -> Self {
+		let shapes = Shapes { tokens: &tokens.tokens };
+		let shape = Shape {
+			kind: SyntaxKind::Root,
+			location: 0..usize::MAX,
+			children: shapes.module(&module),
+		};
+		let mut token_iter = tokens.tokens.iter().cloned().peekable();
+		let mut root = build_node(shape, &mut token_iter);
+		root.location = match (root.tokens().first(), root.tokens().last()) {
+			(Some(first), Some(last)) => first.token.location.start..last.token.location.end,
+			_ => 0..0,
+		};
+		Self { root, end: tokens.end, module }
+	}
+}
+
+impl Display for SyntaxTree<'_> {
+	/// Writes out exactly what was parsed.
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.root)?;
+		for trivia in &self.end {
+			f.write_str(trivia.text)?;
+		}
+		Ok(())
+	}
+}
+
+/// What a node is going to look like, before the tokens get put into it.
+struct Shape {
+	kind: SyntaxKind,
+	location: Range<usize>,
+	children: Vec<Shape>,
+}
+
+/// Works out the shapes of the nodes from the AST.
+/// The AST's locations leave a few tokens out (like `export` and `return`), which get found in the tokens.
+struct Shapes<'t, 'a> {
+	tokens: &'t [LosslessToken<'a>],
+}
+
+impl Shapes<'_, '_> {
+	fn index_at(&self, offset: usize) -> usize {
+		self.tokens.partition_point(|token| token.token.location.start < offset)
+	}
+
+	/// Where the token right before the offset starts, if it's of the given type.
+	fn start_of_previous(&self, offset: usize, token_type: &YuriTokenType) -> Option<usize> {
+		let token = &self.tokens[self.index_at(offset).checked_sub(1)?];
+		(token.token.token_type == *token_type).then_some(token.token.location.start)
+	}
+
+	/// Where the token right at the offset ends, if it's of the given type.
+	fn end_of_next(&self, offset: usize, token_type: &YuriTokenType) -> Option<usize> {
+		let token = self.tokens.get(self.index_at(offset))?;
+		(token.token.token_type == *token_type).then_some(token.token.location.end)
+	}
+
+	/// Stretches a declaration back over its annotations and `export`, which can come in either order.
+	fn declaration(&self, kind: SyntaxKind, location: &Range<usize>, annotations: &[Annotation], exported: bool, mut children: Vec<Shape>) -> Shape {
+		let mut start = location.start;
+		if exported {
+			let export = self.tokens[..self.index_at(location.start)].iter()
+				.rfind(|token| token.token.token_type == YuriTokenType::Keyword(Keyword::Export));
+			if let Some(export) = export {
+				start = start.min(export.token.location.start);
+			}
+		}
+		if let Some(first) = annotations.first() {
+			start = start.min(first.location.start);
+		}
+		children.extend(annotations.iter().map(|annotation| Shape {
+			kind: SyntaxKind::Annotation,
+			location: annotation.location.clone(),
+			children: annotation.arguments.iter().map(|argument| self.expression(&argument.value)).collect(),
+		}));
+		children.sort_by_key(|child| child.location.start);
+		// the `;` after functions and modules is optional, but it's still theirs
+		let end = self.end_of_next(location.end, &YuriTokenType::Terminator).unwrap_or(location.end);
+		Shape { kind, location: start..end, children }
+	}
+
+	fn module(&self, module: &YuriModule) -> Vec<Shape> {
+		let mut declarations = Vec::new();
+		for import in &module.imports {
+			declarations.push(self.declaration(SyntaxKind::Import, &import.location, &[], false, vec![]));
+		}
+		for property in &module.properties {
+			declarations.push(self.declaration(SyntaxKind::Property, &property.location, &property.annotations, false, vec![]));
+		}
+		for global in &module.globals {
+			declarations.push(self.declaration(
+				SyntaxKind::Variable,
+				&global.location,
+				&global.annotations,
+				global.exported,
+				vec![self.expression(&global.value)],
+			));
+		}
+		for function in &module.functions {
+			let mut children: Vec<Shape> = function.arguments.iter()
+				.map(|argument| self.leaf(SyntaxKind::Argument, &argument.location))
+				.collect();
+			children.push(self.block(&function.body));
+			declarations.push(self.declaration(SyntaxKind::Function, &function.location, &function.annotations, function.exported, children));
+		}
+		for submodule in &module.submodules {
+			let children = self.module(&submodule.module);
+			declarations.push(self.declaration(SyntaxKind::Module, &submodule.location, &[], submodule.exported, children));
+		}
+		declarations.sort_by_key(|declaration| declaration.location.start);
+		declarations
+	}
+
+	fn leaf(&self, kind: SyntaxKind, location: &Range<usize>) -> Shape {
+		Shape { kind, location: location.clone(), children: vec![] }
+	}
+
+	fn block(&self, block: &Block) -> Shape {
+		let mut children: Vec<Shape> = block.statements.iter().map(|statement| match statement {
+			Statement::Variable(variable) => self.declaration(
+				SyntaxKind::Variable,
+				&variable.location,
+				&variable.annotations,
+				false,
+				vec![self.expression(&variable.value)],
+			),
+			Statement::Expression(expr) => Shape {
+				kind: SyntaxKind::ExpressionStatement,
+				location: expr.location.start..self.end_of_next(expr.location.end, &YuriTokenType::Terminator).unwrap_or(expr.location.end),
+				children: vec![self.expression(expr)],
+			},
+			Statement::Return(expr) => Shape {
+				kind: SyntaxKind::Return,
+				location: self.start_of_previous(expr.location.start, &YuriTokenType::Keyword(Keyword::Return)).unwrap_or(expr.location.start)
+					..self.end_of_next(expr.location.end, &YuriTokenType::Terminator).unwrap_or(expr.location.end),
+				children: vec![self.expression(expr)],
+			},
+		}).collect();
+		children.extend(block.tail.iter().map(|tail| self.expression(tail)));
+		Shape { kind: SyntaxKind::Block, location: block.location.clone(), children }
+	}
+
+	fn expression(&self, expr: &Expression) -> Shape {
+		let (kind, children) = match &expr.kind {
+			ExpressionKind::Literal(_) => (SyntaxKind::Literal, vec![]),
+			ExpressionKind::Variable(_) => (SyntaxKind::Name, vec![]),
+			ExpressionKind::Builtin(_) => (SyntaxKind::Builtin, vec![]),
+			ExpressionKind::FunctionCall { arguments, .. } => (SyntaxKind::Call, arguments.iter().map(|a| self.expression(a)).collect()),
+			ExpressionKind::Construct { arguments, .. } => (SyntaxKind::Construct, arguments.iter().map(|a| self.expression(a)).collect()),
+			ExpressionKind::Complex(fields) => (SyntaxKind::Complex, fields.iter().map(|(_, value)| self.expression(value)).collect()),
+			ExpressionKind::Array(elements) => (SyntaxKind::Array, elements.iter().map(|e| self.expression(e)).collect()),
+			ExpressionKind::Index { target, index } => (SyntaxKind::Index, vec![self.expression(target), self.expression(index)]),
+			ExpressionKind::Unary { operand, .. } => (SyntaxKind::Unary, vec![self.expression(operand)]),
+			ExpressionKind::Binary { lhs, rhs, .. } => (SyntaxKind::Binary, vec![self.expression(lhs), self.expression(rhs)]),
+			// the block is the whole expression, so there's no need for another node around it
+			ExpressionKind::Block(block) => return self.block(block),
+			ExpressionKind::If(if_expr) => {
+				let mut children = vec![self.expression(&if_expr.condition), self.block(&if_expr.block)];
+				children.extend(if_expr.block_else.iter().map(|block_else| match block_else {
+					Else::Block(block) => self.block(block),
+					Else::If(else_if) => self.expression(else_if),
+				}));
+				(SyntaxKind::If, children)
+			}
+			ExpressionKind::Loop { count, block, .. } => (SyntaxKind::Loop, vec![self.expression(count), self.block(block)]),
+			ExpressionKind::Fold { initial, items, block, .. } => {
+				(SyntaxKind::Fold, vec![self.expression(initial), self.expression(items), self.block(block)])
+			}
+			ExpressionKind::Map { items, block, .. } => (SyntaxKind::Map, vec![self.expression(items), self.block(block)]),
+			ExpressionKind::Filter { items, block, .. } => (SyntaxKind::Filter, vec![self.expression(items), self.block(block)]),
+		};
+		Shape { kind, location: expr.location.clone(), children }
+	}
+}
+
+/// Fills a shape with the tokens that are inside it, handing them down to the children they belong to.
+fn build_node<'a>(shape: Shape, tokens: &mut Peekable<impl Iterator<Item = LosslessToken<'a>>>) -> SyntaxNode<'a> {
+	let mut children = Vec::new();
+	let mut shapes = shape.children.into_iter().peekable();
+	while let Some(token) = tokens.peek() {
+		let start = token.token.location.start;
+		if start >= shape.location.end {
+			break;
+		}
+		match shapes.next_if(|child| child.location.start <= start) {
+			Some(child) => children.push(SyntaxElement::Node(build_node(child, tokens))),
+			None => children.extend(tokens.next().map(SyntaxElement::Token)),
+		}
+	}
+	SyntaxNode { kind: shape.kind, location: shape.location, children }
+}
+
+#[cfg(test)]
+mod test {
+	use crate::cst::{SyntaxElement, SyntaxKind, SyntaxTree};
+	use crate::lex::TriviaKind;
+
+	#[test]
+	fn lossless_round_trip() {
+		let source = "## header\r\n  comment ##\r\nprop time: f; # the time\r\n\n@vert export fn main(pos: f3): f4 {\n\t# leading\n\tlet x = -pos.x * 2;  # trailing\n\treturn f4(pos, x);\n};\nmodule m { let y = 1; }\n# the end";
+		let tree = SyntaxTree::parse(source).unwrap();
+		assert_eq!(tree.to_string(), source);
+
+		let kinds: Vec<_> = tree.root.nodes().map(|node| node.kind).collect();
+		assert_eq!(kinds, [SyntaxKind::Property, SyntaxKind::Function, SyntaxKind::Module]);
+		assert_eq!(tree.end[0].kind, TriviaKind::LineComment);
+
+		// the function takes its annotation, `export` and `;`, and its comments come along
+		let function = tree.root.nodes().nth(1).unwrap();
+		// the blank line before it is its leading trivia
+		assert!(function.to_string().starts_with("\n@vert export fn"));
+		assert!(function.to_string().ends_with("};\n"));
+		let block = function.nodes().find(|node| node.kind == SyntaxKind::Block).unwrap();
+		let statements: Vec<_> = block.nodes().map(|node| node.kind).collect();
+		assert_eq!(statements, [SyntaxKind::Variable, SyntaxKind::Return]);
+		let variable = block.nodes().next().unwrap();
+		assert_eq!(variable.to_string(), "\t# leading\n\tlet x = -pos.x * 2;  # trailing\n");
+		let SyntaxElement::Token(semicolon) = variable.children.last().unwrap() else { panic!("expected the `;`") };
+		assert_eq!(semicolon.trailing.iter().map(|t| t.kind).collect::<Vec<_>>(), [TriviaKind::Whitespace, TriviaKind::LineComment, TriviaKind::Newline]);
+
+		let offset = source.find("2;").unwrap();
+		assert_eq!(tree.root.node_at(offset).kind, SyntaxKind::Literal);
+		assert_eq!(tree.root.node_at(offset).location, offset..offset + 1);
+	}
+
+	#[test]
+	fn only_trivia() {
+		for source in ["", "  \n", "# nothing\n## at all ##"] {
+			let tree = SyntaxTree::parse(source).unwrap();
+			assert!(tree.root.children.is_empty());
+			assert_eq!(tree.to_string(), source);
+		}
+	}
+}
@@ -0,0 +1,1242 @@
+//! Runs the SPIR-V the compiler emits on the CPU, to check it against the [Interpreter].
+//! It only knows the instructions [crate::compile] actually uses, and anything the spec leaves undefined
+//! (dividing an integer by zero, shifting by 32 bits or more, converting a float that doesn't fit into an integer...)
+//! is a [YuriSemanticErrorType::Runtime] error, since a real GPU could come up with anything there.
+//!
+//! [compare_function] runs a function both ways over a bunch of arguments. If they ever disagree, that's a miscompile
+//! (or a folding bug), and it narrows things down to the smallest expression that still disagrees.
+use std::collections::HashMap;
+use std::rc::Rc;
+use rspirv::binary::Disassemble;
+use rspirv::dr::{self, Operand};
+use rspirv::spirv::{self, GLOp, Op, Word};
+use crate::builtin::BuiltinInput;
+use crate::check::{FunctionId, LocalId, TypedExpression, TypedExpressionKind, TypedFunction, TypedProgram, number_type};
+use crate::compile::compile_function;
+use crate::consteval::ConstValue;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::fold;
+use crate::interpret::Interpreter;
+use crate::parse::{ArrayLength, ComplexField, CompositeSize, NumberType, YuriType};
+use crate::target::Target;
+
+/// How many instructions a call can run before it's given up on, in case a loop never ends.
+const MAX_STEPS: usize = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+	Data(ConstValue),
+	/// A variable (by where it is in memory), and the indices into it.
+	Pointer(usize, Vec<u32>),
+}
+
+pub struct Emulator {
+	module: Rc<dr::Module>,
+	/// The type of every value, with `void` as the unit type. Images and samplers don't have one.
+	types: HashMap<Word, YuriType>,
+	/// What each pointer type points to.
+	pointers: HashMap<Word, Word>,
+	/// Constants, and specialization constants with their defaults.
+	constants: HashMap<Word, ConstValue>,
+	/// Where each function is in the module.
+	functions: HashMap<Word, usize>,
+	names: HashMap<Word, String>,
+	/// Every variable's value. The globals come first, the ones of the functions being run go after them.
+	memory: Vec<ConstValue>,
+	globals: HashMap<Word, usize>,
+	builtins: HashMap<spirv::BuiltIn, Word>,
+	/// Where the uniform block is in memory, and its members.
+	props: Option<(usize, Vec<ComplexField>)>,
+	steps: usize,
+}
+
+fn error(error_type: YuriSemanticErrorType, description: String) -> YuriSemanticError {
+	YuriSemanticError { error_type, description: Some(description), markers: vec![] }
+}
+
+fn undefined(what: &str) -> YuriSemanticError {
+	error(YuriSemanticErrorType::Runtime, format!("The result is undefined, since {what}"))
+}
+
+fn malformed(instruction: &dr::Instruction) -> YuriSemanticError {
+	error(YuriSemanticErrorType::Internal, format!("`{}` doesn't make sense here", instruction.disassemble()))
+}
+
+fn id(instruction: &dr::Instruction, index: usize) -> Result<Word, YuriSemanticError> {
+	instruction.operands.get(index).and_then(Operand::id_ref_any).ok_or_else(|| malformed(instruction))
+}
+
+fn literal(instruction: &dr::Instruction, index: usize) -> Result<u32, YuriSemanticError> {
+	match instruction.operands.get(index) {
+		Some(Operand::LiteralBit32(value)) => Ok(*value),
+		_ => Err(malformed(instruction)),
+	}
+}
+
+/// The raw bits of a scalar.
+fn bits(value: &ConstValue) -> u32 {
+	match value {
+		ConstValue::Bool(b) => *b as u32,
+		ConstValue::Float(f) => f.to_bits(),
+		ConstValue::Signed(i) => *i as u32,
+		ConstValue::Unsigned(u) => *u,
+		ConstValue::Composite(_) => 0,
+	}
+}
+
+/// Reads bits as a scalar of the type (or of the type's components).
+fn from_bits(bits: u32, ty: &YuriType) -> ConstValue {
+	match number_type(ty) {
+		Some(NumberType::Float) => ConstValue::Float(f32::from_bits(bits)),
+		Some(NumberType::Signed) => ConstValue::Signed(bits as i32),
+		_ => ConstValue::Unsigned(bits),
+	}
+}
+
+/// Gives the integers in a value another signedness, keeping their bits.
+/// SPIR-V integers don't really have one, it's up to each instruction.
+fn retype(value: ConstValue, number: Option<NumberType>) -> ConstValue {
+	match (value, number) {
+		(ConstValue::Composite(components), _) => ConstValue::Composite(components.into_iter().map(|c| retype(c, number)).collect()),
+		(value @ (ConstValue::Signed(_) | ConstValue::Unsigned(_)), Some(number @ (NumberType::Signed | NumberType::Unsigned))) => {
+			from_bits(bits(&value), &YuriType::Scalar(number))
+		}
+		(value, _) => value,
+	}
+}
+
+fn zero(ty: &YuriType) -> ConstValue {
+	match ty {
+		YuriType::Bool => ConstValue::Bool(false),
+		YuriType::Scalar(number) => ConstValue::zero(*number),
+		YuriType::Vector(number, size) => ConstValue::Composite(vec![ConstValue::zero(*number); size.count() as usize]),
+		YuriType::Matrix(size) => ConstValue::Composite(vec![zero(&YuriType::Vector(NumberType::Float, *size)); size.count() as usize]),
+		YuriType::Array(inner, ArrayLength::Fixed(length)) => ConstValue::Composite(vec![zero(inner); *length]),
+		YuriType::Complex(fields) => ConstValue::Composite(fields.iter().map(|f| zero(&f.field_type)).collect()),
+		_ => ConstValue::UNIT,
+	}
+}
+
+/// The scalar type of a type's components (or the type itself, if it's a scalar).
+fn scalar_of(ty: &YuriType) -> YuriType {
+	match ty {
+		YuriType::Vector(number, _) => YuriType::Scalar(*number),
+		YuriType::Matrix(_) => YuriType::Scalar(NumberType::Float),
+		other => other.clone(),
+	}
+}
+
+/// Calls `f` on each set of matching scalars in the operands. Scalar operands go with every component.
+fn each(
+	operands: &[ConstValue],
+	f: &mut dyn FnMut(&[ConstValue]) -> Result<ConstValue, YuriSemanticError>,
+) -> Result<ConstValue, YuriSemanticError> {
+	let Some(ConstValue::Composite(first)) = operands.iter().find(|o| matches!(o, ConstValue::Composite(_))) else {
+		return f(operands);
+	};
+	let components = (0..first.len())
+		.map(|i| {
+			let parts: Vec<ConstValue> = operands.iter()
+				.map(|o| o.components().get(i).cloned().unwrap_or_else(|| o.clone()))
+				.collect();
+			each(&parts, f)
+		})
+		.collect::<Result<_, _>>()?;
+	Ok(ConstValue::Composite(components))
+}
+
+fn greater(a: &ConstValue, b: &ConstValue) -> bool {
+	match (a, b) {
+		(ConstValue::Float(a), ConstValue::Float(b)) => a > b,
+		(ConstValue::Signed(a), ConstValue::Signed(b)) => a > b,
+		(ConstValue::Unsigned(a), ConstValue::Unsigned(b)) => a > b,
+		_ => false,
+	}
+}
+
+/// The signedness a GLSL.std.450 instruction reads integers with, if it has integer operands at all.
+fn glsl_integers(op: GLOp) -> Option<NumberType> {
+	match op {
+		GLOp::SAbs | GLOp::SSign | GLOp::SMin | GLOp::SMax | GLOp::SClamp => Some(NumberType::Signed),
+		GLOp::UMin | GLOp::UMax | GLOp::UClamp => Some(NumberType::Unsigned),
+		_ => None,
+	}
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+	a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn to_floats(value: &ConstValue) -> Vec<f32> {
+	value.components().iter().map(|c| c.as_f32().unwrap_or(0.0)).collect()
+}
+
+fn from_floats(values: impl IntoIterator<Item = f32>) -> ConstValue {
+	ConstValue::Composite(values.into_iter().map(ConstValue::Float).collect())
+}
+
+/// A matrix as a list of columns.
+fn columns(value: &ConstValue) -> Vec<Vec<f32>> {
+	value.components().iter().map(to_floats).collect()
+}
+
+fn from_columns(columns: &[Vec<f32>]) -> ConstValue {
+	ConstValue::Composite(columns.iter().map(|c| from_floats(c.iter().copied())).collect())
+}
+
+/// The matrix without one of its columns and one of its rows.
+fn minor(m: &[Vec<f32>], column: usize, row: usize) -> Vec<Vec<f32>> {
+	m.iter().enumerate()
+		.filter(|(c, _)| *c != column)
+		.map(|(_, c)| c.iter().enumerate().filter(|(r, _)| *r != row).map(|(_, v)| *v).collect())
+		.collect()
+}
+
+/// Expands along the first row.
+fn determinant(m: &[Vec<f32>]) -> f32 {
+	match m.len() {
+		1 => m[0][0],
+		2 => m[0][0] * m[1][1] - m[1][0] * m[0][1],
+		n => (0..n).map(|column| {
+			let sign = if column % 2 == 0 { 1.0 } else { -1.0 };
+			sign * m[column][0] * determinant(&minor(m, column, 0))
+		}).sum(),
+	}
+}
+
+fn matrix_times_vector(m: &[Vec<f32>], v: &[f32]) -> Vec<f32> {
+	(0..m[0].len())
+		.map(|row| m.iter().zip(v).map(|(column, v)| column[row] * v).sum())
+		.collect()
+}
+
+/// Every operand's components, one at a time, for the instructions that work on whole vectors.
+fn vectors(operands: &[ConstValue]) -> Vec<Vec<f32>> {
+	operands.iter().map(to_floats).collect()
+}
+
+/// Works out a GLSL.std.450 instruction the way its spec defines it, or `None` if the emulator doesn't know it.
+/// This deliberately doesn't share anything with [crate::consteval], since that's what it's checked against.
+fn glsl_operation(op: GLOp, operands: &[ConstValue]) -> Option<ConstValue> {
+	let float = |value: &ConstValue| value.as_f32().unwrap_or(0.0);
+	let floats = |f: &dyn Fn(&[f32]) -> f32| {
+		each(operands, &mut |parts| Ok(ConstValue::Float(f(&parts.iter().map(float).collect::<Vec<_>>())))).ok()
+	};
+	let less = |a: &ConstValue, b: &ConstValue| greater(b, a);
+	let pick = |parts: &[ConstValue], first: bool| if first { parts[0].clone() } else { parts[1].clone() };
+	Some(match op {
+		GLOp::Sin => floats(&|x| x[0].sin())?,
+		GLOp::Cos => floats(&|x| x[0].cos())?,
+		GLOp::Tan => floats(&|x| x[0].tan())?,
+		GLOp::Asin => floats(&|x| x[0].asin())?,
+		GLOp::Acos => floats(&|x| x[0].acos())?,
+		GLOp::Atan => floats(&|x| x[0].atan())?,
+		GLOp::Atan2 => floats(&|x| x[0].atan2(x[1]))?,
+		GLOp::Pow => floats(&|x| x[0].powf(x[1]))?,
+		GLOp::Exp => floats(&|x| x[0].exp())?,
+		GLOp::Exp2 => floats(&|x| x[0].exp2())?,
+		GLOp::Log => floats(&|x| x[0].ln())?,
+		GLOp::Log2 => floats(&|x| x[0].log2())?,
+		GLOp::Sqrt => floats(&|x| x[0].sqrt())?,
+		GLOp::InverseSqrt => floats(&|x| 1.0 / x[0].sqrt())?,
+		GLOp::FAbs => floats(&|x| x[0].abs())?,
+		GLOp::FSign => floats(&|x| if x[0] > 0.0 { 1.0 } else if x[0] < 0.0 { -1.0 } else { 0.0 })?,
+		GLOp::Floor => floats(&|x| x[0].floor())?,
+		GLOp::Ceil => floats(&|x| x[0].ceil())?,
+		GLOp::Trunc => floats(&|x| x[0].trunc())?,
+		GLOp::Fract => floats(&|x| x[0] - x[0].floor())?,
+		GLOp::RoundEven => floats(&|x| x[0].round_ties_even())?,
+		// x * (1 - a) + y * a
+		GLOp::FMix => floats(&|x| x[0] * (1.0 - x[2]) + x[1] * x[2])?,
+		GLOp::Step => floats(&|x| if x[1] < x[0] { 0.0 } else { 1.0 })?,
+		GLOp::SmoothStep => floats(&|x| {
+			let t = ((x[2] - x[0]) / (x[1] - x[0])).clamp(0.0, 1.0);
+			t * t * (3.0 - 2.0 * t)
+		})?,
+		GLOp::SAbs => each(operands, &mut |parts| Ok(ConstValue::Signed((bits(&parts[0]) as i32).wrapping_abs()))).ok()?,
+		GLOp::SSign => each(operands, &mut |parts| Ok(ConstValue::Signed((bits(&parts[0]) as i32).signum()))).ok()?,
+		// y if y < x, otherwise x
+		GLOp::FMin | GLOp::SMin | GLOp::UMin => each(operands, &mut |parts| Ok(pick(parts, !less(&parts[1], &parts[0])))).ok()?,
+		// y if x < y, otherwise x
+		GLOp::FMax | GLOp::SMax | GLOp::UMax => each(operands, &mut |parts| Ok(pick(parts, !less(&parts[0], &parts[1])))).ok()?,
+		// min(max(x, low), high)
+		GLOp::FClamp | GLOp::SClamp | GLOp::UClamp => each(operands, &mut |parts| {
+			let raised = if less(&parts[0], &parts[1]) { &parts[1] } else { &parts[0] };
+			Ok(if less(&parts[2], raised) { parts[2].clone() } else { raised.clone() })
+		}).ok()?,
+		GLOp::Length => {
+			let x = to_floats(&operands[0]);
+			ConstValue::Float(dot(&x, &x).sqrt())
+		}
+		GLOp::Distance => {
+			let [a, b] = vectors(operands).try_into().ok()?;
+			let difference: Vec<f32> = a.iter().zip(&b).map(|(a, b)| a - b).collect();
+			ConstValue::Float(dot(&difference, &difference).sqrt())
+		}
+		GLOp::Cross => {
+			let [x, y] = vectors(operands).try_into().ok()?;
+			from_floats([
+				x[1] * y[2] - y[1] * x[2],
+				x[2] * y[0] - y[2] * x[0],
+				x[0] * y[1] - y[0] * x[1],
+			])
+		}
+		GLOp::Normalize => {
+			let x = to_floats(&operands[0]);
+			let length = dot(&x, &x).sqrt();
+			from_floats(x.iter().map(|x| x / length))
+		}
+		// I - 2 * dot(N, I) * N
+		GLOp::Reflect => {
+			let [i, n] = vectors(operands).try_into().ok()?;
+			let d = 2.0 * dot(&n, &i);
+			from_floats(i.iter().zip(&n).map(|(i, n)| i - d * n))
+		}
+		GLOp::Determinant => ConstValue::Float(determinant(&columns(&operands[0]))),
+		// the adjugate (the transposed cofactors) over the determinant
+		GLOp::MatrixInverse => {
+			let m = columns(&operands[0]);
+			let det = determinant(&m);
+			let n = m.len();
+			let inverse: Vec<Vec<f32>> = (0..n)
+				.map(|column| (0..n).map(|row| {
+					let sign = if (row + column) % 2 == 0 { 1.0 } else { -1.0 };
+					sign * determinant(&minor(&m, row, column)) / det
+				}).collect())
+				.collect();
+			from_columns(&inverse)
+		}
+		_ => return None,
+	})
+}
+
+/// What makes a GLSL.std.450 instruction's result undefined for some scalars of its operands, if anything does.
+fn glsl_undefined(op: GLOp, operands: &[ConstValue]) -> Option<&'static str> {
+	let float = |value: &ConstValue| value.as_f32().unwrap_or(0.0);
+	let nan = |values: &[ConstValue]| values.iter().any(|v| float(v).is_nan());
+	let mut reason = None;
+	let _ = each(operands, &mut |parts| {
+		reason = reason.or(match (op, parts) {
+			(GLOp::Asin | GLOp::Acos, [x]) if float(x).abs() > 1.0 => Some("its argument is outside of -1..1"),
+			(GLOp::Atan2, [y, x]) if float(y) == 0.0 && float(x) == 0.0 => Some("both arguments of `atan2` are 0"),
+			(GLOp::Pow, [x, y]) if float(x) < 0.0 || (float(x) == 0.0 && float(y) <= 0.0) => Some("`pow` can't raise that to that"),
+			(GLOp::Log | GLOp::Log2 | GLOp::InverseSqrt, [x]) if float(x) <= 0.0 => Some("its argument isn't positive"),
+			(GLOp::Sqrt, [x]) if float(x) < 0.0 => Some("it's the square root of a negative number"),
+			(GLOp::FMin | GLOp::FMax | GLOp::FClamp, _) if nan(parts) => Some("which one's smaller isn't up to NaN"),
+			(GLOp::FClamp | GLOp::SClamp | GLOp::UClamp, [_, low, high]) if greater(low, high) => Some("the lower bound of `clamp` is above the upper one"),
+			(GLOp::SmoothStep, [low, high, _]) if float(low) >= float(high) => Some("the edges of `smoothstep` are the wrong way around"),
+			_ => None,
+		});
+		Ok(ConstValue::UNIT)
+	});
+	reason
+}
+
+impl Emulator {
+	/// Loads a module, the same way the rest of the tools do.
+	pub fn load(words: &[u32]) -> Result<Self, YuriSemanticError> {
+		let mut loader = dr::Loader::new();
+		rspirv::binary::parse_words(words, &mut loader)
+			.map_err(|err| error(YuriSemanticErrorType::Internal, format!("The SPIR-V couldn't be loaded: {err}")))?;
+		let module = loader.module();
+		let mut emulator = Emulator {
+			module: Rc::new(dr::Module::new()),
+			types: HashMap::new(),
+			pointers: HashMap::new(),
+			constants: HashMap::new(),
+			functions: HashMap::new(),
+			names: HashMap::new(),
+			memory: Vec::new(),
+			globals: HashMap::new(),
+			builtins: HashMap::new(),
+			props: None,
+			steps: 0,
+		};
+		let mut member_names: HashMap<(Word, u32), String> = HashMap::new();
+		for instruction in &module.debug_names {
+			match (instruction.class.opcode, instruction.operands.as_slice()) {
+				(Op::Name, [Operand::IdRef(target), Operand::LiteralString(name)]) => {
+					emulator.names.insert(*target, name.clone());
+				}
+				(Op::MemberName, [Operand::IdRef(target), Operand::LiteralBit32(member), Operand::LiteralString(name)]) => {
+					member_names.insert((*target, *member), name.clone());
+				}
+				_ => {}
+			}
+		}
+		let mut blocks = Vec::new();
+		for instruction in &module.annotations {
+			match instruction.operands.as_slice() {
+				[Operand::IdRef(target), Operand::Decoration(spirv::Decoration::BuiltIn), Operand::BuiltIn(builtin)] => {
+					emulator.builtins.insert(*builtin, *target);
+				}
+				[Operand::IdRef(target), Operand::Decoration(spirv::Decoration::Block)] => blocks.push(*target),
+				_ => {}
+			}
+		}
+		for instruction in &module.types_global_values {
+			emulator.global_instruction(instruction, &member_names, &blocks)?;
+		}
+		for (index, function) in module.functions.iter().enumerate() {
+			if let Some(id) = function.def_id() {
+				emulator.functions.insert(id, index);
+			}
+		}
+		emulator.module = Rc::new(module);
+		Ok(emulator)
+	}
+
+	fn global_instruction(
+		&mut self,
+		instruction: &dr::Instruction,
+		member_names: &HashMap<(Word, u32), String>,
+		blocks: &[Word],
+	) -> Result<(), YuriSemanticError> {
+		let Some(result) = instruction.result_id else {
+			return Ok(());
+		};
+		let count = |instruction: &dr::Instruction| literal(instruction, 1)
+			.map(|count| CompositeSize::from_count(count as usize))?
+			.ok_or_else(|| malformed(instruction));
+		let ty = match instruction.class.opcode {
+			Op::TypeVoid => YuriType::Unit,
+			Op::TypeBool => YuriType::Bool,
+			Op::TypeFloat => YuriType::Scalar(NumberType::Float),
+			Op::TypeInt if literal(instruction, 1)? == 1 => YuriType::Scalar(NumberType::Signed),
+			Op::TypeInt => YuriType::Scalar(NumberType::Unsigned),
+			Op::TypeVector => {
+				let number = number_type(&self.value_type(id(instruction, 0)?)?).ok_or_else(|| malformed(instruction))?;
+				YuriType::Vector(number, count(instruction)?)
+			}
+			Op::TypeMatrix => YuriType::Matrix(count(instruction)?),
+			Op::TypeArray => {
+				let element = self.value_type(id(instruction, 0)?)?;
+				let length = self.constants.get(&id(instruction, 1)?)
+					.and_then(ConstValue::as_index)
+					.ok_or_else(|| malformed(instruction))?;
+				YuriType::Array(Box::new(element), ArrayLength::Fixed(length))
+			}
+			Op::TypeStruct => {
+				let fields = instruction.operands.iter()
+					.enumerate()
+					.map(|(i, member)| Ok(ComplexField {
+						name: member_names.get(&(result, i as u32)).cloned().unwrap_or_else(|| i.to_string()),
+						field_type: self.value_type(member.id_ref_any().ok_or_else(|| malformed(instruction))?)?,
+						annotations: Vec::new(),
+					}))
+					.collect::<Result<Vec<_>, YuriSemanticError>>()?;
+				YuriType::Complex(fields)
+			}
+			Op::TypePointer => {
+				self.pointers.insert(result, id(instruction, 1)?);
+				return Ok(());
+			}
+			Op::Variable => {
+				let pointee = instruction.result_type.and_then(|ty| self.pointers.get(&ty)).ok_or_else(|| malformed(instruction))?;
+				// samplers don't have a type, or a value
+				let value = self.types.get(pointee).map(zero).unwrap_or(ConstValue::UNIT);
+				if blocks.contains(pointee)
+					&& let Some(YuriType::Complex(fields)) = self.types.get(pointee)
+				{
+					self.props = Some((self.memory.len(), fields.clone()));
+				}
+				self.globals.insert(result, self.memory.len());
+				self.memory.push(value);
+				return Ok(());
+			}
+			Op::Constant | Op::SpecConstant => {
+				let ty = self.value_type(instruction.result_type.unwrap_or(0))?;
+				self.constants.insert(result, from_bits(literal(instruction, 0)?, &ty));
+				return Ok(());
+			}
+			Op::ConstantTrue | Op::SpecConstantTrue | Op::ConstantFalse | Op::SpecConstantFalse => {
+				let value = matches!(instruction.class.opcode, Op::ConstantTrue | Op::SpecConstantTrue);
+				self.constants.insert(result, ConstValue::Bool(value));
+				return Ok(());
+			}
+			Op::ConstantComposite | Op::SpecConstantComposite => {
+				let components = instruction.operands.iter()
+					.map(|o| o.id_ref_any().and_then(|id| self.constants.get(&id).cloned()).ok_or_else(|| malformed(instruction)))
+					.collect::<Result<_, _>>()?;
+				self.constants.insert(result, ConstValue::Composite(components));
+				return Ok(());
+			}
+			Op::ConstantNull | Op::Undef => {
+				let ty = self.value_type(instruction.result_type.unwrap_or(0))?;
+				self.constants.insert(result, zero(&ty));
+				return Ok(());
+			}
+			Op::SpecConstantOp => {
+				let Some(Operand::LiteralSpecConstantOpInteger(opcode)) = instruction.operands.first() else {
+					return Err(malformed(instruction));
+				};
+				let inner = dr::Instruction::new(*opcode, instruction.result_type, Some(result), instruction.operands[1..].to_vec());
+				let value = self.operation(&HashMap::new(), &inner)?;
+				self.constants.insert(result, value);
+				return Ok(());
+			}
+			_ => return Ok(()),
+		};
+		self.types.insert(result, ty);
+		Ok(())
+	}
+
+	fn value_type(&self, id: Word) -> Result<YuriType, YuriSemanticError> {
+		self.types.get(&id).cloned().ok_or_else(|| error(
+			YuriSemanticErrorType::Unsupported,
+			format!("%{id} isn't a type that can be emulated"),
+		))
+	}
+
+	/// Gives a prop (by its fully-qualified name) a value. Props that aren't used don't end up in the module at all,
+	/// so those are ignored.
+	pub fn set_prop(&mut self, name: &str, value: impl Into<ConstValue>) -> Result<(), YuriSemanticError> {
+		let value = value.into();
+		let Some((slot, members)) = &self.props else {
+			return Ok(());
+		};
+		let Some(member) = members.iter().position(|m| m.name == name) else {
+			return Ok(());
+		};
+		let member_type = &members[member].field_type;
+		if !value.has_type(member_type) {
+			return Err(error(YuriSemanticErrorType::Runtime, format!("The prop `{name}` is a `{member_type}`, which {value:?} isn't")));
+		}
+		if let ConstValue::Composite(block) = &mut self.memory[*slot] {
+			block[member] = value;
+		}
+		Ok(())
+	}
+
+	/// Gives a builtin input a value, if the module reads it.
+	pub fn set_input(&mut self, input: BuiltinInput, value: impl Into<ConstValue>) -> Result<(), YuriSemanticError> {
+		let value = value.into();
+		if !value.has_type(&input.input_type()) {
+			return Err(error(YuriSemanticErrorType::Runtime, format!("`@{}` is a `{}`, which {value:?} isn't", input.name(), input.input_type())));
+		}
+		let (builtins, value) = match input {
+			// the GPU gives all 4 components, so z and w are made up
+			BuiltinInput::FragCoord => {
+				let mut components = value.components().to_vec();
+				components.extend([ConstValue::Float(0.0), ConstValue::Float(1.0)]);
+				(&[spirv::BuiltIn::FragCoord][..], ConstValue::Composite(components))
+			}
+			BuiltinInput::FrontFacing => (&[spirv::BuiltIn::FrontFacing][..], value),
+			BuiltinInput::VertexIndex => (&[spirv::BuiltIn::VertexIndex, spirv::BuiltIn::VertexId][..], value),
+			BuiltinInput::InstanceIndex => (&[spirv::BuiltIn::InstanceIndex][..], value),
+		};
+		for builtin in builtins {
+			if let Some(slot) = self.builtins.get(builtin).and_then(|variable| self.globals.get(variable)) {
+				self.memory[*slot] = value.clone();
+			}
+		}
+		Ok(())
+	}
+
+	/// Runs a function (by the name it was given in the module) with the given arguments.
+	pub fn call(&mut self, name: &str, arguments: &[ConstValue]) -> Result<ConstValue, YuriSemanticError> {
+		let Some(index) = self.functions.iter()
+			.find(|(id, _)| self.names.get(id).is_some_and(|n| n == name))
+			.map(|(_, index)| *index)
+		else {
+			return Err(error(YuriSemanticErrorType::Runtime, format!("There's no function named `{name}`")));
+		};
+		let parameters = &self.module.functions[index].parameters;
+		if parameters.len() != arguments.len() {
+			return Err(error(YuriSemanticErrorType::Runtime, format!("`{name}` takes {} arguments, not {}", parameters.len(), arguments.len())));
+		}
+		for (parameter, argument) in parameters.iter().zip(arguments) {
+			let ty = self.value_type(parameter.result_type.unwrap_or(0))?;
+			if !argument.has_type(&ty) {
+				return Err(error(YuriSemanticErrorType::Runtime, format!("An argument of `{name}` is a `{ty}`, which {argument:?} isn't")));
+			}
+		}
+		self.steps = 0;
+		self.run(index, arguments.to_vec())
+	}
+
+	fn run(&mut self, index: usize, arguments: Vec<ConstValue>) -> Result<ConstValue, YuriSemanticError> {
+		let module = self.module.clone();
+		let function = &module.functions[index];
+		let mut values: HashMap<Word, Value> = function.parameters.iter()
+			.filter_map(|p| p.result_id)
+			.zip(arguments.into_iter().map(Value::Data))
+			.collect();
+		let memory = self.memory.len();
+		let result = self.run_blocks(function, &mut values);
+		// the function's variables go away when it returns
+		self.memory.truncate(memory);
+		result
+	}
+
+	fn run_blocks(&mut self, function: &dr::Function, values: &mut HashMap<Word, Value>) -> Result<ConstValue, YuriSemanticError> {
+		let not_found = || error(YuriSemanticErrorType::Internal, "A function branches to a block that isn't there".to_string());
+		let mut block = function.blocks.first().ok_or_else(not_found)?;
+		let mut previous = None;
+		loop {
+			let mut next = None;
+			// phis all take their values at once, from the block that was just left
+			let mut phis = Vec::new();
+			for instruction in &block.instructions {
+				self.steps += 1;
+				if self.steps > MAX_STEPS {
+					return Err(error(YuriSemanticErrorType::Runtime, format!("Gave up after running {MAX_STEPS} instructions")));
+				}
+				let opcode = instruction.class.opcode;
+				if opcode == Op::Phi {
+					let incoming = instruction.operands.chunks(2)
+						.find(|pair| pair.get(1).and_then(Operand::id_ref_any) == previous)
+						.and_then(|pair| pair[0].id_ref_any())
+						.ok_or_else(|| malformed(instruction))?;
+					phis.push((instruction.result_id.ok_or_else(|| malformed(instruction))?, self.value(values, incoming)?));
+					continue;
+				}
+				values.extend(phis.drain(..));
+				match opcode {
+					Op::Line | Op::NoLine | Op::SelectionMerge | Op::LoopMerge => {}
+					Op::Variable => {
+						let pointee = instruction.result_type.and_then(|ty| self.pointers.get(&ty)).ok_or_else(|| malformed(instruction))?;
+						let value = zero(&self.value_type(*pointee)?);
+						values.insert(instruction.result_id.ok_or_else(|| malformed(instruction))?, Value::Pointer(self.memory.len(), vec![]));
+						self.memory.push(value);
+					}
+					Op::Load => {
+						let (slot, path) = self.pointer(values, id(instruction, 0)?)?;
+						let value = self.place(slot, &path)?.clone();
+						values.insert(instruction.result_id.ok_or_else(|| malformed(instruction))?, Value::Data(value));
+					}
+					Op::Store => {
+						let (slot, path) = self.pointer(values, id(instruction, 0)?)?;
+						let value = self.data(values, id(instruction, 1)?)?;
+						*self.place(slot, &path)? = value;
+					}
+					Op::AccessChain => {
+						let (slot, mut path) = self.pointer(values, id(instruction, 0)?)?;
+						for index in instruction.operands[1..].iter().filter_map(Operand::id_ref_any) {
+							let index = self.data(values, index)?.as_index().ok_or_else(|| undefined("an index is negative"))?;
+							path.push(index as u32);
+						}
+						values.insert(instruction.result_id.ok_or_else(|| malformed(instruction))?, Value::Pointer(slot, path));
+					}
+					Op::FunctionCall => {
+						let callee = *self.functions.get(&id(instruction, 0)?).ok_or_else(|| malformed(instruction))?;
+						let arguments = instruction.operands[1..].iter()
+							.filter_map(Operand::id_ref_any)
+							.map(|a| self.data(values, a))
+							.collect::<Result<Vec<_>, _>>()?;
+						let result = self.run(callee, arguments)?;
+						if let Some(id) = instruction.result_id {
+							values.insert(id, Value::Data(result));
+						}
+					}
+					Op::Branch => next = Some(id(instruction, 0)?),
+					Op::BranchConditional => {
+						let condition = self.data(values, id(instruction, 0)?)?.as_bool().ok_or_else(|| malformed(instruction))?;
+						next = Some(id(instruction, if condition { 1 } else { 2 })?);
+					}
+					Op::Return => return Ok(ConstValue::UNIT),
+					Op::ReturnValue => return self.data(values, id(instruction, 0)?),
+					Op::ImageSampleImplicitLod | Op::ImageSampleExplicitLod => {
+						return Err(error(YuriSemanticErrorType::Unsupported, "Sampling textures can't be emulated".to_string()));
+					}
+					_ => {
+						let value = self.operation(values, instruction)?;
+						values.insert(instruction.result_id.ok_or_else(|| malformed(instruction))?, Value::Data(value));
+					}
+				}
+			}
+			let label = next.ok_or_else(|| error(YuriSemanticErrorType::Internal, "A block doesn't end in a branch or return".to_string()))?;
+			previous = block.label_id();
+			block = function.blocks.iter().find(|b| b.label_id() == Some(label)).ok_or_else(not_found)?;
+		}
+	}
+
+	fn value(&self, values: &HashMap<Word, Value>, id: Word) -> Result<Value, YuriSemanticError> {
+		if let Some(value) = values.get(&id) {
+			return Ok(value.clone());
+		}
+		if let Some(slot) = self.globals.get(&id) {
+			return Ok(Value::Pointer(*slot, vec![]));
+		}
+		self.constants.get(&id)
+			.map(|c| Value::Data(c.clone()))
+			.ok_or_else(|| error(YuriSemanticErrorType::Internal, format!("%{id} is used before it has a value")))
+	}
+
+	fn data(&self, values: &HashMap<Word, Value>, id: Word) -> Result<ConstValue, YuriSemanticError> {
+		match self.value(values, id)? {
+			Value::Data(value) => Ok(value),
+			Value::Pointer(..) => Err(error(YuriSemanticErrorType::Internal, format!("%{id} is a pointer, not a value"))),
+		}
+	}
+
+	fn pointer(&self, values: &HashMap<Word, Value>, id: Word) -> Result<(usize, Vec<u32>), YuriSemanticError> {
+		match self.value(values, id)? {
+			Value::Pointer(slot, path) => Ok((slot, path)),
+			Value::Data(_) => Err(error(YuriSemanticErrorType::Internal, format!("%{id} is a value, not a pointer"))),
+		}
+	}
+
+	fn place(&mut self, slot: usize, path: &[u32]) -> Result<&mut ConstValue, YuriSemanticError> {
+		let mut place = &mut self.memory[slot];
+		for index in path {
+			place = match place {
+				ConstValue::Composite(components) => components.get_mut(*index as usize).ok_or_else(|| undefined("an index is out of bounds"))?,
+				_ => return Err(error(YuriSemanticErrorType::Internal, "Indexing into a scalar".to_string())),
+			};
+		}
+		Ok(place)
+	}
+
+	/// Runs an instruction that just works out a value from other values.
+	fn operation(&self, values: &HashMap<Word, Value>, instruction: &dr::Instruction) -> Result<ConstValue, YuriSemanticError> {
+		let opcode = instruction.class.opcode;
+		let ty = self.value_type(instruction.result_type.ok_or_else(|| malformed(instruction))?)?;
+		let scalar = &scalar_of(&ty);
+		if opcode == Op::ExtInst {
+			return self.ext_inst(values, instruction, &ty);
+		}
+		let operands = instruction.operands.iter()
+			.filter_map(Operand::id_ref_any)
+			.map(|id| self.data(values, id))
+			.collect::<Result<Vec<_>, _>>()?;
+		let literals: Vec<u32> = instruction.operands.iter()
+			.filter_map(|o| if let Operand::LiteralBit32(value) = o { Some(*value) } else { None })
+			.collect();
+		let float = |value: &ConstValue| value.as_f32().unwrap_or(0.0);
+		let int = |f: fn(u32, u32) -> u32| move |parts: &[ConstValue]| Ok(from_bits(f(bits(&parts[0]), bits(&parts[1])), scalar));
+		let floats = |f: fn(f32, f32) -> f32| move |parts: &[ConstValue]| Ok(ConstValue::Float(f(float(&parts[0]), float(&parts[1]))));
+		let compare_floats = |f: fn(f32, f32) -> bool| move |parts: &[ConstValue]| Ok(ConstValue::Bool(f(float(&parts[0]), float(&parts[1]))));
+		let compare_signed = |f: fn(i32, i32) -> bool| move |parts: &[ConstValue]| Ok(ConstValue::Bool(f(bits(&parts[0]) as i32, bits(&parts[1]) as i32)));
+		let compare_unsigned = |f: fn(u32, u32) -> bool| move |parts: &[ConstValue]| Ok(ConstValue::Bool(f(bits(&parts[0]), bits(&parts[1]))));
+		let logical = |f: fn(bool, bool) -> bool| move |parts: &[ConstValue]| Ok(ConstValue::Bool(f(parts[0] == ConstValue::Bool(true), parts[1] == ConstValue::Bool(true))));
+		let divide = |f: fn(u32, u32) -> u32, signed: bool| move |parts: &[ConstValue]| {
+			let (a, b) = (bits(&parts[0]), bits(&parts[1]));
+			if b == 0 {
+				return Err(undefined("it divides by zero"));
+			}
+			if signed && a as i32 == i32::MIN && b as i32 == -1 {
+				return Err(undefined("the division overflows"));
+			}
+			Ok(from_bits(f(a, b), scalar))
+		};
+		let shift = |f: fn(u32, u32) -> u32| move |parts: &[ConstValue]| {
+			let amount = bits(&parts[1]);
+			if amount >= 32 {
+				return Err(undefined("it shifts by the whole width or more"));
+			}
+			Ok(from_bits(f(bits(&parts[0]), amount), scalar))
+		};
+		let to_integer = |low: f32, high: f32| move |parts: &[ConstValue]| {
+			let value = float(&parts[0]).trunc();
+			// NaN fails both of these
+			if !(value >= low && value < high) {
+				return Err(undefined("a float doesn't fit in the integer it's converted to"));
+			}
+			Ok(if low < 0.0 { from_bits(value as i32 as u32, scalar) } else { from_bits(value as u32, scalar) })
+		};
+		let component = |value: &ConstValue, index: u32| match value {
+			ConstValue::Composite(components) => components.get(index as usize).cloned().ok_or_else(|| malformed(instruction)),
+			_ => Err(malformed(instruction)),
+		};
+		match (opcode, operands.as_slice()) {
+			(Op::CopyObject, [value]) => Ok(value.clone()),
+			// vectors can be built out of smaller vectors
+			(Op::CompositeConstruct, parts) if matches!(ty, YuriType::Vector(..)) => {
+				Ok(ConstValue::Composite(parts.iter().flat_map(|p| p.components().to_vec()).collect()))
+			}
+			(Op::CompositeConstruct, parts) => Ok(ConstValue::Composite(parts.to_vec())),
+			(Op::CompositeExtract, [value]) => literals.iter().try_fold(value.clone(), |value, index| component(&value, *index)),
+			(Op::VectorShuffle, [a, b]) => {
+				let all: Vec<ConstValue> = a.components().iter().chain(b.components()).cloned().collect();
+				let picked = literals.iter()
+					.map(|i| all.get(*i as usize).cloned().ok_or_else(|| undefined("a shuffle picks a component that isn't there")))
+					.collect::<Result<_, _>>()?;
+				Ok(ConstValue::Composite(picked))
+			}
+			(Op::VectorExtractDynamic, [vector, index]) => index.as_index()
+				.and_then(|i| vector.components().get(i).cloned())
+				.ok_or_else(|| undefined("an index is out of bounds")),
+			(Op::Select, [condition, a, b]) => each(&[condition.clone(), a.clone(), b.clone()], &mut |parts| {
+				Ok(if parts[0] == ConstValue::Bool(true) { parts[1].clone() } else { parts[2].clone() })
+			}),
+			(Op::Bitcast, single @ [_]) => each(single, &mut |parts| Ok(from_bits(bits(&parts[0]), scalar))),
+			(Op::ConvertFToS, single @ [_]) => each(single, &mut to_integer(-2147483648.0, 2147483648.0)),
+			(Op::ConvertFToU, single @ [_]) => each(single, &mut to_integer(0.0, 4294967296.0)),
+			(Op::ConvertSToF, single @ [_]) => each(single, &mut |parts| Ok(ConstValue::Float(bits(&parts[0]) as i32 as f32))),
+			(Op::ConvertUToF, single @ [_]) => each(single, &mut |parts| Ok(ConstValue::Float(bits(&parts[0]) as f32))),
+			(Op::FNegate, single @ [_]) => each(single, &mut |parts| Ok(ConstValue::Float(-float(&parts[0])))),
+			(Op::SNegate, single @ [_]) => each(single, &mut |parts| Ok(from_bits((bits(&parts[0]) as i32).wrapping_neg() as u32, scalar))),
+			(Op::Not, single @ [_]) => each(single, &mut |parts| Ok(from_bits(!bits(&parts[0]), scalar))),
+			(Op::LogicalNot, single @ [_]) => each(single, &mut |parts| Ok(ConstValue::Bool(parts[0] != ConstValue::Bool(true)))),
+			(Op::FAdd, parts) => each(parts, &mut floats(|a, b| a + b)),
+			(Op::FSub, parts) => each(parts, &mut floats(|a, b| a - b)),
+			(Op::FMul, parts) => each(parts, &mut floats(|a, b| a * b)),
+			(Op::FDiv, parts) => each(parts, &mut floats(|a, b| a / b)),
+			(Op::FRem, parts) => each(parts, &mut |parts| {
+				if float(&parts[1]) == 0.0 {
+					return Err(undefined("it takes the remainder of dividing by zero"));
+				}
+				Ok(ConstValue::Float(float(&parts[0]) % float(&parts[1])))
+			}),
+			(Op::IAdd, parts) => each(parts, &mut int(u32::wrapping_add)),
+			(Op::ISub, parts) => each(parts, &mut int(u32::wrapping_sub)),
+			(Op::IMul, parts) => each(parts, &mut int(u32::wrapping_mul)),
+			(Op::SDiv, parts) => each(parts, &mut divide(|a, b| (a as i32 / b as i32) as u32, true)),
+			(Op::SRem, parts) => each(parts, &mut divide(|a, b| (a as i32 % b as i32) as u32, true)),
+			(Op::UDiv, parts) => each(parts, &mut divide(|a, b| a / b, false)),
+			(Op::UMod, parts) => each(parts, &mut divide(|a, b| a % b, false)),
+			(Op::BitwiseAnd, parts) => each(parts, &mut int(|a, b| a & b)),
+			(Op::BitwiseOr, parts) => each(parts, &mut int(|a, b| a | b)),
+			(Op::BitwiseXor, parts) => each(parts, &mut int(|a, b| a ^ b)),
+			(Op::ShiftLeftLogical, parts) => each(parts, &mut shift(|a, b| a << b)),
+			(Op::ShiftRightLogical, parts) => each(parts, &mut shift(|a, b| a >> b)),
+			(Op::ShiftRightArithmetic, parts) => each(parts, &mut shift(|a, b| ((a as i32) >> b) as u32)),
+			(Op::IEqual, parts) => each(parts, &mut compare_unsigned(|a, b| a == b)),
+			(Op::INotEqual, parts) => each(parts, &mut compare_unsigned(|a, b| a != b)),
+			(Op::ULessThan, parts) => each(parts, &mut compare_unsigned(|a, b| a < b)),
+			(Op::ULessThanEqual, parts) => each(parts, &mut compare_unsigned(|a, b| a <= b)),
+			(Op::UGreaterThan, parts) => each(parts, &mut compare_unsigned(|a, b| a > b)),
+			(Op::UGreaterThanEqual, parts) => each(parts, &mut compare_unsigned(|a, b| a >= b)),
+			(Op::SLessThan, parts) => each(parts, &mut compare_signed(|a, b| a < b)),
+			(Op::SLessThanEqual, parts) => each(parts, &mut compare_signed(|a, b| a <= b)),
+			(Op::SGreaterThan, parts) => each(parts, &mut compare_signed(|a, b| a > b)),
+			(Op::SGreaterThanEqual, parts) => each(parts, &mut compare_signed(|a, b| a >= b)),
+			// ordered comparisons are false if either side is NaN, unordered ones are true
+			(Op::FOrdEqual, parts) => each(parts, &mut compare_floats(|a, b| a == b)),
+			(Op::FUnordNotEqual, parts) => each(parts, &mut compare_floats(|a, b| a != b)),
+			(Op::FOrdLessThan, parts) => each(parts, &mut compare_floats(|a, b| a < b)),
+			(Op::FOrdLessThanEqual, parts) => each(parts, &mut compare_floats(|a, b| a <= b)),
+			(Op::FOrdGreaterThan, parts) => each(parts, &mut compare_floats(|a, b| a > b)),
+			(Op::FOrdGreaterThanEqual, parts) => each(parts, &mut compare_floats(|a, b| a >= b)),
+			(Op::LogicalEqual, parts) => each(parts, &mut logical(|a, b| a == b)),
+			(Op::LogicalNotEqual, parts) => each(parts, &mut logical(|a, b| a != b)),
+			(Op::LogicalAnd, parts) => each(parts, &mut logical(|a, b| a && b)),
+			(Op::LogicalOr, parts) => each(parts, &mut logical(|a, b| a || b)),
+			(Op::VectorTimesScalar | Op::MatrixTimesScalar, parts) => each(parts, &mut floats(|a, b| a * b)),
+			(Op::MatrixTimesVector, [m, v]) => Ok(from_floats(matrix_times_vector(&columns(m), &to_floats(v)))),
+			(Op::VectorTimesMatrix, [v, m]) => {
+				let v = to_floats(v);
+				Ok(from_floats(columns(m).iter().map(|column| dot(column, &v))))
+			}
+			(Op::MatrixTimesMatrix, [a, b]) => {
+				let a = columns(a);
+				Ok(from_columns(&columns(b).iter().map(|column| matrix_times_vector(&a, column)).collect::<Vec<_>>()))
+			}
+			(Op::Dot, [a, b]) => Ok(ConstValue::Float(dot(&to_floats(a), &to_floats(b)))),
+			(Op::Transpose, [m]) => {
+				let m = columns(m);
+				Ok(from_columns(&(0..m[0].len()).map(|row| m.iter().map(|column| column[row]).collect()).collect::<Vec<_>>()))
+			}
+			_ => Err(error(YuriSemanticErrorType::Unsupported, format!("`{}` can't be emulated", instruction.disassemble()))),
+		}
+	}
+
+	fn ext_inst(&self, values: &HashMap<Word, Value>, instruction: &dr::Instruction, ty: &YuriType) -> Result<ConstValue, YuriSemanticError> {
+		let set = id(instruction, 0)?;
+		let glsl = self.module.ext_inst_imports.iter()
+			.any(|i| i.result_id == Some(set) && matches!(i.operands.first(), Some(Operand::LiteralString(name)) if name == "GLSL.std.450"));
+		let op = match instruction.operands.get(1) {
+			Some(Operand::LiteralExtInstInteger(op)) if glsl => GLOp::from_u32(*op),
+			_ => None,
+		};
+		let Some(op) = op else {
+			return Err(error(YuriSemanticErrorType::Unsupported, format!("`{}` can't be emulated", instruction.disassemble())));
+		};
+		// integers are read with the signedness of the instruction, not of their type
+		let arguments = instruction.operands[2..].iter()
+			.filter_map(Operand::id_ref_any)
+			.map(|id| Ok(retype(self.data(values, id)?, glsl_integers(op))))
+			.collect::<Result<Vec<_>, YuriSemanticError>>()?;
+		if let Some(reason) = glsl_undefined(op, &arguments) {
+			return Err(undefined(reason));
+		}
+		let result = glsl_operation(op, &arguments)
+			.ok_or_else(|| error(YuriSemanticErrorType::Unsupported, format!("`{}` can't be emulated", instruction.disassemble())))?;
+		Ok(retype(result, number_type(ty)))
+	}
+}
+
+/// Whether two results are the same, with NaN being the same as NaN.
+fn same(a: &ConstValue, b: &ConstValue) -> bool {
+	match (a, b) {
+		(ConstValue::Float(a), ConstValue::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+		(ConstValue::Composite(a), ConstValue::Composite(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b)),
+		(a, b) => a == b,
+	}
+}
+
+/// Whether an error just means the arguments ran into something undefined (or a failed assertion),
+/// so there's nothing to compare.
+fn is_undefined(err: &YuriSemanticError) -> bool {
+	matches!(err.error_type, YuriSemanticErrorType::Runtime | YuriSemanticErrorType::AssertionFailed)
+}
+
+/// Replaces the locals bound in blocks with their values, so any part of the expression
+/// can be worked out from nothing but the function's arguments.
+fn inline_locals(expr: &TypedExpression, values: &HashMap<LocalId, TypedExpression>) -> TypedExpression {
+	match &expr.kind {
+		TypedExpressionKind::Local(id) if values.contains_key(id) => values[id].clone(),
+		TypedExpressionKind::Block { bindings, tail } => {
+			let mut values = values.clone();
+			for (id, value) in bindings {
+				let value = inline_locals(value, &values);
+				values.insert(*id, value);
+			}
+			inline_locals(tail, &values)
+		}
+		_ => {
+			let mut expr = expr.clone();
+			expr.for_each_child_mut(|child| *child = inline_locals(child, values));
+			expr
+		}
+	}
+}
+
+/// Whether every local the expression uses is either one of these or bound inside of it.
+fn closed(expr: &TypedExpression, locals: &[LocalId]) -> bool {
+	let with = |more: &[LocalId]| [locals, more].concat();
+	match &expr.kind {
+		TypedExpressionKind::Local(id) => locals.contains(id),
+		TypedExpressionKind::Loop { index, block, .. } => closed(block, &with(&[*index])),
+		TypedExpressionKind::Fold { accumulator, initial, item, items, block } => {
+			closed(initial, locals) && closed(items, locals) && closed(block, &with(&[*accumulator, *item]))
+		}
+		TypedExpressionKind::Map { item, items, block } => closed(items, locals) && closed(block, &with(&[*item])),
+		TypedExpressionKind::Block { bindings, tail } => {
+			let mut locals = locals.to_vec();
+			for (id, value) in bindings {
+				if !closed(value, &locals) {
+					return false;
+				}
+				locals.push(*id);
+			}
+			closed(tail, &locals)
+		}
+		_ => {
+			let mut all = true;
+			expr.for_each_child(|child| all = all && closed(child, locals));
+			all
+		}
+	}
+}
+
+fn size(expr: &TypedExpression) -> usize {
+	let mut total = 1;
+	expr.for_each_child(|child| total += size(child));
+	total
+}
+
+/// The parts of the expression that could be their own function: the ones that only use the arguments and have a value.
+fn candidates<'e>(expr: &'e TypedExpression, arguments: &[LocalId], found: &mut Vec<&'e TypedExpression>) {
+	let has_value = !matches!(expr.expression_type, YuriType::Unit | YuriType::Sampler(_));
+	if has_value && closed(expr, arguments) {
+		found.push(expr);
+	}
+	expr.for_each_child(|child| candidates(child, arguments, found));
+}
+
+/// A copy of the program with one more function, which works out just the expression
+/// from the same arguments as the function it came from.
+fn isolate(program: &TypedProgram, id: FunctionId, expression: &TypedExpression) -> (TypedProgram, FunctionId) {
+	let mut program = program.clone();
+	let function = &program.functions[id];
+	let isolated = TypedFunction {
+		// not a name anyone could write, so it can't clash with anything
+		name: "<expression>".to_string(),
+		return_type: expression.expression_type.clone(),
+		body: expression.clone(),
+		stage: None,
+		frag_origin: None,
+		annotations: Vec::new(),
+		location: expression.location.clone(),
+		..function.clone()
+	};
+	program.functions.push(isolated);
+	let isolated = program.functions.len() - 1;
+	(program, isolated)
+}
+
+/// Turns a function into SPIR-V. It's always [compile_function], except when the tests need a broken compiler.
+type Compiler<'a> = &'a dyn Fn(&TypedProgram, FunctionId) -> Result<Vec<u32>, YuriSemanticError>;
+
+/// What the interpreter and the SPIR-V came up with, or nothing when either ran into something undefined.
+type Outcome = Option<(ConstValue, ConstValue)>;
+
+struct Comparison<'a> {
+	compile: Compiler<'a>,
+	/// Whether the program gets folded before it's compiled.
+	folding: bool,
+}
+
+impl Comparison<'_> {
+	/// Runs the function both ways for each set of arguments.
+	fn run(&self, program: &TypedProgram, id: FunctionId, inputs: &[Vec<ConstValue>]) -> Result<Vec<Outcome>, YuriSemanticError> {
+		let mut interpreter = Interpreter::new(program);
+		let mut compiled = program.clone();
+		if self.folding {
+			fold::fold_program(&mut compiled);
+		}
+		let mut emulator = Emulator::load(&(self.compile)(&compiled, id)?)?;
+		let name = &program.functions[id].name;
+		inputs.iter()
+			.map(|arguments| {
+				let expected = match interpreter.call_id(id, arguments) {
+					Err(err) if is_undefined(&err) => return Ok(None),
+					result => result?,
+				};
+				let actual = match emulator.call(name, arguments) {
+					Err(err) if is_undefined(&err) => return Ok(None),
+					result => result?,
+				};
+				Ok(Some((expected, actual)))
+			})
+			.collect()
+	}
+
+	fn compare(&self, program: &TypedProgram, id: FunctionId, inputs: &[Vec<ConstValue>]) -> Result<usize, YuriSemanticError> {
+		let results = self.run(program, id, inputs)?;
+		for (arguments, result) in inputs.iter().zip(&results) {
+			if let Some((expected, actual)) = result
+				&& !same(expected, actual)
+			{
+				let body = &program.functions[id].body;
+				return Err(self.narrow(program, id, arguments)?
+					.unwrap_or_else(|| self.report(program, id, body, arguments, expected, actual)));
+			}
+		}
+		Ok(results.iter().flatten().count())
+	}
+
+	/// Finds the smallest expression in the function that comes out differently with these arguments.
+	/// If that's a call, the difference is somewhere in the function being called, so it keeps looking in there.
+	fn narrow(&self, program: &TypedProgram, id: FunctionId, arguments: &[ConstValue]) -> Result<Option<YuriSemanticError>, YuriSemanticError> {
+		let function = &program.functions[id];
+		let body = inline_locals(&function.body, &HashMap::new());
+		let mut found = Vec::new();
+		candidates(&body, &function.arguments, &mut found);
+		found.sort_by_key(|expr| (size(expr), expr.location.start));
+		for candidate in found {
+			let (isolated, isolated_id) = isolate(program, id, candidate);
+			let Some((expected, actual)) = self.run(&isolated, isolated_id, &[arguments.to_vec()])?.remove(0) else {
+				continue;
+			};
+			if same(&expected, &actual) {
+				continue;
+			}
+			if let TypedExpressionKind::Call { function: callee, arguments: call_arguments } = &candidate.kind {
+				// the arguments are smaller, so they already came out the same both ways
+				let values: Option<Vec<ConstValue>> = call_arguments.iter()
+					.map(|argument| {
+						let (isolated, isolated_id) = isolate(program, id, argument);
+						Interpreter::new(&isolated).call_id(isolated_id, arguments).ok()
+					})
+					.collect();
+				if let Some(values) = values
+					&& let Some(narrowed) = self.narrow(program, *callee, &values)?
+				{
+					return Ok(Some(narrowed));
+				}
+			}
+			return Ok(Some(self.report(program, id, candidate, arguments, &expected, &actual)));
+		}
+		Ok(None)
+	}
+
+	fn report(
+		&self,
+		program: &TypedProgram,
+		id: FunctionId,
+		expression: &TypedExpression,
+		arguments: &[ConstValue],
+		expected: &ConstValue,
+		actual: &ConstValue,
+	) -> YuriSemanticError {
+		let function = &program.functions[id];
+		let given: Vec<String> = function.arguments.iter()
+			.zip(arguments)
+			.map(|(local, value)| format!("{} = {value}", function.locals[*local].name))
+			.collect();
+		let given = if given.is_empty() { String::new() } else { format!(" with {}", given.join(", ")) };
+		let folded = if self.folding { " and folded" } else { "" };
+		YuriSemanticError {
+			error_type: YuriSemanticErrorType::Internal,
+			description: Some(format!("% comes out to {expected} when interpreted, but {actual} when compiled{folded}{given}")),
+			markers: vec![expression.location.clone()],
+		}
+	}
+}
+
+/// Runs a function (by its fully-qualified name) through the [Interpreter] and through the SPIR-V it compiles to,
+/// both with and without folding, for each set of arguments. Arguments that run into something undefined either way
+/// are skipped, since there's nothing to compare. Gives back how many sets of arguments could be compared.
+///
+/// If the results ever differ, the error points at the smallest expression that still comes out differently,
+/// and says what it came out to and with which arguments.
+pub fn compare_function(program: &TypedProgram, name: &str, inputs: &[Vec<ConstValue>]) -> Result<usize, YuriSemanticError> {
+	let Some(id) = program.functions.iter().position(|f| f.name == name) else {
+		return Err(error(YuriSemanticErrorType::Runtime, format!("There's no function named `{name}`")));
+	};
+	let compile = |program: &TypedProgram, id: FunctionId| compile_function(program, id, Target::default());
+	let unfolded = Comparison { compile: &compile, folding: false }.compare(program, id, inputs)?;
+	let folded = Comparison { compile: &compile, folding: true }.compare(program, id, inputs)?;
+	Ok(unfolded.min(folded))
+}
+
+#[cfg(test)]
+mod test {
+	use rspirv::spirv::Op;
+	use crate::builtin::BuiltinInput;
+	use crate::check::{check_source, TypedProgram};
+	use crate::compile::compile_function;
+	use crate::consteval::ConstValue;
+	use crate::emulate::{compare_function, Comparison, Emulator};
+	use crate::error::YuriSemanticErrorType;
+	use crate::random::{random_value, Rng};
+	use crate::target::Target;
+
+	fn emulator(program: &TypedProgram, name: &str) -> Emulator {
+		let id = program.functions.iter().position(|f| f.name == name).unwrap();
+		Emulator::load(&compile_function(program, id, Target::default()).unwrap()).unwrap()
+	}
+
+	fn random_inputs(rng: &mut Rng, program: &TypedProgram, name: &str, count: usize) -> Vec<Vec<ConstValue>> {
+		let function = program.functions.iter().find(|f| f.name == name).unwrap();
+		(0..count)
+			.map(|_| function.arguments.iter().map(|a| random_value(rng, &function.locals[*a].local_type)).collect())
+			.collect()
+	}
+
+	#[test]
+	fn emulate_functions() {
+		let program = check_source("
+			prop scale: f;
+			prop offsets: f2[2];
+			@spec(0) let STEPS: u = 4;
+			let HALF = STEPS / 2;
+			fn sum(): u { fold total = 0, k: STEPS { total + k } + HALF }
+			fn squares(n: u): u { (loop k: 4 { k * k })[n] }
+			fn spread(x: f): f2 { offsets[1] * x * scale }
+			fn split(x: i, y: i): i2 { i2(x / y, x % y) }
+			fn turn(m: m2, v: f2): f2 { m * v }
+			@frag fn main(): f4 { f4(@frag.coord, spread(1.0)) }
+		").unwrap();
+		let mut sum = emulator(&program, "sum");
+		assert_eq!(sum.call("sum", &[]).unwrap(), ConstValue::Unsigned(8));
+		let mut squares = emulator(&program, "squares");
+		assert_eq!(squares.call("squares", &[3u32.into()]).unwrap(), ConstValue::Unsigned(9));
+		// indexing out of bounds is undefined, rather than whatever happens to be next to the array
+		assert_eq!(squares.call("squares", &[4u32.into()]).unwrap_err().error_type(), YuriSemanticErrorType::Runtime);
+
+		let mut spread = emulator(&program, "spread");
+		spread.set_prop("scale", 2.0).unwrap();
+		spread.set_prop("offsets", [[1.0, 2.0], [3.0, 4.0]]).unwrap();
+		assert!(spread.set_prop("scale", 1u32).is_err());
+		assert_eq!(spread.call("spread", &[0.5.into()]).unwrap(), ConstValue::from([3.0, 4.0]));
+
+		let mut split = emulator(&program, "split");
+		assert_eq!(split.call("split", &[(-7).into(), 2.into()]).unwrap(), ConstValue::from([-3, -1]));
+		// the things the GPU doesn't have to get right
+		let mut undefined = |x: i32, y: i32| split.call("split", &[x.into(), y.into()]).unwrap_err().error_type();
+		assert_eq!(undefined(1, 0), YuriSemanticErrorType::Runtime);
+		assert_eq!(undefined(i32::MIN, -1), YuriSemanticErrorType::Runtime);
+		assert!(split.call("split", &[1.into()]).is_err());
+		assert!(split.call("split", &[1.0.into(), 2.0.into()]).is_err());
+
+		let mut turn = emulator(&program, "turn");
+		assert_eq!(turn.call("turn", &[[[0.0, 1.0], [-1.0, 0.0]].into(), [1.0, 2.0].into()]).unwrap(), ConstValue::from([-2.0, 1.0]));
+
+		let mut main = emulator(&program, "main");
+		main.set_input(BuiltinInput::FragCoord, [5.0, 6.0]).unwrap();
+		main.set_prop("scale", 1.0).unwrap();
+		main.set_prop("offsets", [[0.0, 0.0], [7.0, 8.0]]).unwrap();
+		assert_eq!(main.call("main", &[]).unwrap(), ConstValue::from([5.0, 6.0, 7.0, 8.0]));
+	}
+
+	#[test]
+	fn glsl_instructions() {
+		let program = check_source("
+			fn rounding(x: f4): f4 { round(x) + fract(-x) * 10.0 }
+			fn limits(x: f3, y: i): f4 { f4(clamp(x.xy, f2(0.0), f2(1.0)), smoothstep(0.0, 2.0, x.z), f(sign(y) * max(y, -3))) }
+			fn geometry(v: f3, w: f3): f4 { f4(cross(v, w), length(v) + distance(v, w)) }
+			fn invert(m: m2): f2 { inverse(m) * f2(1.0, 1.0) * determinant(m) }
+		").unwrap();
+		// worked out by hand from the GLSL.std.450 definitions, rather than with anything the emulator's checked against
+		let mut rounding = emulator(&program, "rounding");
+		assert_eq!(rounding.call("rounding", &[[0.5, 1.5, -2.5, 1.25].into()]).unwrap(), ConstValue::from([5.0, 7.0, 3.0, 8.5]));
+		let mut limits = emulator(&program, "limits");
+		assert_eq!(limits.call("limits", &[[-1.0, 0.5, 0.5].into(), (-7).into()]).unwrap(), ConstValue::from([0.0, 0.5, 0.15625, 3.0]));
+		let mut geometry = emulator(&program, "geometry");
+		assert_eq!(geometry.call("geometry", &[[3.0, 0.0, 4.0].into(), [3.0, 1.0, 4.0].into()]).unwrap(), ConstValue::from([-4.0, 0.0, 3.0, 6.0]));
+		let mut invert = emulator(&program, "invert");
+		assert_eq!(invert.call("invert", &[[[2.0, 0.0], [1.0, 4.0]].into()]).unwrap(), ConstValue::from([3.0, 2.0]));
+	}
+
+	#[test]
+	fn compare_random_inputs() {
+		let program = check_source("
+			fn shade(a: f, b: f, t: f): f { mix(a, b, clamp(t, 0.0, 1.0)) * 2.0 - a / (abs(b) + 1.0) + step(a, b) }
+			fn twiddle(x: u, y: u): u { let s = y % 32; ((x << s) ^ (x >> 3)) + x * y - (x & y) / (y | 1) }
+			fn signs(x: i, y: i): i { if x < y { x % (y | 1) } else { -x / 3 + (x >> 2) + max(x, y) - sign(y) } }
+			fn vectors(v: f3, w: f3, s: f): f3 { let r = floor(v + w); cross(v, w) * s + r.zyx - reflect(v, w) / (dot(w, w) + 1.0) }
+			fn matrices(m: m3, v: f3): f3 { transpose(m) * v + v * m + (m * m) * v * 0.5 - (m / 2.0) * v }
+			fn loops(n: u, x: f): f {
+				let scaled = map y: (loop j: 4 { f(j) * x }) { y + 1.0 };
+				let total = fold total = x, k: 6 { total * 0.5 + f(k) };
+				total + scaled[n % 4]
+			}
+			fn choose(a: bool, b: bool, x: i): i { if a and !b { x * 2 } else if a xor b { i(f(x) * 0.5) } else if u(x) > 10 { 1 } else { 0 } }
+			fn converts(x: f, y: i): u { u(x) + u(y) + u(x > 0.0) + u(y != 0) }
+			fn nested(x: f, y: f): f { shade(x, y, 0.5) + length(vectors(f3(x, y, 1.0), f3(y, x, 2.0), x)) }
+		").unwrap();
+		let names = ["shade", "twiddle", "signs", "vectors", "matrices", "loops", "choose", "converts", "nested"];
+		// seeded, so a failure comes back the same way every time
+		let mut rng = Rng::new(0);
+		for name in names {
+			let inputs = random_inputs(&mut rng, &program, name, 200);
+			let compared = compare_function(&program, name, &inputs)
+				.unwrap_or_else(|err| panic!("{}", err.with_sources(&program.sources)));
+			// plenty of them shouldn't run into anything undefined
+			assert!(compared > 50, "only {compared} of the inputs to `{name}` could be compared");
+		}
+	}
+
+	#[test]
+	fn narrow_miscompiles() {
+		let program = check_source("
+			fn scaled(x: i, y: i): i { let a = x + 1; let b = a * y; b - x }
+			fn outer(x: i): i { scaled(x, 3) + 1 }
+		").unwrap();
+		// a compiler that thinks multiplying is adding
+		let compile = |program: &TypedProgram, id| {
+			let mut words = compile_function(program, id, Target::default())?;
+			let mut i = 5;
+			while i < words.len() {
+				if words[i] & 0xffff == Op::IMul as u32 {
+					words[i] = (words[i] & 0xffff0000) | Op::IAdd as u32;
+				}
+				i += (words[i] >> 16) as usize;
+			}
+			Ok(words)
+		};
+		let comparison = Comparison { compile: &compile, folding: false };
+		let id = |name: &str| program.functions.iter().position(|f| f.name == name).unwrap();
+		let inputs = [vec![ConstValue::Signed(4), ConstValue::Signed(5)]];
+		let err = comparison.compare(&program, id("scaled"), &inputs).unwrap_err();
+		assert_eq!(err.error_type(), YuriSemanticErrorType::Internal);
+		assert_eq!(
+			err.with_sources(&program.sources).to_string().lines().next().unwrap(),
+			"Internal: `a * y` comes out to 25 when interpreted, but 10 when compiled with x = 4, y = 5",
+		);
+		// differences inside of a call get followed into the function being called
+		let err = comparison.compare(&program, id("outer"), &[vec![ConstValue::Signed(4)]]).unwrap_err();
+		assert!(err.with_sources(&program.sources).to_string().starts_with(
+			"Internal: `a * y` comes out to 15 when interpreted, but 8 when compiled with x = 4, y = 3",
+		), "{}", err.with_sources(&program.sources));
+		let folded = Comparison { compile: &compile, folding: true };
+		let err = folded.compare(&program, id("scaled"), &inputs).unwrap_err();
+		assert!(err.description().unwrap().contains("when compiled and folded with x = 4, y = 5"), "{err}");
+		// arguments that don't show the difference don't count
+		assert_eq!(comparison.compare(&program, id("scaled"), &[vec![ConstValue::Signed(1), ConstValue::Signed(2)]]).unwrap(), 1);
+	}
+}
@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::ops::Range;
+use crate::source::SourceMap;
 
 /// Represents a generic error that occurred while trying to compile a Yuri shader.
 #[derive(Eq, PartialEq)]
@@ -36,6 +37,16 @@ impl Error for YuriCompileError {
 	}
 }
 
+impl YuriCompileError {
+	/// Displays the error with the files, lines and columns its locations are at.
+	pub fn with_sources<'a>(&'a self, sources: &'a SourceMap) -> WithSources<'a> {
+		match self {
+			YuriCompileError::Parse(err) => err.with_sources(sources),
+			YuriCompileError::Semantic(err) => err.with_sources(sources),
+		}
+	}
+}
+
 impl From<YuriLexError> for YuriCompileError {
 	fn from(value: YuriLexError) -> Self {
 		Self::Parse(value)
@@ -50,21 +61,60 @@ impl From<YuriSemanticError> for YuriCompileError {
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum YuriSemanticErrorType {
-	UnexpectedToken	
+	UnexpectedToken,
+	UnexpectedEndOfFile,
+	InvalidDeclaration,
+	ModuleNotFound,
+	ImportCycle,
+	DuplicateDeclaration,
+	UnresolvedName,
+	PrivateAccess,
+	TypeMismatch,
+	UnknownMember,
+	NotConstant,
+	Recursion,
+	InvalidEntryPoint,
+	Unsupported,
+	/// Something went wrong running a shader on the CPU, like dividing by zero or a prop without a value.
+	Runtime,
+	/// An `assert`, `assert_eq` or `assert_near` didn't hold, usually in a test.
+	AssertionFailed,
+	Internal,
 }
 
 /// Represents an error that occurred while processing the logical aspects of a Yuri syntax tree.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct YuriSemanticError {
 	pub(crate) error_type: YuriSemanticErrorType,
 	pub(crate) description: Option<String>,
 	pub(crate) markers: Vec<Range<usize>>,
 }
 
+impl YuriSemanticError {
+	pub fn error_type(&self) -> YuriSemanticErrorType {
+		self.error_type
+	}
+
+	/// What went wrong, with a `%` wherever one of the markers goes.
+	pub fn description(&self) -> Option<&str> {
+		self.description.as_deref()
+	}
+
+	/// The locations the error is about, as offsets into the [SourceMap].
+	pub fn markers(&self) -> &[Range<usize>] {
+		&self.markers
+	}
+
+	/// Displays the error with the files, lines and columns its locations are at.
+	pub fn with_sources<'a>(&'a self, sources: &'a SourceMap) -> WithSources<'a> {
+		WithSources { error_type: &self.error_type, description: self.description(), markers: &self.markers, sources: Some(sources) }
+	}
+}
+
 impl Display for YuriSemanticError {
 	fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-		// TODO
-		f.write_fmt(format_args!(""))
+		let plain = WithSources { error_type: &self.error_type, description: self.description(), markers: &self.markers, sources: None };
+		Display::fmt(&plain, f)
 	}
 }
 
@@ -92,13 +142,142 @@ impl YuriLexError {
 	pub fn error_type(&self) -> YuriLexErrorType {
 		self.error_type
 	}
+
+	/// What went wrong, with a `%` wherever one of the markers goes.
+	pub fn description(&self) -> Option<&str> {
+		self.description.as_deref()
+	}
+
+	/// The locations the error is about, as offsets into the [SourceMap].
+	pub fn markers(&self) -> &[Range<usize>] {
+		&self.markers
+	}
+
+	/// Displays the error with the files, lines and columns its locations are at.
+	pub fn with_sources<'a>(&'a self, sources: &'a SourceMap) -> WithSources<'a> {
+		WithSources { error_type: &self.error_type, description: self.description(), markers: &self.markers, sources: Some(sources) }
+	}
 }
 
 impl Display for YuriLexError {
 	fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-		// TODO
-		f.write_fmt(format_args!(""))
+		let plain = WithSources { error_type: &self.error_type, description: self.description(), markers: &self.markers, sources: None };
+		Display::fmt(&plain, f)
+	}
+}
+
+impl Error for YuriLexError {}
+
+/// An error that knows where its markers are, from [YuriCompileError::with_sources].
+/// Without the sources, errors can only display their markers as raw offsets.
+pub struct WithSources<'a> {
+	error_type: &'a dyn Debug,
+	description: Option<&'a str>,
+	markers: &'a [Range<usize>],
+	sources: Option<&'a SourceMap>,
+}
+
+impl WithSources<'_> {
+	/// What goes in place of a `%` in the description.
+	/// Long markers turn into their position, worded to fit whatever comes `before` them,
+	/// since descriptions say "at %", "(%)" and plain "%" alike.
+	fn marker_text(&self, marker: &Range<usize>, before: &str) -> String {
+		let Some(sources) = self.sources else {
+			return format!("`{}..{}`", marker.start, marker.end);
+		};
+		match sources.snippet(marker) {
+			// anything long gets shown underneath anyway
+			Some(snippet) if !snippet.contains('\n') && snippet.chars().count() <= 40 => format!("`{snippet}`"),
+			_ => match sources.position(marker.start) {
+				Some((_, position)) if before.ends_with("at ") => position.to_string(),
+				Some((_, position)) if before.ends_with('(') => format!("at {position}"),
+				Some((_, position)) => format!("(at {position})"),
+				None => format!("`{}..{}`", marker.start, marker.end),
+			},
+		}
+	}
+}
+
+impl Display for WithSources<'_> {
+	fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+		write!(f, "{:?}", self.error_type)?;
+		if let Some(description) = self.description {
+			write!(f, ": ")?;
+			let mut markers = self.markers.iter();
+			let mut pieces = description.split('%');
+			let mut previous = pieces.next().unwrap_or_default();
+			write!(f, "{previous}")?;
+			for piece in pieces {
+				match markers.next() {
+					Some(marker) => write!(f, "{}{piece}", self.marker_text(marker, previous))?,
+					None => write!(f, "%{piece}")?,
+				}
+				previous = piece;
+			}
+		}
+
+		let Some(sources) = self.sources else {
+			return Ok(());
+		};
+		// then every marker, pointed at in its line
+		for marker in self.markers {
+			let Some((file, start)) = sources.position(marker.start) else {
+				continue;
+			};
+			let line = file.line(start.line);
+			let line_number = (start.line + 1).to_string();
+			let gutter = " ".repeat(line_number.len());
+			let before = line[..start.column].chars().count();
+			let end = file.position(marker.end.max(marker.start));
+			let width = if end.line == start.line {
+				line[start.column..end.column].chars().count()
+			} else {
+				line[start.column..].chars().count()
+			};
+			write!(f, "\n{gutter}--> {}:{start}", file.name())?;
+			write!(f, "\n{line_number} | {line}")?;
+			write!(f, "\n{gutter} | {}{}", " ".repeat(before), "^".repeat(width.max(1)))?;
+		}
+		Ok(())
 	}
 }
 
-impl Error for YuriLexError {}
\ No newline at end of file
+#[cfg(test)]
+mod test {
+	use crate::error::YuriCompileError;
+	use crate::source::SourceMap;
+	use crate::YuriShader;
+
+	#[test]
+	fn display_errors() {
+		let mut sources = SourceMap::new();
+		let root = sources.add("shaders/main.yuri", "let a: f = 1.0;\nlet ü: u = a;");
+		let err = YuriShader::compile_sources(&mut sources, root, &Default::default()).err().unwrap();
+		let YuriCompileError::Semantic(semantic) = &err else { panic!("expected a semantic error, got {err:?}") };
+		// markers are byte offsets, and `ü` is two bytes
+		assert_eq!(semantic.markers(), [28..29]);
+
+		assert_eq!(err.with_sources(&sources).to_string(), "\
+TypeMismatch: Expected `u`, but found `f` (`a`)
+ --> shaders/main.yuri:2:13
+2 | let ü: u = a;
+  |            ^");
+		// without the sources there's only the offsets to go on
+		assert_eq!(err.to_string(), "TypeMismatch: Expected `u`, but found `f` (`28..29`)");
+	}
+
+	#[test]
+	fn long_markers() {
+		let mut sources = SourceMap::new();
+		let root = sources.add("main.yuri", "@spec(1) let N: u = 4;\nlet WEIGHTS: f[N] = loop k: N { 1.0 + 2.0 };");
+		let err = YuriShader::compile_sources(&mut sources, root, &Default::default()).err().unwrap();
+		// too long to quote, so it's just the position, and the description already says "at"
+		let text = err.with_sources(&sources).to_string();
+		assert!(text.starts_with("NotConstant: The array length `N` (at 2:1) has to be known at compile time"), "{text}");
+
+		let root = sources.add("other.yuri", "fn g(x: bool): f { if x { 1.0 + 2.0 + 3.0 + 4.0 + 5.0 + 6.0 + 7.0 } }");
+		let err = YuriShader::compile_sources(&mut sources, root, &Default::default()).err().unwrap();
+		let text = err.with_sources(&sources).to_string();
+		assert!(text.starts_with("TypeMismatch: An `if` without an `else` can't give back a value, but this one gives back `f` (at 1:20)\n"), "{text}");
+	}
+}
@@ -0,0 +1,296 @@
+//! Constant folding and algebraic simplification on the typed IR.
+//! Without mutation, anything that doesn't touch a prop or a builtin input can be worked out ahead of time,
+//! so this just hands every such expression to the [ConstEvaluator], then cleans up the leftovers
+//! (like `x * 1.0`) so the generated SPIR-V stays small.
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use crate::check::{FunctionId, GlobalId, LocalId, PropId, TypedExpression, TypedExpressionKind, TypedFunction, TypedProgram, number_type};
+use crate::consteval::{ConstContext, ConstEvaluator, ConstValue};
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::parse::{BinaryOperator, NumberType, UnaryOperator};
+
+struct Folder {
+	/// The value of every global, or nothing if it depends on a specialization constant.
+	globals: Vec<Option<ConstValue>>,
+	global_names: Vec<String>,
+	property_names: Vec<String>,
+	/// The functions as the type checker left them, for evaluating calls.
+	functions: Vec<Rc<TypedFunction>>,
+	/// Locals that turned out to be constant, in the function currently being folded.
+	locals: HashMap<LocalId, ConstValue>,
+}
+
+impl ConstContext for Folder {
+	fn global(&mut self, id: GlobalId, location: &Range<usize>) -> Result<ConstValue, YuriSemanticError> {
+		self.globals[id].clone().ok_or_else(|| YuriSemanticError {
+			error_type: YuriSemanticErrorType::NotConstant,
+			description: Some(format!("`{}` (at %) depends on a specialization constant", self.global_names[id])),
+			markers: vec![location.clone()],
+		})
+	}
+
+	fn function(&mut self, id: FunctionId, _location: &Range<usize>) -> Result<Rc<TypedFunction>, YuriSemanticError> {
+		Ok(self.functions[id].clone())
+	}
+
+	fn property_name(&self, id: PropId) -> String {
+		self.property_names[id].clone()
+	}
+
+	fn specialization(&mut self, id: GlobalId, _location: &Range<usize>) -> Result<Option<String>, YuriSemanticError> {
+		Ok(self.globals[id].is_none().then(|| self.global_names[id].clone()))
+	}
+}
+
+impl Folder {
+	/// Whether the expression can be evaluated on its own, meaning it doesn't depend on anything
+	/// that's only known while the shader runs (or on a local from outside it).
+	fn is_closed(&self, expr: &TypedExpression, bound: &mut Vec<LocalId>) -> bool {
+		match &expr.kind {
+			TypedExpressionKind::Property(_) | TypedExpressionKind::BuiltinInput(_) => return false,
+			TypedExpressionKind::Global(id) => return self.globals[*id].is_some(),
+			TypedExpressionKind::Local(id) => return bound.contains(id) || self.locals.contains_key(id),
+			TypedExpressionKind::Block { bindings, .. } => bound.extend(bindings.iter().map(|(id, _)| *id)),
+			TypedExpressionKind::Loop { index, .. } => bound.push(*index),
+			TypedExpressionKind::Fold { accumulator, item, .. } => bound.extend([*accumulator, *item]),
+			TypedExpressionKind::Map { item, .. } => bound.push(*item),
+			_ => {}
+		}
+		let mut closed = true;
+		expr.for_each_child(|child| closed = closed && self.is_closed(child, bound));
+		closed
+	}
+
+	fn fold(&mut self, expr: &mut TypedExpression) {
+		match &mut expr.kind {
+			TypedExpressionKind::Constant(_) => return,
+			TypedExpressionKind::Local(id) => {
+				if let Some(value) = self.locals.get(id) {
+					expr.kind = TypedExpressionKind::Constant(value.clone());
+				}
+				return;
+			}
+			TypedExpressionKind::Global(id) => {
+				if let Some(value) = &self.globals[*id] {
+					expr.kind = TypedExpressionKind::Constant(value.clone());
+				}
+				return;
+			}
+			TypedExpressionKind::Block { bindings, tail } => {
+				// constant bindings get substituted wherever they're used, so they can go
+				for (id, value) in bindings.iter_mut() {
+					self.fold(value);
+					if let TypedExpressionKind::Constant(constant) = &value.kind {
+						self.locals.insert(*id, constant.clone());
+					}
+				}
+				bindings.retain(|(_, value)| !matches!(value.kind, TypedExpressionKind::Constant(_)));
+				self.fold(tail);
+				if bindings.is_empty() {
+					let location = tail.location.clone();
+					*expr = mem::replace(tail.as_mut(), TypedExpression::unit(location));
+				}
+				return;
+			}
+			TypedExpressionKind::If { condition, block, block_else } => {
+				self.fold(condition);
+				if let TypedExpressionKind::Constant(ConstValue::Bool(condition)) = condition.kind {
+					let taken = if condition {
+						Some(mem::replace(block.as_mut(), TypedExpression::unit(0..0)))
+					} else {
+						block_else.take().map(|block_else| *block_else)
+					};
+					*expr = taken.unwrap_or_else(|| TypedExpression::unit(expr.location.clone()));
+					self.fold(expr);
+					return;
+				}
+			}
+			_ => {}
+		}
+		expr.for_each_child_mut(|child| self.fold(child));
+		// whatever can't be evaluated (like a sample, or a division by zero) is left for the GPU to deal with
+		if self.is_closed(expr, &mut Vec::new())
+			&& let Ok(value) = ConstEvaluator::new(self, "The expression", 0).evaluate(expr)
+		{
+			expr.kind = TypedExpressionKind::Constant(value);
+			return;
+		}
+		simplify(expr);
+	}
+}
+
+/// Whether the expression is a constant with every component equal to the given number.
+/// Floats have to match bit for bit, so `-0.0` isn't zero.
+fn is_number(expr: &TypedExpression, number: i32) -> bool {
+	fn all(value: &ConstValue, number: i32) -> bool {
+		match value {
+			ConstValue::Float(f) => f.to_bits() == (number as f32).to_bits(),
+			ConstValue::Signed(i) => *i == number,
+			ConstValue::Unsigned(u) => *u as i64 == number as i64,
+			ConstValue::Bool(_) => false,
+			ConstValue::Composite(components) => !components.is_empty() && components.iter().all(|c| all(c, number)),
+		}
+	}
+	matches!(&expr.kind, TypedExpressionKind::Constant(value) if all(value, number))
+}
+
+fn is_bool(expr: &TypedExpression, b: bool) -> bool {
+	matches!(expr.kind, TypedExpressionKind::Constant(ConstValue::Bool(value)) if value == b)
+}
+
+/// Gets rid of operations that don't do anything, like `x * 1.0` or `x + 0`.
+/// The other side has to have the same type as the result, so `1.0 * v` works but `v * 1.0` on a scalar doesn't.
+fn simplify(expr: &mut TypedExpression) {
+	use BinaryOperator::*;
+	let replacement = match &mut expr.kind {
+		TypedExpressionKind::Binary { operator, lhs, rhs } => {
+			let keep_lhs = lhs.expression_type == expr.expression_type;
+			let keep_rhs = rhs.expression_type == expr.expression_type;
+			// `-0.0 + 0.0` is `0.0`, so adding zero only does nothing to integers (subtracting it is fine)
+			let float = number_type(&expr.expression_type) == Some(NumberType::Float);
+			let lhs_identity = match operator {
+				Plus if float => false,
+				Plus | BitOr | BitXor => is_number(lhs, 0),
+				Times => is_number(lhs, 1),
+				And => is_bool(lhs, true),
+				Or => is_bool(lhs, false),
+				_ => false,
+			};
+			let rhs_identity = match operator {
+				Plus if float => false,
+				Plus | Minus | BitOr | BitXor | ShiftLeft | ShiftRight => is_number(rhs, 0),
+				Times | Divided | Exponent => is_number(rhs, 1),
+				And => is_bool(rhs, true),
+				Or => is_bool(rhs, false),
+				_ => false,
+			};
+			// there's no mutation, so throwing away the other side is fine
+			let absorbed = match operator {
+				And => is_bool(lhs, false) || is_bool(rhs, false),
+				Or => is_bool(lhs, true) || is_bool(rhs, true),
+				_ => false,
+			};
+			if absorbed {
+				Some(TypedExpression::new(
+					TypedExpressionKind::Constant(ConstValue::Bool(*operator == Or)),
+					expr.expression_type.clone(),
+					expr.location.clone(),
+				))
+			} else if rhs_identity && keep_lhs {
+				Some(mem::replace(lhs.as_mut(), TypedExpression::unit(0..0)))
+			} else if lhs_identity && keep_rhs {
+				Some(mem::replace(rhs.as_mut(), TypedExpression::unit(0..0)))
+			} else {
+				None
+			}
+		}
+		// -(-x) and !(!x)
+		TypedExpressionKind::Unary { operator, operand } => match &mut operand.kind {
+			TypedExpressionKind::Unary { operator: inner, operand: inner_operand }
+				if inner == operator && matches!(operator, UnaryOperator::Negate | UnaryOperator::Not) =>
+			{
+				Some(mem::replace(inner_operand.as_mut(), TypedExpression::unit(0..0)))
+			}
+			_ => None,
+		},
+		_ => None,
+	};
+	if let Some(replacement) = replacement {
+		*expr = replacement;
+	}
+}
+
+/// Folds every function in the program, along with the globals that depend on specialization constants
+/// (the rest are constants already).
+pub fn fold_program(program: &mut TypedProgram) {
+	let mut folder = Folder {
+		globals: program.globals.iter()
+			.map(|global| global.specialization.is_none().then(|| global.constant.clone()))
+			.collect(),
+		global_names: program.globals.iter().map(|global| global.name.clone()).collect(),
+		property_names: program.properties.iter().map(|property| property.name.clone()).collect(),
+		functions: program.functions.iter().cloned().map(Rc::new).collect(),
+		locals: HashMap::new(),
+	};
+	for global in &mut program.globals {
+		if global.specialization.is_some() && global.spec_id.is_none() {
+			folder.locals.clear();
+			folder.fold(&mut global.value);
+		}
+	}
+	for function in &mut program.functions {
+		folder.locals.clear();
+		folder.fold(&mut function.body);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::check::{check_source, TypedExpressionKind, TypedProgram};
+	use crate::consteval::ConstValue;
+	use crate::fold::fold_program;
+
+	fn folded(source: &str) -> TypedProgram {
+		let mut program = check_source(source).unwrap();
+		fold_program(&mut program);
+		program
+	}
+
+	fn body(source: &str) -> TypedExpressionKind {
+		folded(source).functions.last().unwrap().body.kind.clone()
+	}
+
+	fn constant(values: &[f32]) -> TypedExpressionKind {
+		TypedExpressionKind::Constant(ConstValue::Composite(values.iter().map(|v| ConstValue::Float(*v)).collect()))
+	}
+
+	#[test]
+	fn fold_constants() {
+		assert_eq!(body("fn main(): f2 { f2(1.0, 2.0) * 2.0 }"), constant(&[2.0, 4.0]));
+		assert_eq!(body("fn main(): f2 { let v = f4(1.0, 2.0, 3.0, 4.0); v.wy }"), constant(&[4.0, 2.0]));
+		assert_eq!(body("fn main(): f { sqrt(16.0) + max(1.0, 2.0) }"), TypedExpressionKind::Constant(ConstValue::Float(6.0)));
+		// globals and constant locals get substituted, and the bindings go away
+		assert_eq!(body("let G = 3.0; fn main(): f2 { let a = G * 2.0; f2(a, a + 1.0) }"), constant(&[6.0, 7.0]));
+		assert_eq!(body("fn main(): f { if 1 < 2 { 1.0 } else { 2.0 } }"), TypedExpressionKind::Constant(ConstValue::Float(1.0)));
+		assert_eq!(body("fn twice(x: f): f { x * 2.0 } fn main(): f { twice(1.5) }"), TypedExpressionKind::Constant(ConstValue::Float(3.0)));
+		// f32 all the way through, like on the GPU
+		assert_eq!(body("fn main(): f { 16777216.0 + 1.0 }"), TypedExpressionKind::Constant(ConstValue::Float(16777216.0)));
+	}
+
+	#[test]
+	fn leave_runtime_values() {
+		assert!(matches!(body("prop t: f; fn main(): f { t * 2.0 }"), TypedExpressionKind::Binary { .. }));
+		// dividing by zero is the GPU's problem
+		assert!(matches!(body("fn main(): i { 1 / 0 }"), TypedExpressionKind::Binary { .. }));
+		// the binding stays, but the constant parts of it get folded
+		match body("prop t: f; fn main(): f { let a = t + (1.0 + 2.0); a * a }") {
+			TypedExpressionKind::Block { bindings, .. } => {
+				let TypedExpressionKind::Binary { rhs, .. } = &bindings[0].1.kind else { panic!() };
+				assert_eq!(rhs.kind, TypedExpressionKind::Constant(ConstValue::Float(3.0)));
+			}
+			other => panic!("expected a block, got {other:?}"),
+		}
+	}
+
+	#[test]
+	fn simplify_identities() {
+		assert!(matches!(body("fn main(x: f): f { x * 1.0 }"), TypedExpressionKind::Local(0)));
+		assert!(matches!(body("fn main(x: i): i { 0 + x }"), TypedExpressionKind::Local(0)));
+		assert!(matches!(body("fn main(x: f): f { x - 0.0 }"), TypedExpressionKind::Local(0)));
+		// `x` could be `-0.0`, which comes out as `0.0`
+		assert!(matches!(body("fn main(x: f): f { 0.0 + x }"), TypedExpressionKind::Binary { .. }));
+		assert!(matches!(body("fn main(x: f2): f2 { x + f2(0.0) }"), TypedExpressionKind::Binary { .. }));
+		assert!(matches!(body("fn main(x: f): f { x - -0.0 }"), TypedExpressionKind::Binary { .. }));
+		assert!(matches!(body("fn main(x: f3): f3 { x * f3(1.0) - f3(0.0) }"), TypedExpressionKind::Local(0)));
+		assert!(matches!(body("fn main(x: u): u { (x | 0) << 0 }"), TypedExpressionKind::Local(0)));
+		assert!(matches!(body("fn main(x: bool): bool { true and !!x }"), TypedExpressionKind::Local(0)));
+		assert!(matches!(body("fn main(x: f): f { -(-x) }"), TypedExpressionKind::Local(0)));
+		assert_eq!(body("fn main(x: bool): bool { x or true }"), TypedExpressionKind::Constant(ConstValue::Bool(true)));
+		// the scalar would have to become a vector, so this one stays
+		assert!(matches!(body("fn main(x: f): f3 { x * f3(1.0) }"), TypedExpressionKind::Binary { .. }));
+		// not every component is zero
+		assert!(matches!(body("fn main(x: f2): f2 { x + f2(0.0, 1.0) }"), TypedExpressionKind::Binary { .. }));
+	}
+}
@@ -0,0 +1,308 @@
+//! The source formatter behind `yuri fmt`.
+//! It goes over the tokens of the [SyntaxTree] and decides all of the whitespace itself,
+//! so the tokens (and so the AST) stay the same, and comments stay next to whatever they were next to.
+//! The one thing that moves is `export`, which always goes after a declaration's annotations.
+//!
+//! The style is
+//! - tabs for indentation, one level per `{`, and one more for a line that a comment broke in the middle of something
+//! - one statement or declaration per line, and annotations on their own lines above declarations
+//! - spaces between tokens, except inside brackets, before `,`, `;` and `:`, after unary operators, and in calls and indexing
+//! - blank lines are kept, but never more than one in a row, and never at the start or end of a block
+use crate::cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxTree};
+use crate::error::YuriCompileError;
+use crate::lex::{Keyword, LosslessToken, Trivia, TriviaKind, YuriTokenType};
+use crate::parse::YuriType;
+
+/// Formats a whole file. It has to parse, since the formatter goes by the syntax tree.
+pub fn format_source(input: &str) -> Result<String, YuriCompileError> {
+	Ok(format_tree(&SyntaxTree::parse(input)?))
+}
+
+pub fn format_tree(tree: &SyntaxTree) -> String {
+	let mut items = Vec::new();
+	flatten(&tree.root, SyntaxKind::Root, &mut items);
+	let mut printer = Printer::new();
+	for (i, item) in items.iter().enumerate() {
+		let previous = i.checked_sub(1).map(|i| &items[i]);
+		// only a comment can break the line somewhere it wouldn't have been broken anyway
+		printer.continuation = previous.is_some_and(|previous| !breaks_between(previous, item));
+		printer.leading(item.leading);
+		printer.token(previous, item);
+		printer.trailing(&item.token.trailing);
+		if let Some(next) = items.get(i + 1) && breaks_between(item, next) {
+			printer.newline();
+		}
+	}
+	printer.leading(&tree.end);
+	printer.finish()
+}
+
+/// A token, and what it needs to know about where it is in the tree.
+struct Item<'t, 'a> {
+	token: &'t LosslessToken<'a>,
+	/// The token's leading trivia, unless it's been moved.
+	leading: &'t [Trivia<'a>],
+	/// The operator of a unary expression, which doesn't get a space after it.
+	unary: bool,
+	/// The end of an annotation on a declaration, which goes on its own line.
+	ends_annotation: bool,
+}
+
+impl Item<'_, '_> {
+	fn token_type(&self) -> &YuriTokenType<'_> {
+		&self.token.token.token_type
+	}
+}
+
+fn is_declaration(kind: SyntaxKind) -> bool {
+	matches!(kind, SyntaxKind::Import | SyntaxKind::Property | SyntaxKind::Variable | SyntaxKind::Function | SyntaxKind::Module)
+}
+
+fn flatten<'t, 'a>(node: &'t SyntaxNode<'a>, parent: SyntaxKind, items: &mut Vec<Item<'t, 'a>>) {
+	let mut export = None;
+	let mut seen_operator = false;
+	for child in &node.children {
+		match child {
+			SyntaxElement::Node(child) => flatten(child, node.kind, items),
+			SyntaxElement::Token(token) => {
+				let token_type = &token.token.token_type;
+				// the first operator right in a unary expression is the unary one, anything else is in parentheses or deeper
+				let unary = node.kind == SyntaxKind::Unary && !seen_operator && matches!(token_type, YuriTokenType::Operator(_));
+				seen_operator |= unary;
+				if is_declaration(node.kind) && *token_type == YuriTokenType::Keyword(Keyword::Export) {
+					export = Some(items.len());
+				}
+				items.push(Item { token, leading: &token.leading, unary, ends_annotation: false });
+			}
+		}
+	}
+	if node.kind == SyntaxKind::Annotation && is_declaration(parent) && let Some(last) = items.last_mut() {
+		last.ends_annotation = true;
+	}
+	// `export @vert fn` becomes `@vert export fn`
+	if let Some(export) = export {
+		let annotations_end = items[export + 1..].iter()
+			.rposition(|item| item.ends_annotation)
+			.map(|i| export + 1 + i);
+		if let Some(annotations_end) = annotations_end {
+			let mut item = items.remove(export);
+			// whatever was before the `export` is still before the declaration
+			std::mem::swap(&mut item.leading, &mut items[export].leading);
+			items.insert(annotations_end, item);
+		}
+	}
+}
+
+fn is_type_keyword(token_type: &YuriTokenType) -> bool {
+	matches!(token_type, YuriTokenType::Keyword(kw) if YuriType::from_keyword(*kw).is_some())
+}
+
+/// Whether there's a space between two tokens on the same line.
+fn space_between(previous: &Item, next: &Item) -> bool {
+	use YuriTokenType::*;
+	// calls, constructors, annotation arguments, indexing and array types
+	let attaches = matches!(previous.token_type(), Identifier(_) | Annotation(_) | CloseSquare | CloseTri) || is_type_keyword(previous.token_type());
+	match (previous.token_type(), next.token_type()) {
+		_ if previous.unary => false,
+		(_, Separator | Terminator | CloseParen | CloseSquare | TypeHint) => false,
+		(OpenParen | OpenSquare, _) => false,
+		(OpenBrace, CloseBrace) => false,
+		(CloseParen, OpenSquare) => false,
+		(_, OpenParen | OpenSquare) => !attaches,
+		_ => true,
+	}
+}
+
+/// Whether the next token has to go on a new line.
+fn breaks_between(previous: &Item, next: &Item) -> bool {
+	use YuriTokenType::*;
+	match (previous.token_type(), next.token_type()) {
+		_ if previous.ends_annotation => true,
+		(Terminator, _) => true,
+		(OpenBrace, CloseBrace) => false,
+		(OpenBrace, _) | (_, CloseBrace) => true,
+		// `} else {`, `};`, `}, x` and the like
+		(CloseBrace, Keyword(crate::lex::Keyword::Else) | Terminator | Separator | CloseParen | CloseSquare | CloseTri | Operator(_)) => false,
+		(CloseBrace, _) => true,
+		_ => false,
+	}
+}
+
+struct Printer {
+	out: String,
+	indent: usize,
+	/// Whether nothing's been written on the current line yet.
+	line_empty: bool,
+	/// Whether there was a blank line in the source before whatever gets written next.
+	blank_line: bool,
+	/// Whether the last thing written was a `{`, since blocks don't start with blank lines.
+	after_open: bool,
+	/// Whether a new line would be in the middle of something, which gets indented one level deeper.
+	continuation: bool,
+}
+
+impl Printer {
+	fn new() -> Self {
+		Self { out: String::new(), indent: 0, line_empty: true, blank_line: false, after_open: false, continuation: false }
+	}
+
+	fn newline(&mut self) {
+		if !self.line_empty {
+			let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+			self.out.truncate(trimmed);
+			self.out.push('\n');
+			self.line_empty = true;
+		}
+	}
+
+	fn write(&mut self, text: &str, space: bool) {
+		if self.line_empty {
+			if self.blank_line && !self.after_open && !self.out.is_empty() {
+				self.out.push('\n');
+			}
+			self.out.extend(std::iter::repeat_n('\t', self.indent + self.continuation as usize));
+		} else if space {
+			self.out.push(' ');
+		}
+		self.out.push_str(text);
+		self.line_empty = false;
+		self.blank_line = false;
+		self.after_open = false;
+	}
+
+	/// Leading comments are always on their own lines, since anything on the same line as the token before is trailing.
+	fn leading(&mut self, trivia: &[Trivia]) {
+		// the newline at the end of the last token's line already went by
+		let mut newlines = 1;
+		for trivia in trivia {
+			match trivia.kind {
+				TriviaKind::Newline => newlines += 1,
+				TriviaKind::Whitespace => {}
+				TriviaKind::LineComment | TriviaKind::BlockComment => {
+					self.newline();
+					self.blank_line |= newlines >= 2;
+					self.write(trivia.text.trim_end(), false);
+					self.newline();
+					newlines = 0;
+				}
+			}
+		}
+		self.blank_line |= newlines >= 2;
+	}
+
+	fn trailing(&mut self, trivia: &[Trivia]) {
+		for trivia in trivia {
+			match trivia.kind {
+				TriviaKind::LineComment => {
+					self.write(trivia.text.trim_end(), true);
+					self.newline();
+				}
+				TriviaKind::BlockComment => self.write(trivia.text, true),
+				TriviaKind::Whitespace | TriviaKind::Newline => {}
+			}
+		}
+	}
+
+	fn token(&mut self, previous: Option<&Item>, item: &Item) {
+		let open = *item.token_type() == YuriTokenType::OpenBrace;
+		if *item.token_type() == YuriTokenType::CloseBrace {
+			self.indent = self.indent.saturating_sub(1);
+			// no blank lines at the end of a block either
+			if self.line_empty {
+				self.blank_line = false;
+			}
+		}
+		let space = previous.is_some_and(|previous| space_between(previous, item));
+		self.write(item.token.text, space);
+		if open {
+			self.indent += 1;
+			self.after_open = true;
+		}
+	}
+
+	fn finish(mut self) -> String {
+		self.newline();
+		self.out
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::format::format_source;
+	use crate::YuriShader;
+
+	/// The AST's debug output, without the locations, which are the only thing formatting should change.
+	fn ast_without_locations(source: &str) -> String {
+		let module = YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap();
+		let debug = format!("{module:?}");
+		let mut out = String::new();
+		let mut rest = debug.as_str();
+		while let Some(i) = rest.find("location: ") {
+			out.push_str(&rest[..i]);
+			rest = rest[i + "location: ".len()..].trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+		}
+		out.push_str(rest);
+		out
+	}
+
+	#[test]
+	fn canonical_style() {
+		let source = "\
+## header ##
+prop time:f;   # seconds
+
+
+export   @vert fn main( pos:f3 , coord: f2 ):<| @vert.pos out: f4, pos: f3 |>{
+    # shorthand            ___here___
+  let x=-pos.x*2**2  ;
+
+	<|out=f4(pos,1.0)*x,pos|>
+}
+@frag(origin = lower_left) fn frag(): f4 { if time>1.0 { f4(1.0) } else { f4( 0.0 ) } }
+module m{let y:f[2]=[1,2];let z=y[0];}";
+		let expected = "\
+## header ##
+prop time: f; # seconds
+
+@vert
+export fn main(pos: f3, coord: f2): <| @vert.pos out: f4, pos: f3 |> {
+	# shorthand            ___here___
+	let x = -pos.x * 2 ** 2;
+
+	<| out = f4(pos, 1.0) * x, pos |>
+}
+@frag(origin = lower_left)
+fn frag(): f4 {
+	if time > 1.0 {
+		f4(1.0)
+	} else {
+		f4(0.0)
+	}
+}
+module m {
+	let y: f[2] = [1, 2];
+	let z = y[0];
+}
+";
+		let formatted = format_source(source).unwrap();
+		assert_eq!(formatted, expected);
+		assert_eq!(format_source(&formatted).unwrap(), formatted);
+		assert_eq!(ast_without_locations(&formatted), ast_without_locations(source));
+	}
+
+	#[test]
+	fn comments_stay_put() {
+		let source = "fn g(a: f, # first\nb: f): f { ## inline ## a + -b }\n\n\n# the end\n";
+		let formatted = format_source(source).unwrap();
+		assert_eq!(formatted, "fn g(a: f, # first\n\tb: f): f { ## inline ##\n\ta + -b\n}\n\n# the end\n");
+		assert_eq!(format_source(&formatted).unwrap(), formatted);
+		assert_eq!(ast_without_locations(&formatted), ast_without_locations(source));
+
+		// anything a comment breaks up goes one level deeper than the statement it's in
+		let source = "module m { let y = [1, 2, # c\n3]; let z = y[0] +\n# why not\ny[1]; }";
+		let formatted = format_source(source).unwrap();
+		assert_eq!(formatted, "module m {\n\tlet y = [1, 2, # c\n\t\t3];\n\tlet z = y[0] +\n\t\t# why not\n\t\ty[1];\n}\n");
+		assert_eq!(format_source(&formatted).unwrap(), formatted);
+		assert_eq!(ast_without_locations(&formatted), ast_without_locations(source));
+	}
+}
@@ -0,0 +1,968 @@
+//! Random, well-typed programs, for testing the compiler against itself.
+//! [generate] turns a seed into a whole module: `let`s, props, complex types, and functions full of
+//! arithmetic, builtins, arrays, `if`s, loops and calls to the functions before them.
+//! Everything it makes type checks, so it can go through the lexer and parser (written out with [YuriModule]'s `Display`),
+//! the formatter, or the interpreter and the compiler to see if they agree (see [crate::emulate]).
+//! When one of those goes wrong, [shrink] cuts the program down to the smallest one that still does.
+use crate::builtin::BuiltinFunction;
+use crate::check::{binary_result_type, types_match, TypedProgram};
+use crate::error::YuriCompileError;
+use crate::lex::NumberSuffix;
+use crate::options::CompileOptions;
+use crate::parse::{
+	ArrayLength, BinaryOperator, Block, ComplexField, CompositeSize, Else, Expression, ExpressionKind, FunctionArgument,
+	FunctionDeclaration, IfExpression, Literal, NumberType, PropertyDeclaration, Statement, UnaryOperator,
+	VariableDeclaration, YuriModule, YuriType,
+};
+use crate::source::SourceMap;
+use crate::random::Rng;
+use crate::YuriShader;
+
+/// How big the generated programs get.
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+	/// Later functions can call the ones before them, so the last one tends to be the biggest.
+	pub functions: usize,
+	/// Global `let`s, which have to be constants.
+	pub globals: usize,
+	/// Props need values before anything using them can run,
+	/// and [crate::emulate::compare_function] doesn't give them any, so there aren't any by default.
+	pub props: usize,
+	/// How many different complex types the functions pass around.
+	pub complex_types: usize,
+	/// The most arguments a function can take.
+	pub arguments: usize,
+	/// The most statements a block can have before its tail.
+	pub statements: usize,
+	/// How deep expressions can nest before they have to be variables or literals.
+	pub depth: u32,
+}
+
+impl Default for GenerateOptions {
+	fn default() -> Self {
+		Self {
+			functions: 4,
+			globals: 3,
+			props: 0,
+			complex_types: 2,
+			arguments: 3,
+			statements: 3,
+			depth: 4,
+		}
+	}
+}
+
+impl GenerateOptions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_functions(mut self, functions: usize) -> Self {
+		self.functions = functions;
+		self
+	}
+
+	pub fn with_globals(mut self, globals: usize) -> Self {
+		self.globals = globals;
+		self
+	}
+
+	pub fn with_props(mut self, props: usize) -> Self {
+		self.props = props;
+		self
+	}
+
+	pub fn with_complex_types(mut self, complex_types: usize) -> Self {
+		self.complex_types = complex_types;
+		self
+	}
+
+	pub fn with_arguments(mut self, arguments: usize) -> Self {
+		self.arguments = arguments;
+		self
+	}
+
+	pub fn with_statements(mut self, statements: usize) -> Self {
+		self.statements = statements;
+		self
+	}
+
+	pub fn with_depth(mut self, depth: u32) -> Self {
+		self.depth = depth;
+		self
+	}
+}
+
+/// Makes a random module out of the seed. The same seed and options always make the same module.
+/// Everything in it has a location of `0..0`, use [check_module] to get a program with real ones.
+pub fn generate(seed: u64, options: &GenerateOptions) -> YuriModule {
+	let mut generator = Generator {
+		rng: Rng::new(seed),
+		options,
+		complex_types: Vec::new(),
+		functions: Vec::new(),
+		constant: false,
+		names: 0,
+	};
+	generator.generate()
+}
+
+/// The variables that can be used at some point, and their types.
+type Scope = Vec<(String, YuriType)>;
+
+struct Generator<'o> {
+	rng: Rng,
+	options: &'o GenerateOptions,
+	complex_types: Vec<YuriType>,
+	/// The name, argument types and return type of every function so far, since the next ones can call them.
+	functions: Vec<(String, Vec<YuriType>, YuriType)>,
+	/// Set while making the value of a global, which gets evaluated at compile time.
+	/// Only the simplest expressions go there, so nothing ends up undefined (like dividing by zero) before the program even runs.
+	constant: bool,
+	/// Counts up for fresh names, so nothing ever shadows anything else.
+	names: usize,
+}
+
+fn node(kind: ExpressionKind) -> Expression {
+	Expression { kind, location: 0..0 }
+}
+
+fn unsigned(n: usize) -> Expression {
+	node(ExpressionKind::Literal(Literal::DecimalNumber(n as i128, Some(NumberSuffix::Unsigned))))
+}
+
+fn binary(operator: BinaryOperator, lhs: Expression, rhs: Expression) -> Expression {
+	node(ExpressionKind::Binary { operator, lhs: Box::new(lhs), rhs: Box::new(rhs) })
+}
+
+const SIZES: [CompositeSize; 3] = [CompositeSize::Two, CompositeSize::Three, CompositeSize::Four];
+const NUMBER_TYPES: [NumberType; 3] = [NumberType::Float, NumberType::Signed, NumberType::Unsigned];
+
+impl Generator<'_> {
+	fn generate(&mut self) -> YuriModule {
+		let mut module = YuriModule::default();
+		for _ in 0..self.options.complex_types {
+			let fields = (0..self.rng.between(2, 3))
+				.map(|n| ComplexField { name: format!("field{n}"), field_type: self.simple_type(), annotations: Vec::new() })
+				.collect();
+			self.complex_types.push(YuriType::Complex(fields));
+		}
+		let mut scope = Scope::new();
+		self.constant = true;
+		for n in 0..self.options.globals {
+			let name = format!("CONST{n}");
+			let global_type = self.simple_type();
+			let value = self.expression(&mut scope, &global_type, 2);
+			module.globals.push(VariableDeclaration {
+				name: name.clone(),
+				explicit_type: self.rng.one_in(2).then(|| global_type.clone()),
+				value,
+				exported: self.rng.one_in(4),
+				annotations: Vec::new(),
+				location: 0..0,
+			});
+			scope.push((name, global_type));
+		}
+		self.constant = false;
+		// props can't go into globals, so they only come into scope now
+		for n in 0..self.options.props {
+			let name = format!("p{n}");
+			let property_type = self.value_type();
+			module.properties.push(PropertyDeclaration {
+				name: name.clone(),
+				property_type: property_type.clone(),
+				annotations: Vec::new(),
+				location: 0..0,
+			});
+			scope.push((name, property_type));
+		}
+		for n in 0..self.options.functions {
+			let function = self.function(format!("func{n}"), &scope);
+			module.functions.push(function);
+		}
+		module
+	}
+
+	fn fresh(&mut self, prefix: &str) -> String {
+		self.names += 1;
+		format!("{prefix}{}", self.names)
+	}
+
+	/// Bools, scalars and vectors, which can go anywhere.
+	fn simple_type(&mut self) -> YuriType {
+		let number_type = *self.rng.pick(&NUMBER_TYPES);
+		match self.rng.below(10) {
+			0 => YuriType::Bool,
+			1..=5 => YuriType::Scalar(number_type),
+			_ => YuriType::Vector(number_type, *self.rng.pick(&SIZES)),
+		}
+	}
+
+	/// Anything a variable can hold.
+	fn value_type(&mut self) -> YuriType {
+		match self.rng.below(20) {
+			0 | 1 => YuriType::Matrix(*self.rng.pick(&SIZES)),
+			2 | 3 => YuriType::Array(Box::new(self.simple_type()), ArrayLength::Fixed(self.rng.between(2, 4) as usize)),
+			4 | 5 if !self.complex_types.is_empty() => self.rng.pick(&self.complex_types).clone(),
+			_ => self.simple_type(),
+		}
+	}
+
+	fn function(&mut self, name: String, globals: &Scope) -> FunctionDeclaration {
+		let mut scope = globals.clone();
+		let mut arguments = Vec::new();
+		for _ in 0..self.rng.below(self.options.arguments + 1) {
+			let argument_type = self.value_type();
+			let name = self.fresh("a");
+			scope.push((name.clone(), argument_type.clone()));
+			arguments.push(FunctionArgument { name, argument_type, location: 0..0 });
+		}
+		let return_type = self.value_type();
+		let mut body = self.block(&mut scope, &return_type, self.options.depth);
+		// a `return` at the end is the same as the tail
+		if self.rng.one_in(4) && let Some(tail) = body.tail.take() {
+			body.statements.push(Statement::Return(*tail));
+		}
+		self.functions.push((
+			name.clone(),
+			arguments.iter().map(|a| a.argument_type.clone()).collect(),
+			return_type.clone(),
+		));
+		FunctionDeclaration {
+			name,
+			return_type,
+			arguments,
+			body,
+			exported: self.rng.one_in(4),
+			annotations: Vec::new(),
+			location: 0..0,
+		}
+	}
+
+	fn block(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Block {
+		let outer = scope.len();
+		let mut statements = Vec::new();
+		for _ in 0..self.rng.below(self.options.statements + 1) {
+			let value_type = self.value_type();
+			let value = self.expression(scope, &value_type, depth.saturating_sub(1));
+			// the odd value gets thrown away
+			if self.rng.one_in(6) {
+				statements.push(Statement::Expression(value));
+				continue;
+			}
+			let name = self.fresh("v");
+			statements.push(Statement::Variable(VariableDeclaration {
+				name: name.clone(),
+				explicit_type: self.rng.one_in(3).then(|| value_type.clone()),
+				value,
+				exported: false,
+				annotations: Vec::new(),
+				location: 0..0,
+			}));
+			scope.push((name, value_type));
+		}
+		let tail = self.expression(scope, ty, depth);
+		scope.truncate(outer);
+		Block { statements, tail: Some(Box::new(tail)), location: 0..0 }
+	}
+
+	fn expression(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Expression {
+		if depth == 0 || self.rng.one_in(5) {
+			return self.leaf(scope, ty);
+		}
+		let depth = depth - 1;
+		// not every kind of expression can give back every type, so keep trying until one can
+		loop {
+			let made = match self.rng.below(if self.constant { 10 } else { 20 }) {
+				0 | 1 => Some(self.leaf(scope, ty)),
+				2 => self.unary(scope, ty, depth),
+				3..=7 => self.binary(scope, ty, depth),
+				8 | 9 => self.construct(scope, ty, depth),
+				10 | 11 => self.builtin(scope, ty, depth),
+				12 => self.call(scope, ty, depth),
+				13 => self.index(scope, ty, depth),
+				14 => Some(self.if_else(scope, ty, depth)),
+				15 => Some(node(ExpressionKind::Block(self.block(scope, ty, depth)))),
+				16 => Some(self.fold(scope, ty, depth)),
+				_ => self.aggregate(scope, ty, depth),
+			};
+			if let Some(expr) = made {
+				return expr;
+			}
+		}
+	}
+
+	/// A variable (or a field or swizzle of one), or a literal if there aren't any of the right type.
+	fn leaf(&mut self, scope: &Scope, ty: &YuriType) -> Expression {
+		let mut names = Vec::new();
+		for (name, variable_type) in scope {
+			if types_match(variable_type, ty) {
+				names.push(name.clone());
+			}
+			match variable_type {
+				YuriType::Complex(fields) => names.extend(fields.iter()
+					.filter(|field| types_match(&field.field_type, ty))
+					.map(|field| format!("{name}.{}", field.name))),
+				YuriType::Vector(number_type, size) => {
+					let count = match ty {
+						YuriType::Scalar(n) if n == number_type => 1,
+						YuriType::Vector(n, s) if n == number_type => s.count(),
+						_ => continue,
+					};
+					let swizzle: String = (0..count)
+						.map(|_| b"xyzw"[self.rng.below(size.count() as usize)] as char)
+						.collect();
+					names.push(format!("{name}.{swizzle}"));
+				}
+				_ => {}
+			}
+		}
+		if !names.is_empty() && !self.rng.one_in(4) {
+			return node(ExpressionKind::Variable(self.rng.pick(&names).clone()));
+		}
+		self.literal(ty)
+	}
+
+	fn literal(&mut self, ty: &YuriType) -> Expression {
+		let negate = |operand| node(ExpressionKind::Unary { operator: UnaryOperator::Negate, operand: Box::new(operand) });
+		match ty {
+			YuriType::Bool => node(ExpressionKind::Literal(Literal::Boolean(self.rng.one_in(2)))),
+			YuriType::Scalar(NumberType::Float) => {
+				let value = self.rng.between(-40, 40) as f64 / 4.0;
+				let suffix = self.rng.one_in(5).then_some(NumberSuffix::Float);
+				let literal = node(ExpressionKind::Literal(Literal::FloatNumber(value.abs(), suffix)));
+				if value < 0.0 { negate(literal) } else { literal }
+			}
+			YuriType::Scalar(number_type) => {
+				let (value, suffix) = match number_type {
+					NumberType::Signed => (self.rng.between(-20, 20) as i128, NumberSuffix::Signed),
+					_ => (self.rng.between(0, 40) as i128, NumberSuffix::Unsigned),
+				};
+				// every integer gets a suffix, so none of them have to guess their type from what's around them
+				let literal = node(ExpressionKind::Literal(match self.rng.below(6) {
+					0 => Literal::HexNumber(value.abs(), Some(suffix)),
+					1 => Literal::BinaryNumber(value.abs(), Some(suffix)),
+					_ => Literal::DecimalNumber(value.abs(), Some(suffix)),
+				}));
+				if value < 0 { negate(literal) } else { literal }
+			}
+			YuriType::Vector(number_type, size) => {
+				let scalar = YuriType::Scalar(*number_type);
+				let count = if self.rng.one_in(3) { 1 } else { size.count() };
+				let arguments = (0..count).map(|_| self.literal(&scalar)).collect();
+				node(ExpressionKind::Construct { target: ty.clone(), arguments })
+			}
+			YuriType::Matrix(size) => {
+				let column = YuriType::Vector(NumberType::Float, *size);
+				let arguments = (0..size.count()).map(|_| self.literal(&column)).collect();
+				node(ExpressionKind::Construct { target: ty.clone(), arguments })
+			}
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => {
+				node(ExpressionKind::Array((0..*length).map(|_| self.literal(inner)).collect()))
+			}
+			YuriType::Complex(fields) => node(ExpressionKind::Complex(fields.iter()
+				.map(|field| (field.name.clone(), self.literal(&field.field_type)))
+				.collect())),
+			_ => unreachable!("no literals for `{ty}`"),
+		}
+	}
+
+	fn unary(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		use NumberType::*;
+		let operator = match ty {
+			YuriType::Bool => UnaryOperator::Not,
+			YuriType::Scalar(Signed | Unsigned) | YuriType::Vector(Signed | Unsigned, _) if self.rng.one_in(2) => UnaryOperator::Not,
+			// `-` right on an unsigned literal makes a negative one, which doesn't fit
+			YuriType::Scalar(Unsigned) => return None,
+			YuriType::Scalar(_) | YuriType::Vector(..) | YuriType::Matrix(_) => UnaryOperator::Negate,
+			_ => return None,
+		};
+		let operand = self.expression(scope, ty, depth);
+		Some(node(ExpressionKind::Unary { operator, operand: Box::new(operand) }))
+	}
+
+	/// Any operator that works on the type, picked by trying every one on the operand types that could make sense.
+	fn binary(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		use BinaryOperator::*;
+		let operators: &[BinaryOperator] = if self.constant {
+			&[Plus, Minus, Times, BitAnd, BitOr, BitXor, Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual, And, Xor, Or, Nor]
+		} else {
+			&[
+				Plus, Minus, Times, Divided, Modulo, Exponent, BitAnd, BitOr, BitXor, ShiftLeft, ShiftRight,
+				Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual, And, Xor, Or, Nor,
+			]
+		};
+		let mut operand_types = vec![ty.clone()];
+		match ty {
+			YuriType::Bool => operand_types.extend(NUMBER_TYPES.map(YuriType::Scalar)),
+			YuriType::Scalar(_) => operand_types.extend(NUMBER_TYPES.map(YuriType::Scalar)),
+			YuriType::Vector(number_type, size) => {
+				operand_types.extend(NUMBER_TYPES.map(YuriType::Scalar));
+				operand_types.extend(NUMBER_TYPES.map(|n| YuriType::Vector(n, *size)));
+				if *number_type == NumberType::Float {
+					operand_types.push(YuriType::Matrix(*size));
+				}
+			}
+			YuriType::Matrix(_) => operand_types.push(YuriType::Scalar(NumberType::Float)),
+			_ => return None,
+		}
+		operand_types.dedup();
+		let mut choices = Vec::new();
+		for operator in operators {
+			for lhs in &operand_types {
+				for rhs in &operand_types {
+					if binary_result_type(*operator, lhs, rhs).as_ref() == Some(ty) {
+						choices.push((*operator, lhs, rhs));
+					}
+				}
+			}
+		}
+		if choices.is_empty() {
+			return None;
+		}
+		let (operator, lhs_type, rhs_type) = *self.rng.pick(&choices);
+		let (lhs_type, rhs_type) = (lhs_type.clone(), rhs_type.clone());
+		let lhs = self.expression(scope, &lhs_type, depth);
+		let mut rhs = self.expression(scope, &rhs_type, depth);
+		// keep the integer operations that can be undefined defined most of the time,
+		// otherwise most inputs would have nothing to compare
+		let integer = matches!(rhs_type, YuriType::Scalar(NumberType::Signed | NumberType::Unsigned)
+			| YuriType::Vector(NumberType::Signed | NumberType::Unsigned, _));
+		if integer && !self.rng.one_in(8) {
+			let mask = match operator {
+				ShiftLeft | ShiftRight => Some((BitAnd, 15)),
+				Divided | Modulo => Some((BitOr, 1)),
+				_ => None,
+			};
+			if let Some((mask_operator, mask)) = mask {
+				let mask = match &rhs_type {
+					YuriType::Scalar(number_type) => self.integer(*number_type, mask),
+					_ => {
+						let number_type = crate::check::number_type(&rhs_type).unwrap();
+						node(ExpressionKind::Construct { target: rhs_type.clone(), arguments: vec![self.integer(number_type, mask)] })
+					}
+				};
+				rhs = binary(mask_operator, rhs, mask);
+			}
+		}
+		Some(binary(operator, lhs, rhs))
+	}
+
+	fn integer(&mut self, number_type: NumberType, value: i128) -> Expression {
+		let suffix = match number_type {
+			NumberType::Signed => NumberSuffix::Signed,
+			_ => NumberSuffix::Unsigned,
+		};
+		node(ExpressionKind::Literal(Literal::DecimalNumber(value, Some(suffix))))
+	}
+
+	/// Conversions, and vectors, matrices and arrays built out of their parts.
+	fn construct(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		let arguments = match ty {
+			YuriType::Bool | YuriType::Scalar(_) => {
+				let from = if self.rng.one_in(4) { YuriType::Bool } else { YuriType::Scalar(*self.rng.pick(&NUMBER_TYPES)) };
+				vec![self.expression(scope, &from, depth)]
+			}
+			YuriType::Vector(_, size) => match (self.rng.below(4), *self.rng.pick(&NUMBER_TYPES)) {
+				(0, from) => vec![self.expression(scope, &YuriType::Scalar(from), depth)],
+				(1, from) => vec![self.expression(scope, &YuriType::Vector(from, *size), depth)],
+				_ => {
+					let mut arguments = Vec::new();
+					let mut left = size.count() as usize;
+					while left > 0 {
+						let count = 1 + self.rng.below(left.min(3));
+						let number_type = *self.rng.pick(&NUMBER_TYPES);
+						let part = match CompositeSize::from_count(count) {
+							Some(size) => YuriType::Vector(number_type, size),
+							None if self.rng.one_in(5) => YuriType::Bool,
+							None => YuriType::Scalar(number_type),
+						};
+						arguments.push(self.expression(scope, &part, depth));
+						left -= count;
+					}
+					arguments
+				}
+			},
+			YuriType::Matrix(size) => if self.rng.one_in(2) {
+				let column = YuriType::Vector(NumberType::Float, *size);
+				(0..size.count()).map(|_| self.expression(scope, &column, depth)).collect()
+			} else {
+				(0..size.count() * size.count())
+					.map(|_| {
+						let scalar = YuriType::Scalar(*self.rng.pick(&NUMBER_TYPES));
+						self.expression(scope, &scalar, depth)
+					})
+					.collect()
+			},
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => (0..*length).map(|_| self.expression(scope, inner, depth)).collect(),
+			_ => return None,
+		};
+		Some(node(ExpressionKind::Construct { target: ty.clone(), arguments }))
+	}
+
+	/// A builtin function that gives back the type, picked by trying every one on the argument types that could make sense.
+	fn builtin(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		let float = YuriType::Scalar(NumberType::Float);
+		let mut shapes = vec![
+			vec![ty.clone()],
+			vec![ty.clone(), ty.clone()],
+			vec![ty.clone(), ty.clone(), ty.clone()],
+			vec![ty.clone(), ty.clone(), float.clone()],
+			vec![float.clone(), float.clone(), ty.clone()],
+		];
+		if *ty == float {
+			for size in SIZES {
+				shapes.push(vec![YuriType::Vector(NumberType::Float, size)]);
+				shapes.push(vec![YuriType::Vector(NumberType::Float, size); 2]);
+				shapes.push(vec![YuriType::Matrix(size)]);
+			}
+		}
+		let choices: Vec<(BuiltinFunction, &Vec<YuriType>)> = BuiltinFunction::ALL.iter()
+			.filter(|function| **function != BuiltinFunction::Sample && !function.is_assertion())
+			.flat_map(|function| shapes.iter()
+				.filter(|arguments| function.result_type(arguments).is_ok_and(|result| result == *ty))
+				.map(|arguments| (*function, arguments)))
+			.collect();
+		if choices.is_empty() {
+			return None;
+		}
+		let (function, argument_types) = *self.rng.pick(&choices);
+		let argument_types = argument_types.clone();
+		let arguments = argument_types.iter().map(|a| self.expression(scope, a, depth)).collect();
+		Some(node(ExpressionKind::FunctionCall { function_name: function.name().to_string(), arguments }))
+	}
+
+	fn call(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		let candidates: Vec<usize> = (0..self.functions.len())
+			.filter(|i| types_match(&self.functions[*i].2, ty))
+			.collect();
+		if candidates.is_empty() {
+			return None;
+		}
+		let (function_name, argument_types, _) = self.functions[*self.rng.pick(&candidates)].clone();
+		let arguments = argument_types.iter().map(|a| self.expression(scope, a, depth)).collect();
+		Some(node(ExpressionKind::FunctionCall { function_name, arguments }))
+	}
+
+	/// Indexes into a vector, matrix or array, with a constant index or one that's kept in bounds with `%`.
+	fn index(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		let (target_type, length) = match ty {
+			YuriType::Scalar(number_type) if self.rng.one_in(2) => {
+				let size = *self.rng.pick(&SIZES);
+				(YuriType::Vector(*number_type, size), size.count() as usize)
+			}
+			YuriType::Vector(NumberType::Float, size) if self.rng.one_in(2) => (YuriType::Matrix(*size), size.count() as usize),
+			YuriType::Bool | YuriType::Scalar(_) | YuriType::Vector(..) => {
+				let length = self.rng.between(2, 4) as usize;
+				(YuriType::Array(Box::new(ty.clone()), ArrayLength::Fixed(length)), length)
+			}
+			_ => return None,
+		};
+		let target = self.expression(scope, &target_type, depth);
+		let index = if self.rng.one_in(2) {
+			unsigned(self.rng.below(length))
+		} else {
+			let index = self.expression(scope, &YuriType::Scalar(NumberType::Unsigned), depth);
+			binary(BinaryOperator::Modulo, index, unsigned(length))
+		};
+		Some(node(ExpressionKind::Index { target: Box::new(target), index: Box::new(index) }))
+	}
+
+	fn if_else(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Expression {
+		let condition = self.expression(scope, &YuriType::Bool, depth);
+		let block = self.block(scope, ty, depth);
+		let block_else = if self.rng.one_in(4) {
+			Else::If(Box::new(self.if_else(scope, ty, depth)))
+		} else {
+			Else::Block(self.block(scope, ty, depth))
+		};
+		node(ExpressionKind::If(IfExpression {
+			condition: Box::new(condition),
+			block,
+			block_else: Some(block_else),
+		}))
+	}
+
+	/// Folds over an array, or a constant number of times.
+	fn fold(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Expression {
+		let initial = self.expression(scope, ty, depth);
+		let (items, item_type) = if self.rng.one_in(2) {
+			(unsigned(self.rng.between(1, 4) as usize), YuriType::Scalar(NumberType::Unsigned))
+		} else {
+			let item_type = self.simple_type();
+			let length = self.rng.between(2, 4) as usize;
+			let items_type = YuriType::Array(Box::new(item_type.clone()), ArrayLength::Fixed(length));
+			(self.expression(scope, &items_type, depth), item_type)
+		};
+		let accumulator = self.fresh("acc");
+		let item = self.fresh("item");
+		scope.push((accumulator.clone(), ty.clone()));
+		scope.push((item.clone(), item_type));
+		let block = self.block(scope, ty, depth);
+		scope.truncate(scope.len() - 2);
+		node(ExpressionKind::Fold { accumulator, initial: Box::new(initial), item, items: Box::new(items), block })
+	}
+
+	/// Array and complex literals, and the loops that make arrays.
+	fn aggregate(&mut self, scope: &mut Scope, ty: &YuriType, depth: u32) -> Option<Expression> {
+		Some(match ty {
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => match self.rng.below(3) {
+				0 => node(ExpressionKind::Array((0..*length).map(|_| self.expression(scope, inner, depth)).collect())),
+				1 => {
+					let index = self.fresh("k");
+					scope.push((index.clone(), YuriType::Scalar(NumberType::Unsigned)));
+					let block = self.block(scope, inner, depth);
+					scope.pop();
+					node(ExpressionKind::Loop { index, count: Box::new(unsigned(*length)), block })
+				}
+				_ => {
+					let item_type = self.simple_type();
+					let items_type = YuriType::Array(Box::new(item_type.clone()), ArrayLength::Fixed(*length));
+					let items = self.expression(scope, &items_type, depth);
+					let item = self.fresh("item");
+					scope.push((item.clone(), item_type));
+					let block = self.block(scope, inner, depth);
+					scope.pop();
+					node(ExpressionKind::Map { item, items: Box::new(items), block })
+				}
+			},
+			YuriType::Complex(fields) => node(ExpressionKind::Complex(fields.iter()
+				.map(|field| (field.name.clone(), self.expression(scope, &field.field_type, depth)))
+				.collect())),
+			_ => return None,
+		})
+	}
+}
+
+/// Type checks a module the same way as a file. It gets written out and parsed again first,
+/// so the locations in the program (and in any errors) point into the source, which is called `<generated>`.
+pub fn check_module(module: &YuriModule) -> Result<TypedProgram, YuriCompileError> {
+	let mut sources = SourceMap::new();
+	let root = sources.add("<generated>", module.to_string());
+	YuriShader::check_sources(&mut sources, root, &CompileOptions::default())
+}
+
+/// Cuts a program down to the smallest one that `fails` still says fails, for when a generated program finds a bug.
+/// It keeps trying smaller versions (without a declaration, argument or statement,
+/// or with an expression replaced by one of its parts or a plain literal),
+/// and moves on to the first one that's still well typed and still fails, until none of them do.
+pub fn shrink(module: &YuriModule, mut fails: impl FnMut(&YuriModule) -> bool) -> YuriModule {
+	let mut current = module.clone();
+	'smaller: loop {
+		let length = current.to_string().len();
+		for candidate in candidates(&current) {
+			if candidate.to_string().len() < length && check_module(&candidate).is_ok() && fails(&candidate) {
+				current = candidate;
+				continue 'smaller;
+			}
+		}
+		return current;
+	}
+}
+
+/// Either an expression or a block, for going over everything in a module.
+enum Node<'a> {
+	Expression(&'a mut Expression),
+	Block(&'a mut Block),
+}
+
+/// Visits every expression and block in the module, parents before their children.
+/// A visit can replace what it's given, and then the replacement's children get visited instead.
+fn walk(module: &mut YuriModule, visit: &mut dyn FnMut(Node)) {
+	for global in &mut module.globals {
+		walk_expression(&mut global.value, visit);
+	}
+	for function in &mut module.functions {
+		walk_block(&mut function.body, visit);
+	}
+}
+
+fn walk_block(block: &mut Block, visit: &mut dyn FnMut(Node)) {
+	visit(Node::Block(block));
+	for statement in &mut block.statements {
+		match statement {
+			Statement::Expression(expr) | Statement::Return(expr) => walk_expression(expr, visit),
+			Statement::Variable(variable) => walk_expression(&mut variable.value, visit),
+		}
+	}
+	if let Some(tail) = &mut block.tail {
+		walk_expression(tail, visit);
+	}
+}
+
+fn walk_expression(expr: &mut Expression, visit: &mut dyn FnMut(Node)) {
+	visit(Node::Expression(expr));
+	match &mut expr.kind {
+		ExpressionKind::Literal(_) | ExpressionKind::Variable(_) | ExpressionKind::Builtin(_) => {}
+		ExpressionKind::FunctionCall { arguments, .. } | ExpressionKind::Construct { arguments, .. } | ExpressionKind::Array(arguments) => {
+			for argument in arguments {
+				walk_expression(argument, visit);
+			}
+		}
+		ExpressionKind::Complex(fields) => {
+			for (_, value) in fields {
+				walk_expression(value, visit);
+			}
+		}
+		ExpressionKind::Index { target: lhs, index: rhs } | ExpressionKind::Binary { lhs, rhs, .. } => {
+			walk_expression(lhs, visit);
+			walk_expression(rhs, visit);
+		}
+		ExpressionKind::Unary { operand, .. } => walk_expression(operand, visit),
+		ExpressionKind::Block(block) => walk_block(block, visit),
+		ExpressionKind::If(if_expr) => {
+			walk_expression(&mut if_expr.condition, visit);
+			walk_block(&mut if_expr.block, visit);
+			match &mut if_expr.block_else {
+				Some(Else::Block(block)) => walk_block(block, visit),
+				Some(Else::If(else_if)) => walk_expression(else_if, visit),
+				None => {}
+			}
+		}
+		ExpressionKind::Loop { count: items, block, .. }
+		| ExpressionKind::Map { items, block, .. }
+		| ExpressionKind::Filter { items, block, .. } => {
+			walk_expression(items, visit);
+			walk_block(block, visit);
+		}
+		ExpressionKind::Fold { initial, items, block, .. } => {
+			walk_expression(initial, visit);
+			walk_expression(items, visit);
+			walk_block(block, visit);
+		}
+	}
+}
+
+/// The expressions right inside this one (including the tails of its blocks), which might have the same type.
+fn parts(expr: &Expression) -> Vec<Expression> {
+	let tail = |block: &Block| block.tail.as_deref().cloned();
+	match &expr.kind {
+		ExpressionKind::Literal(_) | ExpressionKind::Variable(_) | ExpressionKind::Builtin(_) => Vec::new(),
+		ExpressionKind::FunctionCall { arguments, .. } | ExpressionKind::Construct { arguments, .. } | ExpressionKind::Array(arguments) => {
+			arguments.clone()
+		}
+		ExpressionKind::Complex(fields) => fields.iter().map(|(_, value)| value.clone()).collect(),
+		ExpressionKind::Index { target, .. } => vec![target.as_ref().clone()],
+		ExpressionKind::Unary { operand, .. } => vec![operand.as_ref().clone()],
+		ExpressionKind::Binary { lhs, rhs, .. } => vec![lhs.as_ref().clone(), rhs.as_ref().clone()],
+		ExpressionKind::Block(block) => tail(block).into_iter().collect(),
+		ExpressionKind::If(if_expr) => {
+			let block_else = match &if_expr.block_else {
+				Some(Else::Block(block)) => tail(block),
+				Some(Else::If(else_if)) => Some(else_if.as_ref().clone()),
+				None => None,
+			};
+			tail(&if_expr.block).into_iter().chain(block_else).collect()
+		}
+		ExpressionKind::Loop { .. } | ExpressionKind::Map { .. } | ExpressionKind::Filter { .. } => Vec::new(),
+		ExpressionKind::Fold { initial, .. } => vec![initial.as_ref().clone()],
+	}
+}
+
+/// Every way to make the module a little smaller, biggest cuts first.
+/// Plenty of them won't type check anymore, [shrink] skips those.
+fn candidates(module: &YuriModule) -> Vec<YuriModule> {
+	let mut candidates = Vec::new();
+	let without = |remove: &dyn Fn(&mut YuriModule)| {
+		let mut candidate = module.clone();
+		remove(&mut candidate);
+		candidate
+	};
+	for i in 0..module.functions.len() {
+		candidates.push(without(&|m| { m.functions.remove(i); }));
+	}
+	for i in 0..module.globals.len() {
+		candidates.push(without(&|m| { m.globals.remove(i); }));
+	}
+	for i in 0..module.properties.len() {
+		candidates.push(without(&|m| { m.properties.remove(i); }));
+	}
+	for (i, function) in module.functions.iter().enumerate() {
+		for j in 0..function.arguments.len() {
+			candidates.push(without(&|m| { m.functions[i].arguments.remove(j); }));
+		}
+	}
+	for (i, global) in module.globals.iter().enumerate() {
+		if global.explicit_type.is_some() {
+			candidates.push(without(&|m| m.globals[i].explicit_type = None));
+		}
+	}
+
+	// the blocks and expressions are numbered in the order they're walked,
+	// and every change gets made by walking a copy of the module up to the right one
+	let mut copy = module.clone();
+	let mut block_changes = Vec::new();
+	let mut expression_changes = Vec::new();
+	walk(&mut copy, &mut |node| match node {
+		Node::Block(block) => block_changes.push(block_candidates(block)),
+		Node::Expression(expr) => expression_changes.push(expression_candidates(expr)),
+	});
+	let mut change = |n: usize, is_block: bool, apply: &dyn Fn(Node)| {
+		let mut candidate = module.clone();
+		let (mut blocks, mut expressions) = (0, 0);
+		walk(&mut candidate, &mut |node| match node {
+			Node::Block(block) => {
+				if is_block && blocks == n {
+					apply(Node::Block(block));
+				}
+				blocks += 1;
+			}
+			Node::Expression(expr) => {
+				if !is_block && expressions == n {
+					apply(Node::Expression(expr));
+				}
+				expressions += 1;
+			}
+		});
+		candidates.push(candidate);
+	};
+	for (n, changes) in block_changes.iter().enumerate() {
+		for replacement in changes {
+			change(n, true, &|node| if let Node::Block(block) = node {
+				*block = replacement.clone();
+			});
+		}
+	}
+	for (n, changes) in expression_changes.iter().enumerate() {
+		for replacement in changes {
+			change(n, false, &|node| if let Node::Expression(expr) = node {
+				*expr = replacement.clone();
+			});
+		}
+	}
+	candidates
+}
+
+/// The block without each of its statements, and without the types of its `let`s.
+fn block_candidates(block: &Block) -> Vec<Block> {
+	let mut candidates = Vec::new();
+	for i in 0..block.statements.len() {
+		let mut candidate = block.clone();
+		let removed = candidate.statements.remove(i);
+		// a `return` at the end can just be the tail instead
+		if let Statement::Return(value) = removed && candidate.tail.is_none() {
+			let mut with_tail = candidate.clone();
+			with_tail.tail = Some(Box::new(value));
+			candidates.push(with_tail);
+		}
+		candidates.push(candidate);
+	}
+	for (i, statement) in block.statements.iter().enumerate() {
+		if let Statement::Variable(variable) = statement && variable.explicit_type.is_some() {
+			let mut candidate = block.clone();
+			if let Statement::Variable(variable) = &mut candidate.statements[i] {
+				variable.explicit_type = None;
+			}
+			candidates.push(candidate);
+		}
+	}
+	candidates
+}
+
+/// The parts of the expression, and then the simplest literals, which take their type from wherever they are.
+fn expression_candidates(expr: &Expression) -> Vec<Expression> {
+	let mut candidates = parts(expr);
+	if !matches!(expr.kind, ExpressionKind::Literal(_)) {
+		candidates.extend([
+			Literal::DecimalNumber(0, None),
+			Literal::DecimalNumber(1, None),
+			Literal::FloatNumber(0.0, None),
+			Literal::FloatNumber(1.0, None),
+			Literal::Boolean(false),
+			Literal::Boolean(true),
+		].map(|literal| node(ExpressionKind::Literal(literal))));
+	}
+	candidates
+}
+
+
+#[cfg(test)]
+mod test {
+	use crate::emulate::compare_function;
+	use crate::format::format_source;
+	use crate::generate::{check_module, generate, shrink, GenerateOptions};
+	use crate::random::{random_value, Rng};
+	use crate::parse::YuriModule;
+	use crate::YuriShader;
+
+	fn reparse(source: &str) -> YuriModule {
+		YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap_or_else(|err| panic!("{err:?}\n{source}"))
+	}
+
+	#[test]
+	fn generated_programs_check() {
+		for seed in 0..200 {
+			let module = generate(seed, &GenerateOptions::default());
+			let source = module.to_string();
+			// writing it out and parsing it again gives back the same module
+			assert_eq!(reparse(&source).to_string(), source);
+			if let Err(err) = check_module(&module) {
+				panic!("seed {seed}: {err}\n{source}");
+			}
+		}
+		// bigger ones too
+		let options = GenerateOptions::new().with_functions(8).with_depth(6).with_props(2);
+		for seed in 0..10 {
+			let module = generate(seed, &options);
+			check_module(&module).unwrap_or_else(|err| panic!("seed {seed}: {err}\n{module}"));
+		}
+		assert_eq!(generate(3, &options), generate(3, &options));
+	}
+
+	#[test]
+	fn formatting_generated_programs() {
+		for seed in 0..100 {
+			let source = generate(seed, &GenerateOptions::default()).to_string();
+			let formatted = format_source(&source).unwrap();
+			assert_eq!(format_source(&formatted).unwrap(), formatted, "seed {seed}");
+			// only the whitespace changes
+			assert_eq!(reparse(&formatted).to_string(), source, "seed {seed}");
+		}
+	}
+
+	#[test]
+	fn generated_programs_compile_like_they_interpret() {
+		let options = GenerateOptions::default();
+		let mut compared = 0;
+		for seed in 0..40 {
+			let mut rng = Rng::new(seed);
+			// the first function that the compiler gets wrong, if there is one
+			let mut mismatch = |module: &YuriModule| {
+				let program = check_module(module).unwrap();
+				program.functions.iter().find_map(|function| {
+					let inputs: Vec<Vec<_>> = (0..8)
+						.map(|_| function.arguments.iter().map(|a| random_value(&mut rng, &function.locals[*a].local_type)).collect())
+						.collect();
+					match compare_function(&program, &function.name, &inputs) {
+						Ok(count) => {
+							compared += count;
+							None
+						}
+						Err(err) => Some(err.with_sources(&program.sources).to_string()),
+					}
+				})
+			};
+			let module = generate(seed, &options);
+			if let Some(err) = mismatch(&module) {
+				let shrunk = shrink(&module, |module| mismatch(module).is_some());
+				panic!("seed {seed}: {}\n{shrunk}", mismatch(&shrunk).unwrap_or(err));
+			}
+		}
+		assert!(compared > 200, "only {compared} calls could be compared");
+	}
+
+	#[test]
+	fn shrink_to_the_problem() {
+		let options = GenerateOptions::default();
+		let module = (0..).map(|seed| generate(seed, &options))
+			.find(|module| module.functions.len() == 4 && module.to_string().contains(" * "))
+			.unwrap();
+		// pretend multiplying is broken
+		let shrunk = shrink(&module, |module| module.to_string().contains(" * "));
+		let source = shrunk.to_string();
+		assert!(source.contains(" * "));
+		assert!(check_module(&shrunk).is_ok());
+		assert_eq!(shrunk.functions.len() + shrunk.globals.len(), 1, "{source}");
+		assert!(source.len() < module.to_string().len() / 4, "{source}");
+	}
+}
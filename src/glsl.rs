@@ -0,0 +1,271 @@
+//! GLSL 4.50 generation, for the places SPIR-V can't go (like an OpenGL driver without `ARB_gl_spirv`).
+//! Every entry point gets a shader of its own, with the same interface as the SPIR-V version:
+//! the same locations, the same std140 uniform block and the same sampler bindings, so either one can be used.
+//! See [crate::c_like] for the rest.
+use std::fmt::Write;
+use crate::builtin::FragOrigin;
+use crate::c_like::{Dialect, Emitter};
+use crate::check::{FunctionId, InterfaceSlot, ShaderStage, TypedProgram};
+use crate::compile::{check_props, entry_interface, interpolated, prepare_program, reads_frag_coord};
+use crate::error::YuriSemanticError;
+use crate::options::CompileOptions;
+use crate::parse::YuriType;
+use crate::text::{Emit, Scope, call_order, sections};
+
+/// A single entry point, as GLSL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlslShader {
+	/// The fully-qualified name of the entry point function, which `main` calls.
+	pub name: String,
+	pub stage: ShaderStage,
+	pub source: String,
+	/// How many sampler bindings the shader uses.
+	pub samplers: u32,
+	/// How many uniform buffers the shader uses (zero or one, since every prop goes in the same block).
+	pub uniform_buffers: u32,
+	/// Where the height of the render target has to go in the uniform buffer, in bytes.
+	/// Same as [crate::compile::CompiledShader::viewport_height_offset].
+	pub viewport_height_offset: Option<u32>,
+}
+
+impl GlslShader {
+	/// The file extension glslang expects for the stage.
+	pub fn extension(&self) -> &'static str {
+		match self.stage {
+			ShaderStage::Vertex => "vert",
+			ShaderStage::Fragment => "frag",
+		}
+	}
+}
+
+/// Writes a single entry point as GLSL, with a `main` that reads the inputs, calls it and writes the outputs.
+pub fn emit_entry_point(program: &TypedProgram, entry: FunctionId, options: &CompileOptions) -> Result<GlslShader, YuriSemanticError> {
+	let target = options.target;
+	check_props(program)?;
+	let function = &program.functions[entry];
+	let (stage, interface) = entry_interface(function)?;
+	let order = call_order(program, entry);
+	let origin = function.frag_origin.unwrap_or(target.frag_origin());
+	let reads_coord = reads_frag_coord(program, stage, &order);
+	let flip = reads_coord && !target.env().supports_origin(origin);
+	let mut emitter = Emitter::new(program, Dialect::Glsl, stage, target);
+	let viewport_height_offset = emitter.declare_props(flip)?;
+
+	let mut interface_declarations = String::new();
+	// OpenGL puts the origin in the lower left unless it's told otherwise, Vulkan only does upper left
+	if reads_coord && !target.env().is_vulkan() && origin == FragOrigin::UpperLeft {
+		interface_declarations.push_str("layout(origin_upper_left) in vec4 gl_FragCoord;\n");
+	}
+	let mut inputs = Vec::new();
+	for input in &interface.inputs {
+		let ty = emitter.type_name(&input.variable_type);
+		let name = emitter.global_name(&format!("in_{}", input.name));
+		let InterfaceSlot::Location(location) = input.slot else {
+			unreachable!("inputs always have a location");
+		};
+		let flat = if stage == ShaderStage::Fragment && !interpolated(&input.variable_type) { "flat " } else { "" };
+		let _ = writeln!(interface_declarations, "layout(location = {location}) {flat}in {ty} {name};");
+		inputs.push(name);
+	}
+	let mut outputs = Vec::new();
+	for output in &interface.outputs {
+		let name = match output.slot {
+			InterfaceSlot::Position => "gl_Position".to_string(),
+			InterfaceSlot::Location(location) => {
+				let ty = emitter.type_name(&output.variable_type);
+				// a single output is called `out`, which doesn't say much
+				let name = emitter.global_name(&format!("out_{}", if interface.complex_output { &output.name } else { "value" }));
+				let _ = writeln!(interface_declarations, "layout(location = {location}) out {ty} {name};");
+				name
+			}
+		};
+		outputs.push(name);
+	}
+
+	let mut functions = Vec::new();
+	for id in &order {
+		functions.push(emitter.function(*id)?);
+	}
+	emitter.writer.scope = Scope { depth: 1, ..Scope::default() };
+	let call = emitter.call(entry, inputs);
+	if interface.complex_output {
+		let ty = emitter.type_name(&function.return_type);
+		let result = emitter.local_name("result");
+		emitter.line(format!("{ty} {result} = {call};"));
+		let YuriType::Complex(fields) = &function.return_type else {
+			unreachable!("complex outputs come from complexes");
+		};
+		for (output, field) in outputs.iter().zip(fields) {
+			emitter.line(format!("{output} = {result}.{};", Dialect::Glsl.sanitize(&field.name)));
+		}
+	} else if let Some(output) = outputs.first() {
+		emitter.line(format!("{output} = {call};"));
+	} else {
+		emitter.line(format!("{call};"));
+	}
+	functions.push(format!("void main() {{\n{}}}\n", emitter.writer.scope.body));
+
+	Ok(GlslShader {
+		name: function.name.clone(),
+		stage,
+		source: sections([
+			"#version 450\n".to_string(),
+			emitter.writer.struct_declarations,
+			emitter.writer.resource_declarations,
+			interface_declarations,
+			emitter.writer.global_declarations,
+			functions.join("\n"),
+		]),
+		samplers: emitter.writer.samplers,
+		uniform_buffers: emitter.writer.uniform_buffer as u32,
+		viewport_height_offset,
+	})
+}
+
+/// Writes every entry point in the program (or just the ones the options ask for) as GLSL,
+/// after folding whatever can be worked out ahead of time.
+pub fn emit_program(program: &TypedProgram, options: &CompileOptions) -> Result<Vec<GlslShader>, YuriSemanticError> {
+	let (program, entry_points) = prepare_program(program, options)?;
+	entry_points.into_iter()
+		.map(|id| emit_entry_point(&program, id, options))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use crate::builtin::FragOrigin;
+	use crate::error::{YuriCompileError, YuriSemanticErrorType};
+	use crate::glsl::GlslShader;
+	use crate::target::{Target, TargetEnv};
+	use crate::text::test_support::{assert_lines, source_shader, SourceShader};
+
+	source_shader!(GlslShader, glsl_sources);
+
+	#[test]
+	fn emit_basic() {
+		let shaders = GlslShader::emit_basic(&[
+			"layout(std140, set = 1, binding = 0) uniform Props {",
+			"mat4 transform;",
+			"layout(location = 0) in vec3 in_pos;",
+			"layout(location = 1) in vec2 in_coord;",
+			// `@vert.pos` doesn't take up a location
+			"layout(location = 0) out vec3 out_pos;",
+			"layout(location = 1) out vec2 out_coord;",
+			"void main() {",
+			"gl_Position = result.out_;",
+		], &[
+			"layout(std140, set = 3, binding = 0) uniform Props {",
+			"layout(location = 0) out vec4 out_value;",
+			"out_value = my_frag_main(in_pos, in_coord);",
+		]);
+		let names: Vec<(&str, &str)> = shaders.iter().map(|s| (s.name.as_str(), s.extension())).collect();
+		assert_eq!(names, [("my_vert_main", "vert"), ("my_frag_main", "frag")]);
+		assert!(shaders.iter().all(|s| s.source.starts_with("#version 450\n")));
+	}
+
+	#[test]
+	fn same_interface_as_spirv() {
+		GlslShader::same_interface_as_spirv("
+			prop tint: f3;
+			prop tex: sampler2;
+			prop other: sampler4;
+			@frag(origin = lower_left)
+			fn main(coord: f2): f4 { sample(tex, coord) * f4(@frag.coord, tint.x, 1.0) }
+		", &TargetEnv::ALL.map(Target::new));
+	}
+
+	#[test]
+	fn targets() {
+		let source = "
+			prop tex: sampler2;
+			@frag
+			fn frag_main(coord: f2, id: u): f4 { f4(@frag.coord, f(id), 1.0) + sample(tex, coord) }
+			@vert
+			fn vert_main(pos: f2): f4 { f4(pos, f(@vert.index), 1.0) }
+		";
+		let vulkan = GlslShader::emit_for(source, Target::default()).unwrap();
+		assert_lines(&vulkan[0], &[
+			"layout(set = 2, binding = 0) uniform sampler2D tex;",
+			"layout(location = 1) flat in uint in_id;",
+			"return vec4(gl_FragCoord.xy, float(id), 1.0) + texture(tex, coord);",
+		]);
+		assert!(!vulkan[0].source.contains("origin_upper_left"));
+		// vertex shaders don't have derivatives to pick a mip level with
+		assert_lines(&vulkan[1], &["return vec4(pos, float(uint(gl_VertexIndex)), 1.0);"]);
+
+		let gl = GlslShader::emit_for(source, Target::new(TargetEnv::OpenGL4_5)).unwrap();
+		assert_lines(&gl[0], &[
+			"layout(binding = 0) uniform sampler2D tex;",
+			"layout(origin_upper_left) in vec4 gl_FragCoord;",
+		]);
+		assert_lines(&gl[1], &["return vec4(pos, float(uint(gl_VertexID)), 1.0);"]);
+		let lower_left = GlslShader::emit_for(source, Target::new(TargetEnv::OpenGL4_5).with_frag_origin(FragOrigin::LowerLeft)).unwrap();
+		assert!(!lower_left[0].source.contains("origin_upper_left"));
+
+		let err = GlslShader::emit_for("@vert fn main(): f4 { f4(f(@vert.instance)) }", Target::new(TargetEnv::OpenGL4_5));
+		assert!(matches!(err, Err(YuriCompileError::Semantic(err)) if err.error_type() == YuriSemanticErrorType::Unsupported));
+	}
+
+	#[test]
+	fn statements() {
+		let source = "
+			prop tex: sampler2;
+			@spec(3) let SAMPLES: u = 8;
+			let HALF = SAMPLES / 2;
+			fn shade(coord: f2, n: u, flip: bool): f4 {
+				let total = fold sum = f4(0.0), k: 3 { sum + sample(tex, coord * f(k)) };
+				let steps = loop k: 4u { k * HALF };
+				let pick = if n > 3u { let doubled = n * 2u; doubled % 5u } else { n };
+				let x = if flip { coord.x } else { coord.y };
+				let x = x % 1.5;
+				total * f4(x, f(steps[pick]), f(i(n) % -3), 1.0)
+			}
+			@frag
+			fn main(coord: f2, n: u): f4 { shade(coord, n, true) }
+		";
+		let shader = &GlslShader::emit_for(source, Target::default()).unwrap()[0];
+		assert_lines(shader, &[
+			"layout(constant_id = 3) const uint SAMPLES = 8u;",
+			"const uint HALF = SAMPLES / 2u;",
+			"vec4 sum = vec4(0.0);",
+			"for (int k = 0; k < 3; k++) {",
+			"sum = sum + texture(tex, coord * float(k));",
+			"uint[4] tmp;",
+			"for (uint k_1 = 0u; k_1 < 4u; k_1++) {",
+			"tmp[k_1] = k_1 * HALF;",
+			"if (n > 3u) {",
+			"uint doubled = n * 2u;",
+			"tmp_1 = doubled % 5u;",
+			// simple enough for `?:`
+			"float x = flip ? coord.x : coord.y;",
+			// Yuri's `%` isn't GLSL's `mod`
+			"float x_1 = x - 1.5 * trunc(x / 1.5);",
+		]);
+		// functions come before whatever calls them
+		assert!(shader.source.find("vec4 shade(").unwrap() < shader.source.find("vec4 main_(").unwrap());
+		// without the spec constant, there's nothing to keep around
+		let gl = &GlslShader::emit_for(source, Target::new(TargetEnv::OpenGL4_5)).unwrap()[0];
+		assert_lines(gl, &["const uint HALF = 4u;"]);
+		assert!(!gl.source.contains("SAMPLES"));
+	}
+
+	#[test]
+	fn names() {
+		let source = "
+			fn output(input: f, gl_thing: f, vec3: f): f { input + gl_thing + vec3 }
+			@vert
+			fn main(pos: f2): <| @vert.pos out: f4, texture: f2 |> {
+				<| out = f4(pos, output(pos.x, pos.y, 1.0), 1.0), texture = pos |>
+			}
+		";
+		let shader = &GlslShader::emit_for(source, Target::default()).unwrap()[0];
+		assert_lines(shader, &[
+			"float output_(float input_, float _gl_thing, float vec3_) {",
+			"vec4 out_;",
+			"vec2 texture_;",
+			"layout(location = 0) out vec2 out_texture;",
+			"Complex0 result = main_(in_pos);",
+			"out_texture = result.texture_;",
+		]);
+	}
+}
@@ -0,0 +1,232 @@
+//! The TextMate grammar for `yuri-code`, generated from the lexer's own tables so the highlighting can't fall behind the language.
+//! Every keyword has to be given a scope in [keyword_scope], and every operator and builtin is picked up on its own.
+//! The file in `yuri-code/syntaxes` is checked against this by a test, which rewrites it when run with `YURI_UPDATE_GRAMMAR=1`.
+use std::fmt::Write;
+use crate::builtin::{BuiltinFunction, BuiltinInput};
+use crate::lex::{Keyword, NumberSuffix, Operator};
+
+/// Where the grammar goes, relative to the crate.
+pub const GRAMMAR_PATH: &str = "yuri-code/syntaxes/Yuri.tmLanguage.json";
+
+/// What a keyword gets highlighted as. There's no catch-all on purpose, so a new keyword doesn't compile until it has one.
+pub fn keyword_scope(keyword: Keyword) -> &'static str {
+	use Keyword::*;
+	match keyword {
+		Fn | Let | Prop | Module => "storage.type.yuri",
+		Export => "storage.modifier.yuri",
+		Import => "keyword.control.import.yuri",
+		Loop | Fold | Map | Filter | Switch | If | Else | Return => "keyword.control.yuri",
+		And | Xor | Or | Nor => "keyword.operator.word.yuri",
+		Core => "keyword.other.reserved.yuri",
+		TypeBool | TypeF | TypeU | TypeI | TypeF2 | TypeU2 | TypeI2 | TypeF3 | TypeU3 | TypeI3 | TypeF4 | TypeU4 | TypeI4
+			| TypeM2 | TypeM3 | TypeM4 | TypeSampler1 | TypeSampler2 | TypeSampler3 | TypeSampler4 => "storage.type.primitive.yuri",
+	}
+}
+
+/// Just enough JSON to write a grammar out, with tabs like the rest of `yuri-code`.
+enum Json {
+	String(String),
+	Array(Vec<Json>),
+	Object(Vec<(String, Json)>),
+}
+
+impl Json {
+	fn object<const N: usize>(entries: [(&str, Json); N]) -> Json {
+		Json::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+	}
+
+	fn write(&self, out: &mut String, indent: usize) {
+		let tabs = |n: usize| "\t".repeat(n);
+		match self {
+			Json::String(s) => {
+				out.push('"');
+				for ch in s.chars() {
+					match ch {
+						'"' => out.push_str("\\\""),
+						'\\' => out.push_str("\\\\"),
+						'\n' => out.push_str("\\n"),
+						_ => out.push(ch),
+					}
+				}
+				out.push('"');
+			}
+			Json::Array(items) => {
+				out.push_str("[\n");
+				for (i, item) in items.iter().enumerate() {
+					out.push_str(&tabs(indent + 1));
+					item.write(out, indent + 1);
+					out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+				}
+				let _ = write!(out, "{}]", tabs(indent));
+			}
+			Json::Object(entries) => {
+				out.push_str("{\n");
+				for (i, (key, value)) in entries.iter().enumerate() {
+					let _ = write!(out, "{}\"{key}\": ", tabs(indent + 1));
+					value.write(out, indent + 1);
+					out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+				}
+				let _ = write!(out, "{}}}", tabs(indent));
+			}
+		}
+	}
+}
+
+fn string(s: impl Into<String>) -> Json {
+	Json::String(s.into())
+}
+
+/// A pattern that gives everything it matches one scope.
+fn matching(regex: impl Into<String>, scope: &str) -> Json {
+	Json::object([("match", string(regex)), ("name", string(scope))])
+}
+
+fn include(name: &str) -> Json {
+	Json::object([("include", string(format!("#{name}")))])
+}
+
+/// Oniguruma wants these escaped.
+fn escape(text: &str) -> String {
+	text.chars()
+		.flat_map(|ch| {
+			let special = "\\^$.|?*+()[]{}".contains(ch);
+			special.then_some('\\').into_iter().chain(std::iter::once(ch))
+		})
+		.collect()
+}
+
+/// Identifiers can have dots in them, so a keyword is only a keyword when there's no identifier character on either side.
+const BEFORE: &str = "(?<![\\p{L}\\p{N}_.])";
+const AFTER: &str = "(?![\\p{L}\\p{N}_.])";
+const IDENTIFIER: &str = "[\\p{L}_][\\p{L}\\p{N}_.]*";
+
+/// Matches any of the words, and only as whole words.
+fn words<'a>(words: impl IntoIterator<Item = &'a str>) -> String {
+	let mut words: Vec<&str> = words.into_iter().collect();
+	// longest first, so nothing gets cut short
+	words.sort_by_key(|word| std::cmp::Reverse(word.len()));
+	let words: Vec<String> = words.into_iter().map(escape).collect();
+	format!("{BEFORE}(?:{}){AFTER}", words.join("|"))
+}
+
+/// A declaration keyword, and the name after it.
+fn declaration(keyword: Keyword, name_scope: &str) -> Json {
+	let keyword_text: &str = keyword.into();
+	Json::object([
+		("match", string(format!("{BEFORE}({})\\s+({IDENTIFIER})", escape(keyword_text)))),
+		("captures", Json::object([
+			("1", Json::object([("name", string(keyword_scope(keyword)))])),
+			("2", Json::object([("name", string(name_scope))])),
+		])),
+	])
+}
+
+pub fn textmate_grammar() -> String {
+	// keywords that share a scope share a pattern, in the order the lexer lists them
+	let mut keyword_groups: Vec<(&str, Vec<&str>)> = Vec::new();
+	for keyword in Keyword::ALL {
+		let scope = keyword_scope(keyword);
+		match keyword_groups.iter_mut().find(|(s, _)| *s == scope) {
+			Some((_, group)) => group.push(keyword.into()),
+			None => keyword_groups.push((scope, vec![keyword.into()])),
+		}
+	}
+	let keywords = keyword_groups.into_iter()
+		.map(|(scope, group)| matching(words(group), scope))
+		.collect();
+
+	let suffixes: Vec<&str> = NumberSuffix::ALL.iter().map(|suffix| suffix.name()).collect();
+	let number = format!(
+		"{BEFORE}(?:0x[0-9a-fA-F_]*(?:\\.[0-9a-fA-F_]*)?(?:[pP][+-]?[0-9_]+)?|0b[01_]+|(?:[0-9][0-9_]*(?:\\.[0-9_]*)?|\\.[0-9][0-9_]*)(?:[eE][+-]?[0-9_]+)?)(?:{})?{AFTER}",
+		suffixes.join("|"),
+	);
+
+	let operators: Vec<&str> = Operator::ALL.iter().map(|operator| (*operator).into()).collect();
+	let mut operators: Vec<String> = operators.into_iter().map(escape).collect();
+	operators.sort_by_key(|operator| std::cmp::Reverse(operator.len()));
+
+	let inputs: Vec<String> = BuiltinInput::ALL.iter().map(|input| escape(input.name())).collect();
+
+	let grammar = Json::object([
+		("$schema", string("https://json.schemastore.org/tmlanguage.json")),
+		("name", string("Yuri")),
+		("scopeName", string("source.yuri")),
+		("patterns", Json::Array(["comments", "annotations", "declarations", "keywords", "constants", "numbers", "builtins", "calls", "identifiers", "punctuation", "operators"]
+			.into_iter()
+			.map(include)
+			.collect())),
+		("repository", Json::object([
+			("comments", Json::object([("patterns", Json::Array(vec![
+				Json::object([
+					("begin", string("##")),
+					("end", string("##")),
+					("captures", Json::object([("0", Json::object([("name", string("punctuation.definition.comment.yuri"))]))])),
+					("name", string("comment.block.yuri")),
+				]),
+				Json::object([
+					("match", string("(#).*$")),
+					("captures", Json::object([("1", Json::object([("name", string("punctuation.definition.comment.yuri"))]))])),
+					("name", string("comment.line.number-sign.yuri")),
+				]),
+			]))])),
+			("annotations", Json::object([("patterns", Json::Array(vec![
+				// builtin inputs look like annotations, but they're values (and can be swizzled, like `@frag.coord.x`)
+				matching(format!("@(?:{})(?:\\.[\\p{{L}}\\p{{N}}_]+)*{AFTER}", inputs.join("|")), "support.variable.builtin.yuri"),
+				Json::object([
+					("match", string(format!("(@)({IDENTIFIER})"))),
+					("captures", Json::object([
+						("1", Json::object([("name", string("punctuation.definition.annotation.yuri"))])),
+						("2", Json::object([("name", string("storage.type.annotation.yuri"))])),
+					])),
+					("name", string("meta.annotation.yuri")),
+				]),
+			]))])),
+			("declarations", Json::object([("patterns", Json::Array(vec![
+				declaration(Keyword::Fn, "entity.name.function.yuri"),
+				declaration(Keyword::Let, "variable.other.constant.yuri"),
+				declaration(Keyword::Prop, "variable.other.property.yuri"),
+				declaration(Keyword::Module, "entity.name.namespace.yuri"),
+				declaration(Keyword::Import, "entity.name.namespace.yuri"),
+			]))])),
+			("keywords", Json::object([("patterns", Json::Array(keywords))])),
+			("constants", matching(words(["true", "false"]), "constant.language.boolean.yuri")),
+			("numbers", matching(number, "constant.numeric.yuri")),
+			("builtins", matching(words(BuiltinFunction::ALL.iter().map(|function| function.name())), "support.function.builtin.yuri")),
+			("calls", matching(format!("{IDENTIFIER}(?=\\s*\\()"), "entity.name.function.call.yuri")),
+			("identifiers", matching(IDENTIFIER, "variable.other.yuri")),
+			("punctuation", Json::object([("patterns", Json::Array(vec![
+				matching("<\\|", "punctuation.definition.complex.begin.yuri"),
+				matching("\\|>", "punctuation.definition.complex.end.yuri"),
+				matching(";", "punctuation.terminator.yuri"),
+				matching(",", "punctuation.separator.yuri"),
+				matching(":", "punctuation.separator.type.yuri"),
+				matching("[()\\[\\]{}]", "punctuation.bracket.yuri"),
+			]))])),
+			("operators", Json::object([("patterns", Json::Array(vec![
+				matching(operators.join("|"), "keyword.operator.yuri"),
+				matching("=", "keyword.operator.assignment.yuri"),
+				matching("\\?", "keyword.operator.optional.yuri"),
+			]))])),
+		])),
+	]);
+	let mut out = String::new();
+	grammar.write(&mut out, 0);
+	out.push('\n');
+	out
+}
+
+#[cfg(test)]
+mod test {
+	use crate::grammar::{textmate_grammar, GRAMMAR_PATH};
+
+	#[test]
+	fn grammar_is_up_to_date() {
+		let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(GRAMMAR_PATH);
+		let grammar = textmate_grammar();
+		if std::env::var_os("YURI_UPDATE_GRAMMAR").is_some() {
+			std::fs::write(&path, &grammar).unwrap();
+		}
+		let existing = std::fs::read_to_string(&path).unwrap();
+		assert!(existing == grammar, "{GRAMMAR_PATH} is out of date, run the tests with YURI_UPDATE_GRAMMAR=1 to regenerate it");
+	}
+}
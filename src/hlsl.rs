@@ -0,0 +1,277 @@
+//! HLSL generation, for D3D12 (through DXC, into DXIL for Shader Model 6).
+//! Every entry point gets a source of its own, with an entry point called `main` and the same interface
+//! SDL's GPU API expects from HLSL: vertex inputs and everything between the stages use `TEXCOORD` semantics
+//! numbered by location, samplers are `t[n]` and `s[n]`, and the props are `b0`, in the same spaces
+//! as the SPIR-V's descriptor sets (0 and 1 for vertex shaders, 2 and 3 for fragment shaders).
+//!
+//! Yuri's matrices come out transposed (and `row_major`), so the columns are where std140 puts them,
+//! and `mul` gets its arguments the other way around. See [crate::c_like] for the rest.
+use std::fmt::Write;
+use crate::builtin::FragOrigin;
+use crate::c_like::{Dialect, Emitter};
+use crate::check::{FunctionId, InterfaceSlot, InterfaceVariable, ShaderStage, TypedProgram};
+use crate::compile::{check_props, entry_interface, interpolated, prepare_program, reads_frag_coord};
+use crate::error::YuriSemanticError;
+use crate::options::CompileOptions;
+use crate::parse::YuriType;
+use crate::text::{Emit, Scope, call_order, sections};
+
+/// A single entry point, as HLSL source. The entry point in it is always called `main`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HlslShader {
+	/// The fully-qualified name of the entry point function, which `main` calls.
+	pub name: String,
+	pub stage: ShaderStage,
+	pub source: String,
+	/// How many samplers the shader uses, each of which is a texture and a sampler with the same register number.
+	pub samplers: u32,
+	/// How many uniform buffers the shader uses (zero or one, since every prop goes in the same struct).
+	pub uniform_buffers: u32,
+	/// Where the height of the render target has to go in the uniform buffer, in bytes.
+	/// Same as [crate::compile::CompiledShader::viewport_height_offset].
+	pub viewport_height_offset: Option<u32>,
+}
+
+/// HLSL wants flat inputs marked on both sides.
+fn interpolation(ty: &YuriType) -> &'static str {
+	if interpolated(ty) { "" } else { "nointerpolation " }
+}
+
+/// Writes a single entry point as HLSL, with a `main` that takes the inputs, calls it and returns the outputs.
+pub fn emit_entry_point(program: &TypedProgram, entry: FunctionId, options: &CompileOptions) -> Result<HlslShader, YuriSemanticError> {
+	check_props(program)?;
+	let function = &program.functions[entry];
+	let (stage, interface) = entry_interface(function)?;
+	let order = call_order(program, entry);
+	// D3D puts the origin in the upper left, like Vulkan
+	let origin = function.frag_origin.unwrap_or(options.target.frag_origin());
+	let flip = origin != FragOrigin::UpperLeft && reads_frag_coord(program, stage, &order);
+	let mut emitter = Emitter::new(program, Dialect::Hlsl, stage, options.target);
+	emitter.writer.names.insert("main".to_string());
+	let viewport_height_offset = emitter.declare_props(flip)?;
+
+	let mut functions = Vec::new();
+	for id in &order {
+		functions.push(emitter.function(*id)?);
+	}
+	emitter.writer.scope = Scope { depth: 1, ..Scope::default() };
+	let mut parameters = Vec::new();
+	let mut arguments = Vec::new();
+	for input in &interface.inputs {
+		let ty = emitter.type_name(&input.variable_type);
+		let name = emitter.local_name(&input.name);
+		let InterfaceSlot::Location(location) = input.slot else {
+			unreachable!("inputs always have a location");
+		};
+		let interpolation = if stage == ShaderStage::Fragment { interpolation(&input.variable_type) } else { "" };
+		parameters.push(format!("{interpolation}{ty} {name} : TEXCOORD{location}"));
+		arguments.push(name);
+	}
+	for (_, resource) in emitter.builtins.clone() {
+		let resource = emitter.resources[resource].clone();
+		let (ty, name) = resource.parameter.split_once(' ').expect("builtin parameters have a type and a name");
+		let name = emitter.local_name(name);
+		parameters.push(format!("{ty} {name} : {}", resource.attribute));
+		emitter.line(format!("{} = {name};", resource.name));
+	}
+	let semantic = |output: &InterfaceVariable| match output.slot {
+		InterfaceSlot::Position => "SV_Position".to_string(),
+		InterfaceSlot::Location(location) if stage == ShaderStage::Vertex => format!("TEXCOORD{location}"),
+		InterfaceSlot::Location(location) => format!("SV_Target{location}"),
+	};
+	let call = emitter.call(entry, arguments);
+	let return_type;
+	let mut return_semantic = String::new();
+	if interface.complex_output {
+		let YuriType::Complex(fields) = &function.return_type else {
+			unreachable!("complex outputs come from complexes");
+		};
+		let name = emitter.global_name("Output");
+		let result_type = emitter.type_name(&function.return_type);
+		let mut members = String::new();
+		for (output, field) in interface.outputs.iter().zip(fields) {
+			let ty = emitter.type_name(&output.variable_type);
+			let interpolation = if stage == ShaderStage::Vertex { interpolation(&output.variable_type) } else { "" };
+			let _ = writeln!(members, "\t{interpolation}{ty} {} : {};", Dialect::Hlsl.sanitize(&field.name), semantic(output));
+		}
+		let _ = writeln!(emitter.writer.struct_declarations, "struct {name} {{\n{members}}};");
+		let result = emitter.local_name("result");
+		let output = emitter.local_name("output");
+		emitter.line(format!("{result_type} {result} = {call};"));
+		emitter.line(format!("{name} {output};"));
+		for field in fields {
+			let field = Dialect::Hlsl.sanitize(&field.name);
+			emitter.line(format!("{output}.{field} = {result}.{field};"));
+		}
+		emitter.line(format!("return {output};"));
+		return_type = name;
+	} else if let Some(output) = interface.outputs.first() {
+		return_type = emitter.type_name(&output.variable_type);
+		return_semantic = format!(" : {}", semantic(output));
+		emitter.line(format!("return {call};"));
+	} else {
+		return_type = "void".to_string();
+		emitter.line(format!("{call};"));
+	}
+	functions.push(format!("{return_type} main({}){return_semantic} {{\n{}}}\n", parameters.join(", "), emitter.writer.scope.body));
+
+	Ok(HlslShader {
+		name: function.name.clone(),
+		stage,
+		source: sections([
+			emitter.writer.struct_declarations,
+			emitter.writer.resource_declarations,
+			emitter.writer.global_declarations,
+			functions.join("\n"),
+		]),
+		samplers: emitter.writer.samplers,
+		uniform_buffers: emitter.writer.uniform_buffer as u32,
+		viewport_height_offset,
+	})
+}
+
+/// Writes every entry point in the program (or just the ones the options ask for) as HLSL,
+/// after folding whatever can be worked out ahead of time.
+pub fn emit_program(program: &TypedProgram, options: &CompileOptions) -> Result<Vec<HlslShader>, YuriSemanticError> {
+	let (program, entry_points) = prepare_program(program, options)?;
+	entry_points.into_iter()
+		.map(|id| emit_entry_point(&program, id, options))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use crate::hlsl::HlslShader;
+	use crate::target::Target;
+	use crate::text::test_support::{assert_lines, source_shader, SourceShader};
+
+	source_shader!(HlslShader, hlsl_sources);
+
+	#[test]
+	fn emit_basic() {
+		HlslShader::emit_basic(&[
+			"ConstantBuffer<Props> props : register(b0, space1);",
+			"row_major float4x4 transform;",
+			"float4 out_ : SV_Position;",
+			// `@vert.pos` doesn't take up a location
+			"float3 pos : TEXCOORD0;",
+			"float2 coord : TEXCOORD1;",
+			"Output main(float3 pos : TEXCOORD0, float2 coord : TEXCOORD1) {",
+			"output.coord = result.coord;",
+		], &[
+			"ConstantBuffer<Props> props : register(b0, space3);",
+			"float4 main(float3 pos : TEXCOORD0, float2 coord : TEXCOORD1) : SV_Target0 {",
+		]);
+	}
+
+	#[test]
+	fn same_interface_as_spirv() {
+		HlslShader::same_interface_as_spirv("
+			prop tint: f3;
+			prop lights: <| color: f3, strength: f |>[2];
+			prop weights: f[3];
+			prop tex: sampler2;
+			prop other: sampler4;
+			prop last: m2;
+			@frag(origin = lower_left)
+			fn main(coord: f2): f4 { let column = last[0]; sample(tex, coord) * f4(@frag.coord, tint.x + weights[2], column.x) }
+		", &[Target::default()]);
+	}
+
+	#[test]
+	fn uniform_layout() {
+		let shader = HlslShader::emit("
+			prop tex: sampler2;
+			prop block: <| a: f, b: f2 |>;
+			prop weights: f[3];
+			prop last: m3;
+			prop cut: f;
+			@frag
+			fn main(coord: f2): f4 { sample(tex, coord) * f4(block.a, weights[1], cut, 1.0) }
+		");
+		assert_lines(&shader, &[
+			"Texture2D<float4> tex : register(t0, space2);",
+			"SamplerState tex_sampler : register(s0, space2);",
+			// HLSL would put `b` right after `a`, and `cut` in the last register of `last`
+			"float _pad;",
+			"float2 b;",
+			// arrays go in a struct, which starts every element on a register of its own like std140
+			"float value[3];",
+			"Array1 weights;",
+			"row_major float3x3 last;",
+			"return tex.Sample(tex_sampler, coord) * float4(props.block.a, props.weights.value[1u], props.cut, 1.0);",
+		]);
+	}
+
+	#[test]
+	fn statements() {
+		let shader = HlslShader::emit("
+			prop mats: m3[2];
+			@spec(3) let SAMPLES: u = 8;
+			let HALF = SAMPLES / 2;
+			fn shade(coord: f2, n: u, flip: bool, s: sampler2): f4 {
+				let total = fold sum = f4(0.0), k: 3 { sum + sample(s, coord * f(k)) };
+				let steps = loop k: 4u { k * HALF };
+				let x = if flip { coord.x } else { coord.y };
+				let m = -(mats[n] / 2.0);
+				let v = m * f3(x) + f3(x) * m;
+				let w = m3(v, v, v) * m;
+				let column = w[0];
+				let c = <| a = x, b = steps |>;
+				total * f4(v.x, column.x, x % 1.5, sign(c.a))
+			}
+			prop tex: sampler2;
+			@frag
+			fn main(coord: f2, n: u): f4 { shade(coord, n, true, tex) }
+		");
+		assert_lines(&shader, &[
+			// there's nothing to specialize
+			"static const uint HALF = 4u;",
+			"float4 shade(float2 coord, uint n, bool flip, Texture2D<float4> s, SamplerState s_sampler) {",
+			"float4 sum = float4(0.0, 0.0, 0.0, 0.0);",
+			"sum = sum + s.Sample(s_sampler, coord * ((float)k));",
+			"Array1 tmp;",
+			"tmp.value[k_1] = k_1 * HALF;",
+			"float x = flip ? coord.x : coord.y;",
+			"float3x3 m = -(props.mats.value[n] / 2.0);",
+			// the matrices are transposed, so everything gets multiplied the other way around
+			"float3 v = mul((float3)x, m) + mul(m, (float3)x);",
+			"float3x3 w = mul(m, float3x3(v, v, v));",
+			// structs only have initializers
+			"Complex2 tmp_1 = { x, steps };",
+			"return total * float4(v.x, column.x, x % 1.5, (float)sign(c.a));",
+			"return shade(coord, n, true, tex, tex_sampler);",
+		]);
+	}
+
+	#[test]
+	fn builtin_inputs() {
+		let shader = HlslShader::emit("
+			@frag(origin = lower_left)
+			fn main(id: u): f4 { f4(@frag.coord, f(id), if @frag.front_facing { 1.0 } else { 0.0 }) }
+		");
+		assert_lines(&shader, &[
+			"float viewport_height;",
+			"static float4 frag_coord;",
+			"static bool front_facing;",
+			"return float4(float2(frag_coord.x, props.viewport_height - frag_coord.y), (float)id, front_facing ? 1.0 : 0.0);",
+			"float4 main(nointerpolation uint id : TEXCOORD0, float4 frag_coord_1 : SV_Position, bool front_facing_1 : SV_IsFrontFace) : SV_Target0 {",
+			"frag_coord = frag_coord_1;",
+		]);
+		let shader = HlslShader::emit("@vert fn main(): <| @vert.pos pos: f4, id: u |> { <| pos = f4(1.0), id = 0u |> }");
+		assert_lines(&shader, &["nointerpolation uint id : TEXCOORD0;"]);
+	}
+
+	#[test]
+	fn unsupported() {
+		HlslShader::assert_unsupported("@vert fn main(v: f4): f4 { let m = inverse(m4(v, v, v, v)); m[0] }");
+		// D3D doesn't count them the same way
+		HlslShader::assert_unsupported("@vert fn main(): f4 { f4(f(@vert.index)) }");
+		HlslShader::assert_unsupported("@vert fn main(): f4 { f4(f(@vert.instance)) }");
+		HlslShader::assert_unsupported("
+			prop a: sampler2;
+			prop b: sampler2;
+			@frag fn main(c: f2, n: i): f4 { let s = if n > 0 { a } else { b }; sample(s, c) }
+		");
+	}
+}
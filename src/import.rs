@@ -0,0 +1,271 @@
+//! Resolution of `import` declarations.
+//! Imported modules are loaded through a [SourceLoader], so the compiler doesn't need to care
+//! whether the source text came from the filesystem or from somebody's asset pack.
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use crate::error::{YuriCompileError, YuriSemanticError, YuriSemanticErrorType};
+use crate::lex::lex_file;
+use crate::parse::{parse_input, ImportDeclaration, YuriModule};
+use crate::source::{FileId, SourceMap};
+
+/// The source text of a module, along with where it came from.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct LoadedSource {
+	/// A human-readable description of where the source came from, usually a path.
+	pub origin: String,
+	pub text: String,
+}
+
+/// Something that can find the source text of a module from its dotted name (like `lighting.pbr`).
+pub trait SourceLoader {
+	/// Loads the module with the given name.
+	/// If the module doesn't exist, this should return an error of kind [io::ErrorKind::NotFound].
+	fn load(&self, module: &str) -> io::Result<LoadedSource>;
+}
+
+/// Loads modules from `.yuri` files, looking through each search path in order.
+/// The module `lighting.pbr` is found at `<search path>/lighting/pbr.yuri`.
+#[derive(Debug, Default, Clone)]
+pub struct FileSystemLoader {
+	search_paths: Vec<PathBuf>,
+}
+
+impl FileSystemLoader {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_search_path(mut self, path: impl Into<PathBuf>) -> Self {
+		self.search_paths.push(path.into());
+		self
+	}
+
+	pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+		self.search_paths.push(path.into());
+	}
+
+	pub fn search_paths(&self) -> &[PathBuf] {
+		&self.search_paths
+	}
+}
+
+impl SourceLoader for FileSystemLoader {
+	fn load(&self, module: &str) -> io::Result<LoadedSource> {
+		let relative: PathBuf = module.split('.').collect();
+		let relative = relative.with_extension("yuri");
+		for search_path in &self.search_paths {
+			let path = search_path.join(&relative);
+			match std::fs::read_to_string(&path) {
+				Ok(text) => return Ok(LoadedSource {
+					origin: path.display().to_string(),
+					text,
+				}),
+				Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+				Err(err) => return Err(err),
+			}
+		}
+		Err(io::Error::new(
+			io::ErrorKind::NotFound,
+			format!("no file named {} in any search path", relative.display())
+		))
+	}
+}
+
+/// Serves modules out of memory, for when the sources don't live on disk.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryLoader {
+	sources: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_module(mut self, module: impl Into<String>, source: impl Into<String>) -> Self {
+		self.insert(module, source);
+		self
+	}
+
+	pub fn insert(&mut self, module: impl Into<String>, source: impl Into<String>) {
+		self.sources.insert(module.into(), source.into());
+	}
+}
+
+impl SourceLoader for MemoryLoader {
+	fn load(&self, module: &str) -> io::Result<LoadedSource> {
+		self.sources.get(module)
+			.map(|text| LoadedSource {
+				origin: format!("<memory:{module}>"),
+				text: text.clone(),
+			})
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no module named {module} was provided")))
+	}
+}
+
+/// An imported module after it's been loaded and parsed.
+#[derive(Debug, Clone)]
+pub struct ImportedModule {
+	/// The dotted name the module was imported with.
+	pub name: String,
+	/// Where the module's source ended up in the [SourceMap].
+	pub file: FileId,
+	pub module: YuriModule,
+}
+
+/// Every module (transitively) imported by a root module.
+#[derive(Debug, Default, Clone)]
+pub struct ResolvedImports {
+	/// Sorted so that every module comes after the modules it imports.
+	pub modules: Vec<ImportedModule>,
+}
+
+impl ResolvedImports {
+	pub fn get(&self, name: &str) -> Option<&ImportedModule> {
+		self.modules.iter().find(|m| m.name == name)
+	}
+
+	/// The fully-qualified names of every declaration in the imported modules,
+	/// like `lighting.pbr.brdf`.
+	pub fn qualified_names(&self) -> Vec<String> {
+		self.modules.iter()
+			.flat_map(|m| {
+				m.module.declaration_names()
+					.into_iter()
+					.map(|decl| format!("{}.{decl}", m.name))
+			})
+			.collect()
+	}
+}
+
+struct ImportResolver<'a> {
+	loader: &'a dyn SourceLoader,
+	sources: &'a mut SourceMap,
+	resolved: ResolvedImports,
+	/// The chain of modules currently being loaded, used to detect cycles.
+	stack: Vec<String>,
+}
+
+impl ImportResolver<'_> {
+	fn resolve(&mut self, import: &ImportDeclaration) -> Result<(), YuriCompileError> {
+		let name = &import.module;
+		if let Some(position) = self.stack.iter().position(|m| m == name) {
+			let mut chain = self.stack[position..].to_vec();
+			chain.push(name.clone());
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::ImportCycle,
+				description: Some(format!("The import % creates a cycle ({})", chain.join(" -> "))),
+				markers: vec![import.location.clone()],
+			}.into());
+		}
+		if self.resolved.get(name).is_some() {
+			// only load each module once
+			return Ok(());
+		}
+
+		let source = self.loader.load(name)
+			.map_err(|err| YuriSemanticError {
+				error_type: YuriSemanticErrorType::ModuleNotFound,
+				description: Some(format!("Couldn't load the module `{name}` imported at % ({err})")),
+				markers: vec![import.location.clone()],
+			})?;
+		let file = self.sources.add(source.origin, source.text);
+		let ast = lex_file(self.sources.file(file))?;
+		let module = parse_input(&ast)?;
+
+		self.stack.push(name.clone());
+		for dependency in module.all_imports() {
+			self.resolve(dependency)?;
+		}
+		self.stack.pop();
+
+		self.resolved.modules.push(ImportedModule {
+			name: name.clone(),
+			file,
+			module,
+		});
+		Ok(())
+	}
+}
+
+/// Loads every module imported by `root`, and everything those modules import.
+/// Their sources are added to the map, which should already have the root's source in it.
+pub fn resolve_imports(root: &YuriModule, sources: &mut SourceMap, loader: &dyn SourceLoader) -> Result<ResolvedImports, YuriCompileError> {
+	let mut resolver = ImportResolver {
+		loader,
+		sources,
+		resolved: ResolvedImports::default(),
+		stack: Vec::new(),
+	};
+	for import in root.all_imports() {
+		resolver.resolve(import)?;
+	}
+	Ok(resolver.resolved)
+}
+
+#[cfg(test)]
+mod test {
+	use crate::error::{YuriCompileError, YuriSemanticErrorType};
+	use crate::import::{resolve_imports, FileSystemLoader, MemoryLoader, ResolvedImports, SourceLoader};
+	use crate::source::SourceMap;
+	use crate::YuriShader;
+
+	fn resolve(source: &str, loader: &dyn SourceLoader) -> Result<(ResolvedImports, SourceMap), YuriCompileError> {
+		let mut sources = SourceMap::new();
+		sources.add("<test>", source);
+		let root = YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap();
+		let resolved = resolve_imports(&root, &mut sources, loader)?;
+		Ok((resolved, sources))
+	}
+
+	#[test]
+	fn memory_imports() {
+		let loader = MemoryLoader::new()
+			.with_module("lighting.pbr", "import util; fn brdf(n: f3): f { util.saturate(n[0]) }")
+			.with_module("util", "fn saturate(x: f): f { x }")
+			.with_module("noise", "import util; let seed: u = 7;");
+		let (resolved, sources) = resolve("import lighting.pbr; import noise;", &loader).unwrap();
+		let names: Vec<&str> = resolved.modules.iter().map(|m| m.name.as_str()).collect();
+		// util is shared, but only loaded once (and before anything that needs it)
+		assert_eq!(names, ["util", "lighting.pbr", "noise"]);
+		assert_eq!(
+			resolved.qualified_names(),
+			["util.saturate", "lighting.pbr.brdf", "noise.seed"]
+		);
+		assert_eq!(sources.file(resolved.modules[0].file).name(), "<memory:util>");
+		// locations in imported modules point into their own file
+		let seed = &resolved.modules[2].module.globals[0].location;
+		assert_eq!(sources.lookup(seed.start).unwrap().id(), resolved.modules[2].file);
+		assert_eq!(sources.snippet(seed).map(|s| s.starts_with("let seed")), Some(true));
+	}
+
+	#[test]
+	fn import_cycles() {
+		let loader = MemoryLoader::new()
+			.with_module("a", "import b;")
+			.with_module("b", "import c;")
+			.with_module("c", "import a;");
+		let err = resolve("import a;", &loader).unwrap_err();
+		let YuriCompileError::Semantic(err) = err else { panic!("expected a semantic error, got {err:?}") };
+		assert_eq!(err.error_type(), YuriSemanticErrorType::ImportCycle);
+		assert!(err.description.unwrap().contains("a -> b -> c -> a"));
+	}
+
+	#[test]
+	fn missing_modules() {
+		let loader = FileSystemLoader::new().with_search_path(env!("CARGO_MANIFEST_DIR"));
+		let err = resolve("import does.not.exist;", &loader).unwrap_err();
+		let YuriCompileError::Semantic(err) = err else { panic!("expected a semantic error, got {err:?}") };
+		assert_eq!(err.error_type(), YuriSemanticErrorType::ModuleNotFound);
+		assert_eq!(err.markers, [0..22]);
+	}
+
+	#[test]
+	fn filesystem_imports() {
+		let loader = FileSystemLoader::new().with_search_path(env!("CARGO_MANIFEST_DIR"));
+		let (resolved, sources) = resolve("import basic;", &loader).unwrap();
+		assert!(resolved.qualified_names().contains(&"basic.my_frag_main".to_string()));
+		assert!(sources.file(resolved.modules[0].file).name().ends_with("basic.yuri"));
+	}
+}
@@ -0,0 +1,271 @@
+//! Runs Yuri functions on the CPU, for testing shader math without a GPU (or without setting one up).
+//! It's the constant evaluator with the props, builtin inputs and specialization constants filled in,
+//! so it has the same 32-bit semantics as everything else that works out values ahead of time:
+//! floats are `f32`, integers wrap, and the builtins follow GLSL.std.450.
+//! Anything the GPU leaves undefined, like dividing an integer by zero or indexing out of bounds, is an error.
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use crate::builtin::BuiltinInput;
+use crate::check::{FunctionId, GlobalId, PropId, TypedFunction, TypedProgram};
+use crate::consteval::{ConstContext, ConstEvaluator, ConstValue};
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+
+pub struct Interpreter<'a> {
+	program: &'a TypedProgram,
+	functions: Vec<Rc<TypedFunction>>,
+	properties: Vec<Option<ConstValue>>,
+	inputs: HashMap<BuiltinInput, ConstValue>,
+	/// Values for specialization constants, instead of their defaults.
+	specializations: HashMap<GlobalId, ConstValue>,
+	/// Globals that depend on a specialization constant that's been changed, worked out again.
+	specialized: HashMap<GlobalId, ConstValue>,
+}
+
+fn runtime_error(description: String) -> YuriSemanticError {
+	YuriSemanticError { error_type: YuriSemanticErrorType::Runtime, description: Some(description), markers: vec![] }
+}
+
+impl<'a> Interpreter<'a> {
+	pub fn new(program: &'a TypedProgram) -> Self {
+		Self {
+			program,
+			functions: program.functions.iter().cloned().map(Rc::new).collect(),
+			properties: vec![None; program.properties.len()],
+			inputs: HashMap::new(),
+			specializations: HashMap::new(),
+			specialized: HashMap::new(),
+		}
+	}
+
+	/// Gives a prop (by its fully-qualified name) a value, which has to be of the prop's type.
+	pub fn set_prop(&mut self, name: &str, value: impl Into<ConstValue>) -> Result<(), YuriSemanticError> {
+		let value = value.into();
+		let Some(id) = self.program.properties.iter().position(|p| p.name == name) else {
+			return Err(runtime_error(format!("There's no prop named `{name}`")));
+		};
+		let property_type = &self.program.properties[id].property_type;
+		if !value.has_type(property_type) {
+			return Err(runtime_error(format!("The prop `{name}` is a `{property_type}`, which {value:?} isn't")));
+		}
+		self.properties[id] = Some(value);
+		Ok(())
+	}
+
+	pub fn set_input(&mut self, input: BuiltinInput, value: impl Into<ConstValue>) -> Result<(), YuriSemanticError> {
+		let value = value.into();
+		if !value.has_type(&input.input_type()) {
+			return Err(runtime_error(format!("`@{}` is a `{}`, which {value:?} isn't", input.name(), input.input_type())));
+		}
+		self.inputs.insert(input, value);
+		Ok(())
+	}
+
+	/// Changes the value of a specialization constant (a `let` with `@spec`), like the pipeline would.
+	pub fn set_specialization(&mut self, name: &str, value: impl Into<ConstValue>) -> Result<(), YuriSemanticError> {
+		let value = value.into();
+		let Some(id) = self.program.globals.iter().position(|g| g.name == name && g.spec_id.is_some()) else {
+			return Err(runtime_error(format!("There's no specialization constant named `{name}`")));
+		};
+		let global_type = &self.program.globals[id].global_type;
+		if !value.has_type(global_type) {
+			return Err(runtime_error(format!("The specialization constant `{name}` is a `{global_type}`, which {value:?} isn't")));
+		}
+		self.specializations.insert(id, value);
+		self.specialized.clear();
+		Ok(())
+	}
+
+	/// Runs a function (by its fully-qualified name) with the given arguments.
+	pub fn call(&mut self, name: &str, arguments: &[ConstValue]) -> Result<ConstValue, YuriSemanticError> {
+		let Some(id) = self.program.functions.iter().position(|f| f.name == name) else {
+			return Err(runtime_error(format!("There's no function named `{name}`")));
+		};
+		self.call_id(id, arguments)
+	}
+
+	pub fn call_id(&mut self, id: FunctionId, arguments: &[ConstValue]) -> Result<ConstValue, YuriSemanticError> {
+		let function = self.functions[id].clone();
+		if arguments.len() != function.arguments.len() {
+			return Err(runtime_error(format!(
+				"`{}` takes {} arguments, not {}",
+				function.name,
+				function.arguments.len(),
+				arguments.len(),
+			)));
+		}
+		for (argument, local) in arguments.iter().zip(&function.arguments) {
+			let local = &function.locals[*local];
+			if !argument.has_type(&local.local_type) {
+				return Err(runtime_error(format!(
+					"The argument `{}` of `{}` is a `{}`, which {argument:?} isn't",
+					local.name,
+					function.name,
+					local.local_type,
+				)));
+			}
+		}
+		let what = format!("`{}`", function.name);
+		ConstEvaluator::new(self, what, 0)
+			.allow_specialization()
+			.at_runtime()
+			.call(&function, arguments.to_vec())
+	}
+
+	/// The value of a global (by its fully-qualified name), with any specialization constants taken into account.
+	pub fn global(&mut self, name: &str) -> Result<ConstValue, YuriSemanticError> {
+		let Some(id) = self.program.globals.iter().position(|g| g.name == name) else {
+			return Err(runtime_error(format!("There's no global named `{name}`")));
+		};
+		self.global_value(id)
+	}
+
+	fn global_value(&mut self, id: GlobalId) -> Result<ConstValue, YuriSemanticError> {
+		let global = &self.program.globals[id];
+		if let Some(value) = self.specializations.get(&id).or_else(|| self.specialized.get(&id)) {
+			return Ok(value.clone());
+		}
+		// only globals depending on a changed specialization constant need working out again
+		let changed = global.specialization.is_some_and(|s| self.specializations.contains_key(&s));
+		if !changed {
+			return Ok(global.constant.clone());
+		}
+		let what = format!("`{}`", global.name);
+		let value = ConstEvaluator::new(self, what, global.locals.len())
+			.allow_specialization()
+			.at_runtime()
+			.evaluate(&global.value)?;
+		self.specialized.insert(id, value.clone());
+		Ok(value)
+	}
+}
+
+impl ConstContext for Interpreter<'_> {
+	fn global(&mut self, id: GlobalId, _location: &Range<usize>) -> Result<ConstValue, YuriSemanticError> {
+		self.global_value(id)
+	}
+
+	fn function(&mut self, id: FunctionId, _location: &Range<usize>) -> Result<Rc<TypedFunction>, YuriSemanticError> {
+		Ok(self.functions[id].clone())
+	}
+
+	fn property_name(&self, id: PropId) -> String {
+		self.program.properties[id].name.clone()
+	}
+
+	fn specialization(&mut self, _id: GlobalId, _location: &Range<usize>) -> Result<Option<String>, YuriSemanticError> {
+		// they've all got values, whether they're the defaults or not
+		Ok(None)
+	}
+
+	fn property(&mut self, id: PropId) -> Option<ConstValue> {
+		self.properties[id].clone()
+	}
+
+	fn builtin_input(&mut self, input: BuiltinInput) -> Option<ConstValue> {
+		self.inputs.get(&input).cloned()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::builtin::BuiltinInput;
+	use crate::check::check_source;
+	use crate::consteval::ConstValue;
+	use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+	use crate::interpret::Interpreter;
+
+	#[test]
+	fn run_functions() {
+		let program = check_source("
+			prop scale: f;
+			@spec(0) let STEPS: u = 4;
+			let HALF = STEPS / 2;
+			fn remap(x: f): f { smoothstep(0.0, 1.0, x) * scale }
+			fn sum(): u { fold total = 0, k: STEPS { total + k } }
+			fn wrap(x: i): i { x * 2 }
+			fn safe(a: u, b: u): u { a / b }
+			@frag fn main(): f4 { f4(@frag.coord / 2.0, remap(0.5), 1.0) }
+		").unwrap();
+		let mut interpreter = Interpreter::new(&program);
+		interpreter.set_prop("scale", 2.0).unwrap();
+		assert_eq!(interpreter.call("remap", &[0.5.into()]).unwrap(), ConstValue::Float(1.0));
+		assert_eq!(interpreter.call("sum", &[]).unwrap(), ConstValue::Unsigned(6));
+		// integers wrap like they do on the GPU
+		assert_eq!(interpreter.call("wrap", &[i32::MAX.into()]).unwrap(), ConstValue::Signed(-2));
+
+		interpreter.set_input(BuiltinInput::FragCoord, [3.0, 4.0]).unwrap();
+		assert_eq!(interpreter.call("main", &[]).unwrap(), ConstValue::from([1.5, 2.0, 1.0, 1.0]));
+
+		// specialization constants change whatever depends on them, even other globals
+		assert_eq!(interpreter.global("HALF").unwrap(), ConstValue::Unsigned(2));
+		interpreter.set_specialization("STEPS", 10u32).unwrap();
+		assert_eq!(interpreter.global("HALF").unwrap(), ConstValue::Unsigned(5));
+		assert_eq!(interpreter.call("sum", &[]).unwrap(), ConstValue::Unsigned(45));
+
+		let runtime = |result: Result<ConstValue, YuriSemanticError>| result.unwrap_err().error_type();
+		assert_eq!(runtime(interpreter.call("safe", &[1u32.into(), 0u32.into()])), YuriSemanticErrorType::Runtime);
+		assert_eq!(runtime(interpreter.call("safe", &[1u32.into()])), YuriSemanticErrorType::Runtime);
+		assert_eq!(runtime(interpreter.call("remap", &[1u32.into()])), YuriSemanticErrorType::Runtime);
+		assert_eq!(runtime(interpreter.call("missing", &[])), YuriSemanticErrorType::Runtime);
+		assert!(interpreter.set_prop("scale", true).is_err());
+
+		// props have to be set before anything can use them
+		let mut fresh = Interpreter::new(&program);
+		let err = fresh.call("remap", &[0.5.into()]).unwrap_err();
+		assert_eq!(err.error_type(), YuriSemanticErrorType::Runtime);
+		assert!(err.description().unwrap().contains("the prop `scale` wasn't given a value"), "{err}");
+	}
+
+	#[test]
+	fn composites_and_control_flow() {
+		let program = check_source("
+			fn squares(n: u): u { (loop k: 4 { k * k })[n] }
+			fn doubled(values: f[3]): f[3] { map v: values { v * 2.0 } }
+			fn polar(v: f2): <| length: f, flipped: f2 |> { <| length = length(v), flipped = v.yx |> }
+			fn classify(x: i): i { if x < 0 { -1 } else if x == 0 { 0 } else { let big = x > 100; if big { 2 } else { 1 } } }
+			fn turn(m: m2, v: f2): f2 { m * v }
+			fn twice(x: i): i { classify(x) + classify(x * 2) }
+		").unwrap();
+		let mut interpreter = Interpreter::new(&program);
+		assert_eq!(interpreter.call("squares", &[3u32.into()]).unwrap(), ConstValue::Unsigned(9));
+		// indexing out of bounds is undefined on the GPU, so it's an error rather than a made-up value
+		assert_eq!(interpreter.call("squares", &[4u32.into()]).unwrap_err().error_type(), YuriSemanticErrorType::Runtime);
+		assert_eq!(interpreter.call("doubled", &[[1.0, -2.0, 0.5].into()]).unwrap(), ConstValue::from([2.0, -4.0, 1.0]));
+		assert_eq!(
+			interpreter.call("polar", &[[3.0, 4.0].into()]).unwrap(),
+			ConstValue::Composite(vec![ConstValue::Float(5.0), [4.0, 3.0].into()]),
+		);
+		let classify: Vec<ConstValue> = [-5, 0, 7, 500].into_iter()
+			.map(|x| interpreter.call("classify", &[x.into()]).unwrap())
+			.collect();
+		assert_eq!(classify, [-1, 0, 1, 2].map(ConstValue::Signed));
+		assert_eq!(interpreter.call("twice", &[60.into()]).unwrap(), ConstValue::Signed(3));
+		// matrices are columns, so this turns a quarter of the way around
+		assert_eq!(interpreter.call("turn", &[[[0.0, 1.0], [-1.0, 0.0]].into(), [1.0, 2.0].into()]).unwrap(), ConstValue::from([-2.0, 1.0]));
+	}
+
+	#[test]
+	fn bad_values() {
+		let program = check_source("
+			prop offsets: f2[2];
+			@spec(1) let FLIP: bool = false;
+			let SIGN = if FLIP { -1.0 } else { 1.0 };
+			fn shift(n: u): f2 { offsets[n] * SIGN }
+		").unwrap();
+		let mut interpreter = Interpreter::new(&program);
+		// the wrong length of array is the wrong type
+		assert!(interpreter.set_prop("offsets", [[1.0, 2.0]]).is_err());
+		assert!(interpreter.set_prop("missing", 1.0).is_err());
+		interpreter.set_prop("offsets", [[1.0, 2.0], [3.0, 4.0]]).unwrap();
+		assert!(interpreter.set_specialization("FLIP", 1u32).is_err());
+		// only `@spec` lets can be changed
+		assert!(interpreter.set_specialization("SIGN", -1.0).is_err());
+		assert!(interpreter.set_input(BuiltinInput::FragCoord, 1.0).is_err());
+		assert_eq!(interpreter.call("shift", &[1u32.into()]).unwrap(), ConstValue::from([3.0, 4.0]));
+		interpreter.set_specialization("FLIP", true).unwrap();
+		assert_eq!(interpreter.global("SIGN").unwrap(), ConstValue::Float(-1.0));
+		assert_eq!(interpreter.call("shift", &[0u32.into()]).unwrap(), ConstValue::from([-1.0, -2.0]));
+		assert!(interpreter.global("missing").is_err());
+	}
+}
@@ -1,11 +1,14 @@
 //! The Yuri lexer/tokenizer module. This was written entirely by hand,
 //! ensuring maximum portability and even maximum-er jank.
-use std::ops::{Neg, Range};
+//! It walks the source's bytes and hands out slices of it, so nothing gets copied.
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::iter::FusedIterator;
+use std::ops::Range;
 use crate::error::{YuriLexError, YuriLexErrorType};
+use crate::source::SourceFile;
 
-type Input<'a> = &'a[char];
-
-pub type YuriAst = Vec<YuriToken>;
+pub type YuriAst<'a> = Vec<YuriToken<'a>>;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Keyword {
@@ -56,14 +59,18 @@ pub enum Keyword {
 }
 
 impl Keyword {
-	pub const ALL: [Keyword; 35] = { use Keyword::*; [
+	pub const ALL: [Keyword; 39] = { use Keyword::*; [
 		Fn,
 		Let,
 		Prop,
 
 		Loop,
+		Fold,
 		Map,
 		Filter,
+		Switch,
+		If,
+		Else,
 
 		Import,
 		Export,
@@ -141,10 +148,49 @@ impl Keyword {
 		}
 	}
 	pub fn string_to_keyword(string: &str) -> Option<Self> {
-		// TODO: this is slow as all hell because Rust needs better enum tools.
-		Self::ALL.iter()
-			.find(|k| k.stringify() == string)
-			.copied()
+		// a match on strings compiles down to a length check and a couple of comparisons
+		Some(match string {
+			"fn" => Keyword::Fn,
+			"let" => Keyword::Let,
+			"prop" => Keyword::Prop,
+			"loop" => Keyword::Loop,
+			"map" => Keyword::Map,
+			"fold" => Keyword::Fold,
+			"filter" => Keyword::Filter,
+			"import" => Keyword::Import,
+			"export" => Keyword::Export,
+			"module" => Keyword::Module,
+			"return" => Keyword::Return,
+			"if" => Keyword::If,
+			"and" => Keyword::And,
+			"xor" => Keyword::Xor,
+			"or" => Keyword::Or,
+			"nor" => Keyword::Nor,
+			"else" => Keyword::Else,
+			"switch" => Keyword::Switch,
+			"bool" => Keyword::TypeBool,
+			"f" => Keyword::TypeF,
+			"u" => Keyword::TypeU,
+			"i" => Keyword::TypeI,
+			"f2" => Keyword::TypeF2,
+			"u2" => Keyword::TypeU2,
+			"i2" => Keyword::TypeI2,
+			"f3" => Keyword::TypeF3,
+			"u3" => Keyword::TypeU3,
+			"i3" => Keyword::TypeI3,
+			"f4" => Keyword::TypeF4,
+			"u4" => Keyword::TypeU4,
+			"i4" => Keyword::TypeI4,
+			"m2" => Keyword::TypeM2,
+			"m3" => Keyword::TypeM3,
+			"m4" => Keyword::TypeM4,
+			"sampler1" => Keyword::TypeSampler1,
+			"sampler2" => Keyword::TypeSampler2,
+			"sampler3" => Keyword::TypeSampler3,
+			"sampler4" => Keyword::TypeSampler4,
+			"core" => Keyword::Core,
+			_ => return None,
+		})
 	}
 }
 
@@ -154,14 +200,129 @@ impl From<Keyword> for &'static str {
 	}
 }
 
+/// Operators go by what they look like, not what they do, since `-` can mean two things.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Operator {
+	Plus,
+	Minus,
+	Star,
+	DoubleStar,
+	Slash,
+	Percent,
+	Caret,
+	Bang,
+	Ampersand,
+	DoubleAmpersand,
+	Pipe,
+	DoublePipe,
+	ShiftLeft,
+	ShiftRight,
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+}
+
+impl Operator {
+	pub const ALL: [Operator; 20] = { use Operator::*; [
+		Plus,
+		Minus,
+		Star,
+		DoubleStar,
+		Slash,
+		Percent,
+		Caret,
+		Bang,
+		Ampersand,
+		DoubleAmpersand,
+		Pipe,
+		DoublePipe,
+		ShiftLeft,
+		ShiftRight,
+		Equal,
+		NotEqual,
+		Less,
+		LessEqual,
+		Greater,
+		GreaterEqual,
+	] };
+	const fn stringify(&self) -> &'static str {
+		match self {
+			Operator::Plus 				=> "+",
+			Operator::Minus 			=> "-",
+			Operator::Star 				=> "*",
+			Operator::DoubleStar 		=> "**",
+			Operator::Slash 			=> "/",
+			Operator::Percent 			=> "%",
+			Operator::Caret 			=> "^",
+			Operator::Bang 				=> "!",
+			Operator::Ampersand 		=> "&",
+			Operator::DoubleAmpersand 	=> "&&",
+			Operator::Pipe 				=> "|",
+			Operator::DoublePipe 		=> "||",
+			Operator::ShiftLeft 		=> "<<",
+			Operator::ShiftRight 		=> ">>",
+			Operator::Equal 			=> "==",
+			Operator::NotEqual 			=> "!=",
+			Operator::Less 				=> "<",
+			Operator::LessEqual 		=> "<=",
+			Operator::Greater 			=> ">",
+			Operator::GreaterEqual 		=> ">=",
+		}
+	}
+}
+
+impl From<Operator> for &'static str {
+	fn from(value: Operator) -> Self {
+		value.stringify()
+	}
+}
+
+/// What can go right after a number to say what type it is, like the `u` in `1u`.
+/// Without one, the type checker decides based on where the number is used.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum NumberSuffix {
+	Unsigned,
+	Signed,
+	Float,
+	/// Half-precision floats. There's no half type, so these are `f`s rounded to what a half can hold.
+	Half,
+}
+
+impl NumberSuffix {
+	pub const ALL: [NumberSuffix; 4] = [
+		NumberSuffix::Unsigned,
+		NumberSuffix::Signed,
+		NumberSuffix::Float,
+		NumberSuffix::Half,
+	];
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			NumberSuffix::Unsigned 	=> "u",
+			NumberSuffix::Signed 	=> "i",
+			NumberSuffix::Float 	=> "f",
+			NumberSuffix::Half 		=> "h",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter()
+			.find(|suffix| suffix.name() == name)
+			.copied()
+	}
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct YuriToken {
-	pub token_type: YuriTokenType,
+pub struct YuriToken<'a> {
+	pub token_type: YuriTokenType<'a>,
 	pub location: Range<usize>
 }
 
-impl YuriToken {
-	pub fn new(token_type: YuriTokenType, location: Range<usize>) -> Self {
+impl<'a> YuriToken<'a> {
+	pub fn new(token_type: YuriTokenType<'a>, location: Range<usize>) -> Self {
 		Self {
 			token_type,
 			location
@@ -169,8 +330,9 @@ impl YuriToken {
 	}
 }
 
+/// Names borrow from the source, so tokens can't outlive it.
 #[derive(Debug, Clone, PartialEq)]
-pub enum YuriTokenType {
+pub enum YuriTokenType<'a> {
 	/// A token that we can't fully recognize, usually caused by an error.
 	Unknown(YuriLexError),
 	/// (
@@ -200,379 +362,574 @@ pub enum YuriTokenType {
 	/// ?
 	Optional,
 
-	HexNumber(u32),
-	BinaryNumber(u32),
-	UnsignedNumber(u32),
-	SignedNumber(i32),
-	DecimalNumber(f32),
+	/// Numbers never have a sign, `-1` is a `-` and then a `1`.
+	/// They're kept at 64 bits, so the type checker can say which type they don't fit in.
+	HexNumber(u64, Option<NumberSuffix>),
+	BinaryNumber(u64, Option<NumberSuffix>),
+	UnsignedNumber(u64, Option<NumberSuffix>),
+	/// Any number with a point or an exponent, including hexadecimal ones like `0x1.8p3`.
+	DecimalNumber(f64, Option<NumberSuffix>),
 
 	Keyword(Keyword),
-	Annotation(String),
-	Identifier(String),
-	Operator(String),
+	/// The name of the annotation, without the `@`.
+	Annotation(&'a str),
+	Identifier(&'a str),
+	Operator(Operator),
 }
 
-/// This function is an affront to god. But so are lesbians, so it doesn't really matter.
-/// I am afraid to split this up into multiple other functions because
-/// of how the pieces all fit together. I totally could, but it's fine like this.
-fn take_token(input: Input, seek: &mut usize) -> YuriToken {
-	let initial_seek = *seek;
-	let tt = match input[*seek] {
-		'(' => { *seek += 1; YuriTokenType::OpenParen },
-		')' => { *seek += 1; YuriTokenType::CloseParen },
-		'{' => { *seek += 1; YuriTokenType::OpenBrace },
-		'}' => { *seek += 1; YuriTokenType::CloseBrace },
-		'[' => { *seek += 1; YuriTokenType::OpenSquare },
-		']' => { *seek += 1; YuriTokenType::CloseSquare },
-		':' => { *seek += 1; YuriTokenType::TypeHint },
-		';' => { *seek += 1; YuriTokenType::Terminator },
-		',' => { *seek += 1; YuriTokenType::Separator },
-		'+' => { *seek += 1; YuriTokenType::Operator(String::from("+")) }
-		'/' => { *seek += 1; YuriTokenType::Operator(String::from("/")) }
-		'^' => { *seek += 1; YuriTokenType::Operator(String::from("^")) }
-		'!' => { *seek += 1; YuriTokenType::Operator(String::from("!")) }
-		'%' => { *seek += 1; YuriTokenType::Operator(String::from("%")) }
-		'?' => { *seek += 1; YuriTokenType::Optional }
-
-		'|' => {
-			*seek += 1;
-			match input.get(*seek) {
-				// logical OR
-				Some('|') => { *seek += 1; YuriTokenType::Operator(String::from("||")) },
-				// close |> complex
-				Some('>') => { *seek += 1; YuriTokenType::CloseTri }
-				// binary OR followed by something else
-				// some other character-- we'll try processing it again.
-				None | Some(_) => YuriTokenType::Operator(String::from("|"))
+/// Splits source text into tokens as it goes, for anything that doesn't need all of them at once.
+/// Tokens it can't make sense of come out as [YuriTokenType::Unknown],
+/// except for an unclosed block comment, which is the last thing it gives out.
+#[derive(Debug, Clone)]
+pub struct Lexer<'a> {
+	input: &'a str,
+	/// Where the input starts in its [SourceMap](crate::source::SourceMap), which gets added to every location.
+	start: usize,
+	seek: usize,
+	done: bool,
+}
+
+impl<'a> Lexer<'a> {
+	pub fn new(input: &'a str) -> Self {
+		Self { input, start: 0, seek: 0, done: false }
+	}
+
+	/// Lexes a file from a [SourceMap](crate::source::SourceMap), so the locations point into the right file.
+	pub fn for_file(file: &'a SourceFile) -> Self {
+		Self { input: file.text(), start: file.start(), seek: 0, done: false }
+	}
+
+	fn shift(&self, location: &Range<usize>) -> Range<usize> {
+		(location.start + self.start)..(location.end + self.start)
+	}
+
+	fn shift_error(&self, mut err: YuriLexError) -> YuriLexError {
+		err.markers = err.markers.iter().map(|marker| self.shift(marker)).collect();
+		err
+	}
+
+	/// Turns an unclosed block comment into the last token.
+	fn unclosed_comment(&mut self, err: YuriLexError) -> YuriToken<'a> {
+		self.done = true;
+		let location = self.shift(&err.markers[0]);
+		YuriToken::new(YuriTokenType::Unknown(self.shift_error(err)), location)
+	}
+
+	/// Takes the token at the seek, which has to be in bounds.
+	fn take_token(&mut self) -> YuriToken<'a> {
+		let mut token = take_token(self.input, &mut self.seek);
+		if self.start != 0 {
+			token.location = self.shift(&token.location);
+			if let YuriTokenType::Unknown(err) = token.token_type {
+				token.token_type = YuriTokenType::Unknown(self.shift_error(err));
 			}
-		},
-		'<' => {
-			*seek += 1;
-			match input.get(*seek) {
-				// open <| complex
-				Some('|') => { *seek += 1; YuriTokenType::OpenTri },
-				// shl op
-				Some('<') => { *seek += 1; YuriTokenType::Operator(String::from("<<")) }
-				None | Some(_) => YuriTokenType::Operator(String::from("<"))
+		}
+		token
+	}
+
+	/// Keeps the whitespace and comments instead of skipping them, see [LosslessLexer].
+	pub fn lossless(self) -> LosslessLexer<'a> {
+		LosslessLexer { lexer: self, end: Vec::new() }
+	}
+}
+
+impl<'a> Iterator for Lexer<'a> {
+	type Item = YuriToken<'a>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		match take_whitespace(self.input, self.seek) {
+			Ok(seek) => self.seek = seek,
+			Err(err) => return Some(self.unclosed_comment(err)),
+		}
+		if self.seek >= self.input.len() {
+			self.done = true;
+			return None;
+		}
+		Some(self.take_token())
+	}
+}
+
+impl FusedIterator for Lexer<'_> {}
+
+/// Whitespace and comments, which the parser doesn't care about but formatters and editors do.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TriviaKind {
+	/// Any whitespace that isn't a newline.
+	Whitespace,
+	/// `\n` or `\r\n`.
+	Newline,
+	/// A `#` comment, without the newline at the end.
+	LineComment,
+	/// A `## ... ##` comment, which can go over multiple lines.
+	BlockComment,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Trivia<'a> {
+	pub kind: TriviaKind,
+	pub text: &'a str,
+	pub location: Range<usize>,
+}
+
+/// A token, along with the exact text it came from and the trivia around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessToken<'a> {
+	pub leading: Vec<Trivia<'a>>,
+	pub token: YuriToken<'a>,
+	pub text: &'a str,
+	pub trailing: Vec<Trivia<'a>>,
+}
+
+impl Display for LosslessToken<'_> {
+	/// Writes the token's source back out, trivia included.
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		for trivia in &self.leading {
+			f.write_str(trivia.text)?;
+		}
+		f.write_str(self.text)?;
+		for trivia in &self.trailing {
+			f.write_str(trivia.text)?;
+		}
+		Ok(())
+	}
+}
+
+/// Every token in a file, plus whatever trivia comes after the last one.
+/// Writing them all back out gives the exact source again.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LosslessTokens<'a> {
+	pub tokens: Vec<LosslessToken<'a>>,
+	pub end: Vec<Trivia<'a>>,
+}
+
+impl LosslessTokens<'_> {
+	/// Just the tokens, for the parser.
+	pub fn to_ast(&self) -> YuriAst<'_> {
+		self.tokens.iter().map(|token| token.token.clone()).collect()
+	}
+}
+
+impl Display for LosslessTokens<'_> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		for token in &self.tokens {
+			write!(f, "{token}")?;
+		}
+		for trivia in &self.end {
+			f.write_str(trivia.text)?;
+		}
+		Ok(())
+	}
+}
+
+/// A [Lexer] that keeps the trivia around each token instead of skipping it.
+/// Anything after a token on the same line (up to and including the newline) is its trailing trivia,
+/// and everything else before a token is its leading trivia, so a comment on its own line goes with the token after it.
+#[derive(Debug, Clone)]
+pub struct LosslessLexer<'a> {
+	lexer: Lexer<'a>,
+	end: Vec<Trivia<'a>>,
+}
+
+impl<'a> LosslessLexer<'a> {
+	pub fn new(input: &'a str) -> Self {
+		Lexer::new(input).lossless()
+	}
+
+	/// The trivia after the last token, which is only there once the lexer has run out of tokens.
+	pub fn end_trivia(&self) -> &[Trivia<'a>] {
+		&self.end
+	}
+
+	/// Takes trivia until the next token, or only until the end of the line for trailing trivia.
+	/// An unclosed block comment stops it, and gets left for the next token to report.
+	fn take_trivia(&mut self, trailing: bool) -> Vec<Trivia<'a>> {
+		let lexer = &mut self.lexer;
+		let mut trivia = Vec::new();
+		while let Ok(Some((kind, end))) = take_trivia(lexer.input, lexer.seek) {
+			trivia.push(Trivia {
+				kind,
+				text: &lexer.input[lexer.seek..end],
+				location: lexer.shift(&(lexer.seek..end)),
+			});
+			lexer.seek = end;
+			if trailing && kind == TriviaKind::Newline {
+				break;
 			}
-		},
-		'=' => {
-			*seek += 1;
-			match input.get(*seek) {
-				Some('=') => { *seek += 1; YuriTokenType::Operator(String::from("==")) },
-				None | Some(_) => YuriTokenType::Assignment,
+		}
+		trivia
+	}
+}
+
+impl<'a> Iterator for LosslessLexer<'a> {
+	type Item = LosslessToken<'a>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.lexer.done {
+			return None;
+		}
+		let leading = self.take_trivia(false);
+		let start = self.lexer.seek;
+		let token = match take_trivia(self.lexer.input, start) {
+			Err(err) => self.lexer.unclosed_comment(err),
+			_ if start >= self.lexer.input.len() => {
+				self.lexer.done = true;
+				self.end = leading;
+				return None;
 			}
+			_ => self.lexer.take_token(),
+		};
+		// the unclosed comment goes on until the end, so it's the token's text
+		let end = if self.lexer.done { self.lexer.input.len() } else { self.lexer.seek };
+		self.lexer.seek = end;
+		let trailing = self.take_trivia(true);
+		Some(LosslessToken { leading, token, text: &self.lexer.input[start..end], trailing })
+	}
+}
+
+impl FusedIterator for LosslessLexer<'_> {}
+
+fn unknown<'a>(error_type: YuriLexErrorType, description: String, marker: Range<usize>) -> YuriTokenType<'a> {
+	YuriTokenType::Unknown(YuriLexError {
+		error_type,
+		description: Some(description),
+		markers: vec![marker],
+	})
+}
+
+/// Numbers can have `_`s anywhere to make them easier to read, which have to go before they're parsed.
+fn without_underscores(digits: &str) -> Cow<'_, str> {
+	if digits.contains('_') {
+		Cow::Owned(digits.replace('_', ""))
+	} else {
+		Cow::Borrowed(digits)
+	}
+}
+
+/// A number without its suffix, which decides what kind of token it turns into.
+enum NumberBody {
+	Hex(u64),
+	Binary(u64),
+	Unsigned(u64),
+	Decimal(f64),
+}
+
+/// Takes a number and its suffix, if it has one. Numbers can be
+/// - integers, like `123`, `0xFF` or `0b101`
+/// - floats, like `1.5`, `1.`, `.5` or `1e-3`
+/// - hexadecimal floats, like `0x1.8p3`, which need the `p` exponent (a power of 2) so the `f` suffix isn't a digit
+fn take_number<'a>(input: &'a str, seek: &mut usize) -> YuriTokenType<'a> {
+	let number_start = *seek;
+	let body = take_number_body(input, seek);
+	// whatever letters come right after are the suffix, even if the number itself was broken
+	let bytes = input.as_bytes();
+	let suffix_start = *seek;
+	while bytes.get(*seek).is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_') {
+		*seek += 1;
+	}
+	let body = match body {
+		Ok(body) => body,
+		Err(err) => return YuriTokenType::Unknown(err),
+	};
+	let suffix = match &input[suffix_start..*seek] {
+		"" => None,
+		name => match NumberSuffix::from_name(name) {
+			Some(suffix) => Some(suffix),
+			None => return unknown(
+				YuriLexErrorType::InvalidNumericLiteral,
+				format!("`{name}` isn't a number suffix (in %), it can only be `u`, `i`, `f` or `h`"),
+				number_start..*seek,
+			),
 		},
-		'&' => {
+	};
+	match body {
+		NumberBody::Hex(n) => YuriTokenType::HexNumber(n, suffix),
+		NumberBody::Binary(n) => YuriTokenType::BinaryNumber(n, suffix),
+		NumberBody::Unsigned(n) => YuriTokenType::UnsignedNumber(n, suffix),
+		NumberBody::Decimal(n) => YuriTokenType::DecimalNumber(n, suffix),
+	}
+}
+
+fn take_number_body(input: &str, seek: &mut usize) -> Result<NumberBody, YuriLexError> {
+	let bytes = input.as_bytes();
+	let number_start = *seek;
+	let error = |error_type, description: &str, end: usize| YuriLexError {
+		error_type,
+		description: Some(description.to_string()),
+		markers: vec![number_start..end],
+	};
+	let take_digits = |seek: &mut usize, radix: u32| {
+		let start = *seek;
+		while bytes.get(*seek).is_some_and(|b| *b == b'_' || (*b as char).is_digit(radix)) {
 			*seek += 1;
-			match input.get(*seek) {
-				Some('&') => { *seek += 1; YuriTokenType::Operator(String::from("&&")) }
-				None | Some(_) => YuriTokenType::Operator(String::from("&"))
-			}
-		},
-		'*' => {
+		}
+		without_underscores(&input[start..*seek])
+	};
+	// the sign is optional, but the digits aren't
+	let take_exponent = |seek: &mut usize| -> Option<i32> {
+		let start = *seek;
+		if let Some(b'+' | b'-') = bytes.get(*seek) {
 			*seek += 1;
-			match input.get(*seek) {
-				Some('*') => { *seek += 1; YuriTokenType::Operator(String::from("**")) }
-				None | Some(_) => YuriTokenType::Operator(String::from("*"))
-			}
 		}
-		'-' | '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-			let earliest_seek = *seek;
-			// this control flow is really annoying to model without repetition.
-			let ch = input[*seek];
-
-			let subtraction_negation = if ch == '-' {
-				if let Some(next) = input.get(*seek + 1) {
-					if next.is_digit(10) {
-						None
-					} else {
-						*seek += 1;
-						Some(YuriTokenType::Operator(String::from("-")))
-					}
-				} else {
-					*seek += 1;
-					Some(YuriTokenType::Operator(String::from("-")))
-				}
-			} else {
-				None
+		if !bytes.get(*seek).is_some_and(u8::is_ascii_digit) {
+			*seek = start;
+			return None;
+		}
+		take_digits(seek, 10);
+		// way too big exponents end up as infinity or zero either way
+		Some(without_underscores(&input[start..*seek]).parse::<i32>().unwrap_or(if bytes[start] == b'-' { i32::MIN } else { i32::MAX }))
+	};
+
+	if bytes[*seek] == b'0' && let Some(prefix @ (b'x' | b'b')) = bytes.get(*seek + 1) {
+		let (radix, name) = if *prefix == b'x' { (16, "hexadecimal") } else { (2, "binary") };
+		*seek += 2;
+		let integer = take_digits(seek, radix);
+		let fraction = if radix == 16 && bytes.get(*seek) == Some(&b'.') {
+			*seek += 1;
+			Some(take_digits(seek, radix))
+		} else {
+			None
+		};
+		if integer.is_empty() && fraction.as_deref().is_none_or(str::is_empty) {
+			return Err(error(
+				YuriLexErrorType::InvalidNumericLiteral,
+				&format!("At least one digit must follow a {name} literal prefix (written %)"),
+				*seek,
+			));
+		}
+
+		if radix == 16 && let Some(b'p' | b'P') = bytes.get(*seek) {
+			*seek += 1;
+			let Some(exponent) = take_exponent(seek) else {
+				return Err(error(YuriLexErrorType::InvalidNumericLiteral, "The exponent of % is missing its digits", *seek));
 			};
-			let special_number_formats = if ch == '0' {
-				match input.get(*seek + 1) {
-					// hex number
-					Some('x') => {
-						let hex_start_seek = *seek;
-						*seek += 2;
-						let mut digits = Vec::new();
-
-						while let Some(ch) = input.get(*seek) {
-							if *ch == '_' {
-								*seek += 1;
-							} else if let Some(digit) = ch.to_digit(16) {
-								if !digits.is_empty() || digit != 0 {
-									println!("got digit {digit}");
-									digits.push(digit);
-								} else {
-									println!("skipping leading 0")
-								}
-								*seek += 1;
-							} else {
-								break;
-							}
-						};
-
-						if digits.is_empty() {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::InvalidNumericLiteral,
-									description: Some("0 or more digits must follow a hexadecimal literal prefix (written %)".to_string()),
-									markers: vec![hex_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						}
-
-						let mut sum: i64 = 0;
-						for i in 0..digits.len() {
-							let exp = digits.len() as u32 - i as u32 - 1;
-							let digit_at = digits[i];
-							println!("digit {digit_at} (index {i}) with exp {exp}");
-							if digit_at != 0 {
-								sum += digit_at as i64 * 16i64.pow(exp);
-							}
-						}
-						Some(if let Ok(sum) = u32::try_from(sum) {
-							YuriTokenType::HexNumber(sum)
-						} else {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::NumberOutOfBounds,
-									description: Some("The hexadecimal number % can't be stored in a 32-bit unsigned integer value.".to_string()),
-									markers: vec![hex_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						})
-					}
-					// binary number
-					Some('b') => {
-						let binary_start_seek = *seek;
-						*seek += 2;
-						let mut digits = Vec::<bool>::new();
-						while let Some(ch) = input.get(*seek) {
-							match ch {
-								'0' => {
-									*seek += 1;
-									if !digits.is_empty() {
-										digits.push(false);
-									}
-								}
-								'1' => { *seek += 1; digits.push(true); }
-								'_' => { *seek += 1; }
-								_ => break
-							}
-						};
-						if digits.is_empty() {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::InvalidNumericLiteral,
-									description: Some("0 or more digits must follow a binary literal prefix (written %)".to_string()),
-									markers: vec![binary_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						} else if digits.len() > 32 {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::NumberOutOfBounds,
-									description: Some("The binary number % can't be stored in a 32-bit unsigned integer value.".to_string()),
-									markers: vec![binary_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						}
-						let mut sum = 0;
-						for i in 0..digits.len() {
-							if digits[i] {
-								sum |= 1 << (digits.len() - i - 1);
-							}
-						}
-						Some(YuriTokenType::BinaryNumber(sum))
-					}
-					// EOF
-					None => { *seek += 1; Some(YuriTokenType::UnsignedNumber(0)) },
-					_ => None,
-				}
-			} else { None };
+			let fraction = fraction.unwrap_or_default();
+			// anything past the 53 bits a double can hold gets rounded off, which is fine for a shader
+			let mantissa = integer.chars().chain(fraction.chars())
+				.filter_map(|digit| digit.to_digit(16))
+				.fold(0.0, |value, digit| value * 16.0 + digit as f64);
+			let exponent = exponent.saturating_sub(4 * fraction.len() as i32);
+			let value = mantissa * 2.0f64.powi(exponent);
+			return match value.is_finite() {
+				true => Ok(NumberBody::Decimal(value)),
+				false => Err(error(
+					YuriLexErrorType::NumberOutOfBounds,
+					"The number % is too big to be stored in a floating-point value.",
+					*seek,
+				)),
+			};
+		} else if fraction.is_some() {
+			return Err(error(
+				YuriLexErrorType::InvalidNumericLiteral,
+				"A hexadecimal number with a point (%) needs a `p` exponent, like `0x1.8p0`",
+				*seek,
+			));
+		}
 
-			let exceptions = special_number_formats.or(subtraction_negation);
+		return match u64::from_str_radix(&integer, radix) {
+			Ok(n) if radix == 16 => Ok(NumberBody::Hex(n)),
+			Ok(n) => Ok(NumberBody::Binary(n)),
+			Err(_) => Err(error(
+				YuriLexErrorType::NumberOutOfBounds,
+				&format!("The {name} number % can't be stored in 64 bits, never mind 32."),
+				*seek,
+			)),
+		};
+	}
 
-			if let Some(tok) = exceptions {
-				tok
-			} else {
-				let mut digits = Vec::new();
-				let mut decimal_point: Option<usize> = None;
-				if ch == '-' {
-					*seek += 1;
-				}
-				let number_start_seek = *seek;
-				// parse digits
-				while let Some(ch) = input.get(*seek) {
-					if *ch == '_' {
-						*seek += 1;
-					} else if *ch == '.' {
-						if decimal_point.is_some() {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::InvalidNumericLiteral,
-									description: Some("More than one decimal point found in numeric literal (first is %, next is %)".to_string()),
-									markers: vec![*seek..(*seek + 1), number_start_seek..(number_start_seek + 1)],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						}
-						decimal_point = Some(digits.len());
-						*seek += 1;
-					} else if let Some(digit) = ch.to_digit(10) {
-						let is_next_decimal = input.get(*seek + 1)
-							.map_or(false, |ch| *ch == '.');
-						if !digits.is_empty() || digit != 0 || is_next_decimal{
-							digits.push(digit);
-						}
-						*seek += 1;
-					} else {
-						break;
-					}
-				};
-
-				if let Some(_) = decimal_point {
-					let strung = input[number_start_seek..*seek]
-						.iter()
-						.collect::<String>();
-					match strung
-						.parse::<f32>() {
-						Ok(num) => YuriTokenType::DecimalNumber(if ch == '-' {
-							println!("float: {strung}");
-							num.neg()
-						} else {
-							num
-						}),
-						Err(_) => {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::NumberOutOfBounds,
-									description: Some("The number % can't be stored in a 32-bit floating-point value.".to_string()),
-									markers: vec![number_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						}
-					}
-				} else {
-					let mut sum: i64 = 0;
-					for i in 0..digits.len() {
-						let exp = digits.len() as u32 - i as u32 - 1;
-						let digit_at = digits[i];
-						if digit_at != 0 {
-							sum += digit_at as i64 * 10i64.pow(exp);
-						}
-					}
-					// negation
-					if ch == '-' {
-						if let Ok(sum) = i32::try_from(sum * -1) {
-							YuriTokenType::SignedNumber(sum)
-						} else {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::NumberOutOfBounds,
-									description: Some("The number % can't be stored in a 32-bit signed integer value.".to_string()),
-									markers: vec![number_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						}
-					} else {
-						if let Ok(sum) = u32::try_from(sum) {
-							YuriTokenType::UnsignedNumber(sum)
-						} else {
-							return YuriToken::new(
-								YuriTokenType::Unknown(YuriLexError {
-									error_type: YuriLexErrorType::NumberOutOfBounds,
-									description: Some("The number % can't be stored in a 32-bit unsigned integer value.".to_string()),
-									markers: vec![number_start_seek..*seek],
-								}),
-								earliest_seek..(*seek + 1)
-							);
-						}
-					}
+	let mut decimal_point: Option<usize> = None;
+	while let Some(b) = bytes.get(*seek) {
+		match b {
+			b'0'..=b'9' | b'_' => *seek += 1,
+			b'.' => {
+				if let Some(first) = decimal_point {
+					return Err(YuriLexError {
+						error_type: YuriLexErrorType::InvalidNumericLiteral,
+						description: Some("More than one decimal point found in numeric literal (first is %, next is %)".to_string()),
+						markers: vec![first..(first + 1), *seek..(*seek + 1)],
+					});
 				}
+				decimal_point = Some(*seek);
+				*seek += 1;
 			}
+			_ => break,
 		}
-		ch => {
-			// YuriToken::Unknown
-			fn take_ident(input: Input, seek: &mut usize) -> Option<String> {
-				let ch = *input.get(*seek)?;
-				if ch.is_alphabetic() || ch == '_' {
-					let mut ident = Vec::new();
-					while let Some(ch) = input.get(*seek) {
-						if ch.is_alphanumeric() || *ch == '_' || *ch == '.' {
-							ident.push(*ch);
-							*seek += 1;
-						} else {
-							break;
-						}
-					}
-					Some(ident.into_iter().collect())
-				} else {
-					None
-				}
-			}
+	}
+	// an `e` without digits after it is left alone, and ends up as a (wrong) suffix
+	let mut exponent = false;
+	if let Some(b'e' | b'E') = bytes.get(*seek) {
+		*seek += 1;
+		exponent = take_exponent(seek).is_some();
+		if !exponent {
+			*seek -= 1;
+		}
+	}
+	let digits = without_underscores(&input[number_start..*seek]);
 
-			if ch == '@' {
-				*seek += 1;
-				let annotation = take_ident(input, seek);
-				if annotation.is_none() {
-					return YuriToken::new(
-						YuriTokenType::Unknown(YuriLexError {
-							error_type: YuriLexErrorType::IncompleteAnnotation,
-							description: Some("The annotation % is missing a proper identifier".to_string()),
-							markers: vec![*seek..(*seek + 1)],
-						}),
-						*seek - 1..(*seek + 1)
-					);
-				}
-				YuriTokenType::Annotation(annotation.unwrap())
-			} else if let Some(ident) = take_ident(input, seek) {
-				if let Some(kw) = Keyword::string_to_keyword(&ident) {
-					YuriTokenType::Keyword(kw)
-				} else {
-					YuriTokenType::Identifier(ident)
-				}
-			} else {
-				*seek += 1;
-				// TODO: do a greedy check of this by recursively calling take_token until we stop,
-				//		 that would prevent a chain of errors caused by a sequence of weird chars
-				YuriTokenType::Unknown(YuriLexError {
-					error_type: YuriLexErrorType::UnknownToken,
-					description: Some(format!("Unexpected/unknown character \'{ch}\' %")),
-					markers: vec![*seek..(*seek + 1)],
-				})
+	if decimal_point.is_some() || exponent {
+		match digits.parse::<f64>() {
+			Ok(n) if n.is_finite() => Ok(NumberBody::Decimal(n)),
+			_ => Err(error(
+				YuriLexErrorType::NumberOutOfBounds,
+				"The number % is too big to be stored in a floating-point value.",
+				*seek,
+			)),
+		}
+	} else {
+		// `-2147483648` is still fine, since the sign is its own token and the checker puts them back together
+		match digits.parse::<u64>() {
+			Ok(n) => Ok(NumberBody::Unsigned(n)),
+			Err(_) => Err(error(
+				YuriLexErrorType::NumberOutOfBounds,
+				"The number % can't be stored in 64 bits, never mind 32.",
+				*seek,
+			)),
+		}
+	}
+}
+
+/// Takes a name, which can have `.`s in it for paths like `lighting.pbr.brdf`.
+fn take_ident<'a>(input: &'a str, seek: &mut usize) -> Option<&'a str> {
+	let start = *seek;
+	let mut chars = input[start..].char_indices();
+	match chars.next() {
+		Some((_, ch)) if ch.is_alphabetic() || ch == '_' => {}
+		_ => return None,
+	}
+	let end = chars.find(|(_, ch)| !(ch.is_alphanumeric() || *ch == '_' || *ch == '.'))
+		.map_or(input.len(), |(i, _)| start + i);
+	*seek = end;
+	Some(&input[start..end])
+}
+
+fn take_token<'a>(input: &'a str, seek: &mut usize) -> YuriToken<'a> {
+	let initial_seek = *seek;
+	let bytes = input.as_bytes();
+	let next = bytes.get(*seek + 1).copied();
+	// most tokens are one or two bytes long, and the rest move the seek themselves
+	let (tt, length) = match bytes[*seek] {
+		b'(' => (YuriTokenType::OpenParen, 1),
+		b')' => (YuriTokenType::CloseParen, 1),
+		b'{' => (YuriTokenType::OpenBrace, 1),
+		b'}' => (YuriTokenType::CloseBrace, 1),
+		b'[' => (YuriTokenType::OpenSquare, 1),
+		b']' => (YuriTokenType::CloseSquare, 1),
+		b':' => (YuriTokenType::TypeHint, 1),
+		b';' => (YuriTokenType::Terminator, 1),
+		b',' => (YuriTokenType::Separator, 1),
+		b'?' => (YuriTokenType::Optional, 1),
+		b'+' => (YuriTokenType::Operator(Operator::Plus), 1),
+		b'/' => (YuriTokenType::Operator(Operator::Slash), 1),
+		b'^' => (YuriTokenType::Operator(Operator::Caret), 1),
+		b'%' => (YuriTokenType::Operator(Operator::Percent), 1),
+
+		b'|' => match next {
+			// logical OR
+			Some(b'|') => (YuriTokenType::Operator(Operator::DoublePipe), 2),
+			// close |> complex
+			Some(b'>') => (YuriTokenType::CloseTri, 2),
+			// binary OR followed by something else
+			_ => (YuriTokenType::Operator(Operator::Pipe), 1),
+		},
+		b'<' => match next {
+			// open <| complex
+			Some(b'|') => (YuriTokenType::OpenTri, 2),
+			Some(b'<') => (YuriTokenType::Operator(Operator::ShiftLeft), 2),
+			Some(b'=') => (YuriTokenType::Operator(Operator::LessEqual), 2),
+			_ => (YuriTokenType::Operator(Operator::Less), 1),
+		},
+		b'>' => match next {
+			Some(b'>') => (YuriTokenType::Operator(Operator::ShiftRight), 2),
+			Some(b'=') => (YuriTokenType::Operator(Operator::GreaterEqual), 2),
+			_ => (YuriTokenType::Operator(Operator::Greater), 1),
+		},
+		b'=' => match next {
+			Some(b'=') => (YuriTokenType::Operator(Operator::Equal), 2),
+			_ => (YuriTokenType::Assignment, 1),
+		},
+		b'!' => match next {
+			Some(b'=') => (YuriTokenType::Operator(Operator::NotEqual), 2),
+			_ => (YuriTokenType::Operator(Operator::Bang), 1),
+		},
+		b'&' => match next {
+			Some(b'&') => (YuriTokenType::Operator(Operator::DoubleAmpersand), 2),
+			_ => (YuriTokenType::Operator(Operator::Ampersand), 1),
+		},
+		b'*' => match next {
+			Some(b'*') => (YuriTokenType::Operator(Operator::DoubleStar), 2),
+			_ => (YuriTokenType::Operator(Operator::Star), 1),
+		},
+		// whether it's subtraction or negation is up to the parser
+		b'-' => (YuriTokenType::Operator(Operator::Minus), 1),
+		b'0'..=b'9' => (take_number(input, seek), 0),
+		// floats can start with their point, like `.5`
+		b'.' if next.is_some_and(|b| b.is_ascii_digit()) => (take_number(input, seek), 0),
+
+		b'@' => {
+			*seek += 1;
+			match take_ident(input, seek) {
+				Some(annotation) => (YuriTokenType::Annotation(annotation), 0),
+				None => (unknown(
+					YuriLexErrorType::IncompleteAnnotation,
+					"The annotation % is missing a proper identifier".to_string(),
+					initial_seek..*seek,
+				), 0),
 			}
 		}
+		_ => if let Some(ident) = take_ident(input, seek) {
+			match Keyword::string_to_keyword(ident) {
+				Some(kw) => (YuriTokenType::Keyword(kw), 0),
+				None => (YuriTokenType::Identifier(ident), 0),
+			}
+		} else {
+			// there's always a char here, since the seek is still in bounds
+			let ch = input[*seek..].chars().next().unwrap_or_default();
+			// TODO: do a greedy check of this by recursively calling take_token until we stop,
+			//		 that would prevent a chain of errors caused by a sequence of weird chars
+			(unknown(
+				YuriLexErrorType::UnknownToken,
+				format!("Unexpected/unknown character \'{ch}\' %"),
+				*seek..(*seek + ch.len_utf8()),
+			), ch.len_utf8())
+		},
 	};
+	*seek += length;
 	YuriToken::new(tt, initial_seek..*seek)
 }
 
-pub(super) fn lex_input(input_string: &str) -> Result<YuriAst, YuriLexError> {
-	let mut ast = YuriAst::new();
-	let input: Vec<char> = input_string.chars().collect();
-	let mut seek = take_whitespace(&input, 0)?;
-	while seek < input.len() {
-		let tok = take_token(&input, &mut seek);
-		let tok = tok;
-		ast.push(tok);
+/// Lexes the whole input at once. An unclosed block comment is the only thing that fails outright,
+/// anything else that's wrong ends up as a [YuriTokenType::Unknown] token for the parser to complain about.
+pub(super) fn lex_input(input: &str) -> Result<YuriAst<'_>, YuriLexError> {
+	collect_tokens(Lexer::new(input))
+}
+
+/// Lexes a file from a [SourceMap](crate::source::SourceMap), so the locations point into the right file.
+pub(super) fn lex_file(file: &SourceFile) -> Result<YuriAst<'_>, YuriLexError> {
+	collect_tokens(Lexer::for_file(file))
+}
 
-		seek = take_whitespace(&input, seek)?;
+/// Lexes the whole input at once, keeping the trivia. Fails the same way [lex_input] does.
+pub(super) fn lex_lossless(input: &str) -> Result<LosslessTokens<'_>, YuriLexError> {
+	let mut lexer = LosslessLexer::new(input);
+	let mut tokens = Vec::new();
+	for token in lexer.by_ref() {
+		match token.token.token_type {
+			YuriTokenType::Unknown(err) if err.error_type == YuriLexErrorType::UnexpectedEndOfFile => return Err(err),
+			_ => tokens.push(token),
+		}
+	}
+	Ok(LosslessTokens { tokens, end: lexer.end })
+}
+
+fn collect_tokens<'a>(lexer: Lexer<'a>) -> Result<YuriAst<'a>, YuriLexError> {
+	let mut ast = YuriAst::new();
+	for token in lexer {
+		match token.token_type {
+			YuriTokenType::Unknown(err) if err.error_type == YuriLexErrorType::UnexpectedEndOfFile => return Err(err),
+			_ => ast.push(token),
+		}
 	}
 	Ok(ast)
 }
@@ -580,100 +937,57 @@ pub(super) fn lex_input(input_string: &str) -> Result<YuriAst, YuriLexError> {
 /// Moves the seek forward until it hits a non-whitespace token.
 /// If the function encounters comments, it will treat them as whitespace.
 /// Block comments will generate a lex error if they are not terminated before EOF.
-/// Will return true if once it hits
-fn take_whitespace(input: Input, mut seek: usize) -> Result<usize, YuriLexError> {
-	while seek < input.len() {
-		let ch = input[seek];
-		if ch.is_whitespace() {
-			// keep going.
-			// safe since we know there's at least 1 char
-			seek += 1;
-			continue;
-		} else if ch == '#' {
-			seek += 1;
-
-			// edge case where we have a hash at the VERY END of the file.
-			// why would anyone do this???
-			// it's technically valid though so let's make sure we validate it
-			let next = if let Some(next) = input.get(seek) {
-				*next
-			} else {
-				return Ok(input.len())
-			};
-			// comment, line or block?
-			if next == '#' {
-				let block_comment_start = seek;
-				seek += 1;
-				// block comment
-				static ERROR_STRING: &str = "Missing closing block for block comment (started %). Add `##` to the end of the comment/file to fix this.";
-				loop {
-					if let Some(end_pos) = input.iter()
-						.skip(seek)
-						.position(|c| *c == '#') {
-						seek += end_pos + 1;
-					} else {
-						// EOF
-						return Err(YuriLexError {
-							error_type: YuriLexErrorType::UnexpectedEndOfFile,
-							description: Some(ERROR_STRING.to_string()),
-							markers: vec![block_comment_start..block_comment_start + 2],
-						});
-					};
-
-					// we seek to the position where we found the next hash.
-					// note that this is NOT the character after,
-					// we account for that when we assign the next character
-					let next = match input.get(seek) {
-						None => return Err(YuriLexError {
-							error_type: YuriLexErrorType::UnexpectedEndOfFile,
-							description: Some(ERROR_STRING.to_string()),
-							markers: vec![block_comment_start..block_comment_start + 2],
-						}),
-						Some(ch) => *ch
-					};
-
-					if next == '#' {
-						// move the seek to after the block comment ends,
-						// or else we'll interpret it as the beginning of another comment.
-						seek += 1;
-						break;
-					}
-				}
-			} else {
-				// line comment
-				let end_pos = input.iter()
-					.skip(seek)
-					.position(|c| *c == '\n');
-				match end_pos {
-					// EOF
-					None => return Ok(input.len()),
-					Some(index) => {
-						// note that end_pos is relative here;
-						// the indices we just skipped we need to add back.
-						// and- if we actually find the index of the newline,
-						// we'll consume it too. that's where the +1 comes from.
-						seek += index + 1;
-					}
-				}
-			}
-		} else {
-			// non-whitespace and not a comment
-			return Ok(seek);
-		}
+fn take_whitespace(input: &str, mut seek: usize) -> Result<usize, YuriLexError> {
+	while let Some((_, end)) = take_trivia(input, seek)? {
+		seek = end;
 	}
 	Ok(seek)
 }
 
+/// Finds the end of the one piece of trivia at the seek, if there is one there.
+fn take_trivia(input: &str, seek: usize) -> Result<Option<(TriviaKind, usize)>, YuriLexError> {
+	let bytes = input.as_bytes();
+	let Some(&b) = bytes.get(seek) else {
+		return Ok(None);
+	};
+	Ok(Some(match b {
+		// block comment, which goes until the next `##`
+		b'#' if bytes.get(seek + 1) == Some(&b'#') => match input[seek + 2..].find("##") {
+			Some(end) => (TriviaKind::BlockComment, seek + 2 + end + 2),
+			None => return Err(YuriLexError {
+				error_type: YuriLexErrorType::UnexpectedEndOfFile,
+				description: Some("Missing closing block for block comment (started %). Add `##` to the end of the comment/file to fix this.".to_string()),
+				markers: vec![seek..seek + 2],
+			}),
+		},
+		b'#' => {
+			let end = input[seek..].find('\n').map_or(input.len(), |end| seek + end);
+			// a `\r\n` is still all newline
+			let end = if bytes.get(end) == Some(&b'\n') && bytes[end - 1] == b'\r' { end - 1 } else { end };
+			(TriviaKind::LineComment, end)
+		}
+		b'\n' => (TriviaKind::Newline, seek + 1),
+		b'\r' if bytes.get(seek + 1) == Some(&b'\n') => (TriviaKind::Newline, seek + 2),
+		_ => {
+			// the rest of unicode has whitespace too
+			let end = input[seek..].char_indices()
+				.find(|(i, ch)| *ch == '\n' || !ch.is_whitespace() || input[seek + i..].starts_with("\r\n"))
+				.map_or(input.len(), |(i, _)| seek + i);
+			if end == seek {
+				return Ok(None);
+			}
+			(TriviaKind::Whitespace, end)
+		}
+	}))
+}
+
 #[cfg(test)]
 mod test {
-	use crate::error::{YuriLexError, YuriLexErrorType};
-	use crate::lex::{take_whitespace, YuriTokenType};
+	use crate::error::YuriLexErrorType;
+	use crate::lex::{take_whitespace, Keyword, Lexer, LosslessLexer, NumberSuffix, Operator, TriviaKind, YuriTokenType};
+	use crate::source::SourceMap;
 	use crate::YuriShader;
 
-	fn cvc(s: &str) -> Vec<char> {
-		s.chars().collect()
-	}
-
 	#[test]
 	fn lex_numbers() {
 		for _ in 0..1_345_678 {
@@ -681,17 +995,125 @@ mod test {
 			let val = rand::random_range(-123456789.0f32..=123456789.0f32);
 			let vas = val.to_string();
 			let ast = YuriShader::lex(&vas).unwrap();
-			assert_eq!(ast.len(), 1);
-			let tt = &ast[0].token_type;
+			// the sign is its own token
+			let (sign, tt) = match ast.as_slice() {
+				[minus, number] if minus.token_type == YuriTokenType::Operator(Operator::Minus) => (-1.0, &number.token_type),
+				[number] => (1.0, &number.token_type),
+				_ => unreachable!("not one number: {ast:?} (from {val})"),
+			};
 			match tt {
-				YuriTokenType::DecimalNumber(n) => { assert_eq!(*n, val); }
-				YuriTokenType::SignedNumber(n) => { assert_eq!(*n as f32, val); }
-				YuriTokenType::UnsignedNumber(n) => { assert_eq!(*n as f32, val); }
+				YuriTokenType::DecimalNumber(n, None) => { assert_eq!((sign * *n) as f32, val); }
+				YuriTokenType::UnsignedNumber(n, None) => { assert_eq!((sign * *n as f64) as f32, val); }
 				_ => unreachable!("not decimal: {tt:?} (from {val})")
 			}
 		}
 	}
 
+	#[test]
+	fn number_formats() {
+		fn number(source: &str) -> YuriTokenType<'_> {
+			let ast = YuriShader::lex(source).unwrap();
+			assert_eq!(ast.len(), 1, "{source} lexed as {ast:?}");
+			ast[0].token_type.clone()
+		}
+		assert_eq!(number("1e-3"), YuriTokenType::DecimalNumber(1e-3, None));
+		assert_eq!(number("2.5E+2"), YuriTokenType::DecimalNumber(250.0, None));
+		assert_eq!(number("1."), YuriTokenType::DecimalNumber(1.0, None));
+		assert_eq!(number(".5"), YuriTokenType::DecimalNumber(0.5, None));
+		assert_eq!(number("0x1.8p3"), YuriTokenType::DecimalNumber(12.0, None));
+		assert_eq!(number("0x1p-2f"), YuriTokenType::DecimalNumber(0.25, Some(NumberSuffix::Float)));
+		assert_eq!(number("1u"), YuriTokenType::UnsignedNumber(1, Some(NumberSuffix::Unsigned)));
+		assert_eq!(number("3i"), YuriTokenType::UnsignedNumber(3, Some(NumberSuffix::Signed)));
+		assert_eq!(number("2.0h"), YuriTokenType::DecimalNumber(2.0, Some(NumberSuffix::Half)));
+		// `f` is a hexadecimal digit, not a suffix
+		assert_eq!(number("0x1f"), YuriTokenType::HexNumber(0x1f, None));
+		assert_eq!(number("0xFFu"), YuriTokenType::HexNumber(0xff, Some(NumberSuffix::Unsigned)));
+		// too big for 32 bits is the checker's problem
+		assert_eq!(number("4_294_967_296"), YuriTokenType::UnsignedNumber(1 << 32, None));
+
+		for (source, marker) in [("x = 1e", 4..6), ("x = 2q", 4..6), ("x = 0x1.8", 4..9), ("x = 1e999", 4..9), ("x = 99999999999999999999", 4..24)] {
+			let ast = YuriShader::lex(source).unwrap();
+			let YuriTokenType::Unknown(err) = &ast[2].token_type else { panic!("{source} lexed as {ast:?}") };
+			assert_eq!(err.markers(), [marker], "{source}");
+		}
+	}
+
+	#[test]
+	fn lossless_trivia() {
+		let source = "let a = 1; # one\r\n\n\t## two ##\nb";
+		let tokens = YuriShader::lex_lossless(source).unwrap();
+		assert_eq!(tokens.to_string(), source);
+		let semicolon = &tokens.tokens[4];
+		assert_eq!(semicolon.text, ";");
+		assert_eq!(semicolon.trailing.iter().map(|t| t.text).collect::<Vec<_>>(), [" ", "# one", "\r\n"]);
+		let b = &tokens.tokens[5];
+		assert_eq!(b.leading.iter().map(|t| t.kind).collect::<Vec<_>>(), [TriviaKind::Newline, TriviaKind::Whitespace, TriviaKind::BlockComment, TriviaKind::Newline]);
+		assert_eq!(b.leading[2].location, 20..29);
+
+		// an unclosed comment is the last token, and it goes until the end
+		let mut lexer = LosslessLexer::new("a ## b");
+		assert_eq!(lexer.next().unwrap().trailing.len(), 1);
+		let unclosed = lexer.next().unwrap();
+		assert!(matches!(unclosed.token.token_type, YuriTokenType::Unknown(_)));
+		assert_eq!(unclosed.text, "## b");
+		assert_eq!(lexer.next(), None);
+		assert!(YuriShader::lex_lossless("a ## b").is_err());
+	}
+
+	#[test]
+	fn byte_locations() {
+		let ast = YuriShader::lex("## é ## let ü = 0x1;").unwrap();
+		let locations: Vec<_> = ast.iter().map(|token| token.location.clone()).collect();
+		assert_eq!(locations, [9..12, 13..15, 16..17, 18..21, 21..22]);
+
+		// errors point at bytes too
+		let err = YuriShader::lex("é ## x").unwrap_err();
+		assert_eq!(err.markers(), [3..5]);
+		let ast = YuriShader::lex("let é = 0x;").unwrap();
+		let YuriTokenType::Unknown(err) = &ast[3].token_type else { panic!("expected an unknown token, got {ast:?}") };
+		assert_eq!(err.markers(), [9..11]);
+	}
+
+	#[test]
+	fn keywords_and_operators() {
+		for keyword in Keyword::ALL {
+			assert_eq!(Keyword::string_to_keyword(keyword.into()), Some(keyword));
+		}
+		assert_eq!(Keyword::string_to_keyword("fnord"), None);
+
+		// every operator comes out as itself, even when it's jammed up against the next one
+		let source = Operator::ALL.map(<&str>::from).join(" ");
+		let ast = YuriShader::lex(&source).unwrap();
+		let operators: Vec<_> = ast.iter().map(|token| token.token_type.clone()).collect();
+		assert_eq!(operators, Operator::ALL.map(YuriTokenType::Operator));
+		let ast = YuriShader::lex("a<=-b").unwrap();
+		assert_eq!(ast[1].token_type, YuriTokenType::Operator(Operator::LessEqual));
+		assert_eq!(ast[2].token_type, YuriTokenType::Operator(Operator::Minus));
+		// `-` never sticks to a number, whatever's around it
+		for source in ["a-1", "a - 1", "-1"] {
+			let types: Vec<_> = YuriShader::lex(source).unwrap().into_iter().map(|token| token.token_type).collect();
+			assert_eq!(types[types.len() - 2..], [YuriTokenType::Operator(Operator::Minus), YuriTokenType::UnsignedNumber(1, None)], "{source}");
+		}
+	}
+
+	#[test]
+	fn streaming() {
+		let mut sources = SourceMap::new();
+		sources.add("first", "let a = 1;");
+		let second = sources.add("second", "@frag fn main() {} ## never closed");
+		let file = sources.file(second);
+		let tokens: Vec<_> = Lexer::for_file(file).collect();
+		// names are slices of the source, and locations are in the second file
+		assert_eq!(tokens[0].token_type, YuriTokenType::Annotation("frag"));
+		assert_eq!(tokens[0].location, file.start()..file.start() + 5);
+		let YuriTokenType::Identifier(name) = tokens[2].token_type else { panic!("expected an identifier, got {tokens:?}") };
+		assert!(std::ptr::eq(name.as_ptr(), file.text()[9..].as_ptr()));
+		// the unclosed comment is the last thing out
+		let YuriTokenType::Unknown(err) = &tokens.last().unwrap().token_type else { panic!("expected an error, got {tokens:?}") };
+		assert_eq!(err.error_type, YuriLexErrorType::UnexpectedEndOfFile);
+		assert_eq!(tokens.len(), 8);
+	}
+
 	#[test]
 	fn verify_take_whitespace() {
 		for s in [
@@ -719,10 +1141,9 @@ mod test {
 			" #\n ",
 		] {
 			println!("! testing \"{}\"", s.escape_default());
-			let input = cvc(s);
 			assert_eq!(
-				take_whitespace(&input, 0),
-				Ok(input.len()),
+				take_whitespace(s, 0),
+				Ok(s.len()),
 				"input was \"{}\"", s.escape_default()
 			);
 		}
@@ -736,9 +1157,8 @@ mod test {
 			("## asdasd## asd#", 12),
 		] {
 			println!("! testing \"{}\"", s.0);
-			let input = cvc(s.0);
 			assert_eq!(
-				take_whitespace(&input, 0),
+				take_whitespace(s.0, 0),
 				Ok(s.1),
 				"input was \"{}\"", s.0.escape_default()
 			);
@@ -753,8 +1173,7 @@ mod test {
 			"\n#\n ##\n",
 		] {
 			println!("! testing \"{}\"", s.escape_default());
-			let input = cvc(s);
-			let take = take_whitespace(&input, 0);
+			let take = take_whitespace(s, 0);
 			assert_eq!(
 				take.unwrap_err().error_type,
 				YuriLexErrorType::UnexpectedEndOfFile,
@@ -764,9 +1183,8 @@ mod test {
 
 		let s = "## asdas#d## asd#";
 		println!("testing \"{}\"", s.escape_default());
-		let input = cvc(s);
 		assert_eq!(
-			take_whitespace(&input, 0),
+			take_whitespace(s, 0),
 			Ok(13),
 			"input was \"{}\"", s.escape_default()
 		);
@@ -1,34 +1,172 @@
+// error markers are a list of ranges, and having just one of them is perfectly normal.
+#![allow(clippy::single_range_in_vec_init)]
+
+use crate::check::TypedProgram;
+use crate::compile::CompiledShader;
 use crate::error::{YuriCompileError, YuriLexError, YuriSemanticError};
-use crate::lex::YuriAst;
+use crate::glsl::GlslShader;
+use crate::wgsl::WgslShader;
+use crate::hlsl::HlslShader;
+use crate::msl::MslShader;
+use crate::import::{MemoryLoader, ResolvedImports, SourceLoader};
+use crate::lex::{LosslessTokens, YuriAst};
 use crate::parse::YuriModule;
+use crate::resolve::NameResolution;
+use crate::options::CompileOptions;
+use crate::source::{FileId, SourceMap};
+use crate::testing::TestResult;
 
 pub mod error;
+pub mod source;
 pub mod lex;
+pub mod cst;
+pub mod format;
+pub mod grammar;
 pub mod parse;
+pub mod import;
+pub mod builtin;
+pub mod resolve;
+pub mod check;
+pub mod consteval;
+pub mod interpret;
+pub mod testing;
+pub mod raster;
+pub mod fold;
 pub mod compile;
+pub mod glsl;
+pub mod wgsl;
+pub mod hlsl;
+pub mod msl;
+mod c_like;
+mod text;
+pub mod random;
+pub mod emulate;
+pub mod generate;
+pub mod target;
+pub mod options;
 
 pub struct YuriShader {
-    
+    /// Every entry point in the source, compiled into its own SPIR-V module.
+    pub shaders: Vec<CompiledShader>,
 }
 
 impl YuriShader {
-    /// Wrapper around the [YuriShader::lex], [YuriShader::parse] and [YuriShader::compile] methods,
-    /// chaining them together in the simplest possible way.
+    /// Compiles the source with the default [CompileOptions].
+    /// Imports aren't looked up anywhere, use [YuriShader::new_with_loader] if you have any.
+    /// The SPIR-V targets Vulkan 1.0, which is what SDL wants.
     pub fn new(input: &str) -> Result<Self, YuriCompileError> {
-        let ast = Self::lex(input)?;
-        let shader = Self::parse(&ast)?;
-        Ok(Self::compile(&shader)?)
+        Self::compile_with(input, &CompileOptions::default())
+    }
+
+    /// Same as [YuriShader::new], but imports are loaded through the given loader.
+    pub fn new_with_loader(input: &str, loader: &dyn SourceLoader) -> Result<Self, YuriCompileError> {
+        Self::compile_with(input, &CompileOptions::new().with_loader(loader))
+    }
+
+    /// Wrapper around the [YuriShader::lex], [YuriShader::parse], [YuriShader::check] and [YuriShader::compile] methods,
+    /// chaining them together in the simplest possible way.
+    pub fn compile_with(input: &str, options: &CompileOptions) -> Result<Self, YuriCompileError> {
+        let mut sources = SourceMap::new();
+        let name = options.source_name.clone().unwrap_or_else(|| "<input>".to_string());
+        let root = sources.add(name, input);
+        Self::compile_sources(&mut sources, root, options)
+    }
+
+    /// Same as [YuriShader::compile_with], but the source comes from a [SourceMap], and any imports get added to it.
+    /// That way the locations in errors can be looked up afterward.
+    pub fn compile_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<Self, YuriCompileError> {
+        let program = Self::check_sources(sources, root, options)?;
+        Ok(Self::compile(&program, options)?)
+    }
+
+    /// Same as [YuriShader::compile_sources], but every entry point gets written out as GLSL 4.50 (see [glsl]).
+    pub fn glsl_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<Vec<GlslShader>, YuriCompileError> {
+        let program = Self::check_sources(sources, root, options)?;
+        Ok(glsl::emit_program(&program, options)?)
+    }
+
+    /// Same as [YuriShader::compile_sources], but every entry point gets written out as WGSL, for WebGPU (see [wgsl]).
+    /// Only the frag origin is taken from the target.
+    pub fn wgsl_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<Vec<WgslShader>, YuriCompileError> {
+        let program = Self::check_sources(sources, root, options)?;
+        Ok(wgsl::emit_program(&program, options)?)
+    }
+
+    /// Same as [YuriShader::compile_sources], but every entry point gets written out as HLSL, for D3D12 (see [hlsl]).
+    /// Only the frag origin is taken from the target.
+    pub fn hlsl_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<Vec<HlslShader>, YuriCompileError> {
+        let program = Self::check_sources(sources, root, options)?;
+        Ok(hlsl::emit_program(&program, options)?)
+    }
+
+    /// Same as [YuriShader::compile_sources], but every entry point gets written out as MSL, for Metal (see [msl]).
+    /// Only the frag origin is taken from the target.
+    pub fn msl_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<Vec<MslShader>, YuriCompileError> {
+        let program = Self::check_sources(sources, root, options)?;
+        Ok(msl::emit_program(&program, options)?)
+    }
+
+    /// Runs the `@test` functions in the source on the CPU (see [testing]), or the ones with the filter in their name.
+    /// Only the defines and the loader are taken from the options.
+    pub fn test_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions, filter: Option<&str>) -> Result<Vec<TestResult>, YuriCompileError> {
+        let program = Self::check_sources(sources, root, options)?;
+        Ok(testing::run_tests(&program, filter))
+    }
+
+    /// Everything [YuriShader::compile_sources] does before generating any SPIR-V.
+    fn check_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<TypedProgram, YuriCompileError> {
+        let ast = lex::lex_file(sources.file(root))?;
+        let mut module = Self::parse(&ast)?;
+        options::apply_defines(&mut module, &options.defines)?;
+        let imports = match options.loader {
+            Some(loader) => Self::resolve_imports(&module, sources, loader)?,
+            None => Self::resolve_imports(&module, sources, &MemoryLoader::new())?,
+        };
+        let resolution = Self::resolve_names(&module, &imports)?;
+        Ok(Self::check(&module, sources, &imports, &resolution)?)
     }
 
-    pub fn lex(input: &str) -> Result<YuriAst, YuriLexError> {
+    /// Lexes the whole input at once. Use [lex::Lexer] to go one token at a time instead.
+    pub fn lex(input: &str) -> Result<YuriAst<'_>, YuriLexError> {
         lex::lex_input(input)
     }
 
+    /// Lexes the whole input, keeping the whitespace and comments around each token.
+    /// [cst::SyntaxTree::parse] turns it into a tree, for tools that need to write the source back out.
+    pub fn lex_lossless(input: &str) -> Result<LosslessTokens<'_>, YuriLexError> {
+        lex::lex_lossless(input)
+    }
+
+    /// Formats the source in the canonical style, keeping its comments. See [format] for what that looks like.
+    pub fn format(input: &str) -> Result<String, YuriCompileError> {
+        format::format_source(input)
+    }
+
     pub fn parse(input: &YuriAst) -> Result<YuriModule, YuriSemanticError> {
         parse::parse_input(input)
     }
 
-    pub fn compile(_ast: &YuriModule) -> Result<Self, YuriSemanticError> {
-        todo!()
+    /// Loads (and parses) every module imported by the given module, using the loader to find them.
+    /// Their sources get added to the map.
+    pub fn resolve_imports(module: &YuriModule, sources: &mut SourceMap, loader: &dyn SourceLoader) -> Result<ResolvedImports, YuriCompileError> {
+        import::resolve_imports(module, sources, loader)
+    }
+
+    /// Figures out what every name in the module (and the modules it imports) refers to.
+    pub fn resolve_names(module: &YuriModule, imports: &ResolvedImports) -> Result<NameResolution, YuriSemanticError> {
+        resolve::resolve_names(module, imports)
+    }
+
+    /// Type checks the program, and evaluates every global at compile time.
+    /// The sources are only kept around for debug info.
+    pub fn check(module: &YuriModule, sources: &SourceMap, imports: &ResolvedImports, resolution: &NameResolution) -> Result<TypedProgram, YuriSemanticError> {
+        check::check_program(module, sources, imports, resolution)
+    }
+
+    /// Generates SPIR-V for every entry point (or the ones picked by the options).
+    pub fn compile(program: &TypedProgram, options: &CompileOptions) -> Result<Self, YuriSemanticError> {
+        Ok(Self {
+            shaders: compile::compile_program(program, options)?,
+        })
     }
-}
\ No newline at end of file
+}
@@ -1,47 +1,355 @@
-extern crate core;
-
 use std::env::args;
 use std::fs;
+use std::path::Path;
 use std::process::ExitCode;
-use yuri::lex::YuriTokenType;
+use yuri::check::ShaderStage;
+use yuri::import::FileSystemLoader;
+use yuri::options::{CompileOptions, OptimizationLevel};
+use yuri::source::SourceMap;
+use yuri::target::{SpirvVersion, Target, TargetEnv};
 use yuri::YuriShader;
 
+const USAGE: &str = "\
+Usage: yuri <file> [options]
+  --target <env>        vulkan1.0 (the default), vulkan1.1, vulkan1.2, vulkan1.3 or opengl4.5
+  --spirv <version>     the SPIR-V version, like 1.4 (defaults to whatever the target uses)
+  --emit <format>       spirv (the default, as <name>.spv), glsl (as <name>.vert/<name>.frag), wgsl (as <name>.wgsl),
+                        hlsl (as <name>.hlsl) or msl (as <name>.metal)
+  --entry <name>        only compile this entry point (can be given more than once)
+  -D <name>=<value>     replaces the value of a `let`
+  -O0                   don't fold constants
+  -g                    keep debug info
+
+Usage: yuri fmt [--check] <file>...
+  --check               don't change anything, just list the files that aren't formatted
+
+Usage: yuri test <file> [<filter>] [-D <name>=<value>]
+  runs the `@test` functions in the file on the CPU, or the ones with the filter in their name";
+
+/// What the shaders get written out as.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Emit {
+	Spirv,
+	Glsl,
+	Wgsl,
+	Hlsl,
+	Msl,
+}
+
+impl Emit {
+	fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"spirv" => Some(Emit::Spirv),
+			"glsl" => Some(Emit::Glsl),
+			"wgsl" => Some(Emit::Wgsl),
+			"hlsl" => Some(Emit::Hlsl),
+			"msl" => Some(Emit::Msl),
+			_ => None,
+		}
+	}
+}
+
+/// Everything from the command line except the file.
+struct Arguments {
+	input_path: Option<String>,
+	env: TargetEnv,
+	spirv_version: Option<SpirvVersion>,
+	emit: Emit,
+	entry_points: Vec<String>,
+	defines: Vec<(String, String)>,
+	optimization: OptimizationLevel,
+	debug_info: bool,
+}
+
+/// A compiled entry point, ready to be written to a file.
+struct Output {
+	name: String,
+	stage: ShaderStage,
+	file_name: String,
+	contents: Vec<u8>,
+	viewport_height_offset: Option<u32>,
+}
+
+fn parse_arguments() -> Option<Arguments> {
+	let mut parsed = Arguments {
+		input_path: None,
+		env: TargetEnv::default(),
+		spirv_version: None,
+		emit: Emit::Spirv,
+		entry_points: Vec::new(),
+		defines: Vec::new(),
+		optimization: OptimizationLevel::Default,
+		debug_info: false,
+	};
+	let mut args = args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--target" => parsed.env = TargetEnv::from_name(&args.next()?)?,
+			"--spirv" => parsed.spirv_version = Some(SpirvVersion::from_name(&args.next()?)?),
+			"--emit" => parsed.emit = Emit::from_name(&args.next()?)?,
+			"--entry" => parsed.entry_points.push(args.next()?),
+			"-D" => {
+				let define = args.next()?;
+				let (name, value) = define.split_once('=')?;
+				parsed.defines.push((name.to_string(), value.to_string()));
+			}
+			"-O0" => parsed.optimization = OptimizationLevel::None,
+			"-g" => parsed.debug_info = true,
+			_ if parsed.input_path.is_none() && !arg.starts_with('-') => parsed.input_path = Some(arg),
+			_ => return None,
+		}
+	}
+	Some(parsed)
+}
+
+/// `yuri fmt`, which formats files in place.
+fn format_files(args: impl Iterator<Item = String>) -> ExitCode {
+	let mut check = false;
+	let mut paths = Vec::new();
+	for arg in args {
+		match arg.as_str() {
+			"--check" => check = true,
+			_ if !arg.starts_with('-') => paths.push(arg),
+			_ => {
+				eprintln!("{USAGE}");
+				return ExitCode::FAILURE;
+			}
+		}
+	}
+	if paths.is_empty() {
+		eprintln!("Must provide at least one file to format");
+		eprintln!("{USAGE}");
+		return ExitCode::FAILURE;
+	}
+
+	let mut failed = false;
+	for path in paths {
+		let input = match fs::read_to_string(&path) {
+			Ok(input) => input,
+			Err(err) => {
+				eprintln!("Failed to read {path}: {err}");
+				failed = true;
+				continue;
+			}
+		};
+		let formatted = match YuriShader::format(&input) {
+			Ok(formatted) => formatted,
+			Err(err) => {
+				let mut sources = SourceMap::new();
+				sources.add(path, input);
+				eprintln!("{}", err.with_sources(&sources));
+				failed = true;
+				continue;
+			}
+		};
+		if formatted == input {
+			continue;
+		}
+		if check {
+			println!("{path} isn't formatted");
+			failed = true;
+		} else if let Err(err) = fs::write(&path, formatted) {
+			eprintln!("Failed to write {path}: {err}");
+			failed = true;
+		} else {
+			println!("Formatted {path}");
+		}
+	}
+	if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+/// `yuri test`, which runs the tests in a file.
+fn run_tests(mut args: impl Iterator<Item = String>) -> ExitCode {
+	let mut path = None;
+	let mut filter = None;
+	let mut options = CompileOptions::new();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-D" => {
+				let define = args.next().unwrap_or_default();
+				let Some((name, value)) = define.split_once('=') else {
+					eprintln!("{USAGE}");
+					return ExitCode::FAILURE;
+				};
+				options = options.with_define(name.to_string(), value.to_string());
+			}
+			_ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+			_ if filter.is_none() && !arg.starts_with('-') => filter = Some(arg),
+			_ => {
+				eprintln!("{USAGE}");
+				return ExitCode::FAILURE;
+			}
+		}
+	}
+	let Some(path) = path else {
+		eprintln!("Must provide a file to test");
+		eprintln!("{USAGE}");
+		return ExitCode::FAILURE;
+	};
+	let input = match fs::read_to_string(&path) {
+		Ok(input) => input,
+		Err(err) => {
+			eprintln!("Failed to read {path}: {err}");
+			return ExitCode::FAILURE;
+		}
+	};
+
+	let mut loader = FileSystemLoader::new();
+	if let Some(parent) = Path::new(&path).parent() {
+		loader.add_search_path(parent);
+	}
+	let options = options.with_loader(&loader);
+	let mut sources = SourceMap::new();
+	let root = sources.add(path.clone(), input);
+	let results = match YuriShader::test_sources(&mut sources, root, &options, filter.as_deref()) {
+		Ok(results) => results,
+		Err(err) => {
+			eprintln!("{}", err.with_sources(&sources));
+			return ExitCode::FAILURE;
+		}
+	};
+
+	let mut failed = 0;
+	for result in &results {
+		match &result.failure {
+			None => println!("test {} ... ok", result.name),
+			Some(failure) => {
+				println!("test {} ... FAILED", result.name);
+				println!("{}\n", failure.with_sources(&sources));
+				failed += 1;
+			}
+		}
+	}
+	println!("{} passed, {failed} failed", results.len() - failed);
+	if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
 fn main() -> ExitCode {
-	let args = args()
-		.collect::<Vec<String>>();
+	match args().nth(1).as_deref() {
+		Some("fmt") => return format_files(args().skip(2)),
+		Some("test") => return run_tests(args().skip(2)),
+		_ => {}
+	}
+	let Some(arguments) = parse_arguments() else {
+		eprintln!("{USAGE}");
+		return ExitCode::FAILURE;
+	};
 
-	let input_path = if let Some(arg) = args.get(1) {
-		arg
+	let input_path = if let Some(path) = &arguments.input_path {
+		Path::new(path)
 	} else {
 		eprintln!("Must provide a file as an argument");
+		eprintln!("{USAGE}");
 		return ExitCode::FAILURE;
 	};
 
-    // TODO: frontend; just read the arguments and spit out some SPIR-V somewhere.
-	let input = fs::read_to_string(input_path)
-		.expect("failed to read file");
+	let target = match arguments.spirv_version {
+		Some(version) => match Target::with_spirv_version(arguments.env, version) {
+			Ok(target) => target,
+			Err(err) => {
+				eprintln!("{err}");
+				return ExitCode::FAILURE;
+			}
+		},
+		None => Target::new(arguments.env),
+	};
+
+	let input = match fs::read_to_string(input_path) {
+		Ok(input) => input,
+		Err(err) => {
+			eprintln!("Failed to read {}: {err}", input_path.display());
+			return ExitCode::FAILURE;
+		}
+	};
 
-	let ast = YuriShader::lex(&input).unwrap();
-	let ast_string_errors: String = ast.iter()
-		.filter(|tok| match tok.token_type { YuriTokenType::Unknown(_) => true, _ => false })
-		.map(|tok| format!("{tok:?}"))
-		.collect::<Vec<String>>()
-		.join("\n");
-	let ast_string: String = ast.iter()
-		.filter(|tok| match tok.token_type { YuriTokenType::Unknown(_) => false, _ => true })
-		.map(|tok| format!("{tok:?}"))
-		.collect::<Vec<String>>()
-		.join("\n");
-	println!("AST:\n{ast_string}");
-	println!("errors:\n{ast_string_errors}");
+	// imports are looked up next to the file being compiled
+	let mut loader = FileSystemLoader::new();
+	if let Some(parent) = input_path.parent() {
+		loader.add_search_path(parent);
+	}
 
-	let module = YuriShader::parse(&ast)
-		.unwrap();
+	let mut options = CompileOptions::new()
+		.with_target(target)
+		.with_optimization(arguments.optimization)
+		.with_debug_info(arguments.debug_info)
+		.with_loader(&loader);
+	for name in arguments.entry_points {
+		options = options.with_entry_point(name);
+	}
+	for (name, value) in arguments.defines {
+		options = options.with_define(name, value);
+	}
 
-	println!("module:\n{module:?}");
+	// the sources are kept out here so errors can point into them
+	let mut sources = SourceMap::new();
+	let root = sources.add(input_path.display().to_string(), input);
+	let outputs = match arguments.emit {
+		Emit::Spirv => YuriShader::compile_sources(&mut sources, root, &options).map(|shader| shader.shaders.into_iter()
+			.map(|compiled| Output {
+				file_name: format!("{}.spv", compiled.name),
+				contents: compiled.bytes(),
+				name: compiled.name,
+				stage: compiled.stage,
+				viewport_height_offset: compiled.viewport_height_offset,
+			})
+			.collect::<Vec<_>>()),
+		Emit::Glsl => YuriShader::glsl_sources(&mut sources, root, &options).map(|shaders| shaders.into_iter()
+			.map(|shader| Output {
+				file_name: format!("{}.{}", shader.name, shader.extension()),
+				contents: shader.source.into_bytes(),
+				name: shader.name,
+				stage: shader.stage,
+				viewport_height_offset: shader.viewport_height_offset,
+			})
+			.collect()),
+		Emit::Wgsl => YuriShader::wgsl_sources(&mut sources, root, &options).map(|shaders| shaders.into_iter()
+			.map(|shader| Output {
+				file_name: format!("{}.wgsl", shader.name),
+				contents: shader.source.into_bytes(),
+				name: shader.name,
+				stage: shader.stage,
+				viewport_height_offset: shader.viewport_height_offset,
+			})
+			.collect()),
+		Emit::Hlsl => YuriShader::hlsl_sources(&mut sources, root, &options).map(|shaders| shaders.into_iter()
+			.map(|shader| Output {
+				file_name: format!("{}.hlsl", shader.name),
+				contents: shader.source.into_bytes(),
+				name: shader.name,
+				stage: shader.stage,
+				viewport_height_offset: shader.viewport_height_offset,
+			})
+			.collect()),
+		Emit::Msl => YuriShader::msl_sources(&mut sources, root, &options).map(|shaders| shaders.into_iter()
+			.map(|shader| Output {
+				file_name: format!("{}.metal", shader.name),
+				contents: shader.source.into_bytes(),
+				name: shader.name,
+				stage: shader.stage,
+				viewport_height_offset: shader.viewport_height_offset,
+			})
+			.collect()),
+	};
+	let outputs = match outputs {
+		Ok(outputs) => outputs,
+		Err(err) => {
+			eprintln!("{}", err.with_sources(&sources));
+			return ExitCode::FAILURE;
+		}
+	};
 
-	let _shader = YuriShader::compile(&module)
-		.unwrap();
+	// every entry point gets its own file, named after the function
+	for output in &outputs {
+		let output_path = input_path.with_file_name(&output.file_name);
+		if let Err(err) = fs::write(&output_path, &output.contents) {
+			eprintln!("Failed to write {}: {err}", output_path.display());
+			return ExitCode::FAILURE;
+		}
+		println!("{:?} shader `{}` -> {}", output.stage, output.name, output_path.display());
+		if let Some(offset) = output.viewport_height_offset {
+			println!("  the render target's height goes at byte {offset} of the uniform buffer, to flip `@frag.coord`");
+		}
+	}
 
 	ExitCode::SUCCESS
 }
@@ -0,0 +1,306 @@
+//! Metal Shading Language generation, for MSL 2.0 and up.
+//! Every entry point gets a source of its own, with an entry point called `main0` (C++ won't allow `main`)
+//! and the same interface SDL's GPU API expects from MSL: vertex inputs are `[[attribute(n)]]` by location,
+//! sampler `n` is `[[texture(n)]]` and `[[sampler(n)]]`, and the props are `[[buffer(0)]]`.
+//!
+//! MSL doesn't have global variables, so the textures, the props and the builtin inputs get passed
+//! to every function that needs them. See [crate::c_like] for the rest.
+use std::fmt::Write;
+use crate::builtin::FragOrigin;
+use crate::c_like::{Dialect, Emitter};
+use crate::check::{FunctionId, InterfaceSlot, InterfaceVariable, ShaderStage, TypedProgram};
+use crate::compile::{check_props, entry_interface, interpolated, prepare_program, reads_frag_coord};
+use crate::error::YuriSemanticError;
+use crate::options::CompileOptions;
+use crate::parse::YuriType;
+use crate::text::{Emit, Scope, call_order, sections};
+
+/// A single entry point, as MSL source. The entry point in it is always called `main0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MslShader {
+	/// The fully-qualified name of the entry point function, which `main0` calls.
+	pub name: String,
+	pub stage: ShaderStage,
+	pub source: String,
+	/// How many samplers the shader uses, each of which is a texture and a sampler with the same index.
+	pub samplers: u32,
+	/// How many uniform buffers the shader uses (zero or one, since every prop goes in the same struct).
+	pub uniform_buffers: u32,
+	/// Where the height of the render target has to go in the uniform buffer, in bytes.
+	/// Same as [crate::compile::CompiledShader::viewport_height_offset].
+	pub viewport_height_offset: Option<u32>,
+}
+
+/// Metal wants flat inputs marked on the fragment shader's side.
+fn interpolation(ty: &YuriType) -> &'static str {
+	if interpolated(ty) { "" } else { ", flat" }
+}
+
+/// Writes a single entry point as MSL, with a `main0` that takes the inputs, calls it and returns the outputs.
+pub fn emit_entry_point(program: &TypedProgram, entry: FunctionId, options: &CompileOptions) -> Result<MslShader, YuriSemanticError> {
+	check_props(program)?;
+	let function = &program.functions[entry];
+	let (stage, interface) = entry_interface(function)?;
+	let order = call_order(program, entry);
+	// Metal puts the origin in the upper left, like Vulkan
+	let origin = function.frag_origin.unwrap_or(options.target.frag_origin());
+	let flip = origin != FragOrigin::UpperLeft && reads_frag_coord(program, stage, &order);
+	let mut emitter = Emitter::new(program, Dialect::Msl, stage, options.target);
+	emitter.writer.names.insert("main0".to_string());
+	let viewport_height_offset = emitter.declare_props(flip)?;
+
+	let mut functions = Vec::new();
+	for id in &order {
+		functions.push(emitter.function(*id)?);
+	}
+	emitter.writer.scope = Scope { depth: 1, ..Scope::default() };
+	let mut parameters = Vec::new();
+	let mut arguments = Vec::new();
+	if !interface.inputs.is_empty() {
+		let name = emitter.global_name("Input");
+		let input = emitter.local_name("input");
+		let mut members = String::new();
+		for variable in &interface.inputs {
+			let ty = emitter.type_name(&variable.variable_type);
+			let member = Dialect::Msl.sanitize(&variable.name);
+			let InterfaceSlot::Location(location) = variable.slot else {
+				unreachable!("inputs always have a location");
+			};
+			let attribute = match stage {
+				ShaderStage::Vertex => format!("attribute({location})"),
+				ShaderStage::Fragment => format!("user(locn{location}){}", interpolation(&variable.variable_type)),
+			};
+			let _ = writeln!(members, "\t{ty} {member} [[{attribute}]];");
+			arguments.push(format!("{input}.{member}"));
+		}
+		let _ = writeln!(emitter.writer.struct_declarations, "struct {name} {{\n{members}}};");
+		parameters.push(format!("{name} {input} [[stage_in]]"));
+	}
+	for resource in &emitter.function_resources[&entry] {
+		let resource = &emitter.resources[*resource];
+		parameters.push(format!("{} {}", resource.parameter, resource.attribute));
+	}
+	let attribute = |output: &InterfaceVariable| match output.slot {
+		InterfaceSlot::Position => "position".to_string(),
+		InterfaceSlot::Location(location) if stage == ShaderStage::Vertex => format!("user(locn{location})"),
+		InterfaceSlot::Location(location) => format!("color({location})"),
+	};
+	let call = emitter.call(entry, arguments);
+	let return_type;
+	if interface.outputs.is_empty() {
+		return_type = "void".to_string();
+		emitter.line(format!("{call};"));
+	} else {
+		// even a single output goes in a struct, since that's where its attribute goes
+		return_type = emitter.global_name("Output");
+		let output = emitter.local_name("output");
+		let mut members = String::new();
+		let mut fields = Vec::new();
+		for variable in &interface.outputs {
+			let ty = emitter.type_name(&variable.variable_type);
+			let member = Dialect::Msl.sanitize(&variable.name);
+			let _ = writeln!(members, "\t{ty} {member} [[{}]];", attribute(variable));
+			fields.push(member);
+		}
+		let _ = writeln!(emitter.writer.struct_declarations, "struct {return_type} {{\n{members}}};");
+		emitter.line(format!("{return_type} {output};"));
+		if let (true, YuriType::Complex(complex_fields)) = (interface.complex_output, &function.return_type) {
+			let result_type = emitter.type_name(&function.return_type);
+			let result = emitter.local_name("result");
+			emitter.line(format!("{result_type} {result} = {call};"));
+			for (member, field) in fields.iter().zip(complex_fields) {
+				emitter.line(format!("{output}.{member} = {result}.{};", Dialect::Msl.sanitize(&field.name)));
+			}
+		} else {
+			emitter.line(format!("{output}.{} = {call};", fields[0]));
+		}
+		emitter.line(format!("return {output};"));
+	}
+	let stage_qualifier = match stage {
+		ShaderStage::Vertex => "vertex",
+		ShaderStage::Fragment => "fragment",
+	};
+	functions.push(format!("{stage_qualifier} {return_type} main0({}) {{\n{}}}\n", parameters.join(", "), emitter.writer.scope.body));
+
+	Ok(MslShader {
+		name: function.name.clone(),
+		stage,
+		source: sections([
+			"#include <metal_stdlib>\nusing namespace metal;\n".to_string(),
+			emitter.writer.struct_declarations,
+			emitter.writer.global_declarations,
+			functions.join("\n"),
+		]),
+		samplers: emitter.writer.samplers,
+		uniform_buffers: emitter.writer.uniform_buffer as u32,
+		viewport_height_offset,
+	})
+}
+
+/// Writes every entry point in the program (or just the ones the options ask for) as MSL,
+/// after folding whatever can be worked out ahead of time.
+pub fn emit_program(program: &TypedProgram, options: &CompileOptions) -> Result<Vec<MslShader>, YuriSemanticError> {
+	let (program, entry_points) = prepare_program(program, options)?;
+	entry_points.into_iter()
+		.map(|id| emit_entry_point(&program, id, options))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use crate::msl::MslShader;
+	use crate::target::Target;
+	use crate::text::test_support::{assert_lines, source_shader, SourceShader};
+
+	source_shader!(MslShader, msl_sources);
+
+	#[test]
+	fn emit_basic() {
+		MslShader::emit_basic(&[
+			"#include <metal_stdlib>",
+			"float4x4 transform;",
+			"float3 pos [[attribute(0)]];",
+			"float4 out [[position]];",
+			// `@vert.pos` doesn't take up a location
+			"float3 pos [[user(locn0)]];",
+			"float2 coord [[user(locn1)]];",
+			"Complex0 my_vert_main(float3 pos, float2 coord, constant Props& props) {",
+			"vertex Output main0(Input input [[stage_in]], constant Props& props [[buffer(0)]]) {",
+			"Complex0 result = my_vert_main(input.pos, input.coord, props);",
+		], &[
+			"float2 coord [[user(locn1)]];",
+			"float4 out [[color(0)]];",
+			// it doesn't use the props, so it doesn't get them
+			"fragment Output main0(Input input [[stage_in]]) {",
+		]);
+	}
+
+	#[test]
+	fn same_interface_as_spirv() {
+		MslShader::same_interface_as_spirv("
+			prop tint: f3;
+			prop lights: <| color: f3, strength: f |>[2];
+			prop block: <| a: f, b: f2 |>;
+			prop tex: sampler2;
+			prop other: sampler4;
+			prop last: m3;
+			@frag(origin = lower_left)
+			fn main(coord: f2): f4 { let column = last[0]; sample(tex, coord) * f4(@frag.coord, tint.x, column.x) }
+		", &[Target::default()]);
+	}
+
+	#[test]
+	fn uniform_layout() {
+		let shader = MslShader::emit("
+			prop tint: f3;
+			prop cut: f;
+			prop lights: <| color: f3, strength: f |>[2];
+			prop weights: <| w: f |>[3];
+			prop tex: sampler2;
+			@frag
+			fn main(coord: f2): f4 { let w = weights[1]; let l = lights[0]; sample(tex, coord) * f4(tint * l.color, cut + w.w) }
+		");
+		assert_lines(&shader, &[
+			// std140 puts the next member in the last 4 bytes of a vec3
+			"packed_float3 tint;",
+			"float cut;",
+			"packed_float3 color;",
+			// and array elements are 16 bytes apart
+			"char _pad[12];",
+			"array<Complex1, 3> weights;",
+			"return tex.sample(tex_sampler, coord) * float4(float3(props.tint) * float3(l.color), props.cut + w.w);",
+			"fragment Output main0(Input input [[stage_in]], texture2d<float> tex [[texture(0)]], sampler tex_sampler [[sampler(0)]], constant Props& props [[buffer(0)]]) {",
+		]);
+		// a 4-byte array stride or a two-column matrix has no packed equivalent
+		MslShader::assert_unsupported("prop weights: f[4]; @frag fn main(): f4 { f4(weights[0]) }");
+		MslShader::assert_unsupported("prop m: m2; @frag fn main(): f4 { f4(m[0], m[1]) }");
+	}
+
+	#[test]
+	fn statements() {
+		let shader = MslShader::emit("
+			prop tex: sampler2;
+			prop mats: m3[2];
+			@spec(3) let SAMPLES: u = 8;
+			let HALF = SAMPLES / 2;
+			fn shade(coord: f2, n: u, flip: bool, s: sampler2): f4 {
+				let total = fold sum = f4(0.0), k: 3 { sum + sample(s, coord * f(k)) };
+				let steps = loop k: 4u { k * HALF };
+				let x = if flip { coord.x } else { coord.y };
+				let m = -(mats[n] / 2.0);
+				let v = m * f3(x);
+				let c = <| a = x, b = steps |>;
+				total * f4(v.x, x % 1.5, sign(i(c.a)), round(mix(0.0, 1.0, x)))
+			}
+			@vert
+			fn main(coord: f2, n: u): f4 { shade(coord, n, true, tex) }
+		");
+		assert_lines(&shader, &[
+			// a function constant is only defined if it's set, so the default goes in the constant that reads it
+			"constant uint SAMPLES_spec [[function_constant(3)]];",
+			"constant uint SAMPLES = is_function_constant_defined(SAMPLES_spec) ? SAMPLES_spec : 8u;",
+			"constant uint HALF = SAMPLES / 2u;",
+			"float4 shade(float2 coord, uint n, bool flip, texture2d<float> s, sampler s_sampler, constant Props& props) {",
+			"float4 sum = float4(0.0);",
+			// vertex shaders don't have derivatives
+			"sum = sum + s.sample(s_sampler, coord * float(k), level(0.0));",
+			"array<uint, 4> tmp;",
+			"tmp[k_1] = k_1 * HALF;",
+			"float x = flip ? coord.x : coord.y;",
+			"float3x3 m = (props.mats[n] * (1.0 / 2.0)) * -1.0;",
+			"float3 v = m * float3(x);",
+			"Complex0 c = Complex0{x, steps};",
+			"return total * float4(v.x, fmod(x, 1.5), float(clamp(int(c.a), -1, 1)), rint(mix(0.0, 1.0, x)));",
+			"return shade(coord, n, true, tex, tex_sampler, props);",
+		]);
+	}
+
+	#[test]
+	fn spec_constants() {
+		let shader = MslShader::emit("
+			@spec(0) let FLIP: bool = false;
+			@spec(7) let SCALE: f = 0.5;
+			let OFFSET = f2(SCALE, 1.0);
+			@frag
+			fn main(coord: f2): f4 { f4(if FLIP { coord.yx } else { coord }, OFFSET) }
+		");
+		assert_lines(&shader, &[
+			"constant bool FLIP_spec [[function_constant(0)]];",
+			"constant bool FLIP = is_function_constant_defined(FLIP_spec) ? FLIP_spec : false;",
+			"constant float SCALE_spec [[function_constant(7)]];",
+			"constant float SCALE = is_function_constant_defined(SCALE_spec) ? SCALE_spec : 0.5;",
+			// anything worked out from one changes along with it
+			"constant float2 OFFSET = float2(SCALE, 1.0);",
+		]);
+	}
+
+	#[test]
+	fn builtin_inputs() {
+		let shader = MslShader::emit("
+			@frag(origin = lower_left)
+			fn main(id: u): f4 { f4(@frag.coord, f(id), if @frag.front_facing { 1.0 } else { 0.0 }) }
+		");
+		assert_lines(&shader, &[
+			"float viewport_height;",
+			"uint id [[user(locn0), flat]];",
+			"float4 main_(uint id, constant Props& props, float4 frag_coord, bool front_facing) {",
+			"return float4(float2(frag_coord.x, props.viewport_height - frag_coord.y), float(id), front_facing ? 1.0 : 0.0);",
+			"fragment Output main0(Input input [[stage_in]], constant Props& props [[buffer(0)]], float4 frag_coord [[position]], bool front_facing [[front_facing]]) {",
+		]);
+		let shader = MslShader::emit("@vert fn main(): <| @vert.pos pos: f4, id: u |> { <| pos = f4(f(@vert.instance)), id = @vert.index |> }");
+		assert_lines(&shader, &[
+			"uint id [[user(locn0)]];",
+			"vertex Output main0(uint instance_index [[instance_id]], uint vertex_index [[vertex_id]]) {",
+		]);
+	}
+
+	#[test]
+	fn unsupported() {
+		MslShader::assert_unsupported("@vert fn main(v: f4): f4 { let m = inverse(m4(v, v, v, v)); m[0] }");
+		MslShader::assert_unsupported("
+			prop a: sampler2;
+			prop b: sampler2;
+			@frag fn main(c: f2, n: i): f4 { let s = if n > 0 { a } else { b }; sample(s, c) }
+		");
+	}
+}
@@ -0,0 +1,153 @@
+//! Settings for a whole compilation, so the same source can be compiled in different ways.
+use crate::error::{YuriCompileError, YuriSemanticError, YuriSemanticErrorType};
+use crate::import::SourceLoader;
+use crate::lex::{lex_input, Operator, YuriTokenType};
+use crate::parse::{ExpressionKind, Literal, YuriModule};
+use crate::target::Target;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum OptimizationLevel {
+	/// Generates code for exactly what was written, which is mostly useful for debugging the compiler.
+	None,
+	/// Folds constants and simplifies expressions before generating code.
+	#[default]
+	Default,
+}
+
+/// Everything that can be changed about compiling a shader.
+/// The defaults compile every entry point for Vulkan 1.0, without any imports.
+#[derive(Clone, Default)]
+pub struct CompileOptions<'a> {
+	pub target: Target,
+	/// The fully-qualified names of the entry points to compile, or all of them if there's no list.
+	pub entry_points: Option<Vec<String>>,
+	pub optimization: OptimizationLevel,
+	/// Whether to keep names and other debugging information in the SPIR-V.
+	pub debug_info: bool,
+	/// Replacements for the values of `let`s, as `(name, value)` pairs, like `("QUALITY", "2")`.
+	/// The value has to be a single literal, and the `let` has to exist.
+	pub defines: Vec<(String, String)>,
+	/// Where imports are loaded from. Without one, nothing can be imported.
+	pub loader: Option<&'a dyn SourceLoader>,
+	/// What to call the source in debug info and errors, usually its path.
+	pub source_name: Option<String>,
+}
+
+impl<'a> CompileOptions<'a> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_target(mut self, target: Target) -> Self {
+		self.target = target;
+		self
+	}
+
+	/// Adds an entry point to compile. Once there's at least one, the rest get skipped.
+	pub fn with_entry_point(mut self, name: impl Into<String>) -> Self {
+		self.entry_points.get_or_insert_with(Vec::new).push(name.into());
+		self
+	}
+
+	pub fn with_optimization(mut self, optimization: OptimizationLevel) -> Self {
+		self.optimization = optimization;
+		self
+	}
+
+	pub fn with_debug_info(mut self, debug_info: bool) -> Self {
+		self.debug_info = debug_info;
+		self
+	}
+
+	pub fn with_define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+		self.defines.push((name.into(), value.into()));
+		self
+	}
+
+	pub fn with_loader(mut self, loader: &'a dyn SourceLoader) -> Self {
+		self.loader = Some(loader);
+		self
+	}
+
+	pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+		self.source_name = Some(name.into());
+		self
+	}
+}
+
+/// Replaces the values of the `let`s named by the defines.
+/// Names can reach into `module` blocks in the same file, like `lighting.QUALITY`.
+pub fn apply_defines(root: &mut YuriModule, defines: &[(String, String)]) -> Result<(), YuriCompileError> {
+	for (name, value) in defines {
+		let invalid = |description: String| YuriSemanticError {
+			error_type: YuriSemanticErrorType::InvalidDeclaration,
+			description: Some(description),
+			markers: vec![],
+		};
+		let mut module = &mut *root;
+		let mut path: Vec<&str> = name.split('.').collect();
+		let global_name = path.pop().unwrap_or_default();
+		for part in path {
+			module = match module.submodules.iter_mut().find(|m| m.name == part) {
+				Some(submodule) => &mut submodule.module,
+				None => return Err(invalid(format!("There's no module `{part}` for the define `{name}`")).into()),
+			};
+		}
+		let Some(global) = module.globals.iter_mut().find(|g| g.name == global_name) else {
+			return Err(invalid(format!("The define `{name}` doesn't match any `let`")).into());
+		};
+
+		let tokens = lex_input(value)?;
+		// numbers don't have signs of their own, so a `-` in front gets applied here
+		let (sign, tokens) = match tokens.as_slice() {
+			[minus, rest @ ..] if minus.token_type == YuriTokenType::Operator(Operator::Minus) => (-1, rest),
+			all => (1, all),
+		};
+		let literal = match tokens {
+			[token] => match (&token.token_type, sign) {
+				(YuriTokenType::UnsignedNumber(n, suffix), _) => Some(Literal::DecimalNumber(sign * *n as i128, *suffix)),
+				(YuriTokenType::HexNumber(n, suffix), _) => Some(Literal::HexNumber(sign * *n as i128, *suffix)),
+				(YuriTokenType::BinaryNumber(n, suffix), _) => Some(Literal::BinaryNumber(sign * *n as i128, *suffix)),
+				(YuriTokenType::DecimalNumber(n, suffix), _) => Some(Literal::FloatNumber(sign as f64 * *n, *suffix)),
+				(YuriTokenType::Identifier("true"), 1) => Some(Literal::Boolean(true)),
+				(YuriTokenType::Identifier("false"), 1) => Some(Literal::Boolean(false)),
+				_ => None,
+			},
+			_ => None,
+		};
+		let Some(literal) = literal else {
+			return Err(invalid(format!("The define `{name}` has to be a number or a bool, not `{value}`")).into());
+		};
+		// the original value's location is the closest thing to where the new value is
+		global.value.kind = ExpressionKind::Literal(literal);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use crate::error::YuriCompileError;
+	use crate::options::apply_defines;
+	use crate::parse::{ExpressionKind, Literal};
+	use crate::YuriShader;
+
+	#[test]
+	fn defines() {
+		let mut module = YuriShader::parse(&YuriShader::lex("
+			let QUALITY: u = 1;
+			module lighting { let FANCY = false; }
+		").unwrap()).unwrap();
+		let defines = [("QUALITY".to_string(), "3".to_string()), ("lighting.FANCY".to_string(), "true".to_string())];
+		apply_defines(&mut module, &defines).unwrap();
+		assert_eq!(module.globals[0].value.kind, ExpressionKind::Literal(Literal::DecimalNumber(3, None)));
+		assert_eq!(module.submodules[0].module.globals[0].value.kind, ExpressionKind::Literal(Literal::Boolean(true)));
+
+		apply_defines(&mut module, &[("QUALITY".to_string(), "-2".to_string())]).unwrap();
+		assert_eq!(module.globals[0].value.kind, ExpressionKind::Literal(Literal::DecimalNumber(-2, None)));
+
+		for (name, value) in [("MISSING", "1"), ("QUALITY", "1 + 1"), ("QUALITY", "f2"), ("QUALITY", "-true"), ("nowhere.FANCY", "true")] {
+			let result = apply_defines(&mut module, &[(name.to_string(), value.to_string())]);
+			assert!(matches!(result, Err(YuriCompileError::Semantic(_))), "{name} = {value}");
+		}
+	}
+}
@@ -2,241 +2,1454 @@
 // Unsigned = any unsigned integer literal
 // Signed = any signed integer literal
 // Float = any float literal
-// Number = Unsigned|Signed|Float
+// Suffix = "u"|"i"|"f"|"h"
+// Number = (Unsigned|Signed|Float) + Suffix?
 // WS = whitespace/comments
 // Ident = any valid identifier (including the primitive types and ".")
 // Array = Type + WS? + "[" + WS? + (Unsigned|Ident) + WS? + "]"
-// Complex = "<|" + WS? + Ident + WS? + ":" + WS? + Type + WS? + "|>"
-// Type = Primitive|Array|Complex|Ident
+// Complex = "<|" + WS? + (Annotation + WS?)* + Ident + WS? + ":" + WS? + Type + WS? + "|>"
+// Type = Primitive|Array|Complex
 // Property = "prop" + WS + Ident + WS? + ":" + WS? + Type
 // Variable = ("export" + WS)? + "let" + WS + Ident + WS? + (":" + WS? + Type)? + WS? + "=" + WS? + Expression + WS?
 // Function = "fn" + WS + Ident + WS? + "(" + WS? + ((Ident + WS? ":" + ) + ",")* + ")" + WS? + (":" + WS? + Type)? + WS? + Block
-// Block = "{" + WS? + (Statement + WS?)* + WS? + (Statement|Expression)? + WS? +"}"
-// BinarySymbolOperator = "*"|"/"|"+"|"-"|"%"|"**"
-// BinaryKeywordOperator = "and"|"xor"|"or"
+// Block = "{" + WS? + (Statement + WS?)* + WS? + Expression? + WS? +"}"
+// BinarySymbolOperator = "*"|"/"|"+"|"-"|"%"|"**"|"&"|"|"|"^"|"<<"|">>"|"=="|"!="|"<"|"<="|">"|">="|"&&"|"||"
+// BinaryKeywordOperator = "and"|"xor"|"or"|"nor"
 // BinaryExpression = Expression + ((WS? + BinaryMathOperator + WS?)|(WS + BinaryKeywordOperator + WS)) + Expression
 // UnaryOperator = "!"|"-"
 // UnaryExpression = UnaryOperator + WS? + Expression
-// Expression = Ident|Block|Literal|BinaryExpression|UnaryExpression
-// Annotation = "@" + Ident
-// Statement = (Variable|Expression) + ";"
+// Call = (Ident|Type) + WS? + "(" + WS? + (Expression + WS? + ",")* + WS? + ")"
+// ComplexLiteral = "<|" + WS? + (Ident + WS? + ("=" + WS? + Expression)? + WS? + ",")* + WS? + "|>"
+// If = "if" + WS + Expression + WS? + Block + (WS? + "else" + WS? + (If|Block))?
+// Loop = "loop" + WS + Ident + WS? + ":" + WS? + Expression + WS? + Block
+// Fold = "fold" + WS + Ident + WS? + "=" + WS? + Expression + WS? + "," + WS? + Ident + WS? + ":" + WS? + Expression + WS? + Block
+// Map/Filter = ("map"|"filter") + WS + Ident + WS? + ":" + WS? + Expression + WS? + Block
+// Expression = Ident|Block|Literal|BinaryExpression|UnaryExpression|Call|ComplexLiteral|If|Loop|Fold|Map|Filter
+// AnnotationArgument = (Ident + WS? + "=" + WS?)? + Expression
+// Annotation = "@" + Ident + ("(" + WS? + (AnnotationArgument + WS? + ",")* + WS? + ")")?
+// Statement = ((Variable|Expression) + ";")|("return" + WS + Expression + ";")
+// Import = "import" + WS + Ident
 // Module = "module" + WS + Ident + WS? + "{" + Shader + "}"
-// Declaration = (Variable|Property|Function|Import|Module) + ";"
+// Declaration = (Annotation + WS?)* + (Variable|Property|Function|Import|Module) + ";"
 // Shader = (Declaration|WS)*
 
 // "u"|"i"|"f"|"u2"|"i2"|"f2"|"u3"|"i3"|"f3"|"u4"|"i4"|"f4"|"m2"|"m3"|"m4"
 
-use std::collections::VecDeque;
-use crate::error::{YuriLexError, YuriSemanticError};
-use crate::lex::{Keyword, YuriAst, YuriToken, YuriTokenType};
-use crate::lex::YuriTokenType::Identifier;
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::ops::Range;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::lex::{Keyword, NumberSuffix, Operator, YuriAst, YuriToken, YuriTokenType};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum CompositeSize {
 	Two,
 	Three,
 	Four
 }
 
-#[derive(Debug, Clone)]
+impl CompositeSize {
+	pub fn count(self) -> u32 {
+		match self {
+			CompositeSize::Two => 2,
+			CompositeSize::Three => 3,
+			CompositeSize::Four => 4,
+		}
+	}
+
+	pub fn from_count(count: usize) -> Option<Self> {
+		match count {
+			2 => Some(CompositeSize::Two),
+			3 => Some(CompositeSize::Three),
+			4 => Some(CompositeSize::Four),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum NumberType {
 	Float,
 	Signed,
 	Unsigned,
 }
 
-#[derive(Debug, Clone)]
+/// The dimensionality of a sampler. `sampler4` is a cube map,
+/// since a 4D texture isn't a thing (and I'd rather not find out if it is).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SamplerDimension {
+	One,
+	Two,
+	Three,
+	Cube,
+}
+
+/// The length of an array type, as written in the source.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ArrayLength {
+	Fixed(usize),
+	/// The array is sized by a named constant, which needs to be resolved later.
+	Named(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ComplexField {
+	pub name: String,
+	pub field_type: YuriType,
+	/// Annotations attached to the field, like `@vert.pos`.
+	pub annotations: Vec<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum YuriType {
 	Unit,
+	Bool,
 	Scalar(NumberType),
 	Vector(NumberType, CompositeSize),
-	Array(Box<YuriType>, usize),
-	Complex(Vec<(String, YuriType)>)
+	Matrix(CompositeSize),
+	Sampler(SamplerDimension),
+	Array(Box<YuriType>, ArrayLength),
+	Complex(Vec<ComplexField>)
+}
+
+impl YuriType {
+	/// Gets the type represented by a type keyword (`f4`, `bool`, etc.), if there is one.
+	pub fn from_keyword(keyword: Keyword) -> Option<Self> {
+		use NumberType::*;
+		use CompositeSize::*;
+		Some(match keyword {
+			Keyword::TypeBool => YuriType::Bool,
+			Keyword::TypeF => YuriType::Scalar(Float),
+			Keyword::TypeU => YuriType::Scalar(Unsigned),
+			Keyword::TypeI => YuriType::Scalar(Signed),
+			Keyword::TypeF2 => YuriType::Vector(Float, Two),
+			Keyword::TypeU2 => YuriType::Vector(Unsigned, Two),
+			Keyword::TypeI2 => YuriType::Vector(Signed, Two),
+			Keyword::TypeF3 => YuriType::Vector(Float, Three),
+			Keyword::TypeU3 => YuriType::Vector(Unsigned, Three),
+			Keyword::TypeI3 => YuriType::Vector(Signed, Three),
+			Keyword::TypeF4 => YuriType::Vector(Float, Four),
+			Keyword::TypeU4 => YuriType::Vector(Unsigned, Four),
+			Keyword::TypeI4 => YuriType::Vector(Signed, Four),
+			Keyword::TypeM2 => YuriType::Matrix(Two),
+			Keyword::TypeM3 => YuriType::Matrix(Three),
+			Keyword::TypeM4 => YuriType::Matrix(Four),
+			Keyword::TypeSampler1 => YuriType::Sampler(SamplerDimension::One),
+			Keyword::TypeSampler2 => YuriType::Sampler(SamplerDimension::Two),
+			Keyword::TypeSampler3 => YuriType::Sampler(SamplerDimension::Three),
+			Keyword::TypeSampler4 => YuriType::Sampler(SamplerDimension::Cube),
+			_ => return None,
+		})
+	}
+}
+
+/// Writes the type out the way it would be written in a shader.
+impl Display for YuriType {
+	fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+		let number = |number_type: &NumberType| match number_type {
+			NumberType::Float => "f",
+			NumberType::Signed => "i",
+			NumberType::Unsigned => "u",
+		};
+		match self {
+			YuriType::Unit => f.write_str("()"),
+			YuriType::Bool => f.write_str("bool"),
+			YuriType::Scalar(number_type) => f.write_str(number(number_type)),
+			YuriType::Vector(number_type, size) => write!(f, "{}{}", number(number_type), size.count()),
+			YuriType::Matrix(size) => write!(f, "m{}", size.count()),
+			YuriType::Sampler(dimension) => write!(f, "sampler{}", match dimension {
+				SamplerDimension::One => 1,
+				SamplerDimension::Two => 2,
+				SamplerDimension::Three => 3,
+				SamplerDimension::Cube => 4,
+			}),
+			YuriType::Array(inner, ArrayLength::Fixed(length)) => write!(f, "{inner}[{length}]"),
+			YuriType::Array(inner, ArrayLength::Named(name)) => write!(f, "{inner}[{name}]"),
+			YuriType::Complex(fields) => {
+				f.write_str("<|")?;
+				for (i, field) in fields.iter().enumerate() {
+					if i > 0 {
+						f.write_str(",")?;
+					}
+					f.write_str(" ")?;
+					for annotation in &field.annotations {
+						write!(f, "@{annotation} ")?;
+					}
+					write!(f, "{}: {}", field.name, field.field_type)?;
+				}
+				f.write_str(" |>")
+			}
+		}
+	}
+}
+
+/// An annotation (like `@vert` or `@spec(3)`) attached to a declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+	pub name: String,
+	/// Whatever was in the parentheses after the name, if there were any.
+	pub arguments: Vec<AnnotationArgument>,
+	pub location: Range<usize>,
+}
+
+/// An argument to an annotation, which can be named (like `@frag(origin = lower_left)`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnotationArgument {
+	pub name: Option<String>,
+	pub value: Expression,
 }
 
 // "if" statements are incredibly annoying syntactically.
 // I wish I could put this inside an enum variant, but I need two extra structs!
-#[derive(Debug, Clone)]
-struct IfExpression {
-	condition: Box<Expression>,
-	block: Vec<Statement>,
-	block_else: Option<Else>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfExpression {
+	pub condition: Box<Expression>,
+	pub block: Block,
+	pub block_else: Option<Else>,
 }
 
-#[derive(Debug, Clone)]
-enum Else {
-	Block(Vec<Statement>),
-	If(Box<IfExpression>),
+#[derive(Debug, Clone, PartialEq)]
+pub enum Else {
+	Block(Block),
+	If(Box<Expression>),
 }
 
-#[derive(Debug, Clone)]
-enum Literal {
-	DecimalNumber(i64),
-	HexNumber(i64),
-	BinaryNumber(i64),
-	FloatNumber(f32),
+/// Numbers are wider than anything they can end up as, so the checker can tell when they don't fit.
+/// The suffix is the type they were given, if any, like the `u` in `1u`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+	DecimalNumber(i128, Option<NumberSuffix>),
+	HexNumber(i128, Option<NumberSuffix>),
+	BinaryNumber(i128, Option<NumberSuffix>),
+	FloatNumber(f64, Option<NumberSuffix>),
 	Boolean(bool),
-	Vector {
-		dimensions: CompositeSize,
-		contents: Vec<Expression>,
-	},
-	Matrix {
-		dimensions: CompositeSize,
-		contents: Vec<Expression>,
-	},
-	Array {
-		array_type: YuriType,
-		contents: Vec<Expression>,
-	},
 }
 
-#[derive(Debug, Clone)]
-enum Expression {
+/// A sequence of statements, optionally ending in an expression that gives the block its value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+	pub statements: Vec<Statement>,
+	pub tail: Option<Box<Expression>>,
+	pub location: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionKind {
 	Literal(Literal),
 	Variable(String),
+	/// A builtin input, like `@frag.coord`.
+	Builtin(String),
 	FunctionCall {
 		function_name: String,
 		arguments: Vec<Expression>
 	},
-	Block(Vec<Statement>),
+	/// A constructor call using a type keyword, like `f4(pos, 1.0)`.
+	Construct {
+		target: YuriType,
+		arguments: Vec<Expression>
+	},
+	Complex(Vec<(String, Expression)>),
+	Array(Vec<Expression>),
+	Index {
+		target: Box<Expression>,
+		index: Box<Expression>,
+	},
+	Unary {
+		operator: UnaryOperator,
+		operand: Box<Expression>,
+	},
+	Binary {
+		operator: BinaryOperator,
+		lhs: Box<Expression>,
+		rhs: Box<Expression>,
+	},
+	Block(Block),
 	If(IfExpression),
 	Loop {
+		index: String,
 		count: Box<Expression>,
-		block: Vec<Statement>,
+		block: Block,
 	},
 	Fold {
+		accumulator: String,
 		initial: Box<Expression>,
+		item: String,
 		items: Box<Expression>,
-		block: Vec<Statement>
+		block: Block
 	},
 	Map {
-		initial: Box<Expression>,
-		block: Vec<Statement>
+		item: String,
+		items: Box<Expression>,
+		block: Block
 	},
 	Filter {
-		initial: Box<Expression>,
-		block: Vec<Statement>
+		item: String,
+		items: Box<Expression>,
+		block: Block
 	},
 }
 
-#[derive(Debug, Clone)]
-struct VariableDeclaration {
-	name: String,
-	explicit_type: Option<YuriType>,
-	inferred_type: Option<YuriType>,
-	value: Expression,
-	exported: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+	pub kind: ExpressionKind,
+	pub location: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableDeclaration {
+	pub name: String,
+	pub explicit_type: Option<YuriType>,
+	pub value: Expression,
+	pub exported: bool,
+	pub annotations: Vec<Annotation>,
+	pub location: Range<usize>,
 }
 
 /// A statement is a syntax element that can only occur in blocks.
-#[derive(Debug, Clone)]
-enum Statement {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
 	Expression(Expression),
 	Variable(VariableDeclaration),
 	Return(Expression),
 }
 
-#[derive(Debug, Clone)]
-enum BinaryOperator {
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BinaryOperator {
 	Plus,
 	Minus,
 	Times,
 	Divided,
+	Modulo,
 	Exponent,
+	BitAnd,
+	BitOr,
+	BitXor,
+	ShiftLeft,
+	ShiftRight,
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+	And,
+	Xor,
+	Or,
+	Nor,
+}
+
+impl BinaryOperator {
+	/// Higher binds tighter. [BinaryOperator::Exponent] isn't in here,
+	/// since it binds tighter than the unary operators and is handled separately.
+	fn precedence(self) -> u8 {
+		use BinaryOperator::*;
+		match self {
+			Or | Nor => 1,
+			Xor => 2,
+			And => 3,
+			Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual => 4,
+			BitOr => 5,
+			BitXor => 6,
+			BitAnd => 7,
+			ShiftLeft | ShiftRight => 8,
+			Plus | Minus => 9,
+			Times | Divided | Modulo => 10,
+			Exponent => 11,
+		}
+	}
+
+	fn from_token(token_type: &YuriTokenType) -> Option<Self> {
+		use BinaryOperator::*;
+		Some(match token_type {
+			YuriTokenType::Operator(op) => match op {
+				Operator::Plus => Plus,
+				Operator::Minus => Minus,
+				Operator::Star => Times,
+				Operator::Slash => Divided,
+				Operator::Percent => Modulo,
+				Operator::Ampersand => BitAnd,
+				Operator::Pipe => BitOr,
+				Operator::Caret => BitXor,
+				Operator::ShiftLeft => ShiftLeft,
+				Operator::ShiftRight => ShiftRight,
+				Operator::Equal => Equal,
+				Operator::NotEqual => NotEqual,
+				Operator::Less => Less,
+				Operator::LessEqual => LessEqual,
+				Operator::Greater => Greater,
+				Operator::GreaterEqual => GreaterEqual,
+				Operator::DoubleAmpersand => And,
+				Operator::DoublePipe => Or,
+				// `**` binds tighter than unary operators, so it gets parsed on its own
+				Operator::DoubleStar | Operator::Bang => return None,
+			},
+			YuriTokenType::Keyword(Keyword::And) => And,
+			YuriTokenType::Keyword(Keyword::Xor) => Xor,
+			YuriTokenType::Keyword(Keyword::Or) => Or,
+			YuriTokenType::Keyword(Keyword::Nor) => Nor,
+			_ => return None,
+		})
+	}
+}
+
+impl Display for BinaryOperator {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		use BinaryOperator::*;
+		f.write_str(match self {
+			Plus => "+",
+			Minus => "-",
+			Times => "*",
+			Divided => "/",
+			Modulo => "%",
+			Exponent => "**",
+			BitAnd => "&",
+			BitOr => "|",
+			BitXor => "^",
+			ShiftLeft => "<<",
+			ShiftRight => ">>",
+			Equal => "==",
+			NotEqual => "!=",
+			Less => "<",
+			LessEqual => "<=",
+			Greater => ">",
+			GreaterEqual => ">=",
+			And => "and",
+			Xor => "xor",
+			Or => "or",
+			Nor => "nor",
+		})
+	}
 }
 
-#[derive(Debug, Clone)]
-enum UnaryOperator {
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UnaryOperator {
 	Negate,
 	Not,
 }
 
-#[derive(Debug, Clone)]
-enum Operator {
-	Unary(UnaryOperator),
-	Binary(BinaryOperator),
+impl Display for UnaryOperator {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			UnaryOperator::Negate => "-",
+			UnaryOperator::Not => "!",
+		})
+	}
 }
 
-// impl Operator {
-// 	fn precedence(&self) -> u8 {
-// 		match self {
-// 			Operator::Unary(op) => match op {
-// 				UnaryOperator::Negate => {}
-// 				UnaryOperator::Not => {}
-// 			},
-// 			Operator::Binary(op) => match op {
-//
-// 			},
-// 		}
-// 	}
-// }
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionArgument {
+	pub name: String,
+	pub argument_type: YuriType,
+	pub location: Range<usize>,
+}
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDeclaration {
+	pub name: String,
+	pub return_type: YuriType,
+	pub arguments: Vec<FunctionArgument>,
+	pub body: Block,
+	pub exported: bool,
+	pub annotations: Vec<Annotation>,
+	pub location: Range<usize>,
+}
 
-#[derive(Debug, Clone)]
-struct FunctionDeclaration {
-	name: String,
-	return_type: YuriType,
-	arguments: Vec<(String, YuriType)>,
-	exported: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyDeclaration {
+	pub name: String,
+	pub property_type: YuriType,
+	pub annotations: Vec<Annotation>,
+	pub location: Range<usize>,
 }
 
-#[derive(Debug, Clone)]
-struct PropertyDeclaration {
-	name: String,
-	property_type: YuriType,
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportDeclaration {
+	/// The dotted name of the imported module, like `lighting.pbr`.
+	pub module: String,
+	pub location: Range<usize>,
 }
 
-#[derive(Debug, Clone)]
-struct ImportDeclaration {
-	module: String,
+/// A nested `module name { ... }` block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleDeclaration {
+	pub name: String,
+	pub module: YuriModule,
+	pub exported: bool,
+	pub location: Range<usize>,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct YuriModule {
-	imports: Vec<ImportDeclaration>,
-	properties: Vec<PropertyDeclaration>,
-	globals: Vec<VariableDeclaration>,
-	functions: Vec<FunctionDeclaration>,
-	submodules: Vec<(String, YuriModule)>
+	pub imports: Vec<ImportDeclaration>,
+	pub properties: Vec<PropertyDeclaration>,
+	pub globals: Vec<VariableDeclaration>,
+	pub functions: Vec<FunctionDeclaration>,
+	pub submodules: Vec<ModuleDeclaration>
 }
 
-pub(super) fn parse_input(ast: &YuriAst) -> Result<YuriModule, YuriSemanticError> {
-	fn parse_input_recursive(ast: &mut VecDeque<YuriToken>, current_module: &mut YuriModule) {
-		let mut annotations = Vec::new();
-		while let Some(mut tok) = ast.pop_front() {
-			if let YuriTokenType::Annotation(ann) = tok.token_type {
-				annotations.push(ann);
-				continue;
-			}
-
-			if let YuriTokenType::Keyword(kw) = tok.token_type {
-				// module declaration
-				if kw == Keyword::Module {
-					let name = if let Some(name) = ast.pop_front() {
-						if let Identifier(name) = name.token_type {
-							name
-						} else {
-							eprintln!("unexpected token (expected module name) {name:?}");
-							break;
-						}
-					} else {
+impl YuriModule {
+	/// Every import in this module and all of its submodules.
+	pub fn all_imports(&self) -> Vec<&ImportDeclaration> {
+		let mut imports: Vec<&ImportDeclaration> = self.imports.iter().collect();
+		for submodule in &self.submodules {
+			imports.extend(submodule.module.all_imports());
+		}
+		imports
+	}
+
+	/// The names of every declaration in this module,
+	/// with the declarations in submodules prefixed by the submodule's name.
+	pub fn declaration_names(&self) -> Vec<String> {
+		let mut names: Vec<String> = self.properties.iter().map(|p| p.name.clone())
+			.chain(self.globals.iter().map(|g| g.name.clone()))
+			.chain(self.functions.iter().map(|f| f.name.clone()))
+			.collect();
+		for submodule in &self.submodules {
+			names.extend(
+				submodule.module.declaration_names()
+					.into_iter()
+					.map(|decl| format!("{}.{decl}", submodule.name))
+			);
+		}
+		names
+	}
+}
+
+// Writing the AST back out as source. It all goes on as few lines as it can, `yuri fmt` can make it pretty.
+// Nothing keeps track of parentheses, so any operation inside another one gets them, which parses back the same.
+
+/// Operations get parentheses when they're inside something else, so precedence never comes up.
+struct Operand<'a>(&'a Expression);
+
+impl Display for Operand<'_> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match &self.0.kind {
+			ExpressionKind::Binary { .. } | ExpressionKind::Unary { .. } => write!(f, "({})", self.0),
+			_ => write!(f, "{}", self.0),
+		}
+	}
+}
+
+/// Writes a comma-separated list.
+fn list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> std::fmt::Result {
+	for (i, item) in items.iter().enumerate() {
+		if i > 0 {
+			f.write_str(", ")?;
+		}
+		write!(f, "{item}")?;
+	}
+	Ok(())
+}
+
+impl Display for Literal {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let suffix = |suffix: &Option<NumberSuffix>| suffix.map_or("", NumberSuffix::name);
+		match self {
+			Literal::DecimalNumber(n, s) => write!(f, "{n}{}", suffix(s)),
+			Literal::HexNumber(n, s) => write!(f, "0x{n:X}{}", suffix(s)),
+			Literal::BinaryNumber(n, s) => write!(f, "0b{n:b}{}", suffix(s)),
+			// debug formatting always has a point or an exponent, so it stays a float
+			Literal::FloatNumber(n, s) => write!(f, "{n:?}{}", suffix(s)),
+			Literal::Boolean(b) => write!(f, "{b}"),
+		}
+	}
+}
+
+impl Display for Expression {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match &self.kind {
+			ExpressionKind::Literal(literal) => write!(f, "{literal}"),
+			ExpressionKind::Variable(name) => f.write_str(name),
+			ExpressionKind::Builtin(name) => write!(f, "@{name}"),
+			ExpressionKind::FunctionCall { function_name, arguments } => {
+				write!(f, "{function_name}(")?;
+				list(f, arguments)?;
+				f.write_str(")")
+			}
+			ExpressionKind::Construct { target, arguments } => {
+				write!(f, "{target}(")?;
+				list(f, arguments)?;
+				f.write_str(")")
+			}
+			ExpressionKind::Complex(fields) => {
+				f.write_str("<|")?;
+				for (i, (name, value)) in fields.iter().enumerate() {
+					write!(f, "{} {name} = {value}", if i > 0 { "," } else { "" })?;
+				}
+				f.write_str(" |>")
+			}
+			ExpressionKind::Array(elements) => {
+				f.write_str("[")?;
+				list(f, elements)?;
+				f.write_str("]")
+			}
+			ExpressionKind::Index { target, index } => write!(f, "{}[{index}]", Operand(target)),
+			ExpressionKind::Unary { operator, operand } => write!(f, "{operator}{}", Operand(operand)),
+			ExpressionKind::Binary { operator, lhs, rhs } => write!(f, "{} {operator} {}", Operand(lhs), Operand(rhs)),
+			ExpressionKind::Block(block) => write!(f, "{block}"),
+			ExpressionKind::If(if_expr) => write!(f, "{if_expr}"),
+			ExpressionKind::Loop { index, count, block } => write!(f, "loop {index}: {count} {block}"),
+			ExpressionKind::Fold { accumulator, initial, item, items, block } => {
+				write!(f, "fold {accumulator} = {initial}, {item}: {items} {block}")
+			}
+			ExpressionKind::Map { item, items, block } => write!(f, "map {item}: {items} {block}"),
+			ExpressionKind::Filter { item, items, block } => write!(f, "filter {item}: {items} {block}"),
+		}
+	}
+}
+
+impl Display for IfExpression {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "if {} {}", self.condition, self.block)?;
+		match &self.block_else {
+			Some(Else::Block(block)) => write!(f, " else {block}"),
+			Some(Else::If(else_if)) => write!(f, " else {else_if}"),
+			None => Ok(()),
+		}
+	}
+}
+
+impl Display for Block {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str("{")?;
+		for statement in &self.statements {
+			write!(f, " {statement}")?;
+		}
+		if let Some(tail) = &self.tail {
+			write!(f, " {tail}")?;
+		}
+		f.write_str(" }")
+	}
+}
+
+impl Display for Statement {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Statement::Expression(expr) => write!(f, "{expr};"),
+			Statement::Variable(variable) => write!(f, "{variable}"),
+			Statement::Return(expr) => write!(f, "return {expr};"),
+		}
+	}
+}
+
+impl Display for Annotation {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "@{}", self.name)?;
+		if !self.arguments.is_empty() {
+			f.write_str("(")?;
+			for (i, argument) in self.arguments.iter().enumerate() {
+				if i > 0 {
+					f.write_str(", ")?;
+				}
+				if let Some(name) = &argument.name {
+					write!(f, "{name} = ")?;
+				}
+				write!(f, "{}", argument.value)?;
+			}
+			f.write_str(")")?;
+		}
+		Ok(())
+	}
+}
+
+/// Annotations go first, then `export`, same as the formatter puts them.
+fn declaration_start(f: &mut Formatter<'_>, annotations: &[Annotation], exported: bool) -> std::fmt::Result {
+	for annotation in annotations {
+		write!(f, "{annotation} ")?;
+	}
+	if exported {
+		f.write_str("export ")?;
+	}
+	Ok(())
+}
+
+impl Display for VariableDeclaration {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		declaration_start(f, &self.annotations, self.exported)?;
+		write!(f, "let {}", self.name)?;
+		if let Some(explicit_type) = &self.explicit_type {
+			write!(f, ": {explicit_type}")?;
+		}
+		write!(f, " = {};", self.value)
+	}
+}
+
+impl Display for FunctionDeclaration {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		declaration_start(f, &self.annotations, self.exported)?;
+		write!(f, "fn {}(", self.name)?;
+		for (i, argument) in self.arguments.iter().enumerate() {
+			write!(f, "{}{}: {}", if i > 0 { ", " } else { "" }, argument.name, argument.argument_type)?;
+		}
+		f.write_str(")")?;
+		if self.return_type != YuriType::Unit {
+			write!(f, ": {}", self.return_type)?;
+		}
+		write!(f, " {}", self.body)
+	}
+}
+
+impl Display for PropertyDeclaration {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		declaration_start(f, &self.annotations, false)?;
+		write!(f, "prop {}: {};", self.name, self.property_type)
+	}
+}
+
+/// Every kind of declaration is kept in its own list, so they come out grouped together
+/// (imports, props, `let`s, functions, then modules) instead of in the order they were written.
+impl Display for YuriModule {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		for import in &self.imports {
+			writeln!(f, "import {};", import.module)?;
+		}
+		for property in &self.properties {
+			writeln!(f, "{property}")?;
+		}
+		for global in &self.globals {
+			writeln!(f, "{global}")?;
+		}
+		for function in &self.functions {
+			writeln!(f, "{function}")?;
+		}
+		for submodule in &self.submodules {
+			declaration_start(f, &[], submodule.exported)?;
+			writeln!(f, "module {} {{", submodule.name)?;
+			write!(f, "{}", submodule.module)?;
+			writeln!(f, "}}")?;
+		}
+		Ok(())
+	}
+}
+
+fn unexpected_token(token: &YuriToken, expected: &str) -> YuriSemanticError {
+	let description = if let YuriTokenType::Unknown(err) = &token.token_type {
+		err.description.clone()
+			.unwrap_or_else(|| "Unknown token %".to_string())
+	} else {
+		format!("Unexpected token % (expected {expected})")
+	};
+	YuriSemanticError {
+		error_type: YuriSemanticErrorType::UnexpectedToken,
+		description: Some(description),
+		markers: vec![token.location.clone()],
+	}
+}
+
+struct Parser<'a, 's> {
+	tokens: &'a [YuriToken<'s>],
+	seek: usize,
+}
+
+impl<'a, 's> Parser<'a, 's> {
+	fn peek(&self) -> Option<&'a YuriToken<'s>> {
+		self.tokens.get(self.seek)
+	}
+
+	fn peek_type(&self) -> Option<&'a YuriTokenType<'s>> {
+		self.peek().map(|tok| &tok.token_type)
+	}
+
+	fn is_next(&self, token_type: &YuriTokenType) -> bool {
+		self.peek_type() == Some(token_type)
+	}
+
+	/// Location of the end of the last token, used to point at EOF errors.
+	fn end_location(&self) -> Range<usize> {
+		let end = self.tokens.last().map_or(0, |tok| tok.location.end);
+		end..end
+	}
+
+	/// The end of the previously consumed token.
+	fn previous_end(&self) -> usize {
+		self.seek.checked_sub(1)
+			.and_then(|i| self.tokens.get(i))
+			.map_or(0, |tok| tok.location.end)
+	}
+
+	fn next(&mut self, expected: &str) -> Result<&'a YuriToken<'s>, YuriSemanticError> {
+		if let Some(tok) = self.tokens.get(self.seek) {
+			self.seek += 1;
+			Ok(tok)
+		} else {
+			Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::UnexpectedEndOfFile,
+				description: Some(format!("Unexpected end of file % (expected {expected})")),
+				markers: vec![self.end_location()],
+			})
+		}
+	}
+
+	fn expect(&mut self, token_type: &YuriTokenType, expected: &str) -> Result<&'a YuriToken<'s>, YuriSemanticError> {
+		let tok = self.next(expected)?;
+		if tok.token_type == *token_type {
+			Ok(tok)
+		} else {
+			Err(unexpected_token(tok, expected))
+		}
+	}
+
+	/// Consumes the next token if it matches.
+	fn take(&mut self, token_type: &YuriTokenType) -> bool {
+		if self.is_next(token_type) {
+			self.seek += 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn expect_identifier(&mut self, expected: &str) -> Result<(String, Range<usize>), YuriSemanticError> {
+		let tok = self.next(expected)?;
+		if let YuriTokenType::Identifier(name) = &tok.token_type {
+			Ok((name.to_string(), tok.location.clone()))
+		} else {
+			Err(unexpected_token(tok, expected))
+		}
+	}
+
+	fn parse_type(&mut self) -> Result<YuriType, YuriSemanticError> {
+		let tok = self.next("a type")?;
+		let mut ty = match &tok.token_type {
+			YuriTokenType::Keyword(kw) => YuriType::from_keyword(*kw)
+				.ok_or_else(|| unexpected_token(tok, "a type"))?,
+			YuriTokenType::OpenTri => {
+				let mut fields = Vec::new();
+				while !self.take(&YuriTokenType::CloseTri) {
+					let mut annotations = Vec::new();
+					while let Some(YuriTokenType::Annotation(name)) = self.peek_type() {
+						annotations.push(name.to_string());
+						self.seek += 1;
+					}
+					let (name, _) = self.expect_identifier("a field name")?;
+					self.expect(&YuriTokenType::TypeHint, "`:`")?;
+					let field_type = self.parse_type()?;
+					fields.push(ComplexField { name, field_type, annotations });
+					if !self.take(&YuriTokenType::Separator) {
+						self.expect(&YuriTokenType::CloseTri, "`,` or `|>`")?;
 						break;
+					}
+				}
+				YuriType::Complex(fields)
+			}
+			_ => return Err(unexpected_token(tok, "a type")),
+		};
+		while self.take(&YuriTokenType::OpenSquare) {
+			let tok = self.next("an array length")?;
+			let length = match &tok.token_type {
+				YuriTokenType::UnsignedNumber(n, None | Some(NumberSuffix::Unsigned | NumberSuffix::Signed))
+				| YuriTokenType::HexNumber(n, None | Some(NumberSuffix::Unsigned | NumberSuffix::Signed))
+				| YuriTokenType::BinaryNumber(n, None | Some(NumberSuffix::Unsigned | NumberSuffix::Signed)) => ArrayLength::Fixed(*n as usize),
+				YuriTokenType::Identifier(name) => ArrayLength::Named(name.to_string()),
+				_ => return Err(unexpected_token(tok, "an array length")),
+			};
+			self.expect(&YuriTokenType::CloseSquare, "`]`")?;
+			ty = YuriType::Array(Box::new(ty), length);
+		}
+		Ok(ty)
+	}
+
+	fn parse_expression(&mut self) -> Result<Expression, YuriSemanticError> {
+		self.parse_binary(0)
+	}
+
+	/// Precedence climbing, since writing out a function for every level gets old fast.
+	fn parse_binary(&mut self, min_precedence: u8) -> Result<Expression, YuriSemanticError> {
+		let mut lhs = self.parse_unary()?;
+		while let Some(operator) = self.peek_type().and_then(BinaryOperator::from_token) {
+			let precedence = operator.precedence();
+			if precedence < min_precedence {
+				break;
+			}
+			self.seek += 1;
+			let rhs = self.parse_binary(precedence + 1)?;
+			let location = lhs.location.start..rhs.location.end;
+			lhs = Expression {
+				kind: ExpressionKind::Binary {
+					operator,
+					lhs: Box::new(lhs),
+					rhs: Box::new(rhs),
+				},
+				location,
+			};
+		}
+		Ok(lhs)
+	}
+
+	fn parse_unary(&mut self) -> Result<Expression, YuriSemanticError> {
+		let operator = match self.peek_type() {
+			Some(YuriTokenType::Operator(Operator::Minus)) => Some(UnaryOperator::Negate),
+			Some(YuriTokenType::Operator(Operator::Bang)) => Some(UnaryOperator::Not),
+			_ => None,
+		};
+		if let Some(operator) = operator {
+			let start = self.next("an operator")?.location.start;
+			let operand = self.parse_unary()?;
+			let location = start..operand.location.end;
+			return Ok(Expression {
+				kind: ExpressionKind::Unary { operator, operand: Box::new(operand) },
+				location,
+			});
+		}
+		self.parse_power()
+	}
+
+	/// `**` is right-associative and binds tighter than unary operators, so `-x ** 2` is `-(x ** 2)`.
+	fn parse_power(&mut self) -> Result<Expression, YuriSemanticError> {
+		let base = self.parse_postfix()?;
+		if self.is_next(&YuriTokenType::Operator(Operator::DoubleStar)) {
+			self.seek += 1;
+			let exponent = self.parse_unary()?;
+			let location = base.location.start..exponent.location.end;
+			return Ok(Expression {
+				kind: ExpressionKind::Binary {
+					operator: BinaryOperator::Exponent,
+					lhs: Box::new(base),
+					rhs: Box::new(exponent),
+				},
+				location,
+			});
+		}
+		Ok(base)
+	}
+
+	fn parse_postfix(&mut self) -> Result<Expression, YuriSemanticError> {
+		let mut expr = self.parse_primary()?;
+		while self.take(&YuriTokenType::OpenSquare) {
+			let index = self.parse_expression()?;
+			let end = self.expect(&YuriTokenType::CloseSquare, "`]`")?.location.end;
+			let location = expr.location.start..end;
+			expr = Expression {
+				kind: ExpressionKind::Index { target: Box::new(expr), index: Box::new(index) },
+				location,
+			};
+		}
+		Ok(expr)
+	}
+
+	/// Parses a comma-separated list of expressions up to (and including) the closing token.
+	fn parse_arguments(&mut self, close: &YuriTokenType, expected: &str) -> Result<(Vec<Expression>, usize), YuriSemanticError> {
+		let mut arguments = Vec::new();
+		loop {
+			if let Some(tok) = self.peek() && tok.token_type == *close {
+				self.seek += 1;
+				return Ok((arguments, tok.location.end));
+			}
+			arguments.push(self.parse_expression()?);
+			if !self.take(&YuriTokenType::Separator) {
+				let end = self.expect(close, expected)?.location.end;
+				return Ok((arguments, end));
+			}
+		}
+	}
+
+	fn parse_primary(&mut self) -> Result<Expression, YuriSemanticError> {
+		let tok = self.next("an expression")?;
+		let start = tok.location.start;
+		let kind = match &tok.token_type {
+			YuriTokenType::UnsignedNumber(n, suffix) => ExpressionKind::Literal(Literal::DecimalNumber(*n as i128, *suffix)),
+			YuriTokenType::HexNumber(n, suffix) => ExpressionKind::Literal(Literal::HexNumber(*n as i128, *suffix)),
+			YuriTokenType::BinaryNumber(n, suffix) => ExpressionKind::Literal(Literal::BinaryNumber(*n as i128, *suffix)),
+			YuriTokenType::DecimalNumber(n, suffix) => ExpressionKind::Literal(Literal::FloatNumber(*n, *suffix)),
+			YuriTokenType::Annotation(name) => ExpressionKind::Builtin(name.to_string()),
+			YuriTokenType::Identifier(name) => match *name {
+				"true" => ExpressionKind::Literal(Literal::Boolean(true)),
+				"false" => ExpressionKind::Literal(Literal::Boolean(false)),
+				_ => if self.take(&YuriTokenType::OpenParen) {
+					let (arguments, end) = self.parse_arguments(&YuriTokenType::CloseParen, "`,` or `)`")?;
+					return Ok(Expression {
+						kind: ExpressionKind::FunctionCall { function_name: name.to_string(), arguments },
+						location: start..end,
+					});
+				} else {
+					ExpressionKind::Variable(name.to_string())
+				}
+			},
+			YuriTokenType::Keyword(kw) if YuriType::from_keyword(*kw).is_some() => {
+				// put the keyword back so parse_type can have it (and any array suffix)
+				self.seek -= 1;
+				let target = self.parse_type()?;
+				self.expect(&YuriTokenType::OpenParen, "`(`")?;
+				let (arguments, end) = self.parse_arguments(&YuriTokenType::CloseParen, "`,` or `)`")?;
+				return Ok(Expression {
+					kind: ExpressionKind::Construct { target, arguments },
+					location: start..end,
+				});
+			}
+			YuriTokenType::OpenParen => {
+				let mut inner = self.parse_expression()?;
+				let end = self.expect(&YuriTokenType::CloseParen, "`)`")?.location.end;
+				inner.location = start..end;
+				return Ok(inner);
+			}
+			YuriTokenType::OpenSquare => {
+				let (elements, end) = self.parse_arguments(&YuriTokenType::CloseSquare, "`,` or `]`")?;
+				return Ok(Expression {
+					kind: ExpressionKind::Array(elements),
+					location: start..end,
+				});
+			}
+			YuriTokenType::OpenTri => {
+				let mut fields = Vec::new();
+				let end = loop {
+					if let Some(tok) = self.peek() && tok.token_type == YuriTokenType::CloseTri {
+						self.seek += 1;
+						break tok.location.end;
+					}
+					let (name, location) = self.expect_identifier("a field name")?;
+					let value = if self.take(&YuriTokenType::Assignment) {
+						self.parse_expression()?
+					} else {
+						// shorthand, the field takes the value of the variable with the same name
+						Expression { kind: ExpressionKind::Variable(name.clone()), location }
 					};
+					fields.push((name, value));
+					if !self.take(&YuriTokenType::Separator) {
+						break self.expect(&YuriTokenType::CloseTri, "`,` or `|>`")?.location.end;
+					}
+				};
+				return Ok(Expression {
+					kind: ExpressionKind::Complex(fields),
+					location: start..end,
+				});
+			}
+			YuriTokenType::OpenBrace => {
+				self.seek -= 1;
+				let block = self.parse_block()?;
+				let location = block.location.clone();
+				return Ok(Expression { kind: ExpressionKind::Block(block), location });
+			}
+			YuriTokenType::Keyword(Keyword::If) => {
+				self.seek -= 1;
+				return self.parse_if();
+			}
+			YuriTokenType::Keyword(Keyword::Loop) => {
+				let (index, _) = self.expect_identifier("a loop index name")?;
+				self.expect(&YuriTokenType::TypeHint, "`:`")?;
+				let count = self.parse_expression()?;
+				let block = self.parse_block()?;
+				let location = start..block.location.end;
+				return Ok(Expression {
+					kind: ExpressionKind::Loop { index, count: Box::new(count), block },
+					location,
+				});
+			}
+			YuriTokenType::Keyword(Keyword::Fold) => {
+				let (accumulator, _) = self.expect_identifier("an accumulator name")?;
+				self.expect(&YuriTokenType::Assignment, "`=`")?;
+				let initial = self.parse_expression()?;
+				self.expect(&YuriTokenType::Separator, "`,`")?;
+				let (item, _) = self.expect_identifier("an item name")?;
+				self.expect(&YuriTokenType::TypeHint, "`:`")?;
+				let items = self.parse_expression()?;
+				let block = self.parse_block()?;
+				let location = start..block.location.end;
+				return Ok(Expression {
+					kind: ExpressionKind::Fold {
+						accumulator,
+						initial: Box::new(initial),
+						item,
+						items: Box::new(items),
+						block,
+					},
+					location,
+				});
+			}
+			YuriTokenType::Keyword(kw @ (Keyword::Map | Keyword::Filter)) => {
+				let (item, _) = self.expect_identifier("an item name")?;
+				self.expect(&YuriTokenType::TypeHint, "`:`")?;
+				let items = Box::new(self.parse_expression()?);
+				let block = self.parse_block()?;
+				let location = start..block.location.end;
+				let kind = if *kw == Keyword::Map {
+					ExpressionKind::Map { item, items, block }
+				} else {
+					ExpressionKind::Filter { item, items, block }
+				};
+				return Ok(Expression { kind, location });
+			}
+			_ => return Err(unexpected_token(tok, "an expression")),
+		};
+		Ok(Expression { kind, location: tok.location.clone() })
+	}
+
+	fn parse_if(&mut self) -> Result<Expression, YuriSemanticError> {
+		let start = self.expect(&YuriTokenType::Keyword(Keyword::If), "`if`")?.location.start;
+		let condition = self.parse_expression()?;
+		let block = self.parse_block()?;
+		let mut end = block.location.end;
+		let block_else = if self.take(&YuriTokenType::Keyword(Keyword::Else)) {
+			if self.is_next(&YuriTokenType::Keyword(Keyword::If)) {
+				let else_if = self.parse_if()?;
+				end = else_if.location.end;
+				Some(Else::If(Box::new(else_if)))
+			} else {
+				let else_block = self.parse_block()?;
+				end = else_block.location.end;
+				Some(Else::Block(else_block))
+			}
+		} else {
+			None
+		};
+		Ok(Expression {
+			kind: ExpressionKind::If(IfExpression {
+				condition: Box::new(condition),
+				block,
+				block_else,
+			}),
+			location: start..end,
+		})
+	}
+
+	fn parse_block(&mut self) -> Result<Block, YuriSemanticError> {
+		let start = self.expect(&YuriTokenType::OpenBrace, "`{`")?.location.start;
+		let mut statements = Vec::new();
+		let mut tail = None;
+		loop {
+			match self.peek_type() {
+				Some(YuriTokenType::CloseBrace) => break,
+				Some(YuriTokenType::Keyword(Keyword::Let)) => {
+					let variable = self.parse_variable(Vec::new(), false)?;
+					statements.push(Statement::Variable(variable));
+				}
+				Some(YuriTokenType::Keyword(Keyword::Return)) => {
+					self.seek += 1;
+					let value = self.parse_expression()?;
+					self.expect(&YuriTokenType::Terminator, "`;`")?;
+					statements.push(Statement::Return(value));
+				}
+				_ => {
+					let expr = self.parse_expression()?;
+					if self.take(&YuriTokenType::Terminator) {
+						statements.push(Statement::Expression(expr));
+					} else if self.is_next(&YuriTokenType::CloseBrace) {
+						tail = Some(Box::new(expr));
+						break;
+					} else {
+						let tok = self.next("`;` or `}`")?;
+						return Err(unexpected_token(tok, "`;` or `}`"));
+					}
+				}
+			}
+		}
+		let end = self.expect(&YuriTokenType::CloseBrace, "`}`")?.location.end;
+		Ok(Block { statements, tail, location: start..end })
+	}
+
+	fn parse_variable(&mut self, annotations: Vec<Annotation>, exported: bool) -> Result<VariableDeclaration, YuriSemanticError> {
+		let start = self.expect(&YuriTokenType::Keyword(Keyword::Let), "`let`")?.location.start;
+		let (name, _) = self.expect_identifier("a variable name")?;
+		let explicit_type = if self.take(&YuriTokenType::TypeHint) {
+			Some(self.parse_type()?)
+		} else {
+			None
+		};
+		self.expect(&YuriTokenType::Assignment, "`=`")?;
+		let value = self.parse_expression()?;
+		let end = self.expect(&YuriTokenType::Terminator, "`;`")?.location.end;
+		Ok(VariableDeclaration {
+			name,
+			explicit_type,
+			value,
+			exported,
+			annotations,
+			location: start..end,
+		})
+	}
+
+	fn parse_function(&mut self, annotations: Vec<Annotation>, exported: bool) -> Result<FunctionDeclaration, YuriSemanticError> {
+		let start = self.expect(&YuriTokenType::Keyword(Keyword::Fn), "`fn`")?.location.start;
+		let (name, _) = self.expect_identifier("a function name")?;
+		self.expect(&YuriTokenType::OpenParen, "`(`")?;
+		let mut arguments = Vec::new();
+		while !self.take(&YuriTokenType::CloseParen) {
+			let (name, location) = self.expect_identifier("an argument name")?;
+			self.expect(&YuriTokenType::TypeHint, "`:`")?;
+			let argument_type = self.parse_type()?;
+			let location = location.start..self.previous_end();
+			arguments.push(FunctionArgument { name, argument_type, location });
+			if !self.take(&YuriTokenType::Separator) {
+				self.expect(&YuriTokenType::CloseParen, "`,` or `)`")?;
+				break;
+			}
+		}
+		let return_type = if self.take(&YuriTokenType::TypeHint) {
+			self.parse_type()?
+		} else {
+			YuriType::Unit
+		};
+		let body = self.parse_block()?;
+		let end = body.location.end;
+		// the trailing semicolon is optional for functions.
+		self.take(&YuriTokenType::Terminator);
+		Ok(FunctionDeclaration {
+			name,
+			return_type,
+			arguments,
+			body,
+			exported,
+			annotations,
+			location: start..end,
+		})
+	}
+
+	fn parse_annotation(&mut self) -> Result<Annotation, YuriSemanticError> {
+		let tok = self.next("an annotation")?;
+		let YuriTokenType::Annotation(name) = &tok.token_type else {
+			return Err(unexpected_token(tok, "an annotation"));
+		};
+		let mut arguments = Vec::new();
+		let mut end = tok.location.end;
+		if self.take(&YuriTokenType::OpenParen) {
+			end = loop {
+				if let Some(close) = self.peek() && close.token_type == YuriTokenType::CloseParen {
+					self.seek += 1;
+					break close.location.end;
+				}
+				let named = matches!(self.peek_type(), Some(YuriTokenType::Identifier(_)))
+					&& self.tokens.get(self.seek + 1).is_some_and(|tok| tok.token_type == YuriTokenType::Assignment);
+				let name = if named {
+					let (name, _) = self.expect_identifier("an argument name")?;
+					self.seek += 1;
+					Some(name)
+				} else {
+					None
+				};
+				let value = self.parse_expression()?;
+				arguments.push(AnnotationArgument { name, value });
+				if !self.take(&YuriTokenType::Separator) {
+					break self.expect(&YuriTokenType::CloseParen, "`,` or `)`")?.location.end;
+				}
+			};
+		}
+		Ok(Annotation { name: name.to_string(), arguments, location: tok.location.start..end })
+	}
+
+	/// Parses declarations into the module until the tokens run out,
+	/// or until the closing brace if this is a nested module.
+	fn parse_declarations(&mut self, module: &mut YuriModule, nested: bool) -> Result<(), YuriSemanticError> {
+		let mut annotations = Vec::new();
+		let mut exported: Option<Range<usize>> = None;
+		while let Some(tok) = self.peek() {
+			if nested && tok.token_type == YuriTokenType::CloseBrace {
+				break;
+			}
+			let only_let_fn = |what: &str| YuriSemanticError {
+				error_type: YuriSemanticErrorType::InvalidDeclaration,
+				description: Some(format!("Only `let`, `fn` and `module` declarations can be exported, {what} can't (exported at %)")),
+				markers: vec![exported.clone().unwrap_or(tok.location.clone())],
+			};
+			match &tok.token_type {
+				YuriTokenType::Annotation(_) => {
+					annotations.push(self.parse_annotation()?);
+					continue;
+				}
+				YuriTokenType::Keyword(Keyword::Export) => {
+					self.seek += 1;
+					exported = Some(tok.location.clone());
+					continue;
+				}
+				YuriTokenType::Keyword(Keyword::Import) => {
+					if exported.is_some() {
+						return Err(only_let_fn("imports"));
+					}
+					self.seek += 1;
+					let (name, _) = self.expect_identifier("a module name")?;
+					let end = self.expect(&YuriTokenType::Terminator, "`;`")?.location.end;
+					module.imports.push(ImportDeclaration { module: name, location: tok.location.start..end });
+				}
+				YuriTokenType::Keyword(Keyword::Prop) => {
+					if exported.is_some() {
+						return Err(only_let_fn("props"));
+					}
+					self.seek += 1;
+					let (name, _) = self.expect_identifier("a property name")?;
+					self.expect(&YuriTokenType::TypeHint, "`:`")?;
+					let property_type = self.parse_type()?;
+					let end = self.expect(&YuriTokenType::Terminator, "`;`")?.location.end;
+					module.properties.push(PropertyDeclaration {
+						name,
+						property_type,
+						annotations: mem::take(&mut annotations),
+						location: tok.location.start..end,
+					});
+				}
+				YuriTokenType::Keyword(Keyword::Let) => {
+					let variable = self.parse_variable(mem::take(&mut annotations), exported.is_some())?;
+					module.globals.push(variable);
+				}
+				YuriTokenType::Keyword(Keyword::Fn) => {
+					let function = self.parse_function(mem::take(&mut annotations), exported.is_some())?;
+					module.functions.push(function);
+				}
+				YuriTokenType::Keyword(Keyword::Module) => {
+					self.seek += 1;
+					let (name, _) = self.expect_identifier("a module name")?;
+					self.expect(&YuriTokenType::OpenBrace, "`{`")?;
 					let mut submodule = YuriModule::default();
 					// NOTE: this could cause stack overflow
 					// if modules are nested a comically large amount
-					parse_input_recursive(ast, &mut submodule);
-					current_module.submodules.push((name, submodule));
-					continue;
+					self.parse_declarations(&mut submodule, true)?;
+					let end = self.expect(&YuriTokenType::CloseBrace, "`}`")?.location.end;
+					// like functions, the semicolon is optional.
+					self.take(&YuriTokenType::Terminator);
+					module.submodules.push(ModuleDeclaration {
+						name,
+						module: submodule,
+						exported: exported.is_some(),
+						location: tok.location.start..end,
+					});
 				}
-
-				let is_exported = kw == Keyword::Export;
-
+				_ => return Err(unexpected_token(tok, "a declaration")),
 			}
+			annotations.clear();
+			exported = None;
+		}
+		if let Some(annotation) = annotations.first() {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::UnexpectedEndOfFile,
+				description: Some("The annotation % isn't attached to any declaration".to_string()),
+				markers: vec![annotation.location.clone()],
+			});
 		}
+		if let Some(location) = exported {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::UnexpectedEndOfFile,
+				description: Some("Nothing follows the `export` at %".to_string()),
+				markers: vec![location],
+			});
+		}
+		Ok(())
 	}
-	let mut ast = VecDeque::from(ast.clone());
+}
+
+pub(super) fn parse_input(ast: &YuriAst) -> Result<YuriModule, YuriSemanticError> {
+	let mut parser = Parser { tokens: ast, seek: 0 };
 	let mut module_state = YuriModule::default();
-	parse_input_recursive(&mut ast, &mut module_state);
+	parser.parse_declarations(&mut module_state, false)?;
 	Ok(module_state)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+	use crate::error::YuriSemanticErrorType;
+	use crate::parse::{ArrayLength, BinaryOperator, Else, Expression, ExpressionKind, IfExpression, Literal, NumberType, Statement, UnaryOperator, YuriType};
+	use crate::YuriShader;
+
+	#[test]
+	fn parse_basic() {
+		let source = include_str!("../basic.yuri");
+		let module = YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap();
+		assert_eq!(module.properties.len(), 2);
+		assert_eq!(module.globals[0].name, "global");
+		let names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+		assert_eq!(names, ["my_vert_main", "my_frag_main"]);
+		assert_eq!(module.functions[0].annotations[0].name, "vert");
+		assert!(module.functions[1].exported);
+		let YuriType::Complex(fields) = &module.functions[0].return_type else { panic!() };
+		assert_eq!(fields[0].annotations, ["vert.pos"]);
+	}
+
+	#[test]
+	fn parse_expressions() {
+		let source = "
+			fn shade(x: f, xs: f[4]): f {
+				let doubled = map v: xs { v * 2.0 };
+				let kept = filter v: doubled { v > 1.0 };
+				let total = fold sum = 0.0, v: kept { sum + v };
+				let c = <| a = g(1, 2), x |>;
+				let v = f4(xs[0], @frag.coord, 1.0);
+				if x > total { loop k: 3 { x }; 1.0 } else if x < 0.5 { 2.0 } else { total }
+			}
+		";
+		let module = YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap();
+		let function = &module.functions[0];
+		assert_eq!(function.arguments[1].argument_type, YuriType::Array(Box::new(YuriType::Scalar(NumberType::Float)), ArrayLength::Fixed(4)));
+		let values: Vec<&ExpressionKind> = function.body.statements.iter()
+			.map(|statement| match statement {
+				Statement::Variable(variable) => &variable.value.kind,
+				_ => panic!("{statement:?}"),
+			})
+			.collect();
+		assert!(matches!(values[0], ExpressionKind::Map { item, .. } if item == "v"));
+		assert!(matches!(values[1], ExpressionKind::Filter { .. }));
+		assert!(matches!(values[2], ExpressionKind::Fold { accumulator, item, .. } if accumulator == "sum" && item == "v"));
+		let ExpressionKind::Complex(fields) = values[3] else { panic!() };
+		assert!(matches!(&fields[0].1.kind, ExpressionKind::FunctionCall { function_name, arguments } if function_name == "g" && arguments.len() == 2));
+		// the shorthand takes the variable with the same name
+		assert_eq!(fields[1].1.kind, ExpressionKind::Variable("x".to_string()));
+		let ExpressionKind::Construct { arguments, .. } = values[4] else { panic!() };
+		assert!(matches!(&arguments[0].kind, ExpressionKind::Index { .. }));
+		assert_eq!(arguments[1].kind, ExpressionKind::Builtin("frag.coord".to_string()));
+		let Some(ExpressionKind::If(tail)) = function.body.tail.as_ref().map(|tail| &tail.kind) else { panic!() };
+		assert!(matches!(&tail.block.statements[0], Statement::Expression(Expression { kind: ExpressionKind::Loop { .. }, .. })));
+		let Some(Else::If(else_if)) = &tail.block_else else { panic!() };
+		assert!(matches!(&else_if.kind, ExpressionKind::If(IfExpression { block_else: Some(Else::Block(_)), .. })));
+	}
+
+	#[test]
+	fn parse_errors() {
+		let error = |source: &str| YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap_err().error_type();
+		assert_eq!(error("fn g( { }"), YuriSemanticErrorType::UnexpectedToken);
+		assert_eq!(error("let x = 1 +"), YuriSemanticErrorType::UnexpectedEndOfFile);
+		assert_eq!(error("fn g(): f { 1.0 2.0 }"), YuriSemanticErrorType::UnexpectedToken);
+		assert_eq!(error("@vert"), YuriSemanticErrorType::UnexpectedEndOfFile);
+		assert_eq!(error("export prop p: f;"), YuriSemanticErrorType::InvalidDeclaration);
+	}
+
+	#[test]
+	fn parse_precedence() {
+		let module = YuriShader::parse(&YuriShader::lex("let x = 1 + 2 * 3 ** 2 == 19 and true;").unwrap()).unwrap();
+		let ExpressionKind::Binary { operator, lhs, .. } = &module.globals[0].value.kind else { panic!() };
+		assert_eq!(*operator, BinaryOperator::And);
+		let ExpressionKind::Binary { operator, lhs, .. } = &lhs.kind else { panic!() };
+		assert_eq!(*operator, BinaryOperator::Equal);
+		let ExpressionKind::Binary { operator, rhs, .. } = &lhs.kind else { panic!() };
+		assert_eq!(*operator, BinaryOperator::Plus);
+		let ExpressionKind::Binary { operator, rhs, .. } = &rhs.kind else { panic!() };
+		assert_eq!(*operator, BinaryOperator::Times);
+		let ExpressionKind::Binary { operator, .. } = &rhs.kind else { panic!() };
+		assert_eq!(*operator, BinaryOperator::Exponent);
+	}
+
+	#[test]
+	fn parse_minus() {
+		let module = YuriShader::parse(&YuriShader::lex("let a = x-1; let b = -x; let c = -1 - -x;").unwrap()).unwrap();
+		assert!(matches!(&module.globals[0].value.kind, ExpressionKind::Binary { operator: BinaryOperator::Minus, .. }));
+		assert!(matches!(&module.globals[1].value.kind, ExpressionKind::Unary { operator: UnaryOperator::Negate, .. }));
+		let ExpressionKind::Binary { operator, lhs, rhs } = &module.globals[2].value.kind else { panic!() };
+		assert_eq!(*operator, BinaryOperator::Minus);
+		assert!(matches!(&lhs.kind, ExpressionKind::Unary { operator: UnaryOperator::Negate, .. }));
+		assert!(matches!(&rhs.kind, ExpressionKind::Unary { operator: UnaryOperator::Negate, .. }));
+	}
+
+	#[test]
+	fn parse_annotation_arguments() {
+		let source = "@spec(3) @frag(origin = lower_left, 2) @plain let x: u = 8;";
+		let module = YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap();
+		let annotations = &module.globals[0].annotations;
+		assert_eq!(annotations.len(), 3);
+		assert_eq!(annotations[0].name, "spec");
+		assert_eq!(annotations[0].arguments[0].name, None);
+		assert_eq!(annotations[0].arguments[0].value.kind, ExpressionKind::Literal(Literal::DecimalNumber(3, None)));
+		assert_eq!(annotations[1].arguments[0].name.as_deref(), Some("origin"));
+		assert_eq!(annotations[1].arguments[0].value.kind, ExpressionKind::Variable("lower_left".to_string()));
+		assert_eq!(annotations[1].arguments[1].name, None);
+		assert!(annotations[2].arguments.is_empty());
+		assert!(YuriShader::parse(&YuriShader::lex("@spec(3 let x: u = 8;").unwrap()).is_err());
+	}
+
+	#[test]
+	fn write_back_out() {
+		let parse = |source: &str| YuriShader::parse(&YuriShader::lex(source).unwrap()).unwrap();
+		let module = parse(include_str!("../basic.yuri"));
+		let written = module.to_string();
+		assert_eq!(parse(&written).to_string(), written);
+		// operations inside operations get parentheses, whatever the precedence was
+		let module = parse("module m { @spec(0) export let x = -1 - -y * 2 ** -z; fn g(): f4[2] { loop k: 2 { f4(1.5f) } } }");
+		assert_eq!(
+			module.to_string(),
+			"module m {\n@spec(0) export let x = (-1) - ((-y) * (2 ** (-z)));\nfn g(): f4[2] { loop k: 2 { f4(1.5f) } }\n}\n",
+		);
+	}
+}
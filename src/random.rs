@@ -0,0 +1,76 @@
+//! Seeded random numbers and values, so a test that goes wrong goes wrong the same way every time.
+use crate::consteval::ConstValue;
+use crate::parse::{ArrayLength, NumberType, YuriType};
+
+/// A tiny random number generator (SplitMix64), so a seed is all it takes to get the same numbers back,
+/// without the compiler needing `rand`.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		Self(seed)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	/// A number from `0` up to (but not including) `n`.
+	pub fn below(&mut self, n: usize) -> usize {
+		(self.next_u64() % n as u64) as usize
+	}
+
+	/// A number from `low` to `high`, both included.
+	pub fn between(&mut self, low: i64, high: i64) -> i64 {
+		low + (self.next_u64() % (high - low + 1) as u64) as i64
+	}
+
+	/// A float from 0 up to 1.
+	pub fn unit(&mut self) -> f32 {
+		(self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+	}
+
+	/// True one time out of `n`, on average.
+	pub fn one_in(&mut self, n: usize) -> bool {
+		self.below(n) == 0
+	}
+
+	pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+		&items[self.below(items.len())]
+	}
+}
+
+/// A random value of the type, for calling generated functions with.
+/// It's mostly small numbers (which don't run into undefined behavior as often), with the odd extreme one thrown in.
+/// Panics on samplers and arrays with named lengths, there's no way to make those up.
+pub fn random_value(rng: &mut Rng, ty: &YuriType) -> ConstValue {
+	let extreme = rng.one_in(20);
+	match ty {
+		YuriType::Unit => ConstValue::UNIT,
+		YuriType::Bool => ConstValue::Bool(rng.one_in(2)),
+		YuriType::Scalar(NumberType::Float) if extreme => ConstValue::Float((rng.unit() * 2.0 - 1.0) * 1e30),
+		YuriType::Scalar(NumberType::Float) => ConstValue::Float(rng.unit() * 20.0 - 10.0),
+		YuriType::Scalar(NumberType::Signed) if extreme => ConstValue::Signed(rng.next_u64() as i32),
+		YuriType::Scalar(NumberType::Signed) => ConstValue::Signed(rng.between(-100, 100) as i32),
+		YuriType::Scalar(NumberType::Unsigned) if extreme => ConstValue::Unsigned(rng.next_u64() as u32),
+		YuriType::Scalar(NumberType::Unsigned) => ConstValue::Unsigned(rng.between(0, 100) as u32),
+		YuriType::Vector(number_type, size) => ConstValue::Composite(
+			(0..size.count()).map(|_| random_value(rng, &YuriType::Scalar(*number_type))).collect()
+		),
+		YuriType::Matrix(size) => ConstValue::Composite(
+			(0..size.count()).map(|_| random_value(rng, &YuriType::Vector(NumberType::Float, *size))).collect()
+		),
+		YuriType::Array(inner, ArrayLength::Fixed(length)) => ConstValue::Composite(
+			(0..*length).map(|_| random_value(rng, inner)).collect()
+		),
+		YuriType::Complex(fields) => ConstValue::Composite(
+			fields.iter().map(|field| random_value(rng, &field.field_type)).collect()
+		),
+		YuriType::Array(_, ArrayLength::Named(_)) | YuriType::Sampler(_) => panic!("there are no random values of `{ty}`"),
+	}
+}
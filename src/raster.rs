@@ -0,0 +1,544 @@
+//! A reference pipeline that draws with a `@vert` and `@frag` pair on the CPU, through the [Interpreter].
+//! It's slow, but it doesn't need a GPU, so it's how we check that shaders actually render what they should.
+//!
+//! It works like Vulkan with SDL's defaults: clip space y points down, depth goes from 0 to 1,
+//! pixel centers are at half-integers, counter-clockwise triangles face the front, and nothing gets culled or blended.
+//! Inputs and outputs are matched by location, as [TypedFunction::interface] lays them out for the SPIR-V backend:
+//! the vertex outputs feed the fragment inputs at the same locations, floats get interpolated
+//! (with perspective correction) and integers come from the first vertex of the triangle, since they're `Flat`.
+//! The color at location 0 goes into an RGBA image, which can be compared against a golden image with [Image::check_golden].
+use std::fs;
+use std::path::Path;
+use crate::builtin::{BuiltinInput, FragOrigin};
+use crate::check::{EntryInterface, FunctionId, InterfaceSlot, ShaderStage, TypedFunction, TypedProgram};
+use crate::consteval::ConstValue;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::interpret::Interpreter;
+use crate::parse::{NumberType, YuriType};
+
+/// An 8-bit RGBA image, with rows going from top to bottom.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Image {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+	pub fn new(width: u32, height: u32, color: [u8; 4]) -> Self {
+		Self { width, height, pixels: vec![color; (width * height) as usize] }
+	}
+
+	pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+		self.pixels[(y * self.width + x) as usize]
+	}
+
+	/// Writes the image as a PAM, which is about the simplest format that has alpha.
+	pub fn to_pam(&self) -> Vec<u8> {
+		let mut out = format!(
+			"P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+			self.width,
+			self.height,
+		).into_bytes();
+		out.extend(self.pixels.iter().flatten());
+		out
+	}
+
+	/// Reads back what [Image::to_pam] writes. Anything else (like grayscale or 16-bit PAMs) isn't supported.
+	pub fn from_pam(bytes: &[u8]) -> Option<Self> {
+		const END: &[u8] = b"ENDHDR\n";
+		let header_end = bytes.windows(END.len()).position(|w| w == END)? + END.len();
+		let header = std::str::from_utf8(&bytes[..header_end]).ok()?;
+		let mut lines = header.lines();
+		if lines.next()? != "P7" {
+			return None;
+		}
+		let (mut width, mut height) = (None, None);
+		for line in lines {
+			match line.split_once(' ') {
+				Some(("WIDTH", value)) => width = value.parse().ok(),
+				Some(("HEIGHT", value)) => height = value.parse().ok(),
+				Some(("DEPTH", value)) if value != "4" => return None,
+				Some(("MAXVAL", value)) if value != "255" => return None,
+				_ => {}
+			}
+		}
+		let (width, height): (u32, u32) = (width?, height?);
+		let data = &bytes[header_end..];
+		if data.len() != (width * height * 4) as usize {
+			return None;
+		}
+		let pixels = data.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]).collect();
+		Some(Self { width, height, pixels })
+	}
+
+	/// Checks that every channel of every pixel is within the tolerance of the other image,
+	/// explaining where the worst difference is if they aren't.
+	pub fn compare(&self, expected: &Image, tolerance: u8) -> Result<(), String> {
+		if (self.width, self.height) != (expected.width, expected.height) {
+			return Err(format!(
+				"The image is {}x{}, but it should be {}x{}",
+				self.width, self.height, expected.width, expected.height,
+			));
+		}
+		let difference = |a: &[u8; 4], b: &[u8; 4]| a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+		let differing: Vec<usize> = (0..self.pixels.len())
+			.filter(|i| difference(&self.pixels[*i], &expected.pixels[*i]) > tolerance)
+			.collect();
+		let Some(worst) = differing.iter().copied().max_by_key(|i| difference(&self.pixels[*i], &expected.pixels[*i])) else {
+			return Ok(());
+		};
+		Err(format!(
+			"{} pixel(s) are off by more than {tolerance}, the worst being ({}, {}), which is {:?} instead of {:?}",
+			differing.len(),
+			worst as u32 % self.width,
+			worst as u32 / self.width,
+			self.pixels[worst],
+			expected.pixels[worst],
+		))
+	}
+
+	/// Compares the image against a golden image saved as a PAM.
+	/// If `YURI_UPDATE_GOLDEN` is set, the golden image is overwritten with this one instead.
+	pub fn check_golden(&self, path: impl AsRef<Path>, tolerance: u8) -> Result<(), String> {
+		let path = path.as_ref();
+		let update = "run with YURI_UPDATE_GOLDEN=1 to update it";
+		if std::env::var_os("YURI_UPDATE_GOLDEN").is_some() {
+			return fs::write(path, self.to_pam()).map_err(|err| format!("Failed to write {}: {err}", path.display()));
+		}
+		let bytes = fs::read(path).map_err(|err| format!("Failed to read {} ({err}), {update}", path.display()))?;
+		let golden = Image::from_pam(&bytes).ok_or_else(|| format!("{} isn't an RGBA PAM, {update}", path.display()))?;
+		self.compare(&golden, tolerance)
+			.map_err(|difference| format!("{difference} (compared to {}, {update})", path.display()))
+	}
+}
+
+/// A vertex after the vertex shader, in clip space.
+#[derive(Debug, Clone)]
+struct Vertex {
+	position: [f32; 4],
+	/// The outputs that go to the fragment shader, in the order of its inputs.
+	varyings: Vec<ConstValue>,
+}
+
+/// The vertex in window coordinates, with what's needed to interpolate across the triangle.
+#[derive(Debug, Copy, Clone)]
+struct WindowVertex {
+	x: f32,
+	y: f32,
+	z: f32,
+	inverse_w: f32,
+}
+
+fn error(error_type: YuriSemanticErrorType, description: String, function: &TypedFunction) -> YuriSemanticError {
+	YuriSemanticError { error_type, description: Some(description), markers: vec![function.location.clone()] }
+}
+
+fn is_float(ty: &YuriType) -> bool {
+	matches!(ty, YuriType::Scalar(NumberType::Float) | YuriType::Vector(NumberType::Float, _))
+}
+
+/// Adds up the values with the given weights, component by component.
+fn weighted(values: &[&ConstValue], weights: &[f32]) -> ConstValue {
+	match values[0] {
+		ConstValue::Composite(components) => ConstValue::Composite(
+			(0..components.len())
+				.map(|i| {
+					let parts: Vec<&ConstValue> = values.iter().map(|v| &v.components()[i]).collect();
+					weighted(&parts, weights)
+				})
+				.collect()
+		),
+		ConstValue::Float(_) => ConstValue::Float(
+			values.iter().zip(weights).map(|(v, w)| v.as_f32().unwrap_or(0.0) * w).sum()
+		),
+		other => other.clone(),
+	}
+}
+
+fn to_unorm(value: f32) -> u8 {
+	// NaN ends up as 0, same as the GPU
+	(value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// The clip space planes, as the distance of a position from each one (negative is outside).
+const CLIP_PLANES: [fn(&[f32; 4]) -> f32; 6] = [
+	|p| p[3] + p[0],
+	|p| p[3] - p[0],
+	|p| p[3] + p[1],
+	|p| p[3] - p[1],
+	|p| p[2],
+	|p| p[3] - p[2],
+];
+
+/// Cuts a polygon down to the part on the inside of a plane (Sutherland-Hodgman, one plane at a time).
+fn clip(polygon: Vec<Vertex>, plane: fn(&[f32; 4]) -> f32, varying_types: &[YuriType]) -> Vec<Vertex> {
+	let mut clipped = Vec::new();
+	for (i, a) in polygon.iter().enumerate() {
+		let b = &polygon[(i + 1) % polygon.len()];
+		let (da, db) = (plane(&a.position), plane(&b.position));
+		if da >= 0.0 {
+			clipped.push(a.clone());
+		}
+		if (da >= 0.0) != (db >= 0.0) {
+			let t = da / (da - db);
+			let weights = [1.0 - t, t];
+			let position = std::array::from_fn(|c| a.position[c] * weights[0] + b.position[c] * weights[1]);
+			// flat values get taken from the triangle's first vertex later, whatever happens here
+			let varyings = a.varyings.iter()
+				.zip(&b.varyings)
+				.zip(varying_types)
+				.map(|((a, b), ty)| if is_float(ty) { weighted(&[a, b], &weights) } else { a.clone() })
+				.collect();
+			clipped.push(Vertex { position, varyings });
+		}
+	}
+	clipped
+}
+
+/// Draws triangles with a vertex and fragment shader into an [Image].
+pub struct Pipeline<'a> {
+	interpreter: Interpreter<'a>,
+	vertex: FunctionId,
+	fragment: FunctionId,
+	vertex_interface: EntryInterface,
+	fragment_interface: EntryInterface,
+	/// Which vertex output goes to each fragment input.
+	links: Vec<usize>,
+	/// The origin the fragment shader picked, if it did.
+	frag_origin: Option<FragOrigin>,
+	default_frag_origin: FragOrigin,
+	depth_test: bool,
+	depth: Vec<f32>,
+	image: Image,
+}
+
+impl<'a> Pipeline<'a> {
+	/// Sets up the entry points (by name) to draw into a transparent black image.
+	/// Fails if they aren't a vertex and fragment shader whose inputs and outputs match up.
+	pub fn new(program: &'a TypedProgram, vertex: &str, fragment: &str, width: u32, height: u32) -> Result<Self, YuriSemanticError> {
+		let entry_point = |name: &str, stage: ShaderStage| {
+			let found = program.functions.iter().position(|f| f.name == name);
+			match found {
+				Some(id) if program.functions[id].stage == Some(stage) => Ok(id),
+				_ => Err(YuriSemanticError {
+					error_type: YuriSemanticErrorType::InvalidEntryPoint,
+					description: Some(format!("There's no {stage:?} entry point named `{name}`")),
+					markers: vec![],
+				}),
+			}
+		};
+		let vertex = entry_point(vertex, ShaderStage::Vertex)?;
+		let fragment = entry_point(fragment, ShaderStage::Fragment)?;
+		let vertex_function = &program.functions[vertex];
+		let fragment_function = &program.functions[fragment];
+		let vertex_interface = vertex_function.interface().expect("entry points have an interface");
+		let fragment_interface = fragment_function.interface().expect("entry points have an interface");
+
+		// Vulkan wants every fragment input to be written by the vertex shader, with the same type
+		let mut links = Vec::new();
+		for input in &fragment_interface.inputs {
+			let output = vertex_interface.outputs.iter().position(|output| output.slot == input.slot);
+			match output {
+				Some(output) if vertex_interface.outputs[output].variable_type == input.variable_type => links.push(output),
+				_ => return Err(error(
+					YuriSemanticErrorType::InvalidEntryPoint,
+					format!(
+						"The input `{}` of `{}` (%) doesn't have a matching `{}` output in `{}` at {:?}",
+						input.name, fragment_function.name, input.variable_type, vertex_function.name, input.slot,
+					),
+					fragment_function,
+				)),
+			}
+		}
+		let color = fragment_interface.outputs.iter().find(|output| output.slot == InterfaceSlot::Location(0));
+		if !color.is_some_and(|color| is_float(&color.variable_type)) {
+			return Err(error(
+				YuriSemanticErrorType::InvalidEntryPoint,
+				format!("`{}` (%) has to give back a float color at location 0, to go into an RGBA image", fragment_function.name),
+				fragment_function,
+			));
+		}
+
+		Ok(Self {
+			interpreter: Interpreter::new(program),
+			vertex,
+			fragment,
+			vertex_interface,
+			fragment_interface,
+			links,
+			frag_origin: fragment_function.frag_origin,
+			default_frag_origin: FragOrigin::default(),
+			depth_test: false,
+			depth: vec![1.0; (width * height) as usize],
+			image: Image::new(width, height, [0, 0, 0, 0]),
+		})
+	}
+
+	/// Keeps only the nearest fragments, by comparing depth with less-than. Off by default.
+	pub fn with_depth_test(mut self, depth_test: bool) -> Self {
+		self.depth_test = depth_test;
+		self
+	}
+
+	/// Where `@frag.coord` has its origin, if the fragment shader doesn't pick one itself. The default is the upper left.
+	pub fn with_frag_origin(mut self, frag_origin: FragOrigin) -> Self {
+		self.default_frag_origin = frag_origin;
+		self
+	}
+
+	/// For giving props and specialization constants values.
+	pub fn interpreter(&mut self) -> &mut Interpreter<'a> {
+		&mut self.interpreter
+	}
+
+	pub fn image(&self) -> &Image {
+		&self.image
+	}
+
+	/// Fills the image with a color, and resets the depth.
+	pub fn clear(&mut self, color: [u8; 4]) {
+		self.image.pixels.fill(color);
+		self.depth.fill(1.0);
+	}
+
+	/// Draws a list of triangles, where each vertex is the arguments to the vertex shader.
+	/// Leftover vertices that don't make up a whole triangle are ignored, like they are on the GPU.
+	pub fn draw(&mut self, vertices: &[Vec<ConstValue>]) -> Result<(), YuriSemanticError> {
+		self.interpreter.set_input(BuiltinInput::InstanceIndex, 0u32)?;
+		let mut shaded = Vec::with_capacity(vertices.len());
+		for (index, arguments) in vertices.iter().enumerate() {
+			shaded.push(self.shade_vertex(index as u32, arguments)?);
+		}
+		let varying_types: Vec<YuriType> = self.fragment_interface.inputs.iter().map(|i| i.variable_type.clone()).collect();
+		for triangle in shaded.chunks_exact(3) {
+			let mut polygon = triangle.to_vec();
+			for plane in CLIP_PLANES {
+				if polygon.is_empty() {
+					break;
+				}
+				polygon = clip(polygon, plane, &varying_types);
+			}
+			for i in 1..polygon.len().saturating_sub(1) {
+				self.rasterize([&polygon[0], &polygon[i], &polygon[i + 1]], &triangle[0], &varying_types)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn shade_vertex(&mut self, index: u32, arguments: &[ConstValue]) -> Result<Vertex, YuriSemanticError> {
+		self.interpreter.set_input(BuiltinInput::VertexIndex, index)?;
+		let result = self.interpreter.call_id(self.vertex, arguments)?;
+		let outputs: Vec<ConstValue> = match self.vertex_interface.complex_output {
+			true => result.components().to_vec(),
+			false => vec![result],
+		};
+		let position = self.vertex_interface.outputs.iter()
+			.position(|output| output.slot == InterfaceSlot::Position)
+			.map(|i| outputs[i].components().iter().map(|c| c.as_f32().unwrap_or(0.0)).collect::<Vec<_>>())
+			.expect("vertex shaders always have a position");
+		Ok(Vertex {
+			position: [position[0], position[1], position[2], position[3]],
+			varyings: self.links.iter().map(|i| outputs[*i].clone()).collect(),
+		})
+	}
+
+	fn rasterize(&mut self, triangle: [&Vertex; 3], provoking: &Vertex, varying_types: &[YuriType]) -> Result<(), YuriSemanticError> {
+		let (width, height) = (self.image.width, self.image.height);
+		let window = triangle.map(|v| {
+			let inverse_w = 1.0 / v.position[3];
+			WindowVertex {
+				x: (v.position[0] * inverse_w + 1.0) * 0.5 * width as f32,
+				y: (v.position[1] * inverse_w + 1.0) * 0.5 * height as f32,
+				z: v.position[2] * inverse_w,
+				inverse_w,
+			}
+		});
+		let orient = |a: &WindowVertex, b: &WindowVertex, x: f32, y: f32| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+		let area = orient(&window[0], &window[1], window[2].x, window[2].y);
+		if area == 0.0 || !area.is_finite() {
+			return Ok(());
+		}
+		// with y pointing down, a negative area is counter-clockwise on screen
+		let front_facing = area < 0.0;
+		// flip the triangle around so the edge functions are positive inside it
+		let order = if area > 0.0 { [0, 1, 2] } else { [0, 2, 1] };
+		let [a, b, c] = order.map(|i| window[i]);
+		let area = area.abs();
+		// the top-left rule, so pixels on an edge shared by two triangles only get drawn once
+		let top_left = |from: &WindowVertex, to: &WindowVertex| (from.y == to.y && to.x > from.x) || to.y < from.y;
+		let edges = [(b, c), (c, a), (a, b)];
+
+		let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+		let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+		let max_x = (a.x.max(b.x).max(c.x).ceil() as u32).min(width);
+		let max_y = (a.y.max(b.y).max(c.y).ceil() as u32).min(height);
+		self.interpreter.set_input(BuiltinInput::FrontFacing, front_facing)?;
+		for y in min_y..max_y {
+			for x in min_x..max_x {
+				let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+				let weights = edges.map(|(from, to)| orient(&from, &to, px, py));
+				let inside = weights.iter()
+					.zip(&edges)
+					.all(|(w, (from, to))| *w > 0.0 || (*w == 0.0 && top_left(from, to)));
+				if !inside {
+					continue;
+				}
+				// back to the original order, since that's the order of the varyings
+				let mut linear = [0.0; 3];
+				for (i, w) in order.iter().zip(weights) {
+					linear[*i] = w / area;
+				}
+				let z: f32 = (0..3).map(|i| linear[i] * window[i].z).sum();
+				let pixel = (y * width + x) as usize;
+				if self.depth_test && z >= self.depth[pixel] {
+					continue;
+				}
+				let perspective: [f32; 3] = std::array::from_fn(|i| linear[i] * window[i].inverse_w);
+				let total: f32 = perspective.iter().sum();
+				let perspective = perspective.map(|p| p / total);
+
+				let inputs: Vec<ConstValue> = varying_types.iter()
+					.enumerate()
+					.map(|(i, ty)| match is_float(ty) {
+						true => weighted(&triangle.map(|v| &v.varyings[i]), &perspective),
+						false => provoking.varyings[i].clone(),
+					})
+					.collect();
+				let coord_y = match self.frag_origin.unwrap_or(self.default_frag_origin) {
+					FragOrigin::UpperLeft => py,
+					FragOrigin::LowerLeft => height as f32 - py,
+				};
+				self.interpreter.set_input(BuiltinInput::FragCoord, [px, coord_y])?;
+				let result = self.interpreter.call_id(self.fragment, &inputs)?;
+				let color = match self.fragment_interface.complex_output {
+					true => {
+						let i = self.fragment_interface.outputs.iter()
+							.position(|output| output.slot == InterfaceSlot::Location(0))
+							.expect("checked when the pipeline was made");
+						result.components()[i].clone()
+					}
+					false => result,
+				};
+				// anything the shader leaves out is zero, except alpha, which is one
+				let mut rgba = [0.0, 0.0, 0.0, 1.0];
+				for (channel, value) in rgba.iter_mut().zip(color.components()) {
+					*channel = value.as_f32().unwrap_or(0.0);
+				}
+				self.image.pixels[pixel] = rgba.map(to_unorm);
+				self.depth[pixel] = z;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::fs;
+	use std::path::Path;
+	use crate::builtin::FragOrigin;
+	use crate::check::check_source;
+	use crate::consteval::ConstValue;
+	use crate::error::YuriSemanticErrorType;
+	use crate::raster::{Image, Pipeline};
+
+	fn vertex(pos: [f32; 3], coord: [f32; 2]) -> Vec<ConstValue> {
+		vec![pos.into(), coord.into()]
+	}
+
+	#[test]
+	fn render_basic() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR"));
+		let program = check_source(&fs::read_to_string(path.join("basic.yuri")).unwrap()).unwrap();
+		let mut pipeline = Pipeline::new(&program, "my_vert_main", "my_frag_main", 16, 16).unwrap();
+		let identity = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+		pipeline.interpreter().set_prop("transform", identity).unwrap();
+		// a quad over the middle of the screen, and a triangle that's mostly off of it
+		pipeline.draw(&[
+			vertex([-0.5, -0.5, 0.5], [0.0, 0.0]),
+			vertex([0.5, -0.5, 0.5], [1.0, 0.0]),
+			vertex([0.5, 0.5, 0.5], [1.0, 1.0]),
+			vertex([-0.5, -0.5, 0.5], [0.0, 0.0]),
+			vertex([0.5, 0.5, 0.5], [1.0, 1.0]),
+			vertex([-0.5, 0.5, 0.5], [0.0, 1.0]),
+			vertex([0.75, 0.75, 0.5], [0.0, 1.0]),
+			vertex([3.0, 0.75, 0.5], [0.0, 1.0]),
+			vertex([0.75, 3.0, 0.5], [0.0, 1.0]),
+		]).unwrap();
+		let image = pipeline.image();
+		assert_eq!(image.pixel(0, 0), [0, 0, 0, 0]);
+		// y points down, so the top left of the quad is where `coord` is zero
+		assert_eq!(image.pixel(4, 4), [16, 16, 0, 255]);
+		assert_eq!(image.pixel(11, 11), [239, 239, 0, 255]);
+		assert_eq!(image.pixel(15, 15), [0, 255, 0, 255]);
+		assert_eq!(Image::from_pam(&image.to_pam()).as_ref(), Some(image));
+		image.check_golden(path.join("tests/golden/basic.pam"), 1).unwrap();
+	}
+
+	#[test]
+	fn interpolation() {
+		let program = check_source("
+			@vert fn vert(pos: f4, shade: f, id: u): <| @vert.pos pos: f4, shade: f, id: u |> {
+				<| pos, shade, id |>
+			}
+			@frag fn frag(shade: f, id: u): f4 {
+				f4(shade, f(id) / 255.0, if @frag.front_facing { 1.0 } else { 0.0 }, 1.0)
+			}
+			@frag fn wrong(shade: f, id: f): f4 { f4(shade) }
+		").unwrap();
+		let mut pipeline = Pipeline::new(&program, "vert", "frag", 4, 4).unwrap().with_depth_test(true);
+		let vertex = |pos: [f32; 4], shade: f32, id: u32| vec![pos.into(), shade.into(), id.into()];
+		pipeline.draw(&[
+			// one triangle behind the other, with the far one drawn last, and `w` for perspective
+			vertex([-1.0, -1.0, 0.2, 1.0], 0.0, 10),
+			vertex([-3.0, 3.0, 0.6, 3.0], 0.0, 20),
+			vertex([3.0, -3.0, 0.6, 3.0], 1.0, 30),
+			vertex([-1.0, -1.0, 0.9, 1.0], 1.0, 40),
+			vertex([1.0, -1.0, 0.9, 1.0], 1.0, 50),
+			vertex([-1.0, 1.0, 0.9, 1.0], 1.0, 60),
+		]).unwrap();
+		let image = pipeline.image();
+		// integers are flat, from the first vertex
+		assert_eq!(image.pixel(0, 0)[1], 10);
+		// counter-clockwise on screen, so it faces the front
+		assert_eq!(image.pixel(0, 0)[2], 255);
+		// `shade` goes from 0 to 1 across the triangle, but not linearly on screen because of `w`
+		let shades: Vec<u8> = (0..3).map(|x| image.pixel(x, 0)[0]).collect();
+		assert!(shades[0] < shades[1] && shades[1] < shades[2], "{shades:?}");
+		assert_ne!(shades[1], 255 / 2);
+		// nothing behind the diagonal got drawn
+		assert_eq!(image.pixel(3, 3), [0, 0, 0, 0]);
+
+		let err = Pipeline::new(&program, "vert", "wrong", 4, 4).err().unwrap();
+		assert_eq!(err.error_type(), YuriSemanticErrorType::InvalidEntryPoint);
+		assert!(Pipeline::new(&program, "frag", "vert", 4, 4).is_err());
+	}
+
+	#[test]
+	fn frag_origins() {
+		let program = check_source("
+			@vert fn vert(pos: f4): <| @vert.pos pos: f4 |> { <| pos |> }
+			@frag fn plain(): f4 { let coord = @frag.coord; f4(coord.y / 4.0, 0.0, 0.0, 1.0) }
+			@frag(origin = lower_left) fn flipped(): f4 { let coord = @frag.coord; f4(coord.y / 4.0, 0.0, 0.0, 1.0) }
+		").unwrap();
+		// how far down the top left pixel is, as a fraction of the height
+		let top_left = |fragment: &str, frag_origin: Option<FragOrigin>| {
+			let mut pipeline = Pipeline::new(&program, "vert", fragment, 4, 4).unwrap();
+			if let Some(frag_origin) = frag_origin {
+				pipeline = pipeline.with_frag_origin(frag_origin);
+			}
+			let corner = |x: f32, y: f32| vec![ConstValue::from([x, y, 0.5, 1.0])];
+			pipeline.draw(&[
+				corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0),
+				corner(-1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0),
+			]).unwrap();
+			pipeline.image().pixel(0, 0)[0] as f32 / 255.0
+		};
+		assert!(top_left("plain", None) < 0.5);
+		assert!(top_left("plain", Some(FragOrigin::LowerLeft)) > 0.5);
+		// the pipeline's origin is only a default, the shader's own wins
+		assert!(top_left("flipped", None) > 0.5);
+		assert!(top_left("flipped", Some(FragOrigin::UpperLeft)) > 0.5);
+	}
+}
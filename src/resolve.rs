@@ -0,0 +1,594 @@
+//! Name resolution. This builds a scope for every module (nested or imported),
+//! then walks every function and global to figure out what each name refers to.
+//!
+//! Names can be dotted paths like `lighting.pbr.brdf`, since the lexer folds `.` into identifiers.
+//! Whatever's left of the path after finding a declaration (like the `xy` in `pos.xy`)
+//! is kept around as a member path for the type checker to deal with.
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::builtin::BuiltinFunction;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::import::ResolvedImports;
+use crate::parse::{ArrayLength, Block, Else, Expression, ExpressionKind, Statement, YuriModule, YuriType};
+
+pub type ModuleId = usize;
+pub type SymbolId = usize;
+
+/// The module that the root source file becomes.
+pub const ROOT_MODULE: ModuleId = 0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SymbolKind {
+	Property,
+	Global,
+	Function,
+	Module(ModuleId),
+}
+
+/// Something declared at the module level.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+	pub name: String,
+	/// The full dotted path to the symbol, like `lighting.pbr.brdf`.
+	pub qualified_name: String,
+	pub kind: SymbolKind,
+	pub exported: bool,
+	/// The module the symbol was declared in.
+	pub parent: ModuleId,
+	/// The index of the declaration in its module's list of props/globals/functions/submodules.
+	pub index: usize,
+	pub location: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModuleScope {
+	/// The dotted path of the module, empty for the root module.
+	pub path: String,
+	pub parent: Option<ModuleId>,
+	/// Which file the module came from.
+	/// `None` is the root source, anything else is an index into [ResolvedImports::modules].
+	pub file: Option<usize>,
+	/// Declarations (and submodules) by name.
+	pub members: HashMap<String, SymbolId>,
+	/// Imported modules by the dotted name they were imported with.
+	pub imports: HashMap<String, ModuleId>,
+	/// The indices of the submodules to follow (from the file's top-level module) to find this module.
+	submodule_path: Vec<usize>,
+}
+
+/// What a name refers to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ResolvedName {
+	/// A function argument, `let` in a block, or a loop/fold/map binding.
+	/// Bindings are identified by their name and the location of whatever declared them.
+	Local { name: String, declared_at: Range<usize> },
+	Symbol(SymbolId),
+	Builtin(BuiltinFunction),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Reference {
+	pub target: ResolvedName,
+	/// Any of the path left over after the name was found, like field names or swizzles.
+	pub members: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct NameResolution {
+	pub modules: Vec<ModuleScope>,
+	pub symbols: Vec<Symbol>,
+	/// Everything that was referred to by name, keyed by the module and location of the reference.
+	references: HashMap<(ModuleId, Range<usize>), Reference>,
+}
+
+impl NameResolution {
+	/// Gets what the name used at the given location refers to.
+	pub fn reference(&self, module: ModuleId, location: &Range<usize>) -> Option<&Reference> {
+		self.references.get(&(module, location.clone()))
+	}
+
+	pub fn references(&self) -> impl Iterator<Item = (ModuleId, &Range<usize>, &Reference)> {
+		self.references.iter().map(|((module, location), reference)| (*module, location, reference))
+	}
+
+	/// Finds a symbol by its fully-qualified name.
+	pub fn symbol_by_name(&self, qualified_name: &str) -> Option<SymbolId> {
+		self.symbols.iter().position(|s| s.qualified_name == qualified_name)
+	}
+
+	/// Gets the syntax tree of a module.
+	pub fn module_ast<'a>(&self, module: ModuleId, root: &'a YuriModule, imports: &'a ResolvedImports) -> &'a YuriModule {
+		let scope = &self.modules[module];
+		let mut ast = match scope.file {
+			None => root,
+			Some(file) => &imports.modules[file].module,
+		};
+		for index in &scope.submodule_path {
+			ast = &ast.submodules[*index].module;
+		}
+		ast
+	}
+
+	/// Whether `from` is `module` or nested somewhere inside it.
+	pub fn is_within(&self, from: ModuleId, module: ModuleId) -> bool {
+		let mut scope = Some(from);
+		while let Some(id) = scope {
+			if id == module {
+				return true;
+			}
+			scope = self.modules[id].parent;
+		}
+		false
+	}
+
+	fn register_module(
+		&mut self,
+		ast: &YuriModule,
+		path: String,
+		parent: Option<ModuleId>,
+		file: Option<usize>,
+		submodule_path: Vec<usize>,
+	) -> Result<ModuleId, YuriSemanticError> {
+		let id = self.modules.len();
+		self.modules.push(ModuleScope {
+			path,
+			parent,
+			file,
+			members: HashMap::new(),
+			imports: HashMap::new(),
+			submodule_path: submodule_path.clone(),
+		});
+
+		let declarations = ast.properties.iter().enumerate()
+			.map(|(i, p)| (p.name.clone(), SymbolKind::Property, false, i, p.location.clone()))
+			.chain(ast.globals.iter().enumerate()
+				.map(|(i, g)| (g.name.clone(), SymbolKind::Global, g.exported, i, g.location.clone())))
+			.chain(ast.functions.iter().enumerate()
+				.map(|(i, f)| (f.name.clone(), SymbolKind::Function, f.exported, i, f.location.clone())))
+			.collect::<Vec<_>>();
+		for (name, kind, exported, index, location) in declarations {
+			self.declare(id, name, kind, exported, index, location)?;
+		}
+
+		for (index, submodule) in ast.submodules.iter().enumerate() {
+			let mut nested_path = submodule_path.clone();
+			nested_path.push(index);
+			let qualified = self.qualify(id, &submodule.name);
+			let child = self.register_module(&submodule.module, qualified, Some(id), file, nested_path)?;
+			self.declare(
+				id,
+				submodule.name.clone(),
+				SymbolKind::Module(child),
+				submodule.exported,
+				index,
+				submodule.location.clone()
+			)?;
+		}
+		Ok(id)
+	}
+
+	fn qualify(&self, module: ModuleId, name: &str) -> String {
+		let path = &self.modules[module].path;
+		if path.is_empty() {
+			name.to_string()
+		} else {
+			format!("{path}.{name}")
+		}
+	}
+
+	fn declare(
+		&mut self,
+		module: ModuleId,
+		name: String,
+		kind: SymbolKind,
+		exported: bool,
+		index: usize,
+		location: Range<usize>,
+	) -> Result<(), YuriSemanticError> {
+		if let Some(existing) = self.modules[module].members.get(&name) {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::DuplicateDeclaration,
+				description: Some(format!("`{name}` is declared more than once in the same module (at % and %)")),
+				markers: vec![self.symbols[*existing].location.clone(), location],
+			});
+		}
+		let id = self.symbols.len();
+		self.symbols.push(Symbol {
+			qualified_name: self.qualify(module, &name),
+			name: name.clone(),
+			kind,
+			exported,
+			parent: module,
+			index,
+			location,
+		});
+		self.modules[module].members.insert(name, id);
+		Ok(())
+	}
+
+	/// Looks up a (possibly dotted) name at the module level, starting from the given module
+	/// and working outwards to the root of the file.
+	pub fn lookup(&self, from: ModuleId, path: &str, location: &Range<usize>) -> Result<Reference, YuriSemanticError> {
+		let segments: Vec<&str> = path.split('.').collect();
+		let mut scope = Some(from);
+		while let Some(id) = scope {
+			let module = &self.modules[id];
+			if let Some(symbol) = module.members.get(segments[0]) {
+				return self.lookup_member(from, *symbol, &segments[1..], path, location);
+			}
+			// imports can have dots in them, so try to match the longest one we can.
+			for split in (1..=segments.len()).rev() {
+				if let Some(imported) = module.imports.get(&segments[..split].join(".")) {
+					return self.lookup_in(from, *imported, &segments[split..], path, location);
+				}
+			}
+			scope = module.parent;
+		}
+		if segments.len() == 1 && let Some(builtin) = BuiltinFunction::from_name(path) {
+			return Ok(Reference { target: ResolvedName::Builtin(builtin), members: Vec::new() });
+		}
+		Err(YuriSemanticError {
+			error_type: YuriSemanticErrorType::UnresolvedName,
+			description: Some(format!("Couldn't find anything named `{path}` (used at %)")),
+			markers: vec![location.clone()],
+		})
+	}
+
+	/// Looks up the rest of a path inside a module.
+	fn lookup_in(
+		&self,
+		from: ModuleId,
+		module: ModuleId,
+		rest: &[&str],
+		path: &str,
+		location: &Range<usize>,
+	) -> Result<Reference, YuriSemanticError> {
+		let Some(name) = rest.first() else {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::UnresolvedName,
+				description: Some(format!("`{path}` is a module, and can't be used as a value (used at %)")),
+				markers: vec![location.clone()],
+			});
+		};
+		let Some(symbol) = self.modules[module].members.get(*name) else {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::UnresolvedName,
+				description: Some(format!(
+					"Couldn't find `{name}` in the module `{}` (used at %)",
+					self.modules[module].path
+				)),
+				markers: vec![location.clone()],
+			});
+		};
+		let declared = &self.symbols[*symbol];
+		if !declared.exported && !self.is_within(from, declared.parent) {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::PrivateAccess,
+				description: Some(format!(
+					"`{}` isn't exported from the module `{}`, so it can't be used here (%)",
+					declared.qualified_name,
+					self.modules[declared.parent].path,
+				)),
+				markers: vec![location.clone(), declared.location.clone()],
+			});
+		}
+		self.lookup_member(from, *symbol, &rest[1..], path, location)
+	}
+
+	fn lookup_member(
+		&self,
+		from: ModuleId,
+		symbol: SymbolId,
+		rest: &[&str],
+		path: &str,
+		location: &Range<usize>,
+	) -> Result<Reference, YuriSemanticError> {
+		if let SymbolKind::Module(module) = self.symbols[symbol].kind {
+			self.lookup_in(from, module, rest, path, location)
+		} else {
+			Ok(Reference {
+				target: ResolvedName::Symbol(symbol),
+				members: rest.iter().map(|s| s.to_string()).collect(),
+			})
+		}
+	}
+}
+
+/// Walks the expressions of a single module, keeping track of local bindings.
+struct Walker<'a> {
+	resolution: &'a mut NameResolution,
+	module: ModuleId,
+	/// Every local binding currently in scope, innermost last.
+	locals: Vec<(String, Range<usize>)>,
+}
+
+impl Walker<'_> {
+	fn record(&mut self, location: &Range<usize>, reference: Reference) {
+		self.resolution.references.insert((self.module, location.clone()), reference);
+	}
+
+	fn resolve_value(&mut self, path: &str, location: &Range<usize>) -> Result<(), YuriSemanticError> {
+		let (first, rest) = path.split_once('.')
+			.map_or((path, None), |(first, rest)| (first, Some(rest)));
+		let local = self.locals.iter()
+			.rev()
+			.find(|(name, _)| name == first)
+			.cloned();
+		let reference = if let Some((name, declared_at)) = local {
+			Reference {
+				target: ResolvedName::Local { name, declared_at },
+				members: rest.map_or_else(Vec::new, |rest| rest.split('.').map(String::from).collect()),
+			}
+		} else {
+			self.resolution.lookup(self.module, path, location)?
+		};
+		self.record(location, reference);
+		Ok(())
+	}
+
+	fn walk_type(&mut self, ty: &YuriType, location: &Range<usize>) -> Result<(), YuriSemanticError> {
+		match ty {
+			YuriType::Array(inner, length) => {
+				if let ArrayLength::Named(name) = length {
+					// array lengths are only ever module-level constants
+					let reference = self.resolution.lookup(self.module, name, location)?;
+					self.resolution.references.insert((self.module, location.clone()), reference);
+				}
+				self.walk_type(inner, location)
+			}
+			YuriType::Complex(fields) => {
+				for field in fields {
+					self.walk_type(&field.field_type, location)?;
+				}
+				Ok(())
+			}
+			_ => Ok(()),
+		}
+	}
+
+	fn walk_block(&mut self, block: &Block) -> Result<(), YuriSemanticError> {
+		let scope_start = self.locals.len();
+		for statement in &block.statements {
+			match statement {
+				Statement::Expression(expr) | Statement::Return(expr) => self.walk_expression(expr)?,
+				Statement::Variable(variable) => {
+					if let Some(ty) = &variable.explicit_type {
+						self.walk_type(ty, &variable.location)?;
+					}
+					self.walk_expression(&variable.value)?;
+					// the binding only comes into scope after its own value,
+					// so `let x = x + 1;` refers to the outer `x`.
+					self.locals.push((variable.name.clone(), variable.location.clone()));
+				}
+			}
+		}
+		if let Some(tail) = &block.tail {
+			self.walk_expression(tail)?;
+		}
+		self.locals.truncate(scope_start);
+		Ok(())
+	}
+
+	/// Walks a block with some extra bindings in scope.
+	fn walk_block_with(&mut self, bindings: &[&String], declared_at: &Range<usize>, block: &Block) -> Result<(), YuriSemanticError> {
+		let scope_start = self.locals.len();
+		for binding in bindings {
+			self.locals.push(((*binding).clone(), declared_at.clone()));
+		}
+		self.walk_block(block)?;
+		self.locals.truncate(scope_start);
+		Ok(())
+	}
+
+	fn walk_expression(&mut self, expr: &Expression) -> Result<(), YuriSemanticError> {
+		match &expr.kind {
+			ExpressionKind::Literal(_) | ExpressionKind::Builtin(_) => {}
+			ExpressionKind::Variable(name) => self.resolve_value(name, &expr.location)?,
+			ExpressionKind::FunctionCall { function_name, arguments } => {
+				// functions aren't values, so locals can't shadow them
+				let reference = self.resolution.lookup(self.module, function_name, &expr.location)?;
+				self.record(&expr.location, reference);
+				for argument in arguments {
+					self.walk_expression(argument)?;
+				}
+			}
+			ExpressionKind::Construct { target, arguments } => {
+				self.walk_type(target, &expr.location)?;
+				for argument in arguments {
+					self.walk_expression(argument)?;
+				}
+			}
+			ExpressionKind::Complex(fields) => {
+				for (_, value) in fields {
+					self.walk_expression(value)?;
+				}
+			}
+			ExpressionKind::Array(elements) => {
+				for element in elements {
+					self.walk_expression(element)?;
+				}
+			}
+			ExpressionKind::Index { target, index } => {
+				self.walk_expression(target)?;
+				self.walk_expression(index)?;
+			}
+			ExpressionKind::Unary { operand, .. } => self.walk_expression(operand)?,
+			ExpressionKind::Binary { lhs, rhs, .. } => {
+				self.walk_expression(lhs)?;
+				self.walk_expression(rhs)?;
+			}
+			ExpressionKind::Block(block) => self.walk_block(block)?,
+			ExpressionKind::If(if_expr) => {
+				self.walk_expression(&if_expr.condition)?;
+				self.walk_block(&if_expr.block)?;
+				match &if_expr.block_else {
+					Some(Else::Block(block)) => self.walk_block(block)?,
+					Some(Else::If(else_if)) => self.walk_expression(else_if)?,
+					None => {}
+				}
+			}
+			ExpressionKind::Loop { index, count, block } => {
+				self.walk_expression(count)?;
+				self.walk_block_with(&[index], &expr.location, block)?;
+			}
+			ExpressionKind::Fold { accumulator, initial, item, items, block } => {
+				self.walk_expression(initial)?;
+				self.walk_expression(items)?;
+				self.walk_block_with(&[accumulator, item], &expr.location, block)?;
+			}
+			ExpressionKind::Map { item, items, block }
+			| ExpressionKind::Filter { item, items, block } => {
+				self.walk_expression(items)?;
+				self.walk_block_with(&[item], &expr.location, block)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn walk_module(&mut self, ast: &YuriModule) -> Result<(), YuriSemanticError> {
+		for property in &ast.properties {
+			self.walk_type(&property.property_type, &property.location)?;
+		}
+		for global in &ast.globals {
+			if let Some(ty) = &global.explicit_type {
+				self.walk_type(ty, &global.location)?;
+			}
+			self.walk_expression(&global.value)?;
+		}
+		for function in &ast.functions {
+			for argument in &function.arguments {
+				self.walk_type(&argument.argument_type, &argument.location)?;
+			}
+			self.walk_type(&function.return_type, &function.location)?;
+			self.locals = function.arguments.iter()
+				.map(|argument| (argument.name.clone(), argument.location.clone()))
+				.collect();
+			self.walk_block(&function.body)?;
+			self.locals.clear();
+		}
+		Ok(())
+	}
+}
+
+/// Builds the scopes for the root module and everything it imports,
+/// then resolves every name used in them.
+pub fn resolve_names(root: &YuriModule, imports: &ResolvedImports) -> Result<NameResolution, YuriSemanticError> {
+	let mut resolution = NameResolution::default();
+	resolution.register_module(root, String::new(), None, None, Vec::new())?;
+	let mut imported_roots = HashMap::new();
+	for (file, imported) in imports.modules.iter().enumerate() {
+		let id = resolution.register_module(&imported.module, imported.name.clone(), None, Some(file), Vec::new())?;
+		imported_roots.insert(imported.name.clone(), id);
+	}
+
+	for id in 0..resolution.modules.len() {
+		let ast = resolution.module_ast(id, root, imports);
+		for import in &ast.imports {
+			let Some(imported) = imported_roots.get(&import.module) else {
+				return Err(YuriSemanticError {
+					error_type: YuriSemanticErrorType::ModuleNotFound,
+					description: Some(format!("The module `{}` (imported at %) was never loaded", import.module)),
+					markers: vec![import.location.clone()],
+				});
+			};
+			resolution.modules[id].imports.insert(import.module.clone(), *imported);
+		}
+	}
+
+	for id in 0..resolution.modules.len() {
+		let ast = resolution.module_ast(id, root, imports);
+		let mut walker = Walker {
+			resolution: &mut resolution,
+			module: id,
+			locals: Vec::new(),
+		};
+		walker.walk_module(ast)?;
+	}
+	Ok(resolution)
+}
+
+#[cfg(test)]
+mod test {
+	use crate::error::{YuriCompileError, YuriSemanticErrorType};
+	use crate::import::{resolve_imports, MemoryLoader};
+	use crate::parse::{ExpressionKind, YuriModule};
+	use crate::resolve::{resolve_names, NameResolution, ResolvedName, ROOT_MODULE};
+	use crate::source::SourceMap;
+	use crate::YuriShader;
+
+	fn resolve(source: &str, loader: &MemoryLoader) -> Result<(YuriModule, NameResolution), YuriCompileError> {
+		let root = YuriShader::parse(&YuriShader::lex(source)?)?;
+		let mut sources = SourceMap::new();
+		sources.add("<test>", source);
+		let imports = resolve_imports(&root, &mut sources, loader)?;
+		let resolution = resolve_names(&root, &imports)?;
+		Ok((root, resolution))
+	}
+
+	fn resolve_err(source: &str, loader: &MemoryLoader) -> YuriSemanticErrorType {
+		match resolve(source, loader) {
+			Err(YuriCompileError::Semantic(err)) => err.error_type(),
+			other => panic!("expected a semantic error, got {other:?}"),
+		}
+	}
+
+	#[test]
+	fn nested_modules() {
+		let source = "
+			module lighting {
+				export module pbr {
+					export fn brdf(n: f3): f { util.scale(n[0]) }
+				}
+				export module util {
+					export fn scale(x: f): f { x * 2.0 }
+				}
+			}
+			fn main(n: f3): f { lighting.pbr.brdf(n) }
+		";
+		let (root, resolution) = resolve(source, &MemoryLoader::new()).unwrap();
+		let call = root.functions[0].body.tail.as_ref().unwrap();
+		let reference = resolution.reference(ROOT_MODULE, &call.location).unwrap();
+		let brdf = resolution.symbol_by_name("lighting.pbr.brdf").unwrap();
+		assert_eq!(reference.target, ResolvedName::Symbol(brdf));
+	}
+
+	#[test]
+	fn locals_and_members() {
+		let source = "
+			let scale: f = 2.0;
+			fn main(pos: f3): f2 { let scale = pos.z; pos.xy * scale }
+		";
+		let (root, resolution) = resolve(source, &MemoryLoader::new()).unwrap();
+		let tail = root.functions[0].body.tail.as_ref().unwrap();
+		let ExpressionKind::Binary { lhs, rhs, .. } = &tail.kind else { panic!() };
+		let pos = resolution.reference(ROOT_MODULE, &lhs.location).unwrap();
+		assert!(matches!(&pos.target, ResolvedName::Local { name, .. } if name == "pos"));
+		assert_eq!(pos.members, ["xy"]);
+		// the local shadows the global
+		let scale = resolution.reference(ROOT_MODULE, &rhs.location).unwrap();
+		assert!(matches!(&scale.target, ResolvedName::Local { name, .. } if name == "scale"));
+	}
+
+	#[test]
+	fn exports() {
+		let loader = MemoryLoader::new()
+			.with_module("util", "export fn public(): f { private() } fn private(): f { 1.0 }");
+		assert!(resolve("import util; fn main(): f { util.public() }", &loader).is_ok());
+		assert_eq!(
+			resolve_err("import util; fn main(): f { util.private() }", &loader),
+			YuriSemanticErrorType::PrivateAccess
+		);
+		assert_eq!(
+			resolve_err("module a { fn hidden(): f { 1.0 } } fn main(): f { a.hidden() }", &loader),
+			YuriSemanticErrorType::PrivateAccess
+		);
+		assert_eq!(
+			resolve_err("fn main(): f { nothing.here() }", &loader),
+			YuriSemanticErrorType::UnresolvedName
+		);
+		assert_eq!(
+			resolve_err("fn main(): f { 1.0 } fn main(): f { 2.0 }", &loader),
+			YuriSemanticErrorType::DuplicateDeclaration
+		);
+	}
+}
@@ -0,0 +1,203 @@
+//! Source text, and turning offsets into it into lines and columns.
+//! Every file in a [SourceMap] gets its own stretch of offsets, so a location (a byte range, like
+//! every token, expression and error marker has) is enough to know which file it's in.
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+pub type FileId = usize;
+
+/// A line and column in a file, both starting at 0.
+/// Columns are counted in UTF-8 bytes, and in UTF-16 code units for the editors that want those.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct LineColumn {
+	pub line: usize,
+	pub column: usize,
+	pub utf16_column: usize,
+}
+
+impl Display for LineColumn {
+	/// Writes it the way people count, starting at 1.
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}", self.line + 1, self.column + 1)
+	}
+}
+
+/// A file's text, and where it lives in its [SourceMap].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SourceFile {
+	id: FileId,
+	/// A human-readable description of where the text came from, usually a path.
+	name: String,
+	text: String,
+	/// The offset of the file's first byte.
+	start: usize,
+	/// Where each line starts, relative to the file.
+	line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+	fn new(id: FileId, name: String, text: String, start: usize) -> Self {
+		let newlines = text.match_indices('\n').map(|(i, _)| i + 1);
+		let line_starts = std::iter::once(0).chain(newlines).collect();
+		Self { id, name, text, start, line_starts }
+	}
+
+	pub fn id(&self) -> FileId {
+		self.id
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+
+	/// The offset of the file's first byte. Locations in the file are relative to the whole map, not the file.
+	pub fn start(&self) -> usize {
+		self.start
+	}
+
+	/// The offset just past the file's last byte, which is where errors at the end of the file point.
+	pub fn end(&self) -> usize {
+		self.start + self.text.len()
+	}
+
+	pub fn contains(&self, offset: usize) -> bool {
+		(self.start..=self.end()).contains(&offset)
+	}
+
+	pub fn line_count(&self) -> usize {
+		self.line_starts.len()
+	}
+
+	/// The text of a line, without the newline.
+	pub fn line(&self, line: usize) -> &str {
+		let Some(start) = self.line_starts.get(line) else {
+			return "";
+		};
+		let end = self.line_starts.get(line + 1).map_or(self.text.len(), |next| next - 1);
+		self.text[*start..end].trim_end_matches('\r')
+	}
+
+	/// The line and column of an offset, which gets clamped to the file.
+	pub fn position(&self, offset: usize) -> LineColumn {
+		let mut relative = offset.clamp(self.start, self.end()) - self.start;
+		while !self.text.is_char_boundary(relative) {
+			relative -= 1;
+		}
+		// the first line starts at 0, so there's always at least one
+		let line = self.line_starts.partition_point(|start| *start <= relative) - 1;
+		let before = &self.text[self.line_starts[line]..relative];
+		LineColumn {
+			line,
+			column: before.len(),
+			utf16_column: before.encode_utf16().count(),
+		}
+	}
+
+	/// The lines and columns of both ends of a location.
+	pub fn span(&self, location: &Range<usize>) -> Range<LineColumn> {
+		self.position(location.start)..self.position(location.end)
+	}
+
+	/// The other way around from [SourceFile::position], for editors that speak in UTF-16:
+	/// the offset of a line and UTF-16 column, clamped to the line.
+	pub fn offset(&self, line: usize, utf16_column: usize) -> usize {
+		let Some(line_start) = self.line_starts.get(line) else {
+			return self.end();
+		};
+		let mut units = 0;
+		let mut column = 0;
+		for ch in self.line(line).chars() {
+			if units >= utf16_column {
+				break;
+			}
+			units += ch.len_utf16();
+			column += ch.len_utf8();
+		}
+		self.start + line_start + column
+	}
+}
+
+/// Every file that went into a compilation.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct SourceMap {
+	files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a file after the ones already there. The first file starts at 0,
+	/// so locations from lexing it on its own are still right.
+	pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) -> FileId {
+		let id = self.files.len();
+		// leave a gap, so the end of one file isn't the start of the next
+		let start = self.files.last().map_or(0, |file| file.end() + 1);
+		self.files.push(SourceFile::new(id, name.into(), text.into(), start));
+		id
+	}
+
+	pub fn file(&self, id: FileId) -> &SourceFile {
+		&self.files[id]
+	}
+
+	pub fn files(&self) -> &[SourceFile] {
+		&self.files
+	}
+
+	/// The file an offset is in.
+	pub fn lookup(&self, offset: usize) -> Option<&SourceFile> {
+		let index = self.files.partition_point(|file| file.start <= offset);
+		self.files.get(index.checked_sub(1)?)
+			.filter(|file| file.contains(offset))
+	}
+
+	/// The file an offset is in, along with its line and column there.
+	pub fn position(&self, offset: usize) -> Option<(&SourceFile, LineColumn)> {
+		let file = self.lookup(offset)?;
+		Some((file, file.position(offset)))
+	}
+
+	/// The text at a location, if it's all in one file.
+	pub fn snippet(&self, location: &Range<usize>) -> Option<&str> {
+		let file = self.lookup(location.start)?;
+		file.text.get(location.start - file.start..location.end.checked_sub(file.start)?)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::source::{LineColumn, SourceMap};
+
+	#[test]
+	fn positions() {
+		let mut sources = SourceMap::new();
+		let root = sources.add("root.yuri", "let a = 1;\nlet é = \"𝔂\"; a\r\nend");
+		let other = sources.add("other.yuri", "fn f() {}\n");
+		let root_file = sources.file(root);
+		assert_eq!(root_file.line_count(), 3);
+		assert_eq!(root_file.line(1), "let é = \"𝔂\"; a");
+		assert_eq!(root_file.position(0), LineColumn { line: 0, column: 0, utf16_column: 0 });
+		// `é` is two bytes but one UTF-16 unit, and `𝔂` is four bytes but two units
+		let a = root_file.text().rfind('a').unwrap();
+		assert_eq!(root_file.position(a), LineColumn { line: 1, column: 17, utf16_column: 14 });
+		assert_eq!(root_file.position(a).to_string(), "2:18");
+		assert_eq!(root_file.offset(1, 14), a);
+		assert_eq!(root_file.offset(1, 100), root_file.start() + 11 + root_file.line(1).len());
+
+		// files don't overlap, and their ends still belong to them
+		let other_file = sources.file(other);
+		assert!(other_file.start() > root_file.end());
+		assert_eq!(sources.lookup(root_file.end()).unwrap().id(), root);
+		assert_eq!(sources.lookup(other_file.start()).unwrap().id(), other);
+		assert!(sources.lookup(other_file.end() + 1).is_none());
+		let (file, position) = sources.position(other_file.start() + 3).unwrap();
+		assert_eq!((file.name(), position.line, position.column), ("other.yuri", 0, 3));
+		assert_eq!(sources.snippet(&(other_file.start()..other_file.start() + 2)), Some("fn"));
+	}
+}
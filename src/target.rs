@@ -0,0 +1,196 @@
+//! What the compiled SPIR-V is going to run on.
+//! The environment decides which SPIR-V versions are allowed (and what's allowed in them),
+//! and the version decides how some things have to be written down.
+use std::fmt::{Display, Formatter};
+use crate::builtin::FragOrigin;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum TargetEnv {
+	/// What SDL's GPU API needs, and the default.
+	#[default]
+	Vulkan1_0,
+	Vulkan1_1,
+	Vulkan1_2,
+	Vulkan1_3,
+	/// OpenGL 4.5 with `ARB_gl_spirv`.
+	OpenGL4_5,
+}
+
+impl TargetEnv {
+	pub const ALL: [TargetEnv; 5] = [
+		TargetEnv::Vulkan1_0,
+		TargetEnv::Vulkan1_1,
+		TargetEnv::Vulkan1_2,
+		TargetEnv::Vulkan1_3,
+		TargetEnv::OpenGL4_5,
+	];
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			TargetEnv::Vulkan1_0 	=> "vulkan1.0",
+			TargetEnv::Vulkan1_1 	=> "vulkan1.1",
+			TargetEnv::Vulkan1_2 	=> "vulkan1.2",
+			TargetEnv::Vulkan1_3 	=> "vulkan1.3",
+			TargetEnv::OpenGL4_5 	=> "opengl4.5",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter()
+			.find(|env| env.name() == name)
+			.copied()
+	}
+
+	/// The SPIR-V version the environment natively uses, which is what gets emitted if nothing else is asked for.
+	pub const fn default_spirv_version(self) -> SpirvVersion {
+		match self {
+			TargetEnv::Vulkan1_0 | TargetEnv::OpenGL4_5 => SpirvVersion::new(1, 0),
+			TargetEnv::Vulkan1_1 => SpirvVersion::new(1, 3),
+			TargetEnv::Vulkan1_2 => SpirvVersion::new(1, 5),
+			TargetEnv::Vulkan1_3 => SpirvVersion::new(1, 6),
+		}
+	}
+
+	/// The newest SPIR-V version the environment can load.
+	pub const fn max_spirv_version(self) -> SpirvVersion {
+		match self {
+			// Vulkan 1.1 can do 1.4 with VK_KHR_spirv_1_4, which is common enough to allow
+			TargetEnv::Vulkan1_1 => SpirvVersion::new(1, 4),
+			other => other.default_spirv_version(),
+		}
+	}
+
+	pub const fn is_vulkan(self) -> bool {
+		!matches!(self, TargetEnv::OpenGL4_5)
+	}
+
+	/// Whether fragment shaders can ask the GPU for `@frag.coord` with that origin.
+	/// Vulkan only does upper left, so lower left has to be flipped by hand.
+	pub const fn supports_origin(self, origin: FragOrigin) -> bool {
+		!self.is_vulkan() || matches!(origin, FragOrigin::UpperLeft)
+	}
+}
+
+impl Display for TargetEnv {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TargetEnv::Vulkan1_0 => write!(f, "Vulkan 1.0"),
+			TargetEnv::Vulkan1_1 => write!(f, "Vulkan 1.1"),
+			TargetEnv::Vulkan1_2 => write!(f, "Vulkan 1.2"),
+			TargetEnv::Vulkan1_3 => write!(f, "Vulkan 1.3"),
+			TargetEnv::OpenGL4_5 => write!(f, "OpenGL 4.5"),
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SpirvVersion {
+	pub major: u8,
+	pub minor: u8,
+}
+
+impl SpirvVersion {
+	pub const fn new(major: u8, minor: u8) -> Self {
+		Self { major, minor }
+	}
+
+	/// Parses a version like `1.4`.
+	pub fn from_name(name: &str) -> Option<Self> {
+		let (major, minor) = name.split_once('.')?;
+		Some(Self::new(major.parse().ok()?, minor.parse().ok()?))
+	}
+}
+
+impl Display for SpirvVersion {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}.{}", self.major, self.minor)
+	}
+}
+
+/// An environment along with the SPIR-V version to use for it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Target {
+	env: TargetEnv,
+	spirv_version: SpirvVersion,
+	frag_origin: FragOrigin,
+}
+
+impl Default for Target {
+	fn default() -> Self {
+		Self::new(TargetEnv::default())
+	}
+}
+
+impl Target {
+	/// Targets the environment with the SPIR-V version it natively uses.
+	pub const fn new(env: TargetEnv) -> Self {
+		Self { env, spirv_version: env.default_spirv_version(), frag_origin: FragOrigin::UpperLeft }
+	}
+
+	/// Targets the environment with a specific SPIR-V version, as long as the environment can load it.
+	pub fn with_spirv_version(env: TargetEnv, spirv_version: SpirvVersion) -> Result<Self, YuriSemanticError> {
+		if spirv_version.major != 1 || spirv_version.minor > 6 {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::Unsupported,
+				description: Some(format!("SPIR-V {spirv_version} doesn't exist (or at least isn't supported), use 1.0 through 1.6")),
+				markers: vec![],
+			});
+		}
+		if spirv_version > env.max_spirv_version() {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::Unsupported,
+				description: Some(format!("{env} can't load SPIR-V {spirv_version}, the newest it can do is {}", env.max_spirv_version())),
+				markers: vec![],
+			});
+		}
+		Ok(Self { spirv_version, ..Self::new(env) })
+	}
+
+	/// Changes where `@frag.coord` has its origin, for fragment shaders that don't pick one themselves.
+	pub const fn with_frag_origin(self, frag_origin: FragOrigin) -> Self {
+		Self { frag_origin, ..self }
+	}
+
+	pub const fn env(&self) -> TargetEnv {
+		self.env
+	}
+
+	pub const fn spirv_version(&self) -> SpirvVersion {
+		self.spirv_version
+	}
+
+	pub const fn frag_origin(&self) -> FragOrigin {
+		self.frag_origin
+	}
+
+	/// Before SPIR-V 1.4, `OpEntryPoint` only lists the inputs and outputs. Starting with 1.4 it has to list
+	/// every global variable the entry point uses, uniforms and samplers included.
+	pub(crate) fn lists_every_global(&self) -> bool {
+		self.spirv_version >= SpirvVersion::new(1, 4)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::error::YuriSemanticErrorType;
+	use crate::target::{SpirvVersion, Target, TargetEnv};
+
+	#[test]
+	fn target_versions() {
+		assert_eq!(Target::default().spirv_version(), SpirvVersion::new(1, 0));
+		assert_eq!(Target::new(TargetEnv::Vulkan1_2).spirv_version(), SpirvVersion::new(1, 5));
+		assert!(Target::with_spirv_version(TargetEnv::Vulkan1_1, SpirvVersion::new(1, 4)).unwrap().lists_every_global());
+		assert!(!Target::new(TargetEnv::Vulkan1_1).lists_every_global());
+		// newer environments can still load older versions
+		assert!(Target::with_spirv_version(TargetEnv::Vulkan1_3, SpirvVersion::new(1, 0)).is_ok());
+		let err = Target::with_spirv_version(TargetEnv::Vulkan1_0, SpirvVersion::new(1, 3)).unwrap_err();
+		assert_eq!(err.error_type(), YuriSemanticErrorType::Unsupported);
+		assert!(Target::with_spirv_version(TargetEnv::OpenGL4_5, SpirvVersion::new(1, 1)).is_err());
+		assert!(Target::with_spirv_version(TargetEnv::Vulkan1_3, SpirvVersion::new(2, 0)).is_err());
+
+		assert_eq!(TargetEnv::from_name("opengl4.5"), Some(TargetEnv::OpenGL4_5));
+		assert_eq!(SpirvVersion::from_name("1.4"), Some(SpirvVersion::new(1, 4)));
+		assert_eq!(SpirvVersion::from_name("1"), None);
+	}
+}
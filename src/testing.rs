@@ -0,0 +1,110 @@
+//! Runs the `@test` functions in a module on the CPU, using the [Interpreter].
+//! A test passes if it returns `true` (or nothing at all) without any of its assertions failing.
+//! Assertions are builtins, so tests can use as many as they want:
+//! `assert(x)`, `assert_eq(a, b)` and `assert_near(a, b, tolerance)` for floats, vectors and matrices.
+use std::ops::Range;
+use crate::check::{TypedExpressionKind, TypedProgram};
+use crate::consteval::ConstValue;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::interpret::Interpreter;
+use crate::resolve::ROOT_MODULE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+	/// The fully-qualified name of the test function.
+	pub name: String,
+	pub location: Range<usize>,
+	/// What went wrong, if the test failed. Usually an [YuriSemanticErrorType::AssertionFailed] error,
+	/// but a test can also fail by doing something the GPU can't, like dividing by zero.
+	pub failure: Option<YuriSemanticError>,
+}
+
+impl TestResult {
+	pub fn passed(&self) -> bool {
+		self.failure.is_none()
+	}
+}
+
+/// Runs every test in the root module with the filter in its name (or every test, without one), in the order they're declared.
+/// Tests in imported modules don't run, those get tested on their own.
+pub fn run_tests(program: &TypedProgram, filter: Option<&str>) -> Vec<TestResult> {
+	let mut tests: Vec<_> = program.functions.iter()
+		.enumerate()
+		.filter(|(_, function)| function.is_test() && function.module == ROOT_MODULE)
+		.filter(|(_, function)| filter.is_none_or(|filter| function.name.contains(filter)))
+		.collect();
+	tests.sort_by_key(|(_, function)| function.location.start);
+	tests.into_iter()
+		.map(|(id, function)| {
+			// every test gets a fresh interpreter, so one can't affect another
+			let failure = match Interpreter::new(program).call_id(id, &[]) {
+				Ok(ConstValue::Bool(false)) => {
+					let returned = match &function.body.kind {
+						TypedExpressionKind::Block { tail, .. } => &tail.location,
+						_ => &function.body.location,
+					};
+					Some(YuriSemanticError {
+						error_type: YuriSemanticErrorType::AssertionFailed,
+						description: Some(format!("The test `{}` returned false (at %)", function.name)),
+						markers: vec![returned.clone()],
+					})
+				}
+				Ok(_) => None,
+				Err(err) => Some(err),
+			};
+			TestResult { name: function.name.clone(), location: function.location.clone(), failure }
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use crate::error::YuriSemanticErrorType;
+	use crate::source::SourceMap;
+	use crate::YuriShader;
+
+	#[test]
+	fn run_module_tests() {
+		let source = "
+			fn lerp(a: f, b: f, t: f): f { a + (b - a) * t }
+			fn halve(x: u): u { x / 2 }
+			fn check_halves(x: u) { assert_eq(halve(x * 2), x); }
+
+			@test fn lerp_ends(): bool { lerp(1.0, 3.0, 0.0) == 1.0 and lerp(1.0, 3.0, 1.0) == 3.0 }
+			@test fn lerp_middle() {
+				assert_near(lerp(0.0, 1.0, 0.3), 0.3, 0.0001);
+				assert_near(f2(lerp(0.0, 0.1, 0.5), 1.0), f2(0.05, 1.0), 0.0001);
+				check_halves(21);
+			}
+			@test fn wrong_sum(): bool { 1 + 1 == 3 }
+			@test fn wrong_halves() {
+				let x: u = 3;
+				assert(halve(x) < 2);
+				assert_eq(u2(halve(x), 2), u2(2, 2));
+			}
+			@test fn by_zero(): bool { halve(1) / halve(1) == 1 }
+		";
+		let mut sources = SourceMap::new();
+		let root = sources.add("<test>", source);
+		let results = YuriShader::test_sources(&mut sources, root, &Default::default(), None).unwrap();
+		let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+		assert_eq!(names, ["lerp_ends", "lerp_middle", "wrong_sum", "wrong_halves", "by_zero"]);
+		assert!(results[0].passed());
+		assert!(results[1].passed(), "{:?}", results[1].failure);
+
+		let failure = |i: usize| results[i].failure.as_ref().unwrap().with_sources(&sources).to_string();
+		assert!(failure(2).starts_with("AssertionFailed: The test `wrong_sum` returned false (at `1 + 1 == 3`)"), "{}", failure(2));
+		// assertions say what the values were
+		assert!(failure(3).starts_with("AssertionFailed: The assertion `assert_eq(u2(halve(x), 2), u2(2, 2))` failed: \
+			the left side is [1, 2], but the right side is [2, 2]"), "{}", failure(3));
+		assert_eq!(results[4].failure.as_ref().unwrap().error_type(), YuriSemanticErrorType::Runtime);
+
+		let filtered = YuriShader::test_sources(&mut sources, root, &Default::default(), Some("lerp")).unwrap();
+		assert_eq!(filtered.len(), 2);
+		// tests run without anything to go on
+		assert!(YuriShader::new("@test fn takes(x: f): bool { x > 0.0 }").is_err());
+		assert!(YuriShader::new("@test fn gives(): f { 1.0 }").is_err());
+		// none of it gets in the way of compiling the module for the GPU
+		assert!(YuriShader::new(&format!("{source} @frag fn main(): f4 {{ check_halves(1); f4(lerp(0.0, 1.0, 0.5), 0.0, 0.0, 1.0) }}")).is_ok());
+	}
+}
@@ -0,0 +1,970 @@
+//! What the backends that write shaders out as source code ([crate::glsl], [crate::wgsl], [crate::hlsl], [crate::msl])
+//! have in common, which is most of it: naming things, and turning Yuri's expressions into the statements and
+//! expressions of a C-like language (see [Emit]). What's left to each language is how things are spelled.
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::ops::Range;
+use crate::builtin::{BuiltinFunction, BuiltinInput};
+use crate::check::{number_type, FunctionId, GlobalId, LocalId, PropId, ShaderStage, TypedExpression, TypedExpressionKind, TypedProgram};
+use crate::compile::{check_input_stage, std140_offsets, without_annotations};
+use crate::consteval::ConstValue;
+use crate::error::{YuriSemanticError, YuriSemanticErrorType};
+use crate::parse::{ArrayLength, BinaryOperator, CompositeSize, NumberType, UnaryOperator, YuriType};
+
+pub(crate) fn fixed_length(length: &ArrayLength) -> usize {
+	match length {
+		ArrayLength::Fixed(length) => *length,
+		ArrayLength::Named(_) => unreachable!("array lengths are resolved by the type checker"),
+	}
+}
+
+pub(crate) fn unsupported(description: String, location: &Range<usize>) -> YuriSemanticError {
+	YuriSemanticError {
+		error_type: YuriSemanticErrorType::Unsupported,
+		description: Some(description),
+		markers: vec![location.clone()],
+	}
+}
+
+/// Samplers can only be passed around as they are in most languages, they can't go in anything.
+pub(crate) fn contains_sampler(ty: &YuriType) -> bool {
+	match ty {
+		YuriType::Sampler(_) => true,
+		YuriType::Array(inner, _) => contains_sampler(inner),
+		YuriType::Complex(fields) => fields.iter().any(|f| contains_sampler(&f.field_type)),
+		_ => false,
+	}
+}
+
+/// Turns a Yuri name (which might be qualified, like `lighting.shade`) into a C-style identifier.
+/// What's reserved is up to the language.
+pub(crate) fn identifier(name: &str) -> String {
+	let mut identifier = String::new();
+	for c in name.chars() {
+		let c = if c.is_ascii_alphanumeric() { c } else { '_' };
+		// double underscores are reserved pretty much everywhere
+		if c != '_' || !identifier.ends_with('_') {
+			identifier.push(c);
+		}
+	}
+	if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+		identifier.insert(0, '_');
+	}
+	identifier
+}
+
+/// The name itself if it's free, or the first free one with a number on the end.
+pub(crate) fn unique(name: String, is_free: impl Fn(&str) -> bool) -> String {
+	if is_free(&name) {
+		return name;
+	}
+	let separator = if name.ends_with('_') { "" } else { "_" };
+	(1..)
+		.map(|n| format!("{name}{separator}{n}"))
+		.find(|candidate| is_free(candidate))
+		.unwrap()
+}
+
+/// Same as [unique], for struct members, which have a namespace of their own.
+pub(crate) fn unique_member(names: &mut HashSet<String>, name: String) -> String {
+	let name = unique(name, |candidate| !names.contains(candidate));
+	names.insert(name.clone());
+	name
+}
+
+/// An expression, in whatever language.
+#[derive(Clone)]
+pub(crate) struct Code {
+	pub text: String,
+	/// Whether it needs parentheses before it can be an operand, like `a + b` or `-1`.
+	pub compound: bool,
+}
+
+impl Code {
+	pub fn atom(text: impl Into<String>) -> Self {
+		Self { text: text.into(), compound: false }
+	}
+
+	pub fn compound(text: impl Into<String>) -> Self {
+		Self { text: text.into(), compound: true }
+	}
+
+	pub fn operand(&self) -> String {
+		if self.compound { format!("({})", self.text) } else { self.text.clone() }
+	}
+
+	/// Whether it's just a name or a number, which can be repeated without doing any work twice.
+	pub fn is_simple(&self) -> bool {
+		!self.compound && self.text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+	}
+}
+
+/// The function currently being written.
+#[derive(Default)]
+pub(crate) struct Scope {
+	pub function: Option<FunctionId>,
+	/// Every name used in the function so far. Names are never reused inside a function,
+	/// so nothing has to care about which block a variable was declared in.
+	pub names: HashSet<String>,
+	pub locals: HashMap<LocalId, String>,
+	/// The locals that can be assigned to, as opposed to the ones that only ever get the value they start with.
+	pub variables: HashSet<String>,
+	/// The statements so far, indented to the current depth.
+	pub body: String,
+	pub depth: usize,
+}
+
+/// What kind of declaration a global turns into.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum GlobalKind {
+	/// A constant, which is all a global is when nothing can specialize it.
+	Constant,
+	/// A specialization constant with the given ID, with the value as its default.
+	Spec(u32),
+	/// A global worked out from a specialization constant, which has to change along with it.
+	Specialized,
+}
+
+/// What an emitter keeps track of as it writes.
+#[derive(Default)]
+pub(crate) struct Writer {
+	/// Every name declared at the top level.
+	pub names: HashSet<String>,
+	pub scope: Scope,
+	pub functions: HashMap<FunctionId, String>,
+	pub globals: HashMap<GlobalId, String>,
+	/// What each prop is called, either a member of the uniform buffer (`props.time`) or a sampler,
+	/// which is a texture and a sampler (`tex, tex_sampler`) in most languages.
+	pub props: HashMap<PropId, String>,
+	/// The render target's height, if `@frag.coord` gets flipped with it.
+	pub viewport_height: Option<String>,
+	pub samplers: u32,
+	/// Whether the props (or the viewport height) needed a uniform buffer.
+	pub uniform_buffer: bool,
+	/// The complexes that end up in the uniform buffer, which need their members laid out like std140.
+	pub uniform_structs: HashSet<YuriType>,
+	/// The declarations of the structs, resources and globals that have been used so far.
+	/// Everything comes after what it uses.
+	pub struct_declarations: String,
+	pub resource_declarations: String,
+	pub global_declarations: String,
+}
+
+/// Turning Yuri into statements and expressions, which works the same in every language.
+/// The required methods are how the language spells things, the rest is written in terms of them.
+///
+/// Yuri is made of expressions and these languages aren't, so anything that needs statements (blocks, loops,
+/// `if`s that can't be a single expression) gets written out ahead of the expression it's in,
+/// with its result in a variable.
+pub(crate) trait Emit<'a>: Sized {
+	fn writer(&self) -> &Writer;
+	fn writer_mut(&mut self) -> &mut Writer;
+	fn program(&self) -> &'a TypedProgram;
+	fn stage(&self) -> ShaderStage;
+	/// The name of the language, for errors.
+	fn language(&self) -> &'static str;
+	/// Turns a Yuri name into something the language will take as an identifier.
+	fn sanitize(&self, name: &str) -> String;
+	fn type_name(&mut self, ty: &YuriType) -> String;
+	/// The statement declaring a local. Only a `mutable` one can be declared without a value.
+	fn declaration(&mut self, name: &str, ty: &YuriType, value: Option<&str>, mutable: bool) -> String;
+	/// What goes after a literal of the type, if anything.
+	fn suffix(&self, number_type: NumberType) -> &'static str;
+	/// A float made out of its bits, for the ones that don't have a literal (infinities and NaNs), if the language can.
+	fn float_bits(&self, bits: u32) -> Option<String>;
+	/// Whether there are specialization constants to make. If there aren't, `@spec` globals just keep their defaults.
+	fn specializable(&self) -> bool;
+	/// The declaration of a global, with the value already written out.
+	fn global_declaration(&mut self, name: &str, ty: &YuriType, value: Code, kind: GlobalKind) -> String;
+	/// Repeats a scalar into every component of a vector, if that's what the type wants.
+	fn splat(&mut self, value: Code, from: &YuriType, to: &YuriType) -> Code;
+	/// Picks one of two values without statements, if the language has a way to that works for the type.
+	/// Only used when neither side needed any statements, and there's an `else`.
+	fn conditional(&mut self, ty: &YuriType, condition: &Code, values: [&Code; 2], branches: [&TypedExpression; 2]) -> Option<Code>;
+	/// The operations that aren't just `a op b` in the language, with the operands already written out.
+	fn operation(
+		&mut self,
+		operator: BinaryOperator,
+		operands: [&TypedExpression; 2],
+		values: [&Code; 2],
+		ty: &YuriType,
+	) -> Result<Option<Code>, YuriSemanticError>;
+	/// Calls a builtin function. Assertions never get here, they've been checked already.
+	fn builtin(&mut self, function: BuiltinFunction, arguments: &[TypedExpression], location: &Range<usize>) -> Result<Code, YuriSemanticError>;
+	/// What the builtin input is called in the function being written.
+	fn builtin_input(&mut self, input: BuiltinInput, location: &Range<usize>) -> Result<String, YuriSemanticError>;
+	/// Declares a prop that's a sampler, giving back what it's called (see [Writer::props]).
+	fn declare_sampler(&mut self, id: PropId, name: &str, ty: &YuriType, binding: u32) -> String;
+	/// Declares the uniform buffer with the given members, giving back how each one is read.
+	fn declare_uniforms(&mut self, block: &str, variable: &str, types: &[&YuriType], names: &[String]) -> Vec<String>;
+	/// A function declaration up to its body.
+	fn signature(&mut self, id: FunctionId, name: &str, parameters: Vec<String>) -> String;
+
+	/// A name with its type, the way parameters and struct members are declared (like `float x` or `x: f32`).
+	fn typed(&self, name: &str, ty: &str) -> String {
+		format!("{ty} {name}")
+	}
+
+	/// How a `for` loop declares its counter (without the value).
+	fn counter(&mut self, name: &str, ty: &YuriType) -> String {
+		format!("{} {name}", self.type_name(ty))
+	}
+
+	/// Why the language can't lay out a prop of the type like std140, if it can't.
+	fn std140_mismatch(&self, _ty: &YuriType) -> Option<&'static str> {
+		None
+	}
+
+	/// The type of the sampler half of a sampler, or `None` if the language keeps a texture and its sampler together.
+	fn sampler_type(&self) -> Option<&'static str> {
+		None
+	}
+
+	/// Whether matrices can be negated and divided by a number, as opposed to only multiplied.
+	fn matrix_arithmetic(&self) -> bool {
+		true
+	}
+
+	/// Whether a vector can be made out of a single number, which ends up in every component.
+	fn splats_constants(&self) -> bool {
+		true
+	}
+
+	/// Writes out a struct or an array with the given values in it.
+	fn initializer(&mut self, ty: &YuriType, values: Vec<String>) -> String {
+		format!("{}({})", self.type_name(ty), values.join(", "))
+	}
+
+	/// Where a struct or an array (written out by [Emit::initializer]) can go, which is anywhere in most languages.
+	fn composite(&mut self, value: Code, _ty: &YuriType) -> Code {
+		value
+	}
+
+	/// Converts between number types, or to and from `bool` (which becomes 0 or 1, and anything but 0 becomes true).
+	fn convert(&mut self, value: Code, ty: &YuriType) -> Code {
+		Code::atom(format!("{}({})", self.type_name(ty), value.text))
+	}
+
+	fn property(&mut self, id: PropId) -> String {
+		self.writer().props[&id].clone()
+	}
+
+	/// Calls a function that's been written already.
+	fn call(&mut self, function: FunctionId, arguments: Vec<String>) -> String {
+		format!("{}({})", self.writer().functions[&function], arguments.join(", "))
+	}
+
+	fn field(&mut self, value: Code, ty: &YuriType, index: usize) -> Code {
+		let YuriType::Complex(fields) = ty else {
+			unreachable!("only complexes have fields");
+		};
+		Code::atom(format!("{}.{}", value.operand(), self.sanitize(&fields[index].name)))
+	}
+
+	/// What can be indexed to get the elements of an array (or the columns of a matrix, or the components of a vector).
+	/// `dynamic` is whether the index is anything but a constant.
+	fn elements(&mut self, value: Code, _ty: &YuriType, _dynamic: bool) -> String {
+		value.operand()
+	}
+
+	/// Gets what a loop goes over ready, since it's used once per iteration.
+	fn items(&mut self, items: Code, ty: &YuriType) -> String {
+		self.hoist(items, ty)
+	}
+
+	fn is_free(&self, name: &str) -> bool {
+		let writer = self.writer();
+		!writer.names.contains(name) && !writer.scope.names.contains(name)
+	}
+
+	/// Finds a name like the given one that isn't used yet. Top-level names have to stay clear of the current
+	/// function's locals too, and the functions after it stay clear of the top-level names.
+	fn unique_name(&self, name: &str) -> String {
+		unique(self.sanitize(name), |candidate| self.is_free(candidate))
+	}
+
+	fn global_name(&mut self, name: &str) -> String {
+		let name = self.unique_name(name);
+		self.writer_mut().names.insert(name.clone());
+		name
+	}
+
+	fn local_name(&mut self, name: &str) -> String {
+		let name = self.unique_name(name);
+		self.writer_mut().scope.names.insert(name.clone());
+		name
+	}
+
+	/// Gives one of the current function's locals its name.
+	fn declare_local(&mut self, id: LocalId) -> String {
+		let function = self.writer().scope.function.expect("locals only exist in functions");
+		let name = self.local_name(&self.program().functions[function].locals[id].name);
+		self.writer_mut().scope.locals.insert(id, name.clone());
+		name
+	}
+
+	fn indentation(&self, extra: usize) -> String {
+		"\t".repeat(self.writer().scope.depth + extra)
+	}
+
+	fn line(&mut self, line: impl AsRef<str>) {
+		let indentation = self.indentation(0);
+		let _ = writeln!(self.writer_mut().scope.body, "{indentation}{}", line.as_ref());
+	}
+
+	/// Writes the declaration of a local that already has its name.
+	fn declare(&mut self, name: &str, ty: &YuriType, value: Option<&str>, mutable: bool) {
+		let declaration = self.declaration(name, ty, value, mutable);
+		self.line(declaration);
+		if mutable {
+			self.writer_mut().scope.variables.insert(name.to_string());
+		}
+	}
+
+	/// Declares a variable with a name like the given one, with a value or without.
+	fn variable(&mut self, name: &str, ty: &YuriType, value: Option<&str>) -> String {
+		let name = self.local_name(name);
+		self.declare(&name, ty, value, true);
+		name
+	}
+
+	/// Puts the value in a local, unless it's simple enough to repeat.
+	fn hoist(&mut self, value: Code, ty: &YuriType) -> String {
+		if value.is_simple() {
+			return value.text;
+		}
+		let name = self.local_name("tmp");
+		self.declare(&name, ty, Some(&value.text), false);
+		name
+	}
+
+	/// Textures and samplers can only be passed around as they are, they can't go in anything.
+	fn storable(&self, ty: &YuriType, location: &Range<usize>) -> Result<(), YuriSemanticError> {
+		if contains_sampler(ty) {
+			return Err(unsupported(
+				format!(
+					"{} can't keep samplers in variables, so the `{ty}` at % has to be given to `sample` (or a function) directly",
+					self.language(),
+				),
+				location,
+			));
+		}
+		Ok(())
+	}
+
+	/// The error for a builtin function the language doesn't have.
+	fn missing(&self, function: BuiltinFunction, location: &Range<usize>) -> YuriSemanticError {
+		unsupported(format!("{} doesn't have `{}` (at %)", self.language(), function.name()), location)
+	}
+
+	/// A literal for a number that isn't negative.
+	fn integer(&self, value: u32, number_type: NumberType) -> String {
+		format!("{value}{}", self.suffix(number_type))
+	}
+
+	fn arguments(&mut self, arguments: &[TypedExpression]) -> Result<String, YuriSemanticError> {
+		let arguments = arguments.iter()
+			.map(|a| self.expression(a).map(|code| code.text))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(arguments.join(", "))
+	}
+
+	/// The arguments, splatted to the given type, for the builtins that take a scalar in Yuri but not in the language.
+	fn splatted(&mut self, arguments: &[TypedExpression], ty: &YuriType) -> Result<Vec<String>, YuriSemanticError> {
+		arguments.iter()
+			.map(|argument| {
+				let value = self.expression(argument)?;
+				Ok(self.splat(value, &argument.expression_type, ty).text)
+			})
+			.collect()
+	}
+
+	/// `a ** b`, with both sides splatted to the result type, since `pow` wants them to match.
+	fn power(&mut self, a: Code, b: Code, lhs: &YuriType, rhs: &YuriType, ty: &YuriType) -> Code {
+		let a = self.splat(a, lhs, ty);
+		let b = self.splat(b, rhs, ty);
+		Code::atom(format!("pow({}, {})", a.text, b.text))
+	}
+
+	/// Runs `f` one level deeper, giving back the statements it wrote instead of adding them to the function.
+	fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, YuriSemanticError>) -> Result<(String, T), YuriSemanticError> {
+		let outer = std::mem::take(&mut self.writer_mut().scope.body);
+		self.writer_mut().scope.depth += 1;
+		let result = f(self);
+		let scope = &mut self.writer_mut().scope;
+		scope.depth -= 1;
+		let inner = std::mem::replace(&mut scope.body, outer);
+		Ok((inner, result?))
+	}
+
+	/// Writes out a constant. Structs and arrays come out as an initializer (see [Emit::initializer]).
+	fn constant(&mut self, value: &ConstValue, ty: &YuriType, location: &Range<usize>) -> Result<Code, YuriSemanticError> {
+		let number = |text: String| if text.starts_with('-') { Code::compound(text) } else { Code::atom(text) };
+		Ok(match value {
+			ConstValue::Bool(b) => Code::atom(b.to_string()),
+			ConstValue::Float(f) if f.is_finite() => number(format!("{f:?}{}", self.suffix(NumberType::Float))),
+			// infinities and NaNs don't have a literal
+			ConstValue::Float(f) => match self.float_bits(f.to_bits()) {
+				Some(text) => Code::atom(text),
+				None => return Err(unsupported(format!("{} doesn't have a way to write the constant {f} (at %)", self.language()), location)),
+			},
+			// the literal would be out of range before it's negated
+			ConstValue::Signed(i32::MIN) => {
+				let suffix = self.suffix(NumberType::Signed);
+				Code::compound(format!("-2147483647{suffix} - 1{suffix}"))
+			}
+			ConstValue::Signed(i) => number(format!("{i}{}", self.suffix(NumberType::Signed))),
+			ConstValue::Unsigned(u) => Code::atom(self.integer(*u, NumberType::Unsigned)),
+			ConstValue::Composite(_) if *ty == YuriType::Unit => Code::atom(""),
+			ConstValue::Composite(components) => {
+				let component_types: Vec<YuriType> = match ty {
+					YuriType::Vector(number_type, size) => vec![YuriType::Scalar(*number_type); size.count() as usize],
+					YuriType::Matrix(size) => vec![YuriType::Vector(NumberType::Float, *size); size.count() as usize],
+					YuriType::Array(inner, _) => vec![inner.as_ref().clone(); components.len()],
+					YuriType::Complex(fields) => fields.iter().map(|f| f.field_type.clone()).collect(),
+					_ => Vec::new(),
+				};
+				let values = components.iter()
+					.zip(&component_types)
+					.map(|(c, ty)| self.constant(c, ty, location).map(|code| code.text))
+					.collect::<Result<Vec<_>, _>>()?;
+				match ty {
+					YuriType::Array(..) | YuriType::Complex(_) => Code::atom(self.initializer(ty, values)),
+					// `vec3(0.0)` reads better than `vec3(0.0, 0.0, 0.0)`
+					YuriType::Vector(..) if self.splats_constants() && values.windows(2).all(|pair| pair[0] == pair[1]) => {
+						Code::atom(format!("{}({})", self.type_name(ty), values[0]))
+					}
+					_ => Code::atom(format!("{}({})", self.type_name(ty), values.join(", "))),
+				}
+			}
+		})
+	}
+
+	/// Declares the global the first time it's used.
+	fn global(&mut self, id: GlobalId) -> Result<String, YuriSemanticError> {
+		if let Some(name) = self.writer().globals.get(&id) {
+			return Ok(name.clone());
+		}
+		let global = &self.program().globals[id];
+		if global.global_type == YuriType::Unit {
+			return Ok(String::new());
+		}
+		let specializable = self.specializable();
+		let (value, kind) = match (global.specialization, global.spec_id) {
+			(_, Some(spec_id)) if specializable => (self.constant(&global.constant, &global.global_type, &global.location)?, GlobalKind::Spec(spec_id)),
+			(Some(_), None) if specializable => (self.specialized(&global.value)?, GlobalKind::Specialized),
+			_ => (self.constant(&global.constant, &global.global_type, &global.location)?, GlobalKind::Constant),
+		};
+		let name = self.global_name(&global.name);
+		let declaration = self.global_declaration(&name, &global.global_type, value, kind);
+		let writer = self.writer_mut();
+		let _ = writeln!(writer.global_declarations, "{declaration}");
+		writer.globals.insert(id, name.clone());
+		Ok(name)
+	}
+
+	/// Writes out a global that depends on a specialization constant, which has to stay a single expression
+	/// so it changes along with the constant. The type checker already made sure it can.
+	fn specialized(&mut self, expr: &TypedExpression) -> Result<Code, YuriSemanticError> {
+		let mut expr = expr;
+		while let TypedExpressionKind::Block { tail, .. } = &expr.kind {
+			expr = tail;
+		}
+		let (statements, value) = self.nested(|this| this.expression(expr))?;
+		if !statements.is_empty() {
+			return Err(YuriSemanticError {
+				error_type: YuriSemanticErrorType::Internal,
+				description: Some(format!("{} generation failed: a specialized global needed statements", self.language())),
+				markers: vec![expr.location.clone()],
+			});
+		}
+		Ok(value)
+	}
+
+	/// Puts the props in a uniform buffer (and the samplers next to it), laid out like the SPIR-V does it,
+	/// and numbered the way SDL wants them. With `viewport_height`, the render target's height goes at the end,
+	/// for flipping `@frag.coord` (see [crate::compile::CompiledShader::viewport_height_offset]).
+	/// Gives back where that is, if so.
+	fn declare_props(&mut self, viewport_height: bool) -> Result<Option<u32>, YuriSemanticError> {
+		let program = self.program();
+		let mut members = Vec::new();
+		for (id, property) in program.properties.iter().enumerate() {
+			if let YuriType::Sampler(_) = property.property_type {
+				let binding = self.writer().samplers;
+				let name = self.declare_sampler(id, &property.name, &property.property_type, binding);
+				let writer = self.writer_mut();
+				writer.props.insert(id, name);
+				writer.samplers += 1;
+			} else if let Some(reason) = self.std140_mismatch(&property.property_type) {
+				return Err(unsupported(
+					format!(
+						"The prop `{}` (at %) can't be a `{}` in {}, since it can't be laid out like std140 ({reason})",
+						property.name, property.property_type, self.language(),
+					),
+					&property.location,
+				));
+			} else {
+				self.find_uniform_structs(&property.property_type);
+				members.push(id);
+			}
+		}
+		if members.is_empty() && !viewport_height {
+			return Ok(None);
+		}
+		let float = YuriType::Scalar(NumberType::Float);
+		let types: Vec<&YuriType> = members.iter()
+			.map(|id| &program.properties[*id].property_type)
+			.chain(viewport_height.then_some(&float))
+			.collect();
+		// the members have their own namespace
+		let mut member_names = HashSet::new();
+		let names: Vec<String> = members.iter()
+			.map(|id| program.properties[*id].name.as_str())
+			.chain(viewport_height.then_some("viewport_height"))
+			.map(|name| unique_member(&mut member_names, self.sanitize(name)))
+			.collect();
+		let block = self.global_name("Props");
+		let variable = self.global_name("props");
+		let fields = self.declare_uniforms(&block, &variable, &types, &names);
+		let writer = self.writer_mut();
+		writer.uniform_buffer = true;
+		for (index, field) in fields.into_iter().enumerate() {
+			match members.get(index) {
+				Some(id) => {
+					writer.props.insert(*id, field);
+				}
+				None => writer.viewport_height = Some(field),
+			}
+		}
+		Ok(viewport_height.then(|| std140_offsets(types.into_iter()).last().copied()).flatten())
+	}
+
+	/// Notes down the complexes inside a prop's type, so they get laid out for the uniform buffer.
+	fn find_uniform_structs(&mut self, ty: &YuriType) {
+		match ty {
+			YuriType::Array(inner, _) => self.find_uniform_structs(inner),
+			YuriType::Complex(fields) => {
+				self.writer_mut().uniform_structs.insert(without_annotations(ty));
+				for field in fields {
+					self.find_uniform_structs(&field.field_type);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn expression(&mut self, expr: &TypedExpression) -> Result<Code, YuriSemanticError> {
+		let ty = &expr.expression_type;
+		Ok(match &expr.kind {
+			TypedExpressionKind::Constant(value) => {
+				let value = self.constant(value, ty, &expr.location)?;
+				match ty {
+					YuriType::Array(..) | YuriType::Complex(_) => self.composite(value, ty),
+					_ => value,
+				}
+			}
+			// unit locals never got declared, there's nothing in them
+			TypedExpressionKind::Local(id) => Code::atom(self.writer().scope.locals.get(id).cloned().unwrap_or_default()),
+			TypedExpressionKind::Global(id) => Code::atom(self.global(*id)?),
+			TypedExpressionKind::Property(id) => Code::atom(self.property(*id)),
+			TypedExpressionKind::BuiltinInput(input) => {
+				check_input_stage(*input, self.stage(), &expr.location)?;
+				let value = self.builtin_input(*input, &expr.location)?;
+				match (input, self.writer().viewport_height.clone()) {
+					// flipped, y = height - y, to get the origin the shader asked for
+					(BuiltinInput::FragCoord, Some(height)) => {
+						let f2 = self.type_name(&YuriType::Vector(NumberType::Float, CompositeSize::Two));
+						Code::atom(format!("{f2}({value}.x, {height} - {value}.y)"))
+					}
+					// the GPU gives a 4-component vector
+					(BuiltinInput::FragCoord, None) => Code::atom(format!("{value}.xy")),
+					_ => Code::atom(value),
+				}
+			}
+			TypedExpressionKind::Call { function, arguments } => {
+				let arguments = arguments.iter()
+					.map(|a| self.expression(a).map(|code| code.text))
+					.collect::<Result<Vec<_>, _>>()?;
+				let call = self.call(*function, arguments);
+				if *ty == YuriType::Unit {
+					self.line(format!("{call};"));
+					Code::atom("")
+				} else {
+					Code::atom(call)
+				}
+			}
+			TypedExpressionKind::Builtin { function, .. } if function.is_assertion() => Code::atom(""),
+			TypedExpressionKind::Builtin { function, arguments } => self.builtin(*function, arguments, &expr.location)?,
+			TypedExpressionKind::Construct(arguments) => {
+				let arguments = self.arguments(arguments)?;
+				Code::atom(format!("{}({arguments})", self.type_name(ty)))
+			}
+			TypedExpressionKind::Complex(arguments) | TypedExpressionKind::Array(arguments) => {
+				self.storable(ty, &expr.location)?;
+				let values = arguments.iter()
+					.map(|a| self.expression(a).map(|code| code.text))
+					.collect::<Result<Vec<_>, _>>()?;
+				let value = self.initializer(ty, values);
+				self.composite(Code::atom(value), ty)
+			}
+			TypedExpressionKind::Convert(operand) => {
+				let value = self.expression(operand)?;
+				self.convert(value, ty)
+			}
+			TypedExpressionKind::Splat(operand) => {
+				let value = self.expression(operand)?;
+				self.splat(value, &operand.expression_type, ty)
+			}
+			TypedExpressionKind::Swizzle { target, components } => {
+				let value = self.expression(target)?;
+				let components: String = components.iter()
+					.map(|c| ['x', 'y', 'z', 'w'][*c as usize])
+					.collect();
+				Code::atom(format!("{}.{components}", value.operand()))
+			}
+			TypedExpressionKind::Field { target, index } => {
+				let value = self.expression(target)?;
+				self.field(value, &target.expression_type, *index as usize)
+			}
+			TypedExpressionKind::Index { target, index } => {
+				let value = self.expression(target)?;
+				let index_value = self.expression(index)?;
+				let dynamic = !matches!(index.kind, TypedExpressionKind::Constant(_));
+				let elements = self.elements(value, &target.expression_type, dynamic);
+				Code::atom(format!("{elements}[{}]", index_value.text))
+			}
+			TypedExpressionKind::Unary { operator, operand } => {
+				let value = self.expression(operand)?;
+				let symbol = match (operator, ty) {
+					(UnaryOperator::Negate, YuriType::Matrix(_)) if !self.matrix_arithmetic() => {
+						return Ok(Code::compound(format!("{} * -1.0{}", value.operand(), self.suffix(NumberType::Float))));
+					}
+					(UnaryOperator::Negate, _) => "-",
+					(UnaryOperator::Not, YuriType::Bool) => "!",
+					(UnaryOperator::Not, _) => "~",
+				};
+				Code::compound(format!("{symbol}{}", value.operand()))
+			}
+			TypedExpressionKind::Binary { operator, lhs, rhs } => self.binary(*operator, lhs, rhs, ty)?,
+			TypedExpressionKind::Block { bindings, tail } => {
+				for (id, value) in bindings {
+					let code = self.expression(value)?;
+					match value.expression_type {
+						YuriType::Unit => {}
+						// samplers can't go in a local, but they can be passed around by name
+						YuriType::Sampler(_) => {
+							self.writer_mut().scope.locals.insert(*id, code.text);
+						}
+						_ => {
+							let name = self.declare_local(*id);
+							self.declare(&name, &value.expression_type, Some(&code.text), false);
+						}
+					}
+				}
+				self.expression(tail)?
+			}
+			TypedExpressionKind::If { condition, block, block_else } => {
+				self.storable(ty, &expr.location)?;
+				let condition = self.expression(condition)?;
+				let (then_statements, then_value) = self.nested(|this| this.expression(block))?;
+				let (else_statements, else_value) = match block_else {
+					Some(block_else) => {
+						let (statements, value) = self.nested(|this| this.expression(block_else))?;
+						(statements, Some(value))
+					}
+					None => (String::new(), None),
+				};
+				if then_statements.is_empty() && else_statements.is_empty() {
+					if *ty == YuriType::Unit {
+						return Ok(Code::atom(""));
+					}
+					if let (Some(else_value), Some(block_else)) = (&else_value, block_else)
+						&& let Some(value) = self.conditional(ty, &condition, [&then_value, else_value], [block, block_else])
+					{
+						return Ok(value);
+					}
+				}
+				// anything else needs a real `if`, with the result going in a variable
+				let result = match ty {
+					YuriType::Unit => None,
+					ty => Some(self.variable("tmp", ty, None)),
+				};
+				let indentation = self.indentation(1);
+				let branch = |statements: String, value: &Code| match &result {
+					Some(result) => format!("{statements}{indentation}{result} = {};\n", value.text),
+					None => statements,
+				};
+				let then_branch = branch(then_statements, &then_value);
+				let else_branch = else_value.as_ref().map(|else_value| branch(else_statements, else_value));
+				self.line(format!("if ({}) {{", condition.text));
+				self.writer_mut().scope.body.push_str(&then_branch);
+				if let Some(else_branch) = else_branch {
+					self.line("} else {");
+					self.writer_mut().scope.body.push_str(&else_branch);
+				}
+				self.line("}");
+				Code::atom(result.unwrap_or_default())
+			}
+			TypedExpressionKind::Loop { index, count, block } => {
+				self.storable(ty, &expr.location)?;
+				let array = self.variable("tmp", ty, None);
+				let elements = self.elements(Code::atom(array.clone()), ty, true);
+				let count = self.integer(*count, NumberType::Unsigned);
+				self.for_each(*index, &YuriType::Scalar(NumberType::Unsigned), count, |this, i| {
+					let value = this.expression(block)?;
+					this.line(format!("{elements}[{i}] = {};", value.text));
+					Ok(())
+				})?;
+				Code::atom(array)
+			}
+			TypedExpressionKind::Fold { accumulator, initial, item, items, block } => {
+				self.storable(ty, &expr.location)?;
+				let initial = self.expression(initial)?;
+				let items_value = self.expression(items)?;
+				let items_value = self.items(items_value, &items.expression_type);
+				let accumulator = self.declare_local(*accumulator);
+				self.declare(&accumulator, ty, Some(&initial.text), true);
+				self.for_each(*item, &items.expression_type, items_value, |this, _| {
+					let value = this.expression(block)?;
+					this.line(format!("{accumulator} = {};", value.text));
+					Ok(())
+				})?;
+				Code::atom(accumulator)
+			}
+			TypedExpressionKind::Map { item, items, block } => {
+				self.storable(ty, &expr.location)?;
+				let items_value = self.expression(items)?;
+				let items_value = self.items(items_value, &items.expression_type);
+				let array = self.variable("tmp", ty, None);
+				let elements = self.elements(Code::atom(array.clone()), ty, true);
+				self.for_each(*item, &items.expression_type, items_value, |this, i| {
+					let value = this.expression(block)?;
+					this.line(format!("{elements}[{i}] = {};", value.text));
+					Ok(())
+				})?;
+				Code::atom(array)
+			}
+		})
+	}
+
+	/// Writes a loop going over an array, or over `0..items` if it's a number, with the item in its local.
+	/// The body gets the name of the counter.
+	fn for_each(
+		&mut self,
+		item: LocalId,
+		items_type: &YuriType,
+		items: String,
+		body: impl FnOnce(&mut Self, &str) -> Result<(), YuriSemanticError>,
+	) -> Result<(), YuriSemanticError> {
+		let (counter_type, counter, limit, statements) = match items_type {
+			YuriType::Array(element_type, length) => {
+				let counter = self.local_name("i");
+				let item = self.declare_local(item);
+				let elements = self.elements(Code::atom(items), items_type, true);
+				let (statements, ()) = self.nested(|this| {
+					this.declare(&item, element_type, Some(&format!("{elements}[{counter}]")), false);
+					body(this, &counter)
+				})?;
+				let limit = self.integer(fixed_length(length) as u32, NumberType::Unsigned);
+				(NumberType::Unsigned, counter, limit, statements)
+			}
+			counter_type => {
+				let item = self.declare_local(item);
+				let (statements, ()) = self.nested(|this| body(this, &item))?;
+				(number_type(counter_type).unwrap_or(NumberType::Unsigned), item, items, statements)
+			}
+		};
+		let zero = self.integer(0, counter_type);
+		let declaration = self.counter(&counter, &YuriType::Scalar(counter_type));
+		self.line(format!("for ({declaration} = {zero}; {counter} < {limit}; {counter}++) {{"));
+		self.writer_mut().scope.body.push_str(&statements);
+		self.line("}");
+		Ok(())
+	}
+
+	fn binary(&mut self, operator: BinaryOperator, lhs: &TypedExpression, rhs: &TypedExpression, ty: &YuriType) -> Result<Code, YuriSemanticError> {
+		use BinaryOperator::*;
+		let a = self.expression(lhs)?;
+		let b = self.expression(rhs)?;
+		if let Some(code) = self.operation(operator, [lhs, rhs], [&a, &b], ty)? {
+			return Ok(code);
+		}
+		let symbol = match operator {
+			Plus => "+",
+			Minus => "-",
+			Times => "*",
+			Divided if matches!(ty, YuriType::Matrix(_)) && !self.matrix_arithmetic() => {
+				let one = format!("1.0{}", self.suffix(NumberType::Float));
+				return Ok(Code::compound(format!("{} * ({one} / {})", a.operand(), b.operand())));
+			}
+			Divided => "/",
+			Modulo => "%",
+			Exponent => return Ok(self.power(a, b, &lhs.expression_type, &rhs.expression_type, ty)),
+			BitAnd => "&",
+			BitOr => "|",
+			BitXor => "^",
+			ShiftLeft => "<<",
+			ShiftRight => ">>",
+			Equal => "==",
+			NotEqual => "!=",
+			Less => "<",
+			LessEqual => "<=",
+			Greater => ">",
+			GreaterEqual => ">=",
+			And => "&&",
+			Or => "||",
+			Xor => "!=",
+			Nor => return Ok(Code::compound(format!("!({} || {})", a.operand(), b.operand()))),
+		};
+		Ok(Code::compound(format!("{} {symbol} {}", a.operand(), b.operand())))
+	}
+
+	/// Writes out a function, after the ones it calls. Samplers get split into a texture and a sampler
+	/// if the language wants them that way.
+	fn function(&mut self, id: FunctionId) -> Result<String, YuriSemanticError> {
+		let function = &self.program().functions[id];
+		self.storable(&function.return_type, &function.location)?;
+		let name = self.global_name(&function.name);
+		self.writer_mut().functions.insert(id, name.clone());
+		self.writer_mut().scope = Scope { function: Some(id), depth: 1, ..Scope::default() };
+		let mut parameters = Vec::new();
+		for argument in &function.arguments {
+			let argument_type = &function.locals[*argument].local_type;
+			let ty = self.type_name(argument_type);
+			let name = self.declare_local(*argument);
+			parameters.push(self.typed(&name, &ty));
+			if let (YuriType::Sampler(_), Some(sampler_type)) = (argument_type, self.sampler_type()) {
+				let sampler = self.local_name(&format!("{name}_sampler"));
+				parameters.push(self.typed(&sampler, sampler_type));
+				self.writer_mut().scope.locals.insert(*argument, format!("{name}, {sampler}"));
+			}
+		}
+		let value = self.expression(&function.body)?;
+		if function.return_type != YuriType::Unit {
+			self.line(format!("return {};", value.text));
+		}
+		let body = std::mem::take(&mut self.writer_mut().scope).body;
+		let signature = self.signature(id, &name, parameters);
+		Ok(format!("{signature} {{\n{body}}}\n"))
+	}
+}
+
+/// Every function the entry point (eventually) calls, including itself.
+/// Each one comes after the ones it calls, since some languages want functions declared before they're used.
+pub(crate) fn call_order(program: &TypedProgram, entry: FunctionId) -> Vec<FunctionId> {
+	fn visit(id: FunctionId, program: &TypedProgram, seen: &mut HashSet<FunctionId>, order: &mut Vec<FunctionId>) {
+		if seen.insert(id) {
+			visit_calls(&program.functions[id].body, program, seen, order);
+			order.push(id);
+		}
+	}
+	fn visit_calls(expr: &TypedExpression, program: &TypedProgram, seen: &mut HashSet<FunctionId>, order: &mut Vec<FunctionId>) {
+		if let TypedExpressionKind::Call { function, .. } = &expr.kind {
+			visit(*function, program, seen, order);
+		}
+		expr.for_each_child(|child| visit_calls(child, program, seen, order));
+	}
+	let mut order = Vec::new();
+	visit(entry, program, &mut HashSet::new(), &mut order);
+	order
+}
+
+/// Joins up the sections of a source file, leaving out the empty ones.
+pub(crate) fn sections<const N: usize>(sections: [String; N]) -> String {
+	sections.into_iter().filter(|section| !section.is_empty()).collect::<Vec<_>>().join("\n")
+}
+
+/// What the tests for the source backends have in common.
+#[cfg(test)]
+pub(crate) mod test_support {
+	use crate::builtin::FragOrigin;
+	use crate::error::{YuriCompileError, YuriSemanticErrorType};
+	use crate::options::CompileOptions;
+	use crate::source::{FileId, SourceMap};
+	use crate::target::Target;
+	use crate::YuriShader;
+
+	/// A shader written out by one of the source backends.
+	pub(crate) trait SourceShader: Sized {
+		fn emit_sources(sources: &mut SourceMap, root: FileId, options: &CompileOptions) -> Result<Vec<Self>, YuriCompileError>;
+		fn text(&self) -> &str;
+		/// The name, samplers, uniform buffers and viewport height offset, which have to match the SPIR-V backend's.
+		fn interface(&self) -> (&str, u32, u32, Option<u32>);
+
+		fn emit_for(source: &str, target: Target) -> Result<Vec<Self>, YuriCompileError> {
+			let mut sources = SourceMap::new();
+			let root = sources.add("<input>", source);
+			Self::emit_sources(&mut sources, root, &CompileOptions::new().with_target(target))
+		}
+
+		fn emit(source: &str) -> Self {
+			Self::emit_for(source, Target::default()).unwrap().remove(0)
+		}
+
+		fn assert_unsupported(source: &str) {
+			let err = Self::emit_for(source, Target::default());
+			assert!(matches!(err, Err(YuriCompileError::Semantic(err)) if err.error_type() == YuriSemanticErrorType::Unsupported));
+		}
+
+		/// Emits `basic.yuri` and checks both shaders have the given lines (ignoring indentation), returning them for anything else.
+		fn emit_basic(vert_lines: &[&str], frag_lines: &[&str]) -> Vec<Self> {
+			let shaders = Self::emit_for(include_str!("../basic.yuri"), Target::default()).unwrap();
+			let names: Vec<&str> = shaders.iter().map(|s| s.interface().0).collect();
+			assert_eq!(names, ["my_vert_main", "my_frag_main"]);
+			assert_lines(&shaders[0], vert_lines);
+			assert_lines(&shaders[1], frag_lines);
+			shaders
+		}
+
+		/// Checks `basic.yuri`, a shader that reads `@frag.coord`, and `source` have the same interface as SPIR-V on each target,
+		/// with the fragment origin flipped so the viewport height comes into it.
+		fn same_interface_as_spirv(source: &str, targets: &[Target]) {
+			let sources = [include_str!("../basic.yuri"), source, "@frag fn main(): f4 { f4(@frag.coord, 0.0, 1.0) }"];
+			for source in sources {
+				for target in targets {
+					let target = target.with_frag_origin(FragOrigin::LowerLeft);
+					let shaders = Self::emit_for(source, target).unwrap();
+					let spirv = YuriShader::compile_with(source, &CompileOptions::new().with_target(target)).unwrap().shaders;
+					assert_eq!(shaders.len(), spirv.len());
+					for (shader, spirv) in shaders.iter().zip(&spirv) {
+						assert_eq!(
+							shader.interface(),
+							(spirv.name.as_str(), spirv.samplers, spirv.uniform_buffers, spirv.viewport_height_offset),
+						);
+					}
+				}
+			}
+		}
+	}
+
+	pub(crate) fn assert_lines(shader: &impl SourceShader, lines: &[&str]) {
+		for line in lines {
+			assert!(shader.text().lines().any(|l| l.trim() == *line), "`{line}` isn't in\n{}", shader.text());
+		}
+	}
+
+	/// Implements [SourceShader] for a backend's shader type, given the [YuriShader] method that emits it.
+	/// They all have the same fields.
+	macro_rules! source_shader {
+		($shader:ty, $emit:ident) => {
+			impl $crate::text::test_support::SourceShader for $shader {
+				fn emit_sources(
+					sources: &mut $crate::source::SourceMap,
+					root: $crate::source::FileId,
+					options: &$crate::options::CompileOptions,
+				) -> Result<Vec<Self>, $crate::error::YuriCompileError> {
+					$crate::YuriShader::$emit(sources, root, options)
+				}
+
+				fn text(&self) -> &str {
+					&self.source
+				}
+
+				fn interface(&self) -> (&str, u32, u32, Option<u32>) {
+					(&self.name, self.samplers, self.uniform_buffers, self.viewport_height_offset)
+				}
+			}
+		};
+	}
+	pub(crate) use source_shader;
+}
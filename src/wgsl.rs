@@ -0,0 +1,733 @@
+//! WGSL generation, for WebGPU (which doesn't take SPIR-V).
+//! Every entry point gets a module of its own, with an entry point called `main` and the same interface as the
+//! SPIR-V version: the same locations, the props in a uniform buffer laid out the same way, and the samplers
+//! in the same groups. WGSL splits each sampler into a texture and a sampler, so Yuri's sampler `n`
+//! becomes the texture at binding `2n` and the sampler at binding `2n + 1`.
+//!
+//! The expressions and statements are written like everywhere else (see [crate::text::Emit]).
+//! WGSL is pickier than GLSL though, so anything it can't express (like a matrix `inverse`, or a prop
+//! it can't lay out the same way) is an [crate::error::YuriSemanticErrorType::Unsupported] error instead of a wrong shader.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+use crate::builtin::{BuiltinFunction, BuiltinInput, FragOrigin};
+use crate::check::{FunctionId, InterfaceSlot, InterfaceVariable, PropId, ShaderStage, TypedExpression, TypedExpressionKind, TypedProgram};
+use crate::compile::{check_props, descriptor_sets, entry_interface, implicit_lod, interpolated, prepare_program, reads_frag_coord, round_up, std140_layout, std140_offsets, without_annotations};
+use crate::error::YuriSemanticError;
+use crate::options::CompileOptions;
+use crate::parse::{BinaryOperator, CompositeSize, NumberType, SamplerDimension, YuriType};
+use crate::text::{Code, Emit, GlobalKind, Scope, Writer, call_order, fixed_length, identifier, sections, unsupported};
+
+/// A single entry point, as a WGSL module. The entry point in it is always called `main`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WgslShader {
+	/// The fully-qualified name of the entry point function, which `main` calls.
+	pub name: String,
+	pub stage: ShaderStage,
+	pub source: String,
+	/// How many of Yuri's samplers the shader uses, each of which is a texture binding and a sampler binding.
+	pub samplers: u32,
+	/// How many uniform buffers the shader uses (zero or one, since every prop goes in the same struct).
+	pub uniform_buffers: u32,
+	/// Where the height of the render target has to go in the uniform buffer, in bytes.
+	/// Same as [crate::compile::CompiledShader::viewport_height_offset].
+	pub viewport_height_offset: Option<u32>,
+}
+
+/// Words WGSL has taken (or reserved for later), plus the builtins the generated code uses,
+/// since a variable with the same name would hide them.
+const RESERVED: &[&str] = &[
+	"alias", "break", "case", "const", "const_assert", "continue", "continuing", "default", "diagnostic", "discard",
+	"else", "enable", "false", "fn", "for", "if", "let", "loop", "override", "requires", "return", "struct",
+	"switch", "true", "var", "while",
+	"NULL", "Self", "abstract", "active", "alignas", "alignof", "as", "asm", "asm_fragment", "async", "attribute",
+	"auto", "await", "become", "binding_array", "cast", "catch", "class", "co_await", "co_return", "co_yield",
+	"coherent", "column_major", "common", "compile", "compile_fragment", "concept", "const_cast", "consteval",
+	"constexpr", "constinit", "crate", "debugger", "decltype", "delete", "demote", "demote_to_helper", "do",
+	"dynamic_cast", "enum", "explicit", "export", "extends", "extern", "external", "fallthrough", "filter", "final",
+	"finally", "friend", "from", "fxgroup", "get", "goto", "groupshared", "highp", "impl", "implements", "import",
+	"inline", "instanceof", "interface", "layout", "lowp", "macro", "macro_rules", "match", "mediump", "meta", "mod",
+	"module", "move", "mut", "mutable", "namespace", "new", "nil", "noexcept", "noinline", "nointerpolation",
+	"noperspective", "null", "nullptr", "of", "operator", "package", "packoffset", "partition", "pass", "patch",
+	"pixelfragment", "precise", "precision", "premerge", "priv", "protected", "pub", "public", "readonly", "ref",
+	"regardless", "register", "reinterpret_cast", "require", "resource", "restrict", "self", "set", "shared",
+	"sizeof", "smooth", "snorm", "static", "static_assert", "static_cast", "std", "subroutine", "super", "target",
+	"template", "this", "thread_local", "throw", "trait", "try", "type", "typedef", "typeid", "typename", "typeof",
+	"union", "unless", "unorm", "unsafe", "unsized", "use", "using", "varying", "virtual", "volatile", "wgsl",
+	"where", "with", "writeonly", "yield",
+	"array", "atomic", "bool", "f16", "f32", "i32", "ptr", "sampler", "sampler_comparison", "u32",
+	"abs", "acos", "asin", "atan", "atan2", "ceil", "clamp", "cos", "cross", "determinant", "distance", "dot", "exp",
+	"exp2", "floor", "fract", "inverseSqrt", "length", "log", "log2", "main", "max", "min", "mix", "normalize", "pow",
+	"reflect", "round", "select", "sign", "sin", "smoothstep", "sqrt", "step", "tan", "textureSample",
+	"textureSampleLevel", "transpose", "trunc",
+];
+
+fn is_reserved(name: &str) -> bool {
+	// the vector, matrix and texture types, like `vec3f`, `mat2x3` or `texture_2d`
+	let type_name = ["vec", "mat"].iter()
+		.filter_map(|prefix| name.strip_prefix(prefix))
+		.any(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+	type_name || name.starts_with("texture_") || RESERVED.contains(&name)
+}
+
+/// Turns a Yuri name (which might be qualified, like `lighting.shade`) into something WGSL accepts.
+fn sanitize(name: &str) -> String {
+	let mut sanitized = identifier(name);
+	if is_reserved(&sanitized) {
+		sanitized.push('_');
+	}
+	sanitized
+}
+
+/// Whether the expression calls anything, which a `select` would do even when it isn't picked.
+fn has_calls(expr: &TypedExpression) -> bool {
+	let mut found = matches!(expr.kind, TypedExpressionKind::Call { .. } | TypedExpressionKind::Builtin { function: BuiltinFunction::Sample, .. });
+	expr.for_each_child(|child| found = found || has_calls(child));
+	found
+}
+
+/// Size and alignment of a type in a WGSL uniform buffer, once the structs have their `@align`s and `@size`s
+/// (see [member_attributes]). `None` if it can't be made to match std140: WGSL doesn't let array elements
+/// in a uniform buffer be less than 16 bytes apart, and the columns of a 2x2 matrix are always 8 bytes apart.
+fn uniform_layout(ty: &YuriType) -> Option<(u32, u32)> {
+	Some(match ty {
+		YuriType::Scalar(_) => (4, 4),
+		YuriType::Vector(_, CompositeSize::Two) => (8, 8),
+		YuriType::Vector(_, CompositeSize::Three) => (12, 16),
+		YuriType::Vector(_, CompositeSize::Four) => (16, 16),
+		YuriType::Matrix(CompositeSize::Two) => return None,
+		YuriType::Matrix(size) => (16 * size.count(), 16),
+		YuriType::Array(inner, length) => {
+			let (size, alignment) = uniform_layout(inner)?;
+			let stride = round_up(size, alignment);
+			if !stride.is_multiple_of(16) {
+				return None;
+			}
+			(stride * fixed_length(length) as u32, alignment)
+		}
+		YuriType::Complex(fields) => {
+			let mut alignment = 4;
+			for field in fields {
+				let (_, field_alignment) = uniform_layout(&field.field_type)?;
+				alignment = alignment.max(match field.field_type {
+					YuriType::Array(..) | YuriType::Complex(_) => field_alignment.max(16),
+					_ => field_alignment,
+				});
+			}
+			(std140_layout(ty)?.0, alignment)
+		}
+		_ => return None,
+	})
+}
+
+/// The attributes each member of a struct in the uniform buffer needs to end up where std140 puts it.
+/// Arrays and structs start on 16 bytes, and structs take up a multiple of 16 (if `pad` is set).
+fn member_attributes(types: &[&YuriType], pad: bool) -> Vec<String> {
+	let mut attributes = Vec::new();
+	let mut end = 0;
+	let mut struct_alignment = 4;
+	for ty in types {
+		let (size, mut alignment) = uniform_layout(ty).unwrap();
+		let mut attribute = String::new();
+		if matches!(ty, YuriType::Array(..) | YuriType::Complex(_)) && alignment < 16 {
+			attribute.push_str("@align(16) ");
+			alignment = 16;
+		}
+		end = round_up(end, alignment) + size;
+		struct_alignment = struct_alignment.max(alignment);
+		attributes.push(attribute);
+	}
+	let offsets = std140_offsets(types.iter().copied());
+	let (Some(last), Some(offset)) = (attributes.last_mut(), offsets.last()) else {
+		return attributes;
+	};
+	let size = round_up(end, 16);
+	if pad && round_up(end, struct_alignment) != size {
+		let _ = write!(last, "@size({}) ", size - offset);
+	}
+	attributes
+}
+
+struct Emitter<'a> {
+	program: &'a TypedProgram,
+	stage: ShaderStage,
+	structs: HashMap<YuriType, String>,
+	/// The private variables `main` copies the builtin inputs into, since only it gets to see them.
+	builtins: Vec<(BuiltinInput, String)>,
+	/// Whether anything samples with implicit derivatives.
+	samples: bool,
+	writer: Writer,
+}
+
+impl<'a> Emit<'a> for Emitter<'a> {
+	fn writer(&self) -> &Writer {
+		&self.writer
+	}
+
+	fn writer_mut(&mut self) -> &mut Writer {
+		&mut self.writer
+	}
+
+	fn program(&self) -> &'a TypedProgram {
+		self.program
+	}
+
+	fn stage(&self) -> ShaderStage {
+		self.stage
+	}
+
+	fn language(&self) -> &'static str {
+		"WGSL"
+	}
+
+	fn sanitize(&self, name: &str) -> String {
+		sanitize(name)
+	}
+
+	fn type_name(&mut self, ty: &YuriType) -> String {
+		let suffix = |number_type: &NumberType| match number_type {
+			NumberType::Float => "f",
+			NumberType::Signed => "i",
+			NumberType::Unsigned => "u",
+		};
+		match ty {
+			YuriType::Unit => String::new(),
+			YuriType::Bool => "bool".to_string(),
+			YuriType::Scalar(NumberType::Float) => "f32".to_string(),
+			YuriType::Scalar(NumberType::Signed) => "i32".to_string(),
+			YuriType::Scalar(NumberType::Unsigned) => "u32".to_string(),
+			YuriType::Vector(number_type, size) => format!("vec{}{}", size.count(), suffix(number_type)),
+			YuriType::Matrix(size) => format!("mat{0}x{0}f", size.count()),
+			YuriType::Sampler(dimension) => match dimension {
+				SamplerDimension::One => "texture_1d<f32>",
+				SamplerDimension::Two => "texture_2d<f32>",
+				SamplerDimension::Three => "texture_3d<f32>",
+				SamplerDimension::Cube => "texture_cube<f32>",
+			}.to_string(),
+			YuriType::Array(inner, length) => format!("array<{}, {}>", self.type_name(inner), fixed_length(length)),
+			YuriType::Complex(_) => self.struct_name(ty),
+		}
+	}
+
+	fn typed(&self, name: &str, ty: &str) -> String {
+		format!("{name}: {ty}")
+	}
+
+	/// Only variables can be assigned to (or indexed by anything but a constant), everything else is a `let`.
+	fn declaration(&mut self, name: &str, ty: &YuriType, value: Option<&str>, mutable: bool) -> String {
+		match (value, mutable) {
+			(Some(value), true) => format!("var {name} = {value};"),
+			(Some(value), false) => format!("let {name} = {value};"),
+			(None, _) => format!("var {name}: {};", self.type_name(ty)),
+		}
+	}
+
+	fn counter(&mut self, name: &str, _ty: &YuriType) -> String {
+		format!("var {name}")
+	}
+
+	fn suffix(&self, number_type: NumberType) -> &'static str {
+		match number_type {
+			NumberType::Float => "f",
+			NumberType::Signed => "i",
+			NumberType::Unsigned => "u",
+		}
+	}
+
+	/// Even `bitcast` can't make one, constant expressions that overflow are errors.
+	fn float_bits(&self, _bits: u32) -> Option<String> {
+		None
+	}
+
+	fn specializable(&self) -> bool {
+		true
+	}
+
+	/// Specialization constants are `override`s, and so is anything that depends on one, if it can be.
+	fn global_declaration(&mut self, name: &str, ty: &YuriType, value: Code, kind: GlobalKind) -> String {
+		let type_name = self.type_name(ty);
+		let value = value.text;
+		match kind {
+			GlobalKind::Constant => format!("const {name}: {type_name} = {value};"),
+			GlobalKind::Spec(spec_id) => format!("@id({spec_id}) override {name}: {type_name} = {value};"),
+			// overrides can only be scalars, but private variables can start out as anything
+			GlobalKind::Specialized => match ty {
+				YuriType::Scalar(_) | YuriType::Bool => format!("override {name}: {type_name} = {value};"),
+				_ => format!("var<private> {name}: {type_name} = {value};"),
+			},
+		}
+	}
+
+	fn splat(&mut self, value: Code, from: &YuriType, to: &YuriType) -> Code {
+		match (from, to) {
+			(YuriType::Scalar(_), YuriType::Vector(..)) => Code::atom(format!("{}({})", self.type_name(to), value.text)),
+			_ => value,
+		}
+	}
+
+	/// `select` works out both sides, so it's only for numbers and vectors that are cheap to get.
+	fn conditional(&mut self, ty: &YuriType, condition: &Code, values: [&Code; 2], branches: [&TypedExpression; 2]) -> Option<Code> {
+		let selectable = matches!(ty, YuriType::Bool | YuriType::Scalar(_) | YuriType::Vector(..))
+			&& !branches.into_iter().any(has_calls);
+		selectable.then(|| Code::atom(format!("select({}, {}, {})", values[1].text, values[0].text, condition.text)))
+	}
+
+	/// WGSL's `%` follows the sign of the left side like Yuri's, but the shift amount has to be unsigned,
+	/// with as many components as the left side.
+	fn operation(
+		&mut self,
+		operator: BinaryOperator,
+		[_, rhs]: [&TypedExpression; 2],
+		[a, b]: [&Code; 2],
+		ty: &YuriType,
+	) -> Result<Option<Code>, YuriSemanticError> {
+		let symbol = match operator {
+			BinaryOperator::ShiftLeft => "<<",
+			BinaryOperator::ShiftRight => ">>",
+			_ => return Ok(None),
+		};
+		let unsigned = match &rhs.expression_type {
+			YuriType::Vector(_, size) => YuriType::Vector(NumberType::Unsigned, *size),
+			_ => YuriType::Scalar(NumberType::Unsigned),
+		};
+		let converted = match &rhs.kind {
+			TypedExpressionKind::Constant(value) => value.convert(&unsigned),
+			_ => None,
+		};
+		let b = match converted {
+			_ if rhs.expression_type == unsigned => b.clone(),
+			Some(value) => self.constant(&value, &unsigned, &rhs.location)?,
+			None => Code::atom(format!("{}({})", self.type_name(&unsigned), b.text)),
+		};
+		let b = match ty {
+			YuriType::Vector(_, size) => self.splat(b, &unsigned, &YuriType::Vector(NumberType::Unsigned, *size)),
+			_ => b,
+		};
+		Ok(Some(Code::compound(format!("{} {symbol} {}", a.operand(), b.operand()))))
+	}
+
+	fn builtin(&mut self, function: BuiltinFunction, arguments: &[TypedExpression], location: &Range<usize>) -> Result<Code, YuriSemanticError> {
+		use BuiltinFunction::*;
+		let name = match function {
+			Inverse => return Err(self.missing(function, location)),
+			SmoothStep => {
+				// the edges can be scalars in Yuri, but not in WGSL
+				let arguments = self.splatted(arguments, &arguments[2].expression_type)?;
+				return Ok(Code::atom(format!("smoothstep({})", arguments.join(", "))));
+			}
+			Sample if implicit_lod(self.stage) => {
+				self.samples = true;
+				"textureSample"
+			}
+			Sample => {
+				if arguments[0].expression_type == YuriType::Sampler(SamplerDimension::One) {
+					return Err(unsupported("WGSL can't sample a `sampler1` (at %) outside of a fragment shader".to_string(), location));
+				}
+				let arguments = self.arguments(arguments)?;
+				return Ok(Code::atom(format!("textureSampleLevel({arguments}, 0.0f)")));
+			}
+			Atan2 => "atan2",
+			InverseSqrt => "inverseSqrt",
+			// WGSL's `round` already goes to the even number in halfway cases, same as everywhere else
+			other => other.name(),
+		};
+		let arguments = self.arguments(arguments)?;
+		Ok(Code::atom(format!("{name}({arguments})")))
+	}
+
+	/// Builtin inputs are only parameters of the entry point, so `main` copies them into private variables.
+	fn builtin_input(&mut self, input: BuiltinInput, _location: &Range<usize>) -> Result<String, YuriSemanticError> {
+		Ok(match self.builtins.iter().find(|(i, _)| *i == input) {
+			Some((_, variable)) => variable.clone(),
+			None => {
+				let (name, ty) = builtin_variable(input);
+				let variable = self.global_name(name);
+				let _ = writeln!(self.writer.global_declarations, "var<private> {variable}: {ty};");
+				self.builtins.push((input, variable.clone()));
+				variable
+			}
+		})
+	}
+
+	/// Each sampler is a texture at binding `2n` and a sampler at `2n + 1`.
+	fn declare_sampler(&mut self, _id: PropId, name: &str, ty: &YuriType, binding: u32) -> String {
+		let (sampler_group, _) = descriptor_sets(self.stage);
+		let ty = self.type_name(ty);
+		let texture = self.global_name(name);
+		let sampler = self.global_name(&format!("{name}_sampler"));
+		let _ = writeln!(self.writer.resource_declarations, "@group({sampler_group}) @binding({}) var {texture}: {ty};", 2 * binding);
+		let _ = writeln!(self.writer.resource_declarations, "@group({sampler_group}) @binding({}) var {sampler}: sampler;", 2 * binding + 1);
+		format!("{texture}, {sampler}")
+	}
+
+	fn std140_mismatch(&self, ty: &YuriType) -> Option<&'static str> {
+		uniform_layout(ty).is_none().then_some(
+			"array elements in a uniform buffer have to be a multiple of 16 bytes apart, and `m2` columns are only 8 bytes apart",
+		)
+	}
+
+	fn declare_uniforms(&mut self, block: &str, variable: &str, types: &[&YuriType], names: &[String]) -> Vec<String> {
+		let (_, uniform_group) = descriptor_sets(self.stage);
+		let attributes = member_attributes(types, false);
+		let mut declaration = String::new();
+		for ((ty, name), attribute) in types.iter().zip(names).zip(&attributes) {
+			let ty = self.type_name(ty);
+			let _ = writeln!(declaration, "\t{attribute}{name}: {ty},");
+		}
+		let _ = writeln!(self.writer.struct_declarations, "struct {block} {{\n{declaration}}}");
+		let _ = writeln!(self.writer.resource_declarations, "@group({uniform_group}) @binding(0) var<uniform> {variable}: {block};");
+		names.iter().map(|name| format!("{variable}.{name}")).collect()
+	}
+
+	fn sampler_type(&self) -> Option<&'static str> {
+		Some("sampler")
+	}
+
+	fn signature(&mut self, id: FunctionId, name: &str, parameters: Vec<String>) -> String {
+		let return_type = match &self.program.functions[id].return_type {
+			YuriType::Unit => String::new(),
+			ty => format!(" -> {}", self.type_name(ty)),
+		};
+		format!("fn {name}({}){return_type}", parameters.join(", "))
+	}
+
+	/// There's no negating or dividing a matrix.
+	fn matrix_arithmetic(&self) -> bool {
+		false
+	}
+
+	/// Arrays and matrices have to be in a variable to be indexed by anything but a constant.
+	fn elements(&mut self, value: Code, ty: &YuriType, dynamic: bool) -> String {
+		match ty {
+			YuriType::Array(..) | YuriType::Matrix(_) if dynamic => self.spill(value, ty),
+			_ => value.operand(),
+		}
+	}
+
+	/// Arrays get indexed by the counter, so they need to be in a variable.
+	fn items(&mut self, items: Code, ty: &YuriType) -> String {
+		match ty {
+			YuriType::Array(..) => self.spill(items, ty),
+			_ => self.hoist(items, ty),
+		}
+	}
+}
+
+impl<'a> Emitter<'a> {
+	fn new(program: &'a TypedProgram, stage: ShaderStage) -> Self {
+		Self {
+			program,
+			stage,
+			structs: HashMap::new(),
+			builtins: Vec::new(),
+			samples: false,
+			writer: Writer::default(),
+		}
+	}
+
+	/// Puts the value in a variable, unless it's in one already.
+	fn spill(&mut self, value: Code, ty: &YuriType) -> String {
+		if self.writer.scope.variables.contains(&value.text) {
+			return value.text;
+		}
+		self.variable("tmp", ty, Some(&value.text))
+	}
+
+	/// Complex types are structural, but WGSL wants a struct declared for each one.
+	fn struct_name(&mut self, ty: &YuriType) -> String {
+		let ty = without_annotations(ty);
+		if let Some(name) = self.structs.get(&ty) {
+			return name.clone();
+		}
+		let YuriType::Complex(fields) = &ty else {
+			unreachable!("only complexes are structs");
+		};
+		let attributes = if self.writer.uniform_structs.contains(&ty) {
+			member_attributes(&fields.iter().map(|f| &f.field_type).collect::<Vec<_>>(), true)
+		} else {
+			vec![String::new(); fields.len()]
+		};
+		let mut members = String::new();
+		for (field, attribute) in fields.iter().zip(attributes) {
+			let field_type = self.type_name(&field.field_type);
+			let _ = writeln!(members, "\t{attribute}{}: {field_type},", sanitize(&field.name));
+		}
+		let name = self.global_name(&format!("Complex{}", self.structs.len()));
+		let _ = writeln!(self.writer.struct_declarations, "struct {name} {{\n{members}}}");
+		self.structs.insert(ty, name.clone());
+		name
+	}
+}
+
+/// What the private variable for a builtin input is called, and its type.
+fn builtin_variable(input: BuiltinInput) -> (&'static str, &'static str) {
+	match input {
+		BuiltinInput::FragCoord => ("frag_coord", "vec4f"),
+		BuiltinInput::FrontFacing => ("front_facing", "bool"),
+		BuiltinInput::VertexIndex => ("vertex_index", "u32"),
+		BuiltinInput::InstanceIndex => ("instance_index", "u32"),
+	}
+}
+
+/// The `@builtin` for a builtin input.
+fn builtin_attribute(input: BuiltinInput) -> &'static str {
+	match input {
+		BuiltinInput::FragCoord => "position",
+		BuiltinInput::FrontFacing => "front_facing",
+		BuiltinInput::VertexIndex => "vertex_index",
+		BuiltinInput::InstanceIndex => "instance_index",
+	}
+}
+
+/// WGSL wants flat inputs marked on both sides.
+fn interpolation(ty: &YuriType) -> &'static str {
+	if interpolated(ty) { "" } else { "@interpolate(flat) " }
+}
+
+/// Writes a single entry point as WGSL, with a `main` that takes the inputs, calls it and returns the outputs.
+pub fn emit_entry_point(program: &TypedProgram, entry: FunctionId, options: &CompileOptions) -> Result<WgslShader, YuriSemanticError> {
+	check_props(program)?;
+	let function = &program.functions[entry];
+	let (stage, interface) = entry_interface(function)?;
+	let order = call_order(program, entry);
+	// WebGPU puts the origin in the upper left, like Vulkan
+	let origin = function.frag_origin.unwrap_or(options.target.frag_origin());
+	let flip = origin != FragOrigin::UpperLeft && reads_frag_coord(program, stage, &order);
+	let mut emitter = Emitter::new(program, stage);
+	emitter.writer.names.insert("main".to_string());
+	let viewport_height_offset = emitter.declare_props(flip)?;
+
+	let mut functions = Vec::new();
+	for id in &order {
+		functions.push(emitter.function(*id)?);
+	}
+	emitter.writer.scope = Scope { depth: 1, ..Scope::default() };
+	let mut parameters = Vec::new();
+	let mut arguments = Vec::new();
+	for input in &interface.inputs {
+		let ty = emitter.type_name(&input.variable_type);
+		let name = emitter.local_name(&input.name);
+		let InterfaceSlot::Location(location) = input.slot else {
+			unreachable!("inputs always have a location");
+		};
+		let interpolation = if stage == ShaderStage::Fragment { interpolation(&input.variable_type) } else { "" };
+		parameters.push(format!("@location({location}) {interpolation}{name}: {ty}"));
+		arguments.push(name);
+	}
+	for (input, variable) in emitter.builtins.clone() {
+		let attribute = builtin_attribute(input);
+		let (_, ty) = builtin_variable(input);
+		let name = emitter.local_name(attribute);
+		parameters.push(format!("@builtin({attribute}) {name}: {ty}"));
+		emitter.line(format!("{variable} = {name};"));
+	}
+	let output_attribute = |output: &InterfaceVariable| match output.slot {
+		InterfaceSlot::Position => "@builtin(position) ".to_string(),
+		InterfaceSlot::Location(location) if stage == ShaderStage::Vertex => {
+			format!("@location({location}) {}", interpolation(&output.variable_type))
+		}
+		InterfaceSlot::Location(location) => format!("@location({location}) "),
+	};
+	let call = emitter.call(entry, arguments);
+	let mut return_type = String::new();
+	if interface.complex_output {
+		let YuriType::Complex(fields) = &function.return_type else {
+			unreachable!("complex outputs come from complexes");
+		};
+		let name = emitter.global_name("Output");
+		let mut members = String::new();
+		for (output, field) in interface.outputs.iter().zip(fields) {
+			let ty = emitter.type_name(&output.variable_type);
+			let _ = writeln!(members, "\t{}{}: {ty},", output_attribute(output), sanitize(&field.name));
+		}
+		let _ = writeln!(emitter.writer.struct_declarations, "struct {name} {{\n{members}}}");
+		let result = emitter.local_name("result");
+		emitter.line(format!("let {result} = {call};"));
+		let values: Vec<String> = fields.iter()
+			.map(|field| format!("{result}.{}", sanitize(&field.name)))
+			.collect();
+		emitter.line(format!("return {name}({});", values.join(", ")));
+		return_type = format!(" -> {name}");
+	} else if let Some(output) = interface.outputs.first() {
+		let ty = emitter.type_name(&output.variable_type);
+		return_type = format!(" -> {}{ty}", output_attribute(output));
+		emitter.line(format!("return {call};"));
+	} else {
+		emitter.line(format!("{call};"));
+	}
+	let stage_attribute = match stage {
+		ShaderStage::Vertex => "@vertex",
+		ShaderStage::Fragment => "@fragment",
+	};
+	functions.push(format!("{stage_attribute}\nfn main({}){return_type} {{\n{}}}\n", parameters.join(", "), emitter.writer.scope.body));
+
+	// sampling in non-uniform control flow is fine everywhere else, derivatives there are just undefined
+	let directives = if emitter.samples { "diagnostic(off, derivative_uniformity);\n" } else { "" };
+	Ok(WgslShader {
+		name: function.name.clone(),
+		stage,
+		source: sections([
+			directives.to_string(),
+			emitter.writer.struct_declarations,
+			emitter.writer.resource_declarations,
+			emitter.writer.global_declarations,
+			functions.join("\n"),
+		]),
+		samplers: emitter.writer.samplers,
+		uniform_buffers: emitter.writer.uniform_buffer as u32,
+		viewport_height_offset,
+	})
+}
+
+/// Writes every entry point in the program (or just the ones the options ask for) as WGSL,
+/// after folding whatever can be worked out ahead of time.
+pub fn emit_program(program: &TypedProgram, options: &CompileOptions) -> Result<Vec<WgslShader>, YuriSemanticError> {
+	let (program, entry_points) = prepare_program(program, options)?;
+	entry_points.into_iter()
+		.map(|id| emit_entry_point(&program, id, options))
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use crate::target::Target;
+	use crate::text::test_support::{assert_lines, source_shader, SourceShader};
+	use crate::wgsl::WgslShader;
+
+	source_shader!(WgslShader, wgsl_sources);
+
+	#[test]
+	fn emit_basic() {
+		WgslShader::emit_basic(&[
+			"@group(1) @binding(0) var<uniform> props: Props;",
+			"transform: mat4x4f,",
+			"@builtin(position) out: vec4f,",
+			// `@vert.pos` doesn't take up a location
+			"@location(0) pos: vec3f,",
+			"@location(1) coord: vec2f,",
+			"@vertex",
+			"fn main(@location(0) pos: vec3f, @location(1) coord: vec2f) -> Output {",
+			"return Output(result.out, result.pos, result.coord);",
+		], &[
+			"@group(3) @binding(0) var<uniform> props: Props;",
+			"@fragment",
+			"fn main(@location(0) pos: vec3f, @location(1) coord: vec2f) -> @location(0) vec4f {",
+		]);
+	}
+
+	#[test]
+	fn same_interface_as_spirv() {
+		WgslShader::same_interface_as_spirv("
+			prop tint: f3;
+			prop lights: <| color: f3, strength: f |>[2];
+			prop block: <| a: f, b: f2 |>;
+			prop weights: <| w: f |>[3];
+			prop tex: sampler2;
+			prop other: sampler4;
+			prop last: m3;
+			@frag(origin = lower_left)
+			fn main(coord: f2): f4 { let column = last[0]; sample(tex, coord) * f4(@frag.coord, tint.x, column.x) }
+		", &[Target::default()]);
+	}
+
+	#[test]
+	fn uniform_layout() {
+		let shader = WgslShader::emit("
+			prop tint: f3;
+			prop block: <| a: f, b: f2 |>;
+			prop weights: <| w: f |>[3];
+			prop tex: sampler2;
+			@frag
+			fn main(coord: f2): f4 { let w = weights[1]; sample(tex, coord) * f4(tint, block.a + w.w) }
+		");
+		assert_lines(&shader, &[
+			"@group(2) @binding(0) var tex: texture_2d<f32>;",
+			"@group(2) @binding(1) var tex_sampler: sampler;",
+			// std140 starts structs and arrays on 16 bytes, and rounds structs up to 16
+			"@align(16) block: Complex0,",
+			"@align(16) weights: array<Complex1, 3>,",
+			"@size(16) w: f32,",
+			"return textureSample(tex, tex_sampler, coord) * vec4f(props.tint, props.block.a + w.w);",
+		]);
+		// anything that can't match std140 is an error, not a different layout
+		WgslShader::assert_unsupported("prop weights: f[4]; @frag fn main(): f4 { f4(weights[0]) }");
+		WgslShader::assert_unsupported("prop m: m2; @frag fn main(): f4 { f4(m[0], m[1]) }");
+	}
+
+	#[test]
+	fn statements() {
+		let shader = WgslShader::emit("
+			prop tex: sampler2;
+			prop mats: m3[2];
+			@spec(3) let SAMPLES: u = 8;
+			let HALF = SAMPLES / 2;
+			fn shade(coord: f2, n: u, flip: bool, s: sampler2): f4 {
+				let total = fold sum = f4(0.0), k: 3 { sum + sample(s, coord * f(k)) };
+				let steps = loop k: 4u { k * HALF };
+				let pick = if n > 3u { let doubled = n * 2u; doubled % 5u } else { n };
+				let x = if flip { coord.x } else { coord.y };
+				let m = -(mats[n] / 2.0);
+				let column = m[0];
+				let shifted = (n << 2) >> i(n);
+				total * f4(x % 1.5, f(steps[pick] + shifted), column.x, 1.0)
+			}
+			@frag
+			fn main(coord: f2, n: u): f4 { shade(coord, n, true, tex) }
+		");
+		assert_lines(&shader, &[
+			"diagnostic(off, derivative_uniformity);",
+			"@id(3) override SAMPLES: u32 = 8u;",
+			"override HALF: u32 = SAMPLES / 2u;",
+			// a sampler is a texture and a sampler
+			"fn shade(coord: vec2f, n: u32, flip: bool, s: texture_2d<f32>, s_sampler: sampler) -> vec4f {",
+			"var sum = vec4f(0.0f);",
+			"for (var k = 0i; k < 3i; k++) {",
+			"sum = sum + textureSample(s, s_sampler, coord * f32(k));",
+			"var tmp: array<u32, 4>;",
+			"for (var k_1 = 0u; k_1 < 4u; k_1++) {",
+			"tmp[k_1] = k_1 * HALF;",
+			"if (n > 3u) {",
+			"tmp_1 = doubled % 5u;",
+			"let x = select(coord.y, coord.x, flip);",
+			// indexing with anything but a constant needs a variable
+			"var tmp_2 = props.mats;",
+			"let m = (tmp_2[n] * (1.0f / 2.0f)) * -1.0f;",
+			"let shifted = (n << 2u) >> u32(i32(n));",
+			"return shade(coord, n, true, tex, tex_sampler);",
+		]);
+	}
+
+	#[test]
+	fn builtin_inputs() {
+		let shader = WgslShader::emit("
+			@frag(origin = lower_left)
+			fn main(id: u): f4 { f4(@frag.coord, f(id), if @frag.front_facing { 1.0 } else { 0.0 }) }
+		");
+		assert_lines(&shader, &[
+			"viewport_height: f32,",
+			"var<private> frag_coord: vec4f;",
+			"var<private> front_facing: bool;",
+			"return vec4f(vec2f(frag_coord.x, props.viewport_height - frag_coord.y), f32(id), select(0.0f, 1.0f, front_facing));",
+			"fn main(@location(0) @interpolate(flat) id: u32, @builtin(position) position: vec4f, @builtin(front_facing) front_facing_1: bool) -> @location(0) vec4f {",
+			"frag_coord = position;",
+			"front_facing = front_facing_1;",
+		]);
+		let shader = WgslShader::emit("@vert fn main(): <| @vert.pos pos: f4, id: u |> { <| pos = f4(f(@vert.instance)), id = @vert.index |> }");
+		assert_lines(&shader, &[
+			"@location(0) @interpolate(flat) id: u32,",
+			"fn main(@builtin(instance_index) instance_index_1: u32, @builtin(vertex_index) vertex_index_1: u32) -> Output {",
+		]);
+	}
+
+	#[test]
+	fn unsupported() {
+		WgslShader::assert_unsupported("@vert fn main(v: f4): f4 { let m = inverse(m4(v, v, v, v)); m[0] }");
+		WgslShader::assert_unsupported("@frag fn main(): f4 { f4(0.0 / 0.0) }");
+		WgslShader::assert_unsupported("prop t: sampler1; @vert fn main(): f4 { sample(t, 0.5) }");
+		WgslShader::assert_unsupported("
+			prop a: sampler2;
+			prop b: sampler2;
+			@frag fn main(c: f2, n: i): f4 { let s = if n > 0 { a } else { b }; sample(s, c) }
+		");
+	}
+}
@@ -0,0 +1,24 @@
+// Starts yuri-lsp for .yuri files. The grammar and brackets work without it, but diagnostics, hovers,
+// go-to-definition and completion all come from the server.
+const vscode = require("vscode");
+const { LanguageClient } = require("vscode-languageclient/node");
+
+let client;
+
+function activate(context) {
+	const command = vscode.workspace.getConfiguration("yuri").get("server.path", "yuri-lsp");
+	client = new LanguageClient(
+		"yuri",
+		"Yuri",
+		{ command, args: [] },
+		{ documentSelector: [{ scheme: "file", language: "yuri" }] },
+	);
+	client.start();
+	context.subscriptions.push(client);
+}
+
+function deactivate() {
+	return client?.stop();
+}
+
+module.exports = { activate, deactivate };
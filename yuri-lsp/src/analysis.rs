@@ -0,0 +1,673 @@
+//! Everything the server knows about one document, worked out by running the compiler up to (but not including) codegen.
+//! Locations here are offsets into the [SourceMap], like everywhere else in the compiler.
+//! The document is always the first file in the map, so offsets into it are offsets into the document.
+use std::collections::HashMap;
+use std::ops::Range;
+use yuri::builtin::{BuiltinFunction, BuiltinInput};
+use yuri::check::{TypedExpression, TypedExpressionKind, TypedFunction, TypedLocal, TypedProgram};
+use yuri::error::YuriCompileError;
+use yuri::import::{ResolvedImports, SourceLoader};
+use yuri::lex::{Keyword, Lexer, YuriTokenType};
+use yuri::parse::{CompositeSize, YuriModule, YuriType};
+use yuri::resolve::{NameResolution, ResolvedName, SymbolId, SymbolKind, ROOT_MODULE};
+use yuri::source::{FileId, SourceFile, SourceMap};
+use yuri::YuriShader;
+
+/// A compiler error, pinned to somewhere in the document.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+	pub location: Range<usize>,
+	pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CompletionKind {
+	Keyword,
+	Builtin,
+	Property,
+	Global,
+	Function,
+	Module,
+	Local,
+	Field,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Completion {
+	pub label: String,
+	pub kind: CompletionKind,
+	/// The type or signature, if there is one.
+	pub detail: Option<String>,
+	/// What the label replaces, since names can have dots in them and editors don't know that.
+	pub replace: Range<usize>,
+}
+
+/// What a token is, for highlighting that knows more than the grammar does.
+/// The names are the standard LSP ones.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SemanticKind {
+	Keyword,
+	Type,
+	Number,
+	Operator,
+	Decorator,
+	Namespace,
+	Property,
+	Variable,
+	Parameter,
+	Function,
+}
+
+impl SemanticKind {
+	pub const ALL: [SemanticKind; 10] = { use SemanticKind::*; [
+		Keyword,
+		Type,
+		Number,
+		Operator,
+		Decorator,
+		Namespace,
+		Property,
+		Variable,
+		Parameter,
+		Function,
+	] };
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			SemanticKind::Keyword 		=> "keyword",
+			SemanticKind::Type 			=> "type",
+			SemanticKind::Number 		=> "number",
+			SemanticKind::Operator 		=> "operator",
+			SemanticKind::Decorator 	=> "decorator",
+			SemanticKind::Namespace 	=> "namespace",
+			SemanticKind::Property 		=> "property",
+			SemanticKind::Variable 		=> "variable",
+			SemanticKind::Parameter 	=> "parameter",
+			SemanticKind::Function 		=> "function",
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SemanticModifier {
+	Declaration,
+	Readonly,
+	DefaultLibrary,
+	/// Functions annotated with `@vert` or `@frag`, which isn't a standard modifier.
+	EntryPoint,
+}
+
+impl SemanticModifier {
+	pub const ALL: [SemanticModifier; 4] = [
+		SemanticModifier::Declaration,
+		SemanticModifier::Readonly,
+		SemanticModifier::DefaultLibrary,
+		SemanticModifier::EntryPoint,
+	];
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			SemanticModifier::Declaration 		=> "declaration",
+			SemanticModifier::Readonly 			=> "readonly",
+			SemanticModifier::DefaultLibrary 	=> "defaultLibrary",
+			SemanticModifier::EntryPoint 		=> "entryPoint",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SemanticToken {
+	pub location: Range<usize>,
+	pub kind: SemanticKind,
+	pub modifiers: Vec<SemanticModifier>,
+}
+
+pub struct Analysis {
+	pub sources: SourceMap,
+	pub root: FileId,
+	pub module: Option<YuriModule>,
+	pub imports: Option<ResolvedImports>,
+	pub resolution: Option<NameResolution>,
+	pub program: Option<TypedProgram>,
+	/// Whatever stopped the compiler, if anything did.
+	pub error: Option<YuriCompileError>,
+}
+
+impl Analysis {
+	/// Runs the compiler as far as it gets. Each step needs the one before, so the first error is the only one.
+	pub fn new(name: &str, text: &str, loader: &dyn SourceLoader) -> Self {
+		let mut sources = SourceMap::new();
+		let root = sources.add(name, text);
+		let mut analysis = Self { sources, root, module: None, imports: None, resolution: None, program: None, error: None };
+		if let Err(err) = analysis.run(loader) {
+			analysis.error = Some(err);
+		}
+		analysis
+	}
+
+	fn run(&mut self, loader: &dyn SourceLoader) -> Result<(), YuriCompileError> {
+		let ast = YuriShader::lex(self.sources.file(self.root).text())?;
+		let module = self.module.insert(YuriShader::parse(&ast)?);
+		let imports = self.imports.insert(YuriShader::resolve_imports(module, &mut self.sources, loader)?);
+		let resolution = self.resolution.insert(YuriShader::resolve_names(module, imports)?);
+		self.program = Some(YuriShader::check(module, &self.sources, imports, resolution)?);
+		Ok(())
+	}
+
+	/// Whether a location is in the document, rather than something it imports.
+	fn in_document(&self, location: &Range<usize>) -> bool {
+		location.end <= self.sources.file(self.root).end()
+	}
+
+	pub fn diagnostics(&self) -> Vec<Diagnostic> {
+		let Some(error) = &self.error else {
+			return Vec::new();
+		};
+		let markers = match error {
+			YuriCompileError::Parse(err) => err.markers(),
+			YuriCompileError::Semantic(err) => err.markers(),
+		};
+		// the rest of the message is the markers pointed at in their lines, which the editor does itself
+		let full = error.with_sources(&self.sources).to_string();
+		let mut message = full.lines().next().unwrap_or_default().to_string();
+		let location = match markers.iter().find(|marker| self.in_document(marker)) {
+			Some(marker) => marker.clone(),
+			None => {
+				// the problem is in an imported file, and all the document can do about it is its imports
+				if let Some((file, position)) = markers.first().and_then(|marker| self.sources.position(marker.start)) {
+					message.push_str(&format!(" (in {}:{position})", file.name()));
+				}
+				self.module.iter()
+					.flat_map(|module| module.all_imports())
+					.map(|import| import.location.clone())
+					.next()
+					.unwrap_or(0..0)
+			}
+		};
+		vec![Diagnostic { location, message }]
+	}
+
+	/// What's at the offset and its type, as markdown.
+	pub fn hover(&self, offset: usize) -> Option<String> {
+		let program = self.program.as_ref()?;
+		let text = self.expression_hover(program, offset)
+			.or_else(|| self.declaration_hover(program, offset))?;
+		Some(format!("```yuri\n{text}\n```"))
+	}
+
+	fn expression_hover(&self, program: &TypedProgram, offset: usize) -> Option<String> {
+		let bodies = program.functions.iter()
+			.map(|function| (&function.body, &function.locals))
+			.chain(program.globals.iter().map(|global| (&global.value, &global.locals)));
+		let (expr, locals) = bodies
+			.filter_map(|(body, locals)| Some((innermost(body, offset)?, locals)))
+			.min_by_key(|(expr, _)| expr.location.len())?;
+		let ty = &expr.expression_type;
+		Some(match &expr.kind {
+			TypedExpressionKind::Local(id) => format!("{}: {ty}", locals[*id].name),
+			TypedExpressionKind::Global(id) => format!("let {}: {ty}", program.globals[*id].name),
+			TypedExpressionKind::Property(id) => format!("prop {}: {ty}", program.properties[*id].name),
+			TypedExpressionKind::BuiltinInput(input) => format!("@{}: {ty}", input.name()),
+			TypedExpressionKind::Call { function, .. } => signature(&program.functions[*function]),
+			_ => ty.to_string(),
+		})
+	}
+
+	/// Hovering over the name of something, rather than a use of it.
+	fn declaration_hover(&self, program: &TypedProgram, offset: usize) -> Option<String> {
+		let local = program.functions.iter()
+			.flat_map(|function| &function.locals)
+			.chain(program.globals.iter().flat_map(|global| &global.locals))
+			.filter(|local| local.location.contains(&offset))
+			.min_by_key(|local| local.location.len())
+			.map(|local| (local.location.len(), format!("{}: {}", local.name, local.local_type)));
+		let resolution = self.resolution.as_ref()?;
+		let symbol = resolution.symbols.iter()
+			.enumerate()
+			.filter(|(_, symbol)| symbol.location.contains(&offset))
+			.min_by_key(|(_, symbol)| symbol.location.len())
+			.and_then(|(id, symbol)| Some((symbol.location.len(), self.describe_symbol(id)?)));
+		// whichever is innermost, since locals are inside functions and functions are inside modules
+		[local, symbol].into_iter()
+			.flatten()
+			.min_by_key(|(length, _)| *length)
+			.map(|(_, text)| text)
+	}
+
+	/// The index of a symbol among the symbols of the same kind, which is its ID in the [TypedProgram].
+	fn typed_id(&self, symbol: SymbolId) -> Option<usize> {
+		let resolution = self.resolution.as_ref()?;
+		let kind = resolution.symbols.get(symbol)?.kind;
+		Some(resolution.symbols[..symbol].iter().filter(|other| other.kind == kind).count())
+	}
+
+	fn symbol_type(&self, symbol: SymbolId) -> Option<YuriType> {
+		let program = self.program.as_ref()?;
+		let id = self.typed_id(symbol)?;
+		match self.resolution.as_ref()?.symbols[symbol].kind {
+			SymbolKind::Property => Some(program.properties[id].property_type.clone()),
+			SymbolKind::Global => Some(program.globals[id].global_type.clone()),
+			SymbolKind::Function | SymbolKind::Module(_) => None,
+		}
+	}
+
+	fn describe_symbol(&self, symbol: SymbolId) -> Option<String> {
+		let resolution = self.resolution.as_ref()?;
+		let program = self.program.as_ref()?;
+		let id = self.typed_id(symbol)?;
+		let symbol = &resolution.symbols[symbol];
+		Some(match symbol.kind {
+			SymbolKind::Property => format!("prop {}: {}", symbol.qualified_name, program.properties[id].property_type),
+			SymbolKind::Global => format!("let {}: {}", symbol.qualified_name, program.globals[id].global_type),
+			SymbolKind::Function => signature(&program.functions[id]),
+			SymbolKind::Module(_) => format!("module {}", symbol.qualified_name),
+		})
+	}
+
+	/// Where whatever's named at the offset was declared. It might be in another file.
+	pub fn definition(&self, offset: usize) -> Option<Range<usize>> {
+		let resolution = self.resolution.as_ref()?;
+		let reference = resolution.references()
+			.filter(|(_, location, _)| location.contains(&offset) && self.in_document(location))
+			.min_by_key(|(_, location, _)| location.len())
+			.map(|(_, _, reference)| reference);
+		if let Some(reference) = reference {
+			return match &reference.target {
+				ResolvedName::Local { declared_at, .. } => Some(declared_at.clone()),
+				ResolvedName::Symbol(id) => Some(resolution.symbols[*id].location.clone()),
+				ResolvedName::Builtin(_) => None,
+			};
+		}
+		// an `import` goes to the start of the file it imports
+		let import = self.module.as_ref()?
+			.all_imports()
+			.into_iter()
+			.find(|import| import.location.contains(&offset))?;
+		let file = self.imports.as_ref()?.get(&import.module)?.file;
+		let start = self.sources.file(file).start();
+		Some(start..start)
+	}
+
+	/// The type of a dotted path like `light.color`, as seen from the offset.
+	fn path_type(&self, path: &str, offset: usize) -> Option<YuriType> {
+		let program = self.program.as_ref()?;
+		let resolution = self.resolution.as_ref()?;
+		let mut parts = path.split('.');
+		let first = parts.next()?;
+		// the last local with the name that's been declared by now, in whatever's around the offset
+		let local = enclosing_locals(program, offset)
+			.iter()
+			.filter(|local| local.name == first && local.location.start <= offset)
+			.max_by_key(|local| local.location.start);
+		let mut ty = match local {
+			Some(local) => local.local_type.clone(),
+			None => self.symbol_type(*resolution.modules[ROOT_MODULE].members.get(first)?)?,
+		};
+		for part in parts {
+			let YuriType::Complex(fields) = ty else {
+				return None;
+			};
+			ty = fields.into_iter().find(|field| field.name == part)?.field_type;
+		}
+		Some(ty)
+	}
+
+	fn is_entry_point(&self, symbol: SymbolId) -> bool {
+		let function = self.program.as_ref()
+			.zip(self.typed_id(symbol))
+			.and_then(|(program, id)| program.functions.get(id));
+		function.is_some_and(|function| function.stage.is_some())
+	}
+
+	/// Classifies every token in the document that there's something to say about.
+	/// Names are looked up in the name resolution, so they're only classified if the document got that far.
+	pub fn semantic_tokens(&self) -> Vec<SemanticToken> {
+		use SemanticModifier::*;
+		let mut references = HashMap::new();
+		if let Some(resolution) = &self.resolution {
+			for (_, location, reference) in resolution.references() {
+				// a call's location is the whole call, but it starts with the name
+				let entry = references.entry(location.start).or_insert((location.clone(), reference));
+				if location.len() < entry.0.len() {
+					*entry = (location.clone(), reference);
+				}
+			}
+		}
+		let symbol_kind = |id: SymbolId, declaration: bool| {
+			let symbol = &self.resolution.as_ref()?.symbols[id];
+			let mut modifiers = if declaration { vec![Declaration] } else { Vec::new() };
+			let kind = match symbol.kind {
+				SymbolKind::Property => SemanticKind::Property,
+				SymbolKind::Global => {
+					modifiers.push(Readonly);
+					SemanticKind::Variable
+				}
+				SymbolKind::Function => {
+					if self.is_entry_point(id) {
+						modifiers.push(EntryPoint);
+					}
+					SemanticKind::Function
+				}
+				SymbolKind::Module(_) => SemanticKind::Namespace,
+			};
+			Some((kind, modifiers))
+		};
+		// the name in a declaration belongs to the innermost symbol around it
+		let declared = |offset: usize| {
+			let resolution = self.resolution.as_ref()?;
+			let (id, _) = resolution.symbols.iter()
+				.enumerate()
+				.filter(|(_, symbol)| symbol.location.contains(&offset))
+				.min_by_key(|(_, symbol)| symbol.location.len())?;
+			symbol_kind(id, true)
+		};
+		// locals are declared by a whole `let` or argument, so they're found by name
+		let local = |name: &str, offset: usize| {
+			let program = self.program.as_ref()?;
+			program.functions.iter()
+				.flat_map(|function| function.arguments.iter().map(|id| (&function.locals[*id], SemanticKind::Parameter)))
+				.chain(program.functions.iter().flat_map(|function| &function.locals).map(|local| (local, SemanticKind::Variable)))
+				.chain(program.globals.iter().flat_map(|global| &global.locals).map(|local| (local, SemanticKind::Variable)))
+				.filter(|(local, _)| local.name == name && local.location.contains(&offset))
+				.min_by_key(|(local, _)| local.location.len())
+				.map(|(_, kind)| (kind, vec![Declaration]))
+		};
+
+		let file = self.sources.file(self.root);
+		let mut tokens = Vec::new();
+		let mut previous = None;
+		for token in Lexer::for_file(file) {
+			let location = token.location.clone();
+			let classified = match &token.token_type {
+				YuriTokenType::Keyword(keyword) if YuriType::from_keyword(*keyword).is_some() => Some((SemanticKind::Type, Vec::new())),
+				YuriTokenType::Keyword(_) => Some((SemanticKind::Keyword, Vec::new())),
+				YuriTokenType::HexNumber(..) | YuriTokenType::BinaryNumber(..)
+					| YuriTokenType::UnsignedNumber(..) | YuriTokenType::DecimalNumber(..) => Some((SemanticKind::Number, Vec::new())),
+				YuriTokenType::Operator(_) => Some((SemanticKind::Operator, Vec::new())),
+				YuriTokenType::Annotation(name) => {
+					let input = BuiltinInput::ALL.iter()
+						.any(|input| name.strip_prefix(input.name()).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')));
+					Some(if input { (SemanticKind::Variable, vec![Readonly, DefaultLibrary]) } else { (SemanticKind::Decorator, Vec::new()) })
+				}
+				YuriTokenType::Identifier(name) => match previous {
+					Some(YuriTokenType::Keyword(Keyword::Import)) => Some((SemanticKind::Namespace, Vec::new())),
+					Some(YuriTokenType::Keyword(Keyword::Fn | Keyword::Prop | Keyword::Module)) => declared(location.start),
+					// a `let` in a block is a local, anything else is a global
+					Some(YuriTokenType::Keyword(Keyword::Let)) => local(name, location.start).or_else(|| declared(location.start)),
+					_ => match references.get(&location.start) {
+						Some((_, reference)) => match &reference.target {
+							ResolvedName::Local { name, declared_at } => {
+								let kind = local(name, declared_at.start).map_or(SemanticKind::Variable, |(kind, _)| kind);
+								Some((kind, Vec::new()))
+							}
+							ResolvedName::Symbol(id) => symbol_kind(*id, false),
+							ResolvedName::Builtin(_) => Some((SemanticKind::Function, vec![DefaultLibrary])),
+						},
+						None => local(name, location.start),
+					},
+				},
+				_ => None,
+			};
+			if let Some((kind, modifiers)) = classified {
+				for location in line_pieces(file, location) {
+					tokens.push(SemanticToken { location, kind, modifiers: modifiers.clone() });
+				}
+			}
+			previous = Some(token.token_type);
+		}
+		tokens
+	}
+}
+
+/// Splits a location into one piece per line it's on, without the line breaks or the whitespace around them,
+/// since a semantic token can't go past the end of its line.
+fn line_pieces(file: &SourceFile, location: Range<usize>) -> Vec<Range<usize>> {
+	let text = &file.text()[location.start - file.start()..location.end - file.start()];
+	let mut start = location.start;
+	let mut pieces = Vec::new();
+	for line in text.split('\n') {
+		let trimmed = line.trim();
+		let piece_start = start + (line.len() - line.trim_start().len());
+		if !trimmed.is_empty() {
+			pieces.push(piece_start..piece_start + trimmed.len());
+		}
+		start += line.len() + 1;
+	}
+	pieces
+}
+
+/// The innermost expression that the offset is in.
+fn innermost(expr: &TypedExpression, offset: usize) -> Option<&TypedExpression> {
+	if !expr.location.contains(&offset) {
+		return None;
+	}
+	let mut inner = None;
+	expr.for_each_child(|child| {
+		if inner.is_none() {
+			inner = innermost(child, offset);
+		}
+	});
+	Some(inner.unwrap_or(expr))
+}
+
+/// The locals of whichever function or global the offset is in.
+fn enclosing_locals(program: &TypedProgram, offset: usize) -> &[TypedLocal] {
+	program.functions.iter()
+		.map(|function| (&function.location, &function.locals))
+		.chain(program.globals.iter().map(|global| (&global.location, &global.locals)))
+		.find(|(location, _)| location.contains(&offset))
+		.map_or(&[], |(_, locals)| locals.as_slice())
+}
+
+fn signature(function: &TypedFunction) -> String {
+	let arguments: Vec<String> = function.arguments.iter()
+		.map(|id| format!("{}: {}", function.locals[*id].name, function.locals[*id].local_type))
+		.collect();
+	format!("fn {}({}): {}", function.name, arguments.join(", "), function.return_type)
+}
+
+/// What could go at the offset. The analysis doesn't have to be of the same text, since whatever's being typed
+/// usually doesn't compile, so the last one that did is the next best thing.
+pub fn completions(text: &str, offset: usize, analysis: Option<&Analysis>) -> Vec<Completion> {
+	let offset = offset.min(text.len());
+	let start = text[..offset]
+		.rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == '@'))
+		.map_or(0, |i| i + 1);
+	let prefix = &text[start..offset];
+
+	// builtin inputs are the only things that start with `@` (in an expression, anyway)
+	if prefix.starts_with('@') {
+		return BuiltinInput::ALL.iter()
+			.map(|input| Completion {
+				label: input.name().to_string(),
+				kind: CompletionKind::Builtin,
+				detail: Some(input.input_type().to_string()),
+				replace: start + 1..offset,
+			})
+			.collect();
+	}
+
+	if let Some((receiver, _)) = prefix.rsplit_once('.')
+		&& let Some(ty) = analysis.and_then(|analysis| analysis.path_type(receiver, offset))
+	{
+		let replace = start + receiver.len() + 1..offset;
+		let field = |label: String, ty: &YuriType| Completion {
+			label,
+			kind: CompletionKind::Field,
+			detail: Some(ty.to_string()),
+			replace: replace.clone(),
+		};
+		return match &ty {
+			YuriType::Complex(fields) => fields.iter()
+				.map(|f| field(f.name.clone(), &f.field_type))
+				.collect(),
+			YuriType::Vector(number_type, size) => {
+				let count = match size {
+					CompositeSize::Two => 2,
+					CompositeSize::Three => 3,
+					CompositeSize::Four => 4,
+				};
+				["x", "y", "z", "w"][..count].iter()
+					.map(|component| field(component.to_string(), &YuriType::Scalar(*number_type)))
+					.collect()
+			}
+			_ => Vec::new(),
+		};
+	}
+
+	let replace = start..offset;
+	let mut completions: Vec<Completion> = Keyword::ALL.iter()
+		.map(|keyword| Completion {
+			label: <&str>::from(*keyword).to_string(),
+			kind: CompletionKind::Keyword,
+			detail: None,
+			replace: replace.clone(),
+		})
+		.collect();
+	completions.extend(BuiltinFunction::ALL.iter().map(|function| Completion {
+		label: function.name().to_string(),
+		kind: CompletionKind::Builtin,
+		detail: None,
+		replace: replace.clone(),
+	}));
+	let Some(analysis) = analysis else {
+		return completions;
+	};
+	if let Some(program) = &analysis.program {
+		completions.extend(enclosing_locals(program, offset).iter()
+			// `_` is what assertions used as statements get bound to
+			.filter(|local| local.location.start < offset && local.name != "_")
+			.map(|local| Completion {
+				label: local.name.clone(),
+				kind: CompletionKind::Local,
+				detail: Some(local.local_type.to_string()),
+				replace: replace.clone(),
+			}));
+	}
+	if let Some(resolution) = &analysis.resolution {
+		let symbol = |label: String, id: SymbolId| {
+			let kind = match resolution.symbols[id].kind {
+				SymbolKind::Property => CompletionKind::Property,
+				SymbolKind::Global => CompletionKind::Global,
+				SymbolKind::Function => CompletionKind::Function,
+				SymbolKind::Module(_) => CompletionKind::Module,
+			};
+			Completion { label, kind, detail: analysis.describe_symbol(id), replace: replace.clone() }
+		};
+		let root = &resolution.modules[ROOT_MODULE];
+		completions.extend(root.members.iter().map(|(name, id)| symbol(name.clone(), *id)));
+		// imported names go by their full path
+		for (import, module) in &root.imports {
+			completions.extend(resolution.modules[*module].members.iter()
+				.filter(|(_, id)| resolution.symbols[**id].exported)
+				.map(|(name, id)| symbol(format!("{import}.{name}"), *id)));
+		}
+	}
+	completions
+}
+
+#[cfg(test)]
+mod test {
+	use yuri::import::MemoryLoader;
+	use yuri::source::SourceMap;
+	use crate::analysis::{completions, line_pieces, Analysis, CompletionKind, SemanticKind, SemanticModifier};
+
+	const LIGHTING: &str = "export let AMBIENT: f = 0.1;\nexport fn shade(n: f3): f { AMBIENT + n.z }\n";
+
+	fn analyze(text: &str) -> Analysis {
+		Analysis::new("main.yuri", text, &MemoryLoader::new().with_module("lighting", LIGHTING))
+	}
+
+	#[test]
+	fn diagnostics() {
+		assert_eq!(analyze("let a: f = 1.0;").diagnostics(), []);
+		let diagnostics = analyze("let a: f = 1.0;\nlet b: u = a;").diagnostics();
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].location, 27..28);
+		assert!(diagnostics[0].message.starts_with("TypeMismatch"), "{}", diagnostics[0].message);
+
+		// errors in imported files land on the imports
+		let broken = Analysis::new("main.yuri", "import lighting;", &MemoryLoader::new().with_module("lighting", "let x: u = 1.0;"));
+		let diagnostics = broken.diagnostics();
+		assert_eq!(diagnostics[0].location, 0..16);
+		assert!(diagnostics[0].message.contains("<memory:lighting>:1:12"), "{}", diagnostics[0].message);
+	}
+
+	#[test]
+	fn hover_and_definition() {
+		let text = "import lighting;\nprop tint: f3;\n@frag fn main(n: f3): f4 {\n\tlet light = lighting.shade(n) * tint;\n\tf4(light, 1.0)\n}";
+		let analysis = analyze(text);
+		let at = |needle: &str| text.find(needle).unwrap();
+
+		assert_eq!(analysis.hover(at("tint;")).unwrap(), "```yuri\nprop tint: f3\n```");
+		assert_eq!(analysis.hover(at("shade")).unwrap(), "```yuri\nfn lighting.shade(n: f3): f\n```");
+		assert_eq!(analysis.hover(at("light,")).unwrap(), "```yuri\nlight: f3\n```");
+		assert_eq!(analysis.hover(at("1.0")).unwrap(), "```yuri\nf\n```");
+
+		// a local goes to its `let`, and an imported function goes into the other file
+		let light = analysis.definition(at("light,")).unwrap();
+		assert!(analysis.sources.snippet(&light).unwrap().starts_with("let light"));
+		let shade = analysis.definition(at("shade")).unwrap();
+		let file = analysis.sources.lookup(shade.start).unwrap();
+		assert_eq!(file.name(), "<memory:lighting>");
+		assert!(analysis.sources.snippet(&shade).unwrap().contains("fn shade"));
+		assert_eq!(analysis.definition(at("import")), Some(file.start()..file.start()));
+	}
+
+	#[test]
+	fn completion() {
+		let good = "import lighting;\nlet OUT: <| color: f4, depth: f |> = <| color = f4(1.0), depth = 0.5 |>;\nfn g(v: f3): f { v.x }";
+		let analysis = analyze(good);
+		let labels = |text: &str, analysis: Option<&Analysis>| -> Vec<(String, CompletionKind)> {
+			completions(text, text.len(), analysis).into_iter().map(|c| (c.label, c.kind)).collect()
+		};
+
+		// still finding things with the last analysis that worked
+		let typing = format!("{good}\nfn h(): f {{ OUT.");
+		assert_eq!(labels(&typing, Some(&analysis)), [("color".to_string(), CompletionKind::Field), ("depth".to_string(), CompletionKind::Field)]);
+		let typing = good.replace("v.x }", "v.");
+		assert_eq!(labels(&typing, Some(&analysis)).len(), 3);
+		let typing = format!("{good}\nfn h(): f2 {{ @fr");
+		let inputs = completions(&typing, typing.len(), Some(&analysis));
+		assert!(inputs.iter().any(|c| c.label == "frag.coord" && c.replace == (typing.len() - 2..typing.len())));
+
+		let everything = labels("let x = ", Some(&analysis));
+		for expected in [("fold", CompletionKind::Keyword), ("smoothstep", CompletionKind::Builtin), ("OUT", CompletionKind::Global), ("lighting.shade", CompletionKind::Function), ("lighting.AMBIENT", CompletionKind::Global)] {
+			assert!(everything.contains(&(expected.0.to_string(), expected.1)), "missing {expected:?}");
+		}
+	}
+
+	#[test]
+	fn multiline_tokens() {
+		let text = "let a =\n\tb\r\n   c;";
+		let mut sources = SourceMap::new();
+		let file = sources.add("main.yuri", text);
+		let pieces: Vec<&str> = line_pieces(sources.file(file), 4..text.len()).into_iter().map(|piece| &text[piece]).collect();
+		assert_eq!(pieces, ["a =", "b", "c;"]);
+		assert_eq!(line_pieces(sources.file(file), 0..3), vec![0..3]);
+	}
+
+	#[test]
+	fn semantic_tokens() {
+		let text = "import lighting;\nprop tint: f3;\n@frag fn main(n: f3): f4 {\n\tlet light = lighting.shade(n) * tint;\n\tf4(light, @frag.coord.x)\n}";
+		let analysis = analyze(text);
+		let tokens: Vec<(&str, SemanticKind, Vec<SemanticModifier>)> = analysis.semantic_tokens().into_iter()
+			.map(|token| (&text[token.location], token.kind, token.modifiers))
+			.collect();
+		use SemanticModifier::*;
+		for expected in [
+			("lighting", SemanticKind::Namespace, vec![]),
+			("tint", SemanticKind::Property, vec![Declaration]),
+			("f3", SemanticKind::Type, vec![]),
+			("@frag", SemanticKind::Decorator, vec![]),
+			("main", SemanticKind::Function, vec![Declaration, EntryPoint]),
+			("light", SemanticKind::Variable, vec![Declaration]),
+			("lighting.shade", SemanticKind::Function, vec![]),
+			("n", SemanticKind::Parameter, vec![]),
+			("tint", SemanticKind::Property, vec![]),
+			("@frag.coord.x", SemanticKind::Variable, vec![Readonly, DefaultLibrary]),
+		] {
+			assert!(tokens.contains(&expected), "missing {expected:?} in {tokens:?}");
+		}
+	}
+}
@@ -0,0 +1,385 @@
+//! A language server for Yuri, spoken over stdin and stdout.
+//! Every change to a document runs the compiler over it again (up to type checking),
+//! which is quick enough for shaders that nothing fancier is needed.
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{Completion as CompletionRequest, GotoDefinition, HoverRequest, Request as _, SemanticTokensFullRequest};
+use lsp_types::{
+	CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse, CompletionTextEdit,
+	Diagnostic, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+	HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams,
+	SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
+	SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult, SemanticTokensServerCapabilities,
+	ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Uri,
+};
+use yuri::import::{FileSystemLoader, LoadedSource, SourceLoader};
+use yuri::source::SourceFile;
+use crate::analysis::{completions, Analysis, CompletionKind, SemanticKind, SemanticModifier};
+
+mod analysis;
+
+struct Document {
+	text: String,
+	path: Option<PathBuf>,
+	analysis: Analysis,
+	/// The last analysis that made it all the way through type checking, for completing whatever's half-typed.
+	checked: Option<Analysis>,
+}
+
+/// Loads imports from next to the document, preferring what's open in the editor over what's on disk.
+struct DocumentLoader<'a> {
+	directory: Option<&'a Path>,
+	open: HashMap<PathBuf, &'a str>,
+}
+
+impl SourceLoader for DocumentLoader<'_> {
+	fn load(&self, module: &str) -> io::Result<LoadedSource> {
+		let Some(directory) = self.directory else {
+			return Err(io::Error::new(io::ErrorKind::NotFound, "the document isn't a file, so there's nowhere to look"));
+		};
+		let relative: PathBuf = module.split('.').collect();
+		let path = directory.join(relative.with_extension("yuri"));
+		if let Some(text) = self.open.get(&path) {
+			return Ok(LoadedSource { origin: path.display().to_string(), text: text.to_string() });
+		}
+		FileSystemLoader::new().with_search_path(directory).load(module)
+	}
+}
+
+struct Server {
+	connection: Connection,
+	documents: HashMap<Uri, Document>,
+}
+
+impl Server {
+	/// Analyzes every open document again, since any of them could import the one that changed.
+	fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+		let texts: HashMap<PathBuf, String> = self.documents.values()
+			.filter_map(|document| Some((document.path.clone()?, document.text.clone())))
+			.collect();
+		for (uri, document) in &mut self.documents {
+			let loader = DocumentLoader {
+				directory: document.path.as_deref().and_then(Path::parent),
+				open: texts.iter().map(|(path, text)| (path.clone(), text.as_str())).collect(),
+			};
+			let name = document.path.as_ref().map_or_else(|| uri.as_str().to_string(), |path| path.display().to_string());
+			let analysis = Analysis::new(&name, &document.text, &loader);
+			let file = analysis.sources.file(analysis.root);
+			let diagnostics = analysis.diagnostics().into_iter()
+				.map(|diagnostic| Diagnostic {
+					range: range(file, &diagnostic.location),
+					severity: Some(DiagnosticSeverity::ERROR),
+					source: Some("yuri".to_string()),
+					message: diagnostic.message,
+					..Default::default()
+				})
+				.collect();
+			let params = PublishDiagnosticsParams { uri: uri.clone(), diagnostics, version: None };
+			self.connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_string(), params)))?;
+			let previous = std::mem::replace(&mut document.analysis, analysis);
+			if previous.program.is_some() {
+				document.checked = Some(previous);
+			}
+		}
+		Ok(())
+	}
+
+	/// Notifications don't get a reply, so ones that don't make sense are just ignored.
+	fn notification(&mut self, notification: Notification) -> Result<(), Box<dyn Error>> {
+		match notification.method.as_str() {
+			DidOpenTextDocument::METHOD => {
+				let Ok(params) = serde_json::from_value::<lsp_types::DidOpenTextDocumentParams>(notification.params) else {
+					return Ok(());
+				};
+				let uri = params.text_document.uri;
+				let document = Document {
+					path: uri_to_path(&uri),
+					analysis: Analysis::new(uri.as_str(), "", &FileSystemLoader::new()),
+					text: params.text_document.text,
+					checked: None,
+				};
+				self.documents.insert(uri, document);
+				self.refresh()?;
+			}
+			DidChangeTextDocument::METHOD => {
+				let Ok(params) = serde_json::from_value::<lsp_types::DidChangeTextDocumentParams>(notification.params) else {
+					return Ok(());
+				};
+				// the sync is full, so the last change is the whole text
+				if let Some(document) = self.documents.get_mut(&params.text_document.uri)
+					&& let Some(change) = params.content_changes.into_iter().last()
+				{
+					document.text = change.text;
+					self.refresh()?;
+				}
+			}
+			DidCloseTextDocument::METHOD => {
+				let Ok(params) = serde_json::from_value::<lsp_types::DidCloseTextDocumentParams>(notification.params) else {
+					return Ok(());
+				};
+				self.documents.remove(&params.text_document.uri);
+				let params = PublishDiagnosticsParams { uri: params.text_document.uri, diagnostics: Vec::new(), version: None };
+				self.connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_string(), params)))?;
+				self.refresh()?;
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn request(&self, request: Request) -> Result<(), Box<dyn Error>> {
+		let id = request.id.clone();
+		match request.method.as_str() {
+			HoverRequest::METHOD => {
+				let params: HoverParams = match serde_json::from_value(request.params) {
+					Ok(params) => params,
+					Err(err) => return self.invalid_params(id, err),
+				};
+				let position = params.text_document_position_params;
+				let hover = self.documents.get(&position.text_document.uri).and_then(|document| {
+					let file = document.analysis.sources.file(document.analysis.root);
+					let contents = document.analysis.hover(offset(file, position.position))?;
+					Some(Hover {
+						contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: contents }),
+						range: None,
+					})
+				});
+				self.respond(id, serde_json::to_value(hover)?)
+			}
+			GotoDefinition::METHOD => {
+				let params: GotoDefinitionParams = match serde_json::from_value(request.params) {
+					Ok(params) => params,
+					Err(err) => return self.invalid_params(id, err),
+				};
+				let position = params.text_document_position_params;
+				let uri = position.text_document.uri;
+				let location = self.documents.get(&uri).and_then(|document| {
+					let analysis = &document.analysis;
+					let root = analysis.sources.file(analysis.root);
+					let target = analysis.definition(offset(root, position.position))?;
+					let file = analysis.sources.lookup(target.start)?;
+					let uri = if file.id() == analysis.root { uri.clone() } else { path_to_uri(Path::new(file.name()))? };
+					Some(GotoDefinitionResponse::Scalar(Location { uri, range: range(file, &target) }))
+				});
+				self.respond(id, serde_json::to_value(location)?)
+			}
+			CompletionRequest::METHOD => {
+				let params: CompletionParams = match serde_json::from_value(request.params) {
+					Ok(params) => params,
+					Err(err) => return self.invalid_params(id, err),
+				};
+				let position = params.text_document_position;
+				let items = self.documents.get(&position.text_document.uri).map(|document| {
+					let file = document.analysis.sources.file(document.analysis.root);
+					let analysis = if document.analysis.program.is_some() { Some(&document.analysis) } else { document.checked.as_ref() };
+					let items = completions(&document.text, offset(file, position.position), analysis).into_iter()
+						.map(|completion| CompletionItem {
+							kind: Some(match completion.kind {
+								CompletionKind::Keyword => CompletionItemKind::KEYWORD,
+								CompletionKind::Builtin | CompletionKind::Function => CompletionItemKind::FUNCTION,
+								CompletionKind::Property => CompletionItemKind::PROPERTY,
+								CompletionKind::Global => CompletionItemKind::CONSTANT,
+								CompletionKind::Module => CompletionItemKind::MODULE,
+								CompletionKind::Local => CompletionItemKind::VARIABLE,
+								CompletionKind::Field => CompletionItemKind::FIELD,
+							}),
+							detail: completion.detail,
+							text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+								range: range(file, &completion.replace),
+								new_text: completion.label.clone(),
+							})),
+							label: completion.label,
+							..Default::default()
+						})
+						.collect();
+					CompletionResponse::Array(items)
+				});
+				self.respond(id, serde_json::to_value(items)?)
+			}
+			SemanticTokensFullRequest::METHOD => {
+				let params: SemanticTokensParams = match serde_json::from_value(request.params) {
+					Ok(params) => params,
+					Err(err) => return self.invalid_params(id, err),
+				};
+				let tokens = self.documents.get(&params.text_document.uri).map(|document| {
+					let file = document.analysis.sources.file(document.analysis.root);
+					// each token is relative to the one before, in lines and then in UTF-16 columns
+					let mut last = Position::default();
+					let data = document.analysis.semantic_tokens().into_iter()
+						.map(|token| {
+							let span = range(file, &token.location);
+							let delta_line = span.start.line - last.line;
+							let delta_start = if delta_line == 0 { span.start.character - last.character } else { span.start.character };
+							last = span.start;
+							lsp_types::SemanticToken {
+								delta_line,
+								delta_start,
+								length: span.end.character.saturating_sub(span.start.character),
+								token_type: SemanticKind::ALL.iter().position(|kind| *kind == token.kind).unwrap_or_default() as u32,
+								token_modifiers_bitset: token.modifiers.iter()
+									.filter_map(|modifier| SemanticModifier::ALL.iter().position(|m| m == modifier))
+									.fold(0, |bits, i| bits | 1 << i),
+							}
+						})
+						.collect();
+					SemanticTokensResult::Tokens(SemanticTokens { result_id: None, data })
+				});
+				self.respond(id, serde_json::to_value(tokens)?)
+			}
+			_ => {
+				let response = Response::new_err(id, lsp_server::ErrorCode::MethodNotFound as i32, format!("unknown request {}", request.method));
+				self.connection.sender.send(Message::Response(response))?;
+				Ok(())
+			}
+		}
+	}
+
+	fn respond(&self, id: RequestId, result: serde_json::Value) -> Result<(), Box<dyn Error>> {
+		self.connection.sender.send(Message::Response(Response { id, result: Some(result), error: None }))?;
+		Ok(())
+	}
+
+	/// Tells the client its request didn't make sense, rather than giving up on the whole connection.
+	fn invalid_params(&self, id: RequestId, err: serde_json::Error) -> Result<(), Box<dyn Error>> {
+		let response = Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, format!("invalid params: {err}"));
+		self.connection.sender.send(Message::Response(response))?;
+		Ok(())
+	}
+}
+
+fn position(file: &SourceFile, offset: usize) -> Position {
+	let position = file.position(offset);
+	Position { line: position.line as u32, character: position.utf16_column as u32 }
+}
+
+fn range(file: &SourceFile, location: &Range<usize>) -> lsp_types::Range {
+	lsp_types::Range { start: position(file, location.start), end: position(file, location.end) }
+}
+
+fn offset(file: &SourceFile, position: Position) -> usize {
+	file.offset(position.line as usize, position.character as usize)
+}
+
+/// Characters that can go in a path in a URI as they are.
+fn unreserved(byte: u8) -> bool {
+	byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte)
+}
+
+fn uri_to_path(uri: &Uri) -> Option<PathBuf> {
+	let path = uri.as_str().strip_prefix("file://")?;
+	let mut bytes = Vec::new();
+	let mut rest = path.as_bytes();
+	while let [first, tail @ ..] = rest {
+		match (first, tail) {
+			(b'%', [high, low, tail @ ..]) => {
+				let hex = std::str::from_utf8(&[*high, *low]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())?;
+				bytes.push(hex);
+				rest = tail;
+			}
+			_ => {
+				bytes.push(*first);
+				rest = tail;
+			}
+		}
+	}
+	let path = String::from_utf8(bytes).ok()?;
+	// `file:///C:/shaders` is `C:/shaders` on Windows
+	match path.as_bytes() {
+		[b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => Some(PathBuf::from(&path[1..])),
+		_ => Some(PathBuf::from(path)),
+	}
+}
+
+fn path_to_uri(path: &Path) -> Option<Uri> {
+	let path = path.to_str()?.replace('\\', "/");
+	let mut uri = String::from("file://");
+	if !path.starts_with('/') {
+		uri.push('/');
+	}
+	for byte in path.bytes() {
+		if unreserved(byte) || byte == b':' {
+			uri.push(byte as char);
+		} else {
+			uri.push_str(&format!("%{byte:02X}"));
+		}
+	}
+	Uri::from_str(&uri).ok()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+	let (connection, io_threads) = Connection::stdio();
+	let capabilities = ServerCapabilities {
+		text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+		hover_provider: Some(HoverProviderCapability::Simple(true)),
+		definition_provider: Some(OneOf::Left(true)),
+		completion_provider: Some(CompletionOptions {
+			trigger_characters: Some(vec![".".to_string(), "@".to_string()]),
+			..Default::default()
+		}),
+		semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+			legend: SemanticTokensLegend {
+				token_types: SemanticKind::ALL.iter().map(|kind| SemanticTokenType::new(kind.name())).collect(),
+				token_modifiers: SemanticModifier::ALL.iter().map(|modifier| SemanticTokenModifier::new(modifier.name())).collect(),
+			},
+			full: Some(SemanticTokensFullOptions::Bool(true)),
+			..Default::default()
+		})),
+		..Default::default()
+	};
+	connection.initialize(serde_json::to_value(capabilities)?)?;
+
+	let mut server = Server { connection, documents: HashMap::new() };
+	while let Ok(message) = server.connection.receiver.recv() {
+		match message {
+			Message::Request(request) => {
+				if server.connection.handle_shutdown(&request)? {
+					break;
+				}
+				server.request(request)?;
+			}
+			Message::Notification(notification) => server.notification(notification)?,
+			Message::Response(_) => {}
+		}
+	}
+	drop(server);
+	io_threads.join()?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::HashMap;
+	use std::path::Path;
+	use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId};
+	use lsp_types::notification::{DidOpenTextDocument, Notification as _};
+	use lsp_types::request::{HoverRequest, Request as _};
+	use crate::{path_to_uri, uri_to_path, Server};
+
+	#[test]
+	fn invalid_params() {
+		let (connection, client) = Connection::memory();
+		let mut server = Server { connection, documents: HashMap::new() };
+		// the server keeps going, and says what was wrong with the request
+		server.notification(Notification::new(DidOpenTextDocument::METHOD.to_string(), "not a document")).unwrap();
+		server.request(Request::new(RequestId::from(1), HoverRequest::METHOD.to_string(), 5)).unwrap();
+		let Ok(Message::Response(response)) = client.receiver.try_recv() else {
+			panic!("there should be a response");
+		};
+		assert_eq!(response.id, RequestId::from(1));
+		assert_eq!(response.error.unwrap().code, ErrorCode::InvalidParams as i32);
+		assert!(client.receiver.try_recv().is_err());
+	}
+
+	#[test]
+	fn file_uris() {
+		let uri = path_to_uri(Path::new("/home/me/my shaders/ü.yuri")).unwrap();
+		assert_eq!(uri.as_str(), "file:///home/me/my%20shaders/%C3%BC.yuri");
+		assert_eq!(uri_to_path(&uri).unwrap(), Path::new("/home/me/my shaders/ü.yuri"));
+	}
+}